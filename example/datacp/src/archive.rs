@@ -0,0 +1,187 @@
+// ===================== 归档模式：拷贝旧数据后从源表删除 =====================
+// 迁移窗口限制为 time_field < now() - --older-than，分段拷贝完成后逐段校验行数，
+// 仅对校验通过的分段执行 ALTER TABLE ... DELETE，并等待对应 mutation 完成后记为已归档
+
+use log::{error, info, warn};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::report::{ArchiveSegment, RunReport};
+use crate::{
+    ch_execute, ch_execute_on_cluster, ch_query_rows, json_u64, load_done_segments, mutations,
+    plan_segments, qualified, row_filter, save_done_segment, segment, table_ref, time_range_row, time_zone, Opt, SegmentBlacklist,
+};
+
+// 已归档分段在断点续传文件中的前缀
+const ARCHIVED_PREFIX: &str = "archived:";
+
+// 归档截止时间，按源端服务器时间计算（时间字段声明了时区时为 UTC，与分段键一致）
+pub async fn cutoff(opt: &Opt) -> anyhow::Result<String> {
+    let now = if opt.time_zone.is_some() { "now('UTC')" } else { "now()" };
+    let sql = format!("SELECT toString({} - INTERVAL {} SECOND) AS t FORMAT JSONEachRow", now, opt.older_than.as_secs());
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let t = rows.first().and_then(|r| r.get("t")).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    if t.is_empty() {
+        anyhow::bail!("无法获取归档截止时间");
+    }
+    Ok(t)
+}
+
+// 归档窗口内的时间范围: [start_time, cutoff)
+pub async fn time_range(opt: &Opt, cutoff: &str) -> anyhow::Result<(String, String)> {
+    let sql = format!(
+        "SELECT count() as c, toString(min({tf})) as min_time, toString(max({tf})) as max_time FROM {} WHERE {tf} >= {} AND {tf} < {}{} FORMAT JSONEachRow",
+        qualified(&opt.src_db, &opt.src_table), opt.time_lit(&opt.start_time), opt.time_lit(cutoff), row_filter(opt), tf = opt.time_field
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    Ok(time_zone::range_from_column(opt.time_zone.as_ref(), time_range_row(rows.first())))
+}
+
+async fn count_range(opt: &Opt, dsn: &str, db: &str, table: &str, from: &str, to: &str) -> anyhow::Result<u64> {
+    let sql = format!(
+        "SELECT count() AS c FROM {} WHERE {} >= {} AND {} < {}{} FORMAT JSONEachRow",
+        table, opt.time_field, opt.time_lit(from), opt.time_field, opt.time_lit(to), row_filter(opt)
+    );
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    Ok(json_u64(rows.first().and_then(|r| r.get("c"))))
+}
+
+// 源表上尚未见过的、条件包含该分段起点的 mutation
+async fn new_mutation_ids(opt: &Opt, before: &HashSet<String>, seg: &str) -> anyhow::Result<Vec<String>> {
+    let (from, db, table) = mutations::mutation_source(opt).await?;
+    let sql = format!(
+        "SELECT DISTINCT mutation_id FROM {} WHERE database = '{}' AND table = '{}' AND position(command, '{}') > 0 FORMAT JSONEachRow",
+        from, db, table, seg
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    Ok(rows
+        .iter()
+        .filter_map(|r| r.get("mutation_id").and_then(|v| v.as_str()).map(|s| s.to_string()))
+        .filter(|id| !before.contains(id))
+        .collect())
+}
+
+async fn all_mutation_ids(opt: &Opt) -> anyhow::Result<HashSet<String>> {
+    let (from, db, table) = mutations::mutation_source(opt).await?;
+    let sql = format!(
+        "SELECT DISTINCT mutation_id FROM {} WHERE database = '{}' AND table = '{}' FORMAT JSONEachRow",
+        from, db, table
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    Ok(rows.iter().filter_map(|r| r.get("mutation_id").and_then(|v| v.as_str()).map(|s| s.to_string())).collect())
+}
+
+// 等待 mutation 在所有副本上完成，失败原因非空或超时返回错误
+async fn wait_mutations_done(opt: &Opt, ids: &[String]) -> anyhow::Result<()> {
+    let (from, db, table) = mutations::mutation_source(opt).await?;
+    let id_list = ids.iter().map(|id| format!("'{}'", id)).collect::<Vec<_>>().join(",");
+    let sql = format!(
+        "SELECT min(is_done) AS is_done, max(latest_fail_reason) AS fail FROM {} \
+         WHERE database = '{}' AND table = '{}' AND mutation_id IN ({}) FORMAT JSONEachRow",
+        from, db, table, id_list
+    );
+    let started = Instant::now();
+    loop {
+        let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+        let row = rows.first();
+        let fail = row.and_then(|r| r.get("fail")).and_then(|v| v.as_str()).unwrap_or("");
+        if !fail.is_empty() {
+            anyhow::bail!(format!("mutation {} 执行失败: {}", id_list, fail));
+        }
+        if json_u64(row.and_then(|r| r.get("is_done"))) == 1 {
+            return Ok(());
+        }
+        if started.elapsed() >= opt.ddl_timeout {
+            anyhow::bail!(format!("等待 mutation {} 完成超时 ({:?})", id_list, opt.ddl_timeout));
+        }
+        tokio::time::sleep(opt.ddl_poll_interval).await;
+    }
+}
+
+// 删除单个已校验分段：分布式源表对本地表执行 ON CLUSTER DELETE
+async fn delete_segment(opt: &Opt, from: &str, to: &str) -> anyhow::Result<Vec<String>> {
+    let (_, db, table) = mutations::mutation_source(opt).await?;
+    let before = all_mutation_ids(opt).await?;
+    let on_cluster = opt.is_src_distributed && !opt.cluster_name.is_empty();
+    let sql = format!(
+        "ALTER TABLE {}.{}{} DELETE WHERE {} >= {} AND {} < {}{}",
+        db,
+        table,
+        if on_cluster { format!(" ON CLUSTER {}", opt.cluster_name) } else { String::new() },
+        opt.time_field, opt.time_lit(from), opt.time_field, opt.time_lit(to), row_filter(opt)
+    );
+    info!("segment {from} archive delete SQL: {sql}");
+    if on_cluster {
+        ch_execute_on_cluster(&opt.src_dsn, &opt.src_db, &sql, &opt.cluster_name, opt.ddl_timeout, opt.ddl_poll_interval).await?;
+    } else {
+        ch_execute(&opt.src_dsn, &opt.src_db, &sql).await?;
+    }
+    let ids = new_mutation_ids(opt, &before, from).await?;
+    if ids.is_empty() {
+        anyhow::bail!("未找到删除对应的 mutation");
+    }
+    wait_mutations_done(opt, &ids).await?;
+    Ok(ids)
+}
+
+// 归档收尾：逐段校验，--archive-delete-after-verify 且 --yes 时删除校验通过的分段
+pub async fn verify_and_delete(
+    opt: &Opt,
+    min_time: &str,
+    max_time: &str,
+    cutoff: &str,
+    done_segments_file: &str,
+    blacklist: &SegmentBlacklist,
+    report: &Arc<Mutex<RunReport>>,
+) -> anyhow::Result<()> {
+    let delete = opt.archive_delete_after_verify && opt.yes;
+    if opt.archive_delete_after_verify && !opt.yes {
+        warn!("--archive-delete-after-verify 需要同时指定 --yes，本次只校验不删除");
+    }
+    let done_segments = load_done_segments(done_segments_file)?;
+    let segments = plan_segments(min_time, max_time, &HashSet::new(), blacklist, None)?;
+    let (mut verified, mut deleted, mut failed) = (0, 0, 0);
+    for seg in segments {
+        if done_segments.contains(&format!("{}{}", ARCHIVED_PREFIX, seg)) {
+            continue;
+        }
+        let (_, seg_end) = segment::bounds(&seg);
+        // 最后一个分段截断到截止时间，截止时间之后的数据不属于本次归档
+        let to = if seg_end.as_str() > cutoff { cutoff.to_string() } else { seg_end };
+        let src_rows = count_range(opt, &opt.src_dsn, &opt.src_db, &table_ref(&opt.src_db, &opt.src_table, opt.select_final), &seg, &to).await?;
+        let dst_rows = count_range(opt, &opt.dst_dsn, &opt.dst_db, &table_ref(&opt.dst_db, opt.read_table(), opt.select_final && opt.dst_select_final), &seg, &to).await?;
+        let mut entry = ArchiveSegment { segment: seg.clone(), src_rows, dst_rows, status: String::new(), mutation_ids: Vec::new() };
+        if src_rows != dst_rows {
+            error!("segment {seg} archive verify failed: src {} dst {}，源数据保留", src_rows, dst_rows);
+            entry.status = "verify-failed".to_string();
+            failed += 1;
+        } else if !delete {
+            entry.status = "verified".to_string();
+            verified += 1;
+        } else {
+            match delete_segment(opt, &seg, &to).await {
+                Ok(ids) => {
+                    info!("segment {seg} archived, mutations {:?}", ids);
+                    if let Err(e) = save_done_segment(done_segments_file, &format!("{}{}", ARCHIVED_PREFIX, seg)) {
+                        error!("save_done_segment failed: {e}");
+                    }
+                    entry.status = "deleted".to_string();
+                    entry.mutation_ids = ids;
+                    deleted += 1;
+                }
+                Err(e) => {
+                    error!("segment {seg} archive delete failed: {e}");
+                    entry.status = "delete-failed".to_string();
+                    failed += 1;
+                }
+            }
+        }
+        report.lock().unwrap().archive_segments.push(entry);
+    }
+    info!("归档完成: 校验通过未删除 {}，已删除 {}，失败 {}（截止时间 {}）", verified, deleted, failed, cutoff);
+    if failed > 0 {
+        anyhow::bail!(format!("{} 个分段归档校验或删除失败，详见运行报告", failed));
+    }
+    Ok(())
+}
@@ -0,0 +1,257 @@
+// ===================== 历史分段后台校验（--background-verify） =====================
+// 长时间运行的增量/热备模式下，已完成的历史分段可能因源表 TTL、mutation 或误删而与目标端不再一致。
+// --background-verify rate=2/hour strategy=checksum 在增量循环空闲（本轮未派发分段、队列已清空）时，
+// 按速率挑选最久未校验的已完成分段，两端各执行一次服务端 count()/sum(cityHash64(..)) 比对，不读取明细。
+// 每次校验结果追加到断点续传文件（"verified:分段\t时间\tok" 或 "...\tdrift\t源行数\t目标行数\t源校验和\t目标校验和"），
+// 最近一次时间即 last_verified；不一致时告警（日志、--alert-webhook、事件、报告），重启后历史发现从断点续传文件恢复到报告。
+// --only-segments / --segment-range 重新迁移的分段完成后同样比对一次并追加记录
+
+use log::{error, info, warn};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::report::{self, DriftFinding, RunReport};
+use crate::{events, replace, save_done_segment, segment, table_ref, Opt, RunCtx};
+
+// 校验记录在断点续传文件中的前缀
+pub const VERIFIED_PREFIX: &str = "verified:";
+
+// 空闲时一次最多补做的校验数，长时间忙于迁移后不会集中压到两端
+const MAX_PER_IDLE: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Strategy {
+    Checksum, // 行数与全列校验和
+    Count,    // 只比对行数
+}
+
+pub struct BackgroundVerify {
+    interval: Duration, // 两次校验之间的平均间隔
+    strategy: Strategy,
+    next: Instant,
+    webhook: String,
+}
+
+// 解析 rate=N/hour（也支持 /minute、/day）与 strategy=checksum|count，可用空格或逗号分隔
+fn parse(specs: &[String]) -> anyhow::Result<(Duration, Strategy)> {
+    let (mut interval, mut strategy) = (None, Strategy::Checksum);
+    for item in specs.iter().flat_map(|s| s.split(',')).map(str::trim).filter(|s| !s.is_empty()) {
+        match item.split_once('=') {
+            Some(("rate", v)) => {
+                let (n, unit) = v.split_once('/').unwrap_or((v, "hour"));
+                let n: u64 = n.trim().parse().map_err(|_| anyhow::anyhow!(format!("--background-verify rate 格式为 N/hour: {}", v)))?;
+                let per = match unit.trim() {
+                    "minute" | "min" | "m" => 60,
+                    "hour" | "h" => 3600,
+                    "day" | "d" => 86400,
+                    other => anyhow::bail!(format!("--background-verify rate 的单位只能是 minute/hour/day: {}", other)),
+                };
+                if n == 0 {
+                    anyhow::bail!("--background-verify rate 必须大于 0");
+                }
+                interval = Some(Duration::from_secs_f64(per as f64 / n as f64));
+            }
+            Some(("strategy", "checksum")) => strategy = Strategy::Checksum,
+            Some(("strategy", "count")) => strategy = Strategy::Count,
+            Some(("strategy", other)) => anyhow::bail!(format!("--background-verify strategy 只支持 checksum|count: {}", other)),
+            _ => anyhow::bail!(format!("--background-verify 不支持的参数: {}（rate=N/hour strategy=checksum）", item)),
+        }
+    }
+    let interval = interval.ok_or_else(|| anyhow::anyhow!("--background-verify 需要指定 rate=N/hour"))?;
+    Ok((interval, strategy))
+}
+
+fn is_segment(s: &str) -> bool {
+    segment::parse(s).is_some()
+}
+
+// 从断点续传文件读取各分段最近一次校验时间与全部不一致记录（按文件顺序）
+fn load(done_segments_file: &str) -> (HashMap<String, String>, Vec<DriftFinding>) {
+    let (mut last, mut findings) = (HashMap::new(), Vec::new());
+    let text = std::fs::read_to_string(done_segments_file).unwrap_or_default();
+    for line in text.lines() {
+        let Some(rest) = line.strip_prefix(VERIFIED_PREFIX) else { continue };
+        let f: Vec<&str> = rest.split('\t').collect();
+        if f.len() < 3 {
+            continue;
+        }
+        if f[2] == "drift" && f.len() >= 7 {
+            let n = |i: usize| f[i].parse().unwrap_or(0);
+            findings.push(DriftFinding {
+                segment: f[0].to_string(),
+                checked_at: f[1].to_string(),
+                previously_verified_at: last.get(f[0]).cloned(),
+                src_rows: n(3),
+                dst_rows: n(4),
+                src_checksum: n(5),
+                dst_checksum: n(6),
+            });
+        }
+        last.insert(f[0].to_string(), f[1].to_string());
+    }
+    (last, findings)
+}
+
+// 两端比对一个分段并把结果追加到断点续传文件；返回源端行数，不一致时另返回发现
+async fn check(
+    opt: &Opt,
+    ctx: &RunCtx,
+    done_segments_file: &str,
+    seg: &str,
+    strategy: Strategy,
+    prev: Option<String>,
+) -> anyhow::Result<(u64, Option<DriftFinding>)> {
+    // seg 为断点续传中的区间记录（旧版本只记起点的为 1 小时）
+    let (start, end) = segment::parse(seg).ok_or_else(|| anyhow::anyhow!(format!("分段记录格式不正确: {}", seg)))?;
+    let fmt = |t: chrono::NaiveDateTime| t.format("%Y-%m-%d %H:%M:%S").to_string();
+    let window = format!("{} >= {} AND {} < {}{}", opt.time_field, opt.time_lit(&fmt(start)), opt.time_field, opt.time_lit(&fmt(end)), ctx.filter());
+    let exprs = match strategy {
+        Strategy::Checksum => ctx.binary.value_exprs(&ctx.columns.compare),
+        Strategy::Count => vec!["1".to_string()],
+    };
+    let src = table_ref(&opt.src_db, &opt.src_table, ctx.select_final);
+    let dst = table_ref(&opt.dst_db, &ctx.dst_read_table, ctx.dst_select_final);
+    let (src_rows, src_sum) = replace::checksum(&opt.src_dsn, &opt.src_db, &src, &window, &exprs).await?;
+    let (dst_rows, dst_sum) = replace::checksum(&opt.dst_dsn, &opt.dst_db, &dst, &window, &exprs).await?;
+    let checked_at = report::now_str();
+    let matched = (src_rows, src_sum) == (dst_rows, dst_sum);
+    let line = if matched {
+        format!("{}{}\t{}\tok", VERIFIED_PREFIX, seg, checked_at)
+    } else {
+        format!("{}{}\t{}\tdrift\t{}\t{}\t{}\t{}", VERIFIED_PREFIX, seg, checked_at, src_rows, dst_rows, src_sum, dst_sum)
+    };
+    save_done_segment(done_segments_file, &line)?;
+    if matched {
+        return Ok((src_rows, None));
+    }
+    Ok((
+        src_rows,
+        Some(DriftFinding {
+            segment: seg.to_string(),
+            checked_at,
+            previously_verified_at: prev,
+            src_rows,
+            dst_rows,
+            src_checksum: src_sum,
+            dst_checksum: dst_sum,
+        }),
+    ))
+}
+
+// --only-segments / --segment-range 重新迁移的分段完成后按校验和比对，刷新断点续传文件中的校验记录；
+// 仍不一致的写入报告 background_verify。未完成（失败或黑名单跳过）的分段不比对
+pub async fn refresh(opt: &Opt, ctx: &RunCtx, done_segments_file: &str, segments: &[String], report: &Arc<Mutex<RunReport>>) {
+    let (last, _) = load(done_segments_file);
+    let failed: HashSet<String> = ctx.failed_segments.lock().unwrap().iter().cloned().collect();
+    let text = std::fs::read_to_string(done_segments_file).unwrap_or_default();
+    let done: HashSet<&str> = text.lines().filter(|s| is_segment(s)).collect();
+    for (seg, record) in segments.iter().filter(|s| !failed.contains(*s)).map(|s| (s, segment::record(s))).filter(|(_, r)| done.contains(r.as_str())) {
+        match check(opt, ctx, done_segments_file, &record, Strategy::Checksum, last.get(&record).cloned()).await {
+            Ok((rows, None)) => info!("segment {seg} re-run verify ok: rows={}", rows),
+            Ok((_, Some(f))) => {
+                error!(
+                    "segment {seg} re-run verify drift: src_rows={}, dst_rows={}, checksum {} != {}",
+                    f.src_rows, f.dst_rows, f.src_checksum, f.dst_checksum
+                );
+                events::segment_drift(&f);
+                report.lock().unwrap().background_verify.push(f);
+            }
+            Err(e) => warn!("segment {seg} re-run verify failed: {e}"),
+        }
+    }
+}
+
+impl BackgroundVerify {
+    // 未指定 --background-verify 时返回 None；历史发现写入报告
+    pub fn new(opt: &Opt, done_segments_file: &str, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<Option<Self>> {
+        if opt.background_verify.is_empty() {
+            return Ok(None);
+        }
+        let (interval, strategy) = parse(&opt.background_verify)?;
+        if !opt.standby && opt.cutover_when.is_empty() && opt.cutover_at.is_empty() {
+            warn!("后台校验只在长时间运行的增量循环（--standby、--cutover-when 或 --cutover-at）空闲时进行，本次增量无新数据即结束，可能不会执行");
+        }
+        let (_, findings) = load(done_segments_file);
+        if !findings.is_empty() {
+            warn!("后台校验: 断点续传文件中有 {} 条此前发现的分段不一致，详见报告 background_verify", findings.len());
+        }
+        report.lock().unwrap().background_verify = findings;
+        info!("后台校验: 增量空闲时每 {:?} 校验一个最久未校验的已完成分段（{:?}）", interval, strategy);
+        Ok(Some(BackgroundVerify { interval, strategy, next: Instant::now() + interval, webhook: opt.alert_webhook.clone() }))
+    }
+
+    // 增量循环空闲时调用：按速率校验到期数量的分段
+    pub async fn run_idle(&mut self, opt: &Opt, ctx: &RunCtx, done_segments_file: &str, report: &Arc<Mutex<RunReport>>) {
+        let now = Instant::now();
+        if now < self.next {
+            return;
+        }
+        let due = (1 + ((now - self.next).as_secs_f64() / self.interval.as_secs_f64()) as u64).min(MAX_PER_IDLE);
+        self.next = now + self.interval;
+        let (last, _) = load(done_segments_file);
+        let text = std::fs::read_to_string(done_segments_file).unwrap_or_default();
+        let done: HashSet<&str> = text.lines().filter(|s| is_segment(s)).collect();
+        // 从未校验过的分段（按时间从早到晚）优先，其余按上次校验时间从早到晚
+        let mut order: Vec<(Option<&String>, &str)> = done.into_iter().map(|s| (last.get(s), s)).collect();
+        order.sort();
+        for (prev, seg) in order.into_iter().take(due as usize) {
+            if let Err(e) = self.verify_one(opt, ctx, done_segments_file, seg, prev.cloned(), report).await {
+                warn!("segment {seg} background verify failed: {e}");
+            }
+        }
+    }
+
+    async fn verify_one(
+        &self,
+        opt: &Opt,
+        ctx: &RunCtx,
+        done_segments_file: &str,
+        seg: &str,
+        prev: Option<String>,
+        report: &Arc<Mutex<RunReport>>,
+    ) -> anyhow::Result<()> {
+        let (rows, finding) = check(opt, ctx, done_segments_file, seg, self.strategy, prev).await?;
+        let Some(finding) = finding else {
+            info!("segment {seg} background verify ok: rows={}", rows);
+            return Ok(());
+        };
+        error!(
+            "segment {seg} background verify drift: src_rows={}, dst_rows={}, checksum {} != {}, last verified {}",
+            finding.src_rows,
+            finding.dst_rows,
+            finding.src_checksum,
+            finding.dst_checksum,
+            finding.previously_verified_at.as_deref().unwrap_or("never")
+        );
+        events::segment_drift(&finding);
+        self.alert(opt, &finding).await;
+        report.lock().unwrap().background_verify.push(finding);
+        Ok(())
+    }
+
+    // POST 到 --alert-webhook，失败只记日志
+    async fn alert(&self, opt: &Opt, f: &DriftFinding) {
+        if self.webhook.is_empty() {
+            return;
+        }
+        let body = json!({
+            "alert": "segment_drift",
+            "src": format!("{}.{}", opt.src_db, opt.src_table),
+            "dst": format!("{}.{}", opt.dst_db, opt.dst_table),
+            "finding": f,
+        });
+        post_alert(&self.webhook, &body, "后台校验").await;
+    }
+}
+
+// POST 告警 JSON 到 --alert-webhook（--post-cutover-watch 同样使用），失败只记日志
+pub async fn post_alert(webhook: &str, body: &serde_json::Value, who: &str) {
+    let res = reqwest::Client::new().post(webhook).timeout(Duration::from_secs(10)).json(body).send().await;
+    match res {
+        Ok(r) if r.status().is_success() => {}
+        Ok(r) => warn!("{}: 告警 webhook 返回 {}", who, r.status()),
+        Err(e) => warn!("{}: 告警 webhook 发送失败: {}", who, e),
+    }
+}
@@ -0,0 +1,124 @@
+// ===================== 无法解析的行 =====================
+// 源数据中个别行（如 String 列混入非法 UTF-8）无法按 JSON 解析时，默认整个分段失败且永远无法迁移；
+// --on-bad-row skip 跳过这些行并记录数量与字节偏移，dead-letter 同时把原始行写入 --dead-letter-file 供人工修复。
+// 跳过的行数按分段写入断点续传文件与报告，数据缺失是明确且有界的。
+// 指定了 --dead-letter-file 时超过 --max-row-hard-bytes 的行与目标端拒绝的行（--on-rejected-row）也写入该文件（与 --on-bad-row 无关）
+
+use log::warn;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+
+use crate::report::{BadRowSample, BadRowSegment};
+use crate::Opt;
+
+// 跳过行的分段在断点续传文件中的前缀，行格式 "bad-rows:分段\t端\t行数"
+pub const BAD_ROWS_PREFIX: &str = "bad-rows:";
+
+// 每个分段在日志与报告中保留的样例数
+const SAMPLES: usize = 5;
+
+// 样例中保留的原始内容长度
+const PREVIEW_BYTES: usize = 200;
+
+pub type Rows = Vec<HashMap<String, Value>>;
+
+pub struct BadLine {
+    pub offset: usize, // 在响应中的字节偏移
+    pub error: String,
+    pub raw: Vec<u8>,
+}
+
+// 按行解析 JSONEachRow 响应；abort 为 true 时遇到无法解析的行直接返回错误
+pub fn parse_rows(body: &[u8], abort: bool) -> anyhow::Result<(Rows, Vec<BadLine>)> {
+    let (mut rows, mut bad, mut offset) = (Vec::new(), Vec::new(), 0);
+    for line in body.split(|b| *b == b'\n') {
+        let start = offset;
+        offset += line.len() + 1;
+        if line.iter().all(|b| b.is_ascii_whitespace()) {
+            continue;
+        }
+        match serde_json::from_slice(line) {
+            Ok(v) => rows.push(v),
+            Err(e) if abort => anyhow::bail!(format!("偏移 {} 字节处的行无法解析: {}", start, e)),
+            Err(e) => bad.push(BadLine { offset: start, error: e.to_string(), raw: line.to_vec() }),
+        }
+    }
+    Ok((rows, bad))
+}
+
+pub struct BadRows {
+    pub abort: bool,
+    dead_letter_bad: bool, // 坏行写入死信文件
+    dead_letter: Option<Mutex<std::fs::File>>,
+}
+
+impl BadRows {
+    pub fn new(opt: &Opt) -> anyhow::Result<Self> {
+        match opt.on_bad_row.as_str() {
+            "abort" | "skip" => {}
+            "dead-letter" if opt.dead_letter_file.is_empty() => anyhow::bail!("--on-bad-row dead-letter 需要指定 --dead-letter-file"),
+            "dead-letter" => {}
+            other => anyhow::bail!(format!("不支持的 --on-bad-row: {}（abort|skip|dead-letter）", other)),
+        }
+        let dead_letter = if opt.dead_letter_file.is_empty() {
+            None
+        } else {
+            let f = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&opt.dead_letter_file)
+                .map_err(|e| anyhow::anyhow!(format!("打开 --dead-letter-file {} 失败: {}", opt.dead_letter_file, e)))?;
+            Some(Mutex::new(f))
+        };
+        Ok(BadRows { abort: opt.on_bad_row == "abort", dead_letter_bad: opt.on_bad_row == "dead-letter", dead_letter })
+    }
+
+    // 记录一次读取中跳过的行：写日志、写死信文件，返回报告条目
+    pub fn record(&self, seg: &str, side: &str, bad: &[BadLine]) -> anyhow::Result<BadRowSegment> {
+        let offsets: Vec<String> = bad.iter().take(SAMPLES).map(|b| b.offset.to_string()).collect();
+        warn!(
+            "segment {seg} {side}: 跳过 {} 行无法解析的数据，字节偏移 {}{}",
+            bad.len(),
+            offsets.join(","),
+            if bad.len() > SAMPLES { " ..." } else { "" }
+        );
+        if let Some(f) = self.dead_letter.as_ref().filter(|_| self.dead_letter_bad) {
+            let mut f = f.lock().unwrap();
+            for b in bad {
+                writeln!(f, "# segment={} side={} offset={} error={}", seg, side, b.offset, b.error)?;
+                f.write_all(&b.raw)?;
+                f.write_all(b"\n")?;
+            }
+            f.flush()?;
+        }
+        Ok(BadRowSegment {
+            segment: seg.to_string(),
+            side: side.to_string(),
+            skipped: bad.len() as u64,
+            samples: bad
+                .iter()
+                .take(SAMPLES)
+                .map(|b| BadRowSample {
+                    offset: b.offset as u64,
+                    error: b.error.clone(),
+                    preview: String::from_utf8_lossy(&b.raw[..b.raw.len().min(PREVIEW_BYTES)]).to_string(),
+                })
+                .collect(),
+        })
+    }
+
+    // 未写入目标端的行原样写入死信文件，reason 写在注释行中；未指定 --dead-letter-file 时返回错误，分段不会标记完成
+    pub fn write_row(&self, seg: &str, reason: &str, row: &HashMap<String, Value>) -> anyhow::Result<()> {
+        let Some(f) = &self.dead_letter else {
+            anyhow::bail!(format!("{}，未指定 --dead-letter-file，无法跳过", reason));
+        };
+        let mut f = f.lock().unwrap();
+        writeln!(f, "# segment={} {}", seg, reason.replace('\n', " "))?;
+        serde_json::to_writer(&mut *f, row)?;
+        f.write_all(b"\n")?;
+        f.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+// ===================== 基准测试（datacp bench） =====================
+// 在新的集群组合上评估参数时不必手工建测试表：bench 在源端创建 datacp_bench_src_<pid> 表（DateTime、UInt64、Float64、
+// 指定宽度的 String、Nullable、Array 混合的代表性结构），用 INSERT ... SELECT ... FROM numbers(...) 在服务端生成确定性数据，
+// 再对目标端的 datacp_bench_dst_<pid> 表按 batch-bytes × 并发 × 传输格式的组合逐一跑完整迁移流程（每组前清空目标表，
+// 运行文件写在临时目录），最后打印各组合的吞吐与峰值内存对比表。
+// 传输格式：JSONEachRow 为经本进程的 HTTP 拷贝（--copy-mode http），Native 为服务端 remote-secure 拷贝。
+// 所有临时对象带 datacp_bench_ 前缀，正常结束、出错或 Ctrl-C 时都会删除
+
+use log::{info, warn};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{ch_execute_timeout, ch_query_rows, json_u64, qualified, run_migration, report, transfer, Command, Opt};
+
+const PREFIX: &str = "datacp_bench_";
+
+// 服务端生成数据的超时
+const INSERT_TIMEOUT: Duration = Duration::from_secs(3600);
+
+// 第 3 列起按此顺序循环使用的列类型
+const TYPES: [&str; 5] = ["UInt64", "Float64", "String", "Nullable(String)", "Array(UInt32)"];
+
+const FORMATS: [(&str, &str); 2] = [("JSONEachRow", "http"), ("Native", "remote-secure")];
+
+// --rows 允许 5_000_000 的写法
+pub fn parse_rows(s: &str) -> anyhow::Result<u64> {
+    s.replace('_', "").trim().parse().map_err(|_| anyhow::anyhow!(format!("--rows 不是整数: {}", s)))
+}
+
+// bench 的 --dsn / --target-dsn 替代全局的两端 DSN（须在连接配置初始化之前）
+pub fn apply_dsn(opt: &mut Opt) {
+    if let Some(Command::Bench { dsn, target_dsn, .. }) = opt.cmd.clone() {
+        if !dsn.is_empty() {
+            opt.src_dsn = dsn.clone();
+            opt.dst_dsn = dsn;
+        }
+        if !target_dsn.is_empty() {
+            opt.dst_dsn = target_dsn;
+        }
+    }
+}
+
+struct Trial {
+    format: &'static str,
+    batch_bytes: u64,
+    parallelism: usize,
+    seconds: f64,
+    rows: u64,
+    bytes: u64,
+    peak_rss: Option<u64>,
+    result: String,
+}
+
+// 临时表与临时目录
+#[derive(Clone)]
+struct Temp {
+    tables: Vec<(String, String, String)>, // (DSN, 库, 表)
+    dir: PathBuf,
+}
+
+impl Temp {
+    async fn cleanup(&self) {
+        for (dsn, db, table) in &self.tables {
+            let sql = format!("DROP TABLE IF EXISTS {}", qualified(db, table));
+            if let Err(e) = ch_execute_timeout(dsn, db, &sql, Duration::from_secs(60)).await {
+                warn!("删除临时表 {} 失败，请手动删除: {e}", qualified(db, table));
+            }
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn column_defs(columns: usize) -> Vec<(String, &'static str)> {
+    let mut defs = vec![("ts".to_string(), "DateTime"), ("id".to_string(), "UInt64")];
+    for i in 0..columns.saturating_sub(2) {
+        defs.push((format!("c{}", i), TYPES[i % TYPES.len()]));
+    }
+    defs
+}
+
+// 第 i 列的生成表达式（同一 number 总是生成相同取值）
+fn value_expr(i: usize, t: &str, width: usize) -> String {
+    let h = format!("cityHash64(number, {})", i);
+    match t {
+        "UInt64" => h,
+        "Float64" => format!("({} % 100000000) / 100.0", h),
+        "String" => format!("substring(repeat(hex({}), {}), 1, {})", h, width.div_ceil(16).max(1), width),
+        "Nullable(String)" => format!("if(number % 5 = 0, NULL, toString({}))", h),
+        _ => "arrayMap(x -> toUInt32(x + number), range(number % 5))".to_string(),
+    }
+}
+
+// 进程的峰值常驻内存（Linux：VmHWM，每组前写 /proc/self/clear_refs 重置）
+fn reset_peak_rss() {
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb: u64 = status.lines().find_map(|l| l.strip_prefix("VmHWM:"))?.trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb * 1024)
+}
+
+async fn create(dsn: &str, db: &str, table: &str, defs: &[(String, &str)]) -> anyhow::Result<()> {
+    let cols: Vec<String> = defs.iter().map(|(n, t)| format!("`{}` {}", n, t)).collect();
+    let sql = format!(
+        "CREATE TABLE {} ({}) ENGINE = MergeTree PARTITION BY toYYYYMMDD(ts) ORDER BY (ts, id)",
+        qualified(db, table),
+        cols.join(", ")
+    );
+    ch_execute_timeout(dsn, db, &sql, Duration::from_secs(60)).await
+}
+
+async fn count(dsn: &str, db: &str, table: &str) -> anyhow::Result<u64> {
+    let rows = ch_query_rows(dsn, db, &format!("SELECT count() AS c FROM {} FORMAT JSONEachRow", qualified(db, table))).await?;
+    Ok(json_u64(rows.first().and_then(|r| r.get("c"))))
+}
+
+pub async fn run(opt: &Opt) -> anyhow::Result<()> {
+    let Some(Command::Bench { rows, columns, string_width, hours, db, batch_bytes, parallelism, wire_formats, .. }) = &opt.cmd else {
+        return Ok(());
+    };
+    let rows = parse_rows(rows)?;
+    if rows == 0 || *columns < 2 || *hours == 0 {
+        anyhow::bail!("--rows 与 --hours 须大于 0，--columns 至少为 2（ts 与 id）");
+    }
+    let mut formats = Vec::new();
+    for f in wire_formats {
+        match FORMATS.iter().find(|(name, _)| name.eq_ignore_ascii_case(f.trim())) {
+            Some(x) => formats.push(*x),
+            None => anyhow::bail!(format!("--wire-formats 只支持 JSONEachRow,Native: {}", f)),
+        }
+    }
+    let pid = std::process::id();
+    let (src_table, dst_table) = (format!("{}src_{}", PREFIX, pid), format!("{}dst_{}", PREFIX, pid));
+    let temp = Temp {
+        tables: vec![(opt.src_dsn.clone(), db.clone(), src_table.clone()), (opt.dst_dsn.clone(), db.clone(), dst_table.clone())],
+        dir: std::env::temp_dir().join(format!("{}{}", PREFIX, pid)),
+    };
+    // Ctrl-C 时删除临时对象后退出
+    let on_interrupt = temp.clone();
+    let interrupt = tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("已中断，正在删除 {}* 临时表", PREFIX);
+            on_interrupt.cleanup().await;
+            std::process::exit(130);
+        }
+    });
+    let res = trials(opt, rows, *columns, *string_width, *hours, db, (&src_table, &dst_table), batch_bytes, parallelism, &formats, &temp).await;
+    interrupt.abort();
+    temp.cleanup().await;
+    let trials = res?;
+    print(rows, &trials);
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn trials(
+    opt: &Opt,
+    rows: u64,
+    columns: usize,
+    width: usize,
+    hours: u64,
+    db: &str,
+    (src_table, dst_table): (&str, &str),
+    batch_bytes: &[u64],
+    parallelism: &[usize],
+    formats: &[(&'static str, &'static str)],
+    temp: &Temp,
+) -> anyhow::Result<Vec<Trial>> {
+    let defs = column_defs(columns);
+    create(&opt.src_dsn, db, src_table, &defs).await?;
+    create(&opt.dst_dsn, db, dst_table, &defs).await?;
+    // 数据均匀分布在 2024-01-01 起的 hours 个小时分段中
+    let mut exprs = vec![
+        format!("toDateTime('2024-01-01 00:00:00') + intDiv(number * {}, {})", hours * 3600, rows),
+        "number".to_string(),
+    ];
+    exprs.extend(defs.iter().skip(2).enumerate().map(|(i, (_, t))| value_expr(i, t, width)));
+    info!("bench: 源端 {} 生成 {} 行（{} 列，String 宽度 {}，{} 个小时分段）", qualified(db, src_table), rows, columns, width, hours);
+    let started = Instant::now();
+    let sql = format!("INSERT INTO {} SELECT {} FROM numbers({})", qualified(db, src_table), exprs.join(", "), rows);
+    ch_execute_timeout(&opt.src_dsn, db, &sql, INSERT_TIMEOUT).await?;
+    let generated = count(&opt.src_dsn, db, src_table).await?;
+    if generated != rows {
+        anyhow::bail!(format!("bench 源表生成 {} 行，预期 {} 行", generated, rows));
+    }
+    info!("bench: 数据生成完成，用时 {:.1}s", started.elapsed().as_secs_f64());
+    let mut out = Vec::new();
+    for &(format, copy_mode) in formats {
+        for &b in batch_bytes {
+            for &p in parallelism {
+                let n = out.len();
+                ch_execute_timeout(&opt.dst_dsn, db, &format!("TRUNCATE TABLE {}", qualified(db, dst_table)), Duration::from_secs(60)).await?;
+                let mut t = opt.clone();
+                t.cmd = None;
+                t.src_db = db.to_string();
+                t.dst_db = db.to_string();
+                t.src_table = src_table.to_string();
+                t.dst_table = dst_table.to_string();
+                t.dst_read_table = String::new();
+                t.time_field = "ts".to_string();
+                t.time_expr = String::new();
+                t.tables_file = String::new();
+                t.all_tables = false;
+                t.no_cutover = true;
+                t.skip_disk_check = true;
+                t.copy_mode = copy_mode.to_string();
+                t.batch_bytes = b;
+                t.parallelism = p.max(1);
+                t.state_dir = temp.dir.join(n.to_string()).to_string_lossy().to_string();
+                t.done_segments = String::new();
+                let done = crate::state_dir::done_segments(&t)?;
+                let report = Arc::new(std::sync::Mutex::new(report::RunReport::new(&t)));
+                info!("bench: 第 {} 组 {} batch-bytes {} 并发 {}", n + 1, format, transfer::human(b as f64), p);
+                reset_peak_rss();
+                let started = Instant::now();
+                let res = run_migration(&t, &done, report.clone(), Arc::new(tokio::sync::Semaphore::new(tokio::sync::Semaphore::MAX_PERMITS)), None).await;
+                let seconds = started.elapsed().as_secs_f64();
+                let r = report.lock().unwrap().clone();
+                let copied = count(&opt.dst_dsn, db, dst_table).await.unwrap_or(0);
+                let result = match res {
+                    Err(e) => format!("failed: {}", e),
+                    Ok(()) if !r.segments_failed.is_empty() => format!("failed: {} 个分段失败", r.segments_failed.len()),
+                    Ok(()) if copied != rows => format!("mismatch: 目标端 {} 行", copied),
+                    Ok(()) => "ok".to_string(),
+                };
+                out.push(Trial {
+                    format,
+                    batch_bytes: b,
+                    parallelism: p,
+                    seconds,
+                    rows: copied,
+                    bytes: r.segment_bytes.iter().map(|s| s.wire_bytes).sum(),
+                    peak_rss: peak_rss(),
+                    result,
+                });
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn print(rows: u64, trials: &[Trial]) {
+    println!("datacp bench: {} 行", rows);
+    println!("{:<12} {:>12} {:>6} {:>10} {:>12} {:>12} {:>12}  result", "format", "batch_bytes", "par", "seconds", "rows/s", "wire/s", "peak_rss");
+    for t in trials {
+        let rate = |v: f64| if t.seconds > 0.0 { v / t.seconds } else { 0.0 };
+        println!(
+            "{:<12} {:>12} {:>6} {:>10.1} {:>12.0} {:>12} {:>12}  {}",
+            t.format,
+            if t.batch_bytes == 0 { "5000 rows".to_string() } else { transfer::human(t.batch_bytes as f64) },
+            t.parallelism,
+            t.seconds,
+            rate(t.rows as f64),
+            transfer::human(rate(t.bytes as f64)),
+            t.peak_rss.map(|b| transfer::human(b as f64)).unwrap_or_else(|| "-".to_string()),
+            t.result
+        );
+    }
+    if let Some(best) = trials.iter().filter(|t| t.result == "ok").max_by(|a, b| (a.rows as f64 / a.seconds).total_cmp(&(b.rows as f64 / b.seconds))) {
+        println!(
+            "吞吐最高: --copy-mode {} --batch-bytes {} --parallelism {}",
+            FORMATS.iter().find(|(f, _)| *f == best.format).map(|(_, m)| *m).unwrap_or("http"),
+            best.batch_bytes,
+            best.parallelism
+        );
+    }
+}
@@ -0,0 +1,241 @@
+// ===================== 二进制列 =====================
+// FixedString 及含非 UTF-8 字节的 String 列经 JSONEachRow 输出时转义不稳定，同一行在两端的摘要可能不同，
+// 写回后也未必逐字节一致。这些列在两端都以 hex(col) AS col 读取（摘要只看十六进制文本），
+// 写入时经 INSERT ... SELECT CAST(unhex(col), 类型) FROM input(...) 还原原始字节。
+// --column-default 覆盖的列也在这里替换为 ifNull(col, value)，两端读取一致
+
+use log::info;
+use std::collections::{HashMap, HashSet};
+
+use crate::normalize::Normalize;
+use crate::{ch_query_rows, qualified, Opt};
+
+// 探测 String 列是否含非 UTF-8 字节时抽样的行数
+const UTF8_SAMPLE_ROWS: u64 = 100000;
+
+#[derive(Default)]
+pub struct BinaryColumns {
+    types: Vec<(String, String)>, // 迁移字段及其类型，按字段计划的顺序
+    binary: HashSet<String>,
+    defaults: HashMap<String, String>, // --column-default 覆盖值
+    normalize: Normalize,              // --normalize 比对前规范化
+}
+
+fn is_fixed_string(t: &str) -> bool {
+    t.contains("FixedString(")
+}
+
+fn is_string(t: &str) -> bool {
+    let t = t.trim_start_matches("LowCardinality(").trim_start_matches("Nullable(");
+    t.starts_with("String")
+}
+
+// 列名用于 SQL：普通标识符原样，Nested 子列（events.ts）等含其他字符的加反引号
+pub fn quote_ident(c: &str) -> String {
+    if !c.is_empty() && !c.starts_with(|ch: char| ch.is_ascii_digit()) && c.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '_') {
+        c.to_string()
+    } else {
+        format!("`{}`", c.replace('`', "\\`"))
+    }
+}
+
+impl BinaryColumns {
+    pub fn new(types: Vec<(String, String)>, binary: HashSet<String>) -> Self {
+        BinaryColumns { types, binary, defaults: HashMap::new(), normalize: Normalize::default() }
+    }
+
+    pub fn with_defaults(mut self, defaults: HashMap<String, String>) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
+    pub fn with_normalize(mut self, normalize: Normalize) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    pub fn is_binary(&self, c: &str) -> bool {
+        self.binary.contains(c)
+    }
+
+    // 列的读取表达式（不含别名）：有覆盖值时为 ifNull(col, value)
+    fn value_expr(&self, c: &str) -> String {
+        match self.defaults.get(c) {
+            Some(v) => format!("ifNull({}, {})", quote_ident(c), v),
+            None => quote_ident(c),
+        }
+    }
+
+    // 迁移字段的类型（DESCRIBE 中的 type）
+    pub fn type_of(&self, c: &str) -> Option<&str> {
+        self.types.iter().find(|(n, _)| n == c).map(|(_, t)| t.as_str())
+    }
+
+    // 各列的比对表达式，用于服务端校验和与摘要（--normalize 的列再做规范化）
+    pub fn value_exprs(&self, col_names: &[String]) -> Vec<String> {
+        col_names.iter().map(|c| self.normalize.expr(c, self.value_expr(c))).collect()
+    }
+
+    // 由源表 DESCRIBE 结果探测：FixedString 列、--binary-columns 指定的列，以及抽样中含非 UTF-8 字节的 String 列
+    pub async fn detect(opt: &Opt, col_names: &[String]) -> anyhow::Result<Self> {
+        let table = qualified(&opt.src_db, &opt.src_table);
+        let sql = format!("DESCRIBE TABLE {} FORMAT JSONEachRow", table);
+        let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+        let mut types = Vec::new();
+        for c in col_names {
+            let t = rows
+                .iter()
+                .find(|r| r.get("name").and_then(|v| v.as_str()) == Some(c.as_str()))
+                .and_then(|r| r.get("type").and_then(|v| v.as_str()))
+                .ok_or_else(|| anyhow::anyhow!(format!("DESCRIBE {} 中没有字段 {}", opt.src_table, c)))?;
+            types.push((c.clone(), t.to_string()));
+        }
+        let mut binary: HashSet<String> = types.iter().filter(|(_, t)| is_fixed_string(t)).map(|(c, _)| c.clone()).collect();
+        for c in &opt.binary_columns {
+            if !col_names.contains(c) {
+                anyhow::bail!(format!("--binary-columns 中的 {} 不在迁移字段中", c));
+            }
+            binary.insert(c.clone());
+        }
+        let strings: Vec<&String> = types.iter().filter(|(c, t)| is_string(t) && !binary.contains(c)).map(|(c, _)| c).collect();
+        if !strings.is_empty() {
+            let checks: Vec<String> = strings.iter().map(|c| format!("max(NOT isValidUTF8(ifNull({c}, ''))) AS {c}")).collect();
+            let sql = format!(
+                "SELECT {} FROM (SELECT {} FROM {} LIMIT {}) FORMAT JSONEachRow",
+                checks.join(", "),
+                strings.iter().map(|c| c.as_str()).collect::<Vec<_>>().join(","),
+                table,
+                UTF8_SAMPLE_ROWS
+            );
+            let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+            if let Some(r) = rows.first() {
+                for c in strings {
+                    if crate::json_u64(r.get(c)) > 0 {
+                        binary.insert(c.clone());
+                    }
+                }
+            }
+        }
+        if !binary.is_empty() {
+            let mut cols: Vec<&String> = binary.iter().collect();
+            cols.sort();
+            info!("二进制列按 hex 读取、unhex 写入: {}", cols.iter().map(|c| c.as_str()).collect::<Vec<_>>().join(","));
+        }
+        Ok(BinaryColumns::new(types, binary))
+    }
+
+    // SELECT 字段列表，二进制列以十六进制读取，覆盖默认值的列以 ifNull(col, value) 读取
+    pub fn select_list(&self, col_names: &[String]) -> String {
+        col_names
+            .iter()
+            .map(|c| match (self.binary.contains(c), self.defaults.contains_key(c)) {
+                (true, _) => format!("hex({}) AS {}", self.value_expr(c), quote_ident(c)),
+                (false, true) => format!("{} AS {}", self.value_expr(c), quote_ident(c)),
+                _ => quote_ident(c),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    // 写入语句：显式列出字段（字段计划的顺序）；有二进制列时经 input() 将十六进制还原为原始字节
+    pub fn insert_sql(&self, table: &str) -> String {
+        if self.types.is_empty() {
+            return format!("INSERT INTO {} FORMAT JSONEachRow", table);
+        }
+        if self.binary.is_empty() {
+            let cols: Vec<String> = self.types.iter().map(|(c, _)| quote_ident(c)).collect();
+            return format!("INSERT INTO {} ({}) FORMAT JSONEachRow", table, cols.join(","));
+        }
+        let mut cols = Vec::new();
+        let mut exprs = Vec::new();
+        let mut structure = Vec::new();
+        for (name, t) in &self.types {
+            let c = quote_ident(name);
+            cols.push(c.clone());
+            if self.binary.contains(name) {
+                exprs.push(format!("CAST(unhex({c}), '{}')", t.replace('\'', "\\'")));
+                structure.push(format!("{c} {}", if t.contains("Nullable(") { "Nullable(String)" } else { "String" }));
+            } else {
+                exprs.push(c.clone());
+                structure.push(format!("{c} {t}"));
+            }
+        }
+        format!(
+            "INSERT INTO {} ({}) SELECT {} FROM input('{}') FORMAT JSONEachRow",
+            table,
+            cols.join(","),
+            exprs.join(","),
+            structure.join(", ").replace('\\', "\\\\").replace('\'', "\\'")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    // ClickHouse hex() 输出大写十六进制
+    fn ch_hex(b: &[u8]) -> String {
+        b.iter().map(|x| format!("{:02X}", x)).collect()
+    }
+
+    fn ch_unhex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn digest(row: &HashMap<String, Value>) -> [u8; 32] {
+        datacp::row_digest(row, &row.keys().cloned().collect::<Vec<_>>())
+    }
+
+    fn cols() -> BinaryColumns {
+        BinaryColumns::new(
+            vec![
+                ("id".to_string(), "UInt64".to_string()),
+                ("uid".to_string(), "FixedString(16)".to_string()),
+                ("tag".to_string(), "Nullable(FixedString(4))".to_string()),
+                ("e".to_string(), "Enum8('a' = 1)".to_string()),
+            ],
+            ["uid", "tag"].iter().map(|s| s.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn select_and_insert_sql() {
+        let c = cols();
+        let names: Vec<String> = ["id", "uid", "tag", "e"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(c.select_list(&names), "id,hex(uid) AS uid,hex(tag) AS tag,e");
+        assert_eq!(
+            c.insert_sql("db.t"),
+            "INSERT INTO db.t (id,uid,tag,e) SELECT id,CAST(unhex(uid), 'FixedString(16)'),CAST(unhex(tag), 'Nullable(FixedString(4))'),e \
+             FROM input('id UInt64, uid String, tag Nullable(String), e Enum8(\\'a\\' = 1)') FORMAT JSONEachRow"
+        );
+        assert_eq!(BinaryColumns::default().insert_sql("t"), "INSERT INTO t FORMAT JSONEachRow");
+    }
+
+    #[test]
+    fn random_binary_round_trip() {
+        // xorshift，避免引入随机数依赖
+        let mut x: u64 = 0x9E3779B97F4A7C15;
+        let mut next = || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+        for i in 0..500 {
+            let bytes: Vec<u8> = (0..16).map(|_| next() as u8).collect();
+            let mut src = HashMap::new();
+            src.insert("id".to_string(), Value::from(i));
+            src.insert("uid".to_string(), Value::String(ch_hex(&bytes)));
+            // 写入批次经 JSON 序列化，目标端读回的十六进制文本与源端相同
+            let batches = crate::insert_batches(std::slice::from_ref(&src), 0);
+            let (back, bad) = crate::bad_rows::parse_rows(batches[0].0.as_bytes(), true).unwrap();
+            assert!(bad.is_empty());
+            let dst = &back[0];
+            assert_eq!(digest(&src), digest(dst));
+            assert_eq!(ch_unhex(dst["uid"].as_str().unwrap()), bytes);
+        }
+    }
+}
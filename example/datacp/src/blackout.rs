@@ -0,0 +1,282 @@
+// ===================== 维护窗口（--blackout） =====================
+// 目标集群在固定时段运行重负载任务（如夜间 ETL）时，--blackout "01:00-03:00" 期间暂停写入：
+// worker 做完手上的分段后不再开始新分段，空闲等待，窗口结束后自动继续；切换（_bak 补差与 rename）也等到窗口外才开始。
+// 可重复指定，格式为 [星期] HH:MM-HH:MM [时区]：星期如 Mon-Fri、Sat,Sun（跨午夜的窗口以开始那天为准），
+// 时区为 local（默认）、UTC 或 +08:00 形式的固定偏移。按该时区的墙上时间匹配，夏令时切换当天同样按钟表时间判断：
+// 拨快时跳过的时刻不会出现，拨慢时重复的那一小时两次都按同一钟点判断。
+// 暂停总时长（进程级）显示在状态接口与报告中；--pause-clock-during-blackout 时不计入 --max-duration
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDateTime, NaiveTime, Utc, Weekday};
+use log::{info, warn};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::{status, Opt};
+
+// 暂停期间检查窗口是否结束的间隔
+const POLL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Zone {
+    Local,
+    Fixed(FixedOffset),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    spec: String,
+    days: [bool; 7], // 周一为 0
+    start: NaiveTime,
+    end: NaiveTime,
+    zone: Zone,
+}
+
+fn parse_day(s: &str) -> anyhow::Result<Weekday> {
+    let s = s.trim().to_lowercase();
+    let day = match s.get(..3).unwrap_or("") {
+        "mon" => Weekday::Mon,
+        "tue" => Weekday::Tue,
+        "wed" => Weekday::Wed,
+        "thu" => Weekday::Thu,
+        "fri" => Weekday::Fri,
+        "sat" => Weekday::Sat,
+        "sun" => Weekday::Sun,
+        _ => anyhow::bail!(format!("--blackout 星期格式不正确: {}（Mon..Sun）", s)),
+    };
+    Ok(day)
+}
+
+// Mon-Fri、Sat,Sun、Fri-Mon（跨周）
+fn parse_days(s: &str) -> anyhow::Result<[bool; 7]> {
+    let mut days = [false; 7];
+    for part in s.split(',').filter(|p| !p.trim().is_empty()) {
+        match part.split_once('-') {
+            Some((a, b)) => {
+                let (mut d, b) = (parse_day(a)?, parse_day(b)?);
+                loop {
+                    days[d.num_days_from_monday() as usize] = true;
+                    if d == b {
+                        break;
+                    }
+                    d = d.succ();
+                }
+            }
+            None => days[parse_day(part)?.num_days_from_monday() as usize] = true,
+        }
+    }
+    Ok(days)
+}
+
+fn parse_zone(s: &str) -> anyhow::Result<Zone> {
+    match s.to_lowercase().as_str() {
+        "local" => return Ok(Zone::Local),
+        "utc" | "z" => return Ok(Zone::Fixed(FixedOffset::east_opt(0).unwrap())),
+        _ => {}
+    }
+    let off = s.trim_start_matches("UTC").trim_start_matches("utc");
+    let (sign, rest) = match off.chars().next() {
+        Some('+') => (1, &off[1..]),
+        Some('-') => (-1, &off[1..]),
+        _ => anyhow::bail!(format!("--blackout 时区只支持 local、UTC 或 +08:00 形式的固定偏移: {}", s)),
+    };
+    let (h, m) = rest.split_once(':').unwrap_or((rest, "0"));
+    let secs = match (h.parse::<i32>(), m.parse::<i32>()) {
+        (Ok(h), Ok(m)) if h <= 14 && m < 60 => sign * (h * 3600 + m * 60),
+        _ => anyhow::bail!(format!("--blackout 时区偏移格式不正确: {}", s)),
+    };
+    Ok(Zone::Fixed(FixedOffset::east_opt(secs).unwrap()))
+}
+
+// [星期] HH:MM-HH:MM [时区]
+pub fn parse(spec: &str) -> anyhow::Result<Window> {
+    let (mut days, mut range, mut zone) = (None, None, Zone::Local);
+    for tok in spec.split_whitespace() {
+        if tok.contains(':') && tok.contains('-') && tok.chars().next().is_some_and(|c| c.is_ascii_digit()) {
+            range = Some(tok);
+        } else if range.is_none() && days.is_none() {
+            days = Some(parse_days(tok)?);
+        } else {
+            zone = parse_zone(tok)?;
+        }
+    }
+    let Some(range) = range else {
+        anyhow::bail!(format!("--blackout 格式为 [星期] HH:MM-HH:MM [时区]: {}", spec));
+    };
+    let (a, b) = range.split_once('-').unwrap();
+    let time = |t: &str| {
+        NaiveTime::parse_from_str(t, "%H:%M").map_err(|_| anyhow::anyhow!(format!("--blackout 时间格式为 HH:MM: {}", t)))
+    };
+    // 24:00 表示当天结束
+    let (start, end) = (time(a)?, if b == "24:00" { NaiveTime::MIN } else { time(b)? });
+    if start == end {
+        anyhow::bail!(format!("--blackout 窗口的开始与结束相同: {}", spec));
+    }
+    Ok(Window { spec: spec.trim().to_string(), days: days.unwrap_or([true; 7]), start, end, zone })
+}
+
+impl Window {
+    fn on(&self, d: Weekday) -> bool {
+        self.days[d.num_days_from_monday() as usize]
+    }
+
+    // 墙上时间 t 是否在窗口内；跨午夜的窗口后半段属于前一天
+    fn contains(&self, t: NaiveDateTime) -> bool {
+        let (d, time) = (t.weekday(), t.time());
+        if self.start < self.end {
+            self.on(d) && time >= self.start && time < self.end
+        } else {
+            (self.on(d) && time >= self.start) || (self.on(d.pred()) && time < self.end)
+        }
+    }
+
+    fn active(&self, now: DateTime<Utc>) -> bool {
+        let wall = match self.zone {
+            Zone::Local => now.with_timezone(&chrono::Local).naive_local(),
+            Zone::Fixed(o) => now.with_timezone(&o).naive_local(),
+        };
+        self.contains(wall)
+    }
+}
+
+struct Blackout {
+    windows: Vec<Window>,
+    pause_clock: bool,
+    paused_ms: AtomicU64,
+    since: Mutex<Option<Instant>>, // 当前暂停的开始时间
+}
+
+static BLACKOUT: OnceLock<Blackout> = OnceLock::new();
+
+// 进程启动时解析 --blackout（多表迁移共享）
+pub fn init(opt: &Opt) -> anyhow::Result<()> {
+    if opt.blackout.is_empty() {
+        if opt.pause_clock_during_blackout {
+            warn!("未指定 --blackout，--pause-clock-during-blackout 不起作用");
+        }
+        return Ok(());
+    }
+    let windows = opt.blackout.iter().map(|s| parse(s)).collect::<anyhow::Result<Vec<_>>>()?;
+    info!("维护窗口: {}", windows.iter().map(|w| w.spec.as_str()).collect::<Vec<_>>().join("; "));
+    let _ = BLACKOUT.set(Blackout { windows, pause_clock: opt.pause_clock_during_blackout, paused_ms: AtomicU64::new(0), since: Mutex::new(None) });
+    Ok(())
+}
+
+fn current(b: &Blackout) -> Option<&Window> {
+    let now = Utc::now();
+    b.windows.iter().find(|w| w.active(now))
+}
+
+// 开始新分段或切换前调用：在维护窗口内时等待窗口结束。多个 worker 同时等待时只记一段暂停
+pub async fn wait() {
+    let Some(b) = BLACKOUT.get() else { return };
+    let Some(w) = current(b) else { return };
+    {
+        let mut since = b.since.lock().unwrap();
+        if since.is_none() {
+            *since = Some(Instant::now());
+            warn!("进入维护窗口 {}，当前分段完成后暂停写入，窗口结束后自动继续", w.spec);
+        }
+    }
+    while current(b).is_some() {
+        tokio::time::sleep(POLL).await;
+        status::progress();
+    }
+    let started = b.since.lock().unwrap().take();
+    if let Some(t) = started {
+        b.paused_ms.fetch_add(t.elapsed().as_millis() as u64, Ordering::SeqCst);
+        info!("维护窗口结束，暂停 {:.0}s 后继续写入（累计 {}s）", t.elapsed().as_secs_f64(), paused_seconds());
+    }
+}
+
+// 累计暂停时长，含正在进行的暂停
+fn paused() -> Duration {
+    let Some(b) = BLACKOUT.get() else { return Duration::ZERO };
+    let open = b.since.lock().unwrap().map(|t| t.elapsed()).unwrap_or_default();
+    Duration::from_millis(b.paused_ms.load(Ordering::SeqCst)) + open
+}
+
+pub fn paused_seconds() -> u64 {
+    paused().as_secs()
+}
+
+pub fn pausing() -> bool {
+    BLACKOUT.get().is_some_and(|b| b.since.lock().unwrap().is_some())
+}
+
+// --pause-clock-during-blackout 时从 --max-duration 中扣除的时长
+pub fn excluded() -> Duration {
+    match BLACKOUT.get() {
+        Some(b) if b.pause_clock => paused(),
+        _ => Duration::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn parses_days_times_and_zones() {
+        let w = parse("01:00-03:00").unwrap();
+        assert_eq!(w.days, [true; 7]);
+        assert_eq!(w.zone, Zone::Local);
+        let w = parse("Mon-Fri 01:00-03:00 UTC").unwrap();
+        assert_eq!(w.days, [true, true, true, true, true, false, false]);
+        assert_eq!(w.zone, Zone::Fixed(FixedOffset::east_opt(0).unwrap()));
+        let w = parse("Fri-Mon 22:00-06:00 +08:00").unwrap();
+        assert_eq!(w.days, [true, false, false, false, true, true, true]);
+        assert_eq!(w.zone, Zone::Fixed(FixedOffset::east_opt(8 * 3600).unwrap()));
+        assert_eq!(parse("sat,sun 10:00-12:00 UTC-05:30").unwrap().zone, Zone::Fixed(FixedOffset::west_opt(5 * 3600 + 1800).unwrap()));
+        for bad in ["", "Mon", "01:00-01:00", "25:00-03:00", "Funday 01:00-03:00", "01:00-03:00 Europe/Berlin"] {
+            assert!(parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let w = parse("01:00-03:00").unwrap();
+        assert!(!w.contains(at("2024-05-01 00:59")));
+        assert!(w.contains(at("2024-05-01 01:00")));
+        assert!(w.contains(at("2024-05-01 02:59")));
+        assert!(!w.contains(at("2024-05-01 03:00")));
+    }
+
+    #[test]
+    fn window_spanning_midnight_belongs_to_start_day() {
+        // 2024-05-03 为周五
+        let w = parse("Fri 23:00-02:00").unwrap();
+        assert!(!w.contains(at("2024-05-03 22:59")));
+        assert!(w.contains(at("2024-05-03 23:30")));
+        assert!(w.contains(at("2024-05-04 01:59")));
+        assert!(!w.contains(at("2024-05-04 02:00")));
+        assert!(!w.contains(at("2024-05-04 23:30")));
+        // 周四开始的窗口不存在，周五凌晨不在窗口内
+        assert!(!w.contains(at("2024-05-03 01:00")));
+        let all = parse("22:00-24:00").unwrap();
+        assert!(all.contains(at("2024-05-03 23:59")));
+        assert!(!all.contains(at("2024-05-04 00:00")));
+    }
+
+    #[test]
+    fn dst_transitions_follow_the_wall_clock() {
+        let w = parse("01:00-03:00").unwrap();
+        let (cet, cest) = (FixedOffset::east_opt(3600).unwrap(), FixedOffset::east_opt(7200).unwrap());
+        let utc = |s: &str| at(s).and_utc();
+        // 2024-03-31 欧洲中部拨快：UTC 01:00 时 02:00 CET 变为 03:00 CEST，窗口只持续一小时
+        assert!(w.contains(utc("2024-03-31 00:30").with_timezone(&cet).naive_local()));
+        assert!(!w.contains(utc("2024-03-31 01:00").with_timezone(&cest).naive_local()));
+        // 2024-10-27 拨慢：02:00-03:00 出现两次，两次都在窗口内，03:00 CET 之后结束
+        assert!(w.contains(utc("2024-10-27 00:30").with_timezone(&cest).naive_local()));
+        assert!(w.contains(utc("2024-10-27 01:30").with_timezone(&cet).naive_local()));
+        assert!(!w.contains(utc("2024-10-27 02:00").with_timezone(&cet).naive_local()));
+        // 固定偏移时区按该偏移的钟点匹配
+        let w = parse("01:00-03:00 +08:00").unwrap();
+        assert!(w.active(utc("2024-05-01 17:30")));
+        assert!(!w.active(utc("2024-05-01 19:00")));
+    }
+}
@@ -0,0 +1,98 @@
+// ===================== 启动时吞吐校准 =====================
+// --calibrate：正式迁移前用前若干待迁移分段试跑 batch-bytes 与并发的组合，
+// 统计各组合的吞吐（行/秒）与错误率，选出错误最少、吞吐最高的组合继续迁移；
+// 试跑使用正常 worker，完成的分段照常写入断点续传文件
+
+use log::{info, warn};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::report::{Calibration, CalibrationTrial, RunReport};
+use crate::{endpoint, run_segment_workers, Opt, RunCtx};
+
+const BATCH_BYTES: [u64; 3] = [8 << 20, 32 << 20, 128 << 20];
+const PARALLELISM: [usize; 3] = [2, 4, 8];
+
+// 从 segments 头部取出试跑分段，返回按最优组合调整后的参数
+pub async fn run(
+    opt: &Opt,
+    segments: &mut Vec<String>,
+    col_names: &[String],
+    done_segments_file: &str,
+    client: &Arc<endpoint::Clients>,
+    ctx: &Arc<RunCtx>,
+    report: &Arc<Mutex<RunReport>>,
+) -> Opt {
+    let mut tuned = opt.clone();
+    if opt.copy_mode != "http" {
+        warn!("--calibrate 只支持 --copy-mode http，按配置参数迁移");
+        return tuned;
+    }
+    let mut parallelism: Vec<usize> = PARALLELISM.iter().copied().filter(|p| *p <= opt.parallelism).collect();
+    if parallelism.is_empty() {
+        parallelism.push(opt.parallelism.max(1));
+    }
+    let mut trials = Vec::new();
+    'outer: for &batch_bytes in &BATCH_BYTES {
+        for &p in &parallelism {
+            if segments.len() < p {
+                warn!("待迁移分段不足，校准提前结束（已完成 {} 组）", trials.len());
+                break 'outer;
+            }
+            let trial_segments: Vec<String> = segments.drain(..p).collect();
+            let mut t = opt.clone();
+            t.parallelism = p;
+            ctx.batch_bytes.store(batch_bytes, Ordering::Relaxed);
+            let rows_before = ctx.rows_read.load(Ordering::Relaxed);
+            let errors_before = ctx.insert_errors.load(Ordering::Relaxed);
+            let failed_before = ctx.failed_segments.lock().unwrap().len();
+            let started = Instant::now();
+            run_segment_workers(&t, &opt.src_table, trial_segments, col_names, done_segments_file, client, ctx).await;
+            let seconds = started.elapsed().as_secs_f64();
+            let rows = ctx.rows_read.load(Ordering::Relaxed) - rows_before;
+            let failed_batches = ctx.insert_errors.load(Ordering::Relaxed) - errors_before;
+            let failed_segments = ctx.failed_segments.lock().unwrap().len() - failed_before;
+            let trial = CalibrationTrial {
+                batch_bytes,
+                parallelism: p,
+                segments: p,
+                rows,
+                seconds,
+                rows_per_sec: if seconds > 0.0 { rows as f64 / seconds } else { 0.0 },
+                error_rate: failed_segments as f64 / p as f64,
+                failed_batches,
+            };
+            info!(
+                "校准: batch-bytes {}M 并发 {}: {} 行 {:.1}s，{:.0} 行/s，失败分段 {}，失败批次 {}",
+                batch_bytes >> 20, p, rows, seconds, trial.rows_per_sec, failed_segments, failed_batches
+            );
+            trials.push(trial);
+        }
+    }
+    // 错误率最低者优先，其次失败批次最少，再按吞吐
+    let best = trials
+        .iter()
+        .min_by(|a, b| {
+            a.error_rate
+                .total_cmp(&b.error_rate)
+                .then(a.failed_batches.cmp(&b.failed_batches))
+                .then(b.rows_per_sec.total_cmp(&a.rows_per_sec))
+        })
+        .map(|b| (b.batch_bytes, b.parallelism));
+    match best {
+        Some((batch_bytes, p)) => {
+            info!("校准完成: 选用 batch-bytes {}M、并发 {}", batch_bytes >> 20, p);
+            tuned.batch_bytes = batch_bytes;
+            tuned.parallelism = p;
+        }
+        None => warn!("没有可用于校准的分段，按配置参数迁移"),
+    }
+    ctx.batch_bytes.store(tuned.batch_bytes, Ordering::Relaxed);
+    report.lock().unwrap().calibration = Some(Calibration {
+        trials,
+        chosen_batch_bytes: tuned.batch_bytes,
+        chosen_parallelism: tuned.parallelism,
+    });
+    tuned
+}
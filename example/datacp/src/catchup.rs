@@ -0,0 +1,137 @@
+// ===================== 增量追平与切换时机 =====================
+// 默认增量循环在首次没有新数据时即进入切换；设置 --cutover-when / --cutover-at 后，
+// 每轮增量后计算源表最新时间与已迁移位置的差距（lag）及源表写入速率，
+// 条件连续满足 N 次或到达指定时间才进入 rename 阶段，否则继续增量追平。
+// lag 同时写入状态接口（/status、/metrics 的 datacp_replication_lag_seconds）与报告 replication_lag
+
+use log::info;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use crate::{ch_query_rows, clock_skew, filter_sql, json_u64, parse_duration_str, qualified, segment, Opt};
+
+// --cutover-when 解析结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct CutoverWhen {
+    pub max_lag: Option<Duration>,
+    pub max_rate: Option<f64>, // 行/秒
+    pub checks: u32,           // 连续满足次数
+}
+
+// 解析 "lag<30s for 3 checks"、"lag<1m and rate<100/s for 5 checks"
+pub fn parse_cutover_when(s: &str) -> anyhow::Result<CutoverWhen> {
+    let s = s.trim();
+    let (conds, checks) = match s.split_once(" for ") {
+        Some((c, n)) => {
+            let n = n.trim().trim_end_matches("checks").trim_end_matches("check").trim();
+            let n: u32 = n.parse().map_err(|_| anyhow::anyhow!(format!("--cutover-when 次数不正确: {}", s)))?;
+            (c, n.max(1))
+        }
+        None => (s, 1),
+    };
+    let mut when = CutoverWhen { max_lag: None, max_rate: None, checks };
+    for cond in conds.split(" and ") {
+        let cond = cond.trim();
+        if let Some(v) = cond.strip_prefix("lag<") {
+            when.max_lag = Some(parse_duration_str(v)?);
+        } else if let Some(v) = cond.strip_prefix("rate<") {
+            let v = v.trim_end_matches("/s");
+            when.max_rate = Some(v.parse().map_err(|_| anyhow::anyhow!(format!("--cutover-when 速率不正确: {}", cond)))?);
+        } else {
+            anyhow::bail!(format!("不支持的 --cutover-when 条件: {}（lag<时长 / rate<行数/s）", cond));
+        }
+    }
+    if when.max_lag.is_none() && when.max_rate.is_none() {
+        anyhow::bail!(format!("--cutover-when 未包含任何条件: {}", s));
+    }
+    Ok(when)
+}
+
+fn parse_time(s: &str) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::parse_from_str(s.get(..19).unwrap_or(s), "%Y-%m-%d %H:%M:%S").ok()
+}
+
+// 复制延迟（秒）：源表最大时间减去最晚一个已完成分段的结束时间，没有已完成分段时按 0 计
+pub fn lag_seconds(src_max: &str, done_segments: &HashSet<String>) -> u64 {
+    let done_end = segment::covered(done_segments.iter().map(|s| s.as_str())).end();
+    match (parse_time(src_max), done_end) {
+        (Some(s), Some(e)) => (s - e).num_seconds().max(0) as u64,
+        _ => 0,
+    }
+}
+
+pub struct CatchUp {
+    when: Option<CutoverWhen>,
+    at: Option<chrono::NaiveDateTime>,
+    pub interval: Duration,
+    streak: u32,
+    last_count: Option<(u64, Instant)>,
+    src_dsn: String, // --cutover-at 按该源端服务器的时钟判断
+}
+
+impl CatchUp {
+    // 未设置 --cutover-when / --cutover-at 时返回 None，保持原有行为
+    pub fn new(opt: &Opt) -> anyhow::Result<Option<Self>> {
+        let when = if opt.cutover_when.is_empty() { None } else { Some(parse_cutover_when(&opt.cutover_when)?) };
+        let at = if opt.cutover_at.is_empty() {
+            None
+        } else {
+            Some(parse_time(&opt.cutover_at).ok_or_else(|| anyhow::anyhow!(format!("--cutover-at 格式不正确: {}（YYYY-mm-dd HH:MM:SS）", opt.cutover_at)))?)
+        };
+        if when.is_none() && at.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(CatchUp { when, at, interval: opt.cutover_check_interval, streak: 0, last_count: None, src_dsn: opt.src_dsn.clone() }))
+    }
+
+    async fn source_rows(opt: &Opt) -> anyhow::Result<u64> {
+        let sql = format!(
+            "SELECT count() AS c FROM {} WHERE {} >= {}{} FORMAT JSONEachRow",
+            qualified(&opt.src_db, &opt.src_table), opt.time_field, opt.time_lit(&opt.start_time), filter_sql(&opt.filter)
+        );
+        let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+        Ok(json_u64(rows.first().and_then(|r| r.get("c"))))
+    }
+
+    // 切换条件即将满足：已连续满足过至少一次，或距 --cutover-at 不足两个检查间隔；此时增量不再攒批
+    pub fn approaching(&self) -> bool {
+        let window = chrono::Duration::from_std(self.interval * 2).unwrap_or_else(|_| chrono::Duration::zero());
+        let near_at = self.at.map(|at| at - clock_skew::source_now_local(&self.src_dsn) <= window).unwrap_or(false);
+        self.streak > 0 || near_at
+    }
+
+    // 每轮增量检查一次：lag 为本轮 lag_seconds 的结果；满足切换条件时返回触发原因
+    pub async fn ready(&mut self, opt: &Opt, lag: u64) -> anyhow::Result<Option<&'static str>> {
+        let count = Self::source_rows(opt).await?;
+        let rate = match self.last_count {
+            Some((c, t)) if t.elapsed().as_secs_f64() > 0.0 => count.saturating_sub(c) as f64 / t.elapsed().as_secs_f64(),
+            _ => f64::NAN, // 首次检查没有速率
+        };
+        self.last_count = Some((count, Instant::now()));
+        let now = clock_skew::source_now_local(&self.src_dsn);
+        if let Some(at) = self.at {
+            if now >= at {
+                info!("切换判定: 已到达 --cutover-at {}，lag={}s，写入速率 {:.1} 行/s，开始切换", at, lag, rate);
+                return Ok(Some("cutover-at"));
+            }
+        }
+        let Some(when) = &self.when else {
+            info!("切换判定: lag={}s，写入速率 {:.1} 行/s，等待 --cutover-at {}", lag, rate, self.at.unwrap());
+            return Ok(None);
+        };
+        let lag_ok = when.max_lag.map(|m| lag < m.as_secs()).unwrap_or(true);
+        let rate_ok = when.max_rate.map(|m| rate.is_finite() && rate < m).unwrap_or(true);
+        self.streak = if lag_ok && rate_ok { self.streak + 1 } else { 0 };
+        info!(
+            "切换判定: lag={}s（阈值 {}），写入速率 {:.1} 行/s（阈值 {}），连续满足 {}/{}{}",
+            lag,
+            when.max_lag.map(|m| format!("<{}s", m.as_secs())).unwrap_or_else(|| "-".to_string()),
+            rate,
+            when.max_rate.map(|m| format!("<{}", m)).unwrap_or_else(|| "-".to_string()),
+            self.streak,
+            when.checks,
+            self.at.map(|a| format!("，--cutover-at {}", a)).unwrap_or_default()
+        );
+        Ok(if self.streak >= when.checks { Some("cutover-when") } else { None })
+    }
+}
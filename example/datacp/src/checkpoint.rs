@@ -0,0 +1,168 @@
+// ===================== 断点续传文件合并与缺口检查（datacp checkpoint） =====================
+// 多次中断的运行可能留下几份断点续传文件，难以确认哪些分段真正完成。
+// checkpoint merge a.txt b.txt -o merged.txt 合并多份文件（各自的 .meta 元数据须一致），按首次出现的顺序去重；
+// checkpoint gaps 按源表当前的时间范围重新生成分段列表，输出断点续传文件中没有的分段（缺口），
+// 可写入文件供 --only-segments-file 做只处理这些分段的修补运行。除一次 min/max 查询外只读写本地文件。
+// 校验发现个别分段不一致时，--only-segments / --segment-range 不论断点续传记录重新迁移这些分段（按差异写入，可重复执行），
+// 不做增量与切换，完成后重新比对并追加校验记录
+
+use log::info;
+use std::collections::{BTreeSet, HashSet};
+
+use crate::checkpoint_meta::{self, meta_file};
+use crate::{events, get_time_range_http, load_done_segments, plan_segments, row_filter, segment, time_zone, Opt, SegmentBlacklist};
+
+// 合并断点续传文件：元数据的 identity 字段（见 checkpoint_meta）不一致时拒绝，合并结果与元数据先写临时文件再 rename
+pub fn merge(files: &[String], output: &str) -> anyhow::Result<()> {
+    if files.is_empty() {
+        anyhow::bail!("checkpoint merge 需要至少一个断点续传文件");
+    }
+    let mut meta: Option<(String, serde_json::Value)> = None;
+    for f in files {
+        let Ok(text) = std::fs::read_to_string(meta_file(f)) else { continue };
+        let v: serde_json::Value = serde_json::from_str(&text).map_err(|e| anyhow::anyhow!(format!("解析 {} 失败: {}", meta_file(f), e)))?;
+        match &meta {
+            Some((first, m)) if checkpoint_meta::identity(m) != checkpoint_meta::identity(&v) => anyhow::bail!(format!(
+                "{} 的元数据 {} 与 {} 的 {} 不一致（两端表、时间字段、--where、--shard-of、迁移字段或表结构指纹不同），不能合并",
+                f,
+                v,
+                first,
+                m
+            )),
+            Some(_) => {}
+            None => meta = Some((f.clone(), v)),
+        }
+    }
+    let (mut seen, mut lines) = (HashSet::new(), Vec::new());
+    for f in files {
+        let text = std::fs::read_to_string(f).map_err(|e| anyhow::anyhow!(format!("读取断点续传文件 {} 失败: {}", f, e)))?;
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            if seen.insert(line.to_string()) {
+                lines.push(line.to_string());
+            }
+        }
+    }
+    let tmp = format!("{}.tmp", output);
+    std::fs::write(&tmp, lines.iter().map(|l| format!("{}\n", l)).collect::<String>())
+        .and_then(|_| std::fs::rename(&tmp, output))
+        .map_err(|e| anyhow::anyhow!(format!("写入 {} 失败: {}", output, e)))?;
+    if let Some((_, m)) = &meta {
+        std::fs::write(meta_file(output), serde_json::to_string(m)?)?;
+    }
+    let segments = lines.iter().filter(|l| is_segment(l)).count();
+    events::say(&format!("已合并 {} 个文件到 {}: {} 个已完成分段，共 {} 行", files.len(), output, segments, lines.len()));
+    Ok(())
+}
+
+// 分段完成记录（区间或旧版本的起点）
+fn is_segment(s: &str) -> bool {
+    segment::parse(s).is_some()
+}
+
+// 列出源表当前时间范围内、断点续传文件中没有的分段；黑名单分段不算缺口。指定 output 时逐行写入
+pub async fn gaps(opt: &Opt, done_segments_file: &str, output: &str) -> anyhow::Result<()> {
+    let opt = &time_zone::init(opt).await?;
+    let done = load_done_segments(done_segments_file)?;
+    let blacklist = SegmentBlacklist::load(&opt.skip_segments_file)?;
+    let (min_time, max_time) =
+        get_time_range_http(&opt.src_dsn, &opt.src_db, &opt.src_table, &opt.time_field, &opt.start_time, &row_filter(opt), opt.time_zone.as_ref()).await?;
+    if min_time.is_empty() || max_time.is_empty() {
+        events::say("源表在起始时间之后没有数据，没有缺口");
+        return Ok(());
+    }
+    let holes = plan_segments(&min_time, &max_time, &done, &blacklist, None)?;
+    events::say(&format!(
+        "源表时间范围 {} ~ {}，断点续传文件 {} 中缺少 {} 个分段:",
+        min_time,
+        max_time,
+        done_segments_file,
+        holes.len()
+    ));
+    for h in &holes {
+        events::say(&format!("  {}", h));
+    }
+    if !output.is_empty() {
+        std::fs::write(output, holes.iter().map(|h| format!("{}\n", h)).collect::<String>())
+            .map_err(|e| anyhow::anyhow!(format!("写入 {} 失败: {}", output, e)))?;
+        info!("缺口分段已写入 {}，可用 --only-segments-file {} --no-cutover 修补", output, output);
+    }
+    Ok(())
+}
+
+// --only-segments-file：修补运行只处理列出的分段，未指定时为 None
+pub fn load_only(opt: &Opt) -> anyhow::Result<Option<HashSet<String>>> {
+    if opt.only_segments_file.is_empty() {
+        return Ok(None);
+    }
+    if !opt.no_cutover || opt.archive || opt.standby {
+        anyhow::bail!("--only-segments-file 只用于修补指定分段，须同时指定 --no-cutover，且不能用于归档或热备模式");
+    }
+    let text = std::fs::read_to_string(&opt.only_segments_file)
+        .map_err(|e| anyhow::anyhow!(format!("读取 --only-segments-file {} 失败: {}", opt.only_segments_file, e)))?;
+    let mut only = HashSet::new();
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        if segment::span(line).is_none() {
+            anyhow::bail!(format!("--only-segments-file 中的分段格式不正确: {}", line));
+        }
+        only.insert(line.to_string());
+    }
+    info!("只处理 --only-segments-file 列出的 {} 个分段", only.len());
+    Ok(Some(only))
+}
+
+fn parse_time(s: &str, flag: &str) -> anyhow::Result<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::parse_from_str(s.trim(), "%Y-%m-%d %H:%M:%S")
+        .map_err(|_| anyhow::anyhow!(format!("{} 的时间格式应为 YYYY-MM-DD HH:MM:SS: {}", flag, s.trim())))
+}
+
+// --only-segments / --segment-range：不论断点续传记录重新迁移的分段（按时间排序），未指定时为 None。
+// 分段从源表当前的 min_time 起按 --segment-size 一段（列声明了时区时为 UTC，与断点续传文件一致），指定的分段须是其中之一，
+// 范围须与源表时间范围相交
+pub fn load_rerun(opt: &Opt, min_time: &str, max_time: &str) -> anyhow::Result<Option<Vec<String>>> {
+    if opt.only_segments.trim().is_empty() && opt.segment_range.trim().is_empty() {
+        return Ok(None);
+    }
+    if !opt.only_segments_file.is_empty() || opt.archive || opt.standby {
+        anyhow::bail!("--only-segments / --segment-range 不能与 --only-segments-file 同时指定，且不能用于归档或热备模式");
+    }
+    if min_time.is_empty() || max_time.is_empty() {
+        anyhow::bail!("源表在起始时间之后没有数据，没有可重新迁移的分段");
+    }
+    let grid = plan_segments(min_time, max_time, &HashSet::new(), &SegmentBlacklist::default(), None)?;
+    let mut picked = BTreeSet::new();
+    for seg in opt.only_segments.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let t = parse_time(seg, "--only-segments")?.format("%Y-%m-%d %H:%M:%S").to_string();
+        if t.as_str() < min_time || t.as_str() >= max_time {
+            anyhow::bail!(format!("--only-segments 的分段 {} 不在源表的时间范围 {} ~ {} 内", t, min_time, max_time));
+        }
+        if !grid.contains(&t) {
+            anyhow::bail!(format!(
+                "--only-segments 的 {} 不是分段起点：分段从 {} 起每 {}s 一段，可用 --segment-range 按时间范围指定",
+                t, min_time, segment::size().num_seconds()
+            ));
+        }
+        picked.insert(t);
+    }
+    if !opt.segment_range.trim().is_empty() {
+        let Some((a, b)) = opt.segment_range.split_once("..") else {
+            anyhow::bail!(format!("--segment-range 格式为 \"起..止\": {}", opt.segment_range));
+        };
+        let (from, to) = (parse_time(a, "--segment-range")?, parse_time(b, "--segment-range")?);
+        if to <= from {
+            anyhow::bail!(format!("--segment-range 的止点须晚于起点: {}", opt.segment_range));
+        }
+        let hit: Vec<&String> = grid
+            .iter()
+            .filter(|s| {
+                let (start, end) = segment::span(s).unwrap();
+                start < to && end > from
+            })
+            .collect();
+        if hit.is_empty() {
+            anyhow::bail!(format!("--segment-range {} 与源表的时间范围 {} ~ {} 不相交", opt.segment_range.trim(), min_time, max_time));
+        }
+        picked.extend(hit.into_iter().cloned());
+    }
+    info!("不论断点续传记录，重新迁移 {} 个分段（不做增量与切换）: {}", picked.len(), picked.iter().cloned().collect::<Vec<_>>().join(", "));
+    Ok(Some(picked.into_iter().collect()))
+}
@@ -0,0 +1,132 @@
+// ===================== 断点续传元数据（{done_segments}.meta） =====================
+// 元数据字段分两类：
+//   identity  决定已完成分段含义的参数：两端表、时间字段、分段粒度、--where、--shard-of、迁移字段（--ignore-field）、
+//             比对规范化规则（--normalize / --normalize-columns）、分段键的时区（segment_zone，时间字段声明了时区时为 UTC），
+//             以及 schema_fingerprint 记录的表结构指纹；续传时必须一致，否则拒绝
+//   tunable   只影响速度的参数：--parallelism、--batch-bytes、--incremental-batch-hours、--src-max-concurrent-queries、
+//             --segment-size（已完成分段按区间记录，改变分段大小不影响已完成的时段），续传时可以调整，不一致只打印提示，随后更新为本次的值
+// 另记录上次运行实测的吞吐（last_run），datacp plan / status 在还没有足够分段字节记录时用作预计耗时的速率基线。
+// 旧版本写入的元数据缺少的 identity 字段按本次补齐，不视为不一致；缺少 segment_zone 而本次为 UTC 时例外，
+// 旧断点的分段键是列时区的本地时间，不能沿用
+
+use log::info;
+use serde_json::{json, Value};
+
+use crate::normalize::Normalize;
+use crate::{shard_of, Opt};
+
+// 分段记录的含义：旧版本的起点记录按 1 小时、新记录为 [起, 止) 区间，两者都按区间解读；
+// 分段大小（--segment-size）记在 tunable 中，这里保持 1h 以兼容旧元数据
+const SEGMENT: &str = "1h";
+
+// identity 字段；schema 由 schema_fingerprint 单独比对
+const IDENTITY: [&str; 9] = ["src", "dst", "time_field", "segment", "segment_zone", "where", "shard", "ignore_field", "normalize"];
+const TUNABLE: [&str; 5] = ["parallelism", "batch_bytes", "incremental_batch_hours", "src_max_concurrent_queries", "segment_size"];
+
+pub fn meta_file(done_segments_file: &str) -> String {
+    format!("{}.meta", done_segments_file)
+}
+
+fn current(opt: &Opt) -> anyhow::Result<Value> {
+    let mut ignore: Vec<&str> = opt.ignore_field.iter().map(|s| s.trim()).filter(|s| !s.is_empty()).collect();
+    ignore.sort();
+    Ok(json!({
+        "src": format!("{}.{}", opt.src_db, opt.src_table),
+        "dst": format!("{}.{}", opt.dst_db, opt.dst_table),
+        "time_field": opt.time_field,
+        "segment": SEGMENT,
+        "segment_zone": if opt.time_zone.is_some() { "UTC" } else { "server" },
+        "where": opt.filter.trim(),
+        "shard": shard_of::meta(opt),
+        "ignore_field": ignore,
+        "normalize": Normalize::parse(opt)?.meta(),
+        "parallelism": opt.parallelism,
+        "batch_bytes": opt.batch_bytes,
+        "incremental_batch_hours": opt.incremental_batch_hours,
+        "src_max_concurrent_queries": opt.src_max_concurrent_queries,
+        "segment_size": opt.segment_size.as_secs(),
+    }))
+}
+
+// 合并断点续传文件时比对的部分：identity 字段与表结构指纹
+pub fn identity(meta: &Value) -> Value {
+    let mut out = json!({});
+    for k in IDENTITY {
+        if let Some(v) = meta.get(k) {
+            out[k] = v.clone();
+        }
+    }
+    if let Some(f) = meta.get("schema").and_then(|s| s.get("fingerprint")) {
+        out["schema"] = f.clone();
+    }
+    out
+}
+
+fn read(meta_file: &str) -> anyhow::Result<Option<Value>> {
+    match std::fs::read_to_string(meta_file) {
+        Ok(text) => Ok(Some(serde_json::from_str(&text).map_err(|e| anyhow::anyhow!(format!("解析 {} 失败: {}", meta_file, e)))?)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn write(meta_file: &str, meta: &Value) -> anyhow::Result<()> {
+    std::fs::write(meta_file, serde_json::to_string(meta)?).map_err(|e| anyhow::anyhow!(format!("写入 {} 失败: {}", meta_file, e)))
+}
+
+// 续传时 identity 字段不一致则拒绝，tunable 字段不一致只提示；首次运行写入元数据
+pub fn check(done_segments_file: &str, opt: &Opt) -> anyhow::Result<()> {
+    let meta_file = meta_file(done_segments_file);
+    let now = current(opt)?;
+    let Some(mut saved) = read(&meta_file)? else {
+        return write(&meta_file, &now);
+    };
+    for k in IDENTITY {
+        match saved.get(k) {
+            Some(v) if *v != now[k] => anyhow::bail!(format!(
+                "断点续传文件 {} 记录的 {} 为 {}，与本次 {} 不一致，请更换 --done-segments 或保持相同参数{}",
+                done_segments_file,
+                k,
+                v,
+                now[k],
+                if k == "shard" { "（所有分片进程须使用相同的分片键与分片数）" } else { "" }
+            )),
+            Some(_) => {}
+            None if k == "segment_zone" && now[k] == "UTC" => anyhow::bail!(format!(
+                "断点续传文件 {} 由旧版本写入，分段键为时间字段 {} 的本地时间；本次按 UTC 分段，请更换 --done-segments 重新迁移",
+                done_segments_file, opt.time_field
+            )),
+            None => saved[k] = now[k].clone(),
+        }
+    }
+    let changed: Vec<String> = TUNABLE
+        .iter()
+        .filter_map(|k| match saved.get(*k) {
+            Some(v) if *v != now[*k] => Some(format!("{} {} → {}", k, v, now[*k])),
+            _ => None,
+        })
+        .collect();
+    if !changed.is_empty() {
+        info!("续传时调整了参数（不影响已完成分段）: {}", changed.join(", "));
+    }
+    for k in TUNABLE {
+        saved[k] = now[k].clone();
+    }
+    write(&meta_file, &saved)
+}
+
+// 本次运行实测吞吐，供下次运行估算预计耗时
+pub fn record_throughput(done_segments_file: &str, bytes_per_sec: f64, rows_per_sec: f64) -> anyhow::Result<()> {
+    let meta_file = meta_file(done_segments_file);
+    let mut meta = read(&meta_file)?.unwrap_or_else(|| json!({}));
+    meta["last_run"] = json!({
+        "bytes_per_sec": bytes_per_sec,
+        "rows_per_sec": rows_per_sec,
+        "finished_at": chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+    });
+    write(&meta_file, &meta)
+}
+
+// 上次运行实测的源端读取字节/秒
+pub fn last_throughput(done_segments_file: &str) -> Option<f64> {
+    read(&meta_file(done_segments_file)).ok()??.get("last_run")?.get("bytes_per_sec")?.as_f64().filter(|r| *r > 0.0)
+}
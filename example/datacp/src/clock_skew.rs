@@ -0,0 +1,134 @@
+// ===================== 时钟偏差（--max-clock-skew） =====================
+// 切换时机（--cutover-at）、源表 TTL 过期边界、传输量估算与切换后观察都用“当前时间”与数据时间比较，
+// 默认两端集群与本机时钟一致；源端某个副本快了几分钟时，按本机时钟判断会提前切换或漏掉仍在写入的行。
+// 启动检查时及之后每 INTERVAL 对两端执行 SELECT now64(3)，以请求往返的中点为本机时刻求出偏差（误差不超过往返时间的一半），
+// 写入日志与报告 clock_skew；扣除往返误差后仍超过 --max-clock-skew 时告警，--require-synced-clocks 时启动检查失败、
+// 本次运行在当前分段完成后停止且不切换。需要“当前时间”的判断统一用源端服务器的时钟：本机时钟加上最近一次测得的该源端的偏差
+// （按源端 endpoint 分别记录，多表与 serve 任务的源端不同时互不影响）
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use log::{error, info, warn};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::report::{self, ClockSkew, RunReport};
+use crate::{ch_query_rows, json_u64, sql_log, Opt, RunCtx};
+
+const INTERVAL: Duration = Duration::from_secs(300);
+
+// 各源端 endpoint 最近一次测得的时钟偏差（毫秒，源端快为正）
+static SRC_SKEW_MS: Mutex<BTreeMap<String, i64>> = Mutex::new(BTreeMap::new());
+
+// 源端 src_dsn 服务器的当前时间（未测得偏差时为本机时间）
+pub fn source_now(src_dsn: &str) -> DateTime<Utc> {
+    let skew = SRC_SKEW_MS.lock().unwrap().get(&sql_log::endpoint(src_dsn)).copied().unwrap_or(0);
+    Utc::now() + chrono::Duration::milliseconds(skew)
+}
+
+// 源端服务器的当前时间，换算为本机时区（与 --cutover-at 可比）
+pub fn source_now_local(src_dsn: &str) -> NaiveDateTime {
+    source_now(src_dsn).with_timezone(&chrono::Local).naive_local()
+}
+
+async fn measure(side: &str, dsn: &str, db: &str) -> anyhow::Result<ClockSkew> {
+    let sent = Utc::now();
+    let rows = ch_query_rows(dsn, db, "SELECT toUnixTimestamp64Milli(now64(3)) AS ms FORMAT JSONEachRow").await?;
+    let received = Utc::now();
+    let server = json_u64(rows.first().and_then(|r| r.get("ms"))) as i64;
+    if server == 0 {
+        anyhow::bail!("SELECT now64(3) 未返回时间");
+    }
+    let rtt = (received - sent).num_milliseconds().max(0);
+    Ok(ClockSkew {
+        time: report::now_str(),
+        side: side.to_string(),
+        endpoint: sql_log::endpoint(dsn),
+        skew_ms: server - (sent.timestamp_millis() + rtt / 2),
+        rtt_ms: rtt as u64,
+    })
+}
+
+// 测量两端偏差并记入报告；返回扣除往返误差后超过 --max-clock-skew 的端
+async fn sample(opt: &Opt, report: &Arc<Mutex<RunReport>>) -> Vec<String> {
+    let limit = opt.max_clock_skew.as_millis() as i64;
+    let mut over = Vec::new();
+    for (side, dsn, db) in [("源端", &opt.src_dsn, &opt.src_db), ("目标端", &opt.dst_dsn, &opt.dst_db)] {
+        let s = match measure(side, dsn, db).await {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("测量{}时钟偏差失败: {e}", side);
+                continue;
+            }
+        };
+        if side == "源端" {
+            SRC_SKEW_MS.lock().unwrap().insert(s.endpoint.clone(), s.skew_ms);
+        }
+        let line = format!("{} {} 时钟与本机相差 {:+}ms（往返 {}ms）", s.side, s.endpoint, s.skew_ms, s.rtt_ms);
+        if s.skew_ms.abs() - s.rtt_ms as i64 / 2 > limit {
+            warn!("{}，超过 --max-clock-skew {}s", line, opt.max_clock_skew.as_secs());
+            over.push(line);
+        } else {
+            info!("{}", line);
+        }
+        report.lock().unwrap().clock_skew.push(s);
+    }
+    over
+}
+
+// 启动检查：--require-synced-clocks 时偏差超限即拒绝运行
+pub async fn check(opt: &Opt, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<()> {
+    let over = sample(opt, report).await;
+    if opt.require_synced_clocks && !over.is_empty() {
+        anyhow::bail!(format!("时钟偏差超过 --max-clock-skew（--require-synced-clocks）: {}", over.join("；")));
+    }
+    Ok(())
+}
+
+// 运行中定期测量；--require-synced-clocks 时偏差超限则本次运行在当前分段完成后停止
+pub fn spawn(opt: &Opt, report: Arc<Mutex<RunReport>>, ctx: Arc<RunCtx>) -> tokio::task::JoinHandle<()> {
+    let opt = opt.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(INTERVAL).await;
+            let over = sample(&opt, &report).await;
+            if opt.require_synced_clocks && !over.is_empty() {
+                error!("运行中时钟偏差超过 --max-clock-skew（--require-synced-clocks）: {}", over.join("；"));
+                ctx.deadline.stop("时钟偏差超过 --max-clock-skew");
+                return;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{deadline::Deadline, mock_ch};
+    use structopt::StructOpt;
+
+    // 时钟快一分钟的源端
+    fn minute_ahead(sql: &str) -> (u16, String) {
+        if sql.contains("now64(3)") {
+            (200, format!("{{\"ms\":\"{}\"}}\n", Utc::now().timestamp_millis() + 60_000))
+        } else {
+            (200, String::new())
+        }
+    }
+
+    // 偏差按源端分别记录，超限停止只影响本次运行
+    #[tokio::test]
+    async fn skew_is_tracked_per_source_and_stops_only_its_run() {
+        let (ahead, _) = mock_ch::serve(minute_ahead).await;
+        let (other, _) = mock_ch::serve(mock_ch::empty).await;
+        let opt = Opt::from_iter(["datacp", "--src-dsn", &ahead, "--dst-dsn", &other, "--require-synced-clocks"]);
+        let report = Arc::new(Mutex::new(RunReport::default()));
+        assert!(check(&opt, &report).await.is_err());
+        let skew = (source_now(&ahead) - Utc::now()).num_seconds();
+        assert!((55..=65).contains(&skew), "{}", skew);
+        assert!((source_now(&other) - Utc::now()).num_seconds().abs() < 5);
+        let (this, next) = (Deadline::new(&opt, Arc::default()), Deadline::new(&opt, Arc::default()));
+        this.stop("时钟偏差超过 --max-clock-skew");
+        assert!(this.reached() && !next.reached());
+    }
+}
@@ -0,0 +1,96 @@
+// ===================== 集群子句自检（--cluster-name） =====================
+// 从生产环境复制来的参数在没有集群定义的单机环境中也会带上 --cluster-name，ON CLUSTER 语句随之全部失败。
+// 发出 ON CLUSTER DDL（及 clusterAllReplicas 查询）前，按端点检查一次 system.clusters：
+// 集群存在且包含本机以外的节点时才使用集群形式，否则告警并自动改为单机形式（去掉 ON CLUSTER 子句）；
+// 指定 --strict-cluster 时改为报错退出。每条 DDL 实际执行的形式（on-cluster / local）及回退原因记入报告的 cluster_ddl
+
+use log::warn;
+use regex::Regex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::report::ClusterDdl;
+use crate::{ch_query_rows, json_u64, sql_log, Opt};
+
+// 报告中最多记录的语句数（镜像删除等按分段执行的语句可能很多）
+const MAX_RECORDED: usize = 1000;
+
+static STRICT: AtomicBool = AtomicBool::new(false);
+// "DSN\t集群名" → 不可用时的原因
+static CHECKED: Mutex<Vec<(String, Option<String>)>> = Mutex::new(Vec::new());
+static STATEMENTS: Mutex<Vec<ClusterDdl>> = Mutex::new(Vec::new());
+
+pub fn init(opt: &Opt) {
+    STRICT.store(opt.strict_cluster, Ordering::SeqCst);
+}
+
+// 集群在该端点不可用时返回原因；--strict-cluster 时报错
+pub async fn fallback(dsn: &str, db: &str, cluster: &str) -> anyhow::Result<Option<String>> {
+    let key = format!("{}\t{}", dsn, cluster);
+    let cached = CHECKED.lock().unwrap().iter().find(|(k, _)| *k == key).map(|(_, r)| r.clone());
+    let reason = match cached {
+        Some(r) => r,
+        None => {
+            let sql = format!(
+                "SELECT count() AS nodes, countIf(is_local = 0) AS remote FROM system.clusters WHERE cluster = '{}' FORMAT JSONEachRow",
+                cluster.replace('\'', "\\'")
+            );
+            let rows = ch_query_rows(dsn, db, &sql).await?;
+            let n = |k: &str| json_u64(rows.first().and_then(|r| r.get(k)));
+            let reason = if n("nodes") == 0 {
+                Some(format!("system.clusters 中没有集群 {}", cluster))
+            } else if n("remote") == 0 {
+                Some(format!("集群 {} 只包含本机", cluster))
+            } else {
+                None
+            };
+            if let Some(r) = &reason {
+                if !STRICT.load(Ordering::SeqCst) {
+                    warn!("{}（{}），该端点的 ON CLUSTER 语句改为单机形式执行", r, sql_log::endpoint(dsn));
+                }
+            }
+            CHECKED.lock().unwrap().push((key, reason.clone()));
+            reason
+        }
+    };
+    match reason {
+        Some(r) if STRICT.load(Ordering::SeqCst) => {
+            anyhow::bail!(format!("--cluster-name {} 在 {} 不可用: {}（--strict-cluster）", cluster, sql_log::endpoint(dsn), r))
+        }
+        r => Ok(r),
+    }
+}
+
+// 去掉语句中的 ON CLUSTER 子句
+pub fn strip(sql: &str) -> String {
+    Regex::new(r"\s+ON CLUSTER\s+\S+").unwrap().replace_all(sql, "").to_string()
+}
+
+// 记录一条 DDL 实际执行的形式；fallback 为回退原因，None 表示按集群形式执行
+pub fn record(dsn: &str, cluster: &str, sql: &str, fallback: Option<&str>) {
+    let mut s = STATEMENTS.lock().unwrap();
+    if s.len() >= MAX_RECORDED {
+        return;
+    }
+    s.push(ClusterDdl {
+        endpoint: sql_log::endpoint(dsn),
+        cluster: cluster.to_string(),
+        form: if fallback.is_some() { "local" } else { "on-cluster" }.to_string(),
+        reason: fallback.map(|r| r.to_string()),
+        statement: sql.to_string(),
+    });
+}
+
+// 复制延迟与只读探测查询的 system.replicas 来源：集群可用时经 clusterAllReplicas 覆盖所有副本
+pub async fn replicas_from(opt: &Opt) -> anyhow::Result<String> {
+    if opt.cluster_name.is_empty() || fallback(&opt.dst_dsn, &opt.dst_db, &opt.cluster_name).await?.is_some() {
+        Ok("system.replicas".to_string())
+    } else {
+        Ok(format!("clusterAllReplicas('{}', system.replicas)", opt.cluster_name))
+    }
+}
+
+// 本进程执行过的集群相关 DDL（写入报告）
+pub fn statements() -> Vec<ClusterDdl> {
+    STATEMENTS.lock().unwrap().clone()
+}
@@ -0,0 +1,95 @@
+// ===================== 稀疏表空分段合并（--coalesce-empty） =====================
+// 只在工作日工作时间有数据的表，一年 8760 个小时分段中大部分为空，每个空分段仍要两端各查询一次、各写一行日志。
+// --coalesce-empty 时先按天探测：一条 GROUP BY toDate(time_field) 查询取得迁移范围内每天的行数，
+// 落在无数据日期内的分段（分段起止都在无数据的日期）不再生成，连续的一串以一条范围记录写入断点续传文件：
+//   empty:<首个分段>..<最后一个分段>
+// load_done_segments 把范围记录展开为其中的逐小时分段（按 1 小时的区间记录），后续的分段生成、缺口检查与续传都按已完成处理。
+// 报告 segments_coalesced 与分段汇总给出经合并跳过的分段数
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use log::info;
+use std::collections::{HashMap, HashSet};
+
+use crate::{ch_query_rows, json_u64, qualified, row_filter, save_done_segment, segment, time_zone, Opt};
+
+pub const EMPTY_PREFIX: &str = "empty:";
+
+const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// 范围记录展开为逐小时分段；不是范围记录时返回 None
+pub fn expand(line: &str) -> Option<Vec<String>> {
+    let (a, b) = line.strip_prefix(EMPTY_PREFIX)?.split_once("..")?;
+    let (first, last) = (NaiveDateTime::parse_from_str(a, FORMAT).ok()?, NaiveDateTime::parse_from_str(b, FORMAT).ok()?);
+    let mut out = Vec::new();
+    let mut t = first;
+    while t <= last {
+        out.push(t.format(FORMAT).to_string());
+        t += Duration::hours(1);
+    }
+    Some(out)
+}
+
+// 断点续传文件中经合并记为完成的分段数
+pub fn recorded(done_segments_file: &str) -> usize {
+    std::fs::read_to_string(done_segments_file)
+        .map(|s| s.lines().filter_map(expand).map(|v| v.len()).sum())
+        .unwrap_or(0)
+}
+
+// 按天探测 [min_time, max_time) 内的数据，把无数据日期内尚未完成的分段以范围记录写入断点续传文件并加入 done；
+// 返回本次合并的分段数
+pub async fn run(opt: &Opt, min_time: &str, max_time: &str, done_segments_file: &str, done: &mut HashSet<String>) -> anyhow::Result<usize> {
+    let (Ok(min), Ok(max)) = (NaiveDateTime::parse_from_str(min_time, FORMAT), NaiveDateTime::parse_from_str(max_time, FORMAT)) else {
+        return Ok(0);
+    };
+    // 列声明了时区时按 UTC 日期分组，与分段键对齐
+    let sql = format!(
+        "SELECT toString(toDate({})) AS d, count() AS n FROM {} WHERE {tf} >= {} AND {tf} <= {}{} GROUP BY d FORMAT JSONEachRow",
+        time_zone::utc_expr(opt.time_zone.as_ref(), &opt.time_field),
+        qualified(&opt.src_db, &opt.src_table),
+        opt.time_lit(min_time),
+        opt.time_lit(max_time),
+        row_filter(opt),
+        tf = opt.time_field
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let days: HashMap<NaiveDate, u64> = rows
+        .iter()
+        .filter_map(|r| {
+            let d = NaiveDate::parse_from_str(r.get("d")?.as_str()?, "%Y-%m-%d").ok()?;
+            Some((d, json_u64(r.get("n"))))
+        })
+        .collect();
+    let has_data = |d: NaiveDate| days.get(&d).copied().unwrap_or(0) > 0;
+    // 连续的空分段合并为一条范围记录；已完成区间（任意分段大小）覆盖的小时不再记录
+    let covered = segment::covered(done.iter().map(|s| s.as_str()));
+    let mut runs: Vec<(NaiveDateTime, NaiveDateTime)> = Vec::new();
+    let mut t = min;
+    while t < max {
+        let end = t + Duration::hours(1) - Duration::seconds(1);
+        if !has_data(t.date()) && !has_data(end.date()) && !covered.covers(t, t + Duration::hours(1)) {
+            match runs.last_mut() {
+                Some((_, last)) if *last + Duration::hours(1) == t => *last = t,
+                _ => runs.push((t, t)),
+            }
+        }
+        t += Duration::hours(1);
+    }
+    let mut coalesced = 0;
+    for (first, last) in &runs {
+        let line = format!("{}{}..{}", EMPTY_PREFIX, first.format(FORMAT), last.format(FORMAT));
+        save_done_segment(done_segments_file, &line)?;
+        let segs = expand(&line).unwrap_or_default();
+        coalesced += segs.len();
+        done.extend(segs);
+    }
+    let empty_days = (min.date().iter_days().take_while(|d| *d <= max.date())).filter(|d| !has_data(*d)).count();
+    info!(
+        "空分段合并: {} 天中 {} 天无数据，{} 个分段以 {} 条范围记录标记为完成",
+        (max.date() - min.date()).num_days() + 1,
+        empty_days,
+        coalesced,
+        runs.len()
+    );
+    Ok(coalesced)
+}
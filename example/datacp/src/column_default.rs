@@ -0,0 +1,68 @@
+// ===================== 列默认值覆盖（--column-default） =====================
+// 源表后来 ALTER ADD COLUMN 的列，旧 part 读出的是源端的默认值；目标表新建时默认值可能不同，
+// 目标端重写（mutation、重新物化）后旧行两端的值不一致，重新校验时摘要对不上。
+// --column-default col=value 让两端读取该列时都使用 ifNull(col, value)，比对不再依赖各自服务端的默认值；
+// 源端读出的值即写入数据，覆盖同样落到目标端。启动时比对两端 DESCRIBE 的默认值表达式，不一致时告警
+
+use log::{info, warn};
+use std::collections::HashMap;
+
+use crate::{ch_query_rows, qualified, Opt};
+
+// 解析 col=value，value 为 SQL 表达式（字符串需带引号，如 region='unknown'）
+fn parse(specs: &[String]) -> anyhow::Result<HashMap<String, String>> {
+    let mut defaults = HashMap::new();
+    for s in specs {
+        let (col, value) = s
+            .split_once('=')
+            .map(|(c, v)| (c.trim(), v.trim()))
+            .filter(|(c, v)| !c.is_empty() && !v.is_empty())
+            .ok_or_else(|| anyhow::anyhow!(format!("--column-default 格式为 col=value: {}", s)))?;
+        defaults.insert(col.to_string(), value.to_string());
+    }
+    Ok(defaults)
+}
+
+// 各列的默认值定义（default_type 与 default_expression），没有默认值的列不在其中
+async fn describe_defaults(dsn: &str, db: &str, table: &str) -> anyhow::Result<HashMap<String, String>> {
+    let sql = format!("DESCRIBE TABLE {} FORMAT JSONEachRow", qualified(db, table));
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    let text = |r: &HashMap<String, serde_json::Value>, k: &str| r.get(k).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    Ok(rows
+        .iter()
+        .filter(|r| !text(r, "default_type").is_empty())
+        .map(|r| (text(r, "name"), format!("{} {}", text(r, "default_type"), text(r, "default_expression"))))
+        .collect())
+}
+
+// 解析 --column-default 并比对两端默认值定义，返回列到覆盖值的映射
+pub async fn check(opt: &Opt, col_names: &[String]) -> anyhow::Result<HashMap<String, String>> {
+    let defaults = parse(&opt.column_default)?;
+    for c in defaults.keys() {
+        if !col_names.contains(c) {
+            anyhow::bail!(format!("--column-default 中的 {} 不在迁移字段中", c));
+        }
+    }
+    let src = describe_defaults(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    let dst = describe_defaults(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+    let none = "(无)".to_string();
+    for c in col_names {
+        let (s, d) = (src.get(c).unwrap_or(&none), dst.get(c).unwrap_or(&none));
+        if s == d {
+            continue;
+        }
+        match defaults.get(c) {
+            Some(v) => info!("字段 {} 两端默认值不同（源 {}，目标 {}），按 --column-default 以 {} 比对", c, s, d, v),
+            None => warn!(
+                "字段 {} 两端默认值不同（源 {}，目标 {}）：该列在源端新增前写入的行读出的默认值可能与目标端不一致，目标端重写后比对会报差异，可用 --column-default {}=<值> 固定",
+                c, s, d, c
+            ),
+        }
+    }
+    if !defaults.is_empty() {
+        let mut cols: Vec<String> = defaults.iter().map(|(c, v)| format!("{}={}", c, v)).collect();
+        cols.sort();
+        info!("列默认值覆盖（两端读取均为 ifNull(col, value)）: {}", cols.join(", "));
+    }
+    Ok(defaults)
+}
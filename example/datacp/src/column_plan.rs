@@ -0,0 +1,35 @@
+// ===================== 字段计划 =====================
+// 迁移字段在预检阶段确定一次：源表字段去掉 --ignore-field，按目标写入表的物理顺序排列。
+// 两端 SELECT、写入语句的显式字段列表（INSERT INTO t (a, b, c) FORMAT ...）都使用这一顺序，
+// 不依赖 JSONEachRow 按名称匹配；目标表字段顺序与源表不同时也不会错位，之后按位置解析的格式可直接沿用。
+// 行摘要按字段名排序计算（与 DIGEST_VERSION 一致，不随物理顺序变化）
+
+use crate::{get_column_names_http, is_ignored_field, Opt};
+
+pub struct ColumnPlan {
+    pub columns: Vec<String>, // 迁移字段，按目标写入表的物理顺序
+    pub compare: Vec<String>, // 参与比对的字段（再去掉 --ignore-compare-field），顺序同上
+    pub digest: Vec<String>,  // 摘要字段，按名称排序
+}
+
+impl ColumnPlan {
+    // 源表字段须已通过两端表结构校验；目标写入表缺少的字段在这里报错
+    pub async fn build(opt: &Opt) -> anyhow::Result<Self> {
+        let src = get_column_names_http(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+        let dst = get_column_names_http(&opt.dst_dsn, &opt.dst_db, &opt.dst_table).await?;
+        Self::from_columns(&src, &dst, &opt.ignore_field, &opt.ignore_compare_field)
+            .map_err(|e| anyhow::anyhow!(format!("{}.{}: {}", opt.dst_db, opt.dst_table, e)))
+    }
+
+    fn from_columns(src: &[String], dst: &[String], ignore: &[String], ignore_compare: &[String]) -> anyhow::Result<Self> {
+        let migrated: Vec<&String> = src.iter().filter(|c| !is_ignored_field(c, ignore)).collect();
+        if let Some(missing) = migrated.iter().find(|c| !dst.contains(c)) {
+            anyhow::bail!(format!("目标写入表缺少字段 {}", missing));
+        }
+        let columns: Vec<String> = dst.iter().filter(|c| migrated.contains(c)).cloned().collect();
+        let compare: Vec<String> = columns.iter().filter(|c| !is_ignored_field(c, ignore_compare)).cloned().collect();
+        let mut digest = compare.clone();
+        digest.sort();
+        Ok(ColumnPlan { columns, compare, digest })
+    }
+}
@@ -0,0 +1,203 @@
+// ===================== 多进程写入并发协调（--coordination-table） =====================
+// 多个 datacp 进程（不同表、不同团队）同时写同一目标集群时，各自的 --parallelism 叠加会压垮集群。
+// 指定 --coordination-table dst:<表> 后，每个进程在该表中登记期望的写入并发并定期心跳，
+// 读取所有活跃登记（2 分钟内有心跳）的期望之和，超过 --global-insert-concurrency 时按期望比例缩放本进程的写入许可。
+// 这是尽力而为的协调，不是锁：协调表不可达时告警并回到本地上限，恢复后继续协调
+
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+use crate::{ch_execute, ch_query_rows, json_u64, qualified, Opt};
+
+// 心跳间隔；超过 STALE_SECONDS 没有心跳的登记不计入
+const HEARTBEAT: Duration = Duration::from_secs(20);
+const STALE_SECONDS: u64 = 120;
+
+struct Coordinator {
+    dsn: String,
+    db: String,
+    table: String,
+    instance: String,
+    host: String,
+    run: String,
+    desired: usize,
+    global: usize,
+    local: usize, // 本地上限（写入许可的初始数量）
+    permits: Arc<Semaphore>,
+    granted: Mutex<(usize, usize)>, // 当前生效的许可数，尚未收回的许可数（收缩时正在使用中的许可）
+    reachable: AtomicBool,
+}
+
+static COORD: OnceLock<Coordinator> = OnceLock::new();
+
+// 解析 --coordination-table：dst:<表> 或 src:<表>，表名可带库名，不带时使用该端的 --dst-db / --src-db
+fn parse_target(opt: &Opt) -> anyhow::Result<(String, String, String)> {
+    let (side, table) = opt
+        .coordination_table
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!(format!("--coordination-table 格式为 dst:<表> 或 src:<表>: {}", opt.coordination_table)))?;
+    let (dsn, db) = match side {
+        "dst" => (&opt.dst_dsn, &opt.dst_db),
+        "src" => (&opt.src_dsn, &opt.src_db),
+        _ => anyhow::bail!(format!("--coordination-table 的集群只能是 dst 或 src: {}", opt.coordination_table)),
+    };
+    if table.is_empty() {
+        anyhow::bail!(format!("--coordination-table 缺少表名: {}", opt.coordination_table));
+    }
+    Ok((dsn.clone(), db.clone(), table.to_string()))
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+// 按期望比例分配全局上限，至少 1 个
+fn share(desired: usize, others: usize, global: usize) -> usize {
+    let total = desired + others;
+    if total <= global {
+        return desired;
+    }
+    (global * desired / total).clamp(1, desired)
+}
+
+// 进程启动时调用；未指定 --coordination-table 时不做任何事
+pub async fn start(opt: &Opt, permits: &Arc<Semaphore>) -> anyhow::Result<()> {
+    if opt.coordination_table.is_empty() {
+        return Ok(());
+    }
+    if opt.global_insert_concurrency == 0 {
+        anyhow::bail!("--coordination-table 需要同时指定 --global-insert-concurrency");
+    }
+    let (dsn, db, table) = parse_target(opt)?;
+    let multi = !opt.tables_file.is_empty() || opt.all_tables;
+    let local = if opt.max_concurrent_inserts == 0 { Semaphore::MAX_PERMITS } else { opt.max_concurrent_inserts };
+    // 每个 worker 同时只有一个写入请求，本地不限时按 worker 总数登记
+    let workers = opt.parallelism.max(1) * if multi { opt.table_concurrency.max(1) } else { 1 };
+    let desired = local.min(workers);
+    let host = std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok().map(|h| h.trim().to_string()))
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    let c = Coordinator {
+        dsn,
+        db,
+        table,
+        instance: format!("{}-{}-{}", host, std::process::id(), chrono::Local::now().timestamp()),
+        host,
+        run: if multi { "multi".to_string() } else { format!("{}.{} -> {}.{}", opt.src_db, opt.src_table, opt.dst_db, opt.dst_table) },
+        desired,
+        global: opt.global_insert_concurrency,
+        local,
+        permits: permits.clone(),
+        granted: Mutex::new((local, 0)),
+        reachable: AtomicBool::new(true),
+    };
+    info!(
+        "写入并发协调: 协调表 {}，登记期望并发 {}，全局上限 {}；协调表不可达时回到本地上限",
+        qualified(&c.db, &c.table),
+        c.desired,
+        c.global
+    );
+    let create = format!(
+        "CREATE TABLE IF NOT EXISTS {} (instance String, host String, pid UInt32, run String, desired UInt32, granted UInt32, heartbeat DateTime DEFAULT now()) \
+         ENGINE = ReplacingMergeTree(heartbeat) ORDER BY instance TTL heartbeat + INTERVAL 1 DAY",
+        qualified(&c.db, &c.table)
+    );
+    if let Err(e) = ch_execute(&c.dsn, &c.db, &create).await {
+        warn!("写入并发协调: 创建协调表失败，暂按本地上限写入: {}", e);
+    }
+    let c = COORD.get_or_init(|| c);
+    c.tick().await;
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(HEARTBEAT).await;
+            c.tick().await;
+        }
+    });
+    Ok(())
+}
+
+// 运行结束时登记期望为 0，其他进程不必等登记过期即可使用释放的额度
+pub async fn leave() {
+    if let Some(c) = COORD.get() {
+        if let Err(e) = c.register(0, 0).await {
+            warn!("写入并发协调: 注销登记失败（{} 秒后自动过期）: {}", STALE_SECONDS, e);
+        }
+    }
+}
+
+impl Coordinator {
+    async fn register(&self, desired: usize, granted: usize) -> anyhow::Result<()> {
+        let sql = format!(
+            "INSERT INTO {} (instance, host, pid, run, desired, granted) VALUES ({}, {}, {}, {}, {}, {})",
+            qualified(&self.db, &self.table),
+            quote(&self.instance),
+            quote(&self.host),
+            std::process::id(),
+            quote(&self.run),
+            desired,
+            granted.min(desired)
+        );
+        ch_execute(&self.dsn, &self.db, &sql).await
+    }
+
+    // 其他进程活跃登记的期望并发之和
+    async fn others(&self) -> anyhow::Result<usize> {
+        let sql = format!(
+            "SELECT sum(d) AS total FROM (SELECT argMax(desired, heartbeat) AS d FROM {} WHERE instance != {} GROUP BY instance \
+             HAVING max(heartbeat) >= now() - {}) FORMAT JSONEachRow",
+            qualified(&self.db, &self.table),
+            quote(&self.instance),
+            STALE_SECONDS
+        );
+        let rows = ch_query_rows(&self.dsn, &self.db, &sql).await?;
+        Ok(json_u64(rows.first().and_then(|r| r.get("total"))) as usize)
+    }
+
+    async fn tick(&self) {
+        let granted = self.granted.lock().unwrap().0;
+        let res = async {
+            self.register(self.desired, granted).await?;
+            self.others().await
+        }
+        .await;
+        match res {
+            Ok(others) => {
+                if !self.reachable.swap(true, Ordering::Relaxed) {
+                    info!("写入并发协调: 协调表恢复可用");
+                }
+                let n = share(self.desired, others, self.global);
+                if n != granted {
+                    info!("写入并发协调: 其他进程期望并发 {}，全局上限 {}，本进程写入并发调整为 {}", others, self.global, n);
+                }
+                self.resize(n);
+            }
+            Err(e) => {
+                if self.reachable.swap(false, Ordering::Relaxed) {
+                    warn!("写入并发协调: 协调表不可达，回到本地写入上限: {}", e);
+                }
+                self.resize(self.local);
+            }
+        }
+    }
+
+    // 调整写入许可数：增加时先抵消未收回的部分；减少时收回空闲许可，使用中的许可待释放后在后续心跳收回
+    fn resize(&self, target: usize) {
+        let mut g = self.granted.lock().unwrap();
+        let (cur, mut owed) = *g;
+        if target > cur {
+            let add = target - cur;
+            let cancel = add.min(owed);
+            owed -= cancel;
+            self.permits.add_permits(add - cancel);
+        } else {
+            owed += cur - target;
+        }
+        owed -= self.permits.forget_permits(owed);
+        *g = (target, owed);
+    }
+}
@@ -0,0 +1,278 @@
+// ===================== 建表语句的表级设置与 TTL 改写（datacp ddl --create-*） =====================
+// 目标集群的存储策略、索引粒度与数据保留要求可能与源端不同：原样复制的建表语句会因 storage_policy 不存在而失败，
+// 或把源端的过期规则带到本应保留更久的目标表。对 MergeTree 系列的表，按顶层（括号、引号、反引号之外）结构拆出
+// ENGINE 起的各子句（PARTITION BY / PRIMARY KEY / ORDER BY / SAMPLE BY / TTL / SETTINGS / COMMENT），只改写其中的 SETTINGS 与 TTL：
+//   --create-rewrite k=v  替换源表已有的表级设置（源表没有该设置时不添加）
+//   --create-set k=v      添加或替换表级设置
+//   --create-strip ttl|k  去掉表级 TTL 子句（列级 TTL 不变），或去掉某个表级设置
+// 设置名按目标端 system.merge_tree_settings 校验，未知的设置名在创建任何对象之前报错
+
+use std::collections::HashSet;
+
+use crate::ch_query_rows;
+
+// ENGINE 之后可能出现的顶层子句（SHOW CREATE 输出的关键字均为大写）
+const CLAUSES: [&str; 8] = ["ENGINE", "PARTITION BY", "PRIMARY KEY", "ORDER BY", "SAMPLE BY", "TTL", "SETTINGS", "COMMENT"];
+
+#[derive(Debug, Default, Clone)]
+pub struct Rules {
+    rewrite: Vec<(String, String)>,
+    set: Vec<(String, String)>,
+    strip: Vec<String>,
+}
+
+fn pair(flag: &str, s: &str) -> anyhow::Result<(String, String)> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.trim().is_empty() && !v.trim().is_empty() => Ok((k.trim().to_string(), v.trim().to_string())),
+        _ => anyhow::bail!(format!("{} 需要 key=value 形式: {}", flag, s)),
+    }
+}
+
+impl Rules {
+    pub fn parse(rewrite: &[String], strip: &[String], set: &[String]) -> anyhow::Result<Self> {
+        Ok(Rules {
+            rewrite: rewrite.iter().map(|s| pair("--create-rewrite", s)).collect::<anyhow::Result<_>>()?,
+            set: set.iter().map(|s| pair("--create-set", s)).collect::<anyhow::Result<_>>()?,
+            strip: strip.iter().map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rewrite.is_empty() && self.set.is_empty() && self.strip.is_empty()
+    }
+
+    // 规则的文字形式，写入报告
+    pub fn describe(&self) -> Vec<String> {
+        let mut out: Vec<String> = self.rewrite.iter().map(|(k, v)| format!("rewrite {}={}", k, v)).collect();
+        out.extend(self.strip.iter().map(|k| format!("strip {}", k)));
+        out.extend(self.set.iter().map(|(k, v)| format!("set {}={}", k, v)));
+        out
+    }
+
+    // 规则中的设置名都须是目标端 system.merge_tree_settings 中的设置（--create-strip 另可为 ttl）
+    pub async fn validate(&self, dsn: &str, db: &str) -> anyhow::Result<()> {
+        let keys: Vec<&str> = self
+            .rewrite
+            .iter()
+            .chain(&self.set)
+            .map(|(k, _)| k.as_str())
+            .chain(self.strip.iter().map(|k| k.as_str()).filter(|k| !k.eq_ignore_ascii_case("ttl")))
+            .collect();
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let rows = ch_query_rows(dsn, db, "SELECT name FROM system.merge_tree_settings FORMAT JSONEachRow").await?;
+        let known: HashSet<&str> = rows.iter().filter_map(|r| r.get("name").and_then(|v| v.as_str())).collect();
+        let mut unknown: Vec<&str> = keys.into_iter().filter(|k| !known.contains(k)).collect();
+        unknown.sort_unstable();
+        unknown.dedup();
+        if !unknown.is_empty() {
+            anyhow::bail!(format!("未知的表级设置: {}（目标端 system.merge_tree_settings 中不存在）", unknown.join(", ")));
+        }
+        Ok(())
+    }
+}
+
+// 按顶层结构扫描：f(位置) 只对括号、引号、反引号之外的位置调用，返回值为随后跳过的字节数（0 为逐字节继续）
+fn top_level(s: &str, mut f: impl FnMut(usize) -> usize) {
+    let b = s.as_bytes();
+    let (mut depth, mut quote) = (0i32, None::<u8>);
+    let mut i = 0;
+    while i < b.len() {
+        let c = b[i];
+        if let Some(q) = quote {
+            if c == b'\\' {
+                i += 2;
+                continue;
+            }
+            if c == q {
+                quote = None;
+            }
+        } else {
+            match c {
+                b'\'' | b'"' | b'`' => quote = Some(c),
+                b'(' | b'[' => depth += 1,
+                b')' | b']' => depth -= 1,
+                _ if depth == 0 => {
+                    let skip = f(i);
+                    if skip > 0 {
+                        i += skip;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+}
+
+// 顶层子句关键字的位置：前面是空白，后面是空白、'=' 或语句结尾
+fn clause_starts(ddl: &str) -> Vec<(usize, &'static str)> {
+    let b = ddl.as_bytes();
+    let mut out = Vec::new();
+    top_level(ddl, |i| {
+        if i > 0 && !b[i - 1].is_ascii_whitespace() {
+            return 0;
+        }
+        let hit = CLAUSES.iter().find(|kw| {
+            b[i..].starts_with(kw.as_bytes()) && b.get(i + kw.len()).is_none_or(|c| c.is_ascii_whitespace() || *c == b'=')
+        });
+        match hit {
+            Some(kw) => {
+                out.push((i, *kw));
+                kw.len()
+            }
+            None => 0,
+        }
+    });
+    out
+}
+
+// 顶层逗号分隔
+pub fn split_commas(s: &str) -> Vec<String> {
+    let mut cuts = Vec::new();
+    top_level(s, |i| {
+        if s.as_bytes()[i] == b',' {
+            cuts.push(i);
+        }
+        0
+    });
+    let mut out = Vec::new();
+    let mut from = 0;
+    for c in cuts.into_iter().chain([s.len()]) {
+        let part = s[from..c].trim();
+        if !part.is_empty() {
+            out.push(part.to_string());
+        }
+        from = c + 1;
+    }
+    out
+}
+
+// CREATE TABLE 语句按顶层子句拆分：head 为 ENGINE 之前的部分（表名与列定义），clauses 为 ENGINE 起的各子句
+struct Create {
+    head: String,
+    clauses: Vec<(&'static str, String)>,
+}
+
+impl Create {
+    fn parse(ddl: &str) -> Option<Self> {
+        let starts = clause_starts(ddl);
+        let first = starts.iter().position(|(_, kw)| *kw == "ENGINE")?;
+        let starts = &starts[first..];
+        let clauses = starts
+            .iter()
+            .enumerate()
+            .map(|(n, (i, kw))| {
+                let end = starts.get(n + 1).map_or(ddl.len(), |(j, _)| *j);
+                (*kw, ddl[i + kw.len()..end].trim().to_string())
+            })
+            .collect();
+        Some(Create { head: ddl[..starts[0].0].trim_end().to_string(), clauses })
+    }
+
+    fn find(&self, kw: &str) -> Option<usize> {
+        self.clauses.iter().position(|(k, _)| *k == kw)
+    }
+
+    fn render(&self) -> String {
+        let mut out = self.head.clone();
+        for (kw, body) in &self.clauses {
+            out.push('\n');
+            out.push_str(kw);
+            if !body.is_empty() {
+                out.push(' ');
+                out.push_str(body);
+            }
+        }
+        out
+    }
+}
+
+// 新值按原值的形式书写：原值带引号时加单引号；源表没有该设置时数字与 true/false 原样，其余加单引号
+fn literal(v: &str, like: Option<&str>) -> String {
+    if v.starts_with('\'') {
+        return v.to_string();
+    }
+    let quoted = match like {
+        Some(old) => old.starts_with('\''),
+        None => v.parse::<f64>().is_err() && !v.eq_ignore_ascii_case("true") && !v.eq_ignore_ascii_case("false"),
+    };
+    if quoted {
+        format!("'{}'", v.replace('\'', "\\'"))
+    } else {
+        v.to_string()
+    }
+}
+
+// 按规则改写 MergeTree 系列表的建表语句；返回改写后的语句与每条实际生效的改动，
+// 不是 MergeTree 系列（或无法拆出 ENGINE 子句）以及没有改动时原样返回
+pub fn apply(ddl: &str, rules: &Rules) -> (String, Vec<String>) {
+    let unchanged = (ddl.to_string(), Vec::new());
+    if rules.is_empty() {
+        return unchanged;
+    }
+    let Some(mut c) = Create::parse(ddl) else { return unchanged };
+    if !c.clauses[0].1.contains("MergeTree") {
+        return unchanged;
+    }
+    let mut settings: Vec<(String, String)> = c
+        .find("SETTINGS")
+        .map(|p| {
+            split_commas(&c.clauses[p].1)
+                .into_iter()
+                .map(|s| match s.split_once('=') {
+                    Some((k, v)) => (k.trim().to_string(), v.trim().to_string()),
+                    None => (s, String::new()),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut changes = Vec::new();
+    for (k, v, add) in rules.rewrite.iter().map(|(k, v)| (k, v, false)).chain(rules.set.iter().map(|(k, v)| (k, v, true))) {
+        match settings.iter_mut().find(|(n, _)| n == k) {
+            Some(s) => {
+                let new = literal(v, Some(&s.1));
+                if new != s.1 {
+                    changes.push(format!("{}: {} -> {}", k, s.1, new));
+                    s.1 = new;
+                }
+            }
+            None if add => {
+                let new = literal(v, None);
+                changes.push(format!("{}: 添加 {}", k, new));
+                settings.push((k.clone(), new));
+            }
+            None => {}
+        }
+    }
+    for k in &rules.strip {
+        if k.eq_ignore_ascii_case("ttl") {
+            if let Some(p) = c.find("TTL") {
+                let (_, body) = c.clauses.remove(p);
+                changes.push(format!("去掉 TTL {}", body));
+            }
+        } else if let Some(p) = settings.iter().position(|(n, _)| n == k) {
+            let (k, v) = settings.remove(p);
+            changes.push(format!("去掉 {} = {}", k, v));
+        }
+    }
+    if changes.is_empty() {
+        return unchanged;
+    }
+    let body = settings.iter().map(|(k, v)| if v.is_empty() { k.clone() } else { format!("{} = {}", k, v) }).collect::<Vec<_>>().join(", ");
+    match (c.find("SETTINGS"), body.is_empty()) {
+        (Some(p), true) => {
+            c.clauses.remove(p);
+        }
+        (Some(p), false) => c.clauses[p].1 = body,
+        (None, false) => {
+            // SETTINGS 在 COMMENT 之前
+            let at = c.find("COMMENT").unwrap_or(c.clauses.len());
+            c.clauses.insert(at, ("SETTINGS", body));
+        }
+        (None, true) => {}
+    }
+    (c.render(), changes)
+}
@@ -0,0 +1,503 @@
+// ===================== 切换后处理：校验与 _bak 表保留策略 =====================
+
+use log::{error, info, warn};
+use std::sync::{Arc, Mutex};
+
+use crate::report::{PostCutoverCheck, RunReport, SmokeCheck};
+use crate::{ch_execute, ch_execute_on_cluster, ch_query_rows, filter_sql, get_max_time_http, json_u64, qualified, server_version, shard, time_zone, Opt};
+
+// _bak 表保留策略
+#[derive(Debug, Clone, PartialEq)]
+pub enum BakRetention {
+    Keep,
+    Drop,
+    Ttl(u32), // 天
+}
+
+pub fn parse_bak_retention(s: &str) -> anyhow::Result<BakRetention> {
+    match s.trim() {
+        "keep" => Ok(BakRetention::Keep),
+        "drop" => Ok(BakRetention::Drop),
+        t if t.starts_with("ttl:") => {
+            let days: u32 = t[4..].parse().map_err(|_| anyhow::anyhow!(format!("--bak-retention ttl 天数不正确: {}", t)))?;
+            Ok(BakRetention::Ttl(days))
+        }
+        other => anyhow::bail!(format!("不支持的 --bak-retention: {}（drop|keep|ttl:<days>）", other)),
+    }
+}
+
+fn on_cluster(opt: &Opt) -> bool {
+    opt.is_src_distributed && !opt.cluster_name.is_empty()
+}
+
+// 在源端执行 DDL，分布式源表走 ON CLUSTER 并等待完成
+async fn src_ddl(opt: &Opt, sql: &str) -> anyhow::Result<()> {
+    info!("_bak DDL: {sql}");
+    if on_cluster(opt) {
+        ch_execute_on_cluster(&opt.src_dsn, &opt.src_db, sql, &opt.cluster_name, opt.ddl_timeout, opt.ddl_poll_interval).await
+    } else {
+        ch_execute(&opt.src_dsn, &opt.src_db, sql).await
+    }
+}
+
+fn cluster_clause(opt: &Opt) -> String {
+    if on_cluster(opt) { format!(" ON CLUSTER {}", opt.cluster_name) } else { String::new() }
+}
+
+// 在目标端执行 DDL，分布式目标表走 ON CLUSTER 并等待完成
+async fn dst_ddl(opt: &Opt, sql: &str) -> anyhow::Result<()> {
+    info!("cutover DDL: {sql}");
+    if opt.is_dst_distributed && !opt.cluster_name.is_empty() {
+        let sql = format!("{} ON CLUSTER {}", sql, opt.cluster_name);
+        ch_execute_on_cluster(&opt.dst_dsn, &opt.dst_db, &sql, &opt.cluster_name, opt.ddl_timeout, opt.ddl_poll_interval).await
+    } else {
+        ch_execute(&opt.dst_dsn, &opt.dst_db, sql).await
+    }
+}
+
+// DSN 的 host:port，用于判断两端是否为同一实例
+fn endpoint(dsn: &str) -> String {
+    let re = regex::Regex::new(r"^(https?)://(?:[^@/]*@)?([^/:?]+)(?::(\d+))?").unwrap();
+    re.captures(dsn)
+        .map(|c| format!("{}:{}", &c[2], c.get(3).map(|m| m.as_str()).unwrap_or(if &c[1] == "https" { "443" } else { "8123" })))
+        .unwrap_or_default()
+}
+
+// --cutover-into-src-db 需要跨库 RENAME，两端须为同一实例
+pub fn check_cutover_into_src_db(opt: &Opt) -> anyhow::Result<()> {
+    if opt.cutover_into_src_db && endpoint(&opt.src_dsn) != endpoint(&opt.dst_dsn) {
+        anyhow::bail!(format!(
+            "--cutover-into-src-db 要求源端与目标端为同一实例（源端 {}，目标端 {}）",
+            endpoint(&opt.src_dsn),
+            endpoint(&opt.dst_dsn)
+        ));
+    }
+    Ok(())
+}
+
+// 8.1 源表 src_db.src_table 改名为 src_db.{src_table}_bak
+pub async fn rename_src_to_bak(opt: &Opt, bak_table: &str) -> anyhow::Result<()> {
+    src_ddl(
+        opt,
+        &format!("RENAME TABLE {} TO {}{}", qualified(&opt.src_db, &opt.src_table), qualified(&opt.src_db, bak_table), cluster_clause(opt)),
+    )
+    .await
+}
+
+// 8.5 目标表 dst_db.read_table 改名为 src_table，--cutover-into-src-db 时移到源库
+pub async fn rename_dst_to_src(opt: &Opt) -> anyhow::Result<()> {
+    dst_ddl(opt, &format!("RENAME TABLE {} TO {}", qualified(&opt.dst_db, opt.read_table()), qualified(opt.cutover_db(), &opt.src_table))).await
+}
+
+// ---------- Replicated 表的 ZooKeeper 路径与 --cutover-strategy recreate ----------
+// RENAME 只改表名，不改 ZooKeeper 路径；路径由 {table} 宏生成时，改名后的表仍写旧路径，之后按旧名新建的表会与之冲突。
+// recreate 按切换后的表名新建目标表（路径中的表名一并替换），逐分区 ATTACH PARTITION FROM 中间表，核对行数后删除中间表
+
+pub fn parse_cutover_strategy(s: &str) -> anyhow::Result<()> {
+    match s {
+        "rename" | "recreate" => Ok(()),
+        other => anyhow::bail!(format!("不支持的 --cutover-strategy: {}（rename|recreate）", other)),
+    }
+}
+
+// 表的 zookeeper_path（宏已展开），非 Replicated 表为 None
+pub async fn zookeeper_path(dsn: &str, db: &str, table: &str) -> anyhow::Result<Option<String>> {
+    let sql = format!(
+        "SELECT zookeeper_path FROM system.replicas WHERE database = '{}' AND table = '{}' FORMAT JSONEachRow",
+        db.replace('\'', "\\'"),
+        table.replace('\'', "\\'")
+    );
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    Ok(rows.first().and_then(|r| r.get("zookeeper_path")).and_then(|v| v.as_str()).map(|s| s.to_string()))
+}
+
+// 路径中有一段等于表名
+pub fn path_embeds_table(path: &str, table: &str) -> bool {
+    path.split('/').any(|s| s == table)
+}
+
+fn replace_path_segment(path: &str, from: &str, to: &str) -> String {
+    path.split('/').map(|s| if s == from { to } else { s }).collect::<Vec<_>>().join("/")
+}
+
+// Replicated 表的各副本都需要建表/删表，指定 --cluster-name 时走 ON CLUSTER
+async fn replicated_ddl(opt: &Opt, sql: &str) -> anyhow::Result<()> {
+    info!("cutover DDL: {sql}");
+    if opt.cluster_name.is_empty() {
+        ch_execute(&opt.dst_dsn, &opt.dst_db, sql).await
+    } else {
+        ch_execute_on_cluster(&opt.dst_dsn, &opt.dst_db, sql, &opt.cluster_name, opt.ddl_timeout, opt.ddl_poll_interval).await
+    }
+}
+
+fn on_cluster_clause(opt: &Opt) -> String {
+    if opt.cluster_name.is_empty() { String::new() } else { format!(" ON CLUSTER {}", opt.cluster_name) }
+}
+
+// recreate 8.5.1：按中间表（dst_db.read_table）的建表语句新建切换后的表，ZooKeeper 路径中的表名替换为切换后的表名
+pub async fn create_final_table(opt: &Opt) -> anyhow::Result<()> {
+    let (from, to) = (qualified(&opt.dst_db, opt.read_table()), qualified(opt.cutover_db(), &opt.src_table));
+    let zk = zookeeper_path(&opt.dst_dsn, &opt.dst_db, opt.read_table())
+        .await?
+        .ok_or_else(|| anyhow::anyhow!(format!("{} 不是 Replicated 表，--cutover-strategy recreate 不适用", from)))?;
+    let new_zk = replace_path_segment(&zk, opt.read_table(), &opt.src_table);
+    // 新路径已被占用（如同一 ZooKeeper 上的 _bak 表仍使用该路径）时建表必然失败，提前给出明确原因
+    let (parent, leaf) = new_zk.rsplit_once('/').unwrap_or(("", &new_zk));
+    let sql = format!(
+        "SELECT count() AS c FROM system.zookeeper WHERE path = '{}' AND name = '{}' FORMAT JSONEachRow",
+        if parent.is_empty() { "/" } else { parent },
+        leaf
+    );
+    let rows = ch_query_rows(&opt.dst_dsn, &opt.dst_db, &sql).await?;
+    if json_u64(rows.first().and_then(|r| r.get("c"))) > 0 {
+        anyhow::bail!(format!("ZooKeeper 路径 {} 已存在（可能是同一 ZooKeeper 上的 _bak 表），无法按该路径新建 {}", new_zk, to));
+    }
+    let rows = ch_query_rows(&opt.dst_dsn, &opt.dst_db, &format!("SHOW CREATE TABLE {} FORMAT JSONEachRow", from)).await?;
+    let stmt = rows.first().and_then(|r| r.get("statement")).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    let engine = regex::Regex::new(r"(Replicated\w*MergeTree\(\s*')([^']*)'").unwrap();
+    let Some(path) = engine.captures(&stmt).map(|c| c[2].to_string()) else {
+        anyhow::bail!(format!("无法从 {} 的建表语句中解析 ZooKeeper 路径", from));
+    };
+    let stmt = engine.replace(&stmt, |c: &regex::Captures| format!("{}{}'", &c[1], replace_path_segment(&path, opt.read_table(), &opt.src_table)));
+    let header = regex::Regex::new(r"^CREATE TABLE\s+\S+(\s+UUID\s+'[^']*')?").unwrap();
+    let create = header.replace(&stmt, format!("CREATE TABLE {}{}", to, on_cluster_clause(opt)).as_str()).to_string();
+    replicated_ddl(opt, &create).await?;
+    info!("已按 ZooKeeper 路径 {} 新建 {}", new_zk, to);
+    Ok(())
+}
+
+async fn partition_rows(opt: &Opt, db: &str, table: &str) -> anyhow::Result<Vec<(String, u64)>> {
+    let sql = format!(
+        "SELECT partition_id, sum(rows) AS r FROM system.parts WHERE database = '{}' AND table = '{}' AND active \
+         GROUP BY partition_id ORDER BY partition_id FORMAT JSONEachRow",
+        db, table
+    );
+    let rows = ch_query_rows(&opt.dst_dsn, &opt.dst_db, &sql).await?;
+    Ok(rows
+        .iter()
+        .map(|r| (r.get("partition_id").and_then(|v| v.as_str()).unwrap_or("").to_string(), json_u64(r.get("r"))))
+        .collect())
+}
+
+// recreate 8.5.2：中间表的分区逐个 ATTACH PARTITION FROM 到新表；新表中已有行的分区视为已挂载，重复执行是安全的
+pub async fn attach_partitions(opt: &Opt) -> anyhow::Result<()> {
+    let (from, to) = (qualified(&opt.dst_db, opt.read_table()), qualified(opt.cutover_db(), &opt.src_table));
+    let done: std::collections::HashMap<String, u64> = partition_rows(opt, opt.cutover_db(), &opt.src_table).await?.into_iter().collect();
+    for (pid, rows) in partition_rows(opt, &opt.dst_db, opt.read_table()).await? {
+        if done.get(&pid).copied().unwrap_or(0) > 0 {
+            continue;
+        }
+        let sql = format!("ALTER TABLE {} ATTACH PARTITION ID '{}' FROM {}", to, pid, from);
+        info!("cutover DDL: {sql}（{} 行）", rows);
+        ch_execute(&opt.dst_dsn, &opt.dst_db, &sql).await?;
+    }
+    Ok(())
+}
+
+// recreate 8.5.3：两表行数一致后删除中间表
+pub async fn drop_intermediate(opt: &Opt) -> anyhow::Result<()> {
+    let (from, to) = (qualified(&opt.dst_db, opt.read_table()), qualified(opt.cutover_db(), &opt.src_table));
+    let count = |t: String| async move {
+        let rows = ch_query_rows(&opt.dst_dsn, &opt.dst_db, &format!("SELECT count() AS c FROM {} FORMAT JSONEachRow", t)).await?;
+        anyhow::Ok(json_u64(rows.first().and_then(|r| r.get("c"))))
+    };
+    let (a, b) = (count(from.clone()).await?, count(to.clone()).await?);
+    if a != b {
+        anyhow::bail!(format!("{} 有 {} 行，新表 {} 有 {} 行，不删除中间表", from, a, to, b));
+    }
+    replicated_ddl(opt, &format!("DROP TABLE {}{} SYNC", from, on_cluster_clause(opt))).await
+}
+
+async fn count_since(opt: &Opt, dsn: &str, db: &str, table: &str, start: &str, filter: &str) -> anyhow::Result<u64> {
+    let sql = format!("SELECT count() AS c FROM {} WHERE {} >= {}{} FORMAT JSONEachRow", qualified(db, table), opt.time_field, opt.time_lit(start), filter);
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    Ok(json_u64(rows.first().and_then(|r| r.get("c"))))
+}
+
+// 切换后校验：切换后的新表（原目标表）行数不少于 _bak 表在迁移窗口内的行数
+pub async fn verify_after_cutover(opt: &Opt, bak_table: &str, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<bool> {
+    let bak_rows = count_since(opt, &opt.src_dsn, &opt.src_db, bak_table, &opt.start_time, &filter_sql(&opt.filter)).await?;
+    let new_rows = count_since(opt, &opt.dst_dsn, opt.cutover_db(), &opt.src_table, &opt.start_time, &filter_sql(&opt.filter)).await?;
+    let passed = new_rows >= bak_rows;
+    info!("切换后校验: {} 行数 {}, 新表 {} 行数 {}, 结果 {}", bak_table, bak_rows, opt.src_table, new_rows, if passed { "通过" } else { "失败" });
+    report.lock().unwrap().post_cutover_check = Some(PostCutoverCheck { bak_rows, new_rows, passed });
+    Ok(passed)
+}
+
+// 替换 DSN 中的用户名与密码
+fn dsn_with_user(dsn: &str, user: &str, pass: &str) -> String {
+    let re = regex::Regex::new(r"^(https?://)[^@/]*@").unwrap();
+    re.replace(dsn, |c: &regex::Captures| format!("{}{}:{}@", &c[1], user, pass)).to_string()
+}
+
+// 最大时间与 get_max_time_http 一样换算（列声明了时区时为 UTC），便于比较
+async fn count_and_max(opt: &Opt, dsn: &str, db: &str, table: &str) -> anyhow::Result<(u64, String)> {
+    let sql = format!("SELECT count() AS c, toString(max({})) AS m FROM {} FORMAT JSONEachRow", opt.time_field, qualified(db, table));
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    let r = rows.first();
+    let max = r.and_then(|r| r.get("m")).and_then(|v| v.as_str()).unwrap_or("");
+    Ok((json_u64(r.and_then(|r| r.get("c"))), time_zone::from_column(opt.time_zone.as_ref(), max, true)))
+}
+
+// 切换后冒烟查询：以应用账号（--smoke-user，未指定时为目标端账号）查询切换后的表，
+// 行数须与迁移账号所见一致（允许 --smoke-tolerance 比例的差异，覆盖切换后新写入），最大时间不早于 _bak 表；
+// 可发现新表缺少授权或行策略
+pub async fn smoke_check(opt: &Opt, bak_table: &str, report: &Arc<Mutex<RunReport>>) -> bool {
+    let res: anyhow::Result<SmokeCheck> = async {
+        let (dsn, user) = if opt.smoke_user.is_empty() {
+            (opt.dst_dsn.clone(), String::new())
+        } else {
+            let pass = if opt.smoke_password_env.is_empty() {
+                String::new()
+            } else {
+                std::env::var(&opt.smoke_password_env)
+                    .map_err(|_| anyhow::anyhow!(format!("环境变量 {} 未设置", opt.smoke_password_env)))?
+            };
+            (dsn_with_user(&opt.dst_dsn, &opt.smoke_user, &pass), opt.smoke_user.clone())
+        };
+        let expected_max_time = get_max_time_http(&opt.src_dsn, &opt.src_db, bak_table, &opt.time_field, &filter_sql(&opt.filter), opt.time_zone.as_ref()).await?;
+        let (expected_rows, _) = count_and_max(opt, &opt.dst_dsn, opt.cutover_db(), &opt.src_table).await?;
+        let mut check = SmokeCheck { user, expected_rows, expected_max_time, ..Default::default() };
+        match count_and_max(opt, &dsn, opt.cutover_db(), &opt.src_table).await {
+            Ok((rows, max_time)) => {
+                let diff = rows.abs_diff(expected_rows) as f64;
+                check.passed = diff <= expected_rows as f64 * opt.smoke_tolerance && max_time >= check.expected_max_time;
+                check.rows = rows;
+                check.max_time = max_time;
+            }
+            Err(e) => check.error = Some(e.to_string()),
+        }
+        Ok(check)
+    }
+    .await;
+    let check = res.unwrap_or_else(|e| SmokeCheck { error: Some(e.to_string()), ..Default::default() });
+    info!(
+        "切换后冒烟查询{}: 行数 {}（期望 {}），最大时间 {}（期望不早于 {}），结果 {}{}",
+        if check.user.is_empty() { String::new() } else { format!("（用户 {}）", check.user) },
+        check.rows, check.expected_rows, check.max_time, check.expected_max_time,
+        if check.passed { "通过" } else { "失败" },
+        check.error.as_ref().map(|e| format!(": {}", e)).unwrap_or_default()
+    );
+    let passed = check.passed;
+    report.lock().unwrap().smoke_check = Some(check);
+    passed
+}
+
+// 新表与 _bak 表在同一实例的同一 Atomic 库、不走 ON CLUSTER，且服务端支持 EXCHANGE TABLES 时，回滚可先原子交换
+async fn can_exchange(opt: &Opt) -> bool {
+    if opt.cutover_db() != opt.src_db || endpoint(&opt.src_dsn) != endpoint(&opt.dst_dsn) || !opt.cluster_name.is_empty() {
+        return false;
+    }
+    if !server_version::supports(&opt.src_dsn, "exchange_tables") {
+        return false;
+    }
+    let sql = format!("SELECT engine FROM system.databases WHERE name = '{}' FORMAT JSONEachRow", opt.src_db.replace('\'', "\\'"));
+    match ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await {
+        Ok(rows) => rows.first().and_then(|r| r.get("engine")).and_then(|v| v.as_str()) == Some("Atomic"),
+        Err(e) => {
+            warn!("查询库 {} 的引擎失败，回滚改用两次 RENAME: {e}", opt.src_db);
+            false
+        }
+    }
+}
+
+// --auto-rollback：新表改回原名，_bak 表改回源表名。可以 EXCHANGE 时先交换两表，源表名在回滚过程中始终存在
+pub async fn rollback(opt: &Opt, bak_table: &str) -> anyhow::Result<()> {
+    if can_exchange(opt).await {
+        src_ddl(opt, &format!("EXCHANGE TABLES {} AND {}", qualified(&opt.src_db, &opt.src_table), qualified(&opt.src_db, bak_table))).await?;
+        dst_ddl(opt, &format!("RENAME TABLE {} TO {}", qualified(&opt.src_db, bak_table), qualified(&opt.dst_db, opt.read_table()))).await?;
+        warn!("已回滚切换（EXCHANGE TABLES）: {} 恢复为源表，新表改回 {}", opt.src_table, opt.read_table());
+        return Ok(());
+    }
+    dst_ddl(opt, &format!("RENAME TABLE {} TO {}", qualified(opt.cutover_db(), &opt.src_table), qualified(&opt.dst_db, opt.read_table()))).await?;
+    src_ddl(
+        opt,
+        &format!("RENAME TABLE {} TO {}{}", qualified(&opt.src_db, bak_table), qualified(&opt.src_db, &opt.src_table), cluster_clause(opt)),
+    )
+    .await?;
+    warn!("已回滚切换: {} 恢复为源表，新表改回 {}", opt.src_table, opt.read_table());
+    Ok(())
+}
+
+// _bak 表为分布式表时，同时处理其底层本地表（RENAME ON CLUSTER 不会重命名本地表）
+async fn bak_targets(opt: &Opt, bak_table: &str) -> anyhow::Result<Vec<String>> {
+    let (local_db, local_table) = shard::resolve_local_table(&opt.src_dsn, &opt.src_db, bak_table).await?;
+    let mut targets = Vec::new();
+    if local_table != bak_table || local_db != opt.src_db {
+        // 底层本地表与切换后的新表解析到同一张本地表时绝不能删除
+        let (new_db, new_table) = shard::resolve_local_table(&opt.dst_dsn, opt.cutover_db(), &opt.src_table).await?;
+        if (new_db.as_str(), new_table.as_str()) == (local_db.as_str(), local_table.as_str()) && opt.src_dsn == opt.dst_dsn {
+            anyhow::bail!(format!("_bak 底层本地表 {}.{} 与切换后的新表相同，拒绝处理", local_db, local_table));
+        }
+        targets.push(format!("{}.{}", local_db, local_table));
+    }
+    targets.push(format!("{}.{}", opt.src_db, bak_table));
+    Ok(targets)
+}
+
+// 按 --bak-retention 处理 _bak 表，drop 需要切换后校验通过且指定 --yes
+pub async fn apply_bak_retention(opt: &Opt, bak_table: &str, verified: bool, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<()> {
+    let retention = parse_bak_retention(&opt.bak_retention)?;
+    let action = match retention {
+        BakRetention::Keep => "keep".to_string(),
+        BakRetention::Drop if !verified => {
+            warn!("切换后校验未通过，保留 {}", bak_table);
+            "keep (verification failed)".to_string()
+        }
+        BakRetention::Drop if !opt.yes => {
+            warn!("--bak-retention drop 需要同时指定 --yes，保留 {}", bak_table);
+            "keep (--yes not given)".to_string()
+        }
+        BakRetention::Drop => {
+            let targets = bak_targets(opt, bak_table).await?;
+            // 先删分布式表再删本地表
+            for t in targets.iter().rev() {
+                src_ddl(opt, &format!("DROP TABLE IF EXISTS {}{}", t, cluster_clause(opt))).await?;
+            }
+            format!("dropped {}", targets.join(", "))
+        }
+        BakRetention::Ttl(days) => {
+            let targets = bak_targets(opt, bak_table).await?;
+            // TTL 只能作用于存储数据的本地表
+            let t = &targets[0];
+            let sql = format!(
+                "ALTER TABLE {}{} MODIFY TTL {} + INTERVAL {} DAY",
+                t, cluster_clause(opt), opt.time_field, days
+            );
+            match src_ddl(opt, &sql).await {
+                Ok(()) => format!("ttl {} days on {}", days, t),
+                Err(e) => {
+                    error!("设置 _bak TTL 失败: {e}");
+                    format!("ttl failed: {}; operator action required: {}", e, sql)
+                }
+            }
+        }
+    };
+    info!("_bak 表保留策略: {}", action);
+    report.lock().unwrap().bak_retention_action = Some(action);
+    Ok(())
+}
+
+// datacp cleanup：列出（指定 --yes 时删除）历史运行遗留的 {table}_bak 表。只匹配切换时生成的表名本身，
+// 不按前缀匹配，避免删到 events_backup、events_bakery 之类的用户表
+pub async fn cleanup_bak_tables(opt: &Opt) -> anyhow::Result<()> {
+    if opt.src_table.is_empty() {
+        anyhow::bail!("datacp cleanup 需要指定 --src-table");
+    }
+    let bak_table = format!("{}_bak", opt.src_table);
+    let sql = format!(
+        "SELECT name FROM system.tables WHERE database = '{}' AND name = '{}' FORMAT JSONEachRow",
+        opt.src_db.replace('\\', "\\\\").replace('\'', "\\'"),
+        bak_table.replace('\\', "\\\\").replace('\'', "\\'")
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    if !rows.iter().any(|r| r.get("name").and_then(|v| v.as_str()) == Some(bak_table.as_str())) {
+        info!("未发现遗留的 {}.{} 表", opt.src_db, bak_table);
+        return Ok(());
+    }
+    if !opt.yes {
+        info!("遗留表: {}.{}（指定 --yes 以删除）", opt.src_db, bak_table);
+        return Ok(());
+    }
+    for t in bak_targets(opt, &bak_table).await?.iter().rev() {
+        src_ddl(opt, &format!("DROP TABLE IF EXISTS {}{}", t, cluster_clause(opt))).await?;
+    }
+    info!("已删除遗留表: {}.{}", opt.src_db, bak_table);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+    use crate::mock_ch::{self, Seen};
+
+    fn opt(dsn: &str, extra: &[&str]) -> Opt {
+        let args = ["datacp", "--src-dsn", dsn, "--dst-dsn", dsn, "--src-db", "app", "--dst-db", "app_new"];
+        Opt::from_iter(args.iter().chain(["--src-table", "events", "--dst-table", "events_new", "--time-field", "ts"].iter()).chain(extra))
+    }
+
+    fn take(seen: &Seen) -> Vec<(String, String)> {
+        std::mem::take(&mut *seen.lock().unwrap())
+    }
+
+    fn pairs(v: &[(&str, &str)]) -> Vec<(String, String)> {
+        v.iter().map(|(d, s)| (d.to_string(), s.to_string())).collect()
+    }
+
+    #[tokio::test]
+    async fn statements_name_the_database_on_a_shared_server() {
+        let (dsn, seen) = mock_ch::serve(mock_ch::empty).await;
+        let opt = opt(&dsn, &[]);
+        let report = Arc::new(Mutex::new(RunReport::default()));
+        crate::get_column_names_http(&dsn, &opt.src_db, &opt.src_table).await.unwrap();
+        crate::get_max_time_http(&dsn, &opt.dst_db, opt.read_table(), &opt.time_field, "", None).await.unwrap();
+        rename_src_to_bak(&opt, "events_bak").await.unwrap();
+        rename_dst_to_src(&opt).await.unwrap();
+        verify_after_cutover(&opt, "events_bak", &report).await.unwrap();
+        rollback(&opt, "events_bak").await.unwrap();
+        assert_eq!(
+            take(&seen),
+            pairs(&[
+                ("app", "DESCRIBE TABLE app.events FORMAT JSONEachRow"),
+                ("app_new", "SELECT count() as c, toString(max(ts)) as max_time FROM app_new.events_new WHERE 1 FORMAT JSONEachRow"),
+                ("app", "RENAME TABLE app.events TO app.events_bak"),
+                ("app_new", "RENAME TABLE app_new.events_new TO app_new.events"),
+                ("app", "SELECT count() AS c FROM app.events_bak WHERE ts >= '1970-01-01 08:00:01' FORMAT JSONEachRow"),
+                ("app_new", "SELECT count() AS c FROM app_new.events WHERE ts >= '1970-01-01 08:00:01' FORMAT JSONEachRow"),
+                ("app_new", "RENAME TABLE app_new.events TO app_new.events_new"),
+                ("app", "RENAME TABLE app.events_bak TO app.events"),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn cutover_into_src_db_renames_across_databases() {
+        let (dsn, seen) = mock_ch::serve(mock_ch::empty).await;
+        let opt = opt(&dsn, &["--cutover-into-src-db"]);
+        check_cutover_into_src_db(&opt).unwrap();
+        rename_src_to_bak(&opt, "events_bak").await.unwrap();
+        rename_dst_to_src(&opt).await.unwrap();
+        rollback(&opt, "events_bak").await.unwrap();
+        assert_eq!(
+            take(&seen),
+            pairs(&[
+                ("app", "RENAME TABLE app.events TO app.events_bak"),
+                ("app_new", "RENAME TABLE app_new.events_new TO app.events"),
+                ("app_new", "RENAME TABLE app.events TO app_new.events_new"),
+                ("app", "RENAME TABLE app.events_bak TO app.events"),
+            ])
+        );
+        let elsewhere = Opt::from_iter(["datacp", "--src-dsn", &dsn, "--dst-dsn", "http://default:@127.0.0.1:1", "--cutover-into-src-db"]);
+        assert!(check_cutover_into_src_db(&elsewhere).is_err());
+    }
+
+    // 库中有 events_bak 与前缀相同的用户表
+    fn leftover_bak(sql: &str) -> (u16, String) {
+        if sql.starts_with("SELECT name FROM system.tables") {
+            let names = ["events_backup", "events_bak", "events_bakery"];
+            (200, names.iter().filter(|n| sql.contains(&format!("name = '{}'", n))).map(|n| format!("{{\"name\":\"{}\"}}\n", n)).collect())
+        } else if sql.contains("FROM system.tables") {
+            (200, "{\"engine\":\"MergeTree\",\"engine_full\":\"MergeTree ORDER BY id\"}\n".to_string())
+        } else {
+            (200, String::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn cleanup_drops_only_the_cutover_bak_table() {
+        let (dsn, seen) = mock_ch::serve(leftover_bak).await;
+        cleanup_bak_tables(&opt(&dsn, &[])).await.unwrap();
+        let listed = "SELECT name FROM system.tables WHERE database = 'app' AND name = 'events_bak' FORMAT JSONEachRow";
+        assert_eq!(take(&seen), pairs(&[("app", listed)]));
+        cleanup_bak_tables(&opt(&dsn, &["--yes"])).await.unwrap();
+        let drops: Vec<String> = take(&seen).into_iter().map(|(_, s)| s).filter(|s| s.starts_with("DROP")).collect();
+        assert_eq!(drops, ["DROP TABLE IF EXISTS app.events_bak"]);
+        let quoted = Opt::from_iter(["datacp", "--src-dsn", &dsn, "--src-db", "app", "--src-table", "o'brien", "--yes"]);
+        cleanup_bak_tables(&quoted).await.unwrap();
+        assert!(take(&seen)[0].1.contains("name = 'o\\'brien_bak'"));
+        let unnamed = Opt::from_iter(["datacp", "--src-dsn", &dsn, "--src-db", "app", "--yes"]);
+        assert!(cleanup_bak_tables(&unnamed).await.is_err());
+        assert!(take(&seen).is_empty());
+    }
+}
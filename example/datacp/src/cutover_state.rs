@@ -0,0 +1,307 @@
+// ===================== 切换状态（cutover.state） =====================
+// 第 8 步的各子步骤完成后立即记录到表目录下的 cutover.state（JSON）：
+//   src-renamed → bak-max-captured → bak-diffed（_bak 补差）→ bak-filled（兜底增量，分段记录见 phase_checkpoint）→ dst-renamed → verified（切换后校验与 _bak 保留策略）→ done-file-archived
+// 中途失败（如目标表 rename 超时）时状态文件保留，datacp resume-cutover（单表时直接重新运行亦可）从第一个未完成的步骤继续，不必重跑整个迁移；
+// --cutover-strategy recreate 时 dst-renamed 之前依次为 dst-created（按新表名新建）、dst-partitions-attached（分区挂载），删除中间表后记为 dst-renamed。
+// 多表运行遇到未完成的切换时拒绝启动。rename 前先检查两端表是否已是目标状态，补差按摘要比对，重复执行是安全的。
+// 切换完成或自动回滚后删除状态文件
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::report::{self, RunReport};
+use crate::{ch_query_rows, cutover, events, get_max_time_http, json_u64, post_cutover_watch, qualified, state_dir, status, Opt};
+
+const VERSION: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Step {
+    SrcRenamed,
+    BakMaxCaptured,
+    BakDiffed,
+    BakFilled,
+    DstCreated,
+    DstPartitionsAttached,
+    DstRenamed,
+    Verified,
+    DoneFileArchived,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepRecord {
+    pub step: Step,
+    pub time: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CutoverState {
+    pub version: u64,
+    pub src_db: String,
+    pub src_table: String,
+    pub dst_db: String,
+    pub dst_table: String,
+    pub bak_table: String,
+    pub bak_max_time: Option<String>,
+    pub started: String,
+    pub steps: Vec<StepRecord>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+fn path(opt: &Opt) -> PathBuf {
+    state_dir::table_dir(opt).join("cutover.state")
+}
+
+fn read(opt: &Opt) -> anyhow::Result<Option<CutoverState>> {
+    let p = path(opt);
+    let Ok(text) = std::fs::read_to_string(&p) else { return Ok(None) };
+    let mut s: CutoverState =
+        serde_json::from_str(&text).map_err(|e| anyhow::anyhow!(format!("切换状态文件 {} 无法解析: {}", p.display(), e)))?;
+    s.path = p;
+    Ok(Some(s))
+}
+
+// 普通运行前检查：有未完成的切换时拒绝启动，避免在已 rename 的表上重新迁移
+pub fn check_pending(opt: &Opt) -> anyhow::Result<()> {
+    if let Some(s) = read(opt)? {
+        anyhow::bail!(format!(
+            "{} 记录了未完成的切换（已完成: {}），请使用相同参数运行 datacp resume-cutover 继续，或人工处理后删除该文件",
+            s.path.display(),
+            s.completed()
+        ));
+    }
+    Ok(())
+}
+
+// datacp resume-cutover：读取状态文件，参数须指向同一对表
+pub fn load(opt: &Opt) -> anyhow::Result<CutoverState> {
+    let s = read(opt)?.ok_or_else(|| anyhow::anyhow!(format!("没有未完成的切换（{} 不存在）", path(opt).display())))?;
+    if (s.src_db.as_str(), s.src_table.as_str(), s.dst_db.as_str(), s.dst_table.as_str())
+        != (opt.src_db.as_str(), opt.src_table.as_str(), opt.dst_db.as_str(), opt.dst_table.as_str())
+    {
+        anyhow::bail!(format!(
+            "切换状态文件记录的是 {}.{} -> {}.{}，与本次参数不一致",
+            s.src_db, s.src_table, s.dst_db, s.dst_table
+        ));
+    }
+    info!("继续中断的切换: 开始于 {}，已完成: {}", s.started, s.completed());
+    Ok(s)
+}
+
+async fn table_exists(dsn: &str, db: &str, table: &str) -> anyhow::Result<bool> {
+    let sql = format!(
+        "SELECT count() AS c FROM system.tables WHERE database = '{}' AND name = '{}' FORMAT JSONEachRow",
+        db.replace('\'', "\\'"),
+        table.replace('\'', "\\'")
+    );
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    Ok(json_u64(rows.first().and_then(|r| r.get("c"))) > 0)
+}
+
+// 单表普通运行：有未完成的切换时按 resume-cutover 继续
+pub fn pending(opt: &Opt) -> anyhow::Result<Option<CutoverState>> {
+    if read(opt)?.is_none() {
+        return Ok(None);
+    }
+    warn!("检测到未完成的切换，自动继续（等同于 datacp resume-cutover）");
+    load(opt).map(Some)
+}
+
+impl CutoverState {
+    // 8.1 之前创建
+    pub fn begin(opt: &Opt, bak_table: &str) -> anyhow::Result<Self> {
+        let s = CutoverState {
+            version: VERSION,
+            src_db: opt.src_db.clone(),
+            src_table: opt.src_table.clone(),
+            dst_db: opt.dst_db.clone(),
+            dst_table: opt.dst_table.clone(),
+            bak_table: bak_table.to_string(),
+            bak_max_time: None,
+            started: report::now_str(),
+            steps: Vec::new(),
+            path: path(opt),
+        };
+        s.save()?;
+        Ok(s)
+    }
+
+    fn completed(&self) -> String {
+        if self.steps.is_empty() {
+            return "无".to_string();
+        }
+        self.steps
+            .iter()
+            .filter_map(|r| serde_json::to_value(r.step).ok().and_then(|v| v.as_str().map(|s| s.to_string())))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn done(&self, step: Step) -> bool {
+        self.steps.iter().any(|r| r.step == step)
+    }
+
+    // 先写临时文件再 rename，中途退出不会留下半个状态文件
+    fn save(&self) -> anyhow::Result<()> {
+        let tmp = self.path.with_extension("state.tmp");
+        std::fs::create_dir_all(self.path.parent().unwrap_or(std::path::Path::new(".")))
+            .and_then(|_| std::fs::write(&tmp, serde_json::to_string_pretty(self)?))
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| anyhow::anyhow!(format!("写入切换状态文件 {} 失败: {}", self.path.display(), e)))
+    }
+
+    fn mark(&mut self, step: Step) -> anyhow::Result<()> {
+        if !self.done(step) {
+            self.steps.push(StepRecord { step, time: report::now_str() });
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn clear(&self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("删除切换状态文件 {} 失败: {}", self.path.display(), e);
+        }
+    }
+
+    // 以原表名为源表的参数（resume-cutover 读取 _bak 时 opt 的源表为 _bak 表）
+    pub fn original(&self, opt: &Opt) -> Opt {
+        let mut o = opt.clone();
+        o.src_table = self.src_table.clone();
+        o
+    }
+
+    // resume-cutover 补差阶段的运行参数：源表已改名时从 _bak 表读取
+    pub fn run_opt(&self, opt: &Opt) -> Opt {
+        let mut o = opt.clone();
+        if self.done(Step::SrcRenamed) {
+            o.src_table = self.bak_table.clone();
+        }
+        o
+    }
+
+    // 8.1 源表改名为 _bak；源表已不存在而 _bak 存在时视为已完成
+    pub async fn rename_src(&mut self, opt: &Opt) -> anyhow::Result<()> {
+        if self.done(Step::SrcRenamed) {
+            return Ok(());
+        }
+        if !table_exists(&opt.src_dsn, &opt.src_db, &opt.src_table).await? && table_exists(&opt.src_dsn, &opt.src_db, &self.bak_table).await? {
+            info!("{} 已改名为 {}，跳过", opt.src_table, self.bak_table);
+        } else if let Err(e) = cutover::rename_src_to_bak(opt, &self.bak_table).await {
+            error!("重命名源表失败: {e}");
+            return Err(anyhow::anyhow!(format!("重命名源表失败: {e}")));
+        }
+        self.mark(Step::SrcRenamed)
+    }
+
+    // 8.2 _bak 最大时间戳，记录后重试沿用同一值（空字符串表示 _bak 无数据）
+    pub async fn bak_max_time(&mut self, opt: &Opt, filter: &str) -> anyhow::Result<String> {
+        if let Some(t) = &self.bak_max_time {
+            return Ok(t.clone());
+        }
+        let t = get_max_time_http(&opt.src_dsn, &opt.src_db, &self.bak_table, &opt.time_field, filter, opt.time_zone.as_ref()).await?;
+        self.bak_max_time = Some(t.clone());
+        self.mark(Step::BakMaxCaptured)?;
+        Ok(t)
+    }
+
+    pub fn bak_diffed(&mut self) -> anyhow::Result<()> {
+        self.mark(Step::BakDiffed)
+    }
+
+    pub fn bak_filled(&mut self) -> anyhow::Result<()> {
+        self.mark(Step::BakFilled)
+    }
+
+    // 8.5（recreate）：新建切换后的表 → 分区挂载 → 核对行数后删除中间表；每步先检查两端表的状态，重复执行是安全的
+    async fn recreate_dst(&mut self, opt: &Opt) -> anyhow::Result<()> {
+        let (final_db, read_table) = (opt.cutover_db(), opt.read_table());
+        if !self.done(Step::DstCreated) {
+            if table_exists(&opt.dst_dsn, final_db, &opt.src_table).await? {
+                info!("{} 已存在，跳过建表", qualified(final_db, &opt.src_table));
+            } else {
+                cutover::create_final_table(opt).await?;
+            }
+            self.mark(Step::DstCreated)?;
+        }
+        let intermediate = table_exists(&opt.dst_dsn, &opt.dst_db, read_table).await?;
+        if !self.done(Step::DstPartitionsAttached) {
+            if intermediate {
+                cutover::attach_partitions(opt).await?;
+            }
+            self.mark(Step::DstPartitionsAttached)?;
+        }
+        if intermediate {
+            cutover::drop_intermediate(opt).await?;
+        } else {
+            info!("中间表 {} 已删除，跳过", qualified(&opt.dst_db, read_table));
+        }
+        Ok(())
+    }
+
+    // 8.5 ~ 8.7：目标表 rename、切换后校验与 _bak 保留策略、断点续传文件归档
+    pub async fn finish(&mut self, opt: &Opt, report: &Arc<Mutex<RunReport>>, done_segments_file: &str) -> anyhow::Result<()> {
+        let bak_table = self.bak_table.clone();
+        // 8.5 rename 目标表为 src_table（--cutover-into-src-db 时跨库 rename 到源库）；新表已存在而目标表不存在时视为已完成
+        status::set_phase("cutover");
+        if !self.done(Step::DstRenamed) && opt.cutover_strategy == "recreate" {
+            if let Err(e) = self.recreate_dst(opt).await {
+                error!("按新表名重建目标表失败: {e}");
+                return Err(anyhow::anyhow!(format!("按新表名重建目标表失败: {e}")));
+            }
+            self.mark(Step::DstRenamed)?;
+        }
+        if !self.done(Step::DstRenamed) {
+            if table_exists(&opt.dst_dsn, opt.cutover_db(), &opt.src_table).await?
+                && !table_exists(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?
+            {
+                info!("{} 已改名为 {}，跳过", opt.read_table(), qualified(opt.cutover_db(), &opt.src_table));
+            } else if let Err(e) = cutover::rename_dst_to_src(opt).await {
+                error!("重命名目标表失败: {e}");
+                return Err(anyhow::anyhow!(format!("重命名目标表失败: {e}")));
+            }
+            self.mark(Step::DstRenamed)?;
+        }
+        // 8.6 切换后校验、冒烟查询与 _bak 表保留策略
+        if !self.done(Step::Verified) {
+            let verified = match cutover::verify_after_cutover(opt, &bak_table, report).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("切换后校验失败: {e}");
+                    false
+                }
+            };
+            let verified = cutover::smoke_check(opt, &bak_table, report).await && verified;
+            if !verified && opt.auto_rollback {
+                cutover::rollback(opt, &bak_table).await.map_err(|e| anyhow::anyhow!(format!("自动回滚失败，需人工处理: {e}")))?;
+                self.clear();
+                report.lock().unwrap().cutover = "rolled-back".to_string();
+                events::cutover_done(&qualified(opt.cutover_db(), &opt.src_table), &bak_table, "rolled-back");
+                return Ok(());
+            }
+            cutover::apply_bak_retention(opt, &bak_table, verified, report).await?;
+            self.mark(Step::Verified)?;
+        }
+        // 8.7 done_segments 文件重命名
+        if !self.done(Step::DoneFileArchived) {
+            if std::path::Path::new(done_segments_file).exists() {
+                let ts = chrono::Local::now().format("%Y%m%d_%H%M%S");
+                let new_name = format!("{}_{}.txt", done_segments_file.trim_end_matches(".txt"), ts);
+                std::fs::rename(done_segments_file, &new_name)?;
+                info!("断点续传文件已归档为 {}", new_name);
+            }
+            self.mark(Step::DoneFileArchived)?;
+        }
+        self.clear();
+        report.lock().unwrap().cutover = "performed".to_string();
+        events::cutover_done(&qualified(opt.cutover_db(), &opt.src_table), &bak_table, "performed");
+        info!("最终切换完成，迁移流程结束");
+        if !opt.post_cutover_watch.is_zero() {
+            post_cutover_watch::run(opt, &bak_table, report).await;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+// ===================== DDL 复制与物化视图暂停 =====================
+// datacp ddl：读取源库对象的 SHOW CREATE，改写库名/集群子句（及 --create-* 指定的表级设置与 TTL），按依赖拓扑排序后在目标库执行，
+// 每个对象的原语句与执行的语句写入报告；
+// --pause-mvs：迁移期间 DETACH 由目标表触发的物化视图，批量写入完成后再 ATTACH
+
+use log::{error, info, warn};
+use std::collections::HashSet;
+
+use crate::create_rewrite::{self, Rules};
+use crate::report::{self, DdlObject, DdlReport};
+use crate::{ch_error_code, ch_execute, ch_query_rows, cluster, events, shard, Opt};
+
+// 源库中一个待复制的对象
+struct DbObject {
+    name: String,
+    kind: &'static str, // tables / mvs / dictionaries
+    ddl: String,
+}
+
+async fn list_objects(opt: &Opt, kinds: &HashSet<String>) -> anyhow::Result<Vec<DbObject>> {
+    let sql = format!(
+        "SELECT name, engine FROM system.tables WHERE database = '{}' AND NOT is_temporary AND name NOT LIKE '.inner%' ORDER BY name FORMAT JSONEachRow",
+        opt.src_db
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let mut objects = Vec::new();
+    for r in rows {
+        let name = r.get("name").and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let engine = r.get("engine").and_then(|v| v.as_str()).unwrap_or("");
+        let kind = match engine {
+            "MaterializedView" => "mvs",
+            "Dictionary" => "dictionaries",
+            "View" | "LiveView" | "WindowView" => continue,
+            _ => "tables",
+        };
+        if !kinds.contains(kind) {
+            continue;
+        }
+        let show = if kind == "dictionaries" { "SHOW CREATE DICTIONARY" } else { "SHOW CREATE TABLE" };
+        let sql = format!("{} {}.{} FORMAT JSONEachRow", show, opt.src_db, name);
+        let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+        let ddl = rows.first().and_then(|r| r.get("statement")).and_then(|v| v.as_str()).unwrap_or("").to_string();
+        if ddl.is_empty() {
+            warn!("无法获取 {}.{} 的建表语句，跳过", opt.src_db, name);
+            continue;
+        }
+        objects.push(DbObject { name, kind, ddl });
+    }
+    Ok(objects)
+}
+
+// 改写库名与集群子句：源库名替换为目标库名，设置 --cluster-name 时追加 ON CLUSTER
+fn rewrite_ddl(ddl: &str, src_db: &str, dst_db: &str, cluster: &str) -> String {
+    let mut out = ddl.to_string();
+    if src_db != dst_db {
+        for (from, to) in [
+            (format!("`{}`.", src_db), format!("`{}`.", dst_db)),
+            (format!("'{}'", src_db), format!("'{}'", dst_db)),
+        ] {
+            out = out.replace(&from, &to);
+        }
+        let re = regex::Regex::new(&format!(r"\b{}\.", regex::escape(src_db))).unwrap();
+        out = re.replace_all(&out, format!("{}.", dst_db).as_str()).to_string();
+    }
+    let re = regex::Regex::new(r"\s+ON CLUSTER\s+\S+").unwrap();
+    out = re.replace_all(&out, "").to_string();
+    if !cluster.is_empty() {
+        let re = regex::Regex::new(r"^(CREATE\s+(?:TABLE|MATERIALIZED VIEW|DICTIONARY|VIEW)\s+\S+)").unwrap();
+        out = re.replace(&out, format!("$1 ON CLUSTER {}", cluster).as_str()).to_string();
+    }
+    out
+}
+
+// 按依赖拓扑排序：对象 DDL 中引用到的其他对象先创建（MV 在其 TO 表与源表之后）；存在环时保持原顺序
+fn topo_order(objects: Vec<DbObject>, db: &str) -> Vec<DbObject> {
+    let names: Vec<String> = objects.iter().map(|o| o.name.clone()).collect();
+    let deps: Vec<HashSet<usize>> = objects
+        .iter()
+        .enumerate()
+        .map(|(i, o)| {
+            names
+                .iter()
+                .enumerate()
+                .filter(|(j, n)| {
+                    *j != i && regex::Regex::new(&format!(r"(?:\b{}\.|`{}`\.)`?{}`?\b", regex::escape(db), regex::escape(db), regex::escape(n)))
+                        .unwrap()
+                        .is_match(&o.ddl)
+                })
+                .map(|(j, _)| j)
+                .collect()
+        })
+        .collect();
+    let mut order = Vec::new();
+    let mut placed = vec![false; objects.len()];
+    while order.len() < objects.len() {
+        let ready: Vec<usize> = (0..objects.len()).filter(|&i| !placed[i] && deps[i].iter().all(|&d| placed[d])).collect();
+        // 循环依赖：按原顺序取下一个未放置的对象
+        let next = if ready.is_empty() {
+            let i = (0..objects.len()).find(|&i| !placed[i]).unwrap();
+            warn!("{} 存在循环依赖，按原顺序创建", names[i]);
+            vec![i]
+        } else {
+            ready
+        };
+        for i in next {
+            placed[i] = true;
+            order.push(i);
+        }
+    }
+    let mut slots: Vec<Option<DbObject>> = objects.into_iter().map(Some).collect();
+    order.into_iter().map(|i| slots[i].take().unwrap()).collect()
+}
+
+// datacp ddl --objects tables,mvs,dictionaries [--dry-run] [--create-rewrite k=v] [--create-strip ttl|k] [--create-set k=v]
+pub async fn copy_ddl(opt: &Opt, objects: &str, dry_run: bool, rules: &Rules) -> anyhow::Result<()> {
+    let kinds: HashSet<String> = objects.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+    for k in &kinds {
+        if !["tables", "mvs", "dictionaries"].contains(&k.as_str()) {
+            anyhow::bail!(format!("不支持的 --objects: {}（tables,mvs,dictionaries）", k));
+        }
+    }
+    rules.validate(&opt.dst_dsn, &opt.dst_db).await?;
+    let mut report = DdlReport {
+        src_db: opt.src_db.clone(),
+        dst_db: opt.dst_db.clone(),
+        started_at: report::now_str(),
+        dry_run,
+        rules: rules.describe(),
+        ..Default::default()
+    };
+    let list = topo_order(list_objects(opt, &kinds).await?, &opt.src_db);
+    info!("待复制对象 {} 个: {}", list.len(), list.iter().map(|o| format!("{}({})", o.name, o.kind)).collect::<Vec<_>>().join(", "));
+    // 集群在目标端不可用时按单机形式建对象
+    let fallback = if opt.cluster_name.is_empty() { None } else { cluster::fallback(&opt.dst_dsn, &opt.dst_db, &opt.cluster_name).await? };
+    let cluster_name = if fallback.is_some() { "" } else { opt.cluster_name.as_str() };
+    for o in &list {
+        let ddl = rewrite_ddl(&o.ddl, &opt.src_db, &opt.dst_db, cluster_name);
+        let (ddl, changes) = if o.kind == "tables" { create_rewrite::apply(&ddl, rules) } else { (ddl, Vec::new()) };
+        if !changes.is_empty() {
+            info!("{} 的表级设置/TTL 改写: {}", o.name, changes.join("；"));
+            info!("{} 原建表语句:\n{}", o.name, o.ddl);
+            info!("{} 改写后的建表语句:\n{}", o.name, ddl);
+        }
+        let status = if dry_run {
+            events::say(&format!("-- {} ({})\n{};\n", o.name, o.kind, ddl));
+            "dry_run"
+        } else {
+            if !opt.cluster_name.is_empty() {
+                cluster::record(&opt.dst_dsn, &opt.cluster_name, &ddl, fallback.as_deref());
+            }
+            match ch_execute(&opt.dst_dsn, &opt.dst_db, &ddl).await {
+                Ok(()) => {
+                    info!("已创建 {}.{}", opt.dst_db, o.name);
+                    "created"
+                }
+                // TABLE_ALREADY_EXISTS / DICTIONARY_ALREADY_EXISTS
+                Err(e) if matches!(ch_error_code(&e.to_string()), Some(57) | Some(446)) => {
+                    warn!("{}.{} 已存在，跳过", opt.dst_db, o.name);
+                    "exists"
+                }
+                Err(e) => {
+                    error!("创建 {}.{} 失败: {e}", opt.dst_db, o.name);
+                    report.failed += 1;
+                    "failed"
+                }
+            }
+        };
+        report.objects.push(DdlObject {
+            name: o.name.clone(),
+            kind: o.kind.to_string(),
+            original: o.ddl.clone(),
+            rewritten: ddl,
+            changes,
+            status: status.to_string(),
+        });
+    }
+    report.finished_at = report::now_str();
+    report.write(&opt.report_file)?;
+    info!("DDL 报告已写入 {}", opt.report_file);
+    if report.failed > 0 {
+        anyhow::bail!(format!("{} 个对象创建失败", report.failed));
+    }
+    Ok(())
+}
+
+// 已暂停物化视图的记录文件，进程异常退出后下次运行据此恢复
+fn paused_file(done_segments_file: &str) -> String {
+    format!("{}.paused_mvs", done_segments_file)
+}
+
+// 物化视图 DETACH/ATTACH：分布式目标表追加 ON CLUSTER（集群在目标端不可用时回退为单机形式）
+async fn mv_ddl(opt: &Opt, sql: &str) -> anyhow::Result<()> {
+    let mut sql = sql.to_string();
+    if opt.is_dst_distributed && !opt.cluster_name.is_empty() {
+        let fallback = cluster::fallback(&opt.dst_dsn, &opt.dst_db, &opt.cluster_name).await?;
+        if fallback.is_none() {
+            sql.push_str(&format!(" ON CLUSTER {}", opt.cluster_name));
+        }
+        cluster::record(&opt.dst_dsn, &opt.cluster_name, &sql, fallback.as_deref());
+    }
+    ch_execute(&opt.dst_dsn, &opt.dst_db, &sql).await
+}
+
+// DETACH 由目标表（分布式表取本地表）写入触发的物化视图
+pub async fn pause_mvs(opt: &Opt, done_segments_file: &str) -> anyhow::Result<()> {
+    let (db, table) = shard::resolve_local_table(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+    let mut sources = vec![format!("'{}'", table)];
+    if opt.dst_table != table {
+        sources.push(format!("'{}'", opt.dst_table));
+    }
+    let sql = format!(
+        "SELECT DISTINCT concat(dependencies_database[i], '.', dependencies_table[i]) AS mv \
+         FROM system.tables ARRAY JOIN arrayEnumerate(dependencies_table) AS i \
+         WHERE database = '{}' AND name IN ({}) FORMAT JSONEachRow",
+        db, sources.join(",")
+    );
+    let rows = ch_query_rows(&opt.dst_dsn, &opt.dst_db, &sql).await?;
+    let mut paused: Vec<String> = std::fs::read_to_string(paused_file(done_segments_file))
+        .map(|t| t.lines().map(|l| l.to_string()).collect())
+        .unwrap_or_default();
+    for mv in rows.iter().filter_map(|r| r.get("mv").and_then(|v| v.as_str())) {
+        mv_ddl(opt, &format!("DETACH TABLE {}", mv)).await?;
+        info!("已暂停物化视图 {}", mv);
+        if !paused.iter().any(|p| p == mv) {
+            paused.push(mv.to_string());
+        }
+        std::fs::write(paused_file(done_segments_file), paused.join("\n"))?;
+    }
+    Ok(())
+}
+
+// ATTACH 之前暂停的物化视图，无记录文件时什么也不做
+pub async fn resume_mvs(opt: &Opt, done_segments_file: &str) -> anyhow::Result<()> {
+    let file = paused_file(done_segments_file);
+    let paused: Vec<String> = match std::fs::read_to_string(&file) {
+        Ok(t) => t.lines().filter(|l| !l.trim().is_empty()).map(|l| l.to_string()).collect(),
+        Err(_) => return Ok(()),
+    };
+    let mut remaining = Vec::new();
+    for mv in paused {
+        match mv_ddl(opt, &format!("ATTACH TABLE {}", mv)).await {
+            Ok(()) => info!("已恢复物化视图 {}", mv),
+            // TABLE_ALREADY_EXISTS：已被手动恢复
+            Err(e) if ch_error_code(&e.to_string()) == Some(57) => info!("物化视图 {} 已处于挂载状态", mv),
+            Err(e) => {
+                error!("恢复物化视图 {} 失败: {e}", mv);
+                remaining.push(mv);
+            }
+        }
+    }
+    if remaining.is_empty() {
+        std::fs::remove_file(&file)?;
+        Ok(())
+    } else {
+        std::fs::write(&file, remaining.join("\n"))?;
+        anyhow::bail!(format!("{} 个物化视图未能恢复，见 {}", remaining.len(), file))
+    }
+}
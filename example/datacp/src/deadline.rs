@@ -0,0 +1,93 @@
+// ===================== 运行时间预算 =====================
+// --max-duration 从进程启动起计时（限速与目标端只读等待同样计入，--pause-clock-during-blackout 时扣除维护窗口内的暂停），在分段边界与各阶段切换前检查：
+// 超时后 worker 不再领取新分段，已完成分段照常写入断点续传文件，跳过切换，以单独的退出码结束，
+// 便于调度在下一个维护窗口重新运行。rename 开始后不再检查，避免源表停留在 _bak。
+// --tui 按 q 退出时同样按截止处理（stop），当前分段完成后结束；本次运行要求停止时（如运行中时钟偏差超限）用 Deadline::stop，
+// 只影响该次运行；
+// 本次运行的分段熔断（work_queue）后同样在各阶段切换前停止，报告记为 circuit_broken 而非 deadline_hit
+
+use log::warn;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::report::RunReport;
+use crate::work_queue::CircuitBreaker;
+use crate::{blackout, Opt};
+
+// 人工要求尽快结束（--tui 按 q）
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+pub fn stop() {
+    if !STOPPED.swap(true, Ordering::SeqCst) {
+        warn!("收到退出请求：当前分段完成后停止，断点已保存，不执行切换");
+    }
+}
+
+// 启动时根据 --max-duration 计算截止时间，0 表示不限
+pub fn start(opt: &mut Opt) {
+    if !opt.max_duration.is_zero() {
+        opt.deadline = Some(Instant::now() + opt.max_duration);
+    }
+}
+
+// 截止时间已过（多表迁移在启动下一张表前检查）
+pub fn passed(opt: &Opt) -> bool {
+    STOPPED.load(Ordering::SeqCst) || opt.deadline.map(|d| Instant::now() >= d + blackout::excluded()).unwrap_or(false)
+}
+
+pub struct Deadline {
+    at: Option<Instant>,
+    lifted: AtomicBool,             // 切换开始后不再生效
+    breaker: Arc<CircuitBreaker>,   // 本次运行的熔断器
+    stopped: Mutex<Option<String>>, // 本次运行被要求停止的原因
+}
+
+impl Deadline {
+    pub fn new(opt: &Opt, breaker: Arc<CircuitBreaker>) -> Self {
+        Deadline { at: opt.deadline, lifted: AtomicBool::new(false), breaker, stopped: Mutex::new(None) }
+    }
+
+    // 本次运行尽快结束：当前分段完成后停止，不执行切换
+    pub fn stop(&self, why: &str) {
+        let mut stopped = self.stopped.lock().unwrap();
+        if stopped.is_none() {
+            warn!("{}：当前分段完成后停止，断点已保存，不执行切换", why);
+            *stopped = Some(why.to_string());
+        }
+    }
+
+    pub fn reached(&self) -> bool {
+        !self.lifted.load(Ordering::SeqCst)
+            && (STOPPED.load(Ordering::SeqCst)
+                || self.stopped.lock().unwrap().is_some()
+                || self.at.map(|d| Instant::now() >= d + blackout::excluded()).unwrap_or(false))
+    }
+
+    // 阶段切换前检查，超时或熔断则记入报告（只记首次命中的阶段）
+    pub fn hit(&self, report: &Arc<Mutex<RunReport>>, phase: &str) -> bool {
+        if !self.lifted.load(Ordering::SeqCst) && self.breaker.tripped() {
+            let mut r = report.lock().unwrap();
+            if r.circuit_broken.is_none() {
+                warn!("已熔断，在 {} 阶段前停止，断点已保存，不执行切换", phase);
+                r.circuit_broken = self.breaker.circuit_break().map(|c| c.at(phase));
+            }
+            return true;
+        }
+        if !self.reached() {
+            return false;
+        }
+        let mut r = report.lock().unwrap();
+        if r.deadline_hit.is_none() {
+            let stopped = self.stopped.lock().unwrap().clone();
+            let why = if STOPPED.load(Ordering::SeqCst) { "收到退出请求".to_string() } else { stopped.unwrap_or_else(|| "已超过 --max-duration".to_string()) };
+            warn!("{}，在 {} 阶段前停止，断点已保存，不执行切换", why, phase);
+            r.deadline_hit = Some(phase.to_string());
+        }
+        true
+    }
+
+    pub fn lift(&self) {
+        self.lifted.store(true, Ordering::SeqCst);
+    }
+}
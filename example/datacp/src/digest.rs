@@ -0,0 +1,112 @@
+// ===================== 行摘要 =====================
+// datacp 比对源端与目标端时，每行按以下规则计算 SHA-256 摘要，外部程序可预先计算后与之对比：
+//   1. 只取 columns 中的列，按列名字节序排序（与传入顺序无关）；
+//   2. 行中缺少的列按 JSON null 处理，值本身不做任何转换（ClickHouse JSONEachRow 原样输出，
+//      如被引号包裹的 UInt64、hex() 读取的二进制列均保持字符串）；
+//   3. 组成 {"列名":值,...} 的 JSON 对象，以 serde_json 紧凑格式序列化（无空白，字符串按 serde_json 规则转义，
+//      浮点数按 serde_json 的最短表示）；
+//   4. 对序列化后的 UTF-8 字节计算 SHA-256。
+// 规则有任何变化都会改变摘要，必须同时递增 DIGEST_VERSION
+
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+// 摘要算法版本，规则变化时递增
+pub const DIGEST_VERSION: u32 = 1;
+
+// 可按列名取值的行，datacp 内部使用 HashMap，外部可直接传 serde_json::Map
+pub trait RowValues {
+    fn value(&self, column: &str) -> Option<&Value>;
+}
+
+impl RowValues for Map<String, Value> {
+    fn value(&self, column: &str) -> Option<&Value> {
+        self.get(column)
+    }
+}
+
+impl RowValues for HashMap<String, Value> {
+    fn value(&self, column: &str) -> Option<&Value> {
+        self.get(column)
+    }
+}
+
+/// 按 DIGEST_VERSION 1 的规则计算一行的 SHA-256 摘要：columns 按列名排序，缺失列为 null，
+/// {"列名":值,...} 以 serde_json 紧凑格式序列化后取 SHA-256
+pub fn row_digest<R: RowValues + ?Sized>(row: &R, columns: &[String]) -> [u8; 32] {
+    let mut cols: Vec<&String> = columns.iter().collect();
+    cols.sort();
+    let mut buf = Vec::with_capacity(64 * cols.len());
+    buf.push(b'{');
+    for (i, c) in cols.iter().enumerate() {
+        if i > 0 {
+            buf.push(b',');
+        }
+        serde_json::to_writer(&mut buf, c).unwrap();
+        buf.push(b':');
+        serde_json::to_writer(&mut buf, row.value(c).unwrap_or(&Value::Null)).unwrap();
+    }
+    buf.push(b'}');
+    Sha256::digest(&buf).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn hex(d: [u8; 32]) -> String {
+        d.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn cols(c: &[&str]) -> Vec<String> {
+        c.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn map(v: Value) -> Map<String, Value> {
+        v.as_object().unwrap().clone()
+    }
+
+    // 与比对逻辑原有实现（按排序列插入 Map 后 serde_json::to_vec）一致
+    #[test]
+    fn matches_map_serialization() {
+        let row = map(json!({"b": "x\"y", "a": 1, "c": null, "d": [1, 2], "extra": true}));
+        let c = cols(&["d", "a", "b", "c", "missing"]);
+        let mut norm = Map::new();
+        let mut sorted = c.clone();
+        sorted.sort();
+        for k in &sorted {
+            norm.insert(k.clone(), row.get(k).cloned().unwrap_or(Value::Null));
+        }
+        let expected: [u8; 32] = Sha256::digest(serde_json::to_vec(&norm).unwrap()).into();
+        assert_eq!(row_digest(&row, &c), expected);
+    }
+
+    #[test]
+    fn column_order_and_row_type_do_not_matter() {
+        let row = map(json!({"id": "18446744073709551615", "ts": "2024-01-01 00:00:00"}));
+        let hm: HashMap<String, Value> = row.clone().into_iter().collect();
+        assert_eq!(row_digest(&row, &cols(&["id", "ts"])), row_digest(&row, &cols(&["ts", "id"])));
+        assert_eq!(row_digest(&row, &cols(&["id", "ts"])), row_digest(&hm, &cols(&["id", "ts"])));
+    }
+
+    // 固定摘要值：算法有意变化时同时更新这里与 DIGEST_VERSION
+    #[test]
+    fn golden_values() {
+        assert_eq!(DIGEST_VERSION, 1);
+        let cases = [
+            (json!({}), cols(&[]), "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"),
+            (json!({"a": 1}), cols(&["a"]), "015abd7f5cc57a2dd94b7590f04ad8084273905ee33ec5cebeae62276a97f862"),
+            (json!({"a": null}), cols(&["a", "b"]), "052c4bd5e6ded53bd884485af8b1667a7b70ba3a8573b54bd878f6d2c705c2df"),
+            (
+                json!({"id": "18446744073709551615", "name": "数据\n\"迁移\"", "price": 1.5, "ts": "2024-01-01 00:00:00", "uid": "00FF10AB"}),
+                cols(&["ts", "uid", "id", "price", "name"]),
+                "b8ddb595fd16752630904912fa56b449a4de8f63483cd2b1bd18603619e8e273",
+            ),
+        ];
+        for (row, c, want) in cases {
+            assert_eq!(hex(row_digest(&map(row), &c)), want);
+        }
+    }
+}
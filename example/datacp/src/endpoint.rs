@@ -0,0 +1,306 @@
+// ===================== 连接端点配置（--config 的 [source] / [destination]） =====================
+// 源端与目标端各自的连接设置：地址、账号、密码所在的环境变量、CA 证书、客户端证书（mTLS）、代理与附加 settings。
+// --config 文件中的 [source] / [destination] 块与命令行 --src-dsn / --dst-dsn 映射到同一结构 Endpoint：
+// 账号、密码与 settings 合成 DSN 写回 --src-dsn / --dst-dsn（之后的请求仍按 DSN 寻址），证书与代理登记到全局表，
+// 构造 HTTP 客户端时按 DSN 找到所属一端（endpoint::builder）；每个登记的端点有一个共享连接池（Clients），
+// datacp serve 的任务指定同一端点时复用。
+// 配置文件中的块优先于对应的命令行 DSN；校验错误指明块与字段，如 [source].client_key
+//
+// 示例：
+//   [source]
+//   url = "https://ch-old.internal:8443"
+//   user = "reader"
+//   password_env = "SRC_CH_PASSWORD"
+//   ca_cert = "/etc/datacp/old-ca.pem"
+//   settings = { max_execution_time = 600 }
+//
+//   [destination]
+//   url = "https://ch-new.internal:8443"
+//   user = "writer"
+//   password_env = "DST_CH_PASSWORD"
+//   client_cert = "/etc/datacp/writer.crt"
+//   client_key = "/etc/datacp/writer.key"
+//   proxy = "http://egress-proxy:3128"
+
+use log::info;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{clickhouse_base_url, dsn_with_settings, Opt};
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Endpoint {
+    pub url: String, // http(s)://host[:port][/path]；未指定 user/password_env 时可带账号，即命令行 DSN 的写法
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub password_env: Option<String>, // 密码所在的环境变量，不在配置文件中写明文
+    #[serde(default)]
+    pub ca_cert: Option<String>, // 额外信任的 CA 证书（PEM）
+    #[serde(default)]
+    pub client_cert: Option<String>, // 客户端证书（PEM），须与 client_key 同时指定
+    #[serde(default)]
+    pub client_key: Option<String>, // 客户端私钥（PEM）
+    #[serde(default)]
+    pub proxy: Option<String>, // 该端所有请求经由的代理
+    #[serde(default)]
+    pub settings: BTreeMap<String, toml::Value>, // 附加到该端每个请求的 ClickHouse settings
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    source: Option<Endpoint>,
+    #[serde(default)]
+    destination: Option<Endpoint>,
+}
+
+// 校验后的一端：合成的 DSN 与构造客户端所需的证书、代理
+#[derive(Clone, Default)]
+struct Resolved {
+    base: String, // clickhouse_base_url 的地址部分（不含 settings）
+    user: String,
+    pass: String,
+    password_env: Option<String>, // 密码来自的环境变量（运行清单只记录变量名）
+    ca: Option<reqwest::Certificate>,
+    identity: Option<reqwest::Identity>,
+    proxy: Option<reqwest::Proxy>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Side {
+    Src,
+    Dst,
+}
+
+// 迁移主流程共享连接池的设置
+const POOL_TIMEOUT: Duration = Duration::from_secs(30);
+const POOL_MAX_IDLE_PER_HOST: usize = 16;
+
+// 已登记的一端与其共享连接池；同一地址与账号只登记一次，datacp serve 的各任务复用同一连接池
+struct Registered {
+    side: Side,
+    r: Resolved,
+    pool: reqwest::Client,
+}
+
+static ENDPOINTS: Mutex<Vec<Arc<Registered>>> = Mutex::new(Vec::new());
+
+// 错误信息中的位置：配置文件为 [块].字段，命令行 DSN 为参数名
+fn field(block: &str, name: &str) -> String {
+    if block.starts_with("--") {
+        block.to_string()
+    } else {
+        format!("{}.{}", block, name)
+    }
+}
+
+fn read_pem(block: &str, field: &str, path: &str) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| anyhow::anyhow!(format!("{}.{}: 读取 {} 失败: {}", block, field, path, e)))
+}
+
+fn setting_value(block: &str, key: &str, v: &toml::Value) -> anyhow::Result<String> {
+    match v {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Boolean(b) => Ok(if *b { "1" } else { "0" }.to_string()),
+        _ => anyhow::bail!(format!("{}.settings.{}: 只支持字符串、数字或布尔值", block, key)),
+    }
+}
+
+impl Endpoint {
+    // 命令行 --src-dsn / --dst-dsn 对应的端点（只有地址与账号）
+    pub fn from_dsn(dsn: &str) -> Self {
+        Endpoint { url: dsn.to_string(), ..Default::default() }
+    }
+
+    // 校验并合成 DSN；block 为错误信息中的位置，如 [source] 或 --src-dsn
+    fn resolve(&self, block: &str) -> anyhow::Result<(String, Resolved)> {
+        let url = self.url.trim();
+        if url.is_empty() {
+            anyhow::bail!(format!("{}: 不能为空", field(block, "url")));
+        }
+        let has_account = url.contains('@');
+        let dsn = if has_account {
+            if self.user.is_some() || self.password_env.is_some() {
+                anyhow::bail!(format!("{}.url: 已带账号，不能再指定 user / password_env", block));
+            }
+            url.to_string()
+        } else {
+            let re = regex::Regex::new(r"^(https?)://([^/?]+)(/[^?]*)?$").unwrap();
+            let caps = re
+                .captures(url)
+                .ok_or_else(|| anyhow::anyhow!(format!("{}: 格式应为 http(s)://host[:port][/path]: {}", field(block, "url"), url)))?;
+            let user = self.user.clone().unwrap_or_else(|| "default".to_string());
+            if user.is_empty() || user.contains([':', '@', '/']) {
+                anyhow::bail!(format!("{}.user: 不能为空或包含 : @ /: {}", block, user));
+            }
+            let pass = match &self.password_env {
+                Some(var) => std::env::var(var).map_err(|_| anyhow::anyhow!(format!("{}.password_env: 环境变量 {} 未设置", block, var)))?,
+                None => String::new(),
+            };
+            if pass.contains('@') {
+                anyhow::bail!(format!("{}.password_env: 密码包含 @，无法写入 DSN", block));
+            }
+            format!("{}://{}:{}@{}{}", &caps[1], user, pass, &caps[2], caps.get(3).map(|m| m.as_str()).unwrap_or(""))
+        };
+        let (base, user, pass) = clickhouse_base_url(&dsn).map_err(|e| anyhow::anyhow!(format!("{}: {}", field(block, "url"), e)))?;
+        let mut settings = Vec::new();
+        for (k, v) in &self.settings {
+            settings.push((k.clone(), setting_value(block, k, v)?));
+        }
+        let dsn = dsn_with_settings(&dsn, &settings);
+        let https = base.starts_with("https://");
+        let ca = match &self.ca_cert {
+            Some(_) if !https => anyhow::bail!(format!("{}.ca_cert: url 不是 https，证书不会生效", block)),
+            Some(path) => Some(
+                reqwest::Certificate::from_pem(&read_pem(block, "ca_cert", path)?)
+                    .map_err(|e| anyhow::anyhow!(format!("{}.ca_cert: {} 不是有效的 PEM 证书: {}", block, path, e)))?,
+            ),
+            None => None,
+        };
+        let identity = match (&self.client_cert, &self.client_key) {
+            (Some(_), _) | (_, Some(_)) if !https => anyhow::bail!(format!("{}.client_cert: url 不是 https，客户端证书不会生效", block)),
+            (Some(cert), Some(key)) => {
+                let mut pem = read_pem(block, "client_cert", cert)?;
+                pem.push(b'\n');
+                pem.extend(read_pem(block, "client_key", key)?);
+                Some(
+                    reqwest::Identity::from_pem(&pem)
+                        .map_err(|e| anyhow::anyhow!(format!("{}.client_key: {} 与 {} 不能组成客户端证书: {}", block, cert, key, e)))?,
+                )
+            }
+            (Some(_), None) => anyhow::bail!(format!("{}.client_key: 指定 client_cert 时必须同时指定", block)),
+            (None, Some(_)) => anyhow::bail!(format!("{}.client_cert: 指定 client_key 时必须同时指定", block)),
+            (None, None) => None,
+        };
+        let proxy = match &self.proxy {
+            Some(p) => Some(reqwest::Proxy::all(p).map_err(|e| anyhow::anyhow!(format!("{}.proxy: {}: {}", block, p, e)))?),
+            None => None,
+        };
+        Ok((dsn, Resolved { base, user, pass, password_env: self.password_env.clone(), ca, identity, proxy }))
+    }
+}
+
+// 读取 --config（未指定时两端都来自命令行 DSN），校验后改写 opt.src_dsn / opt.dst_dsn 并登记两端的 TLS 与代理
+pub fn init(opt: &mut Opt) -> anyhow::Result<()> {
+    let file = if opt.config.is_empty() {
+        ConfigFile::default()
+    } else {
+        let text = std::fs::read_to_string(&opt.config).map_err(|e| anyhow::anyhow!(format!("读取 {} 失败: {}", opt.config, e)))?;
+        toml::from_str(&text).map_err(|e| anyhow::anyhow!(format!("解析 {} 失败: {}", opt.config, e)))?
+    };
+    let origin = opt.config.clone();
+    apply_blocks(opt, file.source.as_ref(), file.destination.as_ref(), &origin)
+}
+
+// 以给定的 [source] / [destination] 块（缺少的一端沿用 opt 中的 DSN）设置 opt 的两端连接并登记；
+// origin 为块的来源（配置文件路径或 serve 任务），用于日志
+pub fn apply_blocks(opt: &mut Opt, source: Option<&Endpoint>, destination: Option<&Endpoint>, origin: &str) -> anyhow::Result<()> {
+    let (src_dsn, src) = match source {
+        Some(ep) => ep.resolve("[source]")?,
+        None => Endpoint::from_dsn(&opt.src_dsn).resolve("--src-dsn")?,
+    };
+    let (dst_dsn, dst) = match destination {
+        Some(ep) => ep.resolve("[destination]")?,
+        None => Endpoint::from_dsn(&opt.dst_dsn).resolve("--dst-dsn")?,
+    };
+    for (side, ep, r) in [("源端", source, &src), ("目标端", destination, &dst)] {
+        if ep.is_some() {
+            info!(
+                "{}连接取自 {}: {}（CA 证书: {}，客户端证书: {}，代理: {}）",
+                side,
+                origin,
+                r.base,
+                if r.ca.is_some() { "是" } else { "否" },
+                if r.identity.is_some() { "是" } else { "否" },
+                if r.proxy.is_some() { "是" } else { "否" }
+            );
+        }
+    }
+    register(Side::Src, src)?;
+    register(Side::Dst, dst)?;
+    opt.src_dsn = src_dsn;
+    opt.dst_dsn = dst_dsn;
+    Ok(())
+}
+
+fn register(side: Side, r: Resolved) -> anyhow::Result<()> {
+    let mut all = ENDPOINTS.lock().unwrap();
+    if all.iter().any(|e| e.side == side && e.r.base == r.base && e.r.user == r.user && e.r.pass == r.pass) {
+        return Ok(());
+    }
+    let pool = apply(reqwest::Client::builder(), &r).timeout(POOL_TIMEOUT).pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST).build()?;
+    all.push(Arc::new(Registered { side, r, pool }));
+    Ok(())
+}
+
+// DSN 所属的一端：先按地址与账号精确匹配；主机被改写过的 DSN（分片本地表）按协议、端口与账号匹配，多个符合时优先目标端。
+// 源端首选副本在构造客户端之后才改写主机（src_replica::route），查找时用的仍是原 DSN
+fn lookup(dsn: &str) -> Option<Arc<Registered>> {
+    let (base, user, pass) = clickhouse_base_url(dsn).ok()?;
+    let all = ENDPOINTS.lock().unwrap();
+    if let Some(e) = all.iter().find(|e| e.r.base == base && e.r.user == user) {
+        return Some(e.clone());
+    }
+    let tail = |b: &str| b.split_once("://").map(|(s, rest)| (s.to_string(), rest.split_once(':').map(|(_, p)| p.to_string()))).unwrap_or_default();
+    let same = |e: &&Arc<Registered>| e.r.user == user && e.r.pass == pass && tail(&e.r.base) == tail(&base);
+    all.iter().filter(same).find(|e| e.side == Side::Dst).or_else(|| all.iter().find(same)).cloned()
+}
+
+fn apply(mut b: reqwest::ClientBuilder, r: &Resolved) -> reqwest::ClientBuilder {
+    if let Some(ca) = &r.ca {
+        b = b.add_root_certificate(ca.clone());
+    }
+    if let Some(id) = &r.identity {
+        b = b.identity(id.clone());
+    }
+    if let Some(p) = &r.proxy {
+        b = b.proxy(p.clone());
+    }
+    b
+}
+
+// DSN 的密码所在的环境变量（来自配置块的 password_env）
+pub fn password_env(dsn: &str) -> Option<String> {
+    lookup(dsn).and_then(|e| e.r.password_env.clone())
+}
+
+// 访问 dsn 所用的客户端构造器（带上所属一端的证书与代理）；不属于任一端时为默认设置
+pub fn builder(dsn: &str) -> reqwest::ClientBuilder {
+    match lookup(dsn) {
+        Some(e) => apply(reqwest::Client::builder(), &e.r),
+        None => reqwest::Client::builder(),
+    }
+}
+
+// 迁移主流程使用的连接池：按 DSN 取所属一端登记的共享连接池，不属于任一端时用默认设置的连接池
+pub struct Clients {
+    fallback: reqwest::Client,
+    registered: bool, // 是否按登记的端点选择
+}
+
+impl Clients {
+    pub fn new() -> anyhow::Result<Self> {
+        let fallback = reqwest::Client::builder().timeout(POOL_TIMEOUT).pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST).build()?;
+        Ok(Clients { fallback, registered: true })
+    }
+
+    // 测试等场景：所有请求共用同一个客户端
+    #[cfg(test)]
+    pub fn shared(client: reqwest::Client) -> Self {
+        Clients { fallback: client, registered: false }
+    }
+
+    pub fn for_dsn(&self, dsn: &str) -> reqwest::Client {
+        match lookup(dsn).filter(|_| self.registered) {
+            Some(e) => e.pool.clone(),
+            None => self.fallback.clone(),
+        }
+    }
+}
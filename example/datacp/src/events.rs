@@ -0,0 +1,154 @@
+// ===================== 机器可读事件（--events-stdout） =====================
+// 供编排系统（如 Airflow）实时读取进度，不必解析日志文件：每行一个 JSON 对象写到 stdout，
+// 此模式下人工阅读的输出（参数、汇总、计划等）一律改写到 stderr，日志本来就在 stderr。
+//
+// 格式版本 v=1。所有事件都有 v、event、time（本地时间 YYYY-mm-dd HH:MM:SS），其余字段按事件：
+//   run_started    config_hash（脱敏后生效配置 JSON 的 sha256 前 16 位十六进制）、src、dst
+//   phase_changed  phase（backfill / incremental / standby / bak / cutover / archive / done）
+//   segment_done   segment、rows（本分段写入行数）、duration_ms
+//   segment_failed segment、error
+//   segment_drift  segment、src_rows、dst_rows、previously_verified_at（--background-verify 发现已完成分段两端不一致）
+//   cutover_done   table（切换后的表）、bak_table、outcome（performed / rolled-back）
+//   run_finished   summary{outcome, segments_done, segments_failed, rows_written, duration_seconds, cutover, exit_code}
+// 只增加字段时不升级版本；删除字段或改变字段含义时 v 加一
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::report::{now_str, DriftFinding, RunReport};
+use crate::Opt;
+
+pub const VERSION: u64 = 1;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// 事件输出，未设置时为 stdout；测试中替换为内存缓冲
+static SINK: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+
+// 进程启动时、任何输出之前调用
+pub fn init(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// 人工阅读的输出：--events-stdout 时写到 stderr，保证 stdout 只有事件
+pub fn say(text: &str) {
+    if enabled() {
+        eprintln!("{}", text);
+    } else {
+        println!("{}", text);
+    }
+}
+
+fn emit(event: &str, fields: Value) {
+    if !enabled() {
+        return;
+    }
+    let mut obj = json!({ "v": VERSION, "event": event, "time": now_str() });
+    if let (Some(o), Value::Object(f)) = (obj.as_object_mut(), fields) {
+        o.extend(f);
+    }
+    let line = format!("{}\n", obj);
+    // 整行一次写出并刷新，多个 worker 同时发事件时不会混行
+    let mut sink = SINK.lock().unwrap();
+    match sink.as_mut() {
+        Some(w) => {
+            let _ = w.write_all(line.as_bytes());
+        }
+        None => {
+            let mut out = std::io::stdout().lock();
+            let _ = out.write_all(line.as_bytes());
+            let _ = out.flush();
+        }
+    }
+}
+
+pub fn run_started(opt: &Opt) {
+    let config = crate::status::config_json(opt).to_string();
+    let hash: String = Sha256::digest(config.as_bytes()).iter().take(8).map(|b| format!("{:02x}", b)).collect();
+    emit(
+        "run_started",
+        json!({
+            "config_hash": hash,
+            "src": format!("{}.{}", opt.src_db, opt.src_table),
+            "dst": format!("{}.{}", opt.dst_db, opt.dst_table),
+        }),
+    );
+}
+
+pub fn phase_changed(phase: &str) {
+    emit("phase_changed", json!({ "phase": phase }));
+}
+
+pub fn segment_done(segment: &str, rows: u64, duration_ms: u128) {
+    emit("segment_done", json!({ "segment": segment, "rows": rows, "duration_ms": duration_ms as u64 }));
+}
+
+pub fn segment_failed(segment: &str, error: &str) {
+    emit("segment_failed", json!({ "segment": segment, "error": error }));
+}
+
+pub fn segment_drift(f: &DriftFinding) {
+    emit(
+        "segment_drift",
+        json!({
+            "segment": f.segment,
+            "src_rows": f.src_rows,
+            "dst_rows": f.dst_rows,
+            "previously_verified_at": f.previously_verified_at,
+        }),
+    );
+}
+
+pub fn cutover_done(table: &str, bak_table: &str, outcome: &str) {
+    emit("cutover_done", json!({ "table": table, "bak_table": bak_table, "outcome": outcome }));
+}
+
+// 单表运行结束；多表运行由调用方汇总后调用 run_finished_with
+pub fn run_finished(r: &RunReport, exit_code: i32) {
+    run_finished_with(json!({
+        "outcome": r.outcome,
+        "segments_done": r.segments_done,
+        "segments_failed": r.segments_failed.len(),
+        "rows_written": r.rows_written,
+        "duration_seconds": r.duration_seconds,
+        "cutover": r.cutover,
+        "exit_code": exit_code,
+    }));
+}
+
+pub fn run_finished_with(summary: Value) {
+    emit("run_finished", json!({ "summary": summary }));
+}
+
+#[cfg(test)]
+pub mod capture {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    pub struct Buffer(pub Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, b: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(b);
+            Ok(b.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // 打开事件输出并写入内存缓冲
+    pub fn start() -> Buffer {
+        let buf = Buffer::default();
+        *super::SINK.lock().unwrap() = Some(Box::new(buf.clone()));
+        super::init(true);
+        buf
+    }
+}
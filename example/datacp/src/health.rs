@@ -0,0 +1,266 @@
+// ===================== 端点健康探测 =====================
+// 入口（ingress / 负载均衡）对任何路径都可能返回 200，后端宕机时根路径上的 POST SELECT 1 仍会“通过”，
+// 问题要到分段读写时才以莫名的错误出现。每端按层探测，各层有各自的超时：
+//   1. GET <路径前缀>/ping，期望响应 Ok.：连不上为 unreachable，入口有响应但不是 Ok. 为 backend（入口可达、后端不可用）
+//   2. 按正常查询的地址（路径前缀、?database=、DSN 参数）与认证 POST SELECT 1：认证类错误为 auth，
+//      数据库不存在为 database，其余为 query
+// 启动时两端各探测一次，失败即按所在层报错退出；运行中某端在 BURST_WINDOW 内出现 BURST 次连接失败时在后台重新探测
+// （冷却 COOLDOWN），区分“集群不可用”与“查询本身有问题”。最近一次探测结果由状态接口 /status 的 health 给出
+
+use log::{error, info};
+use serde_json::Value;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::report::{self, EndpointHealth};
+use crate::{ch_error_code, clickhouse_base_url, endpoint, events, parse_clickhouse_dsn, sql_log, Opt};
+
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+// 连接失败突增：窗口内达到次数即重新探测，两次探测之间至少间隔冷却时间
+const BURST: usize = 5;
+const BURST_WINDOW: Duration = Duration::from_secs(60);
+const COOLDOWN: Duration = Duration::from_secs(60);
+
+// 认证与权限类错误码：AUTHENTICATION_FAILED、UNKNOWN_USER、WRONG_PASSWORD、REQUIRED_PASSWORD、ACCESS_DENIED
+const AUTH_CODES: [u32; 5] = [516, 192, 193, 194, 497];
+const UNKNOWN_DATABASE: u32 = 81;
+
+// 已探测的端点：(endpoint, 端, DSN, 库)，每端一条
+static TARGETS: Mutex<Vec<(String, &'static str, String, String)>> = Mutex::new(Vec::new());
+// 各端最近一次探测结果
+static LAST: Mutex<Vec<EndpointHealth>> = Mutex::new(Vec::new());
+// 一个端点最近的连接失败时间与上次重新探测时间
+type Burst = (VecDeque<Instant>, Option<Instant>);
+static ERRORS: Mutex<BTreeMap<String, Burst>> = Mutex::new(BTreeMap::new());
+
+fn describe(layer: &str) -> &'static str {
+    match layer {
+        "unreachable" => "无法连接",
+        "backend" => "入口可达但 ClickHouse 后端不可用",
+        "auth" => "认证失败",
+        "database" => "数据库不存在",
+        _ => "查询失败",
+    }
+}
+
+// SELECT 1 失败所在的层
+fn query_layer(status: u16, body: &str) -> &'static str {
+    let code = ch_error_code(body);
+    if status == 401 || status == 403 || code.is_some_and(|c| AUTH_CODES.contains(&c)) {
+        "auth"
+    } else if code == Some(UNKNOWN_DATABASE) {
+        "database"
+    } else {
+        "query"
+    }
+}
+
+fn short(text: &str) -> String {
+    text.trim().chars().take(200).collect()
+}
+
+// 按层探测一端；失败时 layer 为所在层
+pub async fn probe(side: &str, dsn: &str, db: &str, trigger: &str) -> EndpointHealth {
+    let mut h = EndpointHealth {
+        side: side.to_string(),
+        endpoint: sql_log::endpoint(dsn),
+        healthy: false,
+        layer: None,
+        detail: String::new(),
+        trigger: trigger.to_string(),
+        checked_at: report::now_str(),
+        ping_ms: None,
+        query_ms: None,
+    };
+    let fail = |mut h: EndpointHealth, layer: &str, detail: String| {
+        h.layer = Some(layer.to_string());
+        h.detail = detail;
+        h
+    };
+    let client = match endpoint::builder(dsn).build() {
+        Ok(c) => c,
+        Err(e) => return fail(h, "unreachable", format!("创建 HTTP 客户端失败: {e}")),
+    };
+    let (base, user, pass) = match clickhouse_base_url(dsn) {
+        Ok(b) => b,
+        Err(e) => return fail(h, "unreachable", e.to_string()),
+    };
+    // 1. /ping
+    let started = Instant::now();
+    let ping = async {
+        let resp = client.get(format!("{}/ping", base.trim_end_matches('/'))).timeout(PING_TIMEOUT).send().await?;
+        let status = resp.status();
+        Ok::<_, reqwest::Error>((status, resp.text().await?))
+    };
+    match ping.await {
+        Err(e) if e.is_timeout() => return fail(h, "unreachable", format!("GET /ping 超过 {}s 未响应", PING_TIMEOUT.as_secs())),
+        Err(e) => return fail(h, "unreachable", format!("GET /ping 连接失败: {e}")),
+        Ok((status, body)) if !status.is_success() || body.trim() != "Ok." => {
+            return fail(h, "backend", format!("GET /ping 返回 {} {}（期望 Ok.）", status, short(&body)))
+        }
+        Ok(_) => h.ping_ms = Some(started.elapsed().as_millis() as u64),
+    }
+    // 2. SELECT 1（与正常查询相同的地址与认证）
+    let url = match parse_clickhouse_dsn(dsn, db) {
+        Ok((url, ..)) => url,
+        Err(e) => return fail(h, "query", e.to_string()),
+    };
+    let started = Instant::now();
+    let query = async {
+        let resp = client.post(&url).basic_auth(&user, Some(&pass)).timeout(QUERY_TIMEOUT).body("SELECT 1").send().await?;
+        let status = resp.status();
+        Ok::<_, reqwest::Error>((status, resp.text().await?))
+    };
+    match query.await {
+        Err(e) if e.is_timeout() => fail(h, "query", format!("SELECT 1 超过 {}s 未返回", QUERY_TIMEOUT.as_secs())),
+        Err(e) => fail(h, "query", format!("SELECT 1 连接失败: {e}")),
+        Ok((status, body)) if !status.is_success() => {
+            let layer = query_layer(status.as_u16(), &body);
+            fail(h, layer, format!("SELECT 1（库 {}）返回 {} {}", db, status, short(&body)))
+        }
+        Ok(_) => {
+            h.query_ms = Some(started.elapsed().as_millis() as u64);
+            h.healthy = true;
+            h
+        }
+    }
+}
+
+fn record(h: &EndpointHealth) {
+    let mut last = LAST.lock().unwrap();
+    last.retain(|x| x.side != h.side);
+    last.push(h.clone());
+}
+
+fn summary(h: &EndpointHealth) -> String {
+    match &h.layer {
+        None => format!("{} {} 正常（/ping {}ms，SELECT 1 {}ms）", h.side, h.endpoint, h.ping_ms.unwrap_or(0), h.query_ms.unwrap_or(0)),
+        Some(l) => format!("{} {} {}（{}）: {}", h.side, h.endpoint, describe(l), l, h.detail),
+    }
+}
+
+// 启动检查：两端各探测一次，失败时报告所在层并拒绝运行
+pub async fn check(opt: &Opt) -> anyhow::Result<()> {
+    for (side, dsn, db) in [("源端", &opt.src_dsn, &opt.src_db), ("目标端", &opt.dst_dsn, &opt.dst_db)] {
+        let h = probe(side, dsn, db, "startup").await;
+        events::say(&format!("[health] {}", summary(&h)));
+        record(&h);
+        if !h.healthy {
+            anyhow::bail!(format!("{}", summary(&h)));
+        }
+        let mut targets = TARGETS.lock().unwrap();
+        targets.retain(|t| t.1 != side);
+        targets.push((h.endpoint.clone(), side, dsn.clone(), db.clone()));
+    }
+    Ok(())
+}
+
+// 请求连接失败时调用；某端点短时间内连接失败突增时在后台重新探测
+pub fn connection_error(dsn: &str) {
+    let ep = sql_log::endpoint(dsn);
+    let Some((_, side, dsn, db)) = TARGETS.lock().unwrap().iter().find(|t| t.0 == ep).cloned() else { return };
+    {
+        let now = Instant::now();
+        let mut guard = ERRORS.lock().unwrap();
+        let (recent, last_probe) = guard.entry(ep.clone()).or_default();
+        recent.push_back(now);
+        while recent.front().is_some_and(|t| now.duration_since(*t) > BURST_WINDOW) {
+            recent.pop_front();
+        }
+        if recent.len() < BURST || last_probe.is_some_and(|t| now.duration_since(t) < COOLDOWN) {
+            return;
+        }
+        *last_probe = Some(now);
+        recent.clear();
+    }
+    tokio::spawn(async move {
+        let h = probe(side, &dsn, &db, "connection-errors").await;
+        if h.healthy {
+            info!("{}s 内 {} 次连接失败后重新探测: {}，集群可用，失败更可能来自具体查询", BURST_WINDOW.as_secs(), BURST, summary(&h));
+        } else {
+            error!("{}s 内 {} 次连接失败后重新探测: {}", BURST_WINDOW.as_secs(), BURST, summary(&h));
+        }
+        record(&h);
+    });
+}
+
+// 状态接口：各端最近一次探测结果
+pub fn status_json() -> Value {
+    serde_json::to_value(&*LAST.lock().unwrap()).unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+    use crate::mock_ch;
+
+    fn answer(sql: &str, code: u32, text: &str) -> (u16, String) {
+        match sql {
+            "SELECT 1" => (500, format!("Code: {}. DB::Exception: {}", code, text)),
+            _ => (200, String::new()),
+        }
+    }
+
+    fn auth_failed(sql: &str) -> (u16, String) {
+        answer(sql, 516, "default: Authentication failed: password is incorrect")
+    }
+
+    fn access_denied(sql: &str) -> (u16, String) {
+        answer(sql, 497, "default: Not enough privileges")
+    }
+
+    fn unknown_database(sql: &str) -> (u16, String) {
+        answer(sql, 81, "Database app does not exist")
+    }
+
+    #[tokio::test]
+    async fn probe_reports_the_failing_layer() {
+        let (dsn, _) = mock_ch::serve_pinging("", "<html>502 Bad Gateway</html>", mock_ch::empty).await;
+        assert_eq!(probe("源端", &dsn, "app", "startup").await.layer.as_deref(), Some("backend"));
+        for (respond, layer) in [(auth_failed as fn(&str) -> (u16, String), "auth"), (access_denied, "auth"), (unknown_database, "database")] {
+            let (dsn, _) = mock_ch::serve(respond).await;
+            let h = probe("源端", &dsn, "app", "startup").await;
+            assert!(!h.healthy);
+            assert_eq!(h.layer.as_deref(), Some(layer), "{}", h.detail);
+            assert!(h.ping_ms.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_errors_burst_reprobes_and_targets_follow_the_latest_check() {
+        let (old, _) = mock_ch::serve(mock_ch::empty).await;
+        let (dsn, seen) = mock_ch::serve(mock_ch::empty).await;
+        for dsn in [&old, &dsn] {
+            let args = ["datacp", "--src-dsn", dsn.as_str(), "--dst-dsn", dsn.as_str(), "--src-db", "app", "--dst-db", "app_new"];
+            check(&Opt::from_iter(args)).await.unwrap();
+        }
+        // 再次检查时按端替换，不累积上次的端点
+        let targets: Vec<_> = TARGETS.lock().unwrap().iter().map(|t| (t.1, t.2.clone())).collect();
+        assert_eq!(targets, [("源端", dsn.clone()), ("目标端", dsn.clone())]);
+        let probes = seen.lock().unwrap().len();
+        for _ in 0..BURST - 1 {
+            connection_error(&dsn);
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(seen.lock().unwrap().len(), probes);
+        connection_error(&dsn);
+        for _ in 0..50 {
+            if LAST.lock().unwrap().iter().any(|h| h.trigger == "connection-errors") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let last = LAST.lock().unwrap().clone();
+        assert!(last.iter().any(|h| h.trigger == "connection-errors" && h.healthy), "{:?}", last);
+        assert_eq!(seen.lock().unwrap().len(), probes + 1);
+        // 冷却期内不再重新探测
+        for _ in 0..BURST {
+            connection_error(&dsn);
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(seen.lock().unwrap().len(), probes + 1);
+    }
+}
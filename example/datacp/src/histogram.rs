@@ -0,0 +1,170 @@
+// ===================== 时间字段分布（datacp plan --histogram） =====================
+// 迁移前用一条 GROUP BY toStartOfHour(time_field) 查询统计迁移窗口内每小时的行数，在客户端汇总为 1h / 6h / 1d
+// 三种候选粒度下每个桶的 min / median / p95 / max 行数（无数据的桶按 0 计）。跨度超过 MAX_HOURS 小时时按天抽样
+// （只统计 toUInt32(toDate(time_field)) % k = 0 的日期，整天保留，6h / 1d 桶不被截断）。
+// 按 --target-segment-rows 给出建议：每小时行数远超目标时建议缩小 --segment-size 或 --page-key 分页，
+// 大部分小时为空时建议 --coalesce-empty；单个小时占全表比例过高时告警（自适应拆分需要反复对半拆分该分段）。
+// 结果打印为文本表，并写入 plan.json
+
+use chrono::{Duration, NaiveDateTime, Timelike};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::{ch_query_rows, get_time_range_http, json_u64, qualified, row_filter, time_zone, Opt};
+
+// 不抽样时统计的最大小时数（约 3 年）
+const MAX_HOURS: i64 = 26_280;
+// 单个小时占全表的比例超过该值时告警
+const SKEW_SHARE: f64 = 0.1;
+
+const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BucketStats {
+    pub size: String, // 1h / 6h / 1d
+    pub buckets: usize,
+    pub empty: usize,
+    pub min: u64,
+    pub median: u64,
+    pub p95: u64,
+    pub max: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Histogram {
+    pub min_time: String,
+    pub max_time: String,
+    pub sample_every_days: i64, // 1 为不抽样
+    pub rows: u64,              // 统计到的行数（抽样时为抽样日期内的行数）
+    pub sizes: Vec<BucketStats>,
+    pub busiest_hour: Option<String>,
+    pub busiest_hour_share: f64,
+    pub skew_warning: Option<String>,
+    pub target_segment_rows: u64,
+    pub recommendations: Vec<String>,
+}
+
+fn stats(size: &str, mut counts: Vec<u64>) -> BucketStats {
+    counts.sort_unstable();
+    let at = |q: f64| counts.get(((counts.len() as f64 * q).ceil() as usize).saturating_sub(1)).copied().unwrap_or(0);
+    BucketStats {
+        size: size.to_string(),
+        buckets: counts.len(),
+        empty: counts.iter().filter(|c| **c == 0).count(),
+        min: counts.first().copied().unwrap_or(0),
+        median: at(0.5),
+        p95: at(0.95),
+        max: counts.last().copied().unwrap_or(0),
+    }
+}
+
+// 统计迁移窗口内的分布；源表无数据时返回 None
+pub async fn build(opt: &Opt, target_rows: u64) -> anyhow::Result<Option<Histogram>> {
+    let opt = &time_zone::init(opt).await?;
+    let tz = opt.time_zone.as_ref();
+    let (min_time, max_time) = get_time_range_http(&opt.src_dsn, &opt.src_db, &opt.src_table, &opt.time_field, &opt.start_time, &row_filter(opt), tz).await?;
+    let (Ok(min), Ok(max)) = (NaiveDateTime::parse_from_str(&min_time, FORMAT), NaiveDateTime::parse_from_str(&max_time, FORMAT)) else {
+        return Ok(None);
+    };
+    let hours = (max - min).num_hours() + 1;
+    let every = (hours + MAX_HOURS - 1) / MAX_HOURS;
+    // 列声明了时区时按 UTC 分桶，与分段键对齐
+    let utc = time_zone::utc_expr(tz, &opt.time_field);
+    let sample = if every > 1 { format!(" AND toUInt32(toDate({})) % {} = 0", utc, every) } else { String::new() };
+    let sql = format!(
+        "SELECT toString(toStartOfHour({})) AS h, count() AS n FROM {} WHERE {tf} >= {} AND {tf} <= {}{}{} GROUP BY h FORMAT JSONEachRow",
+        utc,
+        qualified(&opt.src_db, &opt.src_table),
+        opt.time_lit(&min_time),
+        opt.time_lit(&max_time),
+        row_filter(opt),
+        sample,
+        tf = opt.time_field
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let counts: HashMap<NaiveDateTime, u64> = rows
+        .iter()
+        .filter_map(|r| Some((NaiveDateTime::parse_from_str(r.get("h")?.as_str()?, FORMAT).ok()?, json_u64(r.get("n")))))
+        .collect();
+    // 窗口内（抽样日期内）的每个小时，无数据按 0
+    let day0 = chrono::NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+    let first_hour = min.date().and_hms_opt(min.hour(), 0, 0).unwrap();
+    let mut hourly = Vec::new();
+    let mut t = first_hour;
+    while t <= max {
+        if (t.date() - day0).num_days() % every == 0 {
+            hourly.push((t, counts.get(&t).copied().unwrap_or(0)));
+        }
+        t += Duration::hours(1);
+    }
+    let total: u64 = hourly.iter().map(|(_, n)| n).sum();
+    let group = |key: &dyn Fn(&NaiveDateTime) -> NaiveDateTime| {
+        let mut m: HashMap<NaiveDateTime, u64> = HashMap::new();
+        for (t, n) in &hourly {
+            *m.entry(key(t)).or_default() += n;
+        }
+        m.into_values().collect::<Vec<_>>()
+    };
+    let sizes = vec![
+        stats("1h", hourly.iter().map(|(_, n)| *n).collect()),
+        stats("6h", group(&|t| t.date().and_hms_opt(t.hour() / 6 * 6, 0, 0).unwrap())),
+        stats("1d", group(&|t| t.date().and_hms_opt(0, 0, 0).unwrap())),
+    ];
+    let busiest = hourly.iter().max_by_key(|(_, n)| *n).filter(|(_, n)| *n > 0);
+    let share = busiest.map(|(_, n)| *n as f64 / total.max(1) as f64).unwrap_or(0.0);
+    let skew_warning = busiest.filter(|_| share > SKEW_SHARE && hourly.len() > 24).map(|(t, n)| {
+        format!(
+            "{} 这一小时有 {} 行，占{}统计行数的 {:.0}%，该分段会触发自适应拆分（--split-min-window / --max-splits-per-segment）",
+            t.format(FORMAT),
+            n,
+            if every > 1 { "抽样" } else { "" },
+            share * 100.0
+        )
+    });
+    let mut recommendations = Vec::new();
+    let hour = &sizes[0];
+    if hour.p95 > target_rows {
+        recommendations.push(format!(
+            "每小时 p95 为 {} 行，超过目标 {}：建议缩小 --segment-size（如 15m），或 --page-key <排序键列> --page-rows {} 在分段内分页",
+            hour.p95, target_rows, target_rows
+        ));
+    }
+    if hour.buckets > 0 && hour.empty * 2 > hour.buckets {
+        recommendations.push(format!("{} / {} 个小时无数据，建议 --coalesce-empty 按天跳过空分段", hour.empty, hour.buckets));
+    }
+    if recommendations.is_empty() {
+        recommendations.push(format!("每小时 p95 为 {} 行，不超过目标 {}，按默认 1 小时分段即可", hour.p95, target_rows));
+    }
+    Ok(Some(Histogram {
+        min_time,
+        max_time,
+        sample_every_days: every,
+        rows: total,
+        sizes,
+        busiest_hour: busiest.map(|(t, _)| t.format(FORMAT).to_string()),
+        busiest_hour_share: share,
+        skew_warning,
+        target_segment_rows: target_rows,
+        recommendations,
+    }))
+}
+
+// 文本表
+pub fn render(h: &Histogram) -> Vec<String> {
+    let mut out = vec![format!(
+        "时间分布 {} ~ {}，{} 行{}",
+        h.min_time,
+        h.max_time,
+        h.rows,
+        if h.sample_every_days > 1 { format!("（每 {} 天抽样 1 天）", h.sample_every_days) } else { String::new() }
+    )];
+    out.push(format!("{:<6} {:>8} {:>8} {:>12} {:>12} {:>12} {:>12}", "size", "buckets", "empty", "min", "median", "p95", "max"));
+    for s in &h.sizes {
+        out.push(format!("{:<6} {:>8} {:>8} {:>12} {:>12} {:>12} {:>12}", s.size, s.buckets, s.empty, s.min, s.median, s.p95, s.max));
+    }
+    if let Some(w) = &h.skew_warning {
+        out.push(format!("警告: {}", w));
+    }
+    out.extend(h.recommendations.iter().map(|r| format!("建议: {}", r)));
+    out
+}
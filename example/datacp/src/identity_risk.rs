@@ -0,0 +1,234 @@
+// ===================== 忽略字段的行身份风险（--acknowledge-identity-risk） =====================
+// --ignore-field / --ignore-compare-field 常用来绕过一时的表结构差异，但被忽略的列若参与行身份，后果要很久以后才暴露：
+//   - 源表排序键 / 主键中的列：只在该列不同的行在比对摘要中相同，差异与补差都看不到；--ignore-field 时目标端该列为默认值，
+//     原本不同的行在目标端排序键相同，Replacing 系列合并时会被折叠
+//   - ReplacingMergeTree 的版本列（及删除标记列）、VersionedCollapsingMergeTree 的版本列：只有版本不同的行被视为相同，
+//     更新后的版本永远不会同步到目标端
+// 启动前按源表 system.tables 的 engine_full / sorting_key / primary_key 检查，命中时醒目告警并写入报告 identity_risks；
+// 交互运行（stdin 与 stderr 都是终端、未指定 --tui）时询问是否继续，非交互运行须指定 --acknowledge-identity-risk 才继续
+
+use log::{info, warn};
+use std::io::IsTerminal;
+use std::sync::{Arc, Mutex};
+
+use crate::report::{IdentityRisk, RunReport};
+use crate::{ch_query_rows, create_rewrite, preflight, shard, Opt};
+
+// 源表的引擎与键定义
+#[derive(Debug, Default)]
+struct Keys {
+    engine_full: String,
+    sorting_key: String,
+    primary_key: String,
+}
+
+// 引擎名与括号内的顶层参数；没有括号时参数为空
+fn engine_args(engine_full: &str) -> (&str, Vec<String>) {
+    let s = engine_full.trim_start();
+    let name_end = s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(s.len());
+    let name = &s[..name_end];
+    let rest = &s[name_end..];
+    if !rest.starts_with('(') {
+        return (name, Vec::new());
+    }
+    let (mut depth, mut quote, mut escaped) = (0i32, None::<char>, false);
+    for (i, c) in rest.char_indices() {
+        if let Some(q) = quote {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                _ if c == q => quote = None,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' | '`' => quote = Some(c),
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return (name, create_rewrite::split_commas(&rest[1..i]));
+                }
+            }
+            _ => {}
+        }
+    }
+    (name, Vec::new())
+}
+
+// 引擎参数中决定版本取舍的列：(列名, 作用)
+fn version_columns(engine_full: &str) -> Vec<(String, &'static str)> {
+    let (name, args) = engine_args(engine_full);
+    // Replicated 引擎的前两个参数为 ZooKeeper 路径与副本名（字符串字面量，可省略）
+    let args: Vec<String> = args.into_iter().skip_while(|a| name.starts_with("Replicated") && a.starts_with('\'')).collect();
+    let column = |i: usize| args.get(i).map(|a| a.trim_matches('`').to_string()).filter(|a| !a.is_empty());
+    let engine = name.strip_prefix("Replicated").unwrap_or(name);
+    let mut out = Vec::new();
+    match engine {
+        "ReplacingMergeTree" => {
+            out.extend(column(0).map(|c| (c, "ReplacingMergeTree 版本列")));
+            out.extend(column(1).map(|c| (c, "ReplacingMergeTree 删除标记列")));
+        }
+        "VersionedCollapsingMergeTree" => out.extend(column(1).map(|c| (c, "VersionedCollapsingMergeTree 版本列"))),
+        _ => {}
+    }
+    out
+}
+
+// 该列在源表行身份中的作用；主键是排序键的前缀，命中主键时不再重复列出排序键
+fn roles(column: &str, keys: &Keys) -> Vec<String> {
+    let mut out = Vec::new();
+    if preflight::key_mentions(&keys.primary_key, column) {
+        out.push(format!("主键（{}）", keys.primary_key));
+    } else if preflight::key_mentions(&keys.sorting_key, column) {
+        out.push(format!("排序键（{}）", keys.sorting_key));
+    }
+    out.extend(version_columns(&keys.engine_full).into_iter().filter(|(c, _)| c == column).map(|(_, role)| role.to_string()));
+    out
+}
+
+// 被忽略的列中参与行身份的部分
+fn risks(opt: &Opt, keys: &Keys) -> Vec<IdentityRisk> {
+    let ignored = opt.ignore_field.iter().map(|c| (c, "--ignore-field")).chain(opt.ignore_compare_field.iter().map(|c| (c, "--ignore-compare-field")));
+    ignored
+        .filter_map(|(c, flag)| {
+            let column = c.trim().trim_matches('`');
+            let roles = roles(column, keys);
+            (!column.is_empty() && !roles.is_empty()).then(|| IdentityRisk {
+                column: column.to_string(),
+                flag: flag.to_string(),
+                roles,
+                acknowledged: opt.acknowledge_identity_risk,
+            })
+        })
+        .collect()
+}
+
+// 命中时告警；已确认或交互确认后继续，否则拒绝启动
+fn gate(opt: &Opt, found: &[IdentityRisk], interactive: bool) -> anyhow::Result<()> {
+    if found.is_empty() {
+        return Ok(());
+    }
+    for r in found {
+        warn!(
+            "!!! {} 忽略的字段 {} 是源表 {} 的{}：只在该列不同的行会被视为同一行，该列的更新不会被比对发现或补差{} !!!",
+            r.flag,
+            r.column,
+            opt.src_table,
+            r.roles.join("、"),
+            if r.flag == "--ignore-field" { "；目标端该列为默认值，原本不同的行可能在合并时被折叠" } else { "" }
+        );
+    }
+    let columns = found.iter().map(|r| r.column.as_str()).collect::<Vec<_>>().join(", ");
+    if opt.acknowledge_identity_risk {
+        info!("已指定 --acknowledge-identity-risk，忽略参与行身份的字段 {} 继续运行", columns);
+        return Ok(());
+    }
+    if interactive && confirm(&columns) {
+        return Ok(());
+    }
+    anyhow::bail!(format!(
+        "忽略的字段 {} 参与源表行身份（排序键 / 主键 / 版本列），确认后果后请指定 --acknowledge-identity-risk",
+        columns
+    ))
+}
+
+fn confirm(columns: &str) -> bool {
+    eprint!("忽略的字段 {} 参与源表行身份，仍要继续？[y/N] ", columns);
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line).is_ok() && matches!(line.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+// 迁移前检查：没有忽略字段时不查询
+pub async fn check(opt: &Opt, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<()> {
+    if opt.ignore_field.is_empty() && opt.ignore_compare_field.is_empty() {
+        return Ok(());
+    }
+    let (db, table) = shard::resolve_local_table(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    let sql = format!(
+        "SELECT engine_full, sorting_key, primary_key FROM system.tables WHERE database = '{}' AND name = '{}' FORMAT JSONEachRow",
+        db, table
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let get = |k: &str| rows.first().and_then(|r| r.get(k)).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    let keys = Keys { engine_full: get("engine_full"), sorting_key: get("sorting_key"), primary_key: get("primary_key") };
+    let found = risks(opt, &keys);
+    report.lock().unwrap().identity_risks = found.clone();
+    let interactive = !opt.tui && std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+    gate(opt, &found, interactive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    fn opt(extra: &[&str]) -> Opt {
+        let args = ["datacp", "--src-dsn", "http://default:@a:8123", "--dst-dsn", "http://default:@b:8123"];
+        Opt::from_iter(args.iter().chain(extra))
+    }
+
+    fn keys(engine_full: &str, sorting_key: &str, primary_key: &str) -> Keys {
+        Keys { engine_full: engine_full.to_string(), sorting_key: sorting_key.to_string(), primary_key: primary_key.to_string() }
+    }
+
+    fn columns(found: &[IdentityRisk]) -> Vec<(&str, &str, Vec<&str>)> {
+        found.iter().map(|r| (r.column.as_str(), r.flag.as_str(), r.roles.iter().map(|s| s.as_str()).collect())).collect()
+    }
+
+    #[test]
+    fn engine_version_columns() {
+        assert_eq!(version_columns("ReplacingMergeTree(version) ORDER BY id SETTINGS index_granularity = 8192"), vec![("version".to_string(), "ReplacingMergeTree 版本列")]);
+        assert_eq!(
+            version_columns("ReplicatedReplacingMergeTree('/clickhouse/tables/{shard}/db/t', '{replica}', `ver`, is_deleted) ORDER BY id"),
+            vec![("ver".to_string(), "ReplacingMergeTree 版本列"), ("is_deleted".to_string(), "ReplacingMergeTree 删除标记列")]
+        );
+        // 省略 ZooKeeper 路径与副本名
+        assert_eq!(version_columns("ReplicatedReplacingMergeTree(ver) ORDER BY id"), vec![("ver".to_string(), "ReplacingMergeTree 版本列")]);
+        assert_eq!(version_columns("VersionedCollapsingMergeTree(sign, ver) ORDER BY id"), vec![("ver".to_string(), "VersionedCollapsingMergeTree 版本列")]);
+        // 没有版本列：按插入顺序保留最后一行
+        assert!(version_columns("ReplacingMergeTree ORDER BY id").is_empty());
+        assert!(version_columns("ReplicatedReplacingMergeTree('/p', '{replica}') ORDER BY id").is_empty());
+        assert!(version_columns("MergeTree PARTITION BY toYYYYMM(ts) ORDER BY (id, ts)").is_empty());
+        assert!(version_columns("CollapsingMergeTree(sign) ORDER BY id").is_empty());
+    }
+
+    #[test]
+    fn ignored_columns_by_engine_and_key() {
+        let found = risks(&opt(&["--ignore-field", "ts,payload", "--ignore-compare-field", "id"]), &keys("MergeTree ORDER BY (id, toStartOfHour(ts))", "id, toStartOfHour(ts)", "id, toStartOfHour(ts)"));
+        assert_eq!(
+            columns(&found),
+            vec![("ts", "--ignore-field", vec!["主键（id, toStartOfHour(ts)）"]), ("id", "--ignore-compare-field", vec!["主键（id, toStartOfHour(ts)）"])]
+        );
+        // 主键只是排序键的前缀
+        let found = risks(&opt(&["--ignore-field", "ts"]), &keys("MergeTree PRIMARY KEY id ORDER BY (id, ts)", "id, ts", "id"));
+        assert_eq!(columns(&found), vec![("ts", "--ignore-field", vec!["排序键（id, ts）"])]);
+        // 版本列不在排序键中；列名前缀相同的列不算命中
+        let replacing = keys("ReplacingMergeTree(version) ORDER BY (id, version_note)", "id, version_note", "id, version_note");
+        let found = risks(&opt(&["--ignore-field", "`version`"]), &replacing);
+        assert_eq!(columns(&found), vec![("version", "--ignore-field", vec!["ReplacingMergeTree 版本列"])]);
+        let found = risks(&opt(&["--ignore-compare-field", "version_note"]), &replacing);
+        assert_eq!(columns(&found), vec![("version_note", "--ignore-compare-field", vec!["主键（id, version_note）"])]);
+        // 既在排序键中又是版本列
+        let found = risks(&opt(&["--ignore-field", "ver"]), &keys("ReplicatedReplacingMergeTree('/p', '{replica}', ver) ORDER BY (id, ver)", "id, ver", "id, ver"));
+        assert_eq!(columns(&found), vec![("ver", "--ignore-field", vec!["主键（id, ver）", "ReplacingMergeTree 版本列"])]);
+        // 不参与行身份的列、没有排序键的表
+        assert!(risks(&opt(&["--ignore-field", "payload"]), &replacing).is_empty());
+        assert!(risks(&opt(&["--ignore-field", "id"]), &keys("Log", "", "")).is_empty());
+    }
+
+    #[test]
+    fn non_interactive_runs_require_acknowledgement() {
+        let k = keys("ReplacingMergeTree(version) ORDER BY id", "id", "id");
+        let o = opt(&["--ignore-field", "version"]);
+        let err = gate(&o, &risks(&o, &k), false).unwrap_err().to_string();
+        assert!(err.contains("version") && err.contains("--acknowledge-identity-risk"), "{err}");
+        let o = opt(&["--ignore-field", "version", "--acknowledge-identity-risk"]);
+        let found = risks(&o, &k);
+        assert!(found[0].acknowledged);
+        gate(&o, &found, false).unwrap();
+        let o = opt(&["--ignore-field", "payload"]);
+        gate(&o, &risks(&o, &k), false).unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+// ===================== 流式写入 =====================
+// 写入请求体不再整批拼成 String 再发送：行在发送时逐行序列化为固定大小（CHUNK_BYTES）的块，经 channel 交给
+// reqwest::Body::wrap_stream 以 chunked 方式发出，单次写入的峰值内存约为几个块而不是整批（宽行、大 --batch-bytes 时差别明显）。
+// 已发出的块保存在重试缓冲中直到服务端确认成功：不超过 --insert-spill-memory 的部分留在内存，其余写入临时文件；
+// 重试（连接失败、可重试错误、目标端只读等待后）从缓冲原样重放，批次边界与内容和首次发送相同
+
+use bytes::Bytes;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+use tokio::sync::Notify;
+
+// 请求体块大小
+pub const CHUNK_BYTES: usize = 1 << 20;
+// --batch-bytes 为 0 时每批行数
+const BATCH_ROWS: usize = 5000;
+
+static SPILL_SEQ: AtomicU64 = AtomicU64::new(0);
+
+// 重试缓冲：前 limit 字节的块留在内存，之后的块依次写入临时文件
+struct Spill {
+    limit: u64,
+    mem: Vec<Bytes>,
+    mem_bytes: u64,
+    file: Option<(PathBuf, std::fs::File)>,
+    file_chunks: Vec<usize>,
+}
+
+impl Spill {
+    fn new(limit: u64) -> Self {
+        Spill { limit, mem: Vec::new(), mem_bytes: 0, file: None, file_chunks: Vec::new() }
+    }
+
+    fn push(&mut self, chunk: &Bytes) -> std::io::Result<()> {
+        if self.file.is_none() && self.mem_bytes + chunk.len() as u64 <= self.limit {
+            self.mem_bytes += chunk.len() as u64;
+            self.mem.push(chunk.clone());
+            return Ok(());
+        }
+        if self.file.is_none() {
+            let path = std::env::temp_dir().join(format!(
+                "datacp-insert-{}-{}.spill",
+                std::process::id(),
+                SPILL_SEQ.fetch_add(1, Ordering::Relaxed)
+            ));
+            let f = std::fs::File::create(&path)?;
+            self.file = Some((path, f));
+        }
+        if let Some((_, f)) = self.file.as_mut() {
+            f.write_all(chunk)?;
+        }
+        self.file_chunks.push(chunk.len());
+        Ok(())
+    }
+
+    fn chunks(&self) -> usize {
+        self.mem.len() + self.file_chunks.len()
+    }
+
+    // 第 i 块；文件中的块从 reader 顺序读取
+    async fn read(&self, i: usize, reader: &mut Option<tokio::fs::File>) -> std::io::Result<Bytes> {
+        if let Some(c) = self.mem.get(i) {
+            return Ok(c.clone());
+        }
+        if reader.is_none() {
+            let (path, _) = self.file.as_ref().ok_or_else(|| std::io::Error::other("重试缓冲文件不存在"))?;
+            *reader = Some(tokio::fs::File::open(path).await?);
+        }
+        let mut buf = vec![0u8; self.file_chunks[i - self.mem.len()]];
+        if let Some(f) = reader.as_mut() {
+            f.read_exact(&mut buf).await?;
+        }
+        Ok(Bytes::from(buf))
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        if let Some((path, _)) = self.file.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// 从重试缓冲重放的请求体
+fn replay(spill: Arc<Spill>) -> reqwest::Body {
+    let stream = futures::stream::unfold((spill, 0usize, None), |(spill, i, mut reader)| async move {
+        if i >= spill.chunks() {
+            return None;
+        }
+        match spill.read(i, &mut reader).await {
+            Ok(c) => Some((Ok(c), (spill, i + 1, reader))),
+            // 出错后请求中止，不再继续读取
+            Err(e) => {
+                let end = spill.chunks();
+                Some((Err(e), (spill, end, reader)))
+            }
+        }
+    });
+    reqwest::Body::wrap_stream(stream)
+}
+
+type Row = HashMap<String, Value>;
+
+// 一个写入批次：从 rows 开头起按 --batch-bytes 切分（首次发送时确定），首次发送边序列化边发出，重试时从缓冲重放
+pub struct Batch<'a> {
+    rows: &'a [Row],
+    batch_bytes: u64,
+    spill_limit: u64,
+    sent: Option<Arc<Spill>>,
+    len: usize,
+    bytes: u64,
+    settings: Vec<(String, String)>, // 附加到写入请求上的 ClickHouse 设置
+}
+
+impl<'a> Batch<'a> {
+    pub fn new(rows: &'a [Row], batch_bytes: u64, spill_limit: u64) -> Self {
+        Batch { rows, batch_bytes, spill_limit, sent: None, len: 0, bytes: 0, settings: Vec::new() }
+    }
+
+    pub fn with_settings(mut self, settings: Vec<(String, String)>) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn settings(&self) -> &[(String, String)] {
+        &self.settings
+    }
+
+    // 本批行数，首次发送后确定；未发送过时为 0
+    pub fn rows(&self) -> usize {
+        self.len
+    }
+
+    // 本批请求体字节数，首次发送后确定
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    // 以本批数据为请求体发送 req
+    pub async fn send(&mut self, req: reqwest::RequestBuilder) -> anyhow::Result<reqwest::Response> {
+        if let Some(spill) = &self.sent {
+            return Ok(req.body(replay(spill.clone())).send().await?);
+        }
+        let (tx, rx) = tokio::sync::mpsc::channel::<std::io::Result<Bytes>>(2);
+        let body = reqwest::Body::wrap_stream(futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|c| (c, rx)) }));
+        // 服务端提前响应（如报错）后不再等待请求体被读取，其余行只写入缓冲
+        let answered = Notify::new();
+        let send = async {
+            let r = req.body(body).send().await;
+            answered.notify_one();
+            r
+        };
+        let produce = async {
+            let (mut spill, mut chunk, mut size, mut n) = (Spill::new(self.spill_limit), Vec::with_capacity(CHUNK_BYTES), 0u64, 0usize);
+            let (mut open, mut last) = (true, false);
+            let mut it = self.rows.iter();
+            while !last {
+                match it.next() {
+                    Some(row) => {
+                        let line = serde_json::to_vec(row).unwrap();
+                        let full = if self.batch_bytes == 0 { n >= BATCH_ROWS } else { n > 0 && size + line.len() as u64 > self.batch_bytes };
+                        if full {
+                            last = true;
+                        } else {
+                            chunk.extend_from_slice(&line);
+                            chunk.push(b'\n');
+                            size += line.len() as u64 + 1;
+                            n += 1;
+                            if chunk.len() < CHUNK_BYTES {
+                                continue;
+                            }
+                        }
+                    }
+                    None => last = true,
+                }
+                if chunk.is_empty() {
+                    continue;
+                }
+                let c = Bytes::from(std::mem::replace(&mut chunk, Vec::with_capacity(CHUNK_BYTES)));
+                if let Err(e) = spill.push(&c) {
+                    // 缓冲写入失败时中止请求，避免服务端把不完整的请求体当作一批写入
+                    let _ = tx.send(Err(std::io::Error::other(format!("写入重试缓冲失败: {}", e)))).await;
+                    return Err(anyhow::anyhow!(format!("写入重试缓冲失败: {}", e)));
+                }
+                if open {
+                    tokio::select! {
+                        r = tx.send(Ok(c)) => open = r.is_ok(),
+                        _ = answered.notified() => open = false,
+                    }
+                }
+            }
+            drop(tx);
+            Ok((spill, n, size))
+        };
+        let (resp, produced) = tokio::join!(send, produce);
+        // 缓冲不完整时不保留，下次发送重新序列化
+        let (spill, n, size) = produced?;
+        self.sent = Some(Arc::new(spill));
+        self.len = n;
+        self.bytes = size;
+        Ok(resp?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::io::Read;
+
+    // 按线程统计堆占用：测试并行运行，进程 RSS 会混入其他测试，只统计执行写入的测试线程
+    struct Counting;
+
+    thread_local! {
+        static USED: Cell<isize> = const { Cell::new(0) };
+        static PEAK: Cell<isize> = const { Cell::new(0) };
+    }
+
+    fn track(delta: isize) {
+        let _ = USED.try_with(|u| {
+            u.set(u.get() + delta);
+            let _ = PEAK.try_with(|p| p.set(p.get().max(u.get())));
+        });
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, l: Layout) -> *mut u8 {
+            track(l.size() as isize);
+            System.alloc(l)
+        }
+        unsafe fn dealloc(&self, p: *mut u8, l: Layout) {
+            track(-(l.size() as isize));
+            System.dealloc(p, l)
+        }
+        unsafe fn realloc(&self, p: *mut u8, l: Layout, new_size: usize) -> *mut u8 {
+            track(new_size as isize - l.size() as isize);
+            System.realloc(p, l, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOC: Counting = Counting;
+
+    // 测量 f 执行期间本线程的堆峰值增量
+    async fn peak_heap<F: std::future::Future<Output = ()>>(f: F) -> usize {
+        let start = USED.with(|u| u.get());
+        PEAK.with(|p| p.set(start));
+        f.await;
+        (PEAK.with(|p| p.get()) - start).max(0) as usize
+    }
+
+    // 在独立线程上接收写入请求并丢弃请求体（不计入测试线程），返回每个请求体的字节数（chunked 时含分块头）
+    fn sink(requests: usize) -> (String, std::sync::mpsc::Receiver<u64>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for _ in 0..requests {
+                let (mut sock, _) = listener.accept().unwrap();
+                let mut buf = [0u8; 65536];
+                let (mut head, mut body_at) = (Vec::new(), None);
+                while body_at.is_none() {
+                    let n = sock.read(&mut buf).unwrap();
+                    head.extend_from_slice(&buf[..n]);
+                    body_at = head.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4);
+                }
+                let at = body_at.unwrap();
+                let text = String::from_utf8_lossy(&head[..at]).to_ascii_lowercase();
+                let length: Option<u64> = text.lines().find_map(|l| l.strip_prefix("content-length:")).map(|v| v.trim().parse().unwrap());
+                let mut got = (head.len() - at) as u64;
+                let mut tail = head[at..].to_vec();
+                loop {
+                    let done = match length {
+                        Some(l) => got >= l,
+                        None => tail.ends_with(b"0\r\n\r\n"),
+                    };
+                    if done {
+                        break;
+                    }
+                    let n = sock.read(&mut buf).unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    got += n as u64;
+                    tail.extend_from_slice(&buf[..n]);
+                    let keep = tail.len().saturating_sub(5);
+                    tail.drain(..keep);
+                }
+                let _ = std::io::Write::write_all(&mut sock, b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                tx.send(got).unwrap();
+            }
+        });
+        (url, rx)
+    }
+
+    fn wide_rows(n: usize, cols: usize) -> Vec<Row> {
+        (0..n)
+            .map(|i| {
+                (0..cols)
+                    .map(|c| (format!("col_{:03}", c), Value::String(format!("{:08}-{}", i, "x".repeat(100)))))
+                    .collect()
+            })
+            .collect()
+    }
+
+    // 宽行负载下整批 String（原实现：拼接整批后每次重试 clone 一份）与流式写入的堆峰值对比
+    #[tokio::test]
+    async fn streaming_insert_peak_heap() {
+        let rows = wide_rows(4000, 40);
+        let client = reqwest::Client::new();
+        let (url, got) = sink(2);
+
+        let before = peak_heap(async {
+            for (data, _) in crate::insert_batches(&rows, 1 << 30) {
+                let resp = client.post(&url).body(data.clone()).send().await.unwrap();
+                assert!(resp.status().is_success());
+            }
+        })
+        .await;
+        let payload = got.recv().unwrap();
+
+        let after = peak_heap(async {
+            let mut batch = Batch::new(&rows, 1 << 30, 1 << 20);
+            let resp = batch.send(client.post(&url)).await.unwrap();
+            assert!(resp.status().is_success());
+            assert_eq!(batch.rows(), rows.len());
+        })
+        .await;
+        assert!(got.recv().unwrap() >= payload);
+
+        assert!(before as u64 >= payload * 2, "请求体 {} 字节，整批 String 堆峰值 {}", payload, before);
+        assert!(after * 3 < before, "流式写入堆峰值 {} 未明显低于整批 {}", after, before);
+    }
+
+    #[tokio::test]
+    async fn retry_replays_same_batch_from_spill() {
+        let rows = wide_rows(3000, 10);
+        let (dsn, seen) = crate::mock_ch::serve(crate::mock_ch::empty).await;
+        let url = dsn.replace("default:@", "");
+        let client = reqwest::Client::new();
+        // 批次约 3 个块，内存缓冲只放得下第一块，其余写入临时文件
+        let mut batch = Batch::new(&rows, 3 * CHUNK_BYTES as u64, 2 * CHUNK_BYTES as u64);
+        for _ in 0..2 {
+            assert!(batch.send(client.post(&url)).await.unwrap().status().is_success());
+        }
+        let n = batch.rows();
+        assert!(n > 0 && n < rows.len());
+        let spill = batch.sent.clone().unwrap();
+        assert_eq!(spill.mem.len(), 1);
+        assert!(!spill.file_chunks.is_empty());
+        let path = spill.file.as_ref().unwrap().0.clone();
+        assert!(path.exists());
+
+        let seen = seen.lock().unwrap().clone();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0].1, seen[1].1);
+        let (back, bad) = crate::bad_rows::parse_rows(seen[0].1.as_bytes(), true).unwrap();
+        assert!(bad.is_empty());
+        assert_eq!(back, rows[..n]);
+        assert!(seen[0].1.len() as u64 <= 3 * CHUNK_BYTES as u64);
+
+        drop(spill);
+        drop(batch);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,7 @@
+// datacp 库：供外部程序复用的稳定接口（迁移工具本体见 main.rs）
+
+pub mod digest;
+pub mod planner;
+
+pub use digest::{row_digest, RowValues, DIGEST_VERSION};
+pub use planner::{Blacklist, Filters, IntervalSet, Segment, SegmentPlan};
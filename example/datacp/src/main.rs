@@ -1,10 +1,9 @@
 use anyhow::{Context, Result}; // 引入错误处理库
 use chrono::{DateTime, Utc}; // 引入时间库
 use futures::future::join_all; // 并发任务等待工具
-use log::{error, info}; // 日志宏
+use log::{error, info, warn}; // 日志宏
 use reqwest; // HTTP 客户端
 use serde_json::Value; // JSON值类型
-use sha2::{Digest, Sha256}; // sha256哈希
 use std::collections::{HashMap, HashSet}; // 哈希表/集合
 use std::fs::File; // 文件操作
 use std::fs::OpenOptions;
@@ -12,11 +11,90 @@ use std::io::{self, Write}; // 文件写入
 use structopt::StructOpt; // 命令行参数解析
 use std::time::Duration; // 用于设置超时的Duration类型
 use std::sync::Arc; // 新增：用于 Client 复用
+use datacp::planner; // 分段规划
 
-#[derive(StructOpt, Debug)]
+mod archive; // 归档模式
+mod background_verify; // 历史分段后台校验
+mod bad_rows; // 无法解析的行
+mod binary; // 二进制列 hex 读写
+mod bench; // 基准测试
+mod blackout; // 维护窗口暂停写入
+mod calibrate; // 启动时吞吐校准
+mod catchup; // 增量追平与切换时机
+mod checkpoint; // 断点续传文件合并与缺口检查
+mod checkpoint_meta; // 断点续传元数据
+mod clock_skew; // 两端与本机的时钟偏差
+mod cluster; // 集群子句自检与单机回退
+mod coalesce; // 稀疏表空分段合并
+mod column_default; // 列默认值覆盖
+mod column_plan; // 迁移字段及其统一顺序
+mod coordination; // 多进程写入并发协调
+mod create_rewrite; // 建表语句的表级设置与 TTL 改写
+mod cutover; // 切换后处理
+mod cutover_state; // 切换子步骤状态与 resume-cutover
+mod ddl; // DDL 复制与物化视图暂停
+mod events; // 机器可读事件（NDJSON）
+mod health; // 端点健康探测
+mod histogram; // 时间字段分布
+mod identity_risk; // 忽略字段的行身份风险
+mod insert_stream; // 流式写入与重试缓冲
+mod deadline; // 运行时间预算
+mod endpoint; // 两端连接配置（TLS、认证、代理）
+mod manifest; // 运行清单
+mod merge_pause; // 回填期间暂停目标表合并
+mod multi; // 多表迁移
+mod normalize; // 比对前取值规范化
+mod memory; // 内存预算
+mod mirror; // 镜像模式删除多余目标行
+#[cfg(test)]
+mod mock_ch; // 测试用 ClickHouse HTTP 模拟
+mod mutations; // 源表 mutation 监控
+mod optimize; // 迁移后合并
+mod overcopy; // 目标端重复写入检测与去重
+mod oversized; // 超大行单独写入与死信
+mod pager; // 分段内键集分页
+mod phase_checkpoint; // 增量与 _bak 阶段的断点记录
+mod post_cutover_watch; // 切换后新表写入观察
+mod preflight; // 迁移前检查
+mod priority; // 低优先级运行
+mod pushgateway; // 指标推送到 pushgateway
+mod rejected; // 目标端拒绝行的二分隔离
+mod replace; // 按分区整体替换
+mod report; // 运行报告
+mod row_policy; // 源表行策略检查
+mod schema_fingerprint; // 表结构指纹
+mod segment; // 分段区间
+mod serve; // 常驻服务模式（任务 HTTP 接口）
+mod server_copy; // 服务端拷贝
+mod server_digest; // 服务端计算行摘要
+mod server_version; // 服务端版本与功能检测
+mod shard; // 分布式目标表本地写入
+mod shard_of; // 多进程按行分担同一张表
+mod sql_log; // SQL 审计日志与回放
+mod staged; // 分段暂存写入
+mod standby; // 热备模式
+mod src_replica; // 源端副本选择与一致性读
+mod src_limit; // 源端查询并发上限
+mod src_query; // 查询作为源端
+mod state_dir; // 运行文件目录
+mod status; // 本地状态接口
+mod supervise; // 监督运行：可重试的整体失败后在进程内重启
+mod time_expr; // 组合时间表达式
+mod time_zone; // 时间字段的列时区
+mod timing; // 分段耗时归因
+mod transfer; // 传输字节统计与剩余量估算
+mod ttl; // 源表 TTL 过期边界
+mod tui; // 终端仪表盘
+mod verify; // 迁移结果按分区校验
+mod watchdog; // 全局停滞看门狗
+mod work_queue; // 分段工作队列
+mod write_gate; // 目标端只读等待
+
+#[derive(StructOpt, Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[structopt(
     name = "datacp",
-    about = "ClickHouse数据迁移工具")]
+    about = "ClickHouse数据迁移工具",
+    after_help = "退出码:\n    0  全部表迁移成功且无失败分段\n    1  启动/参数等错误，未进入迁移\n    2  部分成功：存在失败分段或表因 --fail-fast 未执行，可直接重试（断点续传）\n    3  切换步骤失败或切换后校验未通过，需人工处理\n    4  超过 --max-duration，已停止且未切换，可在下个窗口重试（断点续传）\n    5  连续失败或失败率超过上限熔断，已停止且未切换，排除故障后重试（断点续传）\n    6  迁移中途失败，未切换，排除错误（见报告 error）后重试")]
 struct Opt {
     /// 源ClickHouse DSN (仅支持http)
     #[structopt(long, default_value = "http://default:@localhost:8123")]
@@ -24,34 +102,55 @@ struct Opt {
     /// 目标ClickHouse DSN (仅支持http)
     #[structopt(long, default_value = "http://default:@localhost:8123")]
     dst_dsn: String, // 目标库连接串
+    /// 连接配置文件（TOML）：[source] / [destination] 块分别配置两端的地址、账号、密码环境变量、CA 证书、客户端证书、代理与 settings，优先于 --src-dsn / --dst-dsn
+    #[structopt(long, default_value = "")]
+    config: String, // 连接配置文件
     /// 源数据库名，必填
     #[structopt(long, default_value="db_data")]
     src_db: String, // 源数据库名
     /// 目标数据库名，必填
     #[structopt(long, default_value="db_data")]
     dst_db: String, // 目标数据库名
-    /// 源表名，必填
+    /// 源表名，必填（指定 --src-query 时不填）
     #[structopt(long, default_value="")]
     src_table: String, // 源表名
+    /// 以 SELECT 查询（如连接两张表的查询）代替 --src-table 作为源端，输出列须包含时间字段；切换时不 rename 源端
+    #[structopt(long, default_value = "")]
+    src_query: String, // 源端查询
     /// 目标表名，必填
     #[structopt(long, default_value="")]
     dst_table: String, // 目标表名
     /// 用于迁移的时间字段（DateTime类型），必填
     #[structopt(long, default_value="")]
     time_field: String, // 时间字段
+    /// 计算 DateTime 的时间表达式，替代 --time-field，如 "toDateTime(event_date) + toIntervalHour(event_hour)"
+    #[structopt(long, default_value = "")]
+    time_expr: String, // 时间表达式
+    /// --time-expr 引用的列，逗号分隔；留空时从表达式中解析
+    #[structopt(long, use_delimiter = true)]
+    time_expr_columns: Vec<String>, // 时间表达式引用的列
     /// 迁移起始时间，默认: 1970-01-01 08:00:01
     #[structopt(long, default_value = "1970-01-01 08:00:01")]
     start_time: String, // 起始时间
+    /// 分段大小（1m ~ 1d，须整除一天），如 15m；断点续传文件按区间记录，中途改变分段大小时已完成的时段不会重做，默认: 1h
+    #[structopt(long, default_value = "1h", parse(try_from_str = parse_duration_str))]
+    segment_size: Duration, // 分段大小
     /// 并发数，默认: 4
     #[structopt(long, default_value = "4")]
     parallelism: usize, // 并发数
-    /// 断点续传文件名，留空自动生成
+    /// 断点续传文件名，留空自动生成；相对路径位于 --state-dir 的运行目录下
     #[structopt(long, default_value = "")]
     done_segments: String, // 断点续传文件名
     /// 忽略校验和插入的字段，可指定多次
     #[structopt(long = "ignore-field", use_delimiter = true)]
     ignore_field: Vec<String>, // 忽略字段
-    /// 日志文件名，默认: log.json
+    /// 只在比对时忽略的字段（不参与摘要，目标端比对查询不读取），仍从源端读取并写入目标端，可指定多次
+    #[structopt(long = "ignore-compare-field", use_delimiter = true)]
+    ignore_compare_field: Vec<String>, // 比对忽略字段
+    /// 忽略的字段属于源表排序键 / 主键或 ReplacingMergeTree 版本列时，非交互运行须指定此项才继续
+    #[structopt(long)]
+    acknowledge_identity_risk: bool, // 确认忽略行身份字段
+    /// 日志文件名，默认: log.json；相对路径位于 --state-dir 的运行目录下
     #[structopt(long, default_value = "log.json")]
     log_file: String, // 日志文件名
     /// 源表是否为分布式表，默认: false
@@ -63,6 +162,701 @@ struct Opt {
     /// ClickHouse集群名（分布式表rename时用）
     #[structopt(long, default_value = "")]
     cluster_name: String, // 集群名
+    /// --cluster-name 指定的集群在端点上不存在或只包含本机时报错退出（默认告警并改为不带 ON CLUSTER 的单机形式）
+    #[structopt(long)]
+    strict_cluster: bool, // 集群不可用时报错
+    /// 目标端用于比对/校验/切换的表（写入仍走 --dst-table，适用于 Buffer/Null 中转表），默认同 --dst-table
+    #[structopt(long, default_value = "")]
+    dst_read_table: String, // 目标读取表
+    /// 目标为分布式表时，客户端计算分片并直接写入各分片本地表
+    #[structopt(long)]
+    dst_write_local: bool, // 直写分片本地表
+    #[structopt(subcommand)]
+    cmd: Option<Command>, // 子命令，缺省为迁移
+    /// 源表查询追加 FINAL（ReplacingMergeTree/CollapsingMergeTree 读取合并后的结果）。
+    /// 注意：版本列不能放入 --ignore-field，否则去重比较失效
+    #[structopt(long)]
+    select_final: bool, // 源表 FINAL 读取
+    /// 目标表比对查询同样追加 FINAL（需配合 --select-final）
+    #[structopt(long)]
+    dst_select_final: bool, // 目标表 FINAL 读取
+    /// 拷贝方式: http(经 datacp 拉取比对写入) / remote-secure(目标端 INSERT SELECT FROM remoteSecure) /
+    /// attach-partition(同实例按分区 ATTACH PARTITION FROM)，默认: http
+    #[structopt(long, default_value = "http")]
+    copy_mode: String, // 拷贝方式
+    /// remote-secure 模式下源端 TLS 原生端口，默认: 9440
+    #[structopt(long, default_value = "9440")]
+    src_secure_port: u16, // 源端安全端口
+    /// remote-secure 模式下单条 INSERT SELECT 的超时时间，默认: 1h
+    #[structopt(long, default_value = "1h", parse(try_from_str = parse_duration_str))]
+    remote_query_timeout: Duration, // 服务端拷贝超时
+    /// 目标端预计磁盘使用率上限(%)，超过则拒绝启动，默认: 85
+    #[structopt(long, default_value = "85")]
+    max_disk_usage_percent: u32, // 磁盘使用率上限
+    /// 源端 bytes_on_disk 估算的修正系数（两端压缩比差异），默认: 1.2
+    #[structopt(long, default_value = "1.2")]
+    disk_estimate_factor: f64, // 磁盘估算系数
+    /// 跳过目标端磁盘空间检查（存储策略无法估算时使用）
+    #[structopt(long)]
+    skip_disk_check: bool, // 跳过磁盘检查
+    /// 时间字段不在源表排序键或分区键中（每个分段都是全表扫描）时拒绝启动，默认只告警
+    #[structopt(long)]
+    require_indexed_time: bool, // 要求时间字段有索引
+    /// 续传或切换前表结构指纹与断点续传元数据不一致时，以当前结构重新记录并继续（仅用于有意且兼容的表结构变更），默认拒绝
+    #[structopt(long)]
+    accept_schema_change: bool, // 接受表结构变更
+    /// 源端查询限制，逗号分隔: mem=内存上限, time=执行时间上限, read=读取字节上限, rows=读取行数上限；0 表示不限制，默认: mem=8G,time=600
+    #[structopt(long, default_value = "mem=8G,time=600")]
+    src_query_limits: String, // 源端查询限制
+    /// 两端所有查询附加 ClickHouse 的 priority 设置（数值越大优先级越低），0 表示不设置，默认: 0
+    #[structopt(long, default_value = "0")]
+    query_priority: u64, // 查询优先级
+    /// 两端所有查询附加的 workload 设置（须已在两端 CREATE WORKLOAD）
+    #[structopt(long, default_value = "")]
+    workload: String, // 工作负载
+    /// 两端所有查询使用的设置档（profile 设置，须存在于两端 system.settings_profiles）
+    #[structopt(long, default_value = "")]
+    settings_profile: String, // 设置档
+    /// 回填与 verify 等摘要计算阶段把本进程的 nice 调为该值（1~19），0 表示不调整，默认: 0
+    #[structopt(long, default_value = "0")]
+    os_nice: u32, // 进程 nice 值
+    /// 归档模式：只迁移 time_field < now() - --older-than 的数据，迁移后不做表切换
+    #[structopt(long)]
+    archive: bool, // 归档模式
+    /// 归档数据的最小年龄，默认: 90d
+    #[structopt(long, default_value = "90d", parse(try_from_str = parse_duration_str))]
+    older_than: Duration, // 归档年龄
+    /// 归档模式下逐段校验通过后从源表删除（需同时指定 --yes；不指定时只校验不删除）
+    #[structopt(long)]
+    archive_delete_after_verify: bool, // 校验后删除源数据
+    /// 迁移后对目标表执行 OPTIMIZE FINAL: full(结束时整表一次) / partition(分段完成后按分区，后台节流) / none，默认: none
+    #[structopt(long, default_value = "none")]
+    optimize_after: String, // 迁移后合并方式
+    /// OPTIMIZE 时追加 DEDUPLICATE
+    #[structopt(long)]
+    optimize_deduplicate: bool, // 合并时去重
+    /// 单次 OPTIMIZE 超时时间，默认: 1h
+    #[structopt(long, default_value = "1h", parse(try_from_str = parse_duration_str))]
+    optimize_timeout: Duration, // 合并超时
+    /// partition 模式下两轮分区合并之间的最小间隔，默认: 5m
+    #[structopt(long, default_value = "5m", parse(try_from_str = parse_duration_str))]
+    optimize_interval: Duration, // 分区合并间隔
+    /// 目标端只读(Code 242)或 part 过多(Code 252)时暂停写入的最长等待时间，默认: 15m
+    #[structopt(long, default_value = "15m", parse(try_from_str = parse_duration_str))]
+    readonly_wait_max: Duration, // 只读等待上限
+    /// 多表迁移 manifest(TOML)，每个 [[tables]] 指定 src_table/dst_table/time_field 及可选覆盖项
+    #[structopt(long, default_value = "")]
+    tables_file: String, // 多表 manifest
+    /// 迁移源库全部 MergeTree 系列表（可配合 --include/--exclude 与 --tables-file 中的单表覆盖）
+    #[structopt(long)]
+    all_tables: bool, // 整库迁移
+    /// --all-tables 时纳入的表名模式（glob，re: 前缀为正则），可指定多次
+    #[structopt(long)]
+    include: Vec<String>, // 纳入模式
+    /// --all-tables 时排除的表名模式（glob，re: 前缀为正则），可指定多次
+    #[structopt(long)]
+    exclude: Vec<String>, // 排除模式
+    /// --all-tables 时分区键中没有 DateTime 列时依次尝试的时间字段，逗号分隔
+    #[structopt(long, use_delimiter = true, default_value = "created_at,event_time")]
+    time_field_candidates: Vec<String>, // 候选时间字段
+    /// 多表迁移时同时迁移的表数，默认: 1
+    #[structopt(long, default_value = "1")]
+    table_concurrency: usize, // 表级并发
+    /// 运行文件（断点续传、日志、报告、死信、SQL 审计日志）所在目录，每张表使用其下的 <src_db>.<src_table>__<dst_db>.<dst_table> 子目录，默认: .
+    #[structopt(long, default_value = ".")]
+    state_dir: String, // 状态目录
+    /// 多表迁移时任一表失败即不再开始新的表
+    #[structopt(long)]
+    fail_fast: bool, // 失败即停止
+    /// 切换时把目标表跨库 RENAME 到源库（RENAME TABLE dst_db.t TO src_db.t），要求源端与目标端为同一实例
+    #[structopt(long)]
+    cutover_into_src_db: bool, // 切换到源库
+    /// 目标表切换方式：rename（RENAME TABLE）或 recreate（按切换后的表名新建表、ATTACH PARTITION FROM 中间表后删除中间表，
+    /// 用于 ZooKeeper 路径含表名的 Replicated 表），默认: rename
+    #[structopt(long, default_value = "rename")]
+    cutover_strategy: String, // 切换方式
+    /// 不执行最终的 _bak 补差与 rename 切换
+    #[structopt(long)]
+    no_cutover: bool, // 跳过切换
+    /// --src-query 时切换阶段把目标表改名为该名称（没有源表可 rename），留空不改名
+    #[structopt(long, default_value = "")]
+    rename_dst_to: String, // 目标表切换名
+    /// 允许源端与目标端解析为同一张表（UUID 相同）或切换时把表 rename 到自身；默认拒绝运行
+    #[structopt(long)]
+    allow_same_server: bool, // 允许同一张表
+    /// 全局同时进行的写入请求上限（多表共享），0 表示不限制，默认: 0
+    #[structopt(long, default_value = "0")]
+    max_concurrent_inserts: usize, // 全局写入并发
+    /// 与其他 datacp 进程协调写入并发的登记表（dst:<表> 或 src:<表>，表名可带库名），配合 --global-insert-concurrency 使用
+    #[structopt(long, default_value = "")]
+    coordination_table: String, // 并发协调表
+    /// 所有登记到 --coordination-table 的进程合计的写入并发上限；超出时各进程按登记的期望并发比例缩减
+    #[structopt(long, default_value = "0")]
+    global_insert_concurrency: usize, // 跨进程写入并发上限
+    /// 同时发往源端的查询上限（数据读取与 count/min/max 等，多表共享），0 表示不限制，默认: 0
+    #[structopt(long, default_value = "0")]
+    src_max_concurrent_queries: usize, // 源端查询并发
+    /// 源端读取固定发往的副本主机（host 或 host:port，须在 system.clusters / system.replicas 中），不可达时回退到 DSN 主机
+    #[structopt(long, default_value = "")]
+    src_prefer_replica: String, // 源端首选副本
+    /// 所有源端查询附加 select_sequential_consistency=1，避免读到落后副本的旧数据
+    #[structopt(long)]
+    src_consistent_reads: bool, // 源端一致性读
+    /// 迁移期间 DETACH 由目标表触发的物化视图，批量写入完成后 ATTACH
+    #[structopt(long)]
+    pause_mvs: bool, // 暂停物化视图
+    /// 首轮分段迁移期间对目标表执行 SYSTEM STOP MERGES，进入增量循环前 START MERGES（失败或中断时同样恢复），需要 SYSTEM MERGES 权限
+    #[structopt(long)]
+    pause_dst_merges: bool, // 回填期间暂停目标表合并
+    /// 分段黑名单文件，每行一个分段起点或 start..end 时间范围，命中的分段不迁移也不校验
+    #[structopt(long, default_value = "")]
+    skip_segments_file: String, // 分段黑名单
+    /// 源表的行策略作用于连接用户（或无法查询 system.row_policies）时拒绝运行，确保迁移与比对覆盖全部行
+    #[structopt(long)]
+    expect_full_visibility: bool, // 要求完整可见
+    /// 接受源表行策略过滤后的拷贝，报告与运行清单中记录为有意按策略过滤
+    #[structopt(long)]
+    accept_row_policies: bool, // 接受行策略
+    /// 稀疏表：先按天探测（一条 GROUP BY toDate 查询），无数据日期内的分段以一条范围记录标记为完成，只为有数据的日期生成小时分段
+    #[structopt(long)]
+    coalesce_empty: bool, // 空分段合并
+    /// 只处理该文件列出的分段（每行一个分段起点，如 checkpoint gaps 的输出），不做增量与切换，须同时指定 --no-cutover
+    #[structopt(long, default_value = "")]
+    only_segments_file: String, // 只处理的分段
+    /// 不论断点续传记录，重新迁移这些分段（逗号分隔的分段起点，如 "2024-05-03 11:00:00,2024-05-03 12:00:00"），自动跳过增量与切换
+    #[structopt(long, default_value = "")]
+    only_segments: String, // 重新迁移的分段
+    /// 不论断点续传记录，重新迁移与该时间范围相交的分段（"起..止"，不含止点），自动跳过增量与切换
+    #[structopt(long, default_value = "")]
+    segment_range: String, // 重新迁移的时间范围
+    /// 行过滤条件(SQL 谓词)，同时作用于源端与目标端的全部查询，例如 "tenant_id = 42"
+    #[structopt(long = "where", default_value = "")]
+    filter: String, // 行过滤条件
+    /// 多进程分担同一张表：i/n 表示本进程只处理 cityHash64(--shard-key) % n = i 的行（如 0/4），
+    /// 各进程的断点续传文件与报告按分片区分；切换只能由分片 0 执行，其余分片须使用 --no-cutover
+    #[structopt(long, default_value = "")]
+    shard_of: String, // 行分片
+    /// --shard-of 的分片键（SQL 表达式，如 user_id），所有进程须相同
+    #[structopt(long, default_value = "")]
+    shard_key: String, // 分片键
+    /// 迁移期间源表出现新 mutation 时的处理: warn(告警并在切换前重新校验全部分段) / pause(暂停至 mutation 完成) / abort，默认: warn
+    #[structopt(long, default_value = "warn")]
+    on_mutation: String, // mutation 处理方式
+    /// 源表 system.mutations 轮询间隔，默认: 30s
+    #[structopt(long, default_value = "30s", parse(try_from_str = parse_duration_str))]
+    mutation_poll_interval: Duration, // mutation 轮询间隔
+    /// 切换后 _bak 表保留策略: keep / drop(校验通过且 --yes 时删除) / ttl:<days>，默认: keep
+    #[structopt(long, default_value = "keep")]
+    bak_retention: String, // _bak 保留策略
+    /// 切换后冒烟查询使用的应用账号，留空时使用目标端 DSN 中的账号
+    #[structopt(long, default_value = "")]
+    smoke_user: String, // 冒烟查询账号
+    /// 保存 --smoke-user 密码的环境变量名
+    #[structopt(long, default_value = "")]
+    smoke_password_env: String, // 冒烟查询密码环境变量
+    /// 冒烟查询行数与迁移账号所见行数允许的相对差异，默认: 0.001
+    #[structopt(long, default_value = "0.001")]
+    smoke_tolerance: f64, // 冒烟查询容差
+    /// 切换后校验或冒烟查询失败时自动回滚切换（新表改回原名、_bak 改回源表名）
+    #[structopt(long)]
+    auto_rollback: bool, // 自动回滚
+    /// 切换完成后继续观察的时长，如 30m：定期比较 _bak 表与新表最近窗口内的行数，确认新表持续收到新行（最大时间前进），
+    /// 新表停滞或 _bak 表仍在增长时告警（日志、--alert-webhook）并记入报告 post_cutover_watch，退出码按切换后校验未通过处理；0 为不观察
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_duration_str))]
+    post_cutover_watch: Duration, // 切换后观察时长
+    /// --post-cutover-watch 的采样间隔，默认: 1m
+    #[structopt(long, default_value = "1m", parse(try_from_str = parse_duration_str))]
+    post_cutover_watch_interval: Duration, // 切换后观察采样间隔
+    /// --post-cutover-watch 期间新表最大时间超过该时长未前进即判定为停滞，默认: 5m
+    #[structopt(long, default_value = "5m", parse(try_from_str = parse_duration_str))]
+    post_cutover_stall_after: Duration, // 新表停滞判定时长
+    /// 确认执行删除类等不可逆操作
+    #[structopt(long)]
+    yes: bool, // 确认不可逆操作
+    /// SQL 审计日志文件（JSON Lines），记录发往 ClickHouse 的每条语句、端点、query_id、耗时与状态
+    #[structopt(long, default_value = "")]
+    sql_log: String, // SQL 审计日志
+    /// 按二进制处理（hex 读取、unhex 写入）的列，逗号分隔；FixedString 列与抽样含非 UTF-8 字节的 String 列自动加入
+    #[structopt(long, use_delimiter = true)]
+    binary_columns: Vec<String>, // 二进制列
+    /// 两端读取该列时使用 ifNull(col, value)（value 为 SQL 表达式，字符串带引号，如 region='unknown'），可指定多次；
+    /// 用于源表后来新增的列在两端默认值不同的情况，覆盖值同样写入目标端
+    #[structopt(long)]
+    column_default: Vec<String>, // 列默认值覆盖
+    /// 比对前对 --normalize-columns 列出的 String 列做的规范化，逗号分隔: trim（去首尾空白）,lowercase；
+    /// 两端按同一规则处理后再比对（客户端摘要与服务端摘要/校验和），写入仍为源端原值，一致只表示规范化后相等
+    #[structopt(long, use_delimiter = true)]
+    normalize: Vec<String>, // 比对规范化规则
+    /// 应用 --normalize 的列，逗号分隔，未列出的列逐字节比对
+    #[structopt(long, use_delimiter = true)]
+    normalize_columns: Vec<String>, // 规范化的列
+    /// 源端/目标端返回无法解析的行（如非法 UTF-8）时的处理: abort 分段失败 / skip 跳过并记录 / dead-letter 跳过并写入 --dead-letter-file
+    #[structopt(long, default_value = "abort")]
+    on_bad_row: String, // 坏行处理方式
+    /// --on-bad-row dead-letter 时写入原始坏行的文件
+    #[structopt(long, default_value = "")]
+    dead_letter_file: String, // 坏行死信文件
+    /// 写入因数据错误（无法解析、类型溢出等）被目标端拒绝时的处理: fail 整批失败 / dead-letter 二分拆分隔离出被拒绝的行，写入 --dead-letter-file 后继续
+    #[structopt(long, default_value = "fail")]
+    on_rejected_row: String, // 拒绝行处理方式
+    /// --on-rejected-row dead-letter 时每个失败批次最多的拆分写入次数，超出后剩余部分按写入失败处理
+    #[structopt(long, default_value = "200")]
+    bisect_max_inserts: usize, // 二分拆分写入上限
+    /// 运行报告文件名(JSON)，留空自动生成；相对路径位于 --state-dir 的运行目录下
+    #[structopt(long, default_value = "")]
+    report_file: String, // 报告文件名
+    /// 每批写入的最大字节数（如 32M），0 表示按 5000 行分批
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_size_str))]
+    batch_bytes: u64, // 批量写入字节数
+    /// 写入重试缓冲留在内存中的最大字节数（如 8M），超出部分写入临时文件，服务端确认写入成功后释放
+    #[structopt(long, default_value = "8M", parse(try_from_str = parse_size_str))]
+    insert_spill_memory: u64, // 写入重试缓冲内存上限
+    /// 单行 JSONEachRow 超过该大小（如 16M）时从批次中拿出单独写入，并上调该请求的 max_query_size，0 表示不检查
+    #[structopt(long, default_value = "16M", parse(try_from_str = parse_size_str))]
+    max_row_bytes: u64, // 单行写入阈值
+    /// 单行超过该大小（如 256M）时不写入，原样写入 --dead-letter-file 并记入报告，0 表示不限
+    #[structopt(long, default_value = "256M", parse(try_from_str = parse_size_str))]
+    max_row_hard_bytes: u64, // 单行硬上限
+    /// 分段比对的内存预算（如 4G），按 worker 均分；估算超出份额的分段拆成多次处理，0 表示不限
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_size_str))]
+    memory_budget: u64, // 内存预算
+    /// 启动时用前若干分段试跑 batch-bytes 8M/32M/128M 与并发 2/4/8（不超过 --parallelism）的组合，选出吞吐最高者继续迁移
+    #[structopt(long)]
+    calibrate: bool, // 吞吐校准
+    /// 镜像模式：同时删除目标端存在、源端已不存在的行（需 --yes，仅 --copy-mode http）
+    #[structopt(long)]
+    mirror: bool, // 镜像删除
+    /// 目标端行摘要的计算位置：client 读取完整行后在客户端计算；server 由服务端按行返回 SHA256（源端读取附带同一表达式），默认 client
+    #[structopt(long, default_value = "client")]
+    dst_digest: String, // 目标端摘要计算位置
+    /// 唯一键列，逗号分隔：镜像模式按键删除多余行（留空时删除整个分段后重写），--fix-overcopy 按键去重
+    #[structopt(long, use_delimiter = true)]
+    key_columns: Vec<String>, // 唯一键列
+    /// 关闭分段写入后的目标端 count() 核对（核对失败的分段不标记完成）
+    #[structopt(long)]
+    no_post_count: bool, // 关闭写入后行数核对
+    /// 写入后核对允许目标端比源端少的行数（源端并发写入等），默认: 0
+    #[structopt(long, default_value = "0")]
+    post_count_tolerance: u64, // 行数核对容差
+    /// 写入后核对发现目标端行数多于源端（重复写入）时，对分段所在分区执行 OPTIMIZE ... FINAL DEDUPLICATE BY --key-columns（需 --yes；键列须包含排序键）
+    #[structopt(long)]
+    fix_overcopy: bool, // 重复写入去重
+    /// 源表有删除型 TTL 时，起点早于过期边界的分段不计入比对差异（报告为 ttl-affected），镜像模式不删除时间已越过边界的目标行；
+    /// TTL 实际只在磁盘间移动数据时可设为 false，默认: true
+    #[structopt(long, default_value = "true", parse(try_from_str))]
+    respect_ttl: bool, // 按源表 TTL 处理过期行
+    /// 不做差集：按目标表分区（分区键须只由时间字段计算）把源端整个分区写入临时表，核对行数与校验和后 REPLACE PARTITION 原子替换（仅 --copy-mode http）
+    #[structopt(long)]
+    replace_partitions: bool, // 整分区替换
+    /// 分段内按该列分页读取与比对（仅 --copy-mode http），每页写入确认后记录进度，中断后从最后完成的页继续；留空不分页
+    #[structopt(long, default_value = "")]
+    page_key: String, // 分页键
+    /// 分段缺失行先写入本次运行的暂存表 <目标表>__datacp_stage，整段批次全部成功后以一条 INSERT ... SELECT 移入目标表，
+    /// 读者不会看到写了一半的分段（仅 --copy-mode http，不支持 --page-key 与 --dst-write-local）
+    #[structopt(long)]
+    staged_inserts: bool, // 分段暂存写入
+    /// --page-key 每页读取的源端行数（同键的行不拆页，实际可能略多），默认: 1000000
+    #[structopt(long, default_value = "1000000")]
+    page_rows: usize, // 每页行数
+    /// 进入切换的条件，如 "lag<30s for 3 checks"、"lag<1m and rate<100/s for 5 checks"；留空时首次无新数据即切换
+    #[structopt(long, default_value = "")]
+    cutover_when: String, // 切换条件
+    /// 源端服务器时钟到达该时间（YYYY-mm-dd HH:MM:SS，本地时区）即进入切换，可与 --cutover-when 同时使用
+    #[structopt(long, default_value = "")]
+    cutover_at: String, // 定时切换
+    /// 设置 --cutover-when / --cutover-at 时增量追平的检查间隔，默认: 30s
+    #[structopt(long, default_value = "30s", parse(try_from_str = parse_duration_str))]
+    cutover_check_interval: Duration, // 切换条件检查间隔
+    /// 两端 ClickHouse 与本机的时钟偏差（启动检查时及运行中每 5 分钟测量，已扣除往返误差）超过该值时告警，默认: 30s
+    #[structopt(long, default_value = "30s", parse(try_from_str = parse_duration_str))]
+    max_clock_skew: Duration, // 时钟偏差上限
+    /// 时钟偏差超过 --max-clock-skew 时视为错误：启动检查失败，运行中在当前分段完成后停止且不切换
+    #[structopt(long)]
+    require_synced_clocks: bool, // 要求时钟同步
+    /// 热备模式：回填后持续增量并定期重扫校验，不自动切换，直到收到切换指令（--control-file 写入 cutover、POST /cutover 到状态接口或 SIGUSR1）；
+    /// 开始 rename 之前写入 cancel 或 POST /cancel 可取消切换、回到热备
+    #[structopt(long)]
+    standby: bool, // 热备模式
+    /// 热备模式的控制文件（相对路径按 --state-dir 表目录解析），写入 cutover 或 cancel，读取后删除，默认: control
+    #[structopt(long, default_value = "control")]
+    control_file: String, // 控制文件
+    /// 热备模式每次重扫校验最近这段时间的数据，默认: 1h
+    #[structopt(long, default_value = "1h", parse(try_from_str = parse_duration_str))]
+    standby_verify_window: Duration, // 热备重扫窗口
+    /// 热备模式重扫校验并记录延迟与校验情况的间隔，默认: 5m
+    #[structopt(long, default_value = "5m", parse(try_from_str = parse_duration_str))]
+    standby_verify_interval: Duration, // 热备重扫间隔
+    /// 增量/热备循环空闲时后台重新校验最久未校验的已完成分段，如 rate=2/hour strategy=checksum（strategy 可选 count）；
+    /// 校验时间与发现的不一致记录在断点续传文件中
+    #[structopt(long)]
+    background_verify: Vec<String>, // 历史分段后台校验
+    /// --background-verify 发现分段不一致、--post-cutover-watch 发现新表停滞时 POST 告警 JSON 的地址
+    #[structopt(long, default_value = "")]
+    alert_webhook: String, // 告警 webhook
+    /// 增量循环本轮未派发分段（未设置 --cutover-when / --cutover-at 时为新分段不足 --incremental-batch-hours）时距下次检查的间隔，默认: 15s
+    #[structopt(long, default_value = "15s", parse(try_from_str = parse_duration_str))]
+    incremental_poll_interval: Duration, // 增量检查间隔
+    /// 读取因内存/时间限制（Code 241/159 等）或读取超时失败时，分段时间窗口对半拆分重试的最小窗口，默认: 1m
+    #[structopt(long, default_value = "1m", parse(try_from_str = parse_duration_str))]
+    split_min_window: Duration, // 拆分最小窗口
+    /// 单个分段最多拆分的次数，超过后按失败处理，默认: 32
+    #[structopt(long, default_value = "32")]
+    max_splits_per_segment: usize, // 单分段拆分上限
+    /// 增量循环攒够这么多个新的小时分段才派发；源端不再增长或即将满足切换条件时立即派发，默认: 1
+    #[structopt(long, default_value = "1")]
+    incremental_batch_hours: usize, // 增量攒批小时数
+    /// 监督运行：整体失败且错误可重试（连接失败、集群重启等）时在本进程内等待后重新读取断点续传记录并重启；
+    /// 表结构不一致、鉴权等错误立即退出。只支持单表迁移
+    #[structopt(long)]
+    supervise: bool, // 监督运行
+    /// --supervise 的最多重启次数，默认: 3
+    #[structopt(long, default_value = "3")]
+    max_restarts: usize, // 最多重启次数
+    /// --supervise 重启前的等待时间，默认: 5m
+    #[structopt(long, default_value = "5m", parse(try_from_str = parse_duration_str))]
+    restart_backoff: Duration, // 重启等待
+    /// 整个运行的时间预算（如 6h），超时后在分段边界停止、保存断点且不执行切换；0 表示不限
+    #[structopt(long, default_value = "0", parse(try_from_str = parse_duration_str))]
+    max_duration: Duration, // 运行时间预算
+    /// 连续这么多个分段失败（各自重试之后）即熔断：不再派发新分段，进行中的分段完成后停止、不执行切换，退出码 5；0 表示不限
+    #[structopt(long, default_value = "0")]
+    max_consecutive_failures: usize, // 连续失败上限
+    /// 时间窗口内分段失败比例上限，如 "50% over 10m"（窗口内至少 10 个分段结果才判断），超过后同样熔断；为空时不限
+    #[structopt(long, default_value = "")]
+    max_failure_rate: String, // 失败率上限
+    /// 维护窗口，期间 worker 做完当前分段后暂停、窗口结束后继续，可重复指定；格式 [星期] HH:MM-HH:MM [时区]，
+    /// 如 "01:00-03:00"、"Mon-Fri 01:00-03:00 UTC"、"Sat,Sun 22:00-06:00 +08:00"（时区默认 local）
+    #[structopt(long)]
+    blackout: Vec<String>, // 维护窗口
+    /// 维护窗口内暂停的时间不计入 --max-duration
+    #[structopt(long)]
+    pause_clock_during_blackout: bool, // 维护窗口不计时
+    /// 本地状态接口监听地址（如 127.0.0.1:9185，只写 :9185 时绑定 127.0.0.1），提供 /status、/healthz 与 /metrics；接口无认证，不要绑定公网地址；留空不启动
+    #[structopt(long, default_value = "")]
+    status_listen: String, // 状态接口地址
+    /// 超过该时长没有任何进展时 /healthz 返回 503，默认: 15m
+    #[structopt(long, default_value = "15m", parse(try_from_str = parse_duration_str))]
+    status_stall_after: Duration, // 停滞判定时长
+    /// 超过该时长没有任何分段进展时取消（必要时强制中止）进行中的分段并记为失败，运行继续而不是无限期挂住；0 表示不启用，默认: 30m
+    #[structopt(long, default_value = "30m", parse(try_from_str = parse_duration_str))]
+    global_stall_timeout: Duration, // 全局停滞超时
+    /// Prometheus pushgateway 地址（如 http://pushgw:9091），按 --push-interval 推送与 /metrics 相同的指标，结束时附带退出码；留空不推送
+    #[structopt(long, default_value = "")]
+    pushgateway_url: String, // pushgateway 地址
+    /// 推送间隔，默认: 15s
+    #[structopt(long, default_value = "15s", parse(try_from_str = parse_duration_str))]
+    push_interval: Duration, // 推送间隔
+    /// 每行一个 JSON 事件写到 stdout（run_started / phase_changed / segment_done / segment_failed / cutover_done / run_finished，
+    /// 格式版本 v=1，字段见 events.rs），供编排系统读取进度；此时人工阅读的输出全部改写到 stderr
+    #[structopt(long)]
+    events_stdout: bool, // 事件输出到 stdout
+    /// 终端全屏仪表盘（分段热力图、写入速率、各 worker 当前分段、复制延迟、最近日志）；按键 p 暂停 / r 继续 / c 热备切换 / q 结束。
+    /// stdout 不是终端、指定了 --events-stdout 或终端过小时退回普通日志
+    #[structopt(long)]
+    tui: bool, // 终端仪表盘
+    #[structopt(skip)]
+    #[serde(skip)]
+    deadline: Option<std::time::Instant>, // 由 --max-duration 计算的截止时间
+    #[structopt(skip)]
+    #[serde(skip)]
+    time_zone: Option<time_zone::ColumnTz>, // 时间字段声明的列时区，设置后分段键为 UTC
+    /// 切换前目标表副本允许的最大复制延迟，默认: 30s
+    #[structopt(long, default_value = "30s", parse(try_from_str = parse_duration_str))]
+    max_replica_lag: Duration, // 最大副本延迟
+    /// 副本延迟超限时等待重试的最长时间，默认: 10m
+    #[structopt(long, default_value = "10m", parse(try_from_str = parse_duration_str))]
+    cutover_wait: Duration, // 切换等待时间
+    /// ON CLUSTER DDL 等待所有节点完成的超时时间，默认: 5m
+    #[structopt(long, default_value = "5m", parse(try_from_str = parse_duration_str))]
+    ddl_timeout: Duration, // 分布式 DDL 超时
+    /// ON CLUSTER DDL 完成状态轮询间隔，默认: 5s
+    #[structopt(long, default_value = "5s", parse(try_from_str = parse_duration_str))]
+    ddl_poll_interval: Duration, // 分布式 DDL 轮询间隔
+}
+
+impl Opt {
+    // 目标端读取表：比对、校验、副本延迟、合并与最终 rename 均作用于该表
+    fn read_table(&self) -> &str {
+        if self.dst_read_table.is_empty() { &self.dst_table } else { &self.dst_read_table }
+    }
+
+    // 切换后新表所在的库：--cutover-into-src-db 时为源库，否则为目标库
+    fn cutover_db(&self) -> &str {
+        if self.cutover_into_src_db { &self.src_db } else { &self.dst_db }
+    }
+
+    // 时间条件中的字面量：列声明了时区时为显式 UTC 字面量
+    fn time_lit(&self, t: &str) -> String {
+        time_zone::lit(self.time_zone.as_ref(), t)
+    }
+}
+
+// 解析时长参数，支持 30s / 10m / 6h / 1d，纯数字按秒处理
+fn parse_duration_str(s: &str) -> anyhow::Result<Duration> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => (&s[..i], &s[i..]),
+        None => (s, "s"),
+    };
+    let n: u64 = num.parse().map_err(|_| anyhow::anyhow!(format!("时长格式不正确: {}", s)))?;
+    let secs = match unit {
+        "ms" => return Ok(Duration::from_millis(n)),
+        "s" => n,
+        "m" => n * 60,
+        "h" => n * 3600,
+        "d" => n * 86400,
+        _ => anyhow::bail!(format!("时长单位不支持: {}", s)),
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+// 子命令（全局参数需写在子命令之前）
+#[derive(StructOpt, Debug, Clone, serde::Serialize, serde::Deserialize)]
+enum Command {
+    /// 列出历史运行遗留的 {src_table}_bak 表（切换生成的表名，不按前缀匹配），指定 --yes 时删除
+    Cleanup,
+    /// 将源库对象的建表语句复制到目标库（改写库名与集群子句，按依赖排序）
+    Ddl {
+        /// 复制的对象类型，逗号分隔: tables,mvs,dictionaries
+        #[structopt(long, default_value = "tables")]
+        objects: String,
+        /// 只打印改写后的 DDL，不执行
+        #[structopt(long, alias = "create-dry-run")]
+        dry_run: bool,
+        /// 替换 MergeTree 表已有的表级设置，如 storage_policy=default，可指定多次；源表没有该设置时不添加
+        #[structopt(long)]
+        create_rewrite: Vec<String>,
+        /// 去掉表级 TTL 子句（ttl）或某个表级设置，可指定多次
+        #[structopt(long)]
+        create_strip: Vec<String>,
+        /// 添加或替换 MergeTree 表的表级设置，如 index_granularity=8192，可指定多次
+        #[structopt(long)]
+        create_set: Vec<String>,
+    },
+    /// 打印 --tables-file / --all-tables 选出的表（未指定时为单表）及其时间字段与已复制/剩余字节，并写入 plan.json，不执行迁移
+    Plan {
+        /// 统计时间字段每小时的行数，打印 1h/6h/1d 粒度下的行数分布、倾斜告警与分段建议
+        #[structopt(long)]
+        histogram: bool,
+        /// --histogram 建议所用的每分段目标行数
+        #[structopt(long, default_value = "1000000")]
+        target_segment_rows: u64,
+    },
+    /// 打印断点续传进度：已复制字节、按 system.parts 估算的剩余字节与预计耗时（多表时逐表），不执行迁移
+    Status,
+    /// 从 cutover.state 记录的第一个未完成步骤继续中断的切换（参数须与原运行相同）
+    ResumeCutover,
+    /// 按源表分区比对两端（迁移窗口内），结果写入 --report-file，不执行迁移
+    Verify {
+        /// 比对方式: count / checksum / parts（未再写入的历史分区按 system.parts 比对，其余回退为 checksum），默认: checksum
+        #[structopt(long, default_value = "checksum")]
+        verify_strategy: String,
+    },
+    /// 断点续传文件合并与缺口检查
+    Checkpoint {
+        #[structopt(subcommand)]
+        cmd: CheckpointCommand,
+    },
+    /// 回放 --sql-log 中的语句：打印，非 --dry-run 时重新执行其中的幂等语句
+    Replay {
+        /// 要回放的 SQL 审计日志
+        #[structopt(long)]
+        sql_log: String,
+        /// 语句类型: mutations / inserts / selects / all
+        #[structopt(long, default_value = "mutations")]
+        only: String,
+        /// 只打印，不执行
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// 常驻服务：通过本地 HTTP 接口（POST /jobs 等）接收并运行迁移任务，各任务复用两端连接池
+    Serve {
+        /// 监听地址，只写端口（如 :9200）时监听 127.0.0.1
+        #[structopt(long, default_value = "127.0.0.1:9200")]
+        listen: String,
+        /// 同时运行的任务数，其余排队
+        #[structopt(long, default_value = "2")]
+        max_concurrent_jobs: usize,
+        /// 请求须携带 Authorization: Bearer <token>；为空时不校验
+        #[structopt(long, default_value = "")]
+        token: String,
+    },
+    /// 迁移（与不带子命令相同）；--from-manifest 时按运行清单中的参数与时间范围重跑
+    Migrate {
+        /// 之前运行写入的 manifest.json，其中的参数替代命令行参数
+        #[structopt(long, default_value = "")]
+        from_manifest: String,
+        /// 当前版本或两端表结构指纹与清单不同时仍然运行（只告警）
+        #[structopt(long)]
+        allow_drift: bool,
+    },
+    /// 基准测试：在两端创建 datacp_bench_ 前缀的临时表、服务端生成数据，按 batch-bytes × 并发 × 传输格式的组合跑迁移并对比吞吐与峰值内存
+    Bench {
+        /// 生成的行数，可写作 5_000_000
+        #[structopt(long, default_value = "1_000_000")]
+        rows: String,
+        /// 列数（含 ts 与 id），其余列循环使用 UInt64/Float64/String/Nullable(String)/Array(UInt32)
+        #[structopt(long, default_value = "20")]
+        columns: usize,
+        /// String 列的宽度（字节）
+        #[structopt(long, default_value = "32")]
+        string_width: usize,
+        /// 数据分布的小时分段数
+        #[structopt(long, default_value = "24")]
+        hours: u64,
+        /// 两端 DSN（替代 --src-dsn / --dst-dsn）
+        #[structopt(long, default_value = "")]
+        dsn: String,
+        /// 目标端 DSN，与源端不同时指定
+        #[structopt(long, default_value = "")]
+        target_dsn: String,
+        /// 临时表所在的库
+        #[structopt(long, default_value = "default")]
+        db: String,
+        /// 试跑的每批写入字节数，逗号分隔（0 为按 5000 行分批）
+        #[structopt(long, default_value = "8M,32M,128M", use_delimiter = true, parse(try_from_str = parse_size_str))]
+        batch_bytes: Vec<u64>,
+        /// 试跑的并发数，逗号分隔
+        #[structopt(long, default_value = "2,4,8", use_delimiter = true)]
+        parallelism: Vec<usize>,
+        /// 试跑的传输格式，逗号分隔: JSONEachRow（--copy-mode http）/ Native（--copy-mode remote-secure）
+        #[structopt(long, default_value = "JSONEachRow", use_delimiter = true)]
+        wire_formats: Vec<String>,
+    },
+}
+
+#[derive(StructOpt, Debug, Clone, serde::Serialize, serde::Deserialize)]
+enum CheckpointCommand {
+    /// 合并多个断点续传文件（元数据须一致），不连接 ClickHouse
+    Merge {
+        /// 要合并的断点续传文件
+        files: Vec<String>,
+        /// 合并结果
+        #[structopt(short = "o", long)]
+        output: String,
+    },
+    /// 按源表当前时间范围列出 --done-segments 中缺少的分段
+    Gaps {
+        /// 缺口分段写入该文件，可作为 --only-segments-file
+        #[structopt(short = "o", long, default_value = "")]
+        output: String,
+    },
+}
+
+// 各 worker 共享的运行时上下文
+struct RunCtx {
+    dst_router: Option<shard::ShardRouter>, // --dst-write-local 分片路由
+    select_final: bool,                     // 源表 FINAL 读取
+    dst_select_final: bool,                 // 目标表 FINAL 读取
+    remote_source: Option<server_copy::RemoteSource>, // remote-secure 模式读取端
+    remote_query_timeout: Duration,
+    mutation_watch: Arc<mutations::MutationWatch>, // 源表 mutation 监控
+    optimizer: Option<Arc<optimize::Optimizer>>,    // --optimize-after
+    where_filter: String,                            // --where 追加条件，形如 " AND (pred)"
+    shard_filter: String,                            // --shard-of 追加条件，形如 " AND cityHash64(key) % n = i"
+    whole_table: std::sync::atomic::AtomicBool,      // 切换阶段按整张表补差，不再追加分片条件
+    bak_phase: std::sync::atomic::AtomicBool,        // _bak 兜底增量阶段，分段完成记录使用 bak: 前缀
+    write_gate: write_gate::WriteGate,               // 目标端只读时全局暂停写入
+    dst_read_table: String,                          // 目标端读取表
+    columns: column_plan::ColumnPlan,                // 迁移字段、比对字段与摘要字段
+    failed_segments: std::sync::Mutex<Vec<String>>,  // 读取失败的分段
+    rows_written: std::sync::atomic::AtomicU64,     // 本次运行写入目标端的行数
+    mirror: Option<mirror::Mirror>,                  // --mirror
+    pager: Option<pager::Pager>,                     // --page-key 分段内分页
+    staged: Option<staged::Stage>,                   // --staged-inserts
+    post_count: bool,                                // 分段写入后核对目标端行数
+    post_count_tolerance: u64,                       // 核对允许目标端少于源端的行数
+    overcopy: Option<overcopy::Deduplicator>,        // --fix-overcopy
+    over_copied: std::sync::Mutex<Vec<report::OverCopied>>, // 目标端行数多于源端的分段
+    ttl: Option<ttl::SourceTtl>,                     // 源表删除型 TTL（--respect-ttl）
+    ttl_affected: std::sync::Mutex<Vec<report::TtlAffected>>, // 过期边界内不计入差异的分段
+    batch_bytes: std::sync::atomic::AtomicU64,      // 每批写入字节数，0 为按行数分批；--calibrate 运行中调整
+    insert_spill_memory: u64,                        // 写入重试缓冲内存上限
+    rows_read: std::sync::atomic::AtomicU64,        // 已读取的源端行数
+    insert_errors: std::sync::atomic::AtomicU64,    // 写入失败的批次数
+    segment_timings: std::sync::Mutex<Vec<report::SegmentTiming>>, // 各分段分阶段耗时
+    segment_bytes: std::sync::Mutex<Vec<report::SegmentBytes>>,   // 各分段传输字节
+    worker_walls: std::sync::Mutex<Vec<(usize, Duration)>>,        // 各 worker 运行时长
+    mirror_deletes: std::sync::Mutex<Vec<report::MirrorDelete>>, // 各分段镜像删除结果
+    bad_rows: bad_rows::BadRows,                                 // --on-bad-row
+    deadline: deadline::Deadline,                                // --max-duration
+    breaker: Arc<work_queue::CircuitBreaker>,                    // 本次运行的分段熔断
+    memory: Option<Arc<memory::Budget>>,                         // --memory-budget 额度，未设置时为 None
+    binary: binary::BinaryColumns,                               // hex 读写的二进制列
+    server_digest: Option<server_digest::ServerDigest>,          // --dst-digest server
+    normalize: normalize::Normalize,                             // --normalize 比对前规范化
+    bad_row_segments: std::sync::Mutex<Vec<report::BadRowSegment>>, // 各分段跳过的坏行
+    oversized: oversized::Oversized,                             // --max-row-bytes
+    rejected: rejected::Rejected,                                // --on-rejected-row
+    insert_permits: Arc<tokio::sync::Semaphore>,     // 全局写入并发（多表共享）
+    split_min_window: Duration,                      // 读取超限时拆分窗口的下限
+    max_splits_per_segment: usize,                   // 单分段拆分次数上限
+    time_zone: Option<time_zone::ColumnTz>,          // 时间字段的列时区
+}
+
+impl RunCtx {
+    // 源端与目标端读取、计数与校验追加的条件（--where 与 --shard-of）
+    fn filter(&self) -> String {
+        if self.whole_table.load(std::sync::atomic::Ordering::Relaxed) {
+            self.where_filter.clone()
+        } else {
+            format!("{}{}", self.where_filter, self.shard_filter)
+        }
+    }
+
+    // 断点续传文件中的分段完成记录（_bak 阶段带前缀）
+    fn done_key(&self, seg: &str) -> String {
+        if self.bak_phase.load(std::sync::atomic::Ordering::Relaxed) {
+            format!("{}{}", phase_checkpoint::BAK_PREFIX, seg)
+        } else {
+            seg.to_string()
+        }
+    }
+
+    // 时间条件中的字面量，见 Opt::time_lit
+    fn time_lit(&self, t: &str) -> String {
+        time_zone::lit(self.time_zone.as_ref(), t)
+    }
+
+    // 分段失败：记入失败列表（报告与退出码），并发出 segment_failed 事件
+    fn segment_failed(&self, seg: &str, error: &str) {
+        self.failed_segments.lock().unwrap().push(seg.to_string());
+        events::segment_failed(seg, error);
+        self.breaker.failed(seg, error);
+    }
+}
+
+// 生成的语句一律写明库名（db.table），URL 上的 database 参数只作默认上下文；已带库名的原样返回
+fn qualified(db: &str, table: &str) -> String {
+    if let Some(q) = src_query::subquery(table) {
+        return q;
+    }
+    if db.is_empty() || table.contains('.') { table.to_string() } else { format!("{}.{}", db, table) }
+}
+
+// 带库名的表引用，按需追加 FINAL
+fn table_ref(db: &str, table: &str, final_: bool) -> String {
+    let table = qualified(db, table);
+    if final_ { format!("{} FINAL", table) } else { table }
+}
+
+// --where 谓词转为追加到 WHERE 之后的条件
+fn filter_sql(filter: &str) -> String {
+    if filter.trim().is_empty() { String::new() } else { format!(" AND ({})", filter.trim()) }
+}
+
+// --where 与 --shard-of 的分片条件
+fn row_filter(opt: &Opt) -> String {
+    format!("{}{}", filter_sql(&opt.filter), shard_of::filter_sql(opt))
+}
+
+// JSONEachRow 中的 UInt64 可能被引号包裹（output_format_json_quote_64bit_integers），统一解析为 u64
+fn json_u64(v: Option<&Value>) -> u64 {
+    match v {
+        Some(Value::Number(n)) => n.as_u64().unwrap_or(0),
+        Some(Value::String(s)) => s.parse().unwrap_or(0),
+        _ => 0,
+    }
 }
 
 fn is_ignored_field(name: &str, ignore_fields: &[String]) -> bool {
@@ -96,9 +890,42 @@ async fn compare_table_columns_http(
     Ok(())
 }
 
-// migrate_segment_worker: 处理分段迁移、断点续传、批量写入、详细日志（HTTP 方案）
+// --ignore-compare-field 的字段不参与比对但仍要写入，源表与目标表都必须存在
+async fn check_compare_ignored_fields(opt: &Opt) -> anyhow::Result<()> {
+    if opt.ignore_compare_field.is_empty() {
+        return Ok(());
+    }
+    let src_cols = get_column_names_http(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    let mut dst_tables = vec![opt.read_table()];
+    if opt.read_table() != opt.dst_table {
+        dst_tables.push(&opt.dst_table);
+    }
+    let mut dst_cols = Vec::new();
+    for t in dst_tables {
+        dst_cols.push((t, get_column_names_http(&opt.dst_dsn, &opt.dst_db, t).await?));
+    }
+    for f in &opt.ignore_compare_field {
+        if is_ignored_field(f, &opt.ignore_field) {
+            anyhow::bail!(format!("字段 {} 同时出现在 --ignore-field 与 --ignore-compare-field", f));
+        }
+        if *f == opt.time_field || *f == opt.page_key || opt.key_columns.contains(f) {
+            anyhow::bail!(format!("字段 {} 是时间字段、--page-key 或 --key-columns，不能在比对时忽略", f));
+        }
+        if !src_cols.contains(f) {
+            anyhow::bail!(format!("--ignore-compare-field 中的 {} 不存在于源表 {}", f, opt.src_table));
+        }
+        for (t, cols) in &dst_cols {
+            if !cols.contains(f) {
+                anyhow::bail!(format!("--ignore-compare-field 中的 {} 不存在于目标表 {}，比对时忽略的字段仍需写入目标端", f, t));
+            }
+        }
+    }
+    Ok(())
+}
+
+// migrate_segment_worker: 从队列领取分段，处理分段迁移、断点续传、批量写入、详细日志（HTTP 方案）
 async fn migrate_segment_worker_http(
-    segments: Vec<String>,
+    queue: Arc<work_queue::SegmentQueue>,
     src_dsn: String,
     dst_dsn: String,
     src_db: String,
@@ -111,103 +938,607 @@ async fn migrate_segment_worker_http(
     ignore_fields: Vec<String>,
     done_segments_file: String,
     log_file_path: String,
-    client: Arc<reqwest::Client>, // 新增参数
+    client: Arc<endpoint::Clients>, // 新增参数
+    ctx: Arc<RunCtx>,
+    worker: usize,
 ) {
-    for seg in segments {
+    // 运行时长只统计处理分段的时间，不含在队列上等待新分段的时间
+    let mut busy = Duration::ZERO;
+    let mut picked: Option<std::time::Instant> = None;
+    'segments: loop {
+        if let Some(t) = picked.take() {
+            busy += t.elapsed();
+            status::segment_finished(worker);
+        }
+        let Some((seg, taken)) = queue.next().await else { break };
+        picked = Some(std::time::Instant::now());
+        status::segment_started(worker, &seg);
+        watchdog::arm(worker);
+        // 中止后仍需取空队列，等待方才能返回
+        if ctx.mutation_watch.aborted() {
+            error!("segment {seg} skipped: 源表出现 mutation，迁移中止");
+            continue;
+        }
+        // 维护窗口内等待窗口结束（暂停期间可能超过 --max-duration，之后再检查）
+        blackout::wait().await;
+        status::wait_if_held().await;
+        if ctx.deadline.reached() {
+            warn!("segment {seg} skipped: 已超过 --max-duration");
+            continue;
+        }
+        ctx.mutation_watch.wait_if_paused().await;
         info!("segment {seg} start");
-        let seg_end = chrono::NaiveDateTime::parse_from_str(&seg, "%Y-%m-%d %H:%M:%S").unwrap() + chrono::Duration::hours(1);
-        let seg_end_str = seg_end.format("%Y-%m-%d %H:%M:%S").to_string();
-        let q = format!("SELECT {} FROM {} WHERE {} >= '{}' AND {} < '{}' FORMAT JSONEachRow", col_names.join(","), src_table, time_field, seg, time_field, seg_end_str);
-        info!("segment {seg} src SQL: {q}");
-        let src_rows = match ch_query_rows_with_client(&src_dsn, &src_db, &q, client.clone()).await {
-            Ok(b) => b,
-            Err(e) => { error!("segment {seg} failed: {e}"); continue; }
-        };
-        let q_dst = format!("SELECT {} FROM {} WHERE {} >= '{}' AND {} < '{}' FORMAT JSONEachRow", col_names.join(","), dst_table, time_field, seg, time_field, seg_end_str);
-        info!("segment {seg} dst SQL: {q_dst}");
-        let dst_rows = match ch_query_rows_with_client(&dst_dsn, &dst_db, &q_dst, client.clone()).await {
-            Ok(b) => b,
-            Err(e) => { error!("segment {seg} dst failed: {e}"); continue; }
-        };
-        let dst_row_set: HashSet<String> = dst_rows.iter().map(|r| {
-            let mut norm = serde_json::Map::new();
-            for col in &sorted_col_names {
-                let v = r.get(col).cloned().unwrap_or(Value::Null);
-                norm.insert(col.clone(), v);
-            }
-            let b = serde_json::to_vec(&norm).unwrap();
-            let mut hasher = Sha256::new();
-            hasher.update(&b);
-            format!("{:x}", hasher.finalize())
-        }).collect();
-        let mut need_insert = Vec::new();
-        for row in src_rows.iter() {
-            let mut norm = serde_json::Map::new();
-            for col in &sorted_col_names {
-                let v = row.get(col).cloned().unwrap_or(Value::Null);
-                norm.insert(col.clone(), v);
-            }
-            let b = serde_json::to_vec(&norm).unwrap();
-            let mut hasher = Sha256::new();
-            hasher.update(&b);
-            let key = format!("{:x}", hasher.finalize());
-            if !dst_row_set.contains(&key) {
-                need_insert.push(row.clone());
+        ctx.oversized.reset(&seg);
+        ctx.rejected.reset(&seg);
+        transfer::start();
+        let mut timer = timing::SegmentTimer::new(&ctx.segment_timings, &seg, worker);
+        // 分段名为起点或 "起..止"（见 segment）
+        let (seg_start, seg_end_str) = segment::bounds(&seg);
+        if let Some(remote) = &ctx.remote_source {
+            match server_copy::copy_segment_remote(
+                remote, ctx.remote_query_timeout, &src_dsn, &src_db, &src_table, &dst_dsn, &dst_db, &dst_table, &ctx.dst_read_table,
+                &time_field, &col_names, &seg_start, &seg_end_str, &ctx.filter(), ctx.time_zone.as_ref(),
+            ).await {
+                Ok(written) => {
+                    timer.lap(timing::Phase::Insert);
+                    ctx.rows_written.fetch_add(written, std::sync::atomic::Ordering::Relaxed);
+                    if let Err(e) = taken.complete(|| save_done_segment(&done_segments_file, &ctx.done_key(&segment::record(&seg)))) {
+                        error!("save_done_segment failed: {e}");
+                    }
+                    events::segment_done(&seg, written, picked.map(|t| t.elapsed().as_millis()).unwrap_or(0));
+                    ctx.breaker.succeeded();
+                    if let Some(o) = &ctx.optimizer {
+                        o.segment_done(&seg_start, &seg_end_str);
+                    }
+                }
+                Err(e) => {
+                    timer.lap(timing::Phase::Insert);
+                    error!("segment {seg} failed: {e}");
+                    ctx.segment_failed(&seg, &e.to_string());
+                }
+            }
+            continue;
+        }
+        // --page-key 时按键分页读取与比对，每页写入全部确认后推进页标记，中断后从标记处继续
+        let mut page_after = ctx.pager.as_ref().and_then(|p| p.resume_from(&seg));
+        if let Some(k) = &page_after {
+            info!("segment {seg} resume after page key {}", k);
+        }
+        let resumed = page_after.is_some();
+        // --staged-inserts：先清理本分段上次失败留下的暂存行
+        if let Some(s) = &ctx.staged {
+            if let Err(e) = s.prepare(&seg).await {
+                error!("segment {seg} failed: {e}");
+                ctx.segment_failed(&seg, &e.to_string());
+                continue;
+            }
+        }
+        // --memory-budget：按两端行数估算占用，超过每个 worker 的份额时按 cityHash64(时间字段) 拆成多次处理（分页时由页大小控制）
+        let (mut passes, mut pass, mut pass_bytes) = (1, 0, 0);
+        if let (Some(budget), None) = (&ctx.memory, &ctx.pager) {
+            let src_count = segment_count(&src_dsn, &src_db, &table_ref(&src_db, &src_table, ctx.select_final), &time_field, &seg_start, &seg_end_str, &ctx.filter(), client.clone(), ctx.time_zone.as_ref()).await;
+            let dst_count = segment_count(&dst_dsn, &dst_db, &table_ref(&dst_db, &ctx.dst_read_table, ctx.dst_select_final), &time_field, &seg_start, &seg_end_str, &ctx.filter(), client.clone(), ctx.time_zone.as_ref()).await;
+            match (src_count, dst_count) {
+                (Ok(s), Ok(d)) => {
+                    let est = memory::Estimate::new(s, d);
+                    passes = budget.passes(&est);
+                    pass_bytes = est.total() / passes;
+                    if passes > 1 {
+                        warn!(
+                            "segment {seg} memory budget: estimated {} exceeds worker share {} MiB (largest: {}), split into {} passes",
+                            est.describe(), budget.share() >> 20, est.largest(), passes
+                        );
+                    }
+                }
+                (Err(e), _) | (_, Err(e)) => warn!("segment {seg} memory estimate failed, processing in one pass: {e}"),
             }
         }
-        let mut rows_written = 0;
-        if !need_insert.is_empty() {
-            for batch in need_insert.chunks(5000) { // 优化：批量写入粒度提升
-                let json_rows: Vec<String> = batch.iter().map(|row| serde_json::to_string(row).unwrap()).collect();
-                let data = json_rows.join("\n");
-                if let Err(e) = insert_rows_http_with_client(&dst_dsn, &dst_db, &dst_table, data, client.clone()).await {
+        let (mut src_total, mut rows_written, mut marks_ok, mut insert_failed) = (0, 0, true, false);
+        // 读取因内存/时间限制失败时把时间窗口对半拆分，待处理窗口后进先出，全部完成后才记录原分段
+        let mut windows = vec![(seg_start.clone(), seg_end_str.clone())];
+        let mut splits = 0;
+        // 当前窗口开始前的源端行数：窗口在第 pass > 0 次处理时拆分，拆出的窗口从第 0 次重新读取，已计入的各次需退回
+        // （已写入的行在重新读取时与目标端一致，不会再次写入，rows_written 不退回）
+        let mut window_src_total = 0;
+        loop {
+            let (win_lo, win_hi) = windows.last().cloned().unwrap();
+            if pass == 0 {
+                window_src_total = src_total;
+            }
+            let _mem = match &ctx.memory {
+                Some(b) => b.reserve(pass_bytes).await,
+                None => None,
+            };
+            let filter = if passes > 1 { format!("{} AND cityHash64({}) % {} = {}", ctx.filter(), time_field, passes, pass) } else { ctx.filter() };
+            let (lower, src_tail) = match &ctx.pager {
+                Some(p) => match p.lower(page_after.as_ref()) {
+                    Ok(l) => (l, p.src_tail()),
+                    Err(e) => { error!("segment {seg} failed: {e}"); ctx.segment_failed(&seg, &e.to_string()); continue 'segments; }
+                },
+                None => (String::new(), String::new()),
+            };
+            // --dst-digest server：源端附带同一摘要表达式，目标端只读取摘要
+            let (src_select, dst_select) = match &ctx.server_digest {
+                Some(d) => (format!("{},{}", ctx.binary.select_list(&col_names), d.select()), d.select()),
+                None => (ctx.binary.select_list(&col_names), ctx.binary.select_list(&ctx.columns.compare)),
+            };
+            let q = format!("SELECT {} FROM {} WHERE {} >= {} AND {} < {}{}{}{} FORMAT JSONEachRow", src_select, table_ref(&src_db, &src_table, ctx.select_final), time_field, ctx.time_lit(&win_lo), time_field, ctx.time_lit(&win_hi), filter, lower, src_tail);
+            info!("segment {seg} src SQL: {q}");
+            let src_res = watchdog::guard(worker, ch_query_rows_with_client(&src_dsn, &src_db, &q, client.clone(), ctx.bad_rows.abort)).await;
+            timer.lap(timing::Phase::ReadSrc);
+            let mut src_rows = match src_res {
+                Ok((b, bad)) => { skip_bad_rows(&ctx, &done_segments_file, &seg, "src", &bad); b }
+                Err(e) if split_window(&ctx, &seg, &mut windows, (&win_lo, &win_hi), &mut splits, &e) => { (pass, src_total) = (0, window_src_total); continue; }
+                Err(e) => { error!("segment {seg} failed: {e}"); ctx.segment_failed(&seg, &e.to_string()); continue 'segments; }
+            };
+            let page_end = ctx.pager.as_ref().and_then(|p| p.page_end(&src_rows));
+            let upper = match &ctx.pager {
+                Some(p) => match p.upper(page_end.as_ref()) {
+                    Ok(u) => u,
+                    Err(e) => { error!("segment {seg} failed: {e}"); ctx.segment_failed(&seg, &e.to_string()); continue 'segments; }
+                },
+                None => String::new(),
+            };
+            let q_dst = format!("SELECT {} FROM {} WHERE {} >= {} AND {} < {}{}{}{} FORMAT JSONEachRow", dst_select, table_ref(&dst_db, &ctx.dst_read_table, ctx.dst_select_final), time_field, ctx.time_lit(&win_lo), time_field, ctx.time_lit(&win_hi), filter, lower, upper);
+            info!("segment {seg} dst SQL: {q_dst}");
+            let dst_res = watchdog::guard(worker, ch_query_rows_with_client(&dst_dsn, &dst_db, &q_dst, client.clone(), ctx.bad_rows.abort)).await;
+            timer.lap(timing::Phase::ReadDst);
+            let mut dst_rows = match dst_res {
+                Ok((b, bad)) => { skip_bad_rows(&ctx, &done_segments_file, &seg, "dst", &bad); b }
+                Err(e) if split_window(&ctx, &seg, &mut windows, (&win_lo, &win_hi), &mut splits, &e) => { (pass, src_total) = (0, window_src_total); continue; }
+                Err(e) => { error!("segment {seg} dst failed: {e}"); ctx.segment_failed(&seg, &e.to_string()); continue 'segments; }
+            };
+            if let Some(b) = &ctx.memory {
+                b.check(&seg, pass, src_rows.len(), dst_rows.len());
+            }
+            // --dst-digest server：取出两端由服务端算出的摘要（并从行中移除）
+            let server_keys = if ctx.server_digest.is_some() {
+                match server_digest::take(&mut src_rows).and_then(|s| Ok((s, server_digest::take(&mut dst_rows)?))) {
+                    Ok(k) => Some(k),
+                    Err(e) => { error!("segment {seg} failed: {e}"); ctx.segment_failed(&seg, &e.to_string()); continue 'segments; }
+                }
+            } else {
+                None
+            };
+            let dst_keys: Vec<[u8; 32]> = match &server_keys {
+                Some((_, d)) => d.clone(),
+                None => dst_rows.iter().map(|r| ctx.normalize.digest(r, &sorted_col_names)).collect(),
+            };
+            let dst_row_set: HashSet<[u8; 32]> = dst_keys.iter().cloned().collect();
+            let mut src_digests = Vec::new();
+            let mut need_insert = Vec::new();
+            for (i, row) in src_rows.iter().enumerate() {
+                let key = match &server_keys {
+                    Some((s, _)) => s[i],
+                    None => ctx.normalize.digest(row, &sorted_col_names),
+                };
+                if !dst_row_set.contains(&key) {
+                    need_insert.push(row.clone());
+                }
+                if ctx.mirror.is_some() {
+                    src_digests.push(key);
+                }
+            }
+            timer.lap(timing::Phase::Diff);
+            // 镜像模式：先删除目标端多余行，再补写
+            if let Some(m) = &ctx.mirror {
+                let src_row_set: HashSet<&[u8; 32]> = src_digests.iter().collect();
+                let mut extra: Vec<&HashMap<String, Value>> = dst_rows.iter().zip(&dst_keys).filter(|(_, k)| !src_row_set.contains(*k)).map(|(r, _)| r).collect();
+                // 源表 TTL：时间已越过过期边界的多余行是源端过期所致，保留；整段重写会连同这些行一起删除，此时本窗口不做镜像删除
+                if let Some(t) = ctx.ttl.as_ref().filter(|t| t.affects(&win_lo)) {
+                    let kept = extra.iter().filter(|r| t.crossed(r, &time_field)).count();
+                    if kept > 0 {
+                        warn!(
+                            "segment {seg} mirror: {} extra rows crossed source TTL boundary {}, not deleted{}",
+                            kept, t.boundary_str(), if m.rewrites() { " (window rewrite skipped)" } else { "" }
+                        );
+                        extra.retain(|r| !m.rewrites() && !t.crossed(r, &time_field));
+                        ctx.ttl_affected.lock().unwrap().push(report::TtlAffected {
+                            segment: seg.clone(),
+                            boundary: t.boundary_str(),
+                            check: "mirror".to_string(),
+                            src_rows: src_rows.len() as u64,
+                            dst_rows: dst_rows.len() as u64,
+                        });
+                    }
+                }
+                if !extra.is_empty() {
+                    let window = format!("{} >= {} AND {} < {}{}{}{}", time_field, ctx.time_lit(&win_lo), time_field, ctx.time_lit(&win_hi), filter, lower, upper);
+                    let (deleted, method) = match m.remove_extra(&ctx, &window, &extra, dst_rows.len()).await {
+                        Ok(mirror::MirrorAction::KeyDelete(n)) => {
+                            // 与被删除行同键、原本已一致（不在补写列表中）的行也一并被删，需重新写入
+                            let keys: HashSet<String> = extra.iter().filter_map(|r| m.key_of(r)).collect();
+                            for (row, digest) in src_rows.iter().zip(&src_digests) {
+                                if dst_row_set.contains(digest) && m.key_of(row).map(|k| keys.contains(&k)).unwrap_or(false) {
+                                    need_insert.push(row.clone());
+                                }
+                            }
+                            (n, "key-delete")
+                        }
+                        Ok(mirror::MirrorAction::Rewrite(n)) => {
+                            need_insert = src_rows.clone();
+                            (n, "rewrite")
+                        }
+                        Ok(mirror::MirrorAction::DryRun) => (0, "dry-run"),
+                        Err(e) => {
+                            error!("segment {seg} mirror delete failed: {e}");
+                            ctx.segment_failed(&seg, &e.to_string());
+                            continue 'segments;
+                        }
+                    };
+                    info!("segment {seg} mirror: extra_rows={}, deleted={}, method={}", extra.len(), deleted, method);
+                    if deleted > 0 {
+                        if let Err(e) = save_done_segment(&done_segments_file, &format!("{}{}\t{}", mirror::MIRRORED_PREFIX, seg, deleted)) {
+                            error!("save_done_segment failed: {e}");
+                        }
+                    }
+                    ctx.mirror_deletes.lock().unwrap().push(report::MirrorDelete {
+                        segment: seg.clone(),
+                        extra_rows: extra.len() as u64,
+                        deleted_rows: deleted,
+                        method: method.to_string(),
+                    });
+                }
+            }
+            let mut page_failed = false;
+            if let Some(router) = &ctx.dst_router {
+                // 按分片分组后直写本地表，无法计算分片的行回退到 Distributed 表
+                let mut per_shard: Vec<Vec<HashMap<String, Value>>> = vec![Vec::new(); router.shards.len()];
+                let mut fallback = Vec::new();
+                for row in need_insert {
+                    match router.shard_for_row(&row) {
+                        Some(i) => per_shard[i].push(row),
+                        None => fallback.push(row),
+                    }
+                }
+                for (i, rows) in per_shard.iter().enumerate() {
+                    let ep = &router.shards[i];
+                    let (n, errors) = insert_rows_batched(&ctx, &seg, &ep.dsn, &router.local_db, &router.local_table, rows, client.clone()).await;
+                    for e in errors {
+                        error!("segment {seg} shard {} batch insert failed: {e}", ep.shard_num);
+                        ctx.insert_errors.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        page_failed = true;
+                    }
+                    rows_written += n;
+                }
+                if !fallback.is_empty() {
+                    warn!("segment {seg} {} 行无法在客户端计算分片，回退写入 Distributed 表 {}", fallback.len(), dst_table);
+                }
+                need_insert = fallback;
+            }
+            if !need_insert.is_empty() {
+                // --staged-inserts：写入暂存表，整段完成后再移入；暂存表创建失败按批次写入失败处理
+                let staging = match &ctx.staged {
+                    Some(s) => s.staging(&seg).await,
+                    None => Ok(None),
+                };
+                let (n, errors) = match &staging {
+                    Ok(table) => insert_rows_batched(&ctx, &seg, &dst_dsn, &dst_db, table.as_deref().unwrap_or(&dst_table), &need_insert, client.clone()).await,
+                    Err(e) => (0, vec![anyhow::anyhow!(format!("创建暂存表失败: {e}"))]),
+                };
+                for e in errors {
                     error!("segment {seg} batch insert failed: {e}");
+                    ctx.insert_errors.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    page_failed = true;
+                }
+                rows_written += n;
+            }
+            timer.lap(timing::Phase::Insert);
+            insert_failed |= page_failed;
+            src_total += src_rows.len();
+            if pass + 1 < passes {
+                pass += 1;
+                continue;
+            }
+            let (Some(p), Some(k)) = (&ctx.pager, page_end) else {
+                // 当前窗口完成，继续处理拆分出的其余窗口
+                windows.pop();
+                if windows.is_empty() {
+                    break;
+                }
+                pass = 0;
+                continue;
+            };
+            // 有写入失败的批次后不再推进页标记，下次从最后确认的页重做
+            marks_ok &= !page_failed;
+            if marks_ok {
+                if let Err(e) = p.advance(&seg, &k) {
+                    error!("segment {seg} save page mark failed: {e}");
+                }
+            }
+            info!("segment {seg} page done, last_key={}, src_rows={}", k, src_total);
+            page_after = Some(k);
+        }
+        if let Some(s) = &ctx.staged {
+            let promoted = s.promote(&ctx, &done_segments_file, &seg, !insert_failed).await;
+            timer.lap(timing::Phase::Insert);
+            if let Err(e) = promoted {
+                error!("segment {seg} failed: {e}");
+                ctx.segment_failed(&seg, &e.to_string());
+                continue;
+            }
+        }
+        // 有批次写入失败时不标记完成、保留页标记，留给重试：续传时 src_total 只含本次读取的页，写入后的行数核对发现不了缺失
+        if insert_failed {
+            ctx.rows_written.fetch_add(rows_written as u64, std::sync::atomic::Ordering::Relaxed);
+            error!("segment {seg} failed: 部分批次写入失败，不标记完成（已写入 {} 行）", rows_written);
+            ctx.segment_failed(&seg, "部分批次写入失败");
+            continue;
+        }
+        info!("segment {seg} end, src_rows={}, inserted={}", src_total, rows_written);
+        ctx.rows_read.fetch_add(src_total as u64, std::sync::atomic::Ordering::Relaxed);
+        ctx.rows_written.fetch_add(rows_written as u64, std::sync::atomic::Ordering::Relaxed);
+        // 写入后核对目标端分段行数，少于源端（超出容差）时不标记完成，留给重试；多于源端时记为重复写入
+        if ctx.post_count {
+            let res = segment_count(&dst_dsn, &dst_db, &table_ref(&dst_db, &ctx.dst_read_table, ctx.dst_select_final), &time_field, &seg_start, &seg_end_str, &ctx.filter(), client.clone(), ctx.time_zone.as_ref()).await;
+            timer.lap(timing::Phase::ReadDst);
+            match res {
+                // 源表 TTL：起点早于过期边界的分段，两端差异可能来自源端过期，记为 ttl-affected，不计入差异
+                Ok(dst_count) if dst_count != src_total as u64 && ctx.ttl.as_ref().map(|t| t.affects(&seg)).unwrap_or(false) => {
+                    let boundary = ctx.ttl.as_ref().map(|t| t.boundary_str()).unwrap_or_default();
+                    warn!("segment {seg} post-count differs before source TTL boundary {}: src_rows={}, dst_rows={}, ttl-affected", boundary, src_total, dst_count);
+                    ctx.ttl_affected.lock().unwrap().push(report::TtlAffected {
+                        segment: seg.clone(),
+                        boundary,
+                        check: "post-count".to_string(),
+                        src_rows: src_total as u64,
+                        dst_rows: dst_count,
+                    });
+                }
+                Ok(dst_count) => {
+                    // 写入死信文件的超大行与被拒绝的行不会出现在目标端
+                    let dead_lettered = ctx.oversized.dead_lettered(&seg) + ctx.rejected.dead_lettered(&seg);
+                    if dst_count + ctx.post_count_tolerance + dead_lettered < src_total as u64 {
+                        error!("segment {seg} post-count mismatch: src_rows={}, dst_rows={}，不标记完成", src_total, dst_count);
+                        ctx.segment_failed(&seg, &format!("post-count mismatch: src_rows={}, dst_rows={}", src_total, dst_count));
+                        continue;
+                    }
+                    if dst_count > src_total as u64 {
+                        // 从页标记续传时 src_total 只含本次读取的页，以源端整段 count() 为准
+                        let src_count = if resumed {
+                            match segment_count(&src_dsn, &src_db, &table_ref(&src_db, &src_table, ctx.select_final), &time_field, &seg_start, &seg_end_str, &ctx.filter(), client.clone(), ctx.time_zone.as_ref()).await {
+                                Ok(c) => c,
+                                Err(e) => {
+                                    warn!("segment {seg} src count failed, over-copy check skipped: {e}");
+                                    dst_count
+                                }
+                            }
+                        } else {
+                            src_total as u64
+                        };
+                        if dst_count > src_count {
+                            check_overcopy(&ctx, &dst_dsn, &dst_db, &time_field, &seg_start, &seg_end_str, src_count, dst_count, client.clone()).await;
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("segment {seg} post-count failed: {e}");
+                    ctx.segment_failed(&seg, &e.to_string());
                     continue;
                 }
-                rows_written += batch.len();
             }
         }
-        info!("segment {seg} end, src_rows={}, inserted={}", src_rows.len(), rows_written);
-        if let Err(e) = save_done_segment(&done_segments_file, &seg) {
+        let rejected = ctx.rejected.dead_lettered(&seg);
+        if rejected > 0 {
+            if let Err(e) = save_done_segment(&done_segments_file, &format!("{}{}\t{}", rejected::REJECTED_PREFIX, seg, rejected)) {
+                error!("save_done_segment failed: {e}");
+            }
+        }
+        match transfer::finish(&done_segments_file, &ctx.done_key(&seg)) {
+            Ok(b) => {
+                status::segment_bytes(&b);
+                ctx.segment_bytes.lock().unwrap().push(b)
+            }
+            Err(e) => error!("save_done_segment failed: {e}"),
+        }
+        if let Err(e) = taken.complete(|| save_done_segment(&done_segments_file, &ctx.done_key(&segment::record(&seg)))) {
             error!("save_done_segment failed: {e}");
         }
+        events::segment_done(&seg, rows_written as u64, picked.map(|t| t.elapsed().as_millis()).unwrap_or(0));
+        ctx.breaker.succeeded();
+        if let Some(p) = &ctx.pager {
+            if let Err(e) = p.clear(&seg) {
+                error!("segment {seg} clear page mark failed: {e}");
+            }
+        }
+        if let (Some(o), true) = (&ctx.optimizer, rows_written > 0) {
+            o.segment_done(&seg_start, &seg_end_str);
+        }
+    }
+    ctx.worker_walls.lock().unwrap().push((worker, busy));
+}
+
+// 分段 [seg, seg_end) 的行数
+#[allow(clippy::too_many_arguments)]
+async fn segment_count(
+    dsn: &str,
+    db: &str,
+    table: &str,
+    time_field: &str,
+    seg: &str,
+    seg_end: &str,
+    filter: &str,
+    client: Arc<endpoint::Clients>,
+    tz: Option<&time_zone::ColumnTz>,
+) -> anyhow::Result<u64> {
+    let q = format!(
+        "SELECT count() AS c FROM {} WHERE {} >= {} AND {} < {}{} FORMAT JSONEachRow",
+        table, time_field, time_zone::lit(tz, seg), time_field, time_zone::lit(tz, seg_end), filter
+    );
+    let (rows, _) = ch_query_rows_with_client(dsn, db, &q, client, true).await?;
+    Ok(json_u64(rows.first().and_then(|r| r.get("c"))))
+}
+
+// 读取失败是否值得缩小窗口重试：查询自身的内存/时间/读取量限制，或读取超时
+fn is_split_error(e: &anyhow::Error) -> bool {
+    let text = e.to_string();
+    classify_ch_error(&text) == ChErrorClass::SplitSegment
+        || e.downcast_ref::<reqwest::Error>().map(|r| r.is_timeout()).unwrap_or(false)
+        || text.contains("timed out")
+}
+
+// 把失败的当前窗口 [lo, hi)（列表末尾）替换为对半拆分的两个窗口（前一半先处理）；分页模式、窗口已到 --split-min-window
+// 或本分段拆分次数已达 --max-splits-per-segment 时不拆分，返回 false 由调用方按失败处理
+fn split_window(ctx: &RunCtx, seg: &str, windows: &mut Vec<(String, String)>, win: (&str, &str), splits: &mut usize, e: &anyhow::Error) -> bool {
+    if ctx.pager.is_some() || !is_split_error(e) {
+        return false;
+    }
+    let parse = |s: &str| chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+    let (lo, hi) = (parse(win.0), parse(win.1));
+    let half = (hi - lo) / 2;
+    if half.to_std().map(|h| h < ctx.split_min_window).unwrap_or(true) {
+        warn!("segment {seg} window [{}, {}) reached --split-min-window {:?}, not split further", win.0, win.1, ctx.split_min_window);
+        return false;
+    }
+    if *splits >= ctx.max_splits_per_segment {
+        warn!("segment {seg} reached --max-splits-per-segment {}, not split further", ctx.max_splits_per_segment);
+        return false;
+    }
+    *splits += 1;
+    windows.pop();
+    let mid = (lo + half).format("%Y-%m-%d %H:%M:%S").to_string();
+    warn!("segment {seg} window [{}, {}) split at {} (split {}/{}): {e}", win.0, win.1, mid, splits, ctx.max_splits_per_segment);
+    windows.push((mid.clone(), win.1.to_string()));
+    windows.push((win.0.to_string(), mid));
+    true
+}
+
+// 目标端行数多于源端：记录到报告，--fix-overcopy 时按键去重后重新核对
+#[allow(clippy::too_many_arguments)]
+async fn check_overcopy(
+    ctx: &RunCtx,
+    dst_dsn: &str,
+    dst_db: &str,
+    time_field: &str,
+    seg: &str,
+    seg_end: &str,
+    src_rows: u64,
+    dst_rows: u64,
+    client: Arc<endpoint::Clients>,
+) {
+    warn!("segment {seg} over-copied: src_rows={}, dst_rows={}, excess={}", src_rows, dst_rows, dst_rows - src_rows);
+    let (fix, dst_rows_after) = match &ctx.overcopy {
+        None => (None, None),
+        Some(d) if !d.apply => (Some("dry-run".to_string()), None),
+        Some(d) => match d.dedupe(seg, seg_end).await {
+            Ok(fix) => {
+                let table = table_ref(dst_db, &ctx.dst_read_table, ctx.dst_select_final);
+                let after = match segment_count(dst_dsn, dst_db, &table, time_field, seg, seg_end, &ctx.filter(), client, ctx.time_zone.as_ref()).await {
+                    Ok(c) => {
+                        info!("segment {seg} overcopy fixed: dst_rows {} -> {}", dst_rows, c);
+                        Some(c)
+                    }
+                    Err(e) => {
+                        warn!("segment {seg} recount after dedupe failed: {e}");
+                        None
+                    }
+                };
+                (Some(fix), after)
+            }
+            Err(e) => {
+                error!("segment {seg} overcopy dedupe failed: {e}");
+                (Some(format!("failed: {}", e)), None)
+            }
+        },
+    };
+    ctx.over_copied.lock().unwrap().push(report::OverCopied {
+        segment: seg.to_string(),
+        src_rows,
+        dst_rows,
+        excess: dst_rows - src_rows,
+        fix,
+        dst_rows_after,
+    });
+}
+
+// 记录一次读取中跳过的坏行：日志、死信文件、断点续传文件与报告
+fn skip_bad_rows(ctx: &RunCtx, done_segments_file: &str, seg: &str, side: &str, bad: &[bad_rows::BadLine]) {
+    if bad.is_empty() {
+        return;
+    }
+    match ctx.bad_rows.record(seg, side, bad) {
+        Ok(r) => ctx.bad_row_segments.lock().unwrap().push(r),
+        Err(e) => error!("segment {seg} write dead letter failed: {e}"),
+    }
+    if let Err(e) = save_done_segment(done_segments_file, &format!("{}{}\t{}\t{}", bad_rows::BAD_ROWS_PREFIX, seg, side, bad.len())) {
+        error!("save_done_segment failed: {e}");
+    }
+}
+
+// 按 --batch-bytes 切分写入批次（JSONEachRow 文本与行数），batch_bytes 为 0 时每批 5000 行；
+// 写入路径已改为流式（insert_stream），这里只用于测试中构造整批文本
+#[cfg(test)]
+fn insert_batches(rows: &[HashMap<String, Value>], batch_bytes: u64) -> Vec<(String, usize)> {
+    let mut batches = Vec::new();
+    let (mut data, mut n) = (String::new(), 0);
+    for row in rows {
+        let line = serde_json::to_string(row).unwrap();
+        let full = if batch_bytes == 0 { n >= 5000 } else { n > 0 && (data.len() + line.len()) as u64 > batch_bytes };
+        if full {
+            batches.push((std::mem::take(&mut data), n));
+            n = 0;
+        }
+        if n > 0 {
+            data.push('\n');
+        }
+        data.push_str(&line);
+        n += 1;
     }
+    if n > 0 {
+        batches.push((data, n));
+    }
+    batches
 }
 
-// 新增：全局复用 Client 的 HTTP 查询
+// 新增：全局复用 Client 的 HTTP 查询，同时返回按 --on-bad-row 跳过的行
 async fn ch_query_rows_with_client(
     dsn: &str,
     db: &str,
     sql: &str,
-    client: Arc<reqwest::Client>,
-) -> anyhow::Result<Vec<HashMap<String, Value>>> {
-    let (url, user, pass, _) = parse_clickhouse_dsn(dsn, db)?;
+    client: Arc<endpoint::Clients>,
+    abort_on_bad_row: bool,
+) -> anyhow::Result<(bad_rows::Rows, Vec<bad_rows::BadLine>)> {
     let mut last_err = None;
     for _ in 0..3 {
+        // --src-prefer-replica 时源端读取发往首选副本，不可达后改回 DSN 主机
+        let routed = src_replica::route(dsn);
+        let (url, user, pass, _) = parse_clickhouse_dsn(&routed, db)?;
+        let _slot = src_limit::acquire(dsn).await;
+        let stmt = sql_log::begin();
         match client
+            .for_dsn(dsn)
             .post(&url)
             .basic_auth(&user, Some(&pass))
+            .query(&stmt.params())
             .body(sql.to_string())
             .send()
             .await
         {
             Ok(resp) => {
                 let status = resp.status();
-                let text = resp.text().await?;
+                let content_length = resp.content_length();
+                let body = resp.bytes().await?;
+                transfer::read(dsn, body.len(), content_length);
                 if !status.is_success() {
+                    let text = String::from_utf8_lossy(&body);
+                    stmt.end(&routed, sql, &format!("{} {}", status, text), None);
+                    let class = classify_ch_error(&text);
+                    if class != ChErrorClass::Retry {
+                        return Err(anyhow::anyhow!(format!("ClickHouse HTTP 错误({:?}): {} {}", class, status, text)));
+                    }
                     last_err = Some(anyhow::anyhow!(format!("ClickHouse HTTP 错误: {} {}", status, text)));
                     tokio::time::sleep(Duration::from_secs(2)).await;
                     continue;
                 }
-                let mut rows = Vec::new();
-                for line in text.lines() {
-                    if line.trim().is_empty() { continue; }
-                    let v: HashMap<String, Value> = serde_json::from_str(line)?;
-                    rows.push(v);
-                }
-                return Ok(rows);
+                let (rows, bad) = bad_rows::parse_rows(&body, abort_on_bad_row)?;
+                memory::observe(rows.len(), body.len());
+                stmt.end(&routed, sql, "ok", Some(rows.len()));
+                return Ok((rows, bad));
             }
             Err(e) => {
+                stmt.end(&routed, sql, &format!("连接失败: {}", e), None);
+                health::connection_error(dsn);
+                src_replica::unreachable(&routed, &e);
                 last_err = Some(anyhow::anyhow!(format!("ClickHouse HTTP 连接失败: {}", e)));
                 tokio::time::sleep(Duration::from_secs(2)).await;
             }
@@ -220,33 +1551,51 @@ async fn ch_query_rows_with_client(
 async fn insert_rows_http_with_client(
     dsn: &str,
     db: &str,
-    table: &str,
-    data: String,
-    client: Arc<reqwest::Client>,
+    sql: &str,
+    batch: &mut insert_stream::Batch<'_>,
+    client: Arc<endpoint::Clients>,
 ) -> anyhow::Result<()> {
     let (url, user, pass, _) = parse_clickhouse_dsn(dsn, db)?;
-    let sql = format!("INSERT INTO {} FORMAT JSONEachRow", table);
     let mut last_err = None;
+    // 目标端支持时各次重试带同一个去重令牌
+    let token = server_version::dedup_token(dsn);
     for _ in 0..3 {
-        match client
-            .post(&url)
-            .basic_auth(&user, Some(&pass))
-            .query(&[("query", sql.clone())])
-            .body(data.clone())
-            .send()
-            .await
-        {
+        let stmt = sql_log::begin();
+        let sent = batch
+            .send(
+                client
+                    .for_dsn(dsn)
+                    .post(&url)
+                    .basic_auth(&user, Some(&pass))
+                    .query(&[("query", sql)])
+                    .query(batch.settings())
+                    .query(&token)
+                    .query(&stmt.params()),
+            )
+            .await;
+        let rows = batch.rows();
+        if sent.is_ok() {
+            transfer::written(batch.bytes());
+        }
+        match sent {
             Ok(resp) => {
                 let status = resp.status();
                 let text = resp.text().await?;
                 if !status.is_success() {
+                    stmt.end(dsn, sql, &format!("{} {}", status, text), Some(rows));
+                    if classify_ch_error(&text) != ChErrorClass::Retry {
+                        return Err(anyhow::anyhow!(format!("ClickHouse 批量写入失败: {} {}", status, text)));
+                    }
                     last_err = Some(anyhow::anyhow!(format!("ClickHouse 批量写入失败: {} {}", status, text)));
                     tokio::time::sleep(Duration::from_secs(2)).await;
                     continue;
                 }
+                stmt.end(dsn, sql, "ok", Some(rows));
                 return Ok(());
             }
             Err(e) => {
+                stmt.end(dsn, sql, &format!("连接失败: {}", e), Some(rows));
+                health::connection_error(dsn);
                 last_err = Some(anyhow::anyhow!(format!("ClickHouse HTTP 连接失败: {}", e)));
                 tokio::time::sleep(Duration::from_secs(2)).await;
             }
@@ -255,57 +1604,273 @@ async fn insert_rows_http_with_client(
     Err(last_err.unwrap_or_else(|| anyhow::anyhow!("ClickHouse HTTP 连接失败: 未知错误")))
 }
 
-// ===================== ClickHouse HTTP 认证最小化测试 =====================
-async fn test_reqwest_clickhouse_auth(dsn: &str) -> anyhow::Result<()> {
-    // 只支持 http(s)://user:pass@host:port 形式
-    let url = if dsn.starts_with("http://") || dsn.starts_with("https://") {
-        let mut url: String = dsn.to_string();
-        // 去掉末尾 /db_data
-        if let Some(idx) = url.rfind('/') {
-            let after = &url[idx+1..];
-            if !after.contains(":") && !after.contains("@") && !after.contains("?") && !after.contains("=") {
-                url = url[..idx].to_string();
+// 经写入闸门的批量写入：目标端只读/part 过多时等待恢复后重试同一批次
+async fn insert_rows_gated(
+    ctx: &RunCtx,
+    dsn: &str,
+    db: &str,
+    table: &str,
+    batch: &mut insert_stream::Batch<'_>,
+    client: Arc<endpoint::Clients>,
+) -> anyhow::Result<()> {
+    loop {
+        ctx.write_gate.wait_writable().await;
+        let _permit = ctx.insert_permits.acquire().await?;
+        match insert_rows_http_with_client(dsn, db, &ctx.binary.insert_sql(&qualified(db, table)), batch, client.clone()).await {
+            Err(e) if classify_ch_error(&e.to_string()) == ChErrorClass::WaitRetry => {
+                ctx.write_gate.wait_until_writable(&e.to_string()).await?;
+            }
+            Ok(()) => {
+                status::progress();
+                return Ok(());
             }
+            res => return res,
         }
-        url
-    } else {
-        anyhow::bail!("只支持 http(s)://user:pass@host:port 形式");
-    };
-    // 解析用户名密码
-    let re = regex::Regex::new(r"https?://([^:]+):([^@]+)@([^/]+)").unwrap();
-    let caps = re.captures(&url).ok_or_else(|| anyhow::anyhow!(format!("DSN 格式不正确: {}", url)))?;
-    let user = &caps[1];
-    let pass = &caps[2];
-    let host = &caps[3];
-    let url = format!("http://{}/", host); // 直接访问根路径
-    let sql = "SELECT 1";
-    let client = reqwest::Client::new();
-    let resp = client
-        .post(&url)
-        .basic_auth(user, Some(pass))
-        .body(sql)
-        .send()
-        .await?;
-    let status = resp.status();
-    let text = resp.text().await?;
-    println!("[reqwest] HTTP status: {status}, body: {text}");
-    if !status.is_success() {
-        anyhow::bail!(format!("reqwest 认证失败: {status} {text}"));
     }
-    Ok(())
+}
+
+// 按 --batch-bytes 分批流式写入 rows，某批失败后继续下一批；返回写入成功的行数与各失败批次的错误。
+// 超过 --max-row-bytes 的行单独写入，超过 --max-row-hard-bytes 的行写入死信文件，计入分段 seg 的超大行统计
+async fn insert_rows_batched(
+    ctx: &RunCtx,
+    seg: &str,
+    dsn: &str,
+    db: &str,
+    table: &str,
+    rows: &[HashMap<String, Value>],
+    client: Arc<endpoint::Clients>,
+) -> (usize, Vec<anyhow::Error>) {
+    let (mut written, mut errors, mut start) = (0, Vec::new(), 0);
+    for i in 0..=rows.len() {
+        let size = rows.get(i).map(|r| ctx.oversized.classify(r));
+        if matches!(size, Some(oversized::Size::Normal)) {
+            continue;
+        }
+        // 超大行之前的普通行照常分批
+        let (n, e) = insert_rows_split(ctx, seg, dsn, db, table, &rows[start..i], client.clone()).await;
+        written += n;
+        errors.extend(e);
+        start = i + 1;
+        match size {
+            Some(oversized::Size::DeadLetter(bytes)) => {
+                warn!("segment {seg} 行大小 {} 字节超过 --max-row-hard-bytes，写入死信文件，不迁移", bytes);
+                match ctx.bad_rows.write_row(seg, &format!("oversized bytes={}（超过 --max-row-hard-bytes）", bytes), &rows[i]) {
+                    Ok(()) => ctx.oversized.note(seg, bytes, true),
+                    Err(e) => errors.push(e),
+                }
+            }
+            Some(oversized::Size::Single(bytes)) => {
+                warn!("segment {seg} 行大小 {} 字节超过 --max-row-bytes，单独写入", bytes);
+                ctx.oversized.note(seg, bytes, false);
+                let mut batch = insert_stream::Batch::new(&rows[i..=i], 0, ctx.insert_spill_memory).with_settings(oversized::settings(bytes));
+                match insert_rows_gated(ctx, dsn, db, table, &mut batch, client.clone()).await {
+                    Ok(()) => written += 1,
+                    Err(e) => {
+                        let (n, e) = bisect_rejected(ctx, seg, dsn, db, table, &rows[i..=i], e, client.clone()).await;
+                        written += n;
+                        errors.extend(e);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    (written, errors)
+}
+
+// 按 --batch-bytes 分批流式写入一段普通行
+async fn insert_rows_split(
+    ctx: &RunCtx,
+    seg: &str,
+    dsn: &str,
+    db: &str,
+    table: &str,
+    rows: &[HashMap<String, Value>],
+    client: Arc<endpoint::Clients>,
+) -> (usize, Vec<anyhow::Error>) {
+    let (mut written, mut errors, mut rest) = (0, Vec::new(), rows);
+    while !rest.is_empty() {
+        let mut batch = insert_stream::Batch::new(rest, ctx.batch_bytes.load(std::sync::atomic::Ordering::Relaxed), ctx.insert_spill_memory);
+        let res = insert_rows_gated(ctx, dsn, db, table, &mut batch, client.clone()).await;
+        let n = batch.rows();
+        match res {
+            Ok(()) => written += n,
+            // 未能发出任何数据（如写入并发信号量已关闭）时其余行不再尝试
+            Err(e) if n == 0 => {
+                errors.push(e);
+                break;
+            }
+            Err(e) => {
+                let (n, e) = bisect_rejected(ctx, seg, dsn, db, table, &rest[..n], e, client.clone()).await;
+                written += n;
+                errors.extend(e);
+            }
+        }
+        rest = &rest[n..];
+    }
+    (written, errors)
+}
+
+// 写入失败的批次 rows：--on-rejected-row dead-letter 且为数据错误时二分拆分重试，隔离出的单行写入死信文件；
+// 其余情况原样返回错误。返回写入成功的行数与未能处理部分的错误
+#[allow(clippy::too_many_arguments)]
+async fn bisect_rejected(
+    ctx: &RunCtx,
+    seg: &str,
+    dsn: &str,
+    db: &str,
+    table: &str,
+    rows: &[HashMap<String, Value>],
+    error: anyhow::Error,
+    client: Arc<endpoint::Clients>,
+) -> (usize, Vec<anyhow::Error>) {
+    let is_data = |e: &anyhow::Error| classify_ch_error(&e.to_string()) == ChErrorClass::Data;
+    if !ctx.rejected.bisect || !is_data(&error) {
+        return (0, vec![error]);
+    }
+    let (mut written, mut errors, mut inserts) = (0, Vec::new(), 0);
+    // 已知因数据错误失败的部分，先处理前半
+    let mut failed = vec![(rows, error)];
+    while let Some((part, e)) = failed.pop() {
+        if part.len() == 1 {
+            let reason = format!("rejected error={}", e);
+            match ctx.bad_rows.write_row(seg, &reason, &part[0]) {
+                Ok(()) => ctx.rejected.note(seg, &e.to_string()),
+                Err(w) => errors.push(w),
+            }
+            continue;
+        }
+        if inserts >= ctx.rejected.max_inserts {
+            errors.push(anyhow::anyhow!(format!("超过 --bisect-max-inserts {}，{} 行未能隔离: {}", ctx.rejected.max_inserts, part.len(), e)));
+            continue;
+        }
+        let (a, b) = part.split_at(part.len() / 2);
+        let mut halves = Vec::new();
+        for half in [a, b] {
+            inserts += 1;
+            // 一半一定不大于原批次，整体作为一批发送
+            let mut batch = insert_stream::Batch::new(half, u64::MAX, ctx.insert_spill_memory);
+            match insert_rows_gated(ctx, dsn, db, table, &mut batch, client.clone()).await {
+                Ok(()) => written += half.len(),
+                Err(e) if is_data(&e) => halves.push((half, e)),
+                Err(e) => errors.push(e),
+            }
+        }
+        failed.extend(halves.into_iter().rev());
+    }
+    info!("segment {seg} bisect: {} rows, {} inserts, {} written, {} unresolved", rows.len(), inserts, written, errors.len());
+    (written, errors)
 }
 
 // ===================== ClickHouse HTTP 方案 =====================
-// 解析 DSN，返回 (url, user, pass, db)
-fn parse_clickhouse_dsn(dsn: &str, db: &str) -> anyhow::Result<(String, String, String, String)> {
-    let re = regex::Regex::new(r"https?://([^:]+):([^@]*)@([^/:]+)(?::(\\d+))?/?").unwrap();
+// DSN 的请求地址与用户名密码：保留协议、端口与路径前缀（如托管服务的 https://host/clickhouse），
+// 未写端口时 http 为 8123、https 为 443；DSN 上的查询参数不在其中
+fn clickhouse_base_url(dsn: &str) -> anyhow::Result<(String, String, String)> {
+    let re = regex::Regex::new(r"^(https?)://([^:@/]+):([^@]*)@([^/:?]+)(?::(\d+))?(/[^?]*)?").unwrap();
     let caps = re.captures(dsn).ok_or_else(|| anyhow::anyhow!(format!("DSN 格式不正确: {}", dsn)))?;
-    let user = &caps[1];
-    let pass = &caps[2];
-    let host = &caps[3];
-    let port = caps.get(4).map(|m| m.as_str()).unwrap_or("8123");
-    let url = format!("http://{}:{}/?database={}", host, port, db);
-    Ok((url, user.to_string(), pass.to_string(), db.to_string()))
+    let scheme = &caps[1];
+    let port = caps.get(5).map(|m| m.as_str()).unwrap_or(if scheme == "https" { "443" } else { "8123" });
+    let path = caps.get(6).map(|m| m.as_str()).unwrap_or("/");
+    Ok((format!("{}://{}:{}{}", scheme, &caps[4], port, path), caps[2].to_string(), caps[3].to_string()))
+}
+
+// 解析 DSN，返回 (url, user, pass, db)；url 为路径前缀之后追加 ?database= 与 DSN 上的查询参数
+fn parse_clickhouse_dsn(dsn: &str, db: &str) -> anyhow::Result<(String, String, String, String)> {
+    let (base, user, pass) = clickhouse_base_url(dsn)?;
+    let mut url = format!("{}?database={}", base, db);
+    // DSN 上的查询参数（如 --src-query-limits 注入的 settings）原样透传
+    if let Some((_, params)) = dsn.split_once('?') {
+        if !params.is_empty() {
+            url.push('&');
+            url.push_str(params);
+        }
+    }
+    Ok((url, user, pass, db.to_string()))
+}
+
+// 查询限制：mem=8G,time=600,read=100G,rows=1000000000 -> ClickHouse settings
+fn parse_query_limits(s: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut settings = Vec::new();
+    for item in s.split(',').map(|i| i.trim()).filter(|i| !i.is_empty()) {
+        let (k, v) = item.split_once('=').ok_or_else(|| anyhow::anyhow!(format!("查询限制格式不正确: {}", item)))?;
+        let (name, value) = match k.trim() {
+            "mem" => ("max_memory_usage", parse_size_str(v)?),
+            "time" => ("max_execution_time", parse_duration_str(v)?.as_secs()),
+            "read" => ("max_bytes_to_read", parse_size_str(v)?),
+            "rows" => ("max_rows_to_read", v.trim().parse()?),
+            other => anyhow::bail!(format!("未知的查询限制项: {}（支持 mem/time/read/rows）", other)),
+        };
+        settings.push((name.to_string(), value.to_string()));
+    }
+    Ok(settings)
+}
+
+// 解析容量参数，支持 K/M/G/T（1024 进制），纯数字按字节处理
+fn parse_size_str(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let (num, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => (&s[..i], s[i..].trim_end_matches(['B', 'b'])),
+        None => (s, ""),
+    };
+    let n: u64 = num.parse().map_err(|_| anyhow::anyhow!(format!("容量格式不正确: {}", s)))?;
+    let mul: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => anyhow::bail!(format!("容量单位不支持: {}", s)),
+    };
+    Ok(n * mul)
+}
+
+// 将 settings 以查询参数形式附加到 DSN 上，使该端点的所有请求都带上这些 settings
+fn dsn_with_settings(dsn: &str, settings: &[(String, String)]) -> String {
+    let (base, query) = dsn.split_once('?').unwrap_or((dsn, ""));
+    let mut params: Vec<String> = query.split('&').filter(|p| !p.is_empty()).map(|p| p.to_string()).collect();
+    // 已有的同名参数替换为新值（按运行清单重跑时不会重复追加）
+    for (k, v) in settings {
+        let kv = format!("{}={}", k, v);
+        match params.iter_mut().find(|p| p.split('=').next() == Some(k.as_str())) {
+            Some(p) => *p = kv,
+            None => params.push(kv),
+        }
+    }
+    if params.is_empty() { base.to_string() } else { format!("{}?{}", base, params.join("&")) }
+}
+
+// ClickHouse 错误分类
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChErrorClass {
+    Retry,        // 网络/临时错误，原样重试
+    WaitRetry,    // 目标端暂时不可写，全局暂停写入并长时间退避后重试
+    SplitSegment, // 触发查询自身的内存/时间/读取量限制，重试同样的查询无意义，应缩小分段
+    Data,         // 数据本身无法解析或超出列类型范围，重试同样的数据无意义
+    Fatal,        // 语法/权限/表不存在等，重试无意义
+}
+
+// 错误分类在每次失败时调用，正则只编译一次
+fn ch_error_code(text: &str) -> Option<u32> {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let re = RE.get_or_init(|| regex::Regex::new(r"Code: (\d+)").unwrap());
+    re.captures(text).and_then(|c| c[1].parse().ok())
+}
+
+fn classify_ch_error(text: &str) -> ChErrorClass {
+    match ch_error_code(text) {
+        // MEMORY_LIMIT_EXCEEDED / TIMEOUT_EXCEEDED / TOO_MANY_ROWS / TOO_SLOW / TOO_MANY_BYTES
+        Some(241) | Some(159) | Some(158) | Some(160) | Some(307) => ChErrorClass::SplitSegment,
+        // UNKNOWN_IDENTIFIER / UNKNOWN_TABLE / SYNTAX_ERROR / UNKNOWN_DATABASE / ACCESS_DENIED / AUTHENTICATION_FAILED
+        Some(47) | Some(60) | Some(62) | Some(81) | Some(497) | Some(516) => ChErrorClass::Fatal,
+        // CANNOT_PARSE_TEXT / CANNOT_PARSE_QUOTED_STRING / CANNOT_PARSE_INPUT_ASSERTION_FAILED / CANNOT_PARSE_DATE / CANNOT_PARSE_DATETIME /
+        // TYPE_MISMATCH / ARGUMENT_OUT_OF_BOUND / CANNOT_CONVERT_TYPE / CANNOT_PARSE_NUMBER / INCORRECT_DATA / VALUE_IS_OUT_OF_RANGE_OF_DATA_TYPE /
+        // CANNOT_INSERT_NULL_IN_ORDINARY_COLUMN / CANNOT_PARSE_UUID / DECIMAL_OVERFLOW
+        Some(6) | Some(26) | Some(27) | Some(38) | Some(41) | Some(53) | Some(69) | Some(70) | Some(72) | Some(117) | Some(321) | Some(349)
+        | Some(376) | Some(407) => ChErrorClass::Data,
+        // TABLE_IS_READ_ONLY / TOO_MANY_PARTS
+        Some(242) | Some(252) => ChErrorClass::WaitRetry,
+        _ => ChErrorClass::Retry,
+    }
 }
 
 // HTTP 查询，返回 Vec<HashMap<String, Value>>
@@ -314,36 +1879,41 @@ async fn ch_query_rows(
     db: &str,
     sql: &str,
 ) -> anyhow::Result<Vec<HashMap<String, Value>>> {
-    let (url, user, pass, _) = parse_clickhouse_dsn(dsn, db)?;
-    let client = reqwest::Client::builder()
+    let client = endpoint::builder(dsn)
         .timeout(Duration::from_secs(30))
         .build()?;
     let mut last_err = None;
     for _ in 0..3 {
+        let routed = src_replica::route(dsn);
+        let (url, user, pass, _) = parse_clickhouse_dsn(&routed, db)?;
+        let _slot = src_limit::acquire(dsn).await;
+        let stmt = sql_log::begin();
         match client
             .post(&url)
             .basic_auth(&user, Some(&pass))
+            .query(&stmt.params())
             .body(sql.to_string())
             .send()
             .await
         {
             Ok(resp) => {
                 let status = resp.status();
-                let text = resp.text().await?;
+                let body = resp.bytes().await?;
                 if !status.is_success() {
+                    let text = String::from_utf8_lossy(&body);
+                    stmt.end(&routed, sql, &format!("{} {}", status, text), None);
                     last_err = Some(anyhow::anyhow!(format!("ClickHouse HTTP 错误: {} {}", status, text)));
                     tokio::time::sleep(Duration::from_secs(2)).await;
                     continue;
                 }
-                let mut rows = Vec::new();
-                for line in text.lines() {
-                    if line.trim().is_empty() { continue; }
-                    let v: HashMap<String, Value> = serde_json::from_str(line)?;
-                    rows.push(v);
-                }
+                let (rows, _) = bad_rows::parse_rows(&body, true)?;
+                stmt.end(&routed, sql, "ok", Some(rows.len()));
                 return Ok(rows);
             }
             Err(e) => {
+                stmt.end(&routed, sql, &format!("连接失败: {}", e), None);
+                health::connection_error(dsn);
+                src_replica::unreachable(&routed, &e);
                 last_err = Some(anyhow::anyhow!(format!("ClickHouse HTTP 连接失败: {}", e)));
                 tokio::time::sleep(Duration::from_secs(2)).await;
             }
@@ -358,15 +1928,27 @@ async fn ch_execute(
     db: &str,
     sql: &str,
 ) -> anyhow::Result<()> {
-    let (url, user, pass, _) = parse_clickhouse_dsn(dsn, db)?;
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(30))
+    ch_execute_timeout(dsn, db, sql, Duration::from_secs(30)).await
+}
+
+// HTTP 执行无返回 SQL，指定超时（用于 INSERT ... SELECT 等长耗时语句），带重试
+async fn ch_execute_timeout(
+    dsn: &str,
+    db: &str,
+    sql: &str,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let (url, user, pass, _) = parse_clickhouse_dsn(dsn, db)?;
+    let client = endpoint::builder(dsn)
+        .timeout(timeout)
         .build()?;
     let mut last_err = None;
     for _ in 0..3 {
+        let stmt = sql_log::begin();
         match client
             .post(&url)
             .basic_auth(&user, Some(&pass))
+            .query(&stmt.params())
             .body(sql.to_string())
             .send()
             .await
@@ -375,13 +1957,17 @@ async fn ch_execute(
                 let status = resp.status();
                 let text = resp.text().await?;
                 if !status.is_success() {
+                    stmt.end(dsn, sql, &format!("{} {}", status, text), None);
                     last_err = Some(anyhow::anyhow!(format!("ClickHouse HTTP 错误: {} {}", status, text)));
                     tokio::time::sleep(Duration::from_secs(2)).await;
                     continue;
                 }
+                stmt.end(dsn, sql, "ok", None);
                 return Ok(());
             }
             Err(e) => {
+                stmt.end(dsn, sql, &format!("连接失败: {}", e), None);
+                health::connection_error(dsn);
                 last_err = Some(anyhow::anyhow!(format!("ClickHouse HTTP 连接失败: {}", e)));
                 tokio::time::sleep(Duration::from_secs(2)).await;
             }
@@ -390,80 +1976,253 @@ async fn ch_execute(
     Err(last_err.unwrap_or_else(|| anyhow::anyhow!("ClickHouse HTTP 连接失败: 未知错误")))
 }
 
-// 批量写入（HTTP 方案，JSONEachRow），带超时和重试
-async fn insert_rows_http(
+// 执行 ON CLUSTER DDL 并等待所有节点完成（集群在该端点不可用时去掉 ON CLUSTER 按单机形式执行，见 cluster.rs）：
+// 先以 distributed_ddl_task_timeout=轮询间隔 同步等待，超时后按 entry 轮询 system.distributed_ddl_queue，
+// 超过总超时仍有节点未完成则返回错误并列出滞后节点
+async fn ch_execute_on_cluster(
     dsn: &str,
     db: &str,
-    table: &str,
-    data: String,
+    sql: &str,
+    cluster: &str,
+    timeout: Duration,
+    poll_interval: Duration,
 ) -> anyhow::Result<()> {
+    let fallback = cluster::fallback(dsn, db, cluster).await?;
+    if let Some(reason) = fallback {
+        let local = cluster::strip(sql);
+        cluster::record(dsn, cluster, &local, Some(&reason));
+        return ch_execute_timeout(dsn, db, &local, timeout).await;
+    }
+    cluster::record(dsn, cluster, sql, None);
     let (url, user, pass, _) = parse_clickhouse_dsn(dsn, db)?;
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(30))
+    let wait_secs = poll_interval.as_secs().max(1);
+    let client = endpoint::builder(dsn)
+        .timeout(Duration::from_secs(wait_secs + 30))
         .build()?;
-    let sql = format!("INSERT INTO {} FORMAT JSONEachRow", table);
-    let mut last_err = None;
-    for _ in 0..3 {
-        match client
-            .post(&url)
-            .basic_auth(&user, Some(&pass))
-            .query(&[("query", sql.clone())])
-            .body(data.clone())
-            .send()
-            .await
-        {
-            Ok(resp) => {
-                let status = resp.status();
-                let text = resp.text().await?;
-                if !status.is_success() {
-                    last_err = Some(anyhow::anyhow!(format!("ClickHouse 批量写入失败: {} {}", status, text)));
-                    tokio::time::sleep(Duration::from_secs(2)).await;
-                    continue;
-                }
-                return Ok(());
+    let deadline = std::time::Instant::now() + timeout;
+    let stmt = sql_log::begin();
+    let resp = client
+        .post(&url)
+        .basic_auth(&user, Some(&pass))
+        .query(&[
+            ("distributed_ddl_task_timeout", wait_secs.to_string()),
+            ("distributed_ddl_output_mode", "throw".to_string()),
+            ("default_format", "JSONEachRow".to_string()),
+        ])
+        .query(&stmt.params())
+        .body(sql.to_string())
+        .send()
+        .await
+        .map_err(|e| {
+            stmt.end(dsn, sql, &format!("连接失败: {}", e), None);
+            health::connection_error(dsn);
+            anyhow::anyhow!(format!("ClickHouse HTTP 连接失败: {}", e))
+        })?;
+    let status = resp.status();
+    let text = resp.text().await?;
+    stmt.end(dsn, sql, if status.is_success() { "ok" } else { &text }, None);
+    if status.is_success() {
+        // 同步模式下每个节点返回一行执行结果
+        let mut failed = Vec::new();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let v: HashMap<String, Value> = serde_json::from_str(line)?;
+            let code = v.get("status").and_then(|s| s.as_i64()).unwrap_or(0);
+            if code != 0 {
+                failed.push(format!(
+                    "{}:{} ({})",
+                    v.get("host").and_then(|h| h.as_str()).unwrap_or(""),
+                    v.get("port").map(|p| p.to_string()).unwrap_or_default(),
+                    v.get("error").and_then(|e| e.as_str()).unwrap_or("")
+                ));
             }
-            Err(e) => {
-                last_err = Some(anyhow::anyhow!(format!("ClickHouse HTTP 连接失败: {}", e)));
-                tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+        if !failed.is_empty() {
+            anyhow::bail!(format!("ON CLUSTER DDL 在以下节点执行失败: {}", failed.join(", ")));
+        }
+        info!("ON CLUSTER DDL 已在集群 {} 全部节点完成: {}", cluster, sql);
+        return Ok(());
+    }
+    // 同步等待超时（Code: 159），DDL 仍在后台执行，改为轮询队列
+    let re = regex::Regex::new(r"(query-\d+)").unwrap();
+    let entry = match re.captures(&text) {
+        Some(c) if text.contains("Code: 159") => c[1].to_string(),
+        _ => anyhow::bail!(format!("ClickHouse HTTP 错误: {} {}", status, text)),
+    };
+    info!("ON CLUSTER DDL {} 尚未在全部节点完成，开始轮询 system.distributed_ddl_queue", entry);
+    let q = format!(
+        "SELECT host, port, status, exception_code FROM system.distributed_ddl_queue WHERE cluster = '{}' AND entry = '{}' FORMAT JSONEachRow",
+        cluster, entry
+    );
+    loop {
+        let rows = ch_query_rows(dsn, db, &q).await?;
+        let mut lagging = Vec::new();
+        let mut failed = Vec::new();
+        for r in &rows {
+            let host = format!(
+                "{}:{}",
+                r.get("host").and_then(|h| h.as_str()).unwrap_or(""),
+                r.get("port").map(|p| p.to_string()).unwrap_or_default()
+            );
+            let st = r.get("status").and_then(|s| s.as_str()).unwrap_or("");
+            let code = r.get("exception_code").and_then(|c| c.as_i64()).unwrap_or(0);
+            if code != 0 {
+                failed.push(format!("{} (exception_code={})", host, code));
+            } else if st != "Finished" {
+                lagging.push(format!("{} ({})", host, st));
             }
         }
+        if !failed.is_empty() {
+            anyhow::bail!(format!("ON CLUSTER DDL {} 在以下节点执行失败: {}", entry, failed.join(", ")));
+        }
+        if !rows.is_empty() && lagging.is_empty() {
+            info!("ON CLUSTER DDL {} 已在集群 {} 全部节点完成", entry, cluster);
+            return Ok(());
+        }
+        if std::time::Instant::now() >= deadline {
+            anyhow::bail!(format!(
+                "ON CLUSTER DDL {} 超时({:?})未完成, 滞后节点: {}",
+                entry, timeout, lagging.join(", ")
+            ));
+        }
+        info!("ON CLUSTER DDL {} 等待中, 未完成节点: {}", entry, lagging.join(", "));
+        tokio::time::sleep(poll_interval).await;
     }
-    Err(last_err.unwrap_or_else(|| anyhow::anyhow!("ClickHouse HTTP 连接失败: 未知错误")))
 }
 
 // 获取所有字段名（HTTP 方案）
 async fn get_column_names_http(dsn: &str, db: &str, table: &str) -> anyhow::Result<Vec<String>> {
-    let sql = format!("DESCRIBE TABLE {} FORMAT JSONEachRow", table);
+    let sql = format!("DESCRIBE TABLE {} FORMAT JSONEachRow", qualified(db, table));
     let rows = ch_query_rows(dsn, db, &sql).await?;
     Ok(rows.into_iter().map(|mut r| r.remove("name").and_then(|v| v.as_str().map(|s| s.to_string())).unwrap_or_default()).collect())
 }
 
 // 获取最大时间戳（HTTP 方案）
-async fn get_max_time_http(dsn: &str, db: &str, table: &str, time_field: &str) -> anyhow::Result<String> {
-    let sql = format!("SELECT toString(max({})) as max_time FROM {} FORMAT JSONEachRow", time_field, table);
+// 列声明了时区时，读回的时间按列时区解析并换算为 UTC
+async fn get_max_time_http(dsn: &str, db: &str, table: &str, time_field: &str, filter: &str, tz: Option<&time_zone::ColumnTz>) -> anyhow::Result<String> {
+    let sql = format!("SELECT count() as c, toString(max({})) as max_time FROM {} WHERE 1{} FORMAT JSONEachRow", time_field, qualified(db, table), filter);
     let rows = ch_query_rows(dsn, db, &sql).await?;
-    Ok(rows.get(0).and_then(|r| r.get("max_time")).and_then(|v| v.as_str()).unwrap_or("").to_string())
+    Ok(time_zone::range_from_column(tz, time_range_row(rows.first())).1)
 }
 
-// 获取时间范围（HTTP 方案）
-async fn get_time_range_http(dsn: &str, db: &str, table: &str, time_field: &str, start: &str) -> anyhow::Result<(String, String)> {
+// 获取时间范围（HTTP 方案）；start 与返回值在列声明了时区时为 UTC
+async fn get_time_range_http(
+    dsn: &str,
+    db: &str,
+    table: &str,
+    time_field: &str,
+    start: &str,
+    filter: &str,
+    tz: Option<&time_zone::ColumnTz>,
+) -> anyhow::Result<(String, String)> {
     let sql = format!(
-        "SELECT toString(min({})) as min_time, toString(max({})) as max_time FROM {} WHERE {} >= '{}' FORMAT JSONEachRow",
-        time_field, time_field, table, time_field, start
+        "SELECT count() as c, toString(min({})) as min_time, toString(max({})) as max_time FROM {} WHERE {} >= {}{} FORMAT JSONEachRow",
+        time_field, time_field, qualified(db, table), time_field, time_zone::lit(tz, start), filter
     );
     let rows = ch_query_rows(dsn, db, &sql).await?;
-    let min_time = rows.get(0).and_then(|r| r.get("min_time")).and_then(|v| v.as_str()).unwrap_or("").to_string();
-    let max_time = rows.get(0).and_then(|r| r.get("max_time")).and_then(|v| v.as_str()).unwrap_or("").to_string();
-    Ok((min_time, max_time))
+    Ok(time_zone::range_from_column(tz, time_range_row(rows.first())))
+}
+
+// 空结果集的 min/max 不是空串：新版本返回 1970-01-01 00:00:00，旧版本返回 0000-00-00 00:00:00。
+// 有 count() 时以其为准（真实数据可能含零值时间）；没有时哨兵值视为无数据；max < min 均视为无数据。无数据返回空串
+fn time_range_or_empty(count: Option<u64>, min_time: &str, max_time: &str) -> (String, String) {
+    let sentinel = |t: &str| t.is_empty() || t.starts_with("0000-00-00") || t.starts_with("1970-01-01 00:00:00");
+    let empty = match count {
+        Some(c) => c == 0,
+        None => sentinel(min_time) || sentinel(max_time),
+    };
+    if empty || max_time.is_empty() || max_time < min_time {
+        return (String::new(), String::new());
+    }
+    (min_time.to_string(), max_time.to_string())
+}
+
+// 解析 count() as c / min_time / max_time 查询结果，缺少 min_time 时只校验 max_time
+fn time_range_row(row: Option<&HashMap<String, Value>>) -> (String, String) {
+    let get = |k: &str| row.and_then(|r| r.get(k)).and_then(|v| v.as_str()).map(|s| s.to_string());
+    let max_time = get("max_time").unwrap_or_default();
+    let min_time = get("min_time").unwrap_or_else(|| max_time.clone());
+    let count = row.and_then(|r| r.get("c")).map(|c| json_u64(Some(c)));
+    time_range_or_empty(count, &min_time, &max_time)
 }
 
 // 获取行数据（HTTP 方案）
-async fn get_rows_http(dsn: &str, db: &str, table: &str, time_field: &str, time_val: &str, col_names: &[String]) -> anyhow::Result<Vec<HashMap<String, Value>>> {
-    let col_list = col_names.join(",");
-    let sql = format!("SELECT {} FROM {} WHERE {} = '{}' FORMAT JSONEachRow", col_list, table, time_field, time_val);
+// time_lit 为时间字面量（Opt::time_lit）
+async fn get_rows_http(dsn: &str, db: &str, table: &str, time_field: &str, time_lit: &str, col_list: &str, filter: &str) -> anyhow::Result<Vec<HashMap<String, Value>>> {
+    let sql = format!("SELECT {} FROM {} WHERE {} = {}{} FORMAT JSONEachRow", col_list, qualified(db, table), time_field, time_lit, filter);
     ch_query_rows(dsn, db, &sql).await
 }
 
+// 查询目标表各副本的复制延迟（system.replicas，配置的集群可用时经 clusterAllReplicas 覆盖所有副本）
+async fn get_dst_replica_lags(opt: &Opt) -> anyhow::Result<Vec<report::ReplicaLag>> {
+    let (db, table) = shard::resolve_local_table(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+    let from = cluster::replicas_from(opt).await?;
+    let sql = format!(
+        "SELECT hostName() AS host, absolute_delay, queue_size FROM {} WHERE database = '{}' AND table = '{}' FORMAT JSONEachRow",
+        from, db, table
+    );
+    let rows = ch_query_rows(&opt.dst_dsn, &opt.dst_db, &sql).await?;
+    Ok(rows
+        .iter()
+        .map(|r| report::ReplicaLag {
+            host: r.get("host").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            absolute_delay: json_u64(r.get("absolute_delay")),
+            queue_size: json_u64(r.get("queue_size")),
+        })
+        .collect())
+}
+
+// 切换前等待目标表所有副本延迟降到 --max-replica-lag 以内，超过 --cutover-wait 仍未满足则放弃切换
+async fn wait_for_dst_replica_lag(opt: &Opt, report: &Arc<std::sync::Mutex<report::RunReport>>) -> anyhow::Result<()> {
+    let start = std::time::Instant::now();
+    loop {
+        let replicas = get_dst_replica_lags(opt).await?;
+        if replicas.is_empty() {
+            info!("目标表非复制表，跳过副本延迟检查");
+            return Ok(());
+        }
+        let max_delay = replicas.iter().map(|r| r.absolute_delay).max().unwrap_or(0);
+        let go = max_delay <= opt.max_replica_lag.as_secs();
+        let timed_out = !go && start.elapsed() >= opt.cutover_wait;
+        let decision = if go { "go" } else if timed_out { "no-go" } else { "wait" };
+        for r in &replicas {
+            info!("副本延迟: host={}, absolute_delay={}s, queue_size={}", r.host, r.absolute_delay, r.queue_size);
+        }
+        info!("副本延迟检查: max_delay={}s, 阈值={:?}, 决策={}", max_delay, opt.max_replica_lag, decision);
+        report.lock().unwrap().replica_lag_checks.push(report::ReplicaLagCheck {
+            time: report::now_str(),
+            max_delay,
+            replicas,
+            decision: decision.to_string(),
+        });
+        if go {
+            return Ok(());
+        }
+        if timed_out {
+            error!("目标表副本延迟 {}s 超过阈值且等待超时，放弃切换", max_delay);
+            anyhow::bail!(format!("目标表副本延迟 {}s 超过 {:?}，等待 {:?} 后仍未恢复", max_delay, opt.max_replica_lag, opt.cutover_wait));
+        }
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+// 在两端以 LIMIT 0 探测 --where 谓词与 --shard-of 分片条件，尽早暴露语法或字段错误
+async fn validate_filter(opt: &Opt) -> anyhow::Result<()> {
+    shard_of::validate(opt)?;
+    let filter = row_filter(opt);
+    if filter.is_empty() {
+        return Ok(());
+    }
+    if opt.copy_mode == "attach-partition" {
+        anyhow::bail!("attach-partition 模式按整个分区挂载，不支持 --where");
+    }
+    for (dsn, db, table) in [(&opt.src_dsn, &opt.src_db, &opt.src_table), (&opt.dst_dsn, &opt.dst_db, &opt.read_table().to_string())] {
+        let sql = format!("SELECT count() FROM {} WHERE 1{} LIMIT 0 FORMAT JSONEachRow", qualified(db, table), filter);
+        ch_query_rows(dsn, db, &sql).await.map_err(|e| anyhow::anyhow!(format!("行过滤条件在 {}.{} 上校验失败: {}", db, table, e)))?;
+    }
+    info!("行过滤条件: {}", filter.trim_start_matches(" AND "));
+    Ok(())
+}
+
 // 断点续传记录加载
 fn load_done_segments(filename: &str) -> Result<HashSet<String>> {
     use std::io::{BufRead, BufReader};
@@ -472,7 +2231,13 @@ fn load_done_segments(filename: &str) -> Result<HashSet<String>> {
         let reader = BufReader::new(f);
         for line in reader.lines() {
             if let Ok(seg) = line {
-                done.insert(seg);
+                // 空分段合并的范围记录展开为其中的各分段
+                match coalesce::expand(&seg) {
+                    Some(segs) => done.extend(segs),
+                    None => {
+                        done.insert(seg);
+                    }
+                }
             }
         }
     }
@@ -487,41 +2252,105 @@ fn save_done_segment(filename: &str, seg: &str) -> Result<()> {
     Ok(())
 }
 
-// 分段生成（每小时一段，跳过已完成）
-fn generate_hourly_segments_with_skip(min_time: &str, max_time: &str, done_segments: &HashSet<String>) -> Vec<String> {
-    use chrono::NaiveDateTime;
-    let mut segments = Vec::new();
-    let min = NaiveDateTime::parse_from_str(min_time, "%Y-%m-%d %H:%M:%S").unwrap();
-    let max = NaiveDateTime::parse_from_str(max_time, "%Y-%m-%d %H:%M:%S").unwrap();
-    let mut t = min;
-    while t < max {
-        let seg = t.format("%Y-%m-%d %H:%M:%S").to_string();
-        if !done_segments.contains(&seg) {
-            segments.push(seg);
+// 分段黑名单：已知数据损坏等无法读取的时间范围
+#[derive(Default)]
+struct SegmentBlacklist {
+    list: planner::Blacklist<chrono::NaiveDateTime>,
+    hits: std::sync::Mutex<std::collections::BTreeSet<String>>, // 实际被跳过的分段
+}
+
+impl SegmentBlacklist {
+    fn load(filename: &str) -> Result<Self> {
+        let mut list = SegmentBlacklist::default();
+        if filename.is_empty() {
+            return Ok(list);
+        }
+        let parse = |t: &str| chrono::NaiveDateTime::parse_from_str(t.trim(), planner::TIME_FORMAT)
+            .map_err(|_| anyhow::anyhow!(format!("分段黑名单时间格式不正确: {}", t)));
+        for line in std::fs::read_to_string(filename).with_context(|| format!("读取分段黑名单 {} 失败", filename))?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once("..") {
+                Some((a, b)) => list.list.ranges.push((parse(a)?, parse(b)?)),
+                None => {
+                    list.list.starts.insert(parse(line)?);
+                }
+            }
+        }
+        info!("分段黑名单: {} 个分段, {} 个范围", list.list.starts.len(), list.list.ranges.len());
+        Ok(list)
+    }
+
+    // 分段 [起, 止) 与任一黑名单范围相交即跳过（分段名格式不正确时不跳过）
+    fn contains(&self, seg: &str) -> bool {
+        let Some((start, end)) = segment::span(seg) else { return false };
+        let hit = self.list.hits(&planner::Segment { start, end, whole: true });
+        if hit {
+            self.hits.lock().unwrap().insert(seg.to_string());
         }
-        t += chrono::Duration::hours(1);
+        hit
     }
-    segments
+}
+
+// 分段生成（见 datacp::planner）：[min_time, max_time] 两端都包含，按 --segment-size 切分，减去已完成区间
+// （断点续传记录的并集，分段大小可与本次不同）、黑名单分段与不在 only 中的分段；被黑名单跳过的分段记入报告
+fn plan_segments(
+    min_time: &str,
+    max_time: &str,
+    done_segments: &HashSet<String>,
+    blacklist: &SegmentBlacklist,
+    only: Option<&HashSet<String>>,
+) -> Result<Vec<String>> {
+    let parse = |t: &str| {
+        chrono::NaiveDateTime::parse_from_str(t, planner::TIME_FORMAT).map_err(|_| anyhow::anyhow!(format!("分段范围的时间格式不正确: {}", t)))
+    };
+    let covered = segment::covered(done_segments.iter().map(|s| s.as_str()));
+    let filters = planner::Filters { blacklist: Some(&blacklist.list), only };
+    let plan = planner::SegmentPlan::new(parse(min_time)?..=parse(max_time)?, segment::size(), &covered, &filters);
+    blacklist.hits.lock().unwrap().extend(plan.blacklisted.iter().map(|s| s.name()));
+    Ok(plan.segments.iter().map(|s| s.name()).collect())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let opt = Opt::from_args();
-    // 先用 reqwest 直接测试 HTTP 认证
-    if let Err(e) = test_reqwest_clickhouse_auth(&opt.src_dsn).await {
-        eprintln!("[reqwest] ClickHouse HTTP 认证失败: {e}");
-        return Err(e);
-    }
-    println!("datacp 启动，参数: {:?}", opt);
-    let parallelism = opt.parallelism;
-    let log_file_path = &opt.log_file;
-    let ignore_fields = &opt.ignore_field;
-    let done_segments_file = if !opt.done_segments.is_empty() {
-        opt.done_segments.clone()
-    } else {
-        format!("done_segments_{}_to_{}.txt", opt.src_table, opt.dst_table)
-    };
-    let log_file = OpenOptions::new().create(true).append(true).open(log_file_path)?;
+    let mut opt = Opt::from_args();
+    // migrate --from-manifest 版本不同（--allow-drift）时的告警，日志初始化后输出
+    let mut manifest_drift = None;
+    if let Some(Command::Migrate { from_manifest, allow_drift }) = opt.cmd.clone() {
+        if from_manifest.is_empty() {
+            opt = Opt { cmd: None, ..opt };
+        } else {
+            (opt, manifest_drift) = manifest::load(&from_manifest, allow_drift)?;
+        }
+    }
+    bench::apply_dsn(&mut opt);
+    events::init(opt.events_stdout);
+    deadline::start(&mut opt);
+    work_queue::check(&opt)?;
+    cluster::init(&opt);
+    time_expr::apply(&mut opt)?;
+    src_query::init(&mut opt)?;
+    // checkpoint merge 只处理本地文件，不连接 ClickHouse
+    if let Some(Command::Checkpoint { cmd: CheckpointCommand::Merge { files, output } }) = &opt.cmd {
+        return checkpoint::merge(files, output);
+    }
+    // 两端连接配置（--config 或命令行 DSN），之后的请求都按端使用各自的证书与代理
+    endpoint::init(&mut opt)?;
+    // 两端按层探测（/ping、认证与数据库），失败时报告所在层（serve 的任务各自指定连接，提交时再连接）
+    let serving = matches!(opt.cmd, Some(Command::Serve { .. }));
+    if !serving {
+        health::check(&opt).await?;
+    }
+    // 源端查询限制以 settings 形式附加到源 DSN，所有源端请求都会带上
+    let src_limits = parse_query_limits(&opt.src_query_limits)?;
+    opt.src_dsn = dsn_with_settings(&opt.src_dsn, &src_limits);
+    // 查询优先级、workload 与设置档附加到两端 DSN
+    priority::apply(&mut opt)?;
+    state_dir::prepare(&mut opt)?;
+    events::say(&format!("datacp 启动，参数: {:?}", opt));
+    let log_file = OpenOptions::new().create(true).append(true).open(&opt.log_file)?;
     let log_file = std::sync::Mutex::new(log_file);
     env_logger::Builder::from_default_env()
         .format(move |buf, record| {
@@ -533,207 +2362,1405 @@ async fn main() -> Result<()> {
                 record.level(),
                 record.args()
             );
+            if record.level() == log::Level::Error {
+                status::error(&record.args().to_string());
+            }
             let _ = log_file.write_all(log_line.as_bytes());
             let _ = log_file.flush(); // 强制落盘，防止日志丢失或混行
+            if tui::capture(log_line.trim_end()) {
+                return Ok(());
+            }
             writeln!(buf, "{}", log_line.trim_end())
         })
         .target(env_logger::Target::Stderr)
         .init();
+    if let Some(msg) = &manifest_drift {
+        warn!("{}", msg);
+    }
+    // 以下初始化会输出日志，须在日志初始化之后
+    segment::init(&opt)?;
+    blackout::init(&opt)?;
+
+    src_replica::init(&mut opt).await?;
+    src_limit::init(&opt.src_dsn, opt.src_max_concurrent_queries);
+    if !serving {
+        // 两端版本与功能检测：明确指定的参数不受支持时启动即拒绝
+        server_version::check(&opt).await?;
+        priority::check(&opt).await?;
+    }
+    if !opt.sql_log.is_empty() {
+        sql_log::init(&opt.sql_log, &opt.src_dsn, &opt.dst_dsn)?;
+    }
+    match &opt.cmd {
+        Some(Command::Cleanup) => return sql_log::closing(cutover::cleanup_bak_tables(&opt).await),
+        Some(Command::Ddl { objects, dry_run, create_rewrite, create_strip, create_set }) => {
+            let rules = create_rewrite::Rules::parse(create_rewrite, create_strip, create_set)?;
+            return sql_log::closing(ddl::copy_ddl(&opt, objects, *dry_run, &rules).await);
+        }
+        Some(Command::Plan { histogram, target_segment_rows }) => {
+            return sql_log::closing(multi::print_plan(&opt, *histogram, *target_segment_rows).await)
+        }
+        Some(Command::Status) => return sql_log::closing(multi::print_status(&opt).await),
+        Some(Command::Verify { verify_strategy }) => return sql_log::closing(verify::run(&opt, verify_strategy).await),
+        Some(Command::Checkpoint { cmd: CheckpointCommand::Merge { files, output } }) => return checkpoint::merge(files, output),
+        Some(Command::Checkpoint { cmd: CheckpointCommand::Gaps { output } }) => {
+            let done_segments_file = state_dir::done_segments(&opt)?;
+            return sql_log::closing(checkpoint::gaps(&opt, &done_segments_file, output).await);
+        }
+        Some(Command::Replay { sql_log: path, only, dry_run }) => {
+            return sql_log::closing(sql_log::replay(&opt, path, only, *dry_run).await)
+        }
+        Some(Command::Bench { .. }) => return sql_log::closing(bench::run(&opt).await),
+        Some(Command::ResumeCutover) | Some(Command::Serve { .. }) | Some(Command::Migrate { .. }) | None => {}
+    }
+    supervise::check(&opt, serving)?;
+    events::run_started(&opt);
+    status::init(&opt).await?;
+    let _push_guard = pushgateway::start(&opt);
+    info!("源端查询限制: {}", src_limits.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join(", "));
+    let insert_permits = Arc::new(tokio::sync::Semaphore::new(if opt.max_concurrent_inserts == 0 {
+        tokio::sync::Semaphore::MAX_PERMITS
+    } else {
+        opt.max_concurrent_inserts
+    }));
+    coordination::start(&opt, &insert_permits).await?;
+    if let Some(Command::Serve { listen, max_concurrent_jobs, token }) = &opt.cmd {
+        return serve::run(&opt, listen, *max_concurrent_jobs, token, insert_permits).await;
+    }
+    let resume = match opt.cmd {
+        Some(Command::ResumeCutover) if !opt.tables_file.is_empty() || opt.all_tables => {
+            anyhow::bail!("resume-cutover 只支持单表，请按表分别指定 --src-table / --dst-table")
+        }
+        Some(Command::ResumeCutover) => Some(cutover_state::load(&opt)?),
+        // 单表运行遇到未完成的切换（如源表已改名为 _bak 后进程退出）时自动继续
+        None if opt.tables_file.is_empty() && !opt.all_tables => cutover_state::pending(&opt)?,
+        _ => None,
+    };
+    let tui_guard = tui::start(&opt);
+    if !opt.tables_file.is_empty() || opt.all_tables {
+        let (entries, skipped) = multi::resolve_tables(&opt).await?;
+        let mut multi_report = multi::run_tables(&opt, entries, skipped, insert_permits).await;
+        // 汇总输出回到普通终端
+        drop(tui_guard);
+        status::set_phase("done");
+        coordination::leave().await;
+        multi_report.finish();
+        multi_report.cluster_ddl = cluster::statements();
+        if let Err(e) = multi_report.write(&opt.report_file) {
+            error!("写入报告失败: {e}");
+        }
+        let failed = multi_report.tables.iter().filter(|t| t.outcome != "ok").count();
+        info!("多表迁移结束: 成功 {}, 未完全成功 {}", multi_report.tables.len() - failed, failed);
+        info!("运行文件: {}，各表断点续传文件在其下的子目录", state_dir::summary(&opt, None));
+        if src_limit::waited_seconds() > 0 {
+            warn!("本次运行等待源端查询并发许可累计 {}s（计入读源端阶段）", src_limit::waited_seconds());
+        }
+        if blackout::paused_seconds() > 0 {
+            info!("本次运行在维护窗口内暂停累计 {}s", blackout::paused_seconds());
+        }
+        multi_report.print_summary();
+        events::run_finished_with(serde_json::json!({
+            "tables": multi_report.tables.len(),
+            "tables_failed": failed,
+            "exit_code": multi_report.exit_code,
+        }));
+        pushgateway::finish(multi_report.exit_code);
+        sql_log::close();
+        std::process::exit(multi_report.exit_code);
+    }
+    let done_segments_file = state_dir::done_segments(&opt)?;
+    supervise::watch_interrupt(&opt);
+    let mut resume = resume;
+    let mut attempts = Vec::new();
+    let (report, res) = loop {
+        let report = Arc::new(std::sync::Mutex::new(report::RunReport::new(&opt)));
+        // resume-cutover：补差已完成时直接继续目标表 rename 及之后的步骤，否则按 _bak 表重建上下文后从补差继续
+        let res = match resume.take() {
+            Some(mut s) if s.done(cutover_state::Step::BakFilled) => {
+                report.lock().unwrap().cutover = "started".to_string();
+                status::set_phase("cutover");
+                s.finish(&time_zone::init(&opt).await?, &report, &done_segments_file).await
+            }
+            Some(s) => run_migration(&s.run_opt(&opt), &done_segments_file, report.clone(), insert_permits.clone(), Some(s)).await,
+            None => run_migration(&opt, &done_segments_file, report.clone(), insert_permits.clone(), None).await,
+        };
+        // --supervise：可重试的整体失败等待后重启，断点续传记录由 run_migration 重新读取
+        let Some(backoff) = supervise::restart(&opt, &report, &res, &mut attempts) else { break (report, res) };
+        status::set_phase("restart-backoff");
+        tokio::time::sleep(backoff).await;
+        resume = cutover_state::pending(&opt)?;
+    };
+    drop(tui_guard);
+    status::set_phase("done");
+    coordination::leave().await;
+    // 迁移中途失败时同样恢复已暂停的物化视图与目标表合并
+    for e in restore_paused(&opt, &done_segments_file).await {
+        error!("{e}");
+    }
+    let code = {
+        let mut r = report.lock().unwrap();
+        r.finish(&res);
+        r.attempts = attempts;
+        if r.readonly_wait_seconds > 0 {
+            warn!("本次运行因目标端只读/part 过多累计等待 {}s", r.readonly_wait_seconds);
+        }
+        r.src_query_wait_seconds = src_limit::waited_seconds();
+        r.blackout_paused_seconds = blackout::paused_seconds();
+        if r.blackout_paused_seconds > 0 {
+            info!("本次运行在维护窗口内暂停累计 {}s", r.blackout_paused_seconds);
+        }
+        r.src_replica = src_replica::served();
+        r.cluster_ddl = cluster::statements();
+        if r.src_query_wait_seconds > 0 {
+            warn!("本次运行等待源端查询并发许可累计 {}s（计入读源端阶段）", r.src_query_wait_seconds);
+        }
+        if let Err(e) = r.write(&opt.report_file) {
+            error!("写入报告失败: {e}");
+        }
+        if let Err(e) = &res {
+            error!("迁移失败: {e}");
+        }
+        info!(
+            "迁移结束: {}，分段完成 {}，失败 {}，写入 {} 行，耗时 {}s，切换 {}，切换后校验 {}",
+            r.outcome, r.segments_done, r.segments_failed.len(), r.rows_written, r.duration_seconds, r.cutover, r.verification()
+        );
+        if let Some(c) = &r.circuit_broken {
+            error!(
+                "已熔断（{}），在 {} 阶段前停止；首个错误（segment {}）: {}；最常见错误（{} 次）: {}",
+                c.reason, c.phase, c.first_segment, c.first_error, c.most_common_count, c.most_common_error
+            );
+        }
+        info!("运行文件: {}", state_dir::summary(&opt, Some(&done_segments_file)));
+        let code = r.exit_code();
+        events::run_finished(&r, code);
+        code
+    };
+    pushgateway::finish(code);
+    sql_log::close();
+    std::process::exit(code)
+}
 
-    // 1. 表结构校验（传入 ignore_fields）
+// 运行结束（含失败）：恢复暂停的物化视图（--pause-mvs）与目标表合并（--pause-dst-merges），
+// 包括上次运行被结束时留下的记录；两者都会尝试，返回各自的错误。单表、多表与 serve 任务的每条退出路径都调用
+async fn restore_paused(opt: &Opt, done_segments_file: &str) -> Vec<anyhow::Error> {
+    let mut errors = Vec::new();
+    if let Err(e) = ddl::resume_mvs(opt, done_segments_file).await {
+        errors.push(e);
+    }
+    if let Err(e) = merge_pause::restore(opt, done_segments_file).await {
+        errors.push(e);
+    }
+    errors
+}
+
+// 按并发数切分分段并启动 worker，等待全部完成（src_table 可为 _bak 表）
+// 一次性运行：启动 worker、处理完给定分段后退出（--calibrate 试跑、_bak 补差）
+async fn run_segment_workers(
+    opt: &Opt,
+    src_table: &str,
+    segments: Vec<String>,
+    col_names: &[String],
+    done_segments_file: &str,
+    client: &Arc<endpoint::Clients>,
+    ctx: &Arc<RunCtx>,
+) {
+    if segments.is_empty() {
+        return;
+    }
+    let pool = WorkerPool::start(opt, src_table, col_names, done_segments_file, client, ctx);
+    pool.run(segments).await;
+    pool.shutdown().await;
+}
+
+// 共享队列上的一组常驻 worker，首轮、增量与重新校验复用
+struct WorkerPool {
+    queue: Arc<work_queue::SegmentQueue>,
+    handles: std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>>,
+    spawn: Box<dyn Fn(usize) -> tokio::task::JoinHandle<()> + Send + Sync>, // 启动第 i 个 worker（看门狗中止后原位替换）
+    ctx: Arc<RunCtx>,
+    stall_timeout: Duration,
+}
+
+// 提前返回（出错）时关闭队列，空闲 worker 随之退出
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+// 后台轮询任务：提前返回或迁移被中止（serve 取消任务）时一并停止
+struct AbortOnDrop(Vec<tokio::task::AbortHandle>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        for h in &self.0 {
+            h.abort();
+        }
+    }
+}
+
+impl WorkerPool {
+    fn start(
+        opt: &Opt,
+        src_table: &str,
+        col_names: &[String],
+        done_segments_file: &str,
+        client: &Arc<endpoint::Clients>,
+        ctx: &Arc<RunCtx>,
+    ) -> Self {
+        let sorted_col_names = ctx.columns.digest.clone();
+        let queue = Arc::new(work_queue::SegmentQueue::new(ctx.breaker.clone()));
+        let (o, q, src_table, col_names, done_segments_file, client, c) =
+            (opt.clone(), queue.clone(), src_table.to_string(), col_names.to_vec(), done_segments_file.to_string(), client.clone(), ctx.clone());
+        let spawn = move |worker: usize| {
+            tokio::spawn(transfer::scope(o.src_dsn.clone(), migrate_segment_worker_http(
+                q.clone(),
+                o.src_dsn.clone(),
+                o.dst_dsn.clone(),
+                o.src_db.clone(),
+                o.dst_db.clone(),
+                src_table.clone(),
+                o.dst_table.clone(),
+                o.time_field.clone(),
+                col_names.clone(),
+                sorted_col_names.clone(),
+                o.ignore_field.clone(),
+                done_segments_file.clone(),
+                o.log_file.clone(),
+                client.clone(),
+                c.clone(),
+                worker,
+            )))
+        };
+        let handles = (0..opt.parallelism.max(1)).map(&spawn).collect();
+        WorkerPool {
+            queue,
+            handles: std::sync::Mutex::new(handles),
+            spawn: Box::new(spawn),
+            ctx: ctx.clone(),
+            stall_timeout: opt.global_stall_timeout,
+        }
+    }
+
+    // 放入一批分段并等待全部处理完（已入队、处理中或已完成的分段不重复处理）
+    async fn run(&self, segments: Vec<String>) {
+        if segments.is_empty() {
+            return;
+        }
+        self.queue.push(segments);
+        self.wait().await;
+    }
+
+    // 重新校验一批分段（已完成的也重新比对）并等待全部处理完
+    async fn recheck(&self, segments: Vec<String>) {
+        if segments.is_empty() {
+            return;
+        }
+        self.queue.requeue(segments);
+        self.wait().await;
+    }
+
+    // 等待队列清空；--global-stall-timeout 时等待期间由看门狗处理卡住的分段
+    async fn wait(&self) {
+        if self.stall_timeout.is_zero() {
+            self.queue.wait_idle().await;
+            return;
+        }
+        let idle = self.queue.wait_idle();
+        tokio::pin!(idle);
+        loop {
+            tokio::select! {
+                _ = &mut idle => return,
+                _ = tokio::time::sleep(watchdog::poll_interval(self.stall_timeout)) => {}
+            }
+            if let Some(stuck) = watchdog::stalled(self.stall_timeout) {
+                self.recover(stuck).await;
+            }
+        }
+    }
+
+    // 看门狗已取消的分段：宽限期内未自行退出的强制中止所在 worker，分段记为失败后原位启动新的 worker
+    async fn recover(&self, stuck: Vec<watchdog::Stuck>) {
+        let deadline = std::time::Instant::now() + watchdog::GRACE;
+        while std::time::Instant::now() < deadline && stuck.iter().any(watchdog::still_on) {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        let workers = self.handles.lock().unwrap().len();
+        for s in stuck.iter().filter(|s| s.worker < workers && watchdog::still_on(s)) {
+            error!("segment {} failed: 取消后 {}s 仍未退出，强制中止 worker {}", s.segment, watchdog::GRACE.as_secs(), s.worker);
+            self.handles.lock().unwrap()[s.worker].abort();
+            while !self.handles.lock().unwrap()[s.worker].is_finished() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            self.ctx.segment_failed(&s.segment, "全局停滞超时（--global-stall-timeout），看门狗已强制中止");
+            status::segment_finished(s.worker);
+            self.handles.lock().unwrap()[s.worker] = (self.spawn)(s.worker);
+        }
+        status::progress();
+    }
+
+    async fn shutdown(self) {
+        self.queue.close();
+        let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+        join_all(handles).await;
+    }
+}
+
+// 迁移主流程：结构校验、分段迁移、增量迁移、_bak 补差与最终切换；
+// resume 为 resume-cutover 读取的切换状态，此时跳过分段与增量迁移，从第一个未完成的切换步骤继续
+async fn run_migration(
+    opt: &Opt,
+    done_segments_file: &str,
+    report: Arc<std::sync::Mutex<report::RunReport>>,
+    insert_permits: Arc<tokio::sync::Semaphore>,
+    resume: Option<cutover_state::CutoverState>,
+) -> Result<()> {
+    if resume.is_none() {
+        cutover_state::check_pending(opt)?;
+    }
+    let ignore_fields = &opt.ignore_field;
+    let done_segments_file = done_segments_file.to_string();
+    // 1. 表结构校验（传入 ignore_fields）；只在比对时忽略的字段必须两端都存在
+    check_compare_ignored_fields(opt).await?;
+    identity_risk::check(opt, &report).await?;
+    cutover::check_cutover_into_src_db(opt)?;
+    preflight::check_identity(opt).await?;
+    preflight::check_replicated_rename(opt, &report).await?;
+    row_policy::check(opt, &report).await?;
+    clock_skew::check(opt, &report).await?;
+    merge_pause::check(opt).await?;
     compare_table_columns_http(
-        &opt.src_dsn, &opt.src_db, &opt.dst_dsn, &opt.dst_db, &opt.src_table, &opt.dst_table, ignore_fields
+        &opt.src_dsn, &opt.src_db, &opt.dst_dsn, &opt.dst_db, &opt.src_table, opt.read_table(), ignore_fields
     ).await?;
-    // 2. 获取字段名，过滤 ignore_fields
-    let all_col_names = get_column_names_http(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
-    let col_names: Vec<String> = all_col_names.iter().filter(|c| !is_ignored_field(c, ignore_fields)).cloned().collect();
-    let mut sorted_col_names = col_names.clone();
-    sorted_col_names.sort();
-    // 3. 校验时间字段
-    if !col_names.contains(&opt.time_field) {
-        error!("time_field {} 不存在于表结构", opt.time_field);
-        return Err(anyhow::anyhow!("time_field 不存在"));
-    }
-    // 4. 获取时间范围
-    info!("get_time_range SQL: SELECT min({}), max({}) FROM {} WHERE {} >= '{}'", opt.time_field, opt.time_field, opt.src_table, opt.time_field, opt.start_time);
-    let (min_time, max_time) = get_time_range_http(&opt.src_dsn, &opt.src_db, &opt.src_table, &opt.time_field, &opt.start_time).await?;
+    // 1.1 写入表与读取表不同时（Buffer/Null 中转表），写入表同样需要与源表字段一致
+    if opt.read_table() != opt.dst_table {
+        compare_table_columns_http(
+            &opt.src_dsn, &opt.src_db, &opt.dst_dsn, &opt.dst_db, &opt.src_table, &opt.dst_table, ignore_fields
+        ).await?;
+        info!("写入表 {} 与读取表 {} 分离", opt.dst_table, opt.read_table());
+    }
+    // 2. 字段计划：源表字段过滤 ignore_fields 后按目标写入表的顺序排列，SELECT 与 INSERT 字段列表统一使用；
+    // 参与比对的字段再去掉 --ignore-compare-field，用于摘要与目标端比对查询
+    let plan = column_plan::ColumnPlan::build(opt).await?;
+    let (col_names, compare_col_names, sorted_col_names) = (plan.columns.clone(), plan.compare.clone(), plan.digest.clone());
+    // 2.0 --column-default：比对两端默认值定义，覆盖的列两端按同一表达式读取
+    let column_defaults = column_default::check(opt, &col_names).await?;
+    // 2.1 源表为 Replacing/Collapsing 系列引擎时提示使用 --select-final
+    let (src_local_db, src_local_table) = shard::resolve_local_table(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    let (src_engine, _) = shard::table_engine(&opt.src_dsn, &src_local_db, &src_local_table).await?;
+    if !opt.select_final && (src_engine.contains("Replacing") || src_engine.contains("Collapsing")) {
+        warn!("源表引擎为 {}，未合并的重复版本会被一并复制，建议使用 --select-final", src_engine);
+    }
+    // 3. 校验时间字段（表达式时校验其引用的列，Nested 子列与 Tuple 元素按所属列校验）
+    for c in time_expr::referenced_columns(opt) {
+        if !time_expr::column_exists(&col_names, &c) {
+            if src_query::enabled(opt) {
+                error!("time_field {} 不存在于 --src-query 的输出列（{}）", c, col_names.join(", "));
+            } else {
+                error!("time_field {} 不存在于表结构", c);
+            }
+            return Err(anyhow::anyhow!("time_field 不存在"));
+        }
+    }
+    // 3.0 时间字段声明了时区时，之后的时间条件、分段键与断点续传记录均为 UTC
+    let opt = &time_zone::init(opt).await?;
+    // 3.1 校验 --where 谓词，并与断点续传记录的谓词比对
+    validate_filter(opt).await?;
+    // resume-cutover 时 opt 的源表可能是 _bak，元数据按原表名比对
+    checkpoint_meta::check(&done_segments_file, &resume.as_ref().map(|s| s.original(opt)).unwrap_or_else(|| opt.clone()))?;
+    // 3.2 表结构指纹：首次运行记录，续传时与实时结构比对
+    schema_fingerprint::check(opt, &done_segments_file, "resume", &report).await?;
+    // 3.3 --from-manifest：两端表结构须与清单记录的一致
+    manifest::check_schema(opt).await?;
+    // 4. 获取时间范围（归档模式限制在截止时间之前）
+    let archive_cutoff = if opt.archive { archive::cutoff(opt).await? } else { String::new() };
+    let (min_time, max_time) = if opt.archive {
+        info!("归档模式: 截止时间 {}", archive_cutoff);
+        archive::time_range(opt, &archive_cutoff).await?
+    } else {
+        info!("get_time_range SQL: SELECT min({}), max({}) FROM {} WHERE {} >= {}", opt.time_field, opt.time_field, qualified(&opt.src_db, &opt.src_table), opt.time_field, opt.time_lit(&opt.start_time));
+        get_time_range_http(&opt.src_dsn, &opt.src_db, &opt.src_table, &opt.time_field, &opt.start_time, &row_filter(opt), opt.time_zone.as_ref()).await?
+    };
     info!("get_time_range result: min_time='{}', max_time='{}'", min_time, max_time);
-    if min_time.is_empty() || max_time.is_empty() {
+    let (min_time, max_time) = manifest::time_range(min_time, max_time);
+    if (min_time.is_empty() || max_time.is_empty()) && resume.is_none() {
         error!("数据源无数据，任务终止");
         return Ok(());
     }
-    println!("min_time: {}, max_time: {}", min_time, max_time);
-    // 5. 断点续传记录
-    let done_segments = load_done_segments(&done_segments_file)?;
-    // 6. 分段并发迁移主流程
-    let segments = generate_hourly_segments_with_skip(&min_time, &max_time, &done_segments);
-    let segment_chunks: Vec<Vec<String>> = segments.chunks((segments.len() + parallelism - 1) / parallelism).map(|c| c.to_vec()).collect();
-    let mut handles = Vec::new();
-    let client = Arc::new(reqwest::Client::builder()
-        .timeout(Duration::from_secs(30))
-        .pool_max_idle_per_host(16)
-        .build()?);
-    for chunk in segment_chunks {
-        let src_dsn = opt.src_dsn.clone();
-        let dst_dsn = opt.dst_dsn.clone();
-        let src_db = opt.src_db.clone();
-        let dst_db = opt.dst_db.clone();
-        let src_table = opt.src_table.clone();
-        let dst_table = opt.dst_table.clone();
-        let time_field = opt.time_field.clone();
-        let col_names = col_names.clone();
-        let sorted_col_names = sorted_col_names.clone();
-        let ignore_fields = ignore_fields.clone();
-        let done_segments_file = done_segments_file.clone();
-        let log_file_path = log_file_path.clone();
-        let client = client.clone();
-        handles.push(tokio::spawn(migrate_segment_worker_http(
-            chunk,
-            src_dsn,
-            dst_dsn,
-            src_db,
-            dst_db,
-            src_table,
-            dst_table,
-            time_field,
-            col_names,
-            sorted_col_names,
-            ignore_fields,
-            done_segments_file,
-            log_file_path,
-            client.clone(),
-        )));
-    }
-    join_all(handles).await;
-
-    // 7. 增量迁移循环
+    events::say(&format!("min_time: {}, max_time: {}", min_time, max_time));
+    // 4.0 时间字段是否命中源表排序键/分区键（未命中时每个分段都是全表扫描）
+    if let (Some(hour), None, false) = (min_time.get(..13), &resume, src_query::enabled(opt)) {
+        preflight::check_time_index(opt, &format!("{}:00:00", hour), &report).await?;
+    }
+    // 4.1 目标端磁盘空间检查
+    preflight::check_disk_space(opt, &opt.start_time, &report).await?;
+    // 4.2 源表 TTL：计算过期边界，边界内的差异不计入比对、镜像模式不删除
+    let source_ttl = ttl::SourceTtl::detect(opt).await?;
+    // 4.3 记录源表 mutation 快照并在迁移期间轮询
+    let mutation_watch = Arc::new(mutations::MutationWatch::snapshot(opt).await?);
+    let mutation_task = mutation_watch.spawn(report.clone());
+    // 5. 断点续传记录与分段黑名单
+    let mut done_segments = load_done_segments(&done_segments_file)?;
+    let blacklist = SegmentBlacklist::load(&opt.skip_segments_file)?;
+    // 5.0 --only-segments / --segment-range：不论断点续传记录，只重新迁移指定的分段
+    let rerun = if resume.is_none() { checkpoint::load_rerun(opt, &min_time, &max_time)? } else { None };
+    // 5.0 --coalesce-empty：按天探测，无数据日期内的分段以范围记录标记为完成
+    if opt.coalesce_empty && resume.is_none() && rerun.is_none() && opt.copy_mode != "attach-partition" && !min_time.is_empty() {
+        coalesce::run(opt, &min_time, &max_time, &done_segments_file, &mut done_segments).await?;
+    }
+    let only = checkpoint::load_only(opt)?;
+    // 5.1 暂停由目标表触发的物化视图，避免回填期间的 MV 扇出
+    if opt.pause_mvs && resume.is_none() {
+        ddl::pause_mvs(opt, &done_segments_file).await?;
+    }
+    // 5.2 --pause-dst-merges：首轮分段迁移期间暂停目标表合并，守卫保证提前返回时恢复
+    let merges = if opt.pause_dst_merges && resume.is_none() { Some(merge_pause::pause(opt, &done_segments_file, &report).await?) } else { None };
+    // 6. 分段并发迁移主流程（attach-partition 模式按分区挂载，替代分段拷贝）
+    status::set_phase("backfill");
+    priority::nice(opt);
+    let backfill_started = std::time::Instant::now();
+    let segments = if resume.is_some() {
+        Vec::new()
+    } else if opt.copy_mode == "attach-partition" {
+        server_copy::attach_partitions(opt, &done_segments_file, &done_segments, &report).await?;
+        Vec::new()
+    } else if let Some(rerun) = &rerun {
+        rerun.iter().filter(|s| !blacklist.contains(s)).cloned().collect()
+    } else {
+        // --only-segments-file：修补运行只处理列出且尚未完成的分段
+        plan_segments(&min_time, &max_time, &done_segments, &blacklist, only.as_ref())?
+    };
+    let client = Arc::new(endpoint::Clients::new()?);
+    let dst_router = if opt.dst_write_local {
+        Some(shard::resolve_shard_router(&opt.dst_dsn, &opt.dst_db, &opt.dst_table).await?)
+    } else {
+        None
+    };
+    let remote_source = match opt.copy_mode.as_str() {
+        "http" | "attach-partition" => None,
+        "remote-secure" => Some(server_copy::resolve_remote_source(opt).await?),
+        m => anyhow::bail!(format!("不支持的 --copy-mode: {}", m)),
+    };
+    let optimizer = optimize::Optimizer::new(opt, report.clone()).await?.map(Arc::new);
+    let optimize_task = optimizer.as_ref().and_then(|o| o.spawn());
+    let _pollers = AbortOnDrop(std::iter::once(mutation_task.abort_handle()).chain(optimize_task.as_ref().map(|t| t.abort_handle())).collect());
+    let binary = binary::BinaryColumns::detect(opt, &col_names).await?.with_defaults(column_defaults);
+    // 2.2 --normalize：规范化的列两端按同一规则比对，规则写入报告
+    let normalize = normalize::Normalize::plan(opt, &binary, &compare_col_names)?;
+    report.lock().unwrap().normalization = normalize.report();
+    report.lock().unwrap().priority = priority::report(opt);
+    report.lock().unwrap().server_versions = server_version::report(opt);
+    let binary = binary.with_normalize(normalize.clone());
+    let server_digest = server_digest::ServerDigest::plan(opt, &binary, &sorted_col_names)?;
+    // 运行清单：生效参数、版本、表结构指纹、分段边界与策略
+    if resume.is_none() {
+        let row_policies = report.lock().unwrap().row_policies.clone();
+        manifest::write(opt, &min_time, &max_time, &segments, server_digest.is_some(), row_policies).await?;
+    }
+    let breaker = Arc::new(work_queue::CircuitBreaker::new(opt)?);
+    let ctx = Arc::new(RunCtx {
+        dst_router,
+        remote_source,
+        remote_query_timeout: opt.remote_query_timeout,
+        mutation_watch: mutation_watch.clone(),
+        optimizer: optimizer.clone(),
+        where_filter: filter_sql(&opt.filter),
+        shard_filter: shard_of::filter_sql(opt),
+        whole_table: std::sync::atomic::AtomicBool::new(false),
+        bak_phase: std::sync::atomic::AtomicBool::new(false),
+        dst_read_table: opt.read_table().to_string(),
+        columns: plan,
+        failed_segments: std::sync::Mutex::new(Vec::new()),
+        rows_written: std::sync::atomic::AtomicU64::new(0),
+        mirror: mirror::Mirror::new(opt, &col_names).await?,
+        pager: pager::Pager::new(opt, &col_names, &done_segments_file)?,
+        staged: staged::Stage::new(opt, &done_segments_file, &done_segments).await?,
+        post_count: !opt.no_post_count,
+        post_count_tolerance: opt.post_count_tolerance,
+        overcopy: overcopy::Deduplicator::new(opt, &col_names).await?,
+        over_copied: std::sync::Mutex::new(Vec::new()),
+        ttl: source_ttl,
+        ttl_affected: std::sync::Mutex::new(Vec::new()),
+        batch_bytes: std::sync::atomic::AtomicU64::new(opt.batch_bytes),
+        insert_spill_memory: opt.insert_spill_memory,
+        rows_read: std::sync::atomic::AtomicU64::new(0),
+        insert_errors: std::sync::atomic::AtomicU64::new(0),
+        segment_timings: std::sync::Mutex::new(Vec::new()),
+        segment_bytes: std::sync::Mutex::new(Vec::new()),
+        worker_walls: std::sync::Mutex::new(Vec::new()),
+        mirror_deletes: std::sync::Mutex::new(Vec::new()),
+        bad_rows: bad_rows::BadRows::new(opt)?,
+        deadline: deadline::Deadline::new(opt, breaker.clone()),
+        breaker,
+        binary,
+        server_digest,
+        normalize,
+        bad_row_segments: std::sync::Mutex::new(Vec::new()),
+        oversized: oversized::Oversized::new(opt)?,
+        rejected: rejected::Rejected::new(opt)?,
+        insert_permits,
+        memory: memory::budget(opt),
+        split_min_window: opt.split_min_window,
+        max_splits_per_segment: opt.max_splits_per_segment,
+        time_zone: opt.time_zone.clone(),
+        write_gate: write_gate::WriteGate::new(opt, report.clone()).await?,
+        select_final: opt.select_final,
+        dst_select_final: opt.select_final && opt.dst_select_final,
+    });
+    status::begin_table(opt, &ctx);
+    let _clock = AbortOnDrop(vec![clock_skew::spawn(opt, report.clone(), ctx.clone()).abort_handle()]);
+    // 6.0 --replace-partitions：首轮按分区整体替换，替代分段比对；之后的增量仍按分段比对
+    let mut segments = segments;
+    if opt.replace_partitions && resume.is_none() && rerun.is_none() {
+        replace::run(opt, &min_time, &max_time, &col_names, &done_segments_file, &done_segments, &client, &ctx, &report).await?;
+        segments.clear();
+    }
+    // 6.1 --calibrate：前若干分段试跑不同批量与并发组合，之后按最优组合迁移
+    let tuned = if opt.calibrate && resume.is_none() {
+        Some(calibrate::run(opt, &mut segments, &col_names, &done_segments_file, &client, &ctx, &report).await)
+    } else {
+        None
+    };
+    let opt = tuned.as_ref().unwrap_or(opt);
+    let pool = WorkerPool::start(opt, &opt.src_table, &col_names, &done_segments_file, &client, &ctx);
+    pool.run(segments).await;
+    // 重新迁移的分段完成后两端重新比对，刷新断点续传文件中的校验记录
+    if let Some(rerun) = &rerun {
+        background_verify::refresh(opt, &ctx, &done_segments_file, rerun, &report).await;
+    }
+    // 首轮分段的实测吞吐记入断点续传元数据，作为下次运行 plan/status 的速率基线
+    let (secs, bytes) = (backfill_started.elapsed().as_secs_f64(), ctx.segment_bytes.lock().unwrap().iter().map(|b| b.uncompressed_bytes).sum::<u64>());
+    if bytes > 0 && secs > 0.0 {
+        let rows = ctx.rows_written.load(std::sync::atomic::Ordering::Relaxed) as f64;
+        if let Err(e) = checkpoint_meta::record_throughput(&done_segments_file, bytes as f64 / secs, rows / secs) {
+            warn!("记录吞吐失败: {e}");
+        }
+    }
+    // 6.2 首轮结束：恢复目标表合并，增量长尾期间不堆积 part
+    if let Some(m) = &merges {
+        m.restart().await;
+    }
+
+    // 7. 增量迁移循环（归档模式无增量）；设置 --cutover-when / --cutover-at 时按条件决定何时结束追平。
+    // 新分段放入常驻 worker 的队列；未派发时按间隔休眠，min/max 查询与读取共用源端并发上限
+    let mut catchup = catchup::CatchUp::new(opt)?;
+    let mut standby = standby::Standby::new(opt)?;
+    let mut background = if opt.archive || resume.is_some() { None } else { background_verify::BackgroundVerify::new(opt, &done_segments_file, &report)? };
     let mut cur_max_time = max_time.clone();
+    if let (Some(t), None) = (phase_checkpoint::last_incremental(&done_segments), &resume) {
+        info!("上次运行增量迁移已推进到 {}，未完成的分段按断点续传记录补齐", t);
+    }
+    let mut last_seen_max = String::new(); // 上次检查时的源端最大时间，用于判断源端是否仍在增长
+    if !opt.archive {
+        status::set_phase(if standby.is_some() { "standby" } else { "incremental" });
+    }
     loop {
-        let (new_min, new_max) = get_time_range_http(&opt.src_dsn, &opt.src_db, &opt.src_table, &opt.time_field, &cur_max_time).await?;
-        if new_min.is_empty() || new_max <= cur_max_time {
-            info!("无新增数据，增量迁移完成");
+        if opt.archive || resume.is_some() || only.is_some() || rerun.is_some() || ctx.deadline.hit(&report, "incremental") {
             break;
         }
-        info!("检测到新数据，增量迁移 {} ~ {}", new_min, new_max);
+        let (new_min, new_max) = get_time_range_http(&opt.src_dsn, &opt.src_db, &opt.src_table, &opt.time_field, &cur_max_time, &ctx.filter(), opt.time_zone.as_ref()).await?;
+        status::progress();
+        let has_new = !new_min.is_empty() && new_max > cur_max_time;
+        // 复制延迟：源端最大时间减去最新已完成分段的结束时间，供状态接口、报告与切换判定共用
         let done_segments = load_done_segments(&done_segments_file)?;
-        let segments = generate_hourly_segments_with_skip(&new_min, &new_max, &done_segments);
-        let segment_chunks: Vec<Vec<String>> = segments.chunks((segments.len() + parallelism - 1) / parallelism).map(|c| c.to_vec()).collect();
-        let mut handles = Vec::new();
-        for chunk in segment_chunks {
-            let src_dsn = opt.src_dsn.clone();
-            let dst_dsn = opt.dst_dsn.clone();
-            let src_db = opt.src_db.clone();
-            let dst_db = opt.dst_db.clone();
-            let src_table = opt.src_table.clone();
-            let dst_table = opt.dst_table.clone();
-            let time_field = opt.time_field.clone();
-            let col_names = col_names.clone();
-            let sorted_col_names = sorted_col_names.clone();
-            let ignore_fields = ignore_fields.clone();
-            let done_segments_file = done_segments_file.clone();
-            let log_file_path = log_file_path.clone();
-            let client = client.clone();
-            handles.push(tokio::spawn(migrate_segment_worker_http(
-                chunk, src_dsn, dst_dsn, src_db, dst_db, src_table, dst_table, time_field, col_names, sorted_col_names, ignore_fields, done_segments_file, log_file_path, client.clone(),
-            )));
+        let src_max = if has_new { &new_max } else { &cur_max_time };
+        let lag = catchup::lag_seconds(src_max, &done_segments);
+        info!("复制延迟: {}s（源端最大时间 {}）", lag, src_max);
+        status::set_lag(lag);
+        report.lock().unwrap().replication_lag.push(report::LagSample { time: report::now_str(), lag_seconds: lag });
+        let trigger = match (&mut standby, &mut catchup) {
+            (Some(s), _) => s.poll(),
+            (None, None) if !has_new => Some("no-new-data"),
+            (None, None) => None,
+            (None, Some(c)) => c.ready(opt, lag).await?,
+        };
+        let mut dispatched = false;
+        if has_new {
+            let segments = plan_segments(&new_min, &new_max, &done_segments, &blacklist, None)?;
+            // 攒批：新分段不足 --incremental-batch-hours 时等待；源端不再增长、即将或已经满足切换条件时立即派发
+            let approaching = trigger.is_some() || catchup.as_ref().map(|c| c.approaching()).unwrap_or(false);
+            if segments.is_empty() || segments.len() >= opt.incremental_batch_hours || approaching || new_max == last_seen_max {
+                info!("检测到新数据，增量迁移 {} ~ {}（{} 个分段）", new_min, new_max, segments.len());
+                pool.run(segments).await;
+                cur_max_time = new_max.clone();
+                if let Err(e) = phase_checkpoint::record_incremental(&done_segments_file, &cur_max_time) {
+                    error!("save_done_segment failed: {e}");
+                }
+                dispatched = true;
+            } else {
+                info!(
+                    "检测到新数据 {} ~ {}，{} 个分段不足 --incremental-batch-hours {}，暂不迁移",
+                    new_min, new_max, segments.len(), opt.incremental_batch_hours
+                );
+            }
+        }
+        last_seen_max = new_max;
+        if let Some(t) = trigger {
+            // 热备模式：最后一轮增量已派发，副本延迟检查通过且未被 cancel 才开始切换
+            if let Some(s) = &mut standby {
+                if !s.confirm(opt, &report).await {
+                    continue;
+                }
+            }
+            info!("增量迁移完成（{}）", t);
+            report.lock().unwrap().cutover_trigger = Some(t.to_string());
+            break;
+        }
+        if let Some(s) = &mut standby {
+            s.verify(&ctx, &pool, &cur_max_time, lag, &blacklist, &report).await;
+        }
+        if !dispatched {
+            // 本轮没有迁移任务（队列已清空）时才做后台校验
+            if let Some(b) = &mut background {
+                b.run_idle(opt, &ctx, &done_segments_file, &report).await;
+            }
+            tokio::time::sleep(catchup.as_ref().map(|c| c.interval).unwrap_or(opt.incremental_poll_interval)).await;
         }
-        join_all(handles).await;
-        cur_max_time = new_max;
     }
-    // 8. _bak 补差与兜底增量、最终表切换
-    // 8.1 rename 源表为 _bak
-    let bak_table = format!("{}_bak", opt.src_table);
-    let rename_sql = if opt.is_src_distributed && !opt.cluster_name.is_empty() {
-        format!("RENAME TABLE {} TO {} ON CLUSTER {}", opt.src_table, bak_table, opt.cluster_name)
-    } else {
-        format!("RENAME TABLE {} TO {}", opt.src_table, bak_table)
-    };
-    if let Err(e) = ch_execute(&opt.src_dsn, &opt.src_db, &rename_sql).await {
-        error!("重命名源表失败: {e}");
-        return Err(anyhow::anyhow!(format!("重命名源表失败: {e}")));
-    }
-    // 8.2 获取 _bak 最大时间戳
-    let bak_max_time = get_max_time_http(&opt.src_dsn, &opt.src_db, &bak_table, &opt.time_field).await?;
-    // 8.3 _bak 补差写入
-    let bak_rows = get_rows_http(&opt.src_dsn, &opt.src_db, &bak_table, &opt.time_field, &bak_max_time, &col_names).await?;
-    let dst_rows = get_rows_http(&opt.dst_dsn, &opt.dst_db, &opt.dst_table, &opt.time_field, &bak_max_time, &col_names).await?;
-    let dst_row_set: HashSet<String> = dst_rows.iter().map(|r| {
-        let mut norm = serde_json::Map::new();
-        for col in &sorted_col_names {
-            let v = r.get(col).cloned().unwrap_or(Value::Null);
-            norm.insert(col.clone(), v);
-        }
-        let b = serde_json::to_vec(&norm).unwrap();
-        let mut hasher = Sha256::new();
-        hasher.update(&b);
-        format!("{:x}", hasher.finalize())
-    }).collect();
-    let mut need_insert = Vec::new();
-    for row in bak_rows.iter() {
-        let mut norm = serde_json::Map::new();
-        for col in &sorted_col_names {
-            let v = row.get(col).cloned().unwrap_or(Value::Null);
-            norm.insert(col.clone(), v);
-        }
-        let b = serde_json::to_vec(&norm).unwrap();
-        let mut hasher = Sha256::new();
-        hasher.update(&b);
-        let key = format!("{:x}", hasher.finalize());
-        if !dst_row_set.contains(&key) {
-            need_insert.push(row.clone());
-        }
-    }
-    if !need_insert.is_empty() {
-        for batch in need_insert.chunks(1000) {
-            let json_rows: Vec<String> = batch.iter().map(|row| serde_json::to_string(row).unwrap()).collect();
-            let data = json_rows.join("\n");
-            insert_rows_http(&opt.dst_dsn, &opt.dst_db, &opt.dst_table, data).await?;
-        }
-    }
-    // 8.4 _bak 兜底增量迁移
-    let bak_min_time = chrono::NaiveDateTime::parse_from_str(&bak_max_time, "%Y-%m-%d %H:%M:%S").unwrap() + chrono::Duration::nanoseconds(1);
-    let bak_min_time_str = bak_min_time.format("%Y-%m-%d %H:%M:%S").to_string();
-    let (bak_new_min, bak_new_max) = get_time_range_http(&opt.src_dsn, &opt.src_db, &bak_table, &opt.time_field, &bak_min_time_str).await?;
-    if !bak_new_min.is_empty() && bak_new_max > bak_max_time {
-        let segments = generate_hourly_segments_with_skip(&bak_new_min, &bak_new_max, &HashSet::new());
-        let segment_chunks: Vec<Vec<String>> = segments.chunks((segments.len() + parallelism - 1) / parallelism).map(|c| c.to_vec()).collect();
-        let mut handles = Vec::new();
-        for chunk in segment_chunks {
-            handles.push(tokio::spawn(migrate_segment_worker_http(
-                chunk,
-                opt.src_dsn.clone(),
-                opt.dst_dsn.clone(),
-                opt.src_db.clone(),
-                opt.dst_db.clone(),
-                bak_table.clone(),
-                opt.dst_table.clone(),
-                opt.time_field.clone(),
-                col_names.clone(),
-                sorted_col_names.clone(),
-                ignore_fields.clone(),
-                done_segments_file.clone(),
-                log_file_path.clone(),
-                client.clone(),
-            )));
+    // 7.1 迁移期间源表出现 mutation：已完成分段可能与源端不一致，全部重新比对（保守处理，不解析 mutation 条件）
+    if mutation_watch.aborted() {
+        mutation_task.abort();
+        anyhow::bail!(format!("迁移期间源表出现新的 mutation（--on-mutation abort），迁移中止，详见运行报告"));
+    }
+    if mutation_watch.observed() && resume.is_none() && !ctx.deadline.hit(&report, "mutation re-verify") {
+        warn!("迁移期间源表出现 mutation，重新校验 {} ~ {} 的全部分段", min_time, cur_max_time);
+        if !opt.mirror {
+            warn!("注意：比对只补写缺失行，源端 DELETE/UPDATE 造成的目标端多余旧行需人工处理（或使用 --mirror）");
         }
-        join_all(handles).await;
+        let segments = plan_segments(&min_time, &cur_max_time, &HashSet::new(), &blacklist, None)?;
+        pool.recheck(segments).await;
     }
-    // 8.5 rename 目标表为 src_table
-    let rename_dst_sql = if opt.is_dst_distributed && !opt.cluster_name.is_empty() {
-        format!("RENAME TABLE {} TO {} ON CLUSTER {}", opt.dst_table, opt.src_table, opt.cluster_name)
-    } else {
-        format!("RENAME TABLE {} TO {}", opt.dst_table, opt.src_table)
+    let deduplicated = pool.queue.duplicates();
+    if deduplicated > 0 {
+        info!("重复入队而跳过的分段: {} 次", deduplicated);
+    }
+    report.lock().unwrap().segments_deduplicated = deduplicated;
+    pool.shutdown().await;
+    if let Some(s) = &ctx.staged {
+        s.finish().await;
+    }
+    mutation_task.abort();
+    if opt.pause_mvs {
+        ddl::resume_mvs(opt, &done_segments_file).await?;
+    }
+    // 7.2 目标表 OPTIMIZE FINAL（切换前执行，源表仍可用）
+    if let Some(o) = &optimizer {
+        o.finish().await;
+    }
+    if let Some(t) = optimize_task {
+        t.abort();
+    }
+    // 7.3 分段汇总：已完成 / 黑名单跳过 / 失败 分开统计，避免把跳过的分段误认为已迁移
+    let done_count = load_done_segments(&done_segments_file)?
+        .iter()
+        .filter(|l| segment::parse(l).is_some())
+        .count();
+    {
+        let mut r = report.lock().unwrap();
+        r.segments_blacklisted = blacklist.hits.lock().unwrap().iter().cloned().collect();
+        r.segments_failed = ctx.failed_segments.lock().unwrap().clone();
+        r.mirror_deletes = ctx.mirror_deletes.lock().unwrap().clone();
+        r.bad_rows = ctx.bad_row_segments.lock().unwrap().clone();
+        r.over_copied = ctx.over_copied.lock().unwrap().clone();
+        r.ttl_affected = ctx.ttl_affected.lock().unwrap().clone();
+        if !r.ttl_affected.is_empty() {
+            warn!("{} 个分段早于源表 TTL 过期边界，差异不计入比对，详见报告 ttl_affected", r.ttl_affected.len());
+        }
+        if !r.bad_rows.is_empty() {
+            warn!(
+                "无法解析而跳过的行: 共 {} 行，涉及 {} 个分段，详见报告 bad_rows",
+                r.bad_rows.iter().map(|b| b.skipped).sum::<u64>(),
+                r.bad_rows.iter().map(|b| b.segment.as_str()).collect::<HashSet<_>>().len()
+            );
+        }
+        r.segments_done = done_count;
+        r.segments_coalesced = coalesce::recorded(&done_segments_file);
+        r.segments_rerun = rerun.clone().unwrap_or_default();
+        r.rows_written = ctx.rows_written.load(std::sync::atomic::Ordering::Relaxed);
+        info!(
+            "分段汇总: 已完成 {}（其中空分段合并 {}）, 黑名单跳过 {}, 失败 {}",
+            done_count, r.segments_coalesced, r.segments_blacklisted.len(), r.segments_failed.len()
+        );
+        if !r.segments_blacklisted.is_empty() {
+            warn!("以下分段因黑名单未迁移: {}", r.segments_blacklisted.join(", "));
+        }
+        // 超大行按分段列出：单独写入 / 写入死信文件的行数
+        r.oversized_rows = ctx.oversized.segments();
+        for o in &r.oversized_rows {
+            warn!(
+                "segment {} oversized rows: single-row={}, dead-lettered={}, largest={} bytes",
+                o.segment, o.single_row, o.dead_lettered, o.largest_bytes
+            );
+        }
+        r.rejected_rows = ctx.rejected.segments();
+        if !r.rejected_rows.is_empty() {
+            warn!(
+                "目标端拒绝、已写入死信文件的行: 共 {} 行，各分段: {}，详见报告 rejected_rows",
+                r.rejected_rows.iter().map(|x| x.rows).sum::<u64>(),
+                r.rejected_rows.iter().map(|x| format!("{}={}", x.segment, x.rows)).collect::<Vec<_>>().join(", ")
+            );
+        }
+        r.segment_bytes = std::mem::take(&mut *ctx.segment_bytes.lock().unwrap());
+        if !r.segment_bytes.is_empty() {
+            info!(
+                "传输字节: 源端读取 {}, 线路 {}",
+                transfer::human(r.segment_bytes.iter().map(|b| b.uncompressed_bytes).sum::<u64>() as f64),
+                transfer::human(r.segment_bytes.iter().map(|b| b.wire_bytes).sum::<u64>() as f64)
+            );
+        }
+        // 耗时归因：各阶段占比与最慢分段
+        let timings = ctx.segment_timings.lock().unwrap();
+        if !timings.is_empty() {
+            let t = timing::summarize(&timings, &ctx.worker_walls.lock().unwrap());
+            info!("耗时归因: {}", t.breakdown());
+            for w in &t.workers {
+                info!(
+                    "  worker {}: 分段 {}, 运行 {:.1}s, 读源 {:.1}s, 读目标 {:.1}s, 比对 {:.1}s, 写入 {:.1}s, 空闲 {:.1}s",
+                    w.worker, w.segments, w.wall_seconds, w.phases.read_src, w.phases.read_dst, w.phases.diff, w.phases.insert, w.idle_seconds
+                );
+            }
+            for (phase, segs) in &t.slowest {
+                let list: Vec<String> = segs.iter().map(|s| format!("{}({:.1}s)", s.segment, s.phases.get(phase))).collect();
+                info!("  {} 最慢分段: {}", phase, list.join(", "));
+            }
+            r.throughput = Some(t);
+        }
+    }
+    // 7.4 归档模式：逐段校验并删除源数据，不做表切换
+    let deadline_hit = resume.is_none() && ctx.deadline.hit(&report, if opt.archive { "archive" } else { "cutover" });
+    if resume.is_none() && (opt.archive || opt.no_cutover || rerun.is_some() || deadline_hit) {
+        report.lock().unwrap().cutover = "skipped".to_string();
+    }
+    if deadline_hit {
+        return Ok(());
+    }
+    if opt.archive && resume.is_none() {
+        status::set_phase("archive");
+        return archive::verify_and_delete(opt, &min_time, &cur_max_time, &archive_cutoff, &done_segments_file, &blacklist, &report).await;
+    }
+    if opt.no_cutover && resume.is_none() {
+        info!("未启用切换，{} 迁移完成", opt.src_table);
+        return Ok(());
+    }
+    if let Some(rerun) = &rerun {
+        info!("{} 个指定分段重新迁移完成，跳过切换", rerun.len());
+        return Ok(());
+    }
+    // 7.5 --src-query：没有源表可 rename，不做 _bak 补差，--rename-dst-to 时只改名目标表
+    if src_query::enabled(opt) {
+        return src_query::cutover(opt, &report).await;
+    }
+    // 8. _bak 补差与兜底增量、最终表切换（此后失败记为切换失败）；各子步骤完成后记录到 cutover.state
+    // 维护窗口内不开始切换
+    blackout::wait().await;
+    report.lock().unwrap().cutover = "started".to_string();
+    let mut state = match resume {
+        Some(s) => s,
+        None => {
+            // 8.0 切换前检查目标表副本复制延迟
+            wait_for_dst_replica_lag(opt, &report).await?;
+            if ctx.deadline.hit(&report, "cutover") {
+                report.lock().unwrap().cutover = "skipped".to_string();
+                return Ok(());
+            }
+            cutover_state::CutoverState::begin(opt, &format!("{}_bak", opt.src_table))?
+        }
     };
-    if let Err(e) = ch_execute(&opt.dst_dsn, &opt.dst_db, &rename_dst_sql).await {
-        error!("重命名目标表失败: {e}");
-        return Err(anyhow::anyhow!(format!("重命名目标表失败: {e}")));
+    // resume-cutover 补差阶段 opt 的源表为 _bak，rename 与切换后处理使用原表名
+    let cut_opt = state.original(opt);
+    // rename 之后必须完成 _bak 补差与切换，不再受 --max-duration 限制
+    ctx.deadline.lift();
+    // --shard-of 时只有分片 0 执行切换，_bak 补差与兜底增量覆盖所有分片的行
+    if !ctx.shard_filter.is_empty() {
+        warn!("--shard-of {}: 由本进程执行切换，_bak 补差按整张表进行；请确认其余分片进程均已完成", opt.shard_of);
+        ctx.whole_table.store(true, std::sync::atomic::Ordering::Relaxed);
     }
-    // 8.6 done_segments 文件重命名
-    if std::path::Path::new(&done_segments_file).exists() {
-        let ts = chrono::Local::now().format("%Y%m%d_%H%M%S");
-        let new_name = format!("{}_{}.txt", done_segments_file.trim_end_matches(".txt"), ts);
-        std::fs::rename(&done_segments_file, &new_name)?;
+    // 8.1 rename 源表为 _bak（改名前再次比对表结构指纹）
+    status::set_phase("cutover");
+    if !state.done(cutover_state::Step::SrcRenamed) {
+        schema_fingerprint::check(&cut_opt, &done_segments_file, "cutover-src", &report).await?;
     }
-    info!("最终切换完成，迁移流程结束");
-    Ok(())
-}
\ No newline at end of file
+    state.rename_src(&cut_opt).await?;
+    let bak_table = state.bak_table.clone();
+    ctx.bak_phase.store(true, std::sync::atomic::Ordering::Relaxed);
+    // 8.2 获取 _bak 最大时间戳（_bak 无数据时跳过补差与兜底增量）
+    status::set_phase("bak");
+    let bak_max_time = state.bak_max_time(&cut_opt, &ctx.filter()).await?;
+    if bak_max_time.is_empty() {
+        info!("{} 无数据，跳过 _bak 补差", bak_table);
+    } else if state.done(cutover_state::Step::BakDiffed) {
+        info!("{} 补差已完成，跳过", bak_table);
+    } else {
+        // 8.3 _bak 补差写入（按摘要比对，只写目标端缺少的行）
+        let bak_rows = get_rows_http(&opt.src_dsn, &opt.src_db, &table_ref(&opt.src_db, &bak_table, ctx.select_final), &opt.time_field, &opt.time_lit(&bak_max_time), &ctx.binary.select_list(&col_names), &ctx.filter()).await?;
+        let dst_rows = get_rows_http(&opt.dst_dsn, &opt.dst_db, &table_ref(&opt.dst_db, opt.read_table(), ctx.dst_select_final), &opt.time_field, &opt.time_lit(&bak_max_time), &ctx.binary.select_list(&compare_col_names), &ctx.filter()).await?;
+        let dst_row_set: HashSet<[u8; 32]> = dst_rows.iter().map(|r| ctx.normalize.digest(r, &sorted_col_names)).collect();
+        let mut need_insert = Vec::new();
+        for row in bak_rows.iter() {
+            if !dst_row_set.contains(&ctx.normalize.digest(row, &sorted_col_names)) {
+                need_insert.push(row.clone());
+            }
+        }
+        // 与分段写入一样经写入闸门按 --batch-bytes 分批；有批次失败时不记录补差完成，切换失败后可 resume-cutover 重试
+        let (n, errors) = insert_rows_batched(&ctx, "bak", &opt.dst_dsn, &opt.dst_db, &opt.dst_table, &need_insert, client.clone()).await;
+        for e in &errors {
+            error!("{} 补差批量写入失败: {e}", bak_table);
+            ctx.insert_errors.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+        if let Some(e) = errors.into_iter().next() {
+            anyhow::bail!(format!("{} 补差写入失败（已写入 {} 行）: {e}", bak_table, n));
+        }
+        state.bak_diffed()?;
+    }
+    if !bak_max_time.is_empty() {
+        // 8.4 _bak 兜底增量迁移（跳过断点续传文件中已完成的 bak: 分段）
+        let bak_min_time = chrono::NaiveDateTime::parse_from_str(&bak_max_time, "%Y-%m-%d %H:%M:%S").unwrap() + chrono::Duration::nanoseconds(1);
+        let bak_min_time_str = bak_min_time.format("%Y-%m-%d %H:%M:%S").to_string();
+        let (bak_new_min, bak_new_max) = get_time_range_http(&opt.src_dsn, &opt.src_db, &bak_table, &opt.time_field, &bak_min_time_str, &ctx.filter(), opt.time_zone.as_ref()).await?;
+        if !bak_new_min.is_empty() && bak_new_max > bak_max_time {
+            let bak_done = phase_checkpoint::bak_segments(&load_done_segments(&done_segments_file)?);
+            let segments = plan_segments(&bak_new_min, &bak_new_max, &bak_done, &blacklist, None)?;
+            run_segment_workers(opt, &bak_table, segments, &col_names, &done_segments_file, &client, &ctx).await;
+        }
+    }
+    state.bak_filled()?;
+    // 目标表 rename 前比对 _bak 与目标表的表结构指纹
+    if !state.done(cutover_state::Step::DstRenamed) {
+        let mut bak_opt = cut_opt.clone();
+        bak_opt.src_table = bak_table.clone();
+        schema_fingerprint::check(&bak_opt, &done_segments_file, "cutover-dst", &report).await?;
+    }
+    // 8.5 ~ 8.7 目标表 rename、切换后校验与 _bak 保留策略、断点续传文件归档
+    state.finish(&cut_opt, &report, &done_segments_file).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datacp::row_digest;
+
+    #[test]
+    fn time_range_sentinels_mean_no_data() {
+        let none = (String::new(), String::new());
+        // 新版本服务端：空结果集 min/max 为 1970-01-01 00:00:00
+        assert_eq!(time_range_or_empty(Some(0), "1970-01-01 00:00:00", "1970-01-01 00:00:00"), none);
+        assert_eq!(time_range_or_empty(None, "1970-01-01 00:00:00", "1970-01-01 00:00:00"), none);
+        // 旧版本服务端：0000-00-00 00:00:00
+        assert_eq!(time_range_or_empty(Some(0), "0000-00-00 00:00:00", "0000-00-00 00:00:00"), none);
+        assert_eq!(time_range_or_empty(None, "0000-00-00 00:00:00", "0000-00-00 00:00:00"), none);
+        // 以 count() 为准：为 0 时即使 min/max 看起来正常也无数据，大于 0 时保留零值时间
+        assert_eq!(time_range_or_empty(Some(0), "2024-01-01 00:00:00", "2024-01-02 00:00:00"), none);
+        assert_eq!(
+            time_range_or_empty(Some(3), "1970-01-01 00:00:00", "2024-01-02 00:00:00"),
+            ("1970-01-01 00:00:00".to_string(), "2024-01-02 00:00:00".to_string())
+        );
+        // max < min
+        assert_eq!(time_range_or_empty(Some(5), "2024-01-02 00:00:00", "2024-01-01 00:00:00"), none);
+        assert_eq!(
+            time_range_or_empty(Some(5), "2024-01-01 00:00:00", "2024-01-02 03:00:00"),
+            ("2024-01-01 00:00:00".to_string(), "2024-01-02 03:00:00".to_string())
+        );
+    }
+
+    #[test]
+    fn time_range_row_from_query_result() {
+        let row = |v: Value| -> HashMap<String, Value> { serde_json::from_value(v).unwrap() };
+        let empty = row(serde_json::json!({"c": "0", "min_time": "1970-01-01 00:00:00", "max_time": "1970-01-01 00:00:00"}));
+        assert_eq!(time_range_row(Some(&empty)), (String::new(), String::new()));
+        let old = row(serde_json::json!({"c": 0, "max_time": "0000-00-00 00:00:00"}));
+        assert_eq!(time_range_row(Some(&old)), (String::new(), String::new()));
+        let data = row(serde_json::json!({"c": "12", "max_time": "2024-05-01 10:00:00"}));
+        assert_eq!(time_range_row(Some(&data)).1, "2024-05-01 10:00:00");
+        assert_eq!(time_range_row(None), (String::new(), String::new()));
+    }
+
+    // Nested 列展开为 events.ts / events.name 两个数组列，Tuple 列为 JSON 数组
+    const NESTED_ROWS: &str = concat!(
+        r#"{"id":1,"events.ts":["2024-01-01 00:10:00","2024-01-01 00:20:00"],"events.name":["open","close"],"t":["2024-01-01 00:10:00",3]}"#,
+        "\n",
+        r#"{"id":2,"events.ts":[],"events.name":[],"t":["2024-01-01 00:30:00",0]}"#,
+        "\n"
+    );
+
+    #[test]
+    fn nested_columns_round_trip() {
+        let (rows, bad) = bad_rows::parse_rows(NESTED_ROWS.as_bytes(), true).unwrap();
+        assert!(bad.is_empty());
+        assert_eq!(rows[0]["events.ts"], serde_json::json!(["2024-01-01 00:10:00", "2024-01-01 00:20:00"]));
+        // 写入批次再解析回来与原始行一致，摘要不变
+        let mut cols: Vec<String> = ["id", "events.ts", "events.name", "t"].iter().map(|c| c.to_string()).collect();
+        cols.sort();
+        let batches = insert_batches(&rows, 0);
+        assert_eq!(batches.len(), 1);
+        let (again, _) = bad_rows::parse_rows(batches[0].0.as_bytes(), true).unwrap();
+        assert_eq!(again, rows);
+        for (a, b) in rows.iter().zip(&again) {
+            assert_eq!(row_digest(a, &cols), row_digest(b, &cols));
+        }
+        assert_ne!(row_digest(&rows[0], &cols), row_digest(&rows[1], &cols));
+    }
+
+    #[test]
+    fn nested_columns_in_sql() {
+        let types = vec![("id".to_string(), "UInt64".to_string()), ("events.ts".to_string(), "Array(DateTime)".to_string())];
+        let plain = binary::BinaryColumns::new(types.clone(), HashSet::new());
+        let cols: Vec<String> = types.iter().map(|(c, _)| c.clone()).collect();
+        assert_eq!(plain.select_list(&cols), "id,`events.ts`");
+        assert_eq!(plain.insert_sql("t"), "INSERT INTO t (id,`events.ts`) FORMAT JSONEachRow");
+        let bin = binary::BinaryColumns::new(
+            vec![("payload".to_string(), "String".to_string()), ("events.ts".to_string(), "Array(DateTime)".to_string())],
+            ["payload".to_string()].into_iter().collect(),
+        );
+        assert!(bin.insert_sql("t").starts_with("INSERT INTO t (payload,`events.ts`) SELECT CAST(unhex(payload), 'String'),`events.ts` FROM input("));
+    }
+
+    #[test]
+    fn time_field_inside_nested_or_tuple() {
+        let cols: Vec<String> = ["id", "events.ts", "events.name", "t"].iter().map(|c| c.to_string()).collect();
+        let opt = Opt::from_iter(["datacp", "--time-field", "events.ts[1]"]);
+        assert_eq!(time_expr::referenced_columns(&opt), vec!["events.ts".to_string()]);
+        let opt = Opt::from_iter(["datacp", "--time-field", "events.ts"]);
+        assert_eq!(time_expr::referenced_columns(&opt), vec!["events.ts".to_string()]);
+        let opt = Opt::from_iter(["datacp", "--time-field", "tupleElement(t, 1)"]);
+        assert_eq!(time_expr::referenced_columns(&opt), vec!["t".to_string()]);
+        assert!(time_expr::column_exists(&cols, "events.ts"));
+        assert!(time_expr::column_exists(&cols, "t.ts"));
+        assert!(!time_expr::column_exists(&cols, "other.ts"));
+        assert!(!time_expr::column_exists(&cols, "ts"));
+    }
+
+    // 两个小时分段：第一个源端两行、目标端为空，第二个读取源端时报错；增量检查无新数据后切换
+    fn mock_migration(sql: &str) -> (u16, String) {
+        let body = if sql.starts_with("DESCRIBE") {
+            "{\"name\":\"id\",\"type\":\"UInt64\"}\n{\"name\":\"ts\",\"type\":\"DateTime\"}\n"
+        } else if sql.contains("FROM system.tables") {
+            "{\"engine\":\"MergeTree\",\"engine_full\":\"MergeTree ORDER BY id\"}\n"
+        } else if sql.contains(" as min_time") {
+            "{\"c\":\"3\",\"min_time\":\"2024-01-01 00:00:00\",\"max_time\":\"2024-01-01 01:30:00\"}\n"
+        } else if sql.starts_with("SELECT count() AS c FROM app_new.events_new WHERE ts >= '2024-01-01 00:00:00'") {
+            "{\"c\":\"2\"}\n"
+        } else if sql.starts_with("SELECT id,ts FROM app.events WHERE ts >= '2024-01-01 01:00:00'") {
+            return (500, "Code: 60. DB::Exception: Table app.events does not exist".to_string());
+        } else if sql.starts_with("SELECT id,ts FROM app.events WHERE") {
+            "{\"id\":1,\"ts\":\"2024-01-01 00:10:00\"}\n{\"id\":2,\"ts\":\"2024-01-01 00:20:00\"}\n"
+        } else {
+            ""
+        };
+        (200, body.to_string())
+    }
+
+    // 第一个分段的写入被拒绝，其余同 mock_migration（写入后的行数核对仍返回 2）
+    fn mock_insert_fails(sql: &str) -> (u16, String) {
+        // 写入的行（字段顺序不定）
+        if sql.starts_with('{') && sql.contains("\"id\":1") {
+            return (500, "Code: 60. DB::Exception: Table app_new.events_new does not exist".to_string());
+        }
+        mock_migration(sql)
+    }
+
+    #[tokio::test]
+    async fn segment_with_failed_batch_is_not_recorded() {
+        let (dsn, _) = mock_ch::serve(mock_insert_fails).await;
+        let dir = std::env::temp_dir().join(format!("datacp_failed_batch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let done = dir.join("done_segments.txt").to_string_lossy().to_string();
+        let state_dir = dir.to_string_lossy().to_string();
+        let opt = Opt::from_iter([
+            "datacp", "--src-dsn", &dsn, "--dst-dsn", &dsn, "--src-db", "app", "--dst-db", "app_new", "--src-table", "events",
+            "--dst-table", "events_new", "--time-field", "ts", "--skip-disk-check", "--no-cutover", "--state-dir", &state_dir,
+        ]);
+        let report = Arc::new(std::sync::Mutex::new(report::RunReport::new(&opt)));
+        let _ = run_migration(&opt, &done, report.clone(), Arc::new(tokio::sync::Semaphore::new(4)), None).await;
+        let recorded = load_done_segments(&done).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(!recorded.contains(&segment::record("2024-01-01 00:00:00")), "{:?}", recorded);
+        assert!(report.lock().unwrap().segments_failed.contains(&"2024-01-01 00:00:00".to_string()));
+    }
+
+    // attach-partition：目标表为空，源表两个分区中 202402 挂载失败
+    fn mock_attach_fails(sql: &str) -> (u16, String) {
+        let body = if sql.contains("hostName()") {
+            "{\"h\":\"ch1\",\"p\":\"9000\"}\n"
+        } else if sql.contains("FROM system.tables") {
+            "{\"engine\":\"MergeTree\",\"engine_full\":\"MergeTree PARTITION BY toYYYYMM(ts) ORDER BY id\",\"partition_key\":\"toYYYYMM(ts)\",\"sorting_key\":\"id\",\"primary_key\":\"id\"}\n"
+        } else if sql.contains("FROM system.parts WHERE database = 'app' ") {
+            "{\"partition_id\":\"202401\",\"rows\":\"2\"}\n{\"partition_id\":\"202402\",\"rows\":\"1\"}\n"
+        } else if sql.contains("ATTACH PARTITION ID '202402'") {
+            return (500, "Code: 36. DB::Exception: Tables have different structure".to_string());
+        } else if sql.contains("_partition_id = '202401'") {
+            "{\"c\":\"2\"}\n"
+        } else {
+            return mock_migration(sql);
+        };
+        (200, body.to_string())
+    }
+
+    #[tokio::test]
+    async fn failed_partition_attach_stops_before_cutover() {
+        let (dsn, seen) = mock_ch::serve(mock_attach_fails).await;
+        let dir = std::env::temp_dir().join(format!("datacp_attach_fails_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let done = dir.join("done_segments.txt").to_string_lossy().to_string();
+        let state_dir = dir.to_string_lossy().to_string();
+        let opt = Opt::from_iter([
+            "datacp", "--src-dsn", &dsn, "--dst-dsn", &dsn, "--src-db", "app", "--dst-db", "app_new", "--src-table", "events",
+            "--dst-table", "events_new", "--time-field", "ts", "--skip-disk-check", "--state-dir", &state_dir, "--copy-mode", "attach-partition",
+        ]);
+        let report = Arc::new(std::sync::Mutex::new(report::RunReport::new(&opt)));
+        let result = run_migration(&opt, &done, report.clone(), Arc::new(tokio::sync::Semaphore::new(4)), None).await;
+        let recorded = load_done_segments(&done).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        let err = result.expect_err("挂载失败时迁移应失败").to_string();
+        assert!(err.contains("202402"), "{}", err);
+        assert!(recorded.contains("partition:202401") && !recorded.contains("partition:202402"), "{:?}", recorded);
+        let statuses: Vec<(String, u64, String)> =
+            report.lock().unwrap().partitions_attached.iter().map(|p| (p.partition_id.clone(), p.dst_rows, p.status.clone())).collect();
+        assert_eq!(statuses[0], ("202401".to_string(), 2, "attached".to_string()));
+        assert!(statuses[1].2.starts_with("failed: "), "{:?}", statuses);
+        assert!(!seen.lock().unwrap().iter().any(|(_, s)| s.starts_with("RENAME")));
+    }
+
+    // 同 mock_attach_fails，目标表带一个物化视图
+    fn mock_attach_fails_with_mv(sql: &str) -> (u16, String) {
+        if sql.contains("dependencies_table") {
+            return (200, "{\"mv\":\"app_new.events_mv\"}\n".to_string());
+        }
+        mock_attach_fails(sql)
+    }
+
+    // 多表运行中某表失败时，该表暂停的物化视图同样恢复
+    #[tokio::test]
+    async fn failed_table_in_multi_run_resumes_paused_mvs() {
+        let (dsn, seen) = mock_ch::serve(mock_attach_fails_with_mv).await;
+        let dir = std::env::temp_dir().join(format!("datacp_multi_mvs_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state_dir = dir.to_string_lossy().to_string();
+        let opt = Opt::from_iter([
+            "datacp", "--src-dsn", &dsn, "--dst-dsn", &dsn, "--src-db", "app", "--dst-db", "app_new", "--time-field", "ts",
+            "--skip-disk-check", "--state-dir", &state_dir, "--copy-mode", "attach-partition", "--pause-mvs",
+        ]);
+        let entry = multi::TableEntry { src_table: "events".to_string(), dst_table: Some("events_new".to_string()), ..Default::default() };
+        let multi = multi::run_tables(&opt, vec![entry], Vec::new(), Arc::new(tokio::sync::Semaphore::new(4))).await;
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(multi.tables[0].status, "failed", "{:?}", multi.tables[0].error);
+        let stmts: Vec<String> = seen.lock().unwrap().iter().map(|(_, s)| s.clone()).filter(|s| s.ends_with("TABLE app_new.events_mv")).collect();
+        assert_eq!(stmts, ["DETACH TABLE app_new.events_mv", "ATTACH TABLE app_new.events_mv"]);
+    }
+
+    // --replace-partitions：源端只有 202401，目标端另有旧分区 202312；替换后清空临时表一直失败
+    fn mock_replace(sql: &str) -> (u16, String) {
+        let body = if sql.contains("FROM system.tables") {
+            "{\"engine\":\"MergeTree\",\"engine_full\":\"MergeTree PARTITION BY toYYYYMM(ts) ORDER BY id\",\"partition_key\":\"toYYYYMM(ts)\",\"sorting_key\":\"id\",\"primary_key\":\"id\"}\n"
+        } else if sql.starts_with("SELECT DISTINCT toYYYYMM(ts) AS p FROM app.events ") {
+            "{\"p\":202401}\n"
+        } else if sql.starts_with("SELECT DISTINCT toYYYYMM(ts) AS p FROM app_new.events_new ") {
+            "{\"p\":202312}\n{\"p\":202401}\n"
+        } else if sql.starts_with("SELECT count() AS c, toStartOfHour(") {
+            if sql.contains("= 202401") {
+                "{\"c\":\"2\",\"lo\":\"2024-01-01 00:00:00\",\"hi\":\"2024-01-01 00:20:00\"}\n"
+            } else {
+                "{\"c\":\"0\",\"lo\":\"1970-01-01 00:00:00\",\"hi\":\"1970-01-01 00:00:00\"}\n"
+            }
+        } else if sql.starts_with("SELECT count() AS c, sum(cityHash64(") {
+            if sql.contains("FROM app_new.events_new WHERE") { "{\"c\":\"5\",\"h\":\"1\"}\n" } else { "{\"c\":\"2\",\"h\":\"7\"}\n" }
+        } else if sql.starts_with("TRUNCATE") && REPLACED.load(std::sync::atomic::Ordering::SeqCst) {
+            return (500, "Code: 159. DB::Exception: Timeout exceeded".to_string());
+        } else if sql.contains("REPLACE PARTITION") {
+            REPLACED.store(true, std::sync::atomic::Ordering::SeqCst);
+            ""
+        } else {
+            return mock_migration(sql);
+        };
+        (200, body.to_string())
+    }
+
+    // mock_replace 刚执行过 REPLACE PARTITION
+    static REPLACED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+    #[tokio::test]
+    async fn replace_partitions_covers_destination_only_partitions() {
+        let (dsn, seen) = mock_ch::serve(mock_replace).await;
+        let dir = std::env::temp_dir().join(format!("datacp_replace_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let done = dir.join("done_segments.txt").to_string_lossy().to_string();
+        let state_dir = dir.to_string_lossy().to_string();
+        let opt = Opt::from_iter([
+            "datacp", "--src-dsn", &dsn, "--dst-dsn", &dsn, "--src-db", "app", "--dst-db", "app_new", "--src-table", "events",
+            "--dst-table", "events_new", "--time-field", "ts", "--skip-disk-check", "--no-cutover", "--state-dir", &state_dir, "--replace-partitions",
+        ]);
+        let report = Arc::new(std::sync::Mutex::new(report::RunReport::new(&opt)));
+        let _ = run_migration(&opt, &done, report.clone(), Arc::new(tokio::sync::Semaphore::new(4)), None).await;
+        let recorded = load_done_segments(&done).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        let staging = "app_new.events_new__datacp_replace";
+        let mut stmts: Vec<String> = seen
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, s)| s.clone())
+            .filter(|s| s.contains("PARTITION") || s.starts_with("TRUNCATE") || s.starts_with(&format!("DROP TABLE IF EXISTS {}", staging)))
+            .collect();
+        // 失败的 TRUNCATE 会重试
+        stmts.dedup();
+        assert_eq!(
+            stmts,
+            [
+                format!("DROP TABLE IF EXISTS {}", staging),
+                format!("TRUNCATE TABLE {}", staging),
+                "ALTER TABLE app_new.events_new DROP PARTITION 202312".to_string(),
+                format!("TRUNCATE TABLE {}", staging),
+                format!("ALTER TABLE app_new.events_new REPLACE PARTITION 202401 FROM {}", staging),
+                format!("TRUNCATE TABLE {}", staging),
+                format!("DROP TABLE IF EXISTS {}", staging),
+            ]
+        );
+        assert!(recorded.contains("replaced:202312") && recorded.contains("replaced:202401"), "{:?}", recorded);
+        let statuses: Vec<(String, u64, String)> =
+            report.lock().unwrap().partitions_replaced.iter().map(|p| (p.partition.clone(), p.src_rows, p.status.clone())).collect();
+        assert_eq!(statuses, [("202312".to_string(), 0, "replaced".to_string()), ("202401".to_string(), 2, "replaced".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn staged_segment_is_promoted_from_the_run_stage_table() {
+        let (dsn, seen) = mock_ch::serve(mock_migration).await;
+        let dir = std::env::temp_dir().join(format!("datacp_staged_run_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let done = dir.join("done_segments.txt").to_string_lossy().to_string();
+        let state_dir = dir.to_string_lossy().to_string();
+        let opt = Opt::from_iter([
+            "datacp", "--src-dsn", &dsn, "--dst-dsn", &dsn, "--src-db", "app", "--dst-db", "app_new", "--src-table", "events",
+            "--dst-table", "events_new", "--time-field", "ts", "--skip-disk-check", "--no-cutover", "--state-dir", &state_dir, "--staged-inserts",
+        ]);
+        let report = Arc::new(std::sync::Mutex::new(report::RunReport::new(&opt)));
+        let _ = run_migration(&opt, &done, report.clone(), Arc::new(tokio::sync::Semaphore::new(4)), None).await;
+        let recorded = load_done_segments(&done).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(recorded.contains(&segment::record("2024-01-01 00:00:00")), "{:?}", recorded);
+        assert!(recorded.iter().any(|l| l.starts_with("promoted:2024-01-01 00:00:00\t")), "{:?}", recorded);
+        let stage = "app_new.events_new__datacp_stage";
+        let window = "ts >= '2024-01-01 00:00:00' AND ts < '2024-01-01 01:00:00'";
+        let stmts: Vec<String> = seen.lock().unwrap().iter().map(|(_, s)| s.clone()).collect();
+        let at = |prefix: String| stmts.iter().position(|s| s.starts_with(&prefix)).unwrap_or_else(|| panic!("missing {prefix}: {:?}", stmts));
+        let created = at(format!("CREATE TABLE IF NOT EXISTS {} AS app_new.events_new", stage));
+        let staged = at("{".to_string());
+        let promoted = at(format!("INSERT INTO app_new.events_new SELECT * FROM {} WHERE {}", stage, window));
+        let cleared = at(format!("ALTER TABLE {} DELETE WHERE {}", stage, window));
+        let dropped = at(format!("DROP TABLE IF EXISTS {}", stage));
+        assert!(created < staged && staged < promoted && promoted < cleared && cleared < dropped);
+        assert_eq!(stmts.iter().filter(|s| s.starts_with("CREATE TABLE IF NOT EXISTS")).count(), 1, "{:?}", stmts);
+    }
+
+    // 目标端 00:00 分段比源端多出 id=3 的行，其余同 mock_migration
+    fn mock_extra_row(sql: &str) -> (u16, String) {
+        if sql.starts_with("SELECT id,ts FROM app_new.events_new WHERE ts >= '2024-01-01 00:00:00'") {
+            return (200, "{\"id\":1,\"ts\":\"2024-01-01 00:10:00\"}\n{\"id\":3,\"ts\":\"2024-01-01 00:30:00\"}\n".to_string());
+        }
+        mock_migration(sql)
+    }
+
+    // --mirror 运行中发往目标端的删除语句
+    async fn mirror_deletes(name: &str, extra: &[&str]) -> Vec<String> {
+        let (dsn, seen) = mock_ch::serve(mock_extra_row).await;
+        let dir = std::env::temp_dir().join(format!("datacp_mirror_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let done = dir.join("done_segments.txt").to_string_lossy().to_string();
+        let state_dir = dir.to_string_lossy().to_string();
+        let mut args = vec![
+            "datacp", "--src-dsn", &dsn, "--dst-dsn", &dsn, "--src-db", "app", "--dst-db", "app_new", "--src-table", "events",
+            "--dst-table", "events_new", "--time-field", "ts", "--skip-disk-check", "--no-cutover", "--state-dir", &state_dir, "--mirror",
+        ];
+        args.extend_from_slice(extra);
+        let opt = Opt::from_iter(args);
+        let report = Arc::new(std::sync::Mutex::new(report::RunReport::new(&opt)));
+        let _ = run_migration(&opt, &done, report, Arc::new(tokio::sync::Semaphore::new(4)), None).await;
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut stmts: Vec<String> = seen.lock().unwrap().iter().map(|(_, s)| s.clone()).filter(|s| s.contains(" DELETE ")).collect();
+        stmts.dedup();
+        stmts
+    }
+
+    #[tokio::test]
+    async fn mirror_deletes_only_with_yes() {
+        let window = "ts >= '2024-01-01 00:00:00' AND ts < '2024-01-01 01:00:00'";
+        assert_eq!(mirror_deletes("dry", &["--key-columns", "id"]).await, Vec::<String>::new());
+        assert_eq!(mirror_deletes("dry_rewrite", &[]).await, Vec::<String>::new());
+        assert_eq!(
+            mirror_deletes("keys", &["--key-columns", "id", "--yes"]).await,
+            [format!("ALTER TABLE app_new.events_new DELETE WHERE {} AND (id) IN ((3))", window)]
+        );
+        assert_eq!(mirror_deletes("rewrite", &["--yes"]).await, [format!("ALTER TABLE app_new.events_new DELETE WHERE {}", window)]);
+    }
+
+    // mock_split_passes 目标表中已写入的行
+    static SPLIT_INSERTED: std::sync::Mutex<Vec<(u64, String)>> = std::sync::Mutex::new(Vec::new());
+
+    // 一个小时分段、四行（id 0..3）：源端估算行数超过内存份额而拆成多次处理（第 id % 次数 次读到该行），
+    // 整段窗口的第 1 次读取超出内存限制而对半拆分；目标端读取与行数核对按已写入的行返回
+    fn mock_split_passes(sql: &str) -> (u16, String) {
+        let source: Vec<(u64, String)> = [(0, "00:10"), (1, "00:20"), (2, "00:40"), (3, "00:50")].iter().map(|(id, t)| (*id, format!("2024-01-01 {}:00", t))).collect();
+        let inserted = SPLIT_INSERTED.lock().unwrap().clone();
+        let select = |rows: &[(u64, String)]| {
+            let re = regex::Regex::new(r"ts >= '([^']+)' AND ts < '([^']+)' AND cityHash64\(ts\) % (\d+) = (\d+)").unwrap();
+            let c = re.captures(sql).unwrap();
+            let (lo, hi, n, k): (&str, &str, u64, u64) = (&c[1], &c[2], c[3].parse().unwrap(), c[4].parse().unwrap());
+            if std::ptr::eq(rows, source.as_slice()) && lo == "2024-01-01 00:00:00" && hi == "2024-01-01 01:00:00" && k == 1 {
+                return None;
+            }
+            Some(
+                rows.iter()
+                    .filter(|(id, ts)| id % n == k && ts.as_str() >= lo && ts.as_str() < hi)
+                    .map(|(id, ts)| format!("{{\"id\":{},\"ts\":\"{}\"}}\n", id, ts))
+                    .collect::<String>(),
+            )
+        };
+        let body = if sql.contains(" as min_time") {
+            "{\"c\":\"4\",\"min_time\":\"2024-01-01 00:00:00\",\"max_time\":\"2024-01-01 00:50:00\"}\n".to_string()
+        } else if sql.starts_with("SELECT count() AS c FROM app.events WHERE") {
+            "{\"c\":\"100000\"}\n".to_string()
+        } else if sql.starts_with("SELECT count() AS c FROM app_new.events_new WHERE") {
+            format!("{{\"c\":\"{}\"}}\n", inserted.len())
+        } else if sql.starts_with("SELECT id,ts FROM app.events WHERE") {
+            match select(&source) {
+                Some(b) => b,
+                None => return (500, "Code: 241. DB::Exception: Memory limit (for query) exceeded".to_string()),
+            }
+        } else if sql.starts_with("SELECT id,ts FROM app_new.events_new WHERE") {
+            select(&inserted).unwrap_or_default()
+        } else if sql.starts_with('{') {
+            // 写入的行（请求体为 JSONEachRow 数据，字段顺序不定）
+            let mut log = SPLIT_INSERTED.lock().unwrap();
+            for row in sql.lines().filter_map(|l| serde_json::from_str::<Value>(l).ok()) {
+                log.push((row["id"].as_u64().unwrap(), row["ts"].as_str().unwrap().to_string()));
+            }
+            String::new()
+        } else {
+            return mock_migration(sql);
+        };
+        (200, body)
+    }
+
+    #[tokio::test]
+    async fn split_after_memory_pass_counts_rows_once() {
+        let (dsn, _) = mock_ch::serve(mock_split_passes).await;
+        let dir = std::env::temp_dir().join(format!("datacp_split_passes_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let done = dir.join("done_segments.txt").to_string_lossy().to_string();
+        let state_dir = dir.to_string_lossy().to_string();
+        let opt = Opt::from_iter([
+            "datacp", "--src-dsn", &dsn, "--dst-dsn", &dsn, "--src-db", "app", "--dst-db", "app_new", "--src-table", "events",
+            "--dst-table", "events_new", "--time-field", "ts", "--skip-disk-check", "--no-cutover", "--state-dir", &state_dir,
+            // 份额 4 MiB：源端估算的 10 万行拆成多次处理
+            "--memory-budget", "4M", "--parallelism", "1",
+        ]);
+        let report = Arc::new(std::sync::Mutex::new(report::RunReport::new(&opt)));
+        let res = run_migration(&opt, &done, report.clone(), Arc::new(tokio::sync::Semaphore::new(4)), None).await;
+        let recorded = load_done_segments(&done).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        res.unwrap();
+        let r = report.lock().unwrap();
+        assert!(r.segments_failed.is_empty(), "{:?}", r.segments_failed);
+        assert!(recorded.contains(&segment::record("2024-01-01 00:00:00")), "{:?}", recorded);
+        assert_eq!(r.rows_written, 4);
+    }
+
+    // 字段名及其 JSON 类型检查
+    type FieldCheck = (&'static str, fn(&Value) -> bool);
+
+    // 每个事件的字段（除公共的 v / event / time）及其 JSON 类型
+    fn event_schema(event: &str) -> Option<Vec<FieldCheck>> {
+        let s: fn(&Value) -> bool = Value::is_string;
+        let n: fn(&Value) -> bool = Value::is_u64;
+        let o: fn(&Value) -> bool = Value::is_object;
+        Some(match event {
+            "run_started" => vec![("config_hash", s), ("src", s), ("dst", s)],
+            "phase_changed" => vec![("phase", s)],
+            "segment_done" => vec![("segment", s), ("rows", n), ("duration_ms", n)],
+            "segment_failed" => vec![("segment", s), ("error", s)],
+            "cutover_done" => vec![("table", s), ("bak_table", s), ("outcome", s)],
+            "run_finished" => vec![("summary", o)],
+            _ => return None,
+        })
+    }
+
+    #[tokio::test]
+    async fn events_stdout_lines_follow_schema() {
+        let (dsn, _) = mock_ch::serve(mock_migration).await;
+        let dir = std::env::temp_dir().join(format!("datacp_events_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let done = dir.join("done_segments.txt").to_string_lossy().to_string();
+        let state_dir = dir.to_string_lossy().to_string();
+        let opt = Opt::from_iter([
+            "datacp", "--src-dsn", &dsn, "--dst-dsn", &dsn, "--src-db", "app", "--dst-db", "app_new", "--src-table", "events",
+            "--dst-table", "events_new", "--time-field", "ts", "--skip-disk-check", "--events-stdout", "--state-dir", &state_dir,
+        ]);
+        let out = events::capture::start();
+        events::run_started(&opt);
+        let report = Arc::new(std::sync::Mutex::new(report::RunReport::new(&opt)));
+        let res = run_migration(&opt, &done, report.clone(), Arc::new(tokio::sync::Semaphore::new(4)), None).await;
+        {
+            let mut r = report.lock().unwrap();
+            r.finish(&res);
+            events::run_finished(&r, r.exit_code());
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = text.lines().map(|l| serde_json::from_str(l).unwrap_or_else(|e| panic!("not JSON: {l}: {e}"))).collect();
+        for line in &lines {
+            let obj = line.as_object().unwrap();
+            assert_eq!(obj.get("v"), Some(&Value::from(events::VERSION)), "{line}");
+            assert!(obj.get("time").map(|t| t.is_string()).unwrap_or(false), "{line}");
+            let event = obj.get("event").and_then(|e| e.as_str()).unwrap_or_default();
+            let schema = event_schema(event).unwrap_or_else(|| panic!("unknown event: {line}"));
+            assert_eq!(obj.len(), schema.len() + 3, "unexpected fields: {line}");
+            for (field, check) in schema {
+                assert!(obj.get(field).map(check).unwrap_or(false), "{field} missing or wrong type: {line}");
+            }
+        }
+        let events: Vec<&str> = lines.iter().map(|l| l["event"].as_str().unwrap()).collect();
+        assert_eq!(events.first(), Some(&"run_started"));
+        assert_eq!(events.last(), Some(&"run_finished"));
+        let find = |event: &str| lines.iter().find(|l| l["event"] == event).unwrap_or_else(|| panic!("no {event} in {text}"));
+        assert_eq!(find("segment_done")["segment"], "2024-01-01 00:00:00");
+        assert_eq!(find("segment_done")["rows"], 2);
+        assert_eq!(find("segment_failed")["segment"], "2024-01-01 01:00:00");
+        assert!(lines.iter().any(|l| l["event"] == "phase_changed" && l["phase"] == "backfill"));
+        assert_eq!(find("cutover_done")["table"], "app_new.events");
+        let summary = &find("run_finished")["summary"];
+        assert_eq!(summary["segments_failed"], 1);
+        assert_eq!(summary["rows_written"], 2);
+    }
+
+    #[test]
+    fn dsn_keeps_scheme_port_and_path_prefix() {
+        let url = |dsn: &str| parse_clickhouse_dsn(dsn, "app").unwrap().0;
+        assert_eq!(url("http://default:@localhost"), "http://localhost:8123/?database=app");
+        assert_eq!(url("https://u:p@svc.example.com"), "https://svc.example.com:443/?database=app");
+        assert_eq!(url("https://u:p@svc.example.com:8443/clickhouse"), "https://svc.example.com:8443/clickhouse?database=app");
+        assert_eq!(
+            url("https://u:p@svc.example.com/clickhouse/?max_memory_usage=1"),
+            "https://svc.example.com:443/clickhouse/?database=app&max_memory_usage=1"
+        );
+        assert_eq!(clickhouse_base_url("https://u:p@svc.example.com/clickhouse").unwrap().0, "https://svc.example.com:443/clickhouse");
+    }
+
+    // 服务挂在 /clickhouse 下（前缀之外返回 404）：查询、写入与 DDL 都应带上前缀
+    #[tokio::test]
+    async fn requests_honor_dsn_path_prefix() {
+        let (dsn, seen) = mock_ch::serve_at("/clickhouse", mock_ch::empty).await;
+        ch_query_rows(&dsn, "app", "SELECT 1 FORMAT JSONEachRow").await.unwrap();
+        let rows: Vec<HashMap<String, Value>> = vec![serde_json::from_value(serde_json::json!({"id": 1})).unwrap()];
+        let mut batch = insert_stream::Batch::new(&rows, 0, 1 << 20);
+        insert_rows_http_with_client(&dsn, "app", "INSERT INTO app.t FORMAT JSONEachRow", &mut batch, Arc::new(endpoint::Clients::shared(reqwest::Client::new())))
+            .await
+            .unwrap();
+        ch_execute(&dsn, "app", "TRUNCATE TABLE app.t").await.unwrap();
+        assert!(health::probe("源端", &dsn, "app", "startup").await.healthy);
+        let seen = seen.lock().unwrap().clone();
+        let sqls: Vec<&str> = seen.iter().map(|(_, sql)| sql.as_str()).collect();
+        assert_eq!(sqls, ["SELECT 1 FORMAT JSONEachRow", "{\"id\":1}\n", "TRUNCATE TABLE app.t", "SELECT 1"]);
+        assert!(seen[..3].iter().all(|(db, _)| db == "app"));
+        // 不带前缀的地址被拒绝，说明上面的请求确实走了前缀
+        let bare = dsn.trim_end_matches("/clickhouse");
+        assert_eq!(health::probe("源端", bare, "app", "startup").await.layer.as_deref(), Some("backend"));
+    }
+}
@@ -0,0 +1,215 @@
+// ===================== 运行清单（manifest.json / migrate --from-manifest） =====================
+// 每次迁移（非 resume-cutover）开始时在表目录写入 manifest.json，记录本次运行的全部生效参数、工具版本与提交、
+// 表结构指纹、分段边界（起止时间与待迁移分段）以及实际采用的策略（拷贝方式、比对摘要、传输格式、批量）。
+// datacp migrate --from-manifest manifest.json 按清单中的参数与时间范围原样重跑：
+// 当前二进制的版本/提交或两端实时表结构指纹与清单不同时拒绝运行，指定 --allow-drift 时只告警。
+// 清单中不写密码：DSN 中的密码替换为 ${环境变量名}（配置块的 password_env，否则为 DATACP_SRC_PASSWORD /
+// DATACP_DST_PASSWORD），告警 webhook 整体替换为 ${DATACP_ALERT_WEBHOOK}；重跑时从同名环境变量取回
+
+use log::{info, warn};
+use regex::Regex;
+use serde_json::Value;
+use std::sync::Mutex;
+
+use crate::report::RowPolicies;
+use crate::{endpoint, schema_fingerprint, state_dir, Opt};
+
+const MANIFEST_VERSION: u64 = 1;
+const FILE_NAME: &str = "manifest.json";
+const SRC_PASSWORD_ENV: &str = "DATACP_SRC_PASSWORD";
+const DST_PASSWORD_ENV: &str = "DATACP_DST_PASSWORD";
+const PUSHGATEWAY_PASSWORD_ENV: &str = "DATACP_PUSHGATEWAY_PASSWORD";
+const WEBHOOK_ENV: &str = "DATACP_ALERT_WEBHOOK";
+
+// --from-manifest 固定的时间范围与表结构指纹
+struct Pinned {
+    path: String,
+    min_time: String,
+    max_time: String,
+    schema_fingerprint: String,
+    allow_drift: bool,
+}
+
+static PINNED: Mutex<Option<Pinned>> = Mutex::new(None);
+
+fn version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+// 构建时通过环境变量 DATACP_GIT_COMMIT 写入
+fn commit() -> &'static str {
+    option_env!("DATACP_GIT_COMMIT").unwrap_or("unknown")
+}
+
+fn password_re() -> Regex {
+    Regex::new(r"^(https?://[^:/@?]+:)([^@/?]*)@").unwrap()
+}
+
+// URL 中的密码替换为 ${var}；没有密码时原样返回
+fn redact_url(url: &str, var: &str) -> String {
+    let re = password_re();
+    match re.captures(url) {
+        Some(c) if !c[2].is_empty() => re.replace(url, format!("${{1}}${{{}}}@", var).as_str()).to_string(),
+        _ => url.to_string(),
+    }
+}
+
+// ${var} 替换为环境变量的值
+fn restore_url(url: &str, field: &str) -> anyhow::Result<String> {
+    let re = password_re();
+    let Some(c) = re.captures(url) else { return Ok(url.to_string()) };
+    let Some(var) = c[2].strip_prefix("${").and_then(|v| v.strip_suffix('}')) else { return Ok(url.to_string()) };
+    let pass = std::env::var(var).map_err(|_| anyhow::anyhow!(format!("清单中 {} 的密码取自环境变量 {}，该变量未设置", field, var)))?;
+    Ok(re.replace(url, format!("${{1}}{}@", pass).as_str()).to_string())
+}
+
+fn password_env(dsn: &str, default: &str) -> String {
+    endpoint::password_env(dsn).unwrap_or_else(|| default.to_string())
+}
+
+// 去掉密码后的参数
+fn redacted(opt: &Opt) -> anyhow::Result<Value> {
+    let mut o = opt.clone();
+    o.src_dsn = redact_url(&opt.src_dsn, &password_env(&opt.src_dsn, SRC_PASSWORD_ENV));
+    o.dst_dsn = redact_url(&opt.dst_dsn, &password_env(&opt.dst_dsn, DST_PASSWORD_ENV));
+    o.pushgateway_url = redact_url(&opt.pushgateway_url, PUSHGATEWAY_PASSWORD_ENV);
+    if !o.alert_webhook.is_empty() {
+        o.alert_webhook = format!("${{{}}}", WEBHOOK_ENV);
+    }
+    o.cmd = None;
+    Ok(serde_json::to_value(&o)?)
+}
+
+// 写入 manifest.json；segments 为本次待迁移的分段，server_digest 为目标端摘要是否由服务端计算，
+// row_policies 为预检发现的源表行策略（accepted 表示有意按策略过滤）
+pub async fn write(
+    opt: &Opt,
+    min_time: &str,
+    max_time: &str,
+    segments: &[String],
+    server_digest: bool,
+    row_policies: Option<RowPolicies>,
+) -> anyhow::Result<()> {
+    let (fingerprint, _, _) = schema_fingerprint::observe(opt).await?;
+    let normalize = crate::normalize::Normalize::parse(opt)?;
+    let secrets = serde_json::json!({
+        "src_password_env": password_env(&opt.src_dsn, SRC_PASSWORD_ENV),
+        "dst_password_env": password_env(&opt.dst_dsn, DST_PASSWORD_ENV),
+        "pushgateway_password_env": PUSHGATEWAY_PASSWORD_ENV,
+        "alert_webhook_env": WEBHOOK_ENV,
+    });
+    let manifest = serde_json::json!({
+        "manifest_version": MANIFEST_VERSION,
+        "created_at": chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        "tool": { "version": version(), "commit": commit() },
+        "opt": redacted(opt)?,
+        "secrets": secrets,
+        "schema_fingerprint": fingerprint,
+        "row_policies": row_policies,
+        "servers": crate::server_version::report(opt),
+        "segments": {
+            "granularity": "1h",
+            "min_time": min_time,
+            "max_time": max_time,
+            "pending": segments.len(),
+            "first": segments.first(),
+            "last": segments.last(),
+        },
+        "strategies": {
+            "copy_mode": opt.copy_mode,
+            "diff": if server_digest { "server-digest" } else { "client-digest" },
+            "digest_version": datacp::DIGEST_VERSION,
+            "wire_format": "JSONEachRow",
+            "batch_bytes": opt.batch_bytes,
+            "incremental_batch_hours": opt.incremental_batch_hours,
+            "parallelism": opt.parallelism,
+            "page_key": opt.page_key,
+            "page_rows": opt.page_rows,
+            "mirror": opt.mirror,
+            "replace_partitions": opt.replace_partitions,
+            "cutover_strategy": opt.cutover_strategy,
+            "normalize": normalize.meta(),
+            "priority": crate::priority::report(opt),
+        },
+    });
+    let dir = state_dir::table_dir(opt);
+    std::fs::create_dir_all(&dir).map_err(|e| anyhow::anyhow!(format!("创建 --state-dir 目录 {} 失败: {}", dir.display(), e)))?;
+    let path = dir.join(FILE_NAME);
+    std::fs::write(&path, serde_json::to_string_pretty(&manifest)?)
+        .map_err(|e| anyhow::anyhow!(format!("写入运行清单 {} 失败: {}", path.display(), e)))?;
+    info!("运行清单已写入 {}", path.display());
+    Ok(())
+}
+
+fn text<'a>(m: &'a Value, path: &[&str]) -> &'a str {
+    path.iter().try_fold(m, |v, k| v.get(k)).and_then(|v| v.as_str()).unwrap_or("")
+}
+
+// 读取清单并还原参数（单表迁移，不带子命令）；版本不同时拒绝，--allow-drift 时返回告警文本，
+// 由调用方在日志初始化之后输出（读取清单时日志尚未初始化）
+pub fn load(path: &str, allow_drift: bool) -> anyhow::Result<(Opt, Option<String>)> {
+    let data = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!(format!("读取运行清单 {} 失败: {}", path, e)))?;
+    let m: Value = serde_json::from_str(&data).map_err(|e| anyhow::anyhow!(format!("解析运行清单 {} 失败: {}", path, e)))?;
+    let v = m.get("manifest_version").and_then(|v| v.as_u64()).unwrap_or(0);
+    if v != MANIFEST_VERSION {
+        anyhow::bail!(format!("运行清单 {} 的格式版本 {} 不受支持（当前为 {}）", path, v, MANIFEST_VERSION));
+    }
+    let (tool_version, tool_commit) = (text(&m, &["tool", "version"]), text(&m, &["tool", "commit"]));
+    let mut drift = None;
+    if tool_version != version() || tool_commit != commit() {
+        let msg = format!("运行清单 {} 由 datacp {}（{}）生成，当前为 {}（{}）", path, tool_version, tool_commit, version(), commit());
+        if !allow_drift {
+            anyhow::bail!(format!("{}，结果可能不同；确认后可加 --allow-drift", msg));
+        }
+        drift = Some(format!("{}（--allow-drift）", msg));
+    }
+    let mut opt: Opt = serde_json::from_value(m.get("opt").cloned().unwrap_or(Value::Null))
+        .map_err(|e| anyhow::anyhow!(format!("运行清单 {} 的参数无法还原: {}", path, e)))?;
+    opt.src_dsn = restore_url(&opt.src_dsn, "src_dsn")?;
+    opt.dst_dsn = restore_url(&opt.dst_dsn, "dst_dsn")?;
+    opt.pushgateway_url = restore_url(&opt.pushgateway_url, "pushgateway_url")?;
+    if let Some(var) = opt.alert_webhook.strip_prefix("${").and_then(|v| v.strip_suffix('}')) {
+        opt.alert_webhook = std::env::var(var).map_err(|_| anyhow::anyhow!(format!("清单中 alert_webhook 取自环境变量 {}，该变量未设置", var)))?;
+    }
+    // 多表迁移为每张表各写一份清单，重跑只针对该表
+    opt.cmd = None;
+    opt.tables_file = String::new();
+    opt.all_tables = false;
+    *PINNED.lock().unwrap() = Some(Pinned {
+        path: path.to_string(),
+        min_time: text(&m, &["segments", "min_time"]).to_string(),
+        max_time: text(&m, &["segments", "max_time"]).to_string(),
+        schema_fingerprint: text(&m, &["schema_fingerprint"]).to_string(),
+        allow_drift,
+    });
+    Ok((opt, drift))
+}
+
+// 两端实时表结构与清单记录的指纹比对
+pub async fn check_schema(opt: &Opt) -> anyhow::Result<()> {
+    let Some((path, expected, allow_drift)) =
+        PINNED.lock().unwrap().as_ref().map(|p| (p.path.clone(), p.schema_fingerprint.clone(), p.allow_drift))
+    else {
+        return Ok(());
+    };
+    let (observed, _, _) = schema_fingerprint::observe(opt).await?;
+    if observed != expected {
+        let msg = format!("表结构指纹 {} 与运行清单 {} 记录的 {} 不同", observed, path, expected);
+        if !allow_drift {
+            anyhow::bail!(format!("{}，两端表结构已变化；确认后可加 --allow-drift", msg));
+        }
+        warn!("{}（--allow-drift）", msg);
+    }
+    Ok(())
+}
+
+// 按清单固定时间范围；未使用清单时原样返回
+pub fn time_range(min_time: String, max_time: String) -> (String, String) {
+    match PINNED.lock().unwrap().as_ref() {
+        Some(p) if !p.min_time.is_empty() && !p.max_time.is_empty() => {
+            info!("按运行清单 {} 的时间范围迁移: {} ~ {}（实时为 {} ~ {}）", p.path, p.min_time, p.max_time, min_time, max_time);
+            (p.min_time.clone(), p.max_time.clone())
+        }
+        _ => (min_time, max_time),
+    }
+}
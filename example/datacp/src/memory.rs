@@ -0,0 +1,133 @@
+// ===================== 内存预算（--memory-budget） =====================
+// 分段比对要同时持有源端行、目标端行与两端摘要集合，个别异常分段（如目标端大量重复行）可能耗尽内存。
+// 设置预算后，worker 读取前按两端 count() 与已观测的平均行大小估算分段占用，超过每个 worker 的份额时
+// 按 cityHash64(时间字段) 把分段拆成多次处理（同一行两端落在同一次），每次处理前向共享额度登记，额度不足时等待。
+// 预算由每次运行的 --memory-budget 决定（RunCtx::memory）；额度相同的运行（多表、serve 任务）共享同一份额度。
+// 未设置时只累计平均行大小的两个计数器
+
+use log::warn;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::Opt;
+
+// 单个分段最多拆分的次数
+const MAX_PASSES: u64 = 256;
+// 摘要集合每项的估算占用（32 字节摘要与哈希表开销）
+const DIGEST_ENTRY_BYTES: u64 = 64;
+// 解析后的行（HashMap<String, Value>）相对 JSON 文本的膨胀倍数
+const ROW_EXPANSION: u64 = 4;
+
+pub struct Budget {
+    total: u64,
+    share: u64,         // 每个 worker 的份额
+    permits: Semaphore, // 以 KiB 计的剩余额度
+}
+
+// 按预算字节数共享的额度
+static BUDGETS: Mutex<BTreeMap<u64, Arc<Budget>>> = Mutex::new(BTreeMap::new());
+static BODY_BYTES: AtomicU64 = AtomicU64::new(0);
+static BODY_ROWS: AtomicU64 = AtomicU64::new(0);
+
+// 本次运行的预算，--memory-budget 为 0 时为 None；份额按首个使用该额度的运行的 --parallelism 计算
+pub fn budget(opt: &Opt) -> Option<Arc<Budget>> {
+    let bytes = opt.memory_budget;
+    if bytes == 0 {
+        return None;
+    }
+    let b = BUDGETS.lock().unwrap().entry(bytes).or_insert_with(|| {
+        Arc::new(Budget { total: bytes, share: bytes / opt.parallelism.max(1) as u64, permits: Semaphore::new((bytes >> 10) as usize) })
+    }).clone();
+    Some(b)
+}
+
+// 每次读取后记录响应大小与行数，用于估算平均行大小
+pub fn observe(rows: usize, body_bytes: usize) {
+    BODY_ROWS.fetch_add(rows as u64, Ordering::Relaxed);
+    BODY_BYTES.fetch_add(body_bytes as u64, Ordering::Relaxed);
+}
+
+// 单行解析后的估算占用，尚未读取过数据时按 1 KiB
+fn row_bytes() -> u64 {
+    match BODY_ROWS.load(Ordering::Relaxed) {
+        0 => 1024,
+        rows => BODY_BYTES.load(Ordering::Relaxed) / rows * ROW_EXPANSION,
+    }
+}
+
+// 分段各结构的估算占用
+pub struct Estimate {
+    pub src_rows: u64, // 源端行与补写列表
+    pub dst_rows: u64, // 目标端行
+    pub digests: u64,  // 两端摘要集合
+}
+
+impl Estimate {
+    pub fn new(src_rows: u64, dst_rows: u64) -> Self {
+        let row = row_bytes();
+        Estimate { src_rows: src_rows * row * 2, dst_rows: dst_rows * row, digests: (src_rows + dst_rows) * DIGEST_ENTRY_BYTES }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.src_rows + self.dst_rows + self.digests
+    }
+
+    // 占用最大的结构，记录在日志中
+    pub fn largest(&self) -> &'static str {
+        if self.dst_rows >= self.src_rows && self.dst_rows >= self.digests {
+            "dst rows"
+        } else if self.src_rows >= self.digests {
+            "src rows"
+        } else {
+            "digest sets"
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} MiB (src rows {} MiB, dst rows {} MiB, digest sets {} MiB)",
+            self.total() >> 20,
+            self.src_rows >> 20,
+            self.dst_rows >> 20,
+            self.digests >> 20
+        )
+    }
+}
+
+impl Budget {
+    // 每个 worker 的份额（字节）
+    pub fn share(&self) -> u64 {
+        self.share
+    }
+
+    // 按份额计算分段需要拆分的次数
+    pub fn passes(&self, est: &Estimate) -> u64 {
+        if self.share == 0 {
+            return 1;
+        }
+        est.total().div_ceil(self.share).clamp(1, MAX_PASSES)
+    }
+
+    // 处理一次前登记占用，额度不足时等待；单次登记不超过一个 worker 的份额，保证总能取得
+    pub async fn reserve(&self, bytes: u64) -> Option<SemaphorePermit<'_>> {
+        let kib = (bytes.min(self.share) >> 10).min(u32::MAX as u64) as u32;
+        self.permits.acquire_many(kib).await.ok()
+    }
+
+    // 读取后按实际行数复核，拆分后仍超出份额（时间分布倾斜）时告警
+    pub fn check(&self, seg: &str, pass: u64, src_rows: usize, dst_rows: usize) {
+        let est = Estimate::new(src_rows as u64, dst_rows as u64);
+        if est.total() > self.share {
+            warn!(
+                "segment {seg} pass {} exceeds memory share {} MiB of --memory-budget {} MiB: {}, largest: {}",
+                pass,
+                self.share >> 20,
+                self.total >> 20,
+                est.describe(),
+                est.largest()
+            );
+        }
+    }
+}
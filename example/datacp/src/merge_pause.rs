@@ -0,0 +1,174 @@
+// ===================== 回填期间暂停目标表合并（--pause-dst-merges） =====================
+// 批量写入时目标表同时合并刚写入的 part 会拖慢回填。--pause-dst-merges 在首轮分段迁移开始前对目标表（分布式表取本地表，
+// 配置了 --cluster-name 时追加 ON CLUSTER）执行 SYSTEM STOP MERGES，首轮结束、进入增量循环之前 START MERGES，
+// 避免长尾期间堆积大量 part；切换在增量循环之后，此时合并已恢复。启动检查时先执行一次 SYSTEM START MERGES
+// （合并未暂停时为空操作）确认当前用户有 SYSTEM MERGES 权限。
+// 恢复语句在暂停前写入 <断点续传文件>.paused_merges：迁移失败或提前返回时由守卫在后台恢复，运行结束（含失败）时按记录文件
+// 再恢复一次，--supervise 与仪表盘的 Ctrl-C 处理在退出前同样先恢复；其他情况下进程被结束时，下次运行结束时按记录文件恢复。
+// 多表与 serve 并发运行时各目标表分别记录、分别恢复。暂停与恢复时间写入报告 merges_paused
+
+use log::{error, info};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::report::{self, MergePause, RunReport};
+use crate::{ch_error_code, ch_execute, cluster, shard, Opt};
+
+// ACCESS_DENIED
+const ACCESS_DENIED: u32 = 497;
+
+// 暂停中的合并：记录文件 → 暂停信息（每张目标表一个记录文件）；异步锁：恢复语句执行完成前其他退出路径等待
+static PAUSED: tokio::sync::Mutex<BTreeMap<String, Paused>> = tokio::sync::Mutex::const_new(BTreeMap::new());
+
+struct Paused {
+    dsn: String,
+    db: String,
+    start_sql: String,
+    file: String,
+    report: Arc<Mutex<RunReport>>,
+}
+
+// 已暂停合并的记录文件，内容为恢复语句
+fn paused_file(done_segments_file: &str) -> String {
+    format!("{}.paused_merges", done_segments_file)
+}
+
+// SYSTEM <verb> MERGES [ON CLUSTER] <目标本地表>（集群在目标端不可用时回退为单机形式）
+async fn statement(opt: &Opt, verb: &str) -> anyhow::Result<(String, String)> {
+    let (db, table) = shard::resolve_local_table(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+    let target = format!("{}.{}", db, table);
+    let mut on_cluster = String::new();
+    if opt.is_dst_distributed && !opt.cluster_name.is_empty() && cluster::fallback(&opt.dst_dsn, &opt.dst_db, &opt.cluster_name).await?.is_none() {
+        on_cluster = format!(" ON CLUSTER {}", opt.cluster_name);
+    }
+    Ok((target.clone(), format!("SYSTEM {} MERGES{} {}", verb, on_cluster, target)))
+}
+
+// 启动检查：确认有 SYSTEM MERGES 权限，缺少时拒绝运行而不是回填开始后才失败
+pub async fn check(opt: &Opt) -> anyhow::Result<()> {
+    if !opt.pause_dst_merges {
+        return Ok(());
+    }
+    let (target, sql) = statement(opt, "START").await?;
+    match ch_execute(&opt.dst_dsn, &opt.dst_db, &sql).await {
+        Ok(()) => Ok(()),
+        Err(e) if ch_error_code(&e.to_string()) == Some(ACCESS_DENIED) => {
+            anyhow::bail!(format!("--pause-dst-merges 需要目标表 {} 的 SYSTEM MERGES 权限: {e}", target))
+        }
+        Err(e) => anyhow::bail!(format!("--pause-dst-merges 检查 {} 失败: {e}", target)),
+    }
+}
+
+// 首轮分段迁移前暂停目标表合并；返回的守卫离开作用域时若尚未恢复则在后台恢复
+pub async fn pause(opt: &Opt, done_segments_file: &str, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<Guard> {
+    let (target, start_sql) = statement(opt, "START").await?;
+    let (_, stop_sql) = statement(opt, "STOP").await?;
+    let file = paused_file(done_segments_file);
+    // 先记录恢复语句，暂停后进程被结束时下次运行仍能恢复
+    std::fs::write(&file, &start_sql)?;
+    let mut paused = PAUSED.lock().await;
+    ch_execute(&opt.dst_dsn, &opt.dst_db, &stop_sql).await?;
+    info!("已暂停目标表 {} 的合并: {}", target, stop_sql);
+    report.lock().unwrap().merges_paused = Some(MergePause { table: target, stopped_at: report::now_str(), restarted_at: None });
+    paused.insert(file.clone(), Paused { dsn: opt.dst_dsn.clone(), db: opt.dst_db.clone(), start_sql, file: file.clone(), report: report.clone() });
+    Ok(Guard { file })
+}
+
+// 恢复记录文件 file 对应的暂停中的合并；失败时保留记录，之后的退出路径重试
+async fn resume(file: &str) {
+    let mut paused = PAUSED.lock().await;
+    let Some(p) = paused.get(file) else { return };
+    match ch_execute(&p.dsn, &p.db, &p.start_sql).await {
+        Ok(()) => {
+            info!("已恢复目标表合并: {}", p.start_sql);
+            if let Some(m) = p.report.lock().unwrap().merges_paused.as_mut() {
+                m.restarted_at = Some(report::now_str());
+            }
+            let _ = std::fs::remove_file(&p.file);
+            paused.remove(file);
+        }
+        Err(e) => error!("恢复目标表合并失败（{}），请手动执行: {e}", p.start_sql),
+    }
+}
+
+// Ctrl-C 退出前调用：恢复所有仍暂停的合并
+pub async fn interrupted() {
+    let files: Vec<String> = PAUSED.lock().await.keys().cloned().collect();
+    for file in files {
+        resume(&file).await;
+    }
+}
+
+// 运行结束（含失败）：恢复本次暂停的合并，以及上次运行被结束时留下的记录
+pub async fn restore(opt: &Opt, done_segments_file: &str) -> anyhow::Result<()> {
+    let file = paused_file(done_segments_file);
+    resume(&file).await;
+    let Ok(sql) = std::fs::read_to_string(&file) else { return Ok(()) };
+    ch_execute(&opt.dst_dsn, &opt.dst_db, &sql)
+        .await
+        .map_err(|e| anyhow::anyhow!(format!("恢复目标表合并失败，请手动执行 {}（记录见 {}）: {e}", sql, file)))?;
+    info!("已按 {} 恢复目标表合并: {}", file, sql);
+    std::fs::remove_file(&file)?;
+    Ok(())
+}
+
+pub struct Guard {
+    file: String,
+}
+
+impl Guard {
+    // 首轮分段迁移结束、进入增量循环之前恢复合并
+    pub async fn restart(&self) {
+        resume(&self.file).await;
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let file = std::mem::take(&mut self.file);
+        tokio::spawn(async move { resume(&file).await });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_ch;
+    use std::path::Path;
+    use structopt::StructOpt;
+
+    fn merge_tree(sql: &str) -> (u16, String) {
+        if sql.contains("FROM system.tables") {
+            (200, "{\"engine\":\"MergeTree\",\"engine_full\":\"MergeTree ORDER BY id\"}\n".to_string())
+        } else {
+            (200, String::new())
+        }
+    }
+
+    // 两张表同时暂停：各自恢复，互不影响
+    #[tokio::test]
+    async fn tables_pause_and_resume_independently() {
+        let (dsn, seen) = mock_ch::serve(merge_tree).await;
+        let dir = std::env::temp_dir().join(format!("datacp_merge_pause_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let done = |t: &str| dir.join(format!("{}.done", t)).to_string_lossy().to_string();
+        let opt = |t: &str| Opt::from_iter(["datacp", "--dst-dsn", &dsn, "--dst-db", "app_new", "--dst-table", t, "--pause-dst-merges"]);
+        let report = Arc::new(Mutex::new(RunReport::default()));
+        let a = pause(&opt("a"), &done("a"), &report).await.unwrap();
+        let b = pause(&opt("b"), &done("b"), &report).await.unwrap();
+        a.restart().await;
+        let (paused_a, paused_b) = (paused_file(&done("a")), paused_file(&done("b")));
+        assert!(!Path::new(&paused_a).exists());
+        assert!(Path::new(&paused_b).exists());
+        restore(&opt("b"), &done("b")).await.unwrap();
+        assert!(!Path::new(&paused_b).exists());
+        drop((a, b));
+        tokio::task::yield_now().await;
+        let _ = std::fs::remove_dir_all(&dir);
+        let stmts: Vec<String> = seen.lock().unwrap().iter().filter(|(_, s)| s.starts_with("SYSTEM")).map(|(_, s)| s.clone()).collect();
+        assert_eq!(
+            stmts,
+            ["SYSTEM STOP MERGES app_new.a", "SYSTEM STOP MERGES app_new.b", "SYSTEM START MERGES app_new.a", "SYSTEM START MERGES app_new.b"]
+        );
+    }
+}
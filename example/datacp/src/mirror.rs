@@ -0,0 +1,159 @@
+// ===================== 镜像模式：删除源端已不存在的目标行 =====================
+// 差集拷贝只补写缺失行，源端 ALTER DELETE 后目标端旧行会一直保留；
+// --mirror 在每个分段同时计算 目标有/源端无 的行并从目标端删除：
+// 指定 --key-columns 时按唯一键 ALTER DELETE，否则删除整个分段窗口后重写源端全部行
+
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::{
+    ch_execute_on_cluster, ch_execute_timeout, classify_ch_error, dsn_with_settings, shard, ChErrorClass, Opt, RunCtx,
+};
+
+// 已删除目标行的分段在断点续传文件中的前缀
+pub const MIRRORED_PREFIX: &str = "mirrored:";
+
+// 单次 ALTER DELETE 中的键数量
+const KEYS_PER_DELETE: usize = 1000;
+
+pub struct Mirror {
+    dsn: String,
+    db: String,
+    target: String,          // 删除作用的表，分布式表取本地表
+    cluster: Option<String>, // 目标为分布式表时 ON CLUSTER 执行
+    key_columns: Vec<String>,
+    apply: bool, // 未指定 --yes 时只统计不删除
+    timeout: std::time::Duration,
+    poll_interval: std::time::Duration,
+}
+
+// 镜像删除方式
+pub enum MirrorAction {
+    KeyDelete(u64),  // 按键删除的行数
+    Rewrite(u64),    // 整段删除的行数，调用方需重写源端全部行
+    DryRun,          // 未指定 --yes
+}
+
+pub fn sql_literal(v: &Value) -> anyhow::Result<String> {
+    Ok(match v {
+        Value::Null => "NULL".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'")),
+        other => anyhow::bail!(format!("键列不支持复合类型的值: {}", other)),
+    })
+}
+
+impl Mirror {
+    // 未指定 --mirror 时返回 None
+    pub async fn new(opt: &Opt, col_names: &[String]) -> anyhow::Result<Option<Self>> {
+        if !opt.mirror {
+            return Ok(None);
+        }
+        if opt.copy_mode != "http" {
+            anyhow::bail!(format!("--mirror 只支持 --copy-mode http，当前为 {}", opt.copy_mode));
+        }
+        for k in &opt.key_columns {
+            if !col_names.contains(k) {
+                anyhow::bail!(format!("--key-columns 中的 {} 不在迁移字段中", k));
+            }
+        }
+        if !opt.yes {
+            warn!("--mirror 需要同时指定 --yes 才会删除目标行，本次只统计多余行");
+        }
+        let (local_db, local_table) = shard::resolve_local_table(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+        let cluster = if local_db != opt.dst_db || local_table != opt.read_table() {
+            let (_, engine_full) = shard::table_engine(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+            let (cluster, _, _, _) = shard::parse_distributed_engine(&engine_full)?;
+            Some(cluster)
+        } else {
+            None
+        };
+        info!(
+            "镜像模式: 删除 {}.{}{} 中源端已不存在的行，方式 {}",
+            local_db,
+            local_table,
+            cluster.as_ref().map(|c| format!(" ON CLUSTER {}", c)).unwrap_or_default(),
+            if opt.key_columns.is_empty() { "整段重写".to_string() } else { format!("按键 ({}) 删除", opt.key_columns.join(",")) }
+        );
+        Ok(Some(Mirror {
+            // 非集群删除同步等待 mutation 完成，避免堆积
+            dsn: dsn_with_settings(&opt.dst_dsn, &[("mutations_sync".to_string(), "2".to_string())]),
+            db: opt.dst_db.clone(),
+            target: format!("{}.{}", local_db, local_table),
+            cluster,
+            key_columns: opt.key_columns.clone(),
+            apply: opt.yes,
+            timeout: opt.ddl_timeout,
+            poll_interval: opt.ddl_poll_interval,
+        }))
+    }
+
+    // 与写入共用只读等待：目标端 242/252 时暂停并重试
+    async fn execute(&self, ctx: &RunCtx, where_sql: &str) -> anyhow::Result<()> {
+        let sql = match &self.cluster {
+            Some(c) => format!("ALTER TABLE {} ON CLUSTER {} DELETE WHERE {}", self.target, c, where_sql),
+            None => format!("ALTER TABLE {} DELETE WHERE {}", self.target, where_sql),
+        };
+        loop {
+            ctx.write_gate.wait_writable().await;
+            let res = match &self.cluster {
+                Some(c) => ch_execute_on_cluster(&self.dsn, &self.db, &sql, c, self.timeout, self.poll_interval).await,
+                None => ch_execute_timeout(&self.dsn, &self.db, &sql, self.timeout).await,
+            };
+            match res {
+                Err(e) if classify_ch_error(&e.to_string()) == ChErrorClass::WaitRetry => {
+                    ctx.write_gate.wait_until_writable(&e.to_string()).await?;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    // 未指定 --key-columns：删除整个窗口后重写
+    pub fn rewrites(&self) -> bool {
+        self.key_columns.is_empty()
+    }
+
+    // 行的键值，用于找回与被删除行同键、但本身与源端一致的行
+    pub fn key_of(&self, row: &HashMap<String, Value>) -> Option<String> {
+        if self.key_columns.is_empty() {
+            return None;
+        }
+        Some(self.key_columns.iter().map(|k| row.get(k).unwrap_or(&Value::Null).to_string()).collect::<Vec<_>>().join("\u{1}"))
+    }
+
+    // 删除分段 [seg, seg_end) 内目标端多余的行；必须在补写之前调用
+    pub async fn remove_extra(
+        &self,
+        ctx: &RunCtx,
+        window: &str,
+        extra: &[&HashMap<String, Value>],
+        dst_rows: usize,
+    ) -> anyhow::Result<MirrorAction> {
+        if !self.apply {
+            return Ok(MirrorAction::DryRun);
+        }
+        if self.key_columns.is_empty() {
+            self.execute(ctx, window).await?;
+            return Ok(MirrorAction::Rewrite(dst_rows as u64));
+        }
+        let cols = format!("({})", self.key_columns.join(","));
+        for batch in extra.chunks(KEYS_PER_DELETE) {
+            let tuples = batch
+                .iter()
+                .map(|r| {
+                    let vals = self
+                        .key_columns
+                        .iter()
+                        .map(|k| sql_literal(r.get(k).unwrap_or(&Value::Null)))
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    Ok(format!("({})", vals.join(",")))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            self.execute(ctx, &format!("{} AND {} IN ({})", window, cols, tuples.join(","))).await?;
+        }
+        Ok(MirrorAction::KeyDelete(extra.len() as u64))
+    }
+}
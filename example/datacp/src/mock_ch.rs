@@ -0,0 +1,98 @@
+// ===================== 测试用 ClickHouse HTTP 模拟 =====================
+// 记录每个请求 URL 上的 database 参数与请求体（语句），按语句由 respond 给出状态码与响应体；
+// 流式写入的请求体为 chunked 编码，记录解码后的内容。serve_at 把服务挂在路径前缀下，前缀之外的请求返回 404 且不记录；
+// GET <前缀>/ping 与 ClickHouse 一样返回 Ok.（serve_pinging 可指定其他响应，模拟入口可达而后端不可用），不记录
+
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+pub type Seen = Arc<Mutex<Vec<(String, String)>>>;
+
+// 请求行上的 database 参数
+fn database_param(head: &str) -> String {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let re = RE.get_or_init(|| regex::Regex::new(r"[?&]database=([^& ]*)").unwrap());
+    re.captures(head).map(|c| c[1].to_string()).unwrap_or_default()
+}
+
+// 一律返回 200 空结果
+pub fn empty(_sql: &str) -> (u16, String) {
+    (200, String::new())
+}
+
+pub async fn serve(respond: fn(&str) -> (u16, String)) -> (String, Seen) {
+    serve_at("", respond).await
+}
+
+pub async fn serve_at(prefix: &'static str, respond: fn(&str) -> (u16, String)) -> (String, Seen) {
+    serve_pinging(prefix, "Ok.\n", respond).await
+}
+
+pub async fn serve_pinging(prefix: &'static str, ping: &'static str, respond: fn(&str) -> (u16, String)) -> (String, Seen) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dsn = format!("http://default:@{}{}", listener.local_addr().unwrap(), prefix);
+    let seen: Seen = Arc::new(Mutex::new(Vec::new()));
+    let log = seen.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut sock, _) = listener.accept().await.unwrap();
+            let log = log.clone();
+            tokio::spawn(async move {
+                let (mut buf, mut chunk) = (Vec::new(), [0u8; 4096]);
+                let mut body_at = None;
+                loop {
+                    let n = sock.read(&mut chunk).await.unwrap_or(0);
+                    buf.extend_from_slice(&chunk[..n]);
+                    if body_at.is_none() {
+                        body_at = buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4);
+                    }
+                    let complete = body_at.map(|at| {
+                        let head = String::from_utf8_lossy(&buf[..at]).to_ascii_lowercase();
+                        if head.contains("transfer-encoding: chunked") {
+                            return buf.len() >= at + 5 && buf.ends_with(b"0\r\n\r\n");
+                        }
+                        let len: usize = head.lines().find_map(|l| l.strip_prefix("content-length:")).map(|v| v.trim().parse().unwrap()).unwrap_or(0);
+                        buf.len() >= at + len
+                    });
+                    if n == 0 || complete.unwrap_or(false) {
+                        break;
+                    }
+                }
+                let Some(at) = body_at else { return };
+                let head = String::from_utf8_lossy(&buf[..at]).to_string();
+                let path = head.split(' ').nth(1).unwrap_or("").split('?').next().unwrap_or("");
+                if !(path == prefix || path.starts_with(&format!("{}/", prefix))) {
+                    let _ = sock.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+                    return;
+                }
+                if head.starts_with("GET ") && path == format!("{}/ping", prefix) {
+                    let resp = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", ping.len(), ping);
+                    let _ = sock.write_all(resp.as_bytes()).await;
+                    return;
+                }
+                let db = database_param(&head);
+                let body = if head.to_ascii_lowercase().contains("transfer-encoding: chunked") { dechunk(&buf[at..]) } else { buf[at..].to_vec() };
+                let sql = String::from_utf8_lossy(&body).to_string();
+                let (code, body) = respond(&sql);
+                log.lock().unwrap().push((db, sql));
+                let reason = if code == 200 { "OK" } else { "Internal Server Error" };
+                let resp = format!("HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", code, reason, body.len(), body);
+                let _ = sock.write_all(resp.as_bytes()).await;
+            });
+        }
+    });
+    (dsn, seen)
+}
+
+fn dechunk(mut raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    while let Some(eol) = raw.windows(2).position(|w| w == b"\r\n") {
+        let size = usize::from_str_radix(String::from_utf8_lossy(&raw[..eol]).trim(), 16).unwrap_or(0);
+        if size == 0 {
+            break;
+        }
+        out.extend_from_slice(&raw[eol + 2..eol + 2 + size]);
+        raw = &raw[eol + 2 + size + 2..];
+    }
+    out
+}
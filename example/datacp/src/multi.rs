@@ -0,0 +1,297 @@
+// ===================== 多表迁移 =====================
+// --tables-file manifest.toml 在一次运行中迁移多张表：每张表独立断点续传文件（--state-dir 下各表子目录）与报告，
+// 按 --table-concurrency 并发执行，共享全局写入并发上限；单表失败不影响其他表（除非 --fail-fast）
+
+use futures::stream::{self, StreamExt};
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::report::{CircuitBreak, MultiReport, RunReport, SkippedTable};
+use crate::{ch_query_rows, deadline, events, histogram, restore_paused, run_migration, src_replica, state_dir, transfer, Opt};
+
+// manifest 中的一张表，未填写的字段沿用命令行参数
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TableEntry {
+    pub src_table: String,
+    #[serde(default)]
+    pub dst_table: Option<String>, // 默认与 src_table 相同
+    #[serde(default)]
+    pub src_db: Option<String>,
+    #[serde(default)]
+    pub dst_db: Option<String>,
+    #[serde(default)]
+    pub time_field: Option<String>,
+    #[serde(default)]
+    pub start_time: Option<String>,
+    #[serde(default)]
+    pub ignore_field: Option<Vec<String>>,
+    #[serde(default)]
+    pub ignore_compare_field: Option<Vec<String>>,
+    #[serde(default, rename = "where")]
+    pub filter: Option<String>,
+    #[serde(default)]
+    pub parallelism: Option<usize>,
+    #[serde(default)]
+    pub cutover: bool, // 是否执行最终 rename 切换，默认不切换
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    tables: Vec<TableEntry>,
+}
+
+pub fn load_manifest(path: &str) -> anyhow::Result<Vec<TableEntry>> {
+    let text = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!(format!("读取 {} 失败: {}", path, e)))?;
+    let m: Manifest = toml::from_str(&text).map_err(|e| anyhow::anyhow!(format!("解析 {} 失败: {}", path, e)))?;
+    if m.tables.is_empty() {
+        anyhow::bail!(format!("{} 中没有 [[tables]]", path));
+    }
+    Ok(m.tables)
+}
+
+// --include/--exclude 模式：默认按 glob（* 与 ?）匹配，re: 前缀按正则匹配
+fn pattern_regex(p: &str) -> anyhow::Result<regex::Regex> {
+    let re = match p.strip_prefix("re:") {
+        Some(r) => r.to_string(),
+        None => {
+            let mut r = String::from("^");
+            for c in p.chars() {
+                match c {
+                    '*' => r.push_str(".*"),
+                    '?' => r.push('.'),
+                    c => r.push_str(&regex::escape(&c.to_string())),
+                }
+            }
+            r.push('$');
+            r
+        }
+    };
+    regex::Regex::new(&re).map_err(|e| anyhow::anyhow!(format!("表名模式 {} 不正确: {}", p, e)))
+}
+
+// 自动选择时间字段：优先分区键中引用的 DateTime 列，其次按 --time-field-candidates 顺序
+fn detect_time_field(partition_key: &str, datetime_cols: &[String], candidates: &[String]) -> Option<(String, &'static str)> {
+    let ident = regex::Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap();
+    for m in ident.find_iter(partition_key) {
+        if datetime_cols.iter().any(|c| c == m.as_str()) {
+            return Some((m.as_str().to_string(), "partition key"));
+        }
+    }
+    candidates
+        .iter()
+        .find(|c| datetime_cols.contains(c))
+        .map(|c| (c.clone(), "candidate"))
+}
+
+// --all-tables：列出源库 MergeTree 系列表，按 include/exclude 过滤并探测时间字段；
+// manifest 中同名条目优先（可为无法探测的表指定 time_field）
+pub async fn discover_tables(opt: &Opt, overrides: &[TableEntry]) -> anyhow::Result<(Vec<TableEntry>, Vec<SkippedTable>)> {
+    let include: Vec<regex::Regex> = opt.include.iter().map(|p| pattern_regex(p)).collect::<anyhow::Result<_>>()?;
+    let exclude: Vec<regex::Regex> = opt.exclude.iter().map(|p| pattern_regex(p)).collect::<anyhow::Result<_>>()?;
+    let sql = format!(
+        "SELECT name, partition_key FROM system.tables WHERE database = '{}' AND engine LIKE '%MergeTree%' \
+         AND NOT startsWith(name, '.inner') ORDER BY name FORMAT JSONEachRow",
+        opt.src_db
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let sql = format!(
+        "SELECT table, name FROM system.columns WHERE database = '{}' AND match(type, '^(Nullable\\\\()?DateTime') FORMAT JSONEachRow",
+        opt.src_db
+    );
+    let col_rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let (mut entries, mut skipped) = (Vec::new(), Vec::new());
+    for r in &rows {
+        let name = r.get("name").and_then(|v| v.as_str()).unwrap_or("").to_string();
+        if (!include.is_empty() && !include.iter().any(|re| re.is_match(&name))) || exclude.iter().any(|re| re.is_match(&name)) {
+            continue;
+        }
+        if let Some(e) = overrides.iter().find(|e| e.src_table == name) {
+            entries.push(e.clone());
+            continue;
+        }
+        let partition_key = r.get("partition_key").and_then(|v| v.as_str()).unwrap_or("");
+        let datetime_cols: Vec<String> = col_rows
+            .iter()
+            .filter(|c| c.get("table").and_then(|v| v.as_str()) == Some(name.as_str()))
+            .filter_map(|c| c.get("name").and_then(|v| v.as_str()).map(|s| s.to_string()))
+            .collect();
+        match detect_time_field(partition_key, &datetime_cols, &opt.time_field_candidates) {
+            Some((field, from)) => {
+                info!("{}: 时间字段 {}（{}）", name, field, from);
+                entries.push(TableEntry { src_table: name, time_field: Some(field), ..Default::default() });
+            }
+            None => {
+                warn!("{}: 未找到可用的 DateTime 时间字段，跳过", name);
+                skipped.push(SkippedTable { table: name, reason: "no DateTime time field detected".to_string() });
+            }
+        }
+    }
+    Ok((entries, skipped))
+}
+
+// 按 --tables-file 与 --all-tables 得到待迁移表列表
+pub async fn resolve_tables(opt: &Opt) -> anyhow::Result<(Vec<TableEntry>, Vec<SkippedTable>)> {
+    let manifest = if opt.tables_file.is_empty() { Vec::new() } else { load_manifest(&opt.tables_file)? };
+    if opt.all_tables {
+        discover_tables(opt, &manifest).await
+    } else {
+        Ok((manifest, Vec::new()))
+    }
+}
+
+// datacp plan：打印将要迁移的表与时间字段，不执行迁移；未指定多表时为单表。
+// 同样的内容写入运行目录下的 plan.json，--histogram 时附带各表的时间字段分布
+pub async fn print_plan(opt: &Opt, histogram: bool, target_rows: u64) -> anyhow::Result<()> {
+    let (entries, skipped) = resolve_tables(opt).await?;
+    let tables: Vec<Opt> = if entries.is_empty() && skipped.is_empty() { vec![opt.clone()] } else { entries.iter().map(|e| table_opt(opt, e)).collect() };
+    events::say(&format!("将迁移 {} 张表:", tables.len()));
+    let mut planned = Vec::new();
+    for t in &tables {
+        events::say(&format!(
+            "  {}.{} -> {}.{}  time_field={}{}{}",
+            t.src_db, t.src_table, t.dst_db, t.dst_table, t.time_field,
+            if t.filter.is_empty() { String::new() } else { format!("  where={}", t.filter) },
+            if t.no_cutover { "" } else { "  cutover" }
+        ));
+        let progress = transfer::describe(t).await;
+        events::say(&format!("    {}", progress));
+        let h = if histogram { histogram::build(t, target_rows).await? } else { None };
+        for line in h.iter().flat_map(histogram::render) {
+            events::say(&format!("    {}", line));
+        }
+        planned.push(serde_json::json!({
+            "src": format!("{}.{}", t.src_db, t.src_table),
+            "dst": format!("{}.{}", t.dst_db, t.dst_table),
+            "time_field": t.time_field,
+            "where": t.filter,
+            "cutover": !t.no_cutover,
+            "progress": progress,
+            "histogram": h,
+        }));
+    }
+    if !skipped.is_empty() {
+        events::say(&format!("跳过 {} 张表:", skipped.len()));
+        for s in &skipped {
+            events::say(&format!("  {}: {}", s.table, s.reason));
+        }
+    }
+    let path = state_dir::resolve(&state_dir::run_dir(opt), "plan.json");
+    let plan = serde_json::json!({ "tables": planned, "skipped": skipped });
+    std::fs::write(&path, serde_json::to_string_pretty(&plan)?).map_err(|e| anyhow::anyhow!(format!("写入 {} 失败: {}", path, e)))?;
+    events::say(&format!("计划已写入 {}", path));
+    Ok(())
+}
+
+// datacp status：逐表打印断点续传进度（已复制字节、剩余估算与预计耗时），不执行迁移；未指定多表时为单表
+pub async fn print_status(opt: &Opt) -> anyhow::Result<()> {
+    let (entries, _) = resolve_tables(opt).await?;
+    let tables: Vec<Opt> = if entries.is_empty() { vec![opt.clone()] } else { entries.iter().map(|e| table_opt(opt, e)).collect() };
+    for t in &tables {
+        events::say(&format!("{}.{} -> {}.{}  {}", t.src_db, t.src_table, t.dst_db, t.dst_table, transfer::describe(t).await));
+    }
+    Ok(())
+}
+
+// 由全局参数与 manifest 条目生成单表参数
+pub fn table_opt(opt: &Opt, e: &TableEntry) -> Opt {
+    let mut t = opt.clone();
+    t.src_table = e.src_table.clone();
+    t.dst_table = e.dst_table.clone().unwrap_or_else(|| e.src_table.clone());
+    if let Some(v) = &e.src_db { t.src_db = v.clone(); }
+    if let Some(v) = &e.dst_db { t.dst_db = v.clone(); }
+    if let Some(v) = &e.time_field {
+        // manifest 指定的时间字段优先于全局 --time-expr
+        t.time_field = v.clone();
+        t.time_expr = String::new();
+    }
+    if let Some(v) = &e.start_time { t.start_time = v.clone(); }
+    if let Some(v) = &e.ignore_field { t.ignore_field = v.clone(); }
+    if let Some(v) = &e.ignore_compare_field { t.ignore_compare_field = v.clone(); }
+    if let Some(v) = &e.filter { t.filter = v.clone(); }
+    if let Some(v) = e.parallelism { t.parallelism = v; }
+    t.no_cutover = opt.no_cutover || !e.cutover;
+    t.dst_read_table = String::new();
+    t.done_segments = String::new();
+    t.tables_file = String::new();
+    t
+}
+
+// 依次（或并发）迁移多张表，返回每张表的结果
+pub async fn run_tables(
+    opt: &Opt,
+    entries: Vec<TableEntry>,
+    skipped: Vec<SkippedTable>,
+    insert_permits: Arc<tokio::sync::Semaphore>,
+) -> MultiReport {
+    let stop = AtomicBool::new(false);
+    // 某表熔断后不再开始其余表（熔断器按表各自计数）
+    let broken: Mutex<Option<CircuitBreak>> = Mutex::new(None);
+    let total = entries.len();
+    info!("多表迁移: {} 张表, 并发 {}", total, opt.table_concurrency.max(1));
+    let tables: Vec<RunReport> = stream::iter(entries.into_iter().enumerate())
+        .map(|(i, e)| {
+            let t = table_opt(opt, &e);
+            let (stop, broken) = (&stop, &broken);
+            let insert_permits = insert_permits.clone();
+            async move {
+                let report = Arc::new(Mutex::new(RunReport::new(&t)));
+                if stop.load(Ordering::SeqCst) {
+                    warn!("[{}/{}] {} 因 --fail-fast 未执行", i + 1, total, t.src_table);
+                    let mut r = report.lock().unwrap();
+                    r.finish(&Err(anyhow::anyhow!("skipped: --fail-fast")));
+                    r.status = "skipped".to_string();
+                    r.outcome = "skipped".to_string();
+                    return r.clone();
+                }
+                let circuit_break = broken.lock().unwrap().clone();
+                if circuit_break.is_some() || deadline::passed(&t) {
+                    let mut r = report.lock().unwrap();
+                    if let Some(c) = circuit_break {
+                        warn!("[{}/{}] {} 因熔断未执行", i + 1, total, t.src_table);
+                        r.circuit_broken = Some(c.at("start"));
+                    } else {
+                        warn!("[{}/{}] {} 因超过 --max-duration 未执行", i + 1, total, t.src_table);
+                        r.deadline_hit = Some("start".to_string());
+                    }
+                    r.cutover = "skipped".to_string();
+                    r.finish(&Ok(()));
+                    return r.clone();
+                }
+                info!("[{}/{}] 开始迁移 {}.{} -> {}.{}", i + 1, total, t.src_db, t.src_table, t.dst_db, t.dst_table);
+                let res = match state_dir::done_segments(&t) {
+                    Ok(done_segments_file) => {
+                        let res = run_migration(&t, &done_segments_file, report.clone(), insert_permits, None).await;
+                        // 与单表运行相同：结束（含失败）时恢复该表暂停的物化视图与目标表合并
+                        for e in restore_paused(&t, &done_segments_file).await {
+                            error!("[{}/{}] {e}", i + 1, total);
+                        }
+                        res
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = &res {
+                    error!("[{}/{}] {} 迁移失败: {e}", i + 1, total, t.src_table);
+                    if opt.fail_fast {
+                        stop.store(true, Ordering::SeqCst);
+                    }
+                }
+                let mut r = report.lock().unwrap();
+                if let Some(c) = &r.circuit_broken {
+                    broken.lock().unwrap().get_or_insert_with(|| c.clone());
+                }
+                r.finish(&res);
+                r.src_replica = src_replica::served();
+                r.clone()
+            }
+        })
+        .buffer_unordered(opt.table_concurrency.max(1))
+        .collect()
+        .await;
+    let mut report = MultiReport::new(tables);
+    report.skipped_tables = skipped;
+    report
+}
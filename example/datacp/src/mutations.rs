@@ -0,0 +1,158 @@
+// ===================== 源表 mutation 监控 =====================
+// 迁移过程中源表执行 ALTER DELETE/UPDATE 会使已完成分段与源端不一致，
+// 启动时记录 system.mutations 快照，运行期间轮询，发现新 mutation 时按 --on-mutation 处理
+
+use log::{info, warn};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::report::{MutationSeen, RunReport};
+use crate::{ch_query_rows, shard, Opt};
+
+// 发现新 mutation 时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnMutation {
+    Warn,  // 告警，迁移结束后重新校验全部分段
+    Pause, // 暂停新分段，直到 mutation 完成
+    Abort, // 中止迁移
+}
+
+pub fn parse_on_mutation(s: &str) -> anyhow::Result<OnMutation> {
+    match s {
+        "warn" => Ok(OnMutation::Warn),
+        "pause" => Ok(OnMutation::Pause),
+        "abort" => Ok(OnMutation::Abort),
+        other => anyhow::bail!(format!("不支持的 --on-mutation: {}（pause|warn|abort）", other)),
+    }
+}
+
+// 源表 mutation 所在的 system.mutations 及本地表：源为分布式表时 mutation 作用于各分片本地表，需查看全部副本
+pub async fn mutation_source(opt: &Opt) -> anyhow::Result<(String, String, String)> {
+    let (db, table) = shard::resolve_local_table(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    let from = if db != opt.src_db || table != opt.src_table {
+        let (_, engine_full) = shard::table_engine(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+        let (cluster, _, _, _) = shard::parse_distributed_engine(&engine_full)?;
+        format!("clusterAllReplicas('{}', system.mutations)", cluster)
+    } else {
+        "system.mutations".to_string()
+    };
+    Ok((from, db, table))
+}
+
+pub struct MutationWatch {
+    sql: String,
+    dsn: String,
+    db: String,
+    action: OnMutation,
+    interval: Duration,
+    seen: Mutex<HashSet<String>>,
+    observed: AtomicBool,
+    paused: AtomicBool,
+    aborted: AtomicBool,
+}
+
+impl MutationWatch {
+    // 记录启动时已存在的 mutation（不论是否完成），之后只关注新增的
+    pub async fn snapshot(opt: &Opt) -> anyhow::Result<Self> {
+        let (from, db, table) = mutation_source(opt).await?;
+        let sql = format!(
+            "SELECT mutation_id, any(command) AS command, toString(min(create_time)) AS create_time, min(is_done) AS is_done \
+             FROM {} WHERE database = '{}' AND table = '{}' GROUP BY mutation_id FORMAT JSONEachRow",
+            from, db, table
+        );
+        let watch = MutationWatch {
+            sql,
+            dsn: opt.src_dsn.clone(),
+            db: opt.src_db.clone(),
+            action: parse_on_mutation(&opt.on_mutation)?,
+            interval: opt.mutation_poll_interval,
+            seen: Mutex::new(HashSet::new()),
+            observed: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            aborted: AtomicBool::new(false),
+        };
+        let rows = ch_query_rows(&watch.dsn, &watch.db, &watch.sql).await?;
+        let mut seen = watch.seen.lock().unwrap();
+        for r in &rows {
+            if let Some(id) = r.get("mutation_id").and_then(|v| v.as_str()) {
+                seen.insert(id.to_string());
+            }
+        }
+        info!("源表 mutation 快照: 已有 {} 个，处理方式 {:?}", seen.len(), watch.action);
+        drop(seen);
+        Ok(watch)
+    }
+
+    // 单次轮询：记录新 mutation，并按处理方式更新暂停/中止状态
+    async fn poll(&self, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<()> {
+        let rows = ch_query_rows(&self.dsn, &self.db, &self.sql).await?;
+        let mut pending = false;
+        let mut seen = self.seen.lock().unwrap();
+        for r in &rows {
+            let id = match r.get("mutation_id").and_then(|v| v.as_str()) {
+                Some(id) => id.to_string(),
+                None => continue,
+            };
+            let is_done = crate::json_u64(r.get("is_done")) == 1;
+            if !seen.contains(&id) {
+                let command = r.get("command").and_then(|v| v.as_str()).unwrap_or("").to_string();
+                let create_time = r.get("create_time").and_then(|v| v.as_str()).unwrap_or("").to_string();
+                warn!("!!! 迁移期间源表出现新的 mutation {}: {} (创建于 {})，已完成分段将在切换前重新校验 !!!", id, command, create_time);
+                self.observed.store(true, Ordering::SeqCst);
+                if self.action == OnMutation::Abort {
+                    self.aborted.store(true, Ordering::SeqCst);
+                }
+                report.lock().unwrap().mutations_observed.push(MutationSeen {
+                    mutation_id: id.clone(),
+                    command,
+                    create_time,
+                    action: format!("{:?}", self.action).to_lowercase(),
+                });
+                seen.insert(id.clone());
+                pending |= !is_done;
+            } else if !is_done && self.paused.load(Ordering::SeqCst) {
+                pending = true;
+            }
+        }
+        drop(seen);
+        if self.action == OnMutation::Pause {
+            let was = self.paused.swap(pending, Ordering::SeqCst);
+            if was && !pending {
+                info!("源表 mutation 已完成，恢复迁移");
+            } else if !was && pending {
+                warn!("源表 mutation 执行中，暂停新的分段迁移");
+            }
+        }
+        Ok(())
+    }
+
+    // 后台轮询任务，迁移结束前由调用方 abort
+    pub fn spawn(self: &Arc<Self>, report: Arc<Mutex<RunReport>>) -> tokio::task::JoinHandle<()> {
+        let watch = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(watch.interval).await;
+                if let Err(e) = watch.poll(&report).await {
+                    warn!("轮询源表 mutation 失败: {e}");
+                }
+            }
+        })
+    }
+
+    pub fn observed(&self) -> bool {
+        self.observed.load(Ordering::SeqCst)
+    }
+
+    pub fn aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    // pause 模式下 mutation 未完成时阻塞，worker 在每个分段开始前调用
+    pub async fn wait_if_paused(&self) {
+        while self.paused.load(Ordering::SeqCst) && !self.aborted() {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
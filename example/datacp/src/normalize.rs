@@ -0,0 +1,138 @@
+// ===================== 比对前取值规范化（--normalize） =====================
+// 历史写入问题可能让同一行在两端只差首尾空白或大小写，逐字节比对会把它们当作缺失行重新写入，造成重复。
+// --normalize trim,lowercase --normalize-columns name,email 只对列出的 String 列在比对时规范化，两端规则相同：
+//   trim       去掉首尾的空格、\t、\n、\r
+//   lowercase  转为小写（客户端按 Unicode 小写，服务端为 lowerUTF8）
+// 客户端摘要在计算前替换取值；服务端表达式（--dst-digest server 的摘要、verify / --background-verify 的校验和）
+// 包一层 lowerUTF8(trim(BOTH ' \t\n\r' FROM col))。写入目标端的仍是源端原值，未列出的列不受影响。
+// 规则记入断点续传元数据（identity），并写入运行报告与 verify 报告：这些列的“一致”只表示规范化后相等，不代表逐字节相同
+
+use log::warn;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::binary::BinaryColumns;
+use crate::report::Normalization;
+use crate::Opt;
+use datacp::{row_digest, RowValues};
+
+const RULES: [&str; 2] = ["trim", "lowercase"];
+
+#[derive(Clone, Default)]
+pub struct Normalize {
+    trim: bool,
+    lowercase: bool,
+    columns: Vec<String>, // 按名称排序
+}
+
+fn trim_ws(s: &str) -> &str {
+    s.trim_matches(|c| matches!(c, ' ' | '\t' | '\n' | '\r'))
+}
+
+// 规范化列取替换值，其余列取原行
+struct Normalized<'a> {
+    row: &'a HashMap<String, Value>,
+    values: HashMap<&'a str, Value>,
+}
+
+impl RowValues for Normalized<'_> {
+    fn value(&self, column: &str) -> Option<&Value> {
+        self.values.get(column).or_else(|| self.row.get(column))
+    }
+}
+
+impl Normalize {
+    // 只解析参数，不检查列类型（断点续传元数据使用）
+    pub fn parse(opt: &Opt) -> anyhow::Result<Self> {
+        let rules: Vec<&str> = opt.normalize.iter().map(|r| r.trim()).filter(|r| !r.is_empty()).collect();
+        if let Some(r) = rules.iter().find(|r| !RULES.contains(r)) {
+            anyhow::bail!(format!("--normalize 只支持 trim,lowercase: {}", r));
+        }
+        let mut columns: Vec<String> = opt.normalize_columns.iter().map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect();
+        columns.sort();
+        columns.dedup();
+        match (rules.is_empty(), columns.is_empty()) {
+            (false, true) => anyhow::bail!("--normalize 需要同时用 --normalize-columns 指定规范化的列"),
+            (true, false) => anyhow::bail!("--normalize-columns 需要同时用 --normalize 指定规则（trim,lowercase）"),
+            _ => {}
+        }
+        Ok(Normalize { trim: rules.contains(&"trim"), lowercase: rules.contains(&"lowercase"), columns })
+    }
+
+    // 解析参数并检查列：须为参与比对的 String 列，不能是按 hex 读取的二进制列
+    pub fn plan(opt: &Opt, binary: &BinaryColumns, compare: &[String]) -> anyhow::Result<Self> {
+        let n = Self::parse(opt)?;
+        for c in &n.columns {
+            if !compare.contains(c) {
+                anyhow::bail!(format!("--normalize-columns 中的 {} 不是参与比对的字段（不存在或已被 --ignore-field / --ignore-compare-field 排除）", c));
+            }
+            let t = binary.type_of(c).unwrap_or("");
+            if binary.is_binary(c) || !t.trim_start_matches("LowCardinality(").trim_start_matches("Nullable(").starts_with("String") {
+                anyhow::bail!(format!("--normalize-columns 只支持 String 列（二进制列除外）: {} 为 {}", c, t));
+            }
+        }
+        if let Some(r) = n.report() {
+            warn!(
+                "比对前规范化 {}（{}）：这些列的一致只表示规范化后相等，不代表逐字节相同；写入目标端的仍是源端原值",
+                r.columns.join(","),
+                r.rules.join(",")
+            );
+        }
+        Ok(n)
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.columns.is_empty()
+    }
+
+    pub fn rules(&self) -> Vec<&'static str> {
+        RULES.iter().copied().filter(|r| (*r == "trim" && self.trim) || (*r == "lowercase" && self.lowercase)).collect()
+    }
+
+    // 断点续传元数据中记录的规则
+    pub fn meta(&self) -> Value {
+        serde_json::json!({ "rules": self.rules(), "columns": self.columns })
+    }
+
+    pub fn report(&self) -> Option<Normalization> {
+        self.enabled().then(|| Normalization {
+            rules: self.rules().iter().map(|r| r.to_string()).collect(),
+            columns: self.columns.clone(),
+            note: "normalized equality, not byte equality".to_string(),
+        })
+    }
+
+    // 服务端表达式：列出的列包一层规范化函数
+    pub fn expr(&self, column: &str, expr: String) -> String {
+        if !self.columns.iter().any(|c| c == column) {
+            return expr;
+        }
+        let mut e = expr;
+        if self.trim {
+            e = format!("trim(BOTH ' \\t\\n\\r' FROM {})", e);
+        }
+        if self.lowercase {
+            e = format!("lowerUTF8({})", e);
+        }
+        e
+    }
+
+    fn value(&self, v: &Value) -> Value {
+        match v {
+            Value::String(s) => {
+                let s = if self.trim { trim_ws(s) } else { s.as_str() };
+                Value::String(if self.lowercase { s.to_lowercase() } else { s.to_string() })
+            }
+            other => other.clone(),
+        }
+    }
+
+    // 客户端摘要：未启用时与 row_digest 相同
+    pub fn digest(&self, row: &HashMap<String, Value>, columns: &[String]) -> [u8; 32] {
+        if !self.enabled() {
+            return row_digest(row, columns);
+        }
+        let values = self.columns.iter().filter_map(|c| row.get(c).map(|v| (c.as_str(), self.value(v)))).collect();
+        row_digest(&Normalized { row, values }, columns)
+    }
+}
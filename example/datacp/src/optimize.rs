@@ -0,0 +1,180 @@
+// ===================== 迁移后 OPTIMIZE FINAL =====================
+// 大量小批量写入后目标表 part 数过多，按 --optimize-after 合并：
+// full 在迁移结束时整表 OPTIMIZE 一次；partition 在分段完成后按分区合并（后台串行、按间隔节流）
+
+use log::{info, warn};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::report::{OptimizeRun, RunReport};
+use crate::{ch_execute_on_cluster, ch_execute_timeout, ch_query_rows, shard, time_zone, Opt};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptimizeAfter {
+    None,
+    Full,
+    Partition,
+}
+
+pub fn parse_optimize_after(s: &str) -> anyhow::Result<OptimizeAfter> {
+    match s {
+        "none" => Ok(OptimizeAfter::None),
+        "full" => Ok(OptimizeAfter::Full),
+        "partition" => Ok(OptimizeAfter::Partition),
+        other => anyhow::bail!(format!("不支持的 --optimize-after: {}（full|partition|none）", other)),
+    }
+}
+
+pub struct Optimizer {
+    mode: OptimizeAfter,
+    dsn: String,
+    db: String,
+    local_db: String,
+    local_table: String,
+    cluster: Option<String>, // 目标为分布式表时对本地表 ON CLUSTER 执行
+    deduplicate: bool,
+    timeout: Duration,
+    interval: Duration,
+    poll_interval: Duration,
+    touched: Mutex<Vec<(String, String)>>, // 已完成且有写入的分段 [seg, seg_end)
+    time_zone: Option<time_zone::ColumnTz>, // 分段键为 UTC 时按显式时区比较
+    running: tokio::sync::Mutex<()>,       // 同一时间只执行一个 OPTIMIZE
+    report: Arc<Mutex<RunReport>>,
+}
+
+impl Optimizer {
+    // --optimize-after none 时返回 None
+    pub async fn new(opt: &Opt, report: Arc<Mutex<RunReport>>) -> anyhow::Result<Option<Self>> {
+        let mode = parse_optimize_after(&opt.optimize_after)?;
+        if mode == OptimizeAfter::None {
+            return Ok(None);
+        }
+        let (local_db, local_table) = shard::resolve_local_table(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+        let cluster = if local_db != opt.dst_db || local_table != opt.read_table() {
+            let (_, engine_full) = shard::table_engine(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+            let (cluster, _, _, _) = shard::parse_distributed_engine(&engine_full)?;
+            Some(cluster)
+        } else {
+            None
+        };
+        Ok(Some(Optimizer {
+            mode,
+            dsn: opt.dst_dsn.clone(),
+            db: opt.dst_db.clone(),
+            local_db,
+            local_table,
+            cluster,
+            deduplicate: opt.optimize_deduplicate,
+            timeout: opt.optimize_timeout,
+            interval: opt.optimize_interval,
+            poll_interval: opt.ddl_poll_interval,
+            touched: Mutex::new(Vec::new()),
+            time_zone: opt.time_zone.clone(),
+            running: tokio::sync::Mutex::new(()),
+            report,
+        }))
+    }
+
+    // worker 在分段完成且有写入时调用
+    pub fn segment_done(&self, seg: &str, seg_end: &str) {
+        if self.mode == OptimizeAfter::Partition {
+            self.touched.lock().unwrap().push((seg.to_string(), seg_end.to_string()));
+        }
+    }
+
+    // 执行一次 OPTIMIZE，失败只告警（数据已正确写入）
+    async fn optimize(&self, partition_id: Option<&str>) {
+        let target = format!("{}.{}", self.local_db, self.local_table);
+        let mut sql = format!("OPTIMIZE TABLE {}", target);
+        if let Some(c) = &self.cluster {
+            sql.push_str(&format!(" ON CLUSTER {}", c));
+        }
+        if let Some(p) = partition_id {
+            sql.push_str(&format!(" PARTITION ID '{}'", p));
+        }
+        sql.push_str(" FINAL");
+        if self.deduplicate {
+            sql.push_str(" DEDUPLICATE");
+        }
+        let started = Instant::now();
+        let res = match &self.cluster {
+            Some(c) => ch_execute_on_cluster(&self.dsn, &self.db, &sql, c, self.timeout, self.poll_interval).await,
+            None => ch_execute_timeout(&self.dsn, &self.db, &sql, self.timeout).await,
+        };
+        let seconds = started.elapsed().as_secs_f64();
+        let status = match &res {
+            Ok(()) => {
+                info!("{} 完成，耗时 {:.1}s", sql, seconds);
+                "ok".to_string()
+            }
+            Err(e) => {
+                warn!("{} 失败（耗时 {:.1}s），不影响迁移结果: {e}", sql, seconds);
+                format!("failed: {}", e)
+            }
+        };
+        self.report.lock().unwrap().optimizations.push(OptimizeRun {
+            target,
+            partition_id: partition_id.map(|p| p.to_string()),
+            seconds,
+            status,
+        });
+    }
+
+    // 合并自上次以来有写入的分段所在的分区
+    async fn run_pending(&self) {
+        let _guard = self.running.lock().await;
+        let touched: Vec<(String, String)> = std::mem::take(&mut *self.touched.lock().unwrap());
+        if touched.is_empty() {
+            return;
+        }
+        let from = match &self.cluster {
+            Some(c) => format!("cluster('{}', system.parts)", c),
+            None => "system.parts".to_string(),
+        };
+        let window = touched
+            .iter()
+            .map(|(s, e)| format!("(max_time >= {} AND min_time < {})", time_zone::lit(self.time_zone.as_ref(), s), time_zone::lit(self.time_zone.as_ref(), e)))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        // 分区键不含时间列时 min/max_time 为 0，只能合并全部分区
+        let sql = format!(
+            "SELECT DISTINCT partition_id FROM {} WHERE database = '{}' AND table = '{}' AND active \
+             AND (toUInt32(max_time) = 0 OR {}) FORMAT JSONEachRow",
+            from, self.local_db, self.local_table, window
+        );
+        let partitions: BTreeSet<String> = match ch_query_rows(&self.dsn, &self.db, &sql).await {
+            Ok(rows) => rows.iter().filter_map(|r| r.get("partition_id").and_then(|v| v.as_str()).map(|s| s.to_string())).collect(),
+            Err(e) => {
+                warn!("查询待合并分区失败: {e}");
+                return;
+            }
+        };
+        for p in partitions {
+            self.optimize(Some(&p)).await;
+        }
+    }
+
+    // partition 模式的后台节流任务，迁移结束时调用方先 finish 再 abort
+    pub fn spawn(self: &Arc<Self>) -> Option<tokio::task::JoinHandle<()>> {
+        if self.mode != OptimizeAfter::Partition {
+            return None;
+        }
+        let o = self.clone();
+        Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(o.interval).await;
+                o.run_pending().await;
+            }
+        }))
+    }
+
+    // 迁移结束：full 整表合并一次，partition 合并剩余分区
+    pub async fn finish(&self) {
+        match self.mode {
+            OptimizeAfter::Full => self.optimize(None).await,
+            OptimizeAfter::Partition => self.run_pending().await,
+            OptimizeAfter::None => {}
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// ===================== 目标端行数多于源端（重复写入） =====================
+// 中断后重跑时已确认写入的批次可能被重复写入，差集比对按行去重，发现不了这种情况；
+// 写入后核对同时检查目标端 count() 是否多于源端，多出的分段在报告中标记 over-copied。
+// --fix-overcopy 配合 --key-columns 对分段所在分区执行 OPTIMIZE ... FINAL DEDUPLICATE BY 键列
+
+use log::{info, warn};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use crate::{ch_execute_on_cluster, ch_execute_timeout, ch_query_rows, shard, time_zone, Opt};
+
+pub struct Deduplicator {
+    dsn: String,
+    db: String,
+    local_db: String,
+    local_table: String,
+    cluster: Option<String>, // 目标为分布式表时对本地表 ON CLUSTER 执行
+    key_columns: Vec<String>,
+    pub apply: bool, // 未指定 --yes 时只报告不去重
+    timeout: Duration,
+    poll_interval: Duration,
+    time_zone: Option<time_zone::ColumnTz>, // 分段键为 UTC 时按显式时区比较
+}
+
+impl Deduplicator {
+    // 未指定 --fix-overcopy 时返回 None
+    pub async fn new(opt: &Opt, col_names: &[String]) -> anyhow::Result<Option<Self>> {
+        if !opt.fix_overcopy {
+            return Ok(None);
+        }
+        if opt.key_columns.is_empty() {
+            anyhow::bail!("--fix-overcopy 需要同时指定 --key-columns");
+        }
+        if opt.no_post_count {
+            anyhow::bail!("--fix-overcopy 依赖写入后行数核对，不能与 --no-post-count 同时使用");
+        }
+        for k in &opt.key_columns {
+            if !col_names.contains(k) {
+                anyhow::bail!(format!("--key-columns 中的 {} 不在迁移字段中", k));
+            }
+        }
+        if !opt.yes {
+            warn!("--fix-overcopy 需要同时指定 --yes 才会对目标表去重，本次只报告重复分段");
+        }
+        let (local_db, local_table) = shard::resolve_local_table(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+        let cluster = if local_db != opt.dst_db || local_table != opt.read_table() {
+            let (_, engine_full) = shard::table_engine(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+            let (cluster, _, _, _) = shard::parse_distributed_engine(&engine_full)?;
+            Some(cluster)
+        } else {
+            None
+        };
+        Ok(Some(Deduplicator {
+            dsn: opt.dst_dsn.clone(),
+            db: opt.dst_db.clone(),
+            local_db,
+            local_table,
+            cluster,
+            key_columns: opt.key_columns.clone(),
+            apply: opt.yes,
+            timeout: opt.ddl_timeout,
+            poll_interval: opt.ddl_poll_interval,
+            time_zone: opt.time_zone.clone(),
+        }))
+    }
+
+    // 分段 [seg, seg_end) 所在的分区；分区键不含时间列时 min/max_time 为 0，只能取全部分区
+    async fn partitions(&self, seg: &str, seg_end: &str) -> anyhow::Result<BTreeSet<String>> {
+        let from = match &self.cluster {
+            Some(c) => format!("cluster('{}', system.parts)", c),
+            None => "system.parts".to_string(),
+        };
+        let sql = format!(
+            "SELECT DISTINCT partition_id FROM {} WHERE database = '{}' AND table = '{}' AND active \
+             AND (toUInt32(max_time) = 0 OR (max_time >= {} AND min_time < {})) FORMAT JSONEachRow",
+            from,
+            self.local_db,
+            self.local_table,
+            time_zone::lit(self.time_zone.as_ref(), seg),
+            time_zone::lit(self.time_zone.as_ref(), seg_end)
+        );
+        let rows = ch_query_rows(&self.dsn, &self.db, &sql).await?;
+        Ok(rows.iter().filter_map(|r| r.get("partition_id").and_then(|v| v.as_str()).map(|s| s.to_string())).collect())
+    }
+
+    // 对分段所在分区按键列去重，返回处理结果（写入报告）；调用方先检查 apply
+    pub async fn dedupe(&self, seg: &str, seg_end: &str) -> anyhow::Result<String> {
+        let partitions = self.partitions(seg, seg_end).await?;
+        for p in &partitions {
+            let sql = format!(
+                "OPTIMIZE TABLE {}.{}{} PARTITION ID '{}' FINAL DEDUPLICATE BY {}",
+                self.local_db,
+                self.local_table,
+                self.cluster.as_ref().map(|c| format!(" ON CLUSTER {}", c)).unwrap_or_default(),
+                p,
+                self.key_columns.join(",")
+            );
+            match &self.cluster {
+                Some(c) => ch_execute_on_cluster(&self.dsn, &self.db, &sql, c, self.timeout, self.poll_interval).await?,
+                None => ch_execute_timeout(&self.dsn, &self.db, &sql, self.timeout).await?,
+            }
+            info!("segment {seg} overcopy: {}", sql);
+        }
+        Ok(format!("deduplicated {} partition(s)", partitions.len()))
+    }
+}
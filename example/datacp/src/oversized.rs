@@ -0,0 +1,107 @@
+// ===================== 超大行（--max-row-bytes） =====================
+// 个别行的 String 列可能是几十 MB 的 JSON，和普通行一起分批时整批请求体过大，触发目标端的解析上限，整批反复失败。
+// 超过 --max-row-bytes 的行从所在批次中拿出来单独写入，请求上调 max_query_size 与 min_chunk_bytes_for_parallel_parsing；
+// 超过 --max-row-hard-bytes 的行不再写入，原样写入 --dead-letter-file 并记入报告，不会让分段永远失败。
+// 各分段遇到的超大行数在汇总中列出
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::report::OversizedRows;
+use crate::Opt;
+
+type Row = HashMap<String, Value>;
+
+// JSON 转义最多把一个字节放大为 6 个字节（\u00XX），粗估超过上限的 1/6 时才精确计算
+const ESCAPE_FACTOR: u64 = 6;
+
+pub enum Size {
+    Normal,
+    Single(u64),     // 单行写入
+    DeadLetter(u64), // 超过硬上限，写入死信文件
+}
+
+pub struct Oversized {
+    max_row_bytes: u64,
+    hard_bytes: u64,
+    found: Mutex<HashMap<String, OversizedRows>>,
+}
+
+// 不含转义的粗略大小：键与字符串的字节数加上分隔符，数字等按文本长度估计
+fn approx(v: &Value) -> u64 {
+    match v {
+        Value::String(s) => s.len() as u64 + 2,
+        Value::Array(a) => a.iter().map(approx).sum::<u64>() + a.len() as u64 + 2,
+        Value::Object(o) => o.iter().map(|(k, v)| k.len() as u64 + 4 + approx(v)).sum::<u64>() + 2,
+        Value::Null => 4,
+        Value::Bool(_) => 5,
+        Value::Number(n) => n.to_string().len() as u64,
+    }
+}
+
+impl Oversized {
+    pub fn new(opt: &Opt) -> anyhow::Result<Self> {
+        if opt.max_row_bytes > 0 && opt.max_row_hard_bytes > 0 && opt.max_row_hard_bytes < opt.max_row_bytes {
+            anyhow::bail!(format!(
+                "--max-row-hard-bytes {} 不能小于 --max-row-bytes {}",
+                opt.max_row_hard_bytes, opt.max_row_bytes
+            ));
+        }
+        Ok(Oversized { max_row_bytes: opt.max_row_bytes, hard_bytes: opt.max_row_hard_bytes, found: Mutex::new(HashMap::new()) })
+    }
+
+    // 按 JSONEachRow 序列化后的大小分类；两个上限都为 0 时不检查
+    pub fn classify(&self, row: &Row) -> Size {
+        let limit = match (self.max_row_bytes, self.hard_bytes) {
+            (0, 0) => return Size::Normal,
+            (0, h) => h,
+            (m, _) => m,
+        };
+        let rough: u64 = row.iter().map(|(k, v)| k.len() as u64 + 4 + approx(v)).sum();
+        if rough * ESCAPE_FACTOR <= limit {
+            return Size::Normal;
+        }
+        let bytes = serde_json::to_vec(row).map(|b| b.len() as u64).unwrap_or(rough);
+        if self.hard_bytes > 0 && bytes > self.hard_bytes {
+            Size::DeadLetter(bytes)
+        } else if self.max_row_bytes > 0 && bytes > self.max_row_bytes {
+            Size::Single(bytes)
+        } else {
+            Size::Normal
+        }
+    }
+
+    // 分段（重新）开始处理时清空上一次的计数，报告只反映最后一次处理
+    pub fn reset(&self, seg: &str) {
+        self.found.lock().unwrap().remove(seg);
+    }
+
+    pub fn note(&self, seg: &str, bytes: u64, dead_lettered: bool) {
+        let mut found = self.found.lock().unwrap();
+        let e = found.entry(seg.to_string()).or_insert_with(|| OversizedRows { segment: seg.to_string(), ..Default::default() });
+        if dead_lettered {
+            e.dead_lettered += 1;
+        } else {
+            e.single_row += 1;
+        }
+        e.largest_bytes = e.largest_bytes.max(bytes);
+    }
+
+    // 分段中写入死信文件、未迁移的行数，写入后行数核对时扣除
+    pub fn dead_lettered(&self, seg: &str) -> u64 {
+        self.found.lock().unwrap().get(seg).map(|e| e.dead_lettered).unwrap_or(0)
+    }
+
+    pub fn segments(&self) -> Vec<OversizedRows> {
+        let mut v: Vec<OversizedRows> = self.found.lock().unwrap().values().cloned().collect();
+        v.sort_by(|a, b| a.segment.cmp(&b.segment));
+        v
+    }
+}
+
+// 单行写入请求的设置：解析上限放宽到行大小的两倍
+pub fn settings(bytes: u64) -> Vec<(String, String)> {
+    let limit = (bytes * 2).to_string();
+    vec![("max_query_size".to_string(), limit.clone()), ("min_chunk_bytes_for_parallel_parsing".to_string(), limit)]
+}
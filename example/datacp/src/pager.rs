@@ -0,0 +1,110 @@
+// ===================== 分段内键集分页与页级断点续传 =====================
+// --page-key 指定后，单个分段按该列排序分页读取（ORDER BY key LIMIT n WITH TIES），
+// 每页与目标端同键区间比对写入；页内写入全部确认后才把该页末尾键记入旁路状态文件，
+// 中断后从最后确认的页继续，分段完成时清除记录
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::mirror::sql_literal;
+use crate::Opt;
+
+pub struct Pager {
+    pub key: String,
+    rows: usize,
+    file: String,
+    marks: Mutex<HashMap<String, Value>>, // 分段 -> 最后确认的页末尾键
+}
+
+// 页标记文件，每行 "分段\t键(JSON)"
+fn pages_file(done_segments_file: &str) -> String {
+    format!("{}.pages", done_segments_file)
+}
+
+impl Pager {
+    // 未指定 --page-key 时返回 None
+    pub fn new(opt: &Opt, col_names: &[String], done_segments_file: &str) -> anyhow::Result<Option<Self>> {
+        if opt.page_key.is_empty() {
+            return Ok(None);
+        }
+        if opt.copy_mode != "http" {
+            anyhow::bail!(format!("--page-key 只支持 --copy-mode http，当前为 {}", opt.copy_mode));
+        }
+        if !col_names.contains(&opt.page_key) {
+            anyhow::bail!(format!("--page-key {} 不在迁移字段中", opt.page_key));
+        }
+        if opt.page_rows == 0 {
+            anyhow::bail!("--page-rows 必须大于 0");
+        }
+        let file = pages_file(done_segments_file);
+        let mut marks = HashMap::new();
+        if let Ok(text) = std::fs::read_to_string(&file) {
+            for line in text.lines() {
+                if let Some((seg, key)) = line.split_once('\t') {
+                    marks.insert(seg.to_string(), serde_json::from_str(key)?);
+                }
+            }
+        }
+        Ok(Some(Pager { key: opt.page_key.clone(), rows: opt.page_rows, file, marks: Mutex::new(marks) }))
+    }
+
+    // 上次中断时最后确认的页末尾键
+    pub fn resume_from(&self, seg: &str) -> Option<Value> {
+        self.marks.lock().unwrap().get(seg).cloned()
+    }
+
+    // 本页下界：键大于上一页末尾键
+    pub fn lower(&self, after: Option<&Value>) -> anyhow::Result<String> {
+        Ok(match after {
+            Some(k) => format!(" AND {} > {}", self.key, sql_literal(k)?),
+            None => String::new(),
+        })
+    }
+
+    // 源端分页：同键的行不拆到两页
+    pub fn src_tail(&self) -> String {
+        format!(" ORDER BY {} LIMIT {} WITH TIES", self.key, self.rows)
+    }
+
+    // 本页末尾键，未取满一页时为最后一页，返回 None
+    pub fn page_end(&self, src_rows: &[HashMap<String, Value>]) -> Option<Value> {
+        if src_rows.len() < self.rows {
+            return None;
+        }
+        src_rows.last().and_then(|r| r.get(&self.key)).cloned()
+    }
+
+    // 本页上界（目标端读取），最后一页不设上界以覆盖目标端多出的行
+    pub fn upper(&self, end: Option<&Value>) -> anyhow::Result<String> {
+        Ok(match end {
+            Some(k) => format!(" AND {} <= {}", self.key, sql_literal(k)?),
+            None => String::new(),
+        })
+    }
+
+    // 先写临时文件再 rename，进程崩溃时不会留下半行
+    fn persist(&self, marks: &HashMap<String, Value>) -> anyhow::Result<()> {
+        let tmp = format!("{}.tmp", self.file);
+        let text: String = marks.iter().map(|(s, k)| format!("{}\t{}\n", s, k)).collect();
+        std::fs::write(&tmp, text)?;
+        std::fs::rename(&tmp, &self.file)?;
+        Ok(())
+    }
+
+    // 页内写入全部确认后调用
+    pub fn advance(&self, seg: &str, key: &Value) -> anyhow::Result<()> {
+        let mut marks = self.marks.lock().unwrap();
+        marks.insert(seg.to_string(), key.clone());
+        self.persist(&marks)
+    }
+
+    // 分段完成后清除页标记
+    pub fn clear(&self, seg: &str) -> anyhow::Result<()> {
+        let mut marks = self.marks.lock().unwrap();
+        if marks.remove(seg).is_some() {
+            self.persist(&marks)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+// ===================== 增量与 _bak 阶段的断点记录 =====================
+// 首轮分段按分段记录完成；增量循环与切换阶段的 _bak 补差同样写入断点续传文件，但使用独立前缀，
+// 不与首轮分段混淆（_bak 分段与源表分段的时间相同）：
+//   incremental:时间    增量循环每次派发完成后推进到的源端最大时间
+//   bak:分段            _bak 兜底增量已完成的分段（字节记录为 bytes:bak:分段）
+// 源表改名为 _bak 之后进程退出时，重新运行会按 cutover.state 自动继续切换：跳过已完成的 _bak 补差与 _bak 分段，再 rename 目标表。
+// 切换完成后断点续传文件整体归档，这些记录不会影响之后的运行
+
+use log::info;
+use std::collections::HashSet;
+
+use crate::save_done_segment;
+
+pub const INCREMENTAL_PREFIX: &str = "incremental:";
+pub const BAK_PREFIX: &str = "bak:";
+
+// 增量循环推进到的源端最大时间
+pub fn record_incremental(done_segments_file: &str, cur_max_time: &str) -> anyhow::Result<()> {
+    save_done_segment(done_segments_file, &format!("{}{}", INCREMENTAL_PREFIX, cur_max_time))
+}
+
+// 上次运行增量循环最后推进到的时间（按时间取最大，文件合并后顺序不可靠）
+pub fn last_incremental(done: &HashSet<String>) -> Option<String> {
+    done.iter().filter_map(|l| l.strip_prefix(INCREMENTAL_PREFIX)).max().map(|s| s.to_string())
+}
+
+// 已完成的 _bak 分段（去掉前缀）
+pub fn bak_segments(done: &HashSet<String>) -> HashSet<String> {
+    let segs: HashSet<String> = done.iter().filter_map(|l| l.strip_prefix(BAK_PREFIX)).map(|s| s.to_string()).collect();
+    if !segs.is_empty() {
+        info!("_bak 兜底增量已完成 {} 个分段，跳过", segs.len());
+    }
+    segs
+}
@@ -0,0 +1,349 @@
+// ===================== 分段规划 =====================
+// 不做 I/O 的分段规划，datacp 的首轮、增量、缺口检查与重新迁移共用，外部程序也可按同一规则预先算出分段：
+// 给定迁移范围、分段粒度、已完成区间与过滤条件，得出要处理的分段。时间轴（NaiveDateTime）与键轴（i64）共用同一套区间运算：
+//   1. 范围两端都包含在内：从起点按粒度切分，起点不晚于终点的各段都生成，末段覆盖终点本身
+//      （终点通常是源表的 max(time)，该时刻的行属于末段，末段可越过终点）；起点等于终点时也有一段，起点晚于终点时没有分段；
+//   2. 每段减去已完成区间的并集（IntervalSet，半开区间 [起, 止)）：整段未完成的以起点为名，
+//      只有部分未完成的以 "起..止" 为名、只处理其中未完成的部分；
+//   3. 按过滤条件去掉分段：只处理列表（分段名须在其中）与黑名单（起点在黑名单中或与黑名单区间相交，另行列出）。
+// 时间只做 NaiveDateTime 上的定长加法，与时区、夏令时无关（分段键是不带时区的墙上时间或 UTC）
+
+use chrono::{Duration, NaiveDateTime};
+use std::collections::{BTreeSet, HashSet};
+use std::ops::RangeInclusive;
+
+// 分段名与断点续传记录中的时间格式
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// 分段轴上的点：时间或整数键
+pub trait Point: Copy + Ord {
+    type Step: Copy;
+    // 加上一个粒度，溢出时为 None
+    fn advance(self, step: Self::Step) -> Option<Self>;
+    // 分段名中的写法
+    fn name(self) -> String;
+}
+
+impl Point for NaiveDateTime {
+    type Step = Duration;
+    fn advance(self, step: Duration) -> Option<Self> {
+        self.checked_add_signed(step)
+    }
+    fn name(self) -> String {
+        self.format(TIME_FORMAT).to_string()
+    }
+}
+
+impl Point for i64 {
+    type Step = i64;
+    fn advance(self, step: i64) -> Option<Self> {
+        self.checked_add(step)
+    }
+    fn name(self) -> String {
+        self.to_string()
+    }
+}
+
+// 区间的并集：按起点排序、互不相交也不相接的半开区间 [起, 止)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntervalSet<P>(Vec<(P, P)>);
+
+impl<P: Point> Default for IntervalSet<P> {
+    fn default() -> Self {
+        IntervalSet(Vec::new())
+    }
+}
+
+impl<P: Point> IntervalSet<P> {
+    // 任意顺序的区间，相接或重叠的合并，空区间忽略
+    pub fn new(spans: impl IntoIterator<Item = (P, P)>) -> Self {
+        let mut spans: Vec<_> = spans.into_iter().filter(|(a, b)| a < b).collect();
+        spans.sort();
+        let mut merged: Vec<(P, P)> = Vec::new();
+        for (a, b) in spans {
+            match merged.last_mut() {
+                Some((_, end)) if a <= *end => *end = (*end).max(b),
+                _ => merged.push((a, b)),
+            }
+        }
+        IntervalSet(merged)
+    }
+
+    pub fn spans(&self) -> &[(P, P)] {
+        &self.0
+    }
+
+    // [from, to) 中未被覆盖的部分
+    pub fn holes(&self, from: P, to: P) -> Vec<(P, P)> {
+        let (mut out, mut t) = (Vec::new(), from);
+        let first = self.0.partition_point(|(_, b)| *b <= from);
+        for (a, b) in &self.0[first..] {
+            if *a >= to {
+                break;
+            }
+            if *a > t {
+                out.push((t, *a));
+            }
+            t = *b;
+            if t >= to {
+                break;
+            }
+        }
+        if t < to {
+            out.push((t, to));
+        }
+        out
+    }
+
+    pub fn covers(&self, from: P, to: P) -> bool {
+        self.holes(from, to).is_empty()
+    }
+
+    pub fn intersects(&self, from: P, to: P) -> bool {
+        from < to && self.holes(from, to) != [(from, to)]
+    }
+
+    // 最晚的终点
+    pub fn end(&self) -> Option<P> {
+        self.0.last().map(|(_, b)| *b)
+    }
+}
+
+// 一个分段：[start, end)；whole 为整段未完成（以起点为名），否则为部分未完成（以 "起..止" 为名）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment<P> {
+    pub start: P,
+    pub end: P,
+    pub whole: bool,
+}
+
+impl<P: Point> Segment<P> {
+    pub fn name(&self) -> String {
+        if self.whole {
+            self.start.name()
+        } else {
+            format!("{}..{}", self.start.name(), self.end.name())
+        }
+    }
+}
+
+// 黑名单：起点为 starts 之一，或与 ranges 中任一 [起, 止) 相交的分段不处理
+#[derive(Debug, Clone)]
+pub struct Blacklist<P> {
+    pub starts: BTreeSet<P>,
+    pub ranges: Vec<(P, P)>,
+}
+
+impl<P: Point> Default for Blacklist<P> {
+    fn default() -> Self {
+        Blacklist { starts: BTreeSet::new(), ranges: Vec::new() }
+    }
+}
+
+impl<P: Point> Blacklist<P> {
+    pub fn hits(&self, seg: &Segment<P>) -> bool {
+        self.starts.contains(&seg.start) || self.ranges.iter().any(|(a, b)| seg.start < *b && seg.end > *a)
+    }
+}
+
+// 过滤条件，未指定的不过滤
+#[derive(Debug, Clone, Copy)]
+pub struct Filters<'a, P> {
+    pub blacklist: Option<&'a Blacklist<P>>,
+    pub only: Option<&'a HashSet<String>>, // 只处理这些分段名
+}
+
+impl<P> Default for Filters<'_, P> {
+    fn default() -> Self {
+        Filters { blacklist: None, only: None }
+    }
+}
+
+// 规划结果：要处理的分段（按起点排序）与被黑名单去掉的分段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentPlan<P> {
+    pub segments: Vec<Segment<P>>,
+    pub blacklisted: Vec<Segment<P>>,
+}
+
+impl<P: Point> SegmentPlan<P> {
+    // range 两端都包含在内；granularity 须为正，否则没有分段
+    pub fn new(range: RangeInclusive<P>, granularity: P::Step, done: &IntervalSet<P>, filters: &Filters<P>) -> Self {
+        let mut plan = SegmentPlan { segments: Vec::new(), blacklisted: Vec::new() };
+        let (mut t, max) = (*range.start(), *range.end());
+        while t <= max {
+            let Some(end) = t.advance(granularity).filter(|e| *e > t) else { break };
+            for (a, b) in done.holes(t, end) {
+                let seg = Segment { start: a, end: b, whole: a == t && b == end };
+                if filters.only.is_some_and(|only| !only.contains(&seg.name())) {
+                    continue;
+                }
+                if filters.blacklist.is_some_and(|bl| bl.hits(&seg)) {
+                    plan.blacklisted.push(seg);
+                } else {
+                    plan.segments.push(seg);
+                }
+            }
+            t = end;
+        }
+        plan
+    }
+
+    // 只要处理的分段
+    pub fn build(range: RangeInclusive<P>, granularity: P::Step, done: &IntervalSet<P>, filters: &Filters<P>) -> Vec<Segment<P>> {
+        Self::new(range, granularity, done, filters).segments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, TIME_FORMAT).unwrap()
+    }
+
+    fn names<P: Point>(segs: &[Segment<P>]) -> Vec<String> {
+        segs.iter().map(|s| s.name()).collect()
+    }
+
+    fn hourly(min: &str, max: &str, done: &IntervalSet<NaiveDateTime>, filters: &Filters<NaiveDateTime>) -> Vec<String> {
+        names(&SegmentPlan::build(t(min)..=t(max), Duration::hours(1), done, filters))
+    }
+
+    fn plain(min: &str, max: &str) -> Vec<String> {
+        hourly(min, max, &IntervalSet::default(), &Filters::default())
+    }
+
+    #[test]
+    fn range_end_is_inclusive() {
+        // 终点正好落在分段边界上：该时刻的行属于以终点为起点的一段
+        assert_eq!(plain("2024-01-01 00:00:00", "2024-01-01 02:00:00"), ["2024-01-01 00:00:00", "2024-01-01 01:00:00", "2024-01-01 02:00:00"]);
+        // 终点在段内：末段越过终点
+        assert_eq!(plain("2024-01-01 00:00:00", "2024-01-01 01:59:59"), ["2024-01-01 00:00:00", "2024-01-01 01:00:00"]);
+        // 起点不对齐整点时分段从起点起算
+        assert_eq!(plain("2024-01-01 00:17:33", "2024-01-01 01:17:33"), ["2024-01-01 00:17:33", "2024-01-01 01:17:33"]);
+    }
+
+    #[test]
+    fn single_point_and_empty_ranges() {
+        assert_eq!(plain("2024-01-01 05:00:00", "2024-01-01 05:00:00"), ["2024-01-01 05:00:00"]);
+        assert!(plain("2024-01-01 06:00:00", "2024-01-01 05:00:00").is_empty());
+        // 粒度不为正时没有分段（不会死循环）
+        let none = IntervalSet::default();
+        assert!(SegmentPlan::build(t("2024-01-01 00:00:00")..=t("2024-01-02 00:00:00"), Duration::zero(), &none, &Filters::default()).is_empty());
+        assert!(SegmentPlan::<i64>::build(0..=10, -1, &IntervalSet::default(), &Filters::default()).is_empty());
+    }
+
+    #[test]
+    fn arithmetic_ignores_dst_and_calendar() {
+        // 美国与欧洲夏令时切换日仍是 24 个小时分段，墙上时间 02:00 照常存在
+        let us = plain("2024-03-10 00:00:00", "2024-03-10 23:59:59");
+        assert_eq!(us.len(), 24);
+        assert!(us.contains(&"2024-03-10 02:00:00".to_string()));
+        assert_eq!(plain("2024-10-27 00:00:00", "2024-10-27 23:59:59").len(), 24);
+        // 按天分段跨过闰日与月末
+        let days = names(&SegmentPlan::build(
+            t("2024-02-28 00:00:00")..=t("2024-03-01 00:00:00"),
+            Duration::days(1),
+            &IntervalSet::default(),
+            &Filters::default(),
+        ));
+        assert_eq!(days, ["2024-02-28 00:00:00", "2024-02-29 00:00:00", "2024-03-01 00:00:00"]);
+        // 15 分钟分段
+        let quarter = names(&SegmentPlan::build(
+            t("2024-01-01 00:00:00")..=t("2024-01-01 00:30:00"),
+            Duration::minutes(15),
+            &IntervalSet::default(),
+            &Filters::default(),
+        ));
+        assert_eq!(quarter, ["2024-01-01 00:00:00", "2024-01-01 00:15:00", "2024-01-01 00:30:00"]);
+    }
+
+    #[test]
+    fn interval_set_merges_and_subtracts() {
+        let set = IntervalSet::<i64>::new([(5, 7), (0, 2), (2, 3), (6, 9), (4, 4)]);
+        assert_eq!(set.spans(), [(0, 3), (5, 9)]);
+        assert_eq!(set.holes(0, 10), [(3, 5), (9, 10)]);
+        assert!(set.holes(1, 2).is_empty());
+        assert_eq!(set.holes(3, 5), [(3, 5)]);
+        assert!(set.covers(5, 9));
+        assert!(!set.covers(4, 9));
+        assert!(set.intersects(2, 4));
+        assert!(!set.intersects(3, 5));
+        assert!(!set.intersects(4, 4));
+        assert_eq!(set.end(), Some(9));
+        assert_eq!(IntervalSet::<i64>::default().end(), None);
+    }
+
+    #[test]
+    fn done_intervals_are_subtracted() {
+        let done = IntervalSet::new([
+            (t("2024-01-01 00:00:00"), t("2024-01-01 01:00:00")),
+            // 先前以 15 分钟分段完成的一部分
+            (t("2024-01-01 01:00:00"), t("2024-01-01 01:15:00")),
+            (t("2024-01-01 01:30:00"), t("2024-01-01 01:45:00")),
+            (t("2024-01-01 03:00:00"), t("2024-01-01 05:00:00")),
+        ]);
+        assert_eq!(
+            hourly("2024-01-01 00:00:00", "2024-01-01 05:00:00", &done, &Filters::default()),
+            [
+                "2024-01-01 01:15:00..2024-01-01 01:30:00",
+                "2024-01-01 01:45:00..2024-01-01 02:00:00",
+                "2024-01-01 02:00:00",
+                "2024-01-01 05:00:00",
+            ]
+        );
+        // 以更大的分段完成过的时段不再生成
+        let day = IntervalSet::new([(t("2024-01-01 00:00:00"), t("2024-01-02 00:00:00"))]);
+        assert!(hourly("2024-01-01 00:00:00", "2024-01-01 23:00:00", &day, &Filters::default()).is_empty());
+    }
+
+    #[test]
+    fn blacklist_is_listed_separately() {
+        let bl = Blacklist {
+            starts: [t("2024-01-01 01:00:00")].into_iter().collect(),
+            ranges: vec![(t("2024-01-01 03:30:00"), t("2024-01-01 03:40:00"))],
+        };
+        let filters = Filters { blacklist: Some(&bl), only: None };
+        let plan = SegmentPlan::new(t("2024-01-01 00:00:00")..=t("2024-01-01 04:00:00"), Duration::hours(1), &IntervalSet::default(), &filters);
+        assert_eq!(names(&plan.segments), ["2024-01-01 00:00:00", "2024-01-01 02:00:00", "2024-01-01 04:00:00"]);
+        assert_eq!(names(&plan.blacklisted), ["2024-01-01 01:00:00", "2024-01-01 03:00:00"]);
+        // 部分未完成的分段按其区间判断
+        let done = IntervalSet::new([(t("2024-01-01 03:00:00"), t("2024-01-01 03:45:00"))]);
+        let plan = SegmentPlan::new(t("2024-01-01 03:00:00")..=t("2024-01-01 03:00:00"), Duration::hours(1), &done, &filters);
+        assert_eq!(names(&plan.segments), ["2024-01-01 03:45:00..2024-01-01 04:00:00"]);
+    }
+
+    #[test]
+    fn only_filter_keeps_listed_names() {
+        let only: HashSet<String> = ["2024-01-01 01:00:00", "2024-01-01 02:30:00..2024-01-01 03:00:00", "2024-01-01 09:00:00"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let done = IntervalSet::new([(t("2024-01-01 02:00:00"), t("2024-01-01 02:30:00"))]);
+        let bl = Blacklist { starts: [t("2024-01-01 00:00:00")].into_iter().collect(), ranges: Vec::new() };
+        let plan = SegmentPlan::new(
+            t("2024-01-01 00:00:00")..=t("2024-01-01 03:00:00"),
+            Duration::hours(1),
+            &done,
+            &Filters { blacklist: Some(&bl), only: Some(&only) },
+        );
+        assert_eq!(names(&plan.segments), ["2024-01-01 01:00:00", "2024-01-01 02:30:00..2024-01-01 03:00:00"]);
+        // 不在列表中的分段不计入黑名单
+        assert!(plan.blacklisted.is_empty());
+    }
+
+    #[test]
+    fn key_ranges() {
+        assert_eq!(names(&SegmentPlan::<i64>::build(0..=999, 250, &IntervalSet::default(), &Filters::default())), ["0", "250", "500", "750"]);
+        assert_eq!(names(&SegmentPlan::<i64>::build(0..=1000, 250, &IntervalSet::default(), &Filters::default())), ["0", "250", "500", "750", "1000"]);
+        let done = IntervalSet::<i64>::new([(0, 100), (500, 750)]);
+        let bl = Blacklist { starts: BTreeSet::new(), ranges: vec![(900, 901)] };
+        let plan = SegmentPlan::new(0..=999, 250, &done, &Filters { blacklist: Some(&bl), only: None });
+        assert_eq!(names(&plan.segments), ["100..250", "250"]);
+        assert_eq!(names(&plan.blacklisted), ["750"]);
+        // 靠近 i64::MAX 时末段溢出，不会 panic
+        assert_eq!(names(&SegmentPlan::build(i64::MAX - 15..=i64::MAX, 10, &IntervalSet::default(), &Filters::default())), [(i64::MAX - 15).to_string()]);
+    }
+}
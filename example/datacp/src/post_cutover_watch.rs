@@ -0,0 +1,140 @@
+// ===================== 切换后新表写入观察（--post-cutover-watch） =====================
+// 切换只改了表名，写入方是否真的写到了新表要等切换之后才看得出来：写入方仍连着旧地址、旧库或缓存了旧表时，
+// 新表收不到新行，_bak 表反而继续增长。切换完成后在 --post-cutover-watch 时长内每隔 --post-cutover-watch-interval
+// 采样一次，两端各一条只读查询统计时间晚于 since（观察开始时 _bak 表的最大时间）的行数与其中的最大时间：
+//   - 新表的最大时间超过 --post-cutover-stall-after 未前进（或整个观察期内没有新鲜行）判定为停滞
+//   - _bak 表出现晚于 since 的行，说明仍有写入方写到旧表
+// 每类问题告警一次（日志、--alert-webhook），结束时报告 post_cutover_watch 给出健康结论与各次采样，
+// 有问题时运行结果按切换后校验未通过处理（退出码 3）
+
+use log::{error, info, warn};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::report::{self, PostCutoverWatch, RunReport, WatchSample};
+use crate::{background_verify, ch_query_rows, filter_sql, get_max_time_http, json_u64, qualified, status, time_zone, Opt};
+
+const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+// 时间晚于 since 的行数与其中的最大时间（没有时为空），最大时间与分段键可比
+async fn fresh(opt: &Opt, dsn: &str, db: &str, table: &str, since: &str) -> anyhow::Result<(u64, String)> {
+    let sql = format!(
+        "SELECT count() AS c, toString(max({tf})) AS m FROM {} WHERE {tf} > {}{} FORMAT JSONEachRow",
+        qualified(db, table),
+        opt.time_lit(since),
+        filter_sql(&opt.filter),
+        tf = opt.time_field
+    );
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    let r = rows.first();
+    let c = json_u64(r.and_then(|r| r.get("c")));
+    if c == 0 {
+        return Ok((0, String::new()));
+    }
+    let max = r.and_then(|r| r.get("m")).and_then(|v| v.as_str()).unwrap_or("");
+    Ok((c, time_zone::from_column(opt.time_zone.as_ref(), max, true)))
+}
+
+async fn sample(opt: &Opt, bak_table: &str, since: &str) -> anyhow::Result<WatchSample> {
+    let (bak_rows, bak_max_time) = fresh(opt, &opt.src_dsn, &opt.src_db, bak_table, since).await?;
+    let (live_rows, live_max_time) = fresh(opt, &opt.dst_dsn, opt.cutover_db(), &opt.src_table, since).await?;
+    Ok(WatchSample { time: report::now_str(), bak_rows, live_rows, bak_max_time, live_max_time })
+}
+
+// 记录问题并告警
+async fn alert(opt: &Opt, w: &mut PostCutoverWatch, kind: &str, bak_table: &str, text: String) {
+    warn!("切换后观察: {}", text);
+    if !opt.alert_webhook.is_empty() {
+        let body = json!({
+            "alert": kind,
+            "table": qualified(opt.cutover_db(), &opt.src_table),
+            "bak_table": qualified(&opt.src_db, bak_table),
+            "since": w.since,
+            "detail": text,
+        });
+        background_verify::post_alert(&opt.alert_webhook, &body, "切换后观察").await;
+    }
+    w.issues.push(text);
+}
+
+// 只读观察，查询失败只记日志；结论写入报告
+pub async fn run(opt: &Opt, bak_table: &str, report: &Arc<Mutex<RunReport>>) {
+    status::set_phase("post-cutover-watch");
+    let tz = opt.time_zone.as_ref();
+    let live = qualified(opt.cutover_db(), &opt.src_table);
+    let since = match get_max_time_http(&opt.src_dsn, &opt.src_db, bak_table, &opt.time_field, &filter_sql(&opt.filter), tz).await {
+        Ok(t) if !t.is_empty() => t,
+        Ok(_) => time_zone::now(&opt.src_dsn, tz).format(FORMAT).to_string(),
+        Err(e) => {
+            warn!("切换后观察: 查询 {} 的最大时间失败，以当前时间为新鲜行下界: {e}", bak_table);
+            time_zone::now(&opt.src_dsn, tz).format(FORMAT).to_string()
+        }
+    };
+    info!(
+        "切换后观察 {}s: 每 {}s 比较新表 {} 与 {} 中时间晚于 {} 的行",
+        opt.post_cutover_watch.as_secs(),
+        opt.post_cutover_watch_interval.as_secs(),
+        live,
+        bak_table,
+        since
+    );
+    let mut w = PostCutoverWatch { started_at: report::now_str(), since: since.clone(), ..Default::default() };
+    let start = Instant::now();
+    let mut last_advance = start;
+    let (mut live_max, mut stalled, mut bak_growing) = (String::new(), false, false);
+    loop {
+        match sample(opt, bak_table, &since).await {
+            Ok(s) => {
+                info!(
+                    "切换后观察: 新表新鲜行 {}（最大时间 {}），{} 新鲜行 {}",
+                    s.live_rows,
+                    if s.live_max_time.is_empty() { "-" } else { &s.live_max_time },
+                    bak_table,
+                    s.bak_rows
+                );
+                if s.live_max_time > live_max {
+                    live_max = s.live_max_time.clone();
+                    last_advance = Instant::now();
+                }
+                if !stalled && last_advance.elapsed() >= opt.post_cutover_stall_after {
+                    stalled = true;
+                    let text = format!(
+                        "新表 {} 已 {}s 没有收到新行（最大时间 {}），写入方可能仍指向旧表或旧地址",
+                        live,
+                        last_advance.elapsed().as_secs(),
+                        if live_max.is_empty() { since.as_str() } else { live_max.as_str() }
+                    );
+                    alert(opt, &mut w, "post_cutover_stalled", bak_table, text).await;
+                }
+                if !bak_growing && s.bak_rows > 0 {
+                    bak_growing = true;
+                    let text = format!("{} 在切换后仍有 {} 行新写入（最大时间 {}），仍有写入方写到旧表", bak_table, s.bak_rows, s.bak_max_time);
+                    alert(opt, &mut w, "post_cutover_bak_growing", bak_table, text).await;
+                }
+                w.samples.push(s);
+            }
+            Err(e) => warn!("切换后观察: 采样失败: {e}"),
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= opt.post_cutover_watch {
+            break;
+        }
+        tokio::time::sleep(opt.post_cutover_watch_interval.min(opt.post_cutover_watch - elapsed)).await;
+    }
+    if w.samples.is_empty() {
+        let text = "观察期内采样全部失败，无法确认新表在收到新行".to_string();
+        alert(opt, &mut w, "post_cutover_unknown", bak_table, text).await;
+    } else if !stalled && live_max.is_empty() {
+        let text = format!("观察期内新表 {} 没有收到时间晚于 {} 的行，写入方可能仍指向旧表或旧地址", live, since);
+        alert(opt, &mut w, "post_cutover_stalled", bak_table, text).await;
+    }
+    w.healthy = w.issues.is_empty();
+    w.finished_at = report::now_str();
+    if w.healthy {
+        info!("切换后健康: OK（{} 次采样，新表最大时间前进到 {}）", w.samples.len(), live_max);
+    } else {
+        error!("切换后健康: 有问题: {}", w.issues.join("；"));
+    }
+    report.lock().unwrap().post_cutover_watch = Some(w);
+}
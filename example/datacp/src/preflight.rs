@@ -0,0 +1,283 @@
+// ===================== 迁移前检查 =====================
+
+use log::{info, warn};
+use std::sync::{Arc, Mutex};
+
+use crate::report::{DiskCheck, RunReport, TimeIndexCheck};
+use crate::{ch_query_rows, cutover, json_u64, qualified, row_filter, segment, shard, src_query, time_expr, Opt};
+
+// 源表数据所在的本地表与查询其 part 的来源：源为分布式表时 cluster() 每个分片取一个副本，汇总全部分片
+pub async fn src_parts_source(opt: &Opt) -> anyhow::Result<(String, String, String)> {
+    let (src_db, src_table) = shard::resolve_local_table(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    let from = if src_db != opt.src_db || src_table != opt.src_table {
+        let (_, engine_full) = shard::table_engine(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+        let (cluster, _, _, _) = shard::parse_distributed_engine(&engine_full)?;
+        format!("cluster('{}', system.parts)", cluster)
+    } else {
+        "system.parts".to_string()
+    };
+    Ok((src_db, src_table, from))
+}
+
+// 目标端磁盘空间检查：按源表 system.parts 估算待迁移数据量（乘以压缩比修正系数），
+// 与目标表存储策略所用磁盘的剩余空间比较，预计使用率超过 --max-disk-usage-percent 时拒绝启动
+pub async fn check_disk_space(opt: &Opt, start_time: &str, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<()> {
+    if opt.skip_disk_check {
+        info!("已指定 --skip-disk-check，跳过目标端磁盘空间检查");
+        return Ok(());
+    }
+    // 源端估算：分区 min/max 时间与迁移窗口相交的活跃 part（无时间分区时 max_time 为 0，全部计入）
+    let (src_db, src_table, from) = src_parts_source(opt).await?;
+    let sql = format!(
+        "SELECT sum(bytes_on_disk) AS bytes FROM {} WHERE database = '{}' AND table = '{}' AND active \
+         AND (toUInt32(max_time) = 0 OR max_time >= {}) FORMAT JSONEachRow",
+        from, src_db, src_table, opt.time_lit(start_time)
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let src_bytes = json_u64(rows.first().and_then(|r| r.get("bytes")));
+    // 目标端：目标表（分布式表取本地表）所用存储策略的磁盘
+    let (dst_db, dst_table) = shard::resolve_local_table(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+    let sql = format!(
+        "SELECT sum(free_space) AS free, sum(total_space) AS total FROM system.disks WHERE name IN \
+         (SELECT arrayJoin(disks) FROM system.storage_policies WHERE policy_name = \
+         (SELECT storage_policy FROM system.tables WHERE database = '{}' AND name = '{}')) FORMAT JSONEachRow",
+        dst_db, dst_table
+    );
+    let rows = ch_query_rows(&opt.dst_dsn, &opt.dst_db, &sql).await?;
+    let free = json_u64(rows.first().and_then(|r| r.get("free")));
+    let total = json_u64(rows.first().and_then(|r| r.get("total")));
+    if total == 0 {
+        warn!("无法获取目标表存储策略的磁盘信息，跳过磁盘空间检查");
+        return Ok(());
+    }
+    // 目标为分布式表时数据分散到各分片，按分片数均摊
+    let shards = if dst_db != opt.dst_db || dst_table != opt.read_table() {
+        let (_, engine_full) = shard::table_engine(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+        let (cluster, _, _, _) = shard::parse_distributed_engine(&engine_full)?;
+        shard::cluster_shard_hosts(&opt.dst_dsn, &opt.dst_db, &cluster).await?.len().max(1) as u64
+    } else {
+        1
+    };
+    let estimated = (src_bytes as f64 * opt.disk_estimate_factor / shards as f64) as u64;
+    let used = total.saturating_sub(free);
+    let projected = (used + estimated) as f64 * 100.0 / total as f64;
+    let gb = |b: u64| b as f64 / 1024.0 / 1024.0 / 1024.0;
+    info!(
+        "磁盘空间检查: 源数据 {:.2} GB x 系数 {} / 分片 {} = 预计写入 {:.2} GB; 目标磁盘已用 {:.2} GB / 总计 {:.2} GB, 预计使用率 {:.1}% (上限 {}%)",
+        gb(src_bytes), opt.disk_estimate_factor, shards, gb(estimated), gb(used), gb(total), projected, opt.max_disk_usage_percent
+    );
+    let ok = projected <= opt.max_disk_usage_percent as f64;
+    report.lock().unwrap().disk_check = Some(DiskCheck {
+        src_bytes,
+        factor: opt.disk_estimate_factor,
+        shards,
+        estimated_bytes: estimated,
+        dst_free_bytes: free,
+        dst_total_bytes: total,
+        projected_usage_percent: projected,
+        max_usage_percent: opt.max_disk_usage_percent,
+        passed: ok,
+    });
+    if !ok {
+        anyhow::bail!(format!(
+            "目标端预计磁盘使用率 {:.1}% 超过 --max-disk-usage-percent {}%，拒绝启动（可用 --skip-disk-check 跳过）",
+            projected, opt.max_disk_usage_percent
+        ));
+    }
+    Ok(())
+}
+
+// 一端的身份：服务端 hostName()、URL 默认库 database()、currentUser()，以及表（分布式表取本地表）的 UUID
+struct Identity {
+    host: String,
+    db: String,
+    user: String,
+    table: String,
+    uuid: String, // Ordinary 库的表为全零 UUID，视为无
+}
+
+async fn identity(dsn: &str, db: &str, table: &str) -> anyhow::Result<Identity> {
+    let rows = ch_query_rows(dsn, db, "SELECT hostName() AS host, database() AS db, currentUser() AS user FORMAT JSONEachRow").await?;
+    let get = |k: &str| rows.first().and_then(|r| r.get(k)).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    let (host, cur_db, user) = (get("host"), get("db"), get("user"));
+    let (local_db, local_table) = shard::resolve_local_table(dsn, db, table).await?;
+    let sql = format!(
+        "SELECT toString(uuid) AS uuid FROM system.tables WHERE database = '{}' AND name = '{}' FORMAT JSONEachRow",
+        local_db, local_table
+    );
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    let uuid = rows.first().and_then(|r| r.get("uuid")).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    let uuid = if uuid.chars().all(|c| c == '0' || c == '-') { String::new() } else { uuid };
+    Ok(Identity { host, db: cur_db, user, table: format!("{}.{}", local_db, local_table), uuid })
+}
+
+impl Identity {
+    fn describe(&self) -> String {
+        format!(
+            "hostName()={} database()={} currentUser()={} 表 {} UUID={}",
+            self.host,
+            self.db,
+            self.user,
+            self.table,
+            if self.uuid.is_empty() { "-" } else { &self.uuid }
+        )
+    }
+}
+
+// 时间字段（或表达式引用的列）出现在源表排序键或分区键中时，分段查询 >= x AND < y 可按索引裁剪；
+// 否则每个分段都是全表扫描。未命中时实测一个分段的 count() 耗时并醒目告警，--require-indexed-time 时拒绝启动
+pub async fn check_time_index(opt: &Opt, first_segment: &str, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<()> {
+    let (db, table) = shard::resolve_local_table(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    let sql = format!(
+        "SELECT sorting_key, partition_key FROM system.tables WHERE database = '{}' AND name = '{}' FORMAT JSONEachRow",
+        db, table
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let key = |k: &str| rows.first().and_then(|r| r.get(k)).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    let (sorting_key, partition_key) = (key("sorting_key"), key("partition_key"));
+    let columns = time_expr::referenced_columns(opt);
+    let indexed = columns.iter().any(|c| key_mentions(&sorting_key, c) || key_mentions(&partition_key, c));
+    let mut check = TimeIndexCheck { indexed, sorting_key, partition_key, probe_segment: None, probe_seconds: None };
+    if indexed {
+        info!("时间字段 {} 命中源表排序键/分区键，分段查询可按索引裁剪", opt.time_field);
+        report.lock().unwrap().time_index = Some(check);
+        return Ok(());
+    }
+    let (start, end) = segment::bounds(first_segment);
+    let sql = format!(
+        "SELECT count() AS c FROM {} WHERE {} >= {} AND {} < {}{} FORMAT JSONEachRow",
+        qualified(&opt.src_db, &opt.src_table),
+        opt.time_field,
+        opt.time_lit(&start),
+        opt.time_field,
+        opt.time_lit(&end),
+        row_filter(opt)
+    );
+    let started = std::time::Instant::now();
+    let probe = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await;
+    let seconds = started.elapsed().as_secs_f64();
+    let cost = match &probe {
+        Ok(_) => format!("探测分段 {} 的 count() 耗时 {:.1}s，每个分段的读取都会扫描全表", first_segment, seconds),
+        Err(e) => format!("探测分段 {} 的 count() 失败（{:.1}s）: {}", first_segment, seconds, e),
+    };
+    check.probe_segment = Some(first_segment.to_string());
+    check.probe_seconds = probe.is_ok().then_some(seconds);
+    warn!(
+        "!!! 时间字段 {} 不在源表 {} 的排序键（{}）或分区键（{}）中，分段查询无法按索引裁剪；{} !!!",
+        opt.time_field,
+        qualified(&db, &table),
+        if check.sorting_key.is_empty() { "无" } else { &check.sorting_key },
+        if check.partition_key.is_empty() { "无" } else { &check.partition_key },
+        cost
+    );
+    warn!("可考虑: --where 追加命中索引的列条件缩小扫描范围；--page-key 按排序键分页读取；--replace-partitions 按分区整体替换");
+    report.lock().unwrap().time_index = Some(check);
+    if opt.require_indexed_time {
+        anyhow::bail!(format!("时间字段 {} 未命中源表排序键或分区键，已指定 --require-indexed-time，拒绝启动", opt.time_field));
+    }
+    Ok(())
+}
+
+// 键表达式中出现该列（作为完整标识符，如 toYYYYMM(ts)、(user_id, ts)）
+pub fn key_mentions(key: &str, column: &str) -> bool {
+    let ident = regex::Regex::new(r"`[^`]*`|[A-Za-z_][A-Za-z0-9_]*(?:\.[A-Za-z_][A-Za-z0-9_]*)*").unwrap();
+    let found = ident.find_iter(key).any(|m| m.as_str().trim_matches('`') == column);
+    found
+}
+
+// 切换会 RENAME 的 Replicated 表（源表 → _bak、目标表 → 源表名）：ZooKeeper 路径由 {table} 宏生成、含表名时，
+// RENAME 后路径仍是旧表名，之后按旧名建表会与之冲突，运维也难以对应。预检时醒目告警并建议 --cutover-strategy recreate；
+// 指定 recreate 时目标表必须是路径含表名的 Replicated 表
+pub async fn check_replicated_rename(opt: &Opt, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<()> {
+    cutover::parse_cutover_strategy(&opt.cutover_strategy)?;
+    if opt.no_cutover || opt.archive || src_query::enabled(opt) {
+        return Ok(());
+    }
+    let recreate = opt.cutover_strategy == "recreate";
+    let mut found = Vec::new();
+    for (side, dsn, db, table, renamed_to) in [
+        ("源表", &opt.src_dsn, &opt.src_db, &opt.src_table, format!("{}_bak", opt.src_table)),
+        ("目标表", &opt.dst_dsn, &opt.dst_db, &opt.read_table().to_string(), opt.src_table.clone()),
+    ] {
+        let Some(path) = cutover::zookeeper_path(dsn, db, table).await? else { continue };
+        if !cutover::path_embeds_table(&path, table) {
+            continue;
+        }
+        // recreate 只处理目标表，目标表的告警此时不再需要
+        if recreate && side == "目标表" {
+            continue;
+        }
+        let msg = format!(
+            "{} {} 是 Replicated 表，ZooKeeper 路径 {} 含表名；切换时 RENAME 为 {} 后路径不会随之改变",
+            side,
+            qualified(db, table),
+            path,
+            renamed_to
+        );
+        warn!("!!! {} !!!", msg);
+        found.push(msg);
+    }
+    if recreate {
+        let path = cutover::zookeeper_path(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+        if !path.as_deref().is_some_and(|p| cutover::path_embeds_table(p, opt.read_table())) {
+            anyhow::bail!(format!(
+                "--cutover-strategy recreate 要求目标表 {} 是 ZooKeeper 路径含表名的 Replicated 表",
+                qualified(&opt.dst_db, opt.read_table())
+            ));
+        }
+        info!("切换方式 recreate：按新表名新建目标表并挂载分区，ZooKeeper 路径随表名更新");
+    } else if found.iter().any(|m| m.starts_with("目标表")) {
+        warn!("建议使用 --cutover-strategy recreate：按切换后的表名新建表（路径随之更新）、挂载分区后删除中间表");
+    }
+    report.lock().unwrap().replicated_rename = found;
+    Ok(())
+}
+
+// 源端与目标端身份检查：两端解析为同一张表（UUID 相同；无 UUID 时同一主机上的同名表），
+// 或切换时会把目标表 rename 到自身，拒绝运行，除非指定 --allow-same-server
+pub async fn check_identity(opt: &Opt) -> anyhow::Result<()> {
+    let src = identity(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    let dst = identity(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+    info!("源端: {}", src.describe());
+    info!("目标端: {}", dst.describe());
+    let mut problems = Vec::new();
+    let same_table = if !src.uuid.is_empty() && !dst.uuid.is_empty() {
+        src.uuid == dst.uuid
+    } else {
+        !src.host.is_empty() && src.host == dst.host && src.table == dst.table
+    };
+    if same_table {
+        let mut matched: Vec<&str> = Vec::new();
+        for (name, a, b) in [
+            ("hostName()", &src.host, &dst.host),
+            ("database()", &src.db, &dst.db),
+            ("currentUser()", &src.user, &dst.user),
+            ("表名", &src.table, &dst.table),
+            ("UUID", &src.uuid, &dst.uuid),
+        ] {
+            if !a.is_empty() && a == b {
+                matched.push(name);
+            }
+        }
+        problems.push(format!(
+            "源端与目标端是同一张表（相同的标识: {}）；源端 {}；目标端 {}",
+            matched.join(", "),
+            src.describe(),
+            dst.describe()
+        ));
+    }
+    let cutover_target = qualified(opt.cutover_db(), &opt.src_table);
+    if !opt.no_cutover && !opt.archive && qualified(&opt.dst_db, opt.read_table()) == cutover_target {
+        problems.push(format!("切换时会执行 RENAME TABLE {} TO {}，把表 rename 到自身", cutover_target, cutover_target));
+    }
+    if problems.is_empty() {
+        return Ok(());
+    }
+    if opt.allow_same_server {
+        for p in &problems {
+            warn!("已指定 --allow-same-server，继续运行: {}", p);
+        }
+        return Ok(());
+    }
+    anyhow::bail!(format!("{}。请检查 --src-dsn/--dst-dsn 与库表参数；确为同一服务器上的合法拷贝时可指定 --allow-same-server", problems.join("；")))
+}
@@ -0,0 +1,99 @@
+// ===================== 低优先级运行（--query-priority / --workload / --settings-profile / --os-nice） =====================
+// 迁移查询须始终让位于在线查询：--query-priority N（ClickHouse 的 priority，数值越大优先级越低）、--workload 与
+// --settings-profile 以 settings 形式附加到两端 DSN，所有请求都会带上（priority / workload / profile）。
+// 预检时确认两端存在该设置档（system.settings_profiles）与 workload（system.workloads），拼写错误时拒绝启动，
+// 避免静默按默认优先级运行。--os-nice N 在首次进入摘要计算密集的阶段（回填、verify）时把本进程各线程的 nice 调为 N；
+// 非特权进程无法再调回，之后一直保持。生效的设置写入报告与运行清单
+
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::report::QueryPriority;
+use crate::{ch_query_rows, dsn_with_settings, json_u64, sql_log, Opt};
+
+static NICED: AtomicBool = AtomicBool::new(false);
+
+// 附加到 DSN 的 settings
+fn settings(opt: &Opt) -> Vec<(String, String)> {
+    let mut s = Vec::new();
+    if opt.query_priority > 0 {
+        s.push(("priority".to_string(), opt.query_priority.to_string()));
+    }
+    if !opt.workload.is_empty() {
+        s.push(("workload".to_string(), opt.workload.clone()));
+    }
+    if !opt.settings_profile.is_empty() {
+        s.push(("profile".to_string(), opt.settings_profile.clone()));
+    }
+    s
+}
+
+pub fn apply(opt: &mut Opt) -> anyhow::Result<()> {
+    if opt.os_nice > 19 {
+        anyhow::bail!(format!("--os-nice 取值为 0~19: {}", opt.os_nice));
+    }
+    for (k, v) in settings(opt) {
+        if v.contains(['&', '=', '#', ' ']) {
+            anyhow::bail!(format!("--{} 包含不允许的字符: {}", if k == "profile" { "settings-profile" } else { k.as_str() }, v));
+        }
+    }
+    let s = settings(opt);
+    opt.src_dsn = dsn_with_settings(&opt.src_dsn, &s);
+    opt.dst_dsn = dsn_with_settings(&opt.dst_dsn, &s);
+    Ok(())
+}
+
+// 两端须存在指定的设置档与 workload
+pub async fn check(opt: &Opt) -> anyhow::Result<()> {
+    for dsn in [&opt.src_dsn, &opt.dst_dsn] {
+        for (name, table, flag) in [(&opt.settings_profile, "system.settings_profiles", "--settings-profile"), (&opt.workload, "system.workloads", "--workload")] {
+            if name.is_empty() {
+                continue;
+            }
+            let sql = format!("SELECT count() AS n FROM {} WHERE name = '{}' FORMAT JSONEachRow", table, name.replace('\'', "\\'"));
+            let rows = ch_query_rows(dsn, "system", &sql).await.map_err(|e| {
+                anyhow::anyhow!(format!("{} {}: 无法查询 {}（需要 SHOW ACCESS 权限或服务端版本支持）: {}", flag, name, table, e))
+            })?;
+            if json_u64(rows.first().and_then(|r| r.get("n"))) == 0 {
+                anyhow::bail!(format!("{} {} 在 {} 不存在（{}），请检查拼写", flag, name, sql_log::endpoint(dsn), table));
+            }
+        }
+    }
+    if let Some(p) = report(opt) {
+        info!(
+            "查询优先级: priority={}，workload={}，settings profile={}，os nice={}",
+            p.query_priority,
+            p.workload.as_deref().unwrap_or("-"),
+            p.settings_profile.as_deref().unwrap_or("-"),
+            p.os_nice
+        );
+    }
+    Ok(())
+}
+
+// 回填 / verify 开始时降低本进程各线程的 CPU 优先级（只执行一次）
+pub fn nice(opt: &Opt) {
+    if opt.os_nice == 0 || NICED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    // Linux 的 nice 按线程生效：对 /proc/self/task 下的全部线程调整，之后创建的线程继承
+    let tids: Vec<String> = std::fs::read_dir("/proc/self/task")
+        .map(|d| d.filter_map(|e| e.ok()).map(|e| e.file_name().to_string_lossy().to_string()).collect())
+        .unwrap_or_else(|_| vec![std::process::id().to_string()]);
+    let res = std::process::Command::new("renice").arg("-n").arg(opt.os_nice.to_string()).arg("-p").args(&tids).output();
+    match res {
+        Ok(out) if out.status.success() => info!("已将本进程 {} 个线程的 nice 调为 {}（--os-nice）", tids.len(), opt.os_nice),
+        Ok(out) => warn!("--os-nice {} 未生效: {}", opt.os_nice, String::from_utf8_lossy(&out.stderr).trim()),
+        Err(e) => warn!("--os-nice {} 未生效，无法执行 renice: {e}", opt.os_nice),
+    }
+}
+
+pub fn report(opt: &Opt) -> Option<QueryPriority> {
+    let some = |s: &String| (!s.is_empty()).then(|| s.clone());
+    (opt.query_priority > 0 || !opt.workload.is_empty() || !opt.settings_profile.is_empty() || opt.os_nice > 0).then(|| QueryPriority {
+        query_priority: opt.query_priority,
+        workload: some(&opt.workload),
+        settings_profile: some(&opt.settings_profile),
+        os_nice: opt.os_nice,
+    })
+}
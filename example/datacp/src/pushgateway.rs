@@ -0,0 +1,90 @@
+// ===================== Prometheus pushgateway 推送（--pushgateway-url） =====================
+// 批处理环境无法抓取短时运行的进程时，按 --push-interval 把 /metrics 的同一组指标（status::metrics）PUT 到 pushgateway，
+// job 为 datacp-{run_id}-{源表}-{目标表}（非 [A-Za-z0-9_.-] 的字符替换为 _）。
+// 进程结束前再推送一次，附加 datacp_run_completed{exit_code="N"} 1；迁移失败、main 提前返回错误时同样推送。
+// 推送失败只记日志并重试，不影响迁移
+
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::{status, Opt};
+
+// 单次推送的重试次数
+const ATTEMPTS: u32 = 3;
+
+static URL: OnceLock<String> = OnceLock::new();
+static FINISHED: AtomicBool = AtomicBool::new(false);
+
+fn job(opt: &Opt) -> String {
+    format!("datacp-{}-{}.{}-{}.{}", status::run_id(), opt.src_db, opt.src_table, opt.dst_db, opt.dst_table)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "_.-".contains(c) { c } else { '_' })
+        .collect()
+}
+
+async fn push(url: &str, body: String) {
+    let client = reqwest::Client::new();
+    for attempt in 1..=ATTEMPTS {
+        let res = client.put(url).timeout(Duration::from_secs(10)).body(body.clone()).send().await;
+        match res {
+            Ok(r) if r.status().is_success() => return,
+            Ok(r) => warn!("pushgateway 推送返回 {}（第 {}/{} 次）", r.status(), attempt, ATTEMPTS),
+            Err(e) => warn!("pushgateway 推送失败（第 {}/{} 次）: {}", attempt, ATTEMPTS, e),
+        }
+        if attempt < ATTEMPTS {
+            tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+        }
+    }
+}
+
+// main 提前返回错误时由 Drop 做最后一次推送（退出码按 1 计）；正常结束前调用 finish
+pub struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        finish(1);
+    }
+}
+
+// 须在 status::init 之后调用；未指定 --pushgateway-url 时返回 None
+pub fn start(opt: &Opt) -> Option<Guard> {
+    if opt.pushgateway_url.is_empty() {
+        return None;
+    }
+    let url = format!("{}/metrics/job/{}", opt.pushgateway_url.trim_end_matches('/'), job(opt));
+    info!("pushgateway: 每 {:?} 推送指标到 {}", opt.push_interval, url);
+    let _ = URL.set(url.clone());
+    let interval = opt.push_interval.max(Duration::from_secs(1));
+    tokio::spawn(async move {
+        while !FINISHED.load(Ordering::SeqCst) {
+            push(&url, status::metrics()).await;
+            tokio::time::sleep(interval).await;
+        }
+    });
+    Some(Guard)
+}
+
+// 最后一次推送（附带退出码），在 process::exit 之前调用；只执行一次。
+// 在独立线程的运行时中同步完成，process::exit 与 Drop 中都可使用
+pub fn finish(exit_code: i32) {
+    let Some(url) = URL.get().cloned() else { return };
+    if FINISHED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let body = format!(
+        "{}# HELP datacp_run_completed Run finished; the exit_code label carries the process exit status.\n\
+         # TYPE datacp_run_completed gauge\ndatacp_run_completed{{exit_code=\"{}\"}} 1\n",
+        status::metrics(),
+        exit_code
+    );
+    let res = std::thread::spawn(move || match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(rt) => rt.block_on(push(&url, body)),
+        Err(e) => warn!("pushgateway 最后一次推送失败: {}", e),
+    })
+    .join();
+    if res.is_err() {
+        warn!("pushgateway 最后一次推送失败");
+    }
+}
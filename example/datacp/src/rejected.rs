@@ -0,0 +1,65 @@
+// ===================== 目标端拒绝的行（--on-rejected-row） =====================
+// 个别行被目标端以数据错误拒绝（CANNOT_PARSE、类型溢出，如 UInt8 列收到 300）时，默认整批失败。
+// --on-rejected-row dead-letter 时对失败批次二分重试：每半各写一次，仍因数据错误失败的一半继续拆分，
+// 直到隔离出单行，这些行连同服务端错误写入 --dead-letter-file，其余行照常写入；分段仍可标记完成，
+// 拒绝行数按分段写入断点续传文件与报告。--bisect-max-inserts 限制每个失败批次的拆分写入次数，整批都坏时不会无限拆分
+
+use log::warn;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::report::RejectedRows;
+use crate::Opt;
+
+// 拒绝行的分段在断点续传文件中的前缀，行格式 "rejected-rows:分段\t行数"
+pub const REJECTED_PREFIX: &str = "rejected-rows:";
+
+// 每个分段在报告中保留的错误样例数
+const SAMPLES: usize = 5;
+
+// 错误样例保留的长度
+const ERROR_CHARS: usize = 300;
+
+pub struct Rejected {
+    pub bisect: bool,
+    pub max_inserts: usize,
+    found: Mutex<HashMap<String, RejectedRows>>,
+}
+
+impl Rejected {
+    pub fn new(opt: &Opt) -> anyhow::Result<Self> {
+        let bisect = match opt.on_rejected_row.as_str() {
+            "fail" => false,
+            "dead-letter" if opt.dead_letter_file.is_empty() => anyhow::bail!("--on-rejected-row dead-letter 需要指定 --dead-letter-file"),
+            "dead-letter" => true,
+            other => anyhow::bail!(format!("不支持的 --on-rejected-row: {}（fail|dead-letter）", other)),
+        };
+        Ok(Rejected { bisect, max_inserts: opt.bisect_max_inserts, found: Mutex::new(HashMap::new()) })
+    }
+
+    // 分段（重新）开始处理时清空上一次的计数
+    pub fn reset(&self, seg: &str) {
+        self.found.lock().unwrap().remove(seg);
+    }
+
+    pub fn note(&self, seg: &str, error: &str) {
+        warn!("segment {seg} 目标端拒绝 1 行，已写入死信文件: {}", error);
+        let mut found = self.found.lock().unwrap();
+        let e = found.entry(seg.to_string()).or_insert_with(|| RejectedRows { segment: seg.to_string(), ..Default::default() });
+        e.rows += 1;
+        if e.errors.len() < SAMPLES {
+            e.errors.push(error.chars().take(ERROR_CHARS).collect());
+        }
+    }
+
+    // 分段中写入死信文件、未迁移的行数
+    pub fn dead_lettered(&self, seg: &str) -> u64 {
+        self.found.lock().unwrap().get(seg).map(|e| e.rows).unwrap_or(0)
+    }
+
+    pub fn segments(&self) -> Vec<RejectedRows> {
+        let mut v: Vec<RejectedRows> = self.found.lock().unwrap().values().cloned().collect();
+        v.sort_by(|a, b| a.segment.cmp(&b.segment));
+        v
+    }
+}
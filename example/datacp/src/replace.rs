@@ -0,0 +1,221 @@
+// ===================== 按分区整体替换（--replace-partitions） =====================
+// 不做差集：对迁移时间范围涉及的每个目标分区，把源端该分区的全部数据写入临时表，
+// 核对行数与校验和一致后 ALTER TABLE dst REPLACE PARTITION ... FROM 临时表原子替换，
+// 目标端多余的旧行随之消失；范围内只在目标端存在的分区（源端已无数据）直接 DROP PARTITION。任一步骤失败时原分区保持不变；已替换的分区以 replaced:<分区值> 记录断点
+
+use log::{error, info, warn};
+use std::collections::{BTreeSet, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::report::{PartitionReplace, RunReport};
+use crate::{
+    ch_execute_timeout, ch_query_rows, ch_query_rows_with_client, endpoint, insert_rows_batched, json_u64, mirror,
+    save_done_segment, server_copy, table_ref, time_zone, Opt, RunCtx,
+};
+
+// 已替换分区在断点续传文件中的前缀
+pub const REPLACED_PREFIX: &str = "replaced:";
+
+// 分区键中不是列的标识符（INTERVAL 1 DAY 等）
+const KEYWORDS: [&str; 9] = ["INTERVAL", "SECOND", "MINUTE", "HOUR", "DAY", "WEEK", "MONTH", "QUARTER", "YEAR"];
+
+// 分区键表达式引用的列：去掉字符串字面量、函数名、数字与关键字后剩下的标识符
+fn partition_columns(partition_key: &str) -> HashSet<String> {
+    let no_strings = regex::Regex::new(r"'(?:[^'\\]|\\.)*'").unwrap().replace_all(partition_key, "");
+    let ident = regex::Regex::new(r"`([^`]+)`|([A-Za-z_][A-Za-z0-9_]*)(\s*\()?").unwrap();
+    ident
+        .captures_iter(&no_strings)
+        .filter(|c| c.get(3).is_none())
+        .filter_map(|c| c.get(1).or_else(|| c.get(2)).map(|m| m.as_str().to_string()))
+        .filter(|s| !KEYWORDS.contains(&s.to_ascii_uppercase().as_str()))
+        .collect()
+}
+
+// 目标表须为非分布式的 MergeTree 系列，且分区键只由时间字段计算，才能按时间范围确定要替换的分区
+async fn check_compatible(opt: &Opt) -> anyhow::Result<std::collections::HashMap<String, String>> {
+    if opt.copy_mode != "http" {
+        anyhow::bail!(format!("--replace-partitions 只支持 --copy-mode http，当前为 {}", opt.copy_mode));
+    }
+    if !opt.filter.trim().is_empty() {
+        anyhow::bail!("--replace-partitions 整分区替换，不支持 --where");
+    }
+    if opt.dst_write_local || opt.calibrate || opt.mirror || !opt.page_key.is_empty() {
+        anyhow::bail!("--replace-partitions 不能与 --dst-write-local / --calibrate / --mirror / --page-key 同时使用");
+    }
+    if !opt.dst_read_table.is_empty() && opt.dst_read_table != opt.dst_table {
+        anyhow::bail!("--replace-partitions 要求写入表与读取表相同");
+    }
+    let keys = server_copy::table_keys(&opt.dst_dsn, &opt.dst_db, &opt.dst_table).await?;
+    let engine = keys.get("engine").map(|s| s.as_str()).unwrap_or("");
+    if !engine.contains("MergeTree") {
+        anyhow::bail!(format!("--replace-partitions 仅支持 MergeTree 系列目标表，当前引擎 {}", engine));
+    }
+    let partition_key = keys.get("partition_key").map(|s| s.as_str()).unwrap_or("");
+    let cols = partition_columns(partition_key);
+    if partition_key.is_empty() || cols.len() != 1 || !cols.contains(&opt.time_field) {
+        anyhow::bail!(format!(
+            "--replace-partitions 要求目标表分区键只由时间字段 {} 计算，当前分区键 [{}]",
+            opt.time_field, partition_key
+        ));
+    }
+    Ok(keys)
+}
+
+// 替换 [min_time, max_time] 涉及的全部目标分区，完成后由调用方继续增量循环
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    opt: &Opt,
+    min_time: &str,
+    max_time: &str,
+    col_names: &[String],
+    done_segments_file: &str,
+    done: &HashSet<String>,
+    client: &Arc<endpoint::Clients>,
+    ctx: &RunCtx,
+    report: &Arc<Mutex<RunReport>>,
+) -> anyhow::Result<()> {
+    let keys = check_compatible(opt).await?;
+    let pk = keys.get("partition_key").cloned().unwrap_or_default();
+    // 源端与目标端在时间范围内的分区取并集：只在目标端存在的旧分区同样替换（清空）
+    let mut partitions = BTreeSet::new();
+    for (dsn, db, table) in [
+        (&opt.src_dsn, &opt.src_db, table_ref(&opt.src_db, &opt.src_table, ctx.select_final)),
+        (&opt.dst_dsn, &opt.dst_db, format!("{}.{}", opt.dst_db, opt.dst_table)),
+    ] {
+        let sql = format!(
+            "SELECT DISTINCT {} AS p FROM {} WHERE {} >= {} AND {} <= {} ORDER BY p FORMAT JSONEachRow",
+            pk, table, opt.time_field, opt.time_lit(min_time), opt.time_field, opt.time_lit(max_time)
+        );
+        for r in ch_query_rows(dsn, db, &sql).await? {
+            partitions.insert(mirror::sql_literal(r.get("p").unwrap_or(&serde_json::Value::Null))?);
+        }
+    }
+    info!("replace-partitions: 分区键 {}，共 {} 个分区", pk, partitions.len());
+
+    // 临时表与目标表结构、分区键、排序键一致；复制表改用普通 MergeTree，避免与目标表共用 ZooKeeper 路径
+    let staging = format!("{}.{}__datacp_replace", opt.dst_db, opt.dst_table);
+    let engine = keys.get("engine").map(|s| s.as_str()).unwrap_or("");
+    let engine_sql = if engine.starts_with("Replicated") {
+        let sorting = keys.get("sorting_key").filter(|s| !s.is_empty()).cloned().unwrap_or_else(|| "tuple()".to_string());
+        let primary = keys.get("primary_key").filter(|s| !s.is_empty()).map(|p| format!(" PRIMARY KEY ({})", p)).unwrap_or_default();
+        format!(" ENGINE = MergeTree PARTITION BY {} ORDER BY ({}){}", pk, sorting, primary)
+    } else {
+        String::new()
+    };
+    ch_execute_timeout(&opt.dst_dsn, &opt.dst_db, &format!("DROP TABLE IF EXISTS {}", staging), opt.ddl_timeout).await?;
+    let create = format!("CREATE TABLE {} AS {}.{}{}", staging, opt.dst_db, opt.dst_table, engine_sql);
+    info!("replace-partitions: {}", create);
+    ch_execute_timeout(&opt.dst_dsn, &opt.dst_db, &create, opt.ddl_timeout).await?;
+
+    for p in partitions {
+        let key = format!("{}{}", REPLACED_PREFIX, p);
+        if done.contains(&key) {
+            continue;
+        }
+        if ctx.mutation_watch.aborted() || ctx.deadline.reached() || ctx.breaker.tripped() {
+            warn!("partition {p} skipped: 迁移已中止、超过 --max-duration 或已熔断");
+            break;
+        }
+        let (status, src_rows, dst_rows_before) = match replace_one(opt, &pk, &p, &staging, col_names, client, ctx).await {
+            Ok((src_rows, dst_rows_before)) => {
+                if let Err(e) = save_done_segment(done_segments_file, &key) {
+                    error!("save_done_segment failed: {e}");
+                }
+                ctx.breaker.succeeded();
+                ("replaced".to_string(), src_rows, dst_rows_before)
+            }
+            Err(e) => {
+                error!("partition {p} replace failed, 原分区保持不变: {e}");
+                ctx.segment_failed(&format!("partition:{}", p), &e.to_string());
+                (format!("failed: {}", e), 0, 0)
+            }
+        };
+        report.lock().unwrap().partitions_replaced.push(PartitionReplace { partition: p, src_rows, dst_rows_before, status });
+    }
+    ch_execute_timeout(&opt.dst_dsn, &opt.dst_db, &format!("DROP TABLE IF EXISTS {}", staging), opt.ddl_timeout).await?;
+    Ok(())
+}
+
+// 行数与校验和（各列 cityHash64 之和），exprs 为各列的读取表达式（含 --column-default 覆盖）
+pub async fn checksum(dsn: &str, db: &str, table: &str, where_sql: &str, exprs: &[String]) -> anyhow::Result<(u64, u64)> {
+    let sql = format!(
+        "SELECT count() AS c, sum(cityHash64({})) AS h FROM {} WHERE {} FORMAT JSONEachRow",
+        exprs.join(","),
+        table,
+        where_sql
+    );
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    let r = rows.first();
+    Ok((json_u64(r.and_then(|r| r.get("c"))), json_u64(r.and_then(|r| r.get("h")))))
+}
+
+// 单个分区：清空临时表 → 按小时写入源端数据 → 核对 → REPLACE PARTITION → 清空临时表；源端无数据时 DROP PARTITION
+async fn replace_one(
+    opt: &Opt,
+    pk: &str,
+    p: &str,
+    staging: &str,
+    col_names: &[String],
+    client: &Arc<endpoint::Clients>,
+    ctx: &RunCtx,
+) -> anyhow::Result<(u64, u64)> {
+    ch_execute_timeout(&opt.dst_dsn, &opt.dst_db, &format!("TRUNCATE TABLE {}", staging), opt.ddl_timeout).await?;
+    let src = table_ref(&opt.src_db, &opt.src_table, ctx.select_final);
+    let in_partition = format!("{} = {}", pk, p);
+    // 列声明了时区时按 UTC 小时读取
+    let utc = time_zone::utc_expr(opt.time_zone.as_ref(), &opt.time_field);
+    let sql = format!(
+        "SELECT count() AS c, toStartOfHour(min({})) AS lo, max({}) AS hi FROM {} WHERE {} FORMAT JSONEachRow",
+        utc, opt.time_field, src, in_partition
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let dst = format!("{}.{}", opt.dst_db, opt.dst_table);
+    let exprs = ctx.binary.value_exprs(col_names);
+    if json_u64(rows.first().and_then(|r| r.get("c"))) == 0 {
+        // 源端该分区已无数据：目标分区整体删除
+        let (dst_rows_before, _) = checksum(&opt.dst_dsn, &opt.dst_db, &dst, &in_partition, &exprs).await?;
+        let sql = format!("ALTER TABLE {} DROP PARTITION {}", dst, p);
+        info!("partition {p} 源端无数据, drop SQL: {sql}");
+        ch_execute_timeout(&opt.dst_dsn, &opt.dst_db, &sql, opt.ddl_timeout).await?;
+        return Ok((0, dst_rows_before));
+    }
+    // DateTime64 带小数秒，只取前 19 位；hi 按列时区输出，换算为 UTC
+    let bound = |k: &str| rows.first().and_then(|r| r.get(k)).and_then(|v| v.as_str()).map(|s| s.get(..19).unwrap_or(s)).unwrap_or("").to_string();
+    let (lo, hi) = (bound("lo"), time_zone::from_column(opt.time_zone.as_ref(), &bound("hi"), true));
+    let mut t = chrono::NaiveDateTime::parse_from_str(&lo, "%Y-%m-%d %H:%M:%S")
+        .map_err(|_| anyhow::anyhow!(format!("分区 {} 的时间范围无法解析: {} ~ {}", p, lo, hi)))?;
+    let hi = chrono::NaiveDateTime::parse_from_str(&hi, "%Y-%m-%d %H:%M:%S").unwrap_or(t);
+    // 按小时读取，单次读取量与分段拷贝相同
+    while t <= hi {
+        let (from, to) = (t.format("%Y-%m-%d %H:%M:%S").to_string(), (t + chrono::Duration::hours(1)).format("%Y-%m-%d %H:%M:%S").to_string());
+        let q = format!(
+            "SELECT {} FROM {} WHERE {} AND {} >= {} AND {} < {} FORMAT JSONEachRow",
+            ctx.binary.select_list(col_names), src, in_partition, opt.time_field, opt.time_lit(&from), opt.time_field, opt.time_lit(&to)
+        );
+        let (rows, _) = ch_query_rows_with_client(&opt.src_dsn, &opt.src_db, &q, client.clone(), true).await?;
+        if let Some(e) = insert_rows_batched(ctx, p, &opt.dst_dsn, &opt.dst_db, staging, &rows, client.clone()).await.1.into_iter().next() {
+            return Err(e);
+        }
+        ctx.rows_read.fetch_add(rows.len() as u64, std::sync::atomic::Ordering::Relaxed);
+        t += chrono::Duration::hours(1);
+    }
+    let (src_rows, src_sum) = checksum(&opt.src_dsn, &opt.src_db, &src, &in_partition, &exprs).await?;
+    let (stage_rows, stage_sum) = checksum(&opt.dst_dsn, &opt.dst_db, staging, &in_partition, &exprs).await?;
+    if (src_rows, src_sum) != (stage_rows, stage_sum) {
+        anyhow::bail!(format!(
+            "临时表核对不一致: 源 {} 行 校验和 {}，临时表 {} 行 校验和 {}",
+            src_rows, src_sum, stage_rows, stage_sum
+        ));
+    }
+    let (dst_rows_before, _) = checksum(&opt.dst_dsn, &opt.dst_db, &dst, &in_partition, &exprs).await?;
+    let sql = format!("ALTER TABLE {} REPLACE PARTITION {} FROM {}", dst, p, staging);
+    info!("partition {p} replace SQL: {sql}");
+    ch_execute_timeout(&opt.dst_dsn, &opt.dst_db, &sql, opt.ddl_timeout).await?;
+    ctx.rows_written.fetch_add(src_rows, std::sync::atomic::Ordering::Relaxed);
+    info!("partition {p} replaced: src_rows={}, dst_rows_before={}", src_rows, dst_rows_before);
+    // 分区已替换，清空临时表失败不影响结果（下个分区开始前会再次清空）
+    if let Err(e) = ch_execute_timeout(&opt.dst_dsn, &opt.dst_db, &format!("TRUNCATE TABLE {}", staging), opt.ddl_timeout).await {
+        warn!("partition {p} 已替换，清空临时表 {} 失败: {e}", staging);
+    }
+    Ok((src_rows, dst_rows_before))
+}
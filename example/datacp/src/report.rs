@@ -0,0 +1,816 @@
+// ===================== 运行报告 =====================
+// 记录一次迁移运行的关键决策与结果，结束时写入 JSON 文件，便于审计
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use crate::{events, Opt};
+
+// 单个副本的复制延迟
+#[derive(Serialize, Debug, Clone)]
+pub struct ReplicaLag {
+    pub host: String,
+    pub absolute_delay: u64, // 秒
+    pub queue_size: u64,
+}
+
+// 一次切换前副本延迟检查
+#[derive(Serialize, Debug, Clone)]
+pub struct ReplicaLagCheck {
+    pub time: String,
+    pub max_delay: u64,
+    pub replicas: Vec<ReplicaLag>,
+    pub decision: String, // go / wait / no-go
+}
+
+// 增量阶段每轮的复制延迟
+#[derive(Serialize, Debug, Clone)]
+pub struct LagSample {
+    pub time: String,
+    pub lag_seconds: u64,
+}
+
+// 一端 ClickHouse 与本机的时钟偏差（服务器时间减去请求往返中点的本机时间，源端/目标端快为正）
+#[derive(Serialize, Debug, Clone)]
+pub struct ClockSkew {
+    pub time: String,
+    pub side: String,
+    pub endpoint: String,
+    pub skew_ms: i64,
+    pub rtt_ms: u64,
+}
+
+// --pause-dst-merges：目标表合并的暂停与恢复时间，恢复失败时 restarted_at 为空
+#[derive(Serialize, Debug, Clone)]
+pub struct MergePause {
+    pub table: String,
+    pub stopped_at: String,
+    pub restarted_at: Option<String>,
+}
+
+// attach-partition 模式下单个分区的挂载结果
+#[derive(Serialize, Debug, Clone)]
+pub struct PartitionAttach {
+    pub partition_id: String,
+    pub src_rows: u64,
+    pub dst_rows: u64,
+    pub status: String, // attached / skipped / failed / count-mismatch
+}
+
+// 目标端磁盘空间检查所用数据
+#[derive(Serialize, Debug, Clone)]
+pub struct DiskCheck {
+    pub src_bytes: u64,
+    pub factor: f64,
+    pub shards: u64,
+    pub estimated_bytes: u64,
+    pub dst_free_bytes: u64,
+    pub dst_total_bytes: u64,
+    pub projected_usage_percent: f64,
+    pub max_usage_percent: u32,
+    pub passed: bool,
+}
+
+// 时间字段是否命中源表排序键/分区键，以及未命中时一个探测分段的耗时
+#[derive(Serialize, Debug, Clone)]
+pub struct TimeIndexCheck {
+    pub indexed: bool,
+    pub sorting_key: String,
+    pub partition_key: String,
+    pub probe_segment: Option<String>,
+    pub probe_seconds: Option<f64>,
+}
+
+// 被忽略但参与源表行身份的字段（排序键 / 主键 / 版本列）
+#[derive(Serialize, Debug, Clone)]
+pub struct IdentityRisk {
+    pub column: String,
+    pub flag: String, // --ignore-field / --ignore-compare-field
+    pub roles: Vec<String>,
+    pub acknowledged: bool, // 指定了 --acknowledge-identity-risk
+}
+
+// 一端的分层健康探测结果（/ping 与 SELECT 1）
+#[derive(Serialize, Debug, Clone)]
+pub struct EndpointHealth {
+    pub side: String,
+    pub endpoint: String,
+    pub healthy: bool,
+    pub layer: Option<String>, // 失败所在层: unreachable / backend / auth / database / query
+    pub detail: String,
+    pub trigger: String, // startup / connection-errors
+    pub checked_at: String,
+    pub ping_ms: Option<u64>,
+    pub query_ms: Option<u64>,
+}
+
+// 表结构中的一个字段（DESCRIBE 的 name 与 type）
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ColumnType {
+    pub name: String,
+    pub r#type: String,
+}
+
+// 续传或切换前实时表结构与断点续传元数据记录的指纹不一致
+#[derive(Serialize, Debug, Clone)]
+pub struct SchemaMismatch {
+    pub stage: String, // resume / cutover-src / cutover-dst
+    pub recorded_fingerprint: String,
+    pub observed_fingerprint: String,
+    pub recorded_src: Vec<ColumnType>,
+    pub recorded_dst: Vec<ColumnType>,
+    pub src_columns: Vec<ColumnType>, // 本次观察到的两端共有字段
+    pub dst_columns: Vec<ColumnType>,
+    pub accepted: bool, // --accept-schema-change 重新记录后继续
+}
+
+// 迁移期间观察到的源表 mutation
+#[derive(Serialize, Debug, Clone)]
+pub struct MutationSeen {
+    pub mutation_id: String,
+    pub command: String,
+    pub create_time: String,
+    pub action: String, // warn / pause / abort
+}
+
+// 归档模式下单个分段的校验与删除结果
+#[derive(Serialize, Debug, Clone)]
+pub struct ArchiveSegment {
+    pub segment: String,
+    pub src_rows: u64,
+    pub dst_rows: u64,
+    pub status: String, // verified / verify-failed / deleted / delete-failed
+    pub mutation_ids: Vec<String>,
+}
+
+// --replace-partitions 单个分区的替换结果
+#[derive(Serialize, Debug, Clone)]
+pub struct PartitionReplace {
+    pub partition: String, // 分区值（SQL 字面量）
+    pub src_rows: u64,
+    pub dst_rows_before: u64, // 替换前目标分区行数
+    pub status: String,       // replaced / failed: ...
+}
+
+// 一次 OPTIMIZE FINAL
+#[derive(Serialize, Debug, Clone)]
+pub struct OptimizeRun {
+    pub target: String,
+    pub partition_id: Option<String>,
+    pub seconds: f64,
+    pub status: String, // ok / failed: ...
+}
+
+// 热备模式一次重扫校验
+#[derive(Serialize, Debug, Clone)]
+pub struct StandbyCheck {
+    pub time: String,
+    pub lag_seconds: u64,
+    pub verify_from: String,
+    pub verify_to: String,
+    pub rows_repaired: u64, // 重扫时补写的行数，非 0 说明增量有遗漏
+    pub failed_segments: usize,
+}
+
+// 早于源表 TTL 过期边界、差异不计入比对的分段
+#[derive(Serialize, Debug, Clone)]
+pub struct TtlAffected {
+    pub segment: String,
+    pub boundary: String, // 判定时的过期边界
+    pub check: String,    // post-count / mirror
+    pub src_rows: u64,
+    pub dst_rows: u64,
+}
+
+// --background-verify 发现的已完成分段两端不一致
+#[derive(Serialize, Debug, Clone)]
+pub struct DriftFinding {
+    pub segment: String,
+    pub checked_at: String,
+    pub previously_verified_at: Option<String>, // 上次后台校验时间，从未校验过时为空
+    pub src_rows: u64,
+    pub dst_rows: u64,
+    pub src_checksum: u64,
+    pub dst_checksum: u64,
+}
+
+// 分段中被目标端以数据错误拒绝、写入死信文件的行（--on-rejected-row dead-letter）
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RejectedRows {
+    pub segment: String,
+    pub rows: u64,
+    pub errors: Vec<String>, // 服务端错误样例
+}
+
+// 分段中超过 --max-row-bytes 的行
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct OversizedRows {
+    pub segment: String,
+    pub single_row: u64,    // 单独写入的行数
+    pub dead_lettered: u64, // 超过 --max-row-hard-bytes、写入死信文件未迁移的行数
+    pub largest_bytes: u64,
+}
+
+// 分段传输的字节数（断点续传文件 bytes: 行）
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SegmentBytes {
+    pub segment: String,
+    pub uncompressed_bytes: u64, // 源端读取的响应体（JSONEachRow 文本）
+    pub wire_bytes: u64,         // 两端读取响应与写入请求体在线路上的字节，含重试
+}
+
+// 镜像模式下单个分段删除的目标端多余行
+#[derive(Serialize, Debug, Clone)]
+pub struct MirrorDelete {
+    pub segment: String,
+    pub extra_rows: u64,   // 目标有、源端无的行数
+    pub deleted_rows: u64, // 实际删除行数（rewrite 为整段行数）
+    pub method: String,    // key-delete / rewrite / dry-run
+}
+
+// 写入后核对发现目标端行数多于源端的分段（多为中断重跑时的重复写入）
+#[derive(Serialize, Debug, Clone)]
+pub struct OverCopied {
+    pub segment: String,
+    pub src_rows: u64,
+    pub dst_rows: u64,
+    pub excess: u64,
+    pub fix: Option<String>,        // --fix-overcopy: deduplicated N partition(s) / dry-run / failed: ...
+    pub dst_rows_after: Option<u64>, // 去重后重新核对的目标端行数
+}
+
+// 无法解析而跳过的行样例
+#[derive(Serialize, Debug, Clone)]
+pub struct BadRowSample {
+    pub offset: u64, // 在响应中的字节偏移
+    pub error: String,
+    pub preview: String, // 原始内容开头，非法字节替换为 U+FFFD
+}
+
+// 单个分段一次读取中跳过的行
+#[derive(Serialize, Debug, Clone)]
+pub struct BadRowSegment {
+    pub segment: String,
+    pub side: String, // src / dst
+    pub skipped: u64,
+    pub samples: Vec<BadRowSample>,
+}
+
+// --calibrate 的一组试跑
+#[derive(Serialize, Debug, Clone)]
+pub struct CalibrationTrial {
+    pub batch_bytes: u64,
+    pub parallelism: usize,
+    pub segments: usize,
+    pub rows: u64,
+    pub seconds: f64,
+    pub rows_per_sec: f64,
+    pub error_rate: f64, // 失败分段占比
+    pub failed_batches: u64,
+}
+
+// --calibrate 结果
+#[derive(Serialize, Debug, Clone)]
+pub struct Calibration {
+    pub trials: Vec<CalibrationTrial>,
+    pub chosen_batch_bytes: u64,
+    pub chosen_parallelism: usize,
+}
+
+// 分段各阶段耗时（秒）
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PhaseTimes {
+    pub read_src: f64,
+    pub read_dst: f64,
+    pub diff: f64, // 哈希与差集
+    pub insert: f64, // 写入（含镜像删除、服务端拷贝）
+}
+
+impl PhaseTimes {
+    pub fn sum(&self) -> f64 {
+        self.read_src + self.read_dst + self.diff + self.insert
+    }
+
+    pub const NAMES: [&'static str; 4] = ["read_src", "read_dst", "diff", "insert"];
+
+    pub fn get(&self, name: &str) -> f64 {
+        match name {
+            "read_src" => self.read_src,
+            "read_dst" => self.read_dst,
+            "diff" => self.diff,
+            _ => self.insert,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SegmentTiming {
+    pub segment: String,
+    pub worker: usize,
+    pub phases: PhaseTimes,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct WorkerTiming {
+    pub worker: usize,
+    pub segments: usize,
+    pub wall_seconds: f64,
+    pub idle_seconds: f64, // 等待闸门、mutation 暂停、写断点续传等
+    pub phases: PhaseTimes,
+}
+
+// 分段耗时归因汇总
+#[derive(Serialize, Debug, Clone)]
+pub struct Throughput {
+    pub wall_seconds: f64, // 所有 worker 运行时长之和
+    pub idle_seconds: f64,
+    pub total: PhaseTimes,
+    pub workers: Vec<WorkerTiming>,
+    pub slowest: std::collections::BTreeMap<String, Vec<SegmentTiming>>, // 各阶段最慢的 5 个分段
+}
+
+impl Throughput {
+    // 如 "read src 30%, read dst 12%, hash 13%, insert 41%, idle 4%"
+    pub fn breakdown(&self) -> String {
+        let pct = |v: f64| if self.wall_seconds > 0.0 { v * 100.0 / self.wall_seconds } else { 0.0 };
+        format!(
+            "read src {:.0}%, read dst {:.0}%, hash {:.0}%, insert {:.0}%, idle {:.0}%",
+            pct(self.total.read_src), pct(self.total.read_dst), pct(self.total.diff), pct(self.total.insert), pct(self.idle_seconds)
+        )
+    }
+}
+
+// 进程退出码（单表与多表运行一致，多表取所有表中最严重的结果）：
+// 0 全部表迁移成功且无失败分段；1 启动/参数等错误，未进入迁移；
+// 2 部分成功：有表存在失败分段或因 --fail-fast 未执行，其余分段已完成，可按断点续传直接重试；
+// 3 有表切换步骤失败或切换后校验未通过（含 --post-cutover-watch 发现新表停滞），需人工确认后再处理（3 优先于其他）
+// 4 超过 --max-duration 停止，未切换，可在下个窗口按断点续传重试
+// 5 连续失败或失败率超过上限熔断，未切换，排除故障（见报告 circuit_broken）后按断点续传重试
+// 6 迁移中途失败（运行返回错误，见报告 error），未切换，需排除错误后再重试；未知结果同样按此处理
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_PARTIAL: i32 = 2;
+pub const EXIT_CUTOVER_FAILED: i32 = 3;
+pub const EXIT_DEADLINE: i32 = 4;
+pub const EXIT_CIRCUIT_BROKEN: i32 = 5;
+pub const EXIT_FAILED: i32 = 6;
+
+// 切换后校验
+#[derive(Serialize, Debug, Clone)]
+pub struct PostCutoverCheck {
+    pub bak_rows: u64,
+    pub new_rows: u64,
+    pub passed: bool,
+}
+
+// 切换后观察（--post-cutover-watch）的一次采样；行数为时间晚于 since 的行
+#[derive(Serialize, Debug, Clone)]
+pub struct WatchSample {
+    pub time: String,
+    pub bak_rows: u64,
+    pub live_rows: u64,
+    pub bak_max_time: String,
+    pub live_max_time: String,
+}
+
+// 切换后观察
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct PostCutoverWatch {
+    pub started_at: String,
+    pub finished_at: String,
+    pub since: String, // 新鲜行的时间下界：观察开始时 _bak 表的最大时间
+    pub samples: Vec<WatchSample>,
+    pub issues: Vec<String>,
+    pub healthy: bool,
+}
+
+// 切换后冒烟查询
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct SmokeCheck {
+    pub user: String, // 空为目标端迁移账号
+    pub rows: u64,
+    pub expected_rows: u64,
+    pub max_time: String,
+    pub expected_max_time: String,
+    pub passed: bool,
+    pub error: Option<String>,
+}
+
+// --normalize 比对前规范化的规则与列
+#[derive(Serialize, Debug, Clone)]
+pub struct Normalization {
+    pub rules: Vec<String>,
+    pub columns: Vec<String>,
+    pub note: String,
+}
+
+// --query-priority / --workload / --settings-profile / --os-nice 生效的设置
+#[derive(Serialize, Debug, Clone)]
+pub struct QueryPriority {
+    pub query_priority: u64, // 0 为未设置
+    pub workload: Option<String>,
+    pub settings_profile: Option<String>,
+    pub os_nice: u32, // 0 为未调整
+}
+
+// 一端的 ClickHouse 版本与各功能是否可用（版本未知时 version 为空、capabilities 为空）
+#[derive(Serialize, Debug, Clone)]
+pub struct ServerVersion {
+    pub side: String, // src / dst / src/dst
+    pub endpoint: String,
+    pub version: Option<String>,
+    pub capabilities: BTreeMap<String, bool>,
+}
+
+// 源表的一条行策略
+#[derive(Serialize, Debug, Clone)]
+pub struct RowPolicy {
+    pub name: String,
+    pub table: String,
+    pub filter: String, // select_filter
+    pub restrictive: bool,
+    pub applies: bool, // 作用于连接用户（或其生效角色）
+}
+
+// 源表行策略与连接用户；accepted 为 --accept-row-policies（拷贝有意按策略过滤）
+#[derive(Serialize, Debug, Clone)]
+pub struct RowPolicies {
+    pub user: String,
+    pub roles: Vec<String>,
+    pub policies: Vec<RowPolicy>,
+    pub accepted: bool,
+}
+
+// 一条集群相关 DDL 实际执行的形式
+#[derive(Serialize, Debug, Clone)]
+pub struct ClusterDdl {
+    pub endpoint: String,
+    pub cluster: String,
+    pub form: String,           // on-cluster / local
+    pub reason: Option<String>, // 回退为单机形式的原因
+    pub statement: String,      // 实际执行的语句
+}
+
+// --supervise 的一次运行尝试
+#[derive(Serialize, Debug, Clone)]
+pub struct RunAttempt {
+    pub attempt: usize, // 从 1 开始
+    pub started_at: String,
+    pub finished_at: String,
+    pub duration_seconds: u64,
+    pub outcome: String,
+    pub error: Option<String>,
+    pub segments_done: usize,
+    pub segments_failed: usize,
+    pub rows_written: u64,
+    pub restart_reason: Option<String>, // 判为可重试的错误；为空表示之后没有重启
+    pub backoff_seconds: u64,
+}
+
+// 熔断原因与失败概况
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct CircuitBreak {
+    pub reason: String,
+    pub phase: String, // 熔断后停止前的阶段
+    pub failures: usize,
+    pub first_segment: String,
+    pub first_error: String,
+    pub most_common_error: String, // 数字归一为 # 后的错误文本
+    pub most_common_count: usize,
+}
+
+impl CircuitBreak {
+    pub fn at(mut self, phase: &str) -> Self {
+        self.phase = phase.to_string();
+        self
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct RunReport {
+    pub src: String,
+    pub dst: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub status: String, // ok / failed
+    pub outcome: String, // ok / partial / failed / cutover-failed / circuit-broken / deadline / skipped
+    pub error: Option<String>,
+    pub duration_seconds: u64,
+    pub segments_done: usize,
+    pub rows_written: u64,
+    pub cutover: String, // performed / rolled-back / skipped / failed / not-reached；切换开始后为 started
+    pub cutover_trigger: Option<String>, // no-new-data / cutover-when / cutover-at / standby
+    pub deadline_hit: Option<String>,    // 超过 --max-duration 时停止前的阶段
+    pub circuit_broken: Option<CircuitBreak>, // --max-consecutive-failures / --max-failure-rate 熔断
+    pub replica_lag_checks: Vec<ReplicaLagCheck>,
+    pub replication_lag: Vec<LagSample>, // 增量阶段 每轮时间 → 复制延迟
+    pub clock_skew: Vec<ClockSkew>,      // 启动检查及运行中各次测得的时钟偏差
+    pub merges_paused: Option<MergePause>, // --pause-dst-merges 目标表合并的暂停与恢复时间
+    pub standby_checks: Vec<StandbyCheck>, // --standby 各次重扫校验
+    pub partitions_attached: Vec<PartitionAttach>,
+    pub partitions_replaced: Vec<PartitionReplace>,
+    pub disk_check: Option<DiskCheck>,
+    pub time_index: Option<TimeIndexCheck>,
+    pub identity_risks: Vec<IdentityRisk>, // 被忽略但参与行身份的字段
+    pub schema_mismatches: Vec<SchemaMismatch>,
+    pub normalization: Option<Normalization>, // --normalize：这些列的比对结果只表示规范化后相等
+    pub priority: Option<QueryPriority>, // 附加到两端查询的优先级设置
+    pub server_versions: Vec<ServerVersion>, // 两端 ClickHouse 版本与可用功能
+    pub row_policies: Option<RowPolicies>, // 源表的行策略：有作用于连接用户的策略时拷贝只含可见的行
+    pub replicated_rename: Vec<String>, // ZooKeeper 路径含表名、RENAME 后路径与表名不符的 Replicated 表
+    pub mutations_observed: Vec<MutationSeen>,
+    pub archive_segments: Vec<ArchiveSegment>,
+    pub optimizations: Vec<OptimizeRun>,
+    pub segments_blacklisted: Vec<String>, // 命中 --skip-segments-file 未迁移
+    pub segments_coalesced: usize, // --coalesce-empty 按天探测为空、以范围记录标记完成的分段数（计入 segments_done）
+    pub segments_rerun: Vec<String>, // --only-segments / --segment-range 重新迁移的分段
+    pub segments_failed: Vec<String>,
+    pub segments_deduplicated: u64, // 已入队、处理中或已完成而跳过的重复入队次数
+    pub mirror_deletes: Vec<MirrorDelete>,
+    pub bad_rows: Vec<BadRowSegment>, // --on-bad-row skip/dead-letter 跳过的行
+    pub over_copied: Vec<OverCopied>,  // 目标端行数多于源端的分段
+    pub ttl_affected: Vec<TtlAffected>, // 早于源表 TTL 过期边界、差异不计入比对的分段
+    pub oversized_rows: Vec<OversizedRows>, // 超过 --max-row-bytes 的行
+    pub rejected_rows: Vec<RejectedRows>,   // 目标端拒绝、写入死信文件的行
+    pub segment_bytes: Vec<SegmentBytes>,   // 本次完成的各分段传输字节
+    pub background_verify: Vec<DriftFinding>, // --background-verify 发现的不一致（含此前运行）
+    pub calibration: Option<Calibration>,
+    pub throughput: Option<Throughput>,
+    pub readonly_wait_seconds: u64, // 目标端只读/part 过多累计等待
+    pub src_query_wait_seconds: u64, // 等待 --src-max-concurrent-queries 许可累计（进程级）
+    pub blackout_paused_seconds: u64, // --blackout 维护窗口内暂停累计（进程级）
+    pub src_replica: Option<String>, // --src-prefer-replica 时实际提供源端读取的副本（进程级）
+    pub cluster_ddl: Vec<ClusterDdl>, // --cluster-name 相关 DDL 实际执行的形式（进程级）
+    pub post_cutover_check: Option<PostCutoverCheck>,
+    pub smoke_check: Option<SmokeCheck>,
+    pub post_cutover_watch: Option<PostCutoverWatch>,
+    pub bak_retention_action: Option<String>,
+    pub attempts: Vec<RunAttempt>, // --supervise 各次尝试，最后一项即本报告对应的运行
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+impl RunReport {
+    pub fn new(opt: &Opt) -> Self {
+        RunReport {
+            src: format!("{}.{}", opt.src_db, opt.src_table),
+            dst: format!("{}.{}", opt.dst_db, opt.dst_table),
+            started_at: now_str(),
+            started: Some(Instant::now()),
+            ..Default::default()
+        }
+    }
+
+    pub fn finish(&mut self, res: &anyhow::Result<()>) {
+        self.finished_at = now_str();
+        self.duration_seconds = self.started.map(|t| t.elapsed().as_secs()).unwrap_or(0);
+        match res {
+            Ok(()) => self.status = "ok".to_string(),
+            Err(e) => {
+                self.status = "failed".to_string();
+                self.error = Some(e.to_string());
+            }
+        }
+        self.cutover = match (self.cutover.as_str(), res.is_ok()) {
+            ("started", false) => "failed".to_string(),
+            ("", _) => "not-reached".to_string(),
+            (c, _) => c.to_string(),
+        };
+        let verify_failed = self.verification() == "failed";
+        self.outcome = if self.cutover == "failed" || verify_failed {
+            "cutover-failed"
+        } else if res.is_err() {
+            "failed"
+        } else if self.circuit_broken.is_some() {
+            "circuit-broken"
+        } else if self.deadline_hit.is_some() {
+            "deadline"
+        } else if !self.segments_failed.is_empty() {
+            "partial"
+        } else {
+            "ok"
+        }
+        .to_string();
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.outcome.as_str() {
+            "ok" => EXIT_OK,
+            "cutover-failed" => EXIT_CUTOVER_FAILED,
+            "deadline" => EXIT_DEADLINE,
+            "circuit-broken" => EXIT_CIRCUIT_BROKEN,
+            "partial" | "skipped" => EXIT_PARTIAL,
+            "failed" => EXIT_FAILED,
+            // 未完成（finish 之前）或无法识别的结果不能当作可直接重试的部分成功
+            _ => EXIT_FAILED,
+        }
+    }
+
+    pub fn verification(&self) -> &'static str {
+        let checks = [
+            self.post_cutover_check.as_ref().map(|c| c.passed),
+            self.smoke_check.as_ref().map(|c| c.passed),
+            self.post_cutover_watch.as_ref().map(|w| w.healthy),
+        ];
+        if checks.contains(&Some(false)) {
+            "failed"
+        } else if checks.iter().all(|c| c.is_none()) {
+            "-"
+        } else {
+            "passed"
+        }
+    }
+
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+// datacp verify 单个分区的比对结果
+#[derive(Serialize, Debug, Clone)]
+pub struct PartitionVerify {
+    pub partition_id: String,
+    pub method: String, // part-layout / part-aggregate / checksum / count
+    pub src_rows: u64,
+    pub dst_rows: u64,
+    pub src_bytes: Option<u64>, // system.parts 未压缩字节（part-* 方式）
+    pub dst_bytes: Option<u64>,
+    pub src_checksum: Option<u64>, // 校验和（checksum 方式）
+    pub dst_checksum: Option<u64>,
+    pub matched: bool,
+}
+
+// datacp verify 报告：confidence 说明本次实际用到的各比对方式检查了什么
+#[derive(Serialize, Debug, Default)]
+pub struct VerifyReport {
+    pub src: String,
+    pub dst: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub strategy: String,
+    pub confidence: std::collections::BTreeMap<String, String>,
+    pub normalization: Option<Normalization>,
+    pub partitions: Vec<PartitionVerify>,
+    pub mismatched: usize,
+}
+
+impl VerifyReport {
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+// datacp ddl 报告：每个对象的源端建表语句与实际执行（或 --dry-run 打印）的语句
+#[derive(Serialize, Debug, Default)]
+pub struct DdlReport {
+    pub src_db: String,
+    pub dst_db: String,
+    pub started_at: String,
+    pub finished_at: String,
+    pub dry_run: bool,
+    pub rules: Vec<String>, // --create-rewrite / --create-strip / --create-set
+    pub objects: Vec<DdlObject>,
+    pub failed: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DdlObject {
+    pub name: String,
+    pub kind: String,
+    pub original: String,
+    pub rewritten: String,
+    pub changes: Vec<String>, // 表级设置与 TTL 的改动
+    pub status: String,       // created / exists / failed / dry_run
+}
+
+impl DdlReport {
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+// --all-tables 未纳入迁移的表
+#[derive(Serialize, Debug, Clone)]
+pub struct SkippedTable {
+    pub table: String,
+    pub reason: String,
+}
+
+// 多表迁移汇总报告
+#[derive(Serialize, Debug, Default)]
+pub struct MultiReport {
+    pub started_at: String,
+    pub finished_at: String,
+    pub exit_code: i32,
+    pub tables: Vec<RunReport>,
+    pub skipped_tables: Vec<SkippedTable>,
+    pub cluster_ddl: Vec<ClusterDdl>, // --cluster-name 相关 DDL 实际执行的形式
+}
+
+impl MultiReport {
+    pub fn new(mut tables: Vec<RunReport>) -> Self {
+        tables.sort_by(|a, b| a.src.cmp(&b.src));
+        let started_at = tables.iter().map(|t| t.started_at.clone()).min().unwrap_or_default();
+        MultiReport { started_at, tables, ..Default::default() }
+    }
+
+    pub fn finish(&mut self) {
+        self.finished_at = now_str();
+        // 切换失败需人工处理，优先于超时与部分成功
+        self.exit_code = self
+            .tables
+            .iter()
+            .map(|t| t.exit_code())
+            .max_by_key(|c| if *c == EXIT_CUTOVER_FAILED { i32::MAX } else { *c })
+            .unwrap_or(EXIT_OK);
+    }
+
+    // 按表打印结果汇总，与 JSON 报告中的数据一致
+    pub fn print_summary(&self) {
+        events::say(&format!(
+            "{:<40} {:<15} {:>8} {:>8} {:>12} {:>9} {:<12} {:<8}",
+            "table", "outcome", "done", "failed", "rows", "seconds", "cutover", "verify"
+        ));
+        for t in &self.tables {
+            events::say(&format!(
+                "{:<40} {:<15} {:>8} {:>8} {:>12} {:>9} {:<12} {:<8}",
+                t.src, t.outcome, t.segments_done, t.segments_failed.len(), t.rows_written, t.duration_seconds, t.cutover, t.verification()
+            ));
+            if let Some(tp) = &t.throughput {
+                events::say(&format!("    {}", tp.breakdown()));
+            }
+            if let Some(e) = &t.error {
+                events::say(&format!("    error: {}", e));
+            }
+            if let Some(c) = &t.circuit_broken {
+                events::say(&format!("    circuit broken: {}; first error ({}): {}; most common ({}x): {}", c.reason, c.first_segment, c.first_error, c.most_common_count, c.most_common_error));
+            }
+        }
+        for s in &self.skipped_tables {
+            events::say(&format!("{:<40} {:<15} {}", s.table, "not-selected", s.reason));
+        }
+        events::say(&format!("exit code: {}", self.exit_code));
+    }
+
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+pub fn now_str() -> String {
+    chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_outcome(outcome: &str) -> RunReport {
+        RunReport { outcome: outcome.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn each_outcome_has_its_exit_code() {
+        let cases = [
+            ("ok", EXIT_OK),
+            ("partial", EXIT_PARTIAL),
+            ("skipped", EXIT_PARTIAL),
+            ("cutover-failed", EXIT_CUTOVER_FAILED),
+            ("deadline", EXIT_DEADLINE),
+            ("circuit-broken", EXIT_CIRCUIT_BROKEN),
+            ("failed", EXIT_FAILED),
+            ("", EXIT_FAILED),
+            ("something-new", EXIT_FAILED),
+        ];
+        for (outcome, code) in cases {
+            assert_eq!(with_outcome(outcome).exit_code(), code, "{}", outcome);
+        }
+    }
+
+    #[test]
+    fn finish_separates_hard_failure_from_partial_success() {
+        let mut partial = RunReport { segments_failed: vec!["2024-01-01 00:00:00".to_string()], ..Default::default() };
+        partial.finish(&Ok(()));
+        assert_eq!((partial.outcome.as_str(), partial.exit_code()), ("partial", EXIT_PARTIAL));
+        let mut failed = RunReport::default();
+        failed.finish(&Err(anyhow::anyhow!("Code: 60. DB::Exception: Table app.events does not exist")));
+        assert_eq!((failed.outcome.as_str(), failed.exit_code()), ("failed", EXIT_FAILED));
+    }
+
+    #[test]
+    fn multi_run_takes_the_most_severe_code() {
+        let multi = |outcomes: &[&str]| {
+            let mut m = MultiReport::new(outcomes.iter().map(|o| with_outcome(o)).collect());
+            m.finish();
+            m.exit_code
+        };
+        assert_eq!(multi(&["ok", "ok"]), EXIT_OK);
+        assert_eq!(multi(&["ok", "partial"]), EXIT_PARTIAL);
+        assert_eq!(multi(&["partial", "failed"]), EXIT_FAILED);
+        assert_eq!(multi(&["failed", "cutover-failed"]), EXIT_CUTOVER_FAILED);
+    }
+}
@@ -0,0 +1,216 @@
+// ===================== 源表行策略检查（--expect-full-visibility / --accept-row-policies） =====================
+// 源表有行策略（row policy）时，datacp 连接所用的账号可能只看得到一部分行：迁移与比对都只覆盖可见的行，
+// 目标端缺少策略隐藏的行也会“校验通过”。预检查询 system.row_policies 中源表（分布式表另查其本地表）的策略，
+// 按 apply_to_all / apply_to_list / apply_to_except 与当前用户（currentUser()）及其生效角色（system.enabled_roles）
+// 判断每条策略是否作用于本次连接，并醒目告警列出策略与连接用户：
+//   --expect-full-visibility  任一策略作用于当前用户（或无法查询策略）时拒绝运行
+//   --accept-row-policies     明确接受按策略过滤的拷贝，报告与运行清单的 row_policies.accepted 为 true
+// 两者都未指定时只告警，报告中同样记录
+
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::report::{RowPolicies, RowPolicy, RunReport};
+use crate::{ch_query_rows, qualified, shard, Opt};
+
+fn strings(v: Option<&Value>) -> Vec<String> {
+    v.and_then(|v| v.as_array()).map(|a| a.iter().filter_map(|s| s.as_str().map(|s| s.to_string())).collect()).unwrap_or_default()
+}
+
+fn flag(v: Option<&Value>) -> bool {
+    match v {
+        Some(Value::Bool(b)) => *b,
+        Some(Value::Number(n)) => n.as_u64().unwrap_or(0) != 0,
+        Some(Value::String(s)) => s == "1" || s == "true",
+        _ => false,
+    }
+}
+
+// 策略是否作用于该用户（或其任一生效角色）
+fn applies(row: &HashMap<String, Value>, user: &str, roles: &[String]) -> bool {
+    let hit = |list: &[String]| list.iter().any(|n| n == user || roles.contains(n));
+    let except = strings(row.get("apply_to_except"));
+    if flag(row.get("apply_to_all")) {
+        !hit(&except)
+    } else {
+        hit(&strings(row.get("apply_to_list")))
+    }
+}
+
+// 查询源表的行策略；源表没有策略时返回 None
+async fn detect(opt: &Opt) -> anyhow::Result<Option<RowPolicies>> {
+    let mut tables = vec![(opt.src_db.clone(), opt.src_table.clone())];
+    let local = shard::resolve_local_table(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    if !tables.contains(&local) {
+        tables.push(local);
+    }
+    let cond: Vec<String> = tables.iter().map(|(d, t)| format!("(database = '{}' AND table = '{}')", d, t)).collect();
+    let sql = format!(
+        "SELECT name, database, table, select_filter, is_restrictive, apply_to_all, apply_to_list, apply_to_except \
+         FROM system.row_policies WHERE {} ORDER BY name FORMAT JSONEachRow",
+        cond.join(" OR ")
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
+    let who = ch_query_rows(
+        &opt.src_dsn,
+        &opt.src_db,
+        "SELECT currentUser() AS user, (SELECT groupArray(role_name) FROM system.enabled_roles) AS roles FORMAT JSONEachRow",
+    )
+    .await?;
+    let user = who.first().and_then(|r| r.get("user")).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+    let roles = strings(who.first().and_then(|r| r.get("roles")));
+    let policies = rows
+        .iter()
+        .map(|r| RowPolicy {
+            name: r.get("name").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            table: qualified(
+                r.get("database").and_then(|v| v.as_str()).unwrap_or_default(),
+                r.get("table").and_then(|v| v.as_str()).unwrap_or_default(),
+            ),
+            filter: r.get("select_filter").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            restrictive: flag(r.get("is_restrictive")),
+            applies: applies(r, &user, &roles),
+        })
+        .collect();
+    Ok(Some(RowPolicies { user, roles, policies, accepted: opt.accept_row_policies }))
+}
+
+// 预检：告警并按参数决定是否继续，结果写入报告
+pub async fn check(opt: &Opt, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<()> {
+    if opt.expect_full_visibility && opt.accept_row_policies {
+        anyhow::bail!("--expect-full-visibility 与 --accept-row-policies 不能同时指定");
+    }
+    let found = match detect(opt).await {
+        Ok(f) => f,
+        Err(e) if opt.expect_full_visibility => {
+            anyhow::bail!(format!("--expect-full-visibility: 无法查询源表的行策略（system.row_policies），不能确认可见全部行: {}", e))
+        }
+        Err(e) => {
+            warn!("无法查询源表的行策略（system.row_policies），未检查行级可见性: {e}");
+            return Ok(());
+        }
+    };
+    let Some(p) = found else {
+        info!("源表 {} 没有行策略", qualified(&opt.src_db, &opt.src_table));
+        return Ok(());
+    };
+    let applied: Vec<&RowPolicy> = p.policies.iter().filter(|x| x.applies).collect();
+    warn!(
+        "源表 {} 有 {} 条行策略，其中 {} 条作用于连接用户 {}（角色: {}）: {}",
+        qualified(&opt.src_db, &opt.src_table),
+        p.policies.len(),
+        applied.len(),
+        p.user,
+        if p.roles.is_empty() { "-".to_string() } else { p.roles.join(",") },
+        p.policies
+            .iter()
+            .map(|x| format!("{} on {} USING {}{}", x.name, x.table, x.filter, if x.applies { "（生效）" } else { "" }))
+            .collect::<Vec<_>>()
+            .join("；")
+    );
+    if !applied.is_empty() {
+        if opt.expect_full_visibility {
+            anyhow::bail!(format!(
+                "--expect-full-visibility: 行策略 {} 作用于用户 {}，迁移与比对只会覆盖策略可见的行；请改用不受策略限制的账号，或确认后改用 --accept-row-policies",
+                applied.iter().map(|x| x.name.as_str()).collect::<Vec<_>>().join(", "),
+                p.user
+            ));
+        }
+        if opt.accept_row_policies {
+            warn!("已指定 --accept-row-policies：本次拷贝按行策略过滤，目标端只包含用户 {} 可见的行", p.user);
+        } else {
+            warn!("目标端将只包含用户 {} 可见的行，校验也只覆盖这些行；有意如此时指定 --accept-row-policies，要求完整拷贝时指定 --expect-full-visibility", p.user);
+        }
+    }
+    report.lock().unwrap().row_policies = Some(p);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_ch;
+    use structopt::StructOpt;
+
+    fn opt(dsn: &str, extra: &[&str]) -> Opt {
+        let args = ["datacp", "--src-dsn", dsn, "--dst-dsn", dsn, "--src-db", "app", "--src-table", "events"];
+        Opt::from_iter(args.iter().chain(extra))
+    }
+
+    const MERGE_TREE: &str = "{\"engine\":\"MergeTree\",\"engine_full\":\"MergeTree ORDER BY id\"}\n";
+
+    // 源表有两条策略：tenant 作用于所有用户（admin 除外），audit 只作用于 auditor
+    fn with_policies(sql: &str) -> (u16, String) {
+        let body = if sql.contains("FROM system.tables") {
+            MERGE_TREE
+        } else if sql.contains("FROM system.row_policies") {
+            concat!(
+                "{\"name\":\"tenant ON app.events\",\"database\":\"app\",\"table\":\"events\",\"select_filter\":\"tenant_id = 1\",",
+                "\"is_restrictive\":0,\"apply_to_all\":1,\"apply_to_list\":[],\"apply_to_except\":[\"admin\"]}\n",
+                "{\"name\":\"audit ON app.events\",\"database\":\"app\",\"table\":\"events\",\"select_filter\":\"1\",",
+                "\"is_restrictive\":0,\"apply_to_all\":0,\"apply_to_list\":[\"auditor\"],\"apply_to_except\":[]}\n"
+            )
+        } else if sql.contains("currentUser()") {
+            "{\"user\":\"default\",\"roles\":[]}\n"
+        } else {
+            ""
+        };
+        (200, body.to_string())
+    }
+
+    fn without_policies(sql: &str) -> (u16, String) {
+        (200, if sql.contains("FROM system.tables") { MERGE_TREE } else { "" }.to_string())
+    }
+
+    fn no_access(sql: &str) -> (u16, String) {
+        if sql.contains("FROM system.row_policies") {
+            return (500, "Code: 497. DB::Exception: default: Not enough privileges".to_string());
+        }
+        without_policies(sql)
+    }
+
+    #[tokio::test]
+    async fn policies_applying_to_the_user_are_detected_and_guarded() {
+        let (dsn, _) = mock_ch::serve(with_policies).await;
+        let report = Arc::new(Mutex::new(RunReport::default()));
+        check(&opt(&dsn, &[]), &report).await.unwrap();
+        let p = report.lock().unwrap().row_policies.clone().unwrap();
+        assert_eq!(p.user, "default");
+        assert!(!p.accepted);
+        assert_eq!(p.policies.iter().map(|x| (x.name.as_str(), x.applies)).collect::<Vec<_>>(), vec![("tenant ON app.events", true), ("audit ON app.events", false)]);
+
+        let err = check(&opt(&dsn, &["--expect-full-visibility"]), &report).await.unwrap_err().to_string();
+        assert!(err.contains("tenant ON app.events") && err.contains("default"), "{err}");
+
+        let report = Arc::new(Mutex::new(RunReport::default()));
+        check(&opt(&dsn, &["--accept-row-policies"]), &report).await.unwrap();
+        assert!(report.lock().unwrap().row_policies.as_ref().unwrap().accepted);
+        assert!(check(&opt(&dsn, &["--accept-row-policies", "--expect-full-visibility"]), &report).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn no_policies_or_no_access() {
+        let (dsn, _) = mock_ch::serve(without_policies).await;
+        let report = Arc::new(Mutex::new(RunReport::default()));
+        check(&opt(&dsn, &["--expect-full-visibility"]), &report).await.unwrap();
+        assert!(report.lock().unwrap().row_policies.is_none());
+
+        let (dsn, _) = mock_ch::serve(no_access).await;
+        check(&opt(&dsn, &[]), &report).await.unwrap();
+        assert!(check(&opt(&dsn, &["--expect-full-visibility"]), &report).await.is_err());
+    }
+
+    #[test]
+    fn except_list_matches_roles() {
+        let row: HashMap<String, Value> =
+            serde_json::from_str("{\"apply_to_all\":1,\"apply_to_list\":[],\"apply_to_except\":[\"etl\"]}").unwrap();
+        assert!(applies(&row, "datacp", &[]));
+        assert!(!applies(&row, "datacp", &["etl".to_string()]));
+        assert!(!applies(&row, "etl", &[]));
+    }
+}
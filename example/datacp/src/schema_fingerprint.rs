@@ -0,0 +1,103 @@
+// ===================== 表结构指纹（断点续传与切换前校验） =====================
+// 首次运行时对两端共有的迁移字段（源表字段去掉 --ignore-field 且目标读取表中存在，按目标表物理顺序）
+// 取 DESCRIBE 的 (name, type)，两端分别列出后计算 sha256，与两端结构一起记入断点续传元数据（{done_segments}.meta 的 schema）。
+// 每次续传、源表改名为 _bak 之前、目标表 rename 之前按实时 DESCRIBE 重新计算，不一致时拒绝继续：
+// 中断与续传之间改过表结构时，已完成分段与之后写入的数据不再对应，继续运行只会在 worker 深处报错或写入错位的数据。
+// 确认是有意且兼容的变更时指定 --accept-schema-change，以当前结构重新记录基线后继续。
+// 不一致时报告的 schema_mismatches 记录两次指纹与本次观察到的两端结构
+
+use log::{info, warn};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+
+use crate::report::{ColumnType, RunReport, SchemaMismatch};
+use crate::{ch_query_rows, checkpoint_meta, is_ignored_field, qualified, Opt};
+
+async fn describe(dsn: &str, db: &str, table: &str) -> anyhow::Result<Vec<ColumnType>> {
+    let sql = format!("DESCRIBE TABLE {} FORMAT JSONEachRow", qualified(db, table));
+    let s = |r: &std::collections::HashMap<String, Value>, k: &str| r.get(k).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    Ok(ch_query_rows(dsn, db, &sql).await?.iter().map(|r| ColumnType { name: s(r, "name"), r#type: s(r, "type") }).collect())
+}
+
+// 两端共有的迁移字段：按目标表顺序，各自取本端类型
+fn shared(src: &[ColumnType], dst: &[ColumnType], ignore: &[String]) -> (Vec<ColumnType>, Vec<ColumnType>) {
+    dst.iter()
+        .filter(|d| !is_ignored_field(&d.name, ignore))
+        .filter_map(|d| src.iter().find(|s| s.name == d.name).map(|s| (s.clone(), d.clone())))
+        .unzip()
+}
+
+fn fingerprint(src: &[ColumnType], dst: &[ColumnType]) -> String {
+    let mut text = String::new();
+    for (side, cols) in [("src", src), ("dst", dst)] {
+        text.push_str(side);
+        text.push('\n');
+        for c in cols {
+            text.push_str(&format!("{}\t{}\n", c.name, c.r#type));
+        }
+    }
+    Sha256::digest(text.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn write_meta(meta_file: &str, meta: &Value) -> anyhow::Result<()> {
+    std::fs::write(meta_file, serde_json::to_string(meta)?).map_err(|e| anyhow::anyhow!(format!("写入 {} 失败: {}", meta_file, e)))
+}
+
+// 按 opt 的源表与目标读取表的实时结构计算指纹，返回 (指纹, 源端共有字段, 目标端共有字段)
+pub async fn observe(opt: &Opt) -> anyhow::Result<(String, Vec<ColumnType>, Vec<ColumnType>)> {
+    let src_all = describe(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    let dst_all = describe(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+    let (src, dst) = shared(&src_all, &dst_all, &opt.ignore_field);
+    Ok((fingerprint(&src, &dst), src, dst))
+}
+
+// 按 opt 的源表与目标读取表计算指纹并与断点续传元数据比对；元数据中还没有指纹时记录为基线。
+// stage 为检查时机：resume / cutover-src / cutover-dst
+pub async fn check(opt: &Opt, done_segments_file: &str, stage: &str, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<()> {
+    let (observed, src, dst) = observe(opt).await?;
+    let meta_file = checkpoint_meta::meta_file(done_segments_file);
+    let mut meta: Value = match std::fs::read_to_string(&meta_file) {
+        Ok(text) => serde_json::from_str(&text).map_err(|e| anyhow::anyhow!(format!("解析 {} 失败: {}", meta_file, e)))?,
+        Err(_) => json!({}),
+    };
+    let baseline = json!({ "fingerprint": observed, "src": src, "dst": dst });
+    let recorded = match meta.get("schema").and_then(|s| s.get("fingerprint")).and_then(|f| f.as_str()) {
+        Some(f) => f.to_string(),
+        None => {
+            info!("记录表结构指纹 {}（{} 个共有字段）到 {}", &observed[..16], src.len(), meta_file);
+            meta["schema"] = baseline;
+            return write_meta(&meta_file, &meta);
+        }
+    };
+    if recorded == observed {
+        info!("表结构指纹一致（{}）: {}", stage, &observed[..16]);
+        return Ok(());
+    }
+    let mismatch = SchemaMismatch {
+        stage: stage.to_string(),
+        recorded_fingerprint: recorded.clone(),
+        observed_fingerprint: observed.clone(),
+        recorded_src: serde_json::from_value(meta["schema"]["src"].clone()).unwrap_or_default(),
+        recorded_dst: serde_json::from_value(meta["schema"]["dst"].clone()).unwrap_or_default(),
+        src_columns: src,
+        dst_columns: dst,
+        accepted: opt.accept_schema_change,
+    };
+    report.lock().unwrap().schema_mismatches.push(mismatch);
+    if !opt.accept_schema_change {
+        anyhow::bail!(format!(
+            "{} 与 {} 的表结构指纹 {} 与断点续传元数据 {} 记录的 {} 不一致（{}），两端结构见报告 schema_mismatches；\
+             确认是有意且兼容的变更后指定 --accept-schema-change 重新记录",
+            qualified(&opt.src_db, &opt.src_table),
+            qualified(&opt.dst_db, opt.read_table()),
+            &observed[..16],
+            meta_file,
+            &recorded[..recorded.len().min(16)],
+            stage
+        ));
+    }
+    warn!("表结构指纹已变化（{}）: {} → {}，按 --accept-schema-change 重新记录", stage, &recorded[..recorded.len().min(16)], &observed[..16]);
+    meta["schema"] = baseline;
+    write_meta(&meta_file, &meta)
+}
@@ -0,0 +1,76 @@
+// ===================== 分段区间（--segment-size） =====================
+// 分段默认每小时一段，--segment-size 可改为 15m、30m、6h、1d 等能整除一天的时长。中途改变分段大小时不必重做已完成的时段：
+// 断点续传文件按 [起, 止) 区间记录完成的分段（"2024-01-01 00:00:00..2024-01-01 00:15:00"），旧版本只记起点的记录按 1 小时处理，
+// 空分段合并的范围记录展开后同样按 1 小时处理。生成分段时从迁移窗口中减去已完成区间的并集，按分段大小切分剩余部分
+// （见 datacp::planner）：分段起点从 min_time 起按分段大小对齐，整段未完成的以起点为名；只有部分未完成的
+// （先用小分段、后改大分段时）以 "起..止" 为名，只处理其中未完成的部分
+
+use chrono::{Duration, NaiveDateTime};
+use datacp::planner::{IntervalSet, TIME_FORMAT};
+use log::info;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use crate::Opt;
+
+// 分段大小（秒，进程级）
+static SIZE_SECS: AtomicI64 = AtomicI64::new(3600);
+
+pub fn init(opt: &Opt) -> anyhow::Result<()> {
+    let secs = opt.segment_size.as_secs() as i64;
+    if secs < 60 || 86400 % secs != 0 || opt.segment_size.subsec_nanos() != 0 {
+        anyhow::bail!(format!("--segment-size 须为 1m ~ 1d 之间、能整除一天的时长（如 15m、1h、6h）: {}s", opt.segment_size.as_secs_f64()));
+    }
+    SIZE_SECS.store(secs, Ordering::Relaxed);
+    if secs != 3600 {
+        info!("分段大小: {}s", secs);
+    }
+    Ok(())
+}
+
+pub fn size() -> Duration {
+    Duration::seconds(SIZE_SECS.load(Ordering::Relaxed))
+}
+
+fn time(s: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(s, TIME_FORMAT).ok()
+}
+
+fn fmt(t: NaiveDateTime) -> String {
+    t.format(TIME_FORMAT).to_string()
+}
+
+// 断点续传记录表示的区间："起..止"，或旧版本只有起点的记录（1 小时）；不是分段记录时为 None
+pub fn parse(record: &str) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    match record.split_once("..") {
+        Some((a, b)) => {
+            let (a, b) = (time(a)?, time(b)?);
+            (b > a).then_some((a, b))
+        }
+        None => time(record).map(|a| (a, a + Duration::hours(1))),
+    }
+}
+
+// 分段名表示的区间：起点（按当前分段大小）或 "起..止"
+pub fn span(seg: &str) -> Option<(NaiveDateTime, NaiveDateTime)> {
+    match seg.split_once("..") {
+        Some(_) => parse(seg),
+        None => time(seg).map(|a| (a, a + size())),
+    }
+}
+
+// 分段的起止时间
+pub fn bounds(seg: &str) -> (String, String) {
+    let (a, b) = span(seg).unwrap_or_else(|| panic!("分段格式不正确: {}", seg));
+    (fmt(a), fmt(b))
+}
+
+// 分段完成时写入断点续传文件的区间记录
+pub fn record(seg: &str) -> String {
+    let (a, b) = bounds(seg);
+    format!("{}..{}", a, b)
+}
+
+// 断点续传记录中已完成区间的并集，带前缀的其他记录忽略
+pub fn covered<'a>(records: impl IntoIterator<Item = &'a str>) -> IntervalSet<NaiveDateTime> {
+    IntervalSet::new(records.into_iter().filter_map(parse))
+}
@@ -0,0 +1,349 @@
+// ===================== 常驻服务模式（datacp serve） =====================
+// 数据平台服务内嵌 datacp 时不再每次迁移启动一个进程：datacp serve --listen 127.0.0.1:9200 在本地 HTTP 接口上接收迁移任务，
+// 同一进程内运行，各任务按端点复用共享连接池（endpoint::Clients）与全局写入并发上限。接口（JSON）：
+//   POST /jobs               提交任务，返回任务 ID；请求体为 {"job_id"?, "source"?, "destination"?, "table": {...}}，
+//                            source / destination 与 --config 的 [source] / [destination] 块相同，缺少时沿用命令行 DSN，
+//                            table 与 --tables-file 的 [[tables]] 条目相同（未填写的字段沿用命令行参数）
+//   GET  /jobs               列出全部任务
+//   GET  /jobs/{id}          任务阶段（queued / running / cutover / ok / failed / cancelled）、已完成分段数、结束后的运行报告
+//   POST /jobs/{id}/cancel   取消排队中的任务，或中止运行中的任务（worker 做完手上的分段后退出）；切换开始后不能取消
+// 最多同时运行 --max-concurrent-jobs 个任务，其余排队。指定 --token 时请求须带 Authorization: Bearer <token>。
+// 各任务的运行文件写在 <state-dir>/jobs/<任务 ID>/ 下，断点续传照常记录；队列不持久化，进程重启后以相同 job_id 重新提交即从断点继续
+// （含未完成的切换）
+
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Semaphore;
+
+use crate::endpoint::{self, Endpoint};
+use crate::multi::{self, TableEntry};
+use crate::report::{self, RunReport};
+use crate::{cutover_state, load_done_segments, priority, restore_paused, run_migration, segment, state_dir, Opt};
+
+// 请求体上限
+const MAX_BODY: usize = 1 << 20;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobSpec {
+    #[serde(default)]
+    job_id: Option<String>, // 以相同 ID 重新提交时沿用同一运行目录，从断点继续
+    #[serde(default)]
+    source: Option<Endpoint>,
+    #[serde(default)]
+    destination: Option<Endpoint>,
+    table: TableEntry,
+}
+
+struct Job {
+    id: String,
+    opt: Opt,
+    done_segments_file: String,
+    state: Mutex<String>, // queued / running / ok / failed / cancelled
+    submitted_at: String,
+    started_at: Mutex<Option<String>>,
+    report: Arc<Mutex<RunReport>>,
+    abort: Mutex<Option<tokio::task::AbortHandle>>,
+}
+
+struct Server {
+    opt: Opt,
+    token: String,
+    jobs: Mutex<BTreeMap<String, Arc<Job>>>,
+    slots: Arc<Semaphore>,
+    insert_permits: Arc<Semaphore>,
+    seq: AtomicU64,
+}
+
+impl Job {
+    fn finished(&self) -> bool {
+        matches!(self.state.lock().unwrap().as_str(), "ok" | "failed" | "cancelled")
+    }
+
+    fn to_json(&self, detail: bool) -> Value {
+        let state = self.state.lock().unwrap().clone();
+        let r = self.report.lock().unwrap();
+        let phase = if state == "running" && r.cutover == "started" { "cutover".to_string() } else { state.clone() };
+        let mut v = json!({
+            "id": self.id,
+            "phase": phase,
+            "src": format!("{}.{}", self.opt.src_db, self.opt.src_table),
+            "dst": format!("{}.{}", self.opt.dst_db, self.opt.dst_table),
+            "submitted_at": self.submitted_at,
+            "started_at": *self.started_at.lock().unwrap(),
+            "finished_at": if r.finished_at.is_empty() { Value::Null } else { json!(r.finished_at) },
+            "error": r.error,
+        });
+        if detail {
+            let done = load_done_segments(&self.done_segments_file)
+                .map(|d| d.iter().filter(|l| segment::parse(l).is_some()).count())
+                .unwrap_or(0);
+            v["progress"] = json!({ "segments_done": done });
+            if self.finished() {
+                v["summary"] = serde_json::to_value(&*r).unwrap_or(Value::Null);
+            }
+        }
+        v
+    }
+
+    // 结束任务：记录结果并写入任务目录下的报告
+    fn close(&self, state: &str, res: &anyhow::Result<()>) {
+        *self.state.lock().unwrap() = state.to_string();
+        let mut r = self.report.lock().unwrap();
+        r.finish(res);
+        if let Err(e) = r.write(&self.opt.report_file) {
+            error!("任务 {} 写入报告失败: {e}", self.id);
+        }
+        info!("任务 {} 结束: {}，分段完成 {}，写入 {} 行", self.id, state, r.segments_done, r.rows_written);
+    }
+}
+
+// 任务 ID 用作 jobs/ 下的目录名，只允许字母、数字与 _-（不允许 .，避免 . 与 .. 指向 jobs 目录之外）
+fn valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_alphanumeric() || "_-".contains(c))
+}
+
+impl Server {
+    // 由请求体生成任务参数：表参数同 --tables-file 条目，两端连接同 --config 块，运行文件位于 jobs/<ID>/
+    fn create(&self, body: &str) -> Result<Arc<Job>, (u16, String)> {
+        let spec: JobSpec = serde_json::from_str(body).map_err(|e| (400, format!("任务格式不正确: {}", e)))?;
+        let id = match spec.job_id {
+            Some(id) if !valid_id(&id) => return Err((400, format!("job_id 只能包含字母、数字与 _-: {}", id))),
+            Some(id) => id,
+            None => format!("{}-{}", chrono::Local::now().format("%Y%m%d%H%M%S"), self.seq.fetch_add(1, Ordering::SeqCst)),
+        };
+        let mut t = multi::table_opt(&self.opt, &spec.table);
+        t.cmd = None;
+        let dir = Path::new(&self.opt.state_dir).join("jobs").join(&id);
+        t.state_dir = dir.to_string_lossy().to_string();
+        t.done_segments = String::new();
+        t.report_file = state_dir::resolve(&dir, "report.json");
+        if t.time_field.is_empty() {
+            return Err((400, "table.time_field 不能为空（命令行也未指定 --time-field）".to_string()));
+        }
+        endpoint::apply_blocks(&mut t, spec.source.as_ref(), spec.destination.as_ref(), &format!("任务 {}", id)).map_err(|e| (400, e.to_string()))?;
+        priority::apply(&mut t).map_err(|e| (400, e.to_string()))?;
+        let done_segments_file = state_dir::done_segments(&t).map_err(|e| (500, e.to_string()))?;
+        Ok(Arc::new(Job {
+            id,
+            report: Arc::new(Mutex::new(RunReport::new(&t))),
+            opt: t,
+            done_segments_file,
+            state: Mutex::new("queued".to_string()),
+            submitted_at: report::now_str(),
+            started_at: Mutex::new(None),
+            abort: Mutex::new(None),
+        }))
+    }
+
+    // 同一 ID 的任务正在排队或运行时拒绝；检查与登记在同一把锁内，并发提交相同 ID 时只有一个成功
+    fn submit(self: &Arc<Self>, job: Arc<Job>) -> Result<(), (u16, String)> {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            if jobs.get(&job.id).is_some_and(|j| !j.finished()) {
+                return Err((409, format!("任务 {} 正在排队或运行", job.id)));
+            }
+            jobs.insert(job.id.clone(), job.clone());
+        }
+        info!("任务 {} 已提交: {}.{} -> {}.{}", job.id, job.opt.src_db, job.opt.src_table, job.opt.dst_db, job.opt.dst_table);
+        let (slots, permits) = (self.slots.clone(), self.insert_permits.clone());
+        let j = job.clone();
+        let handle = tokio::spawn(async move {
+            let Ok(_slot) = slots.acquire_owned().await else { return };
+            *j.state.lock().unwrap() = "running".to_string();
+            *j.started_at.lock().unwrap() = Some(report::now_str());
+            info!("任务 {} 开始运行", j.id);
+            let res = execute(&j, permits).await;
+            if let Err(e) = &res {
+                error!("任务 {} 失败: {e}", j.id);
+            }
+            j.close(if res.is_ok() { "ok" } else { "failed" }, &res);
+        });
+        *job.abort.lock().unwrap() = Some(handle.abort_handle());
+        Ok(())
+    }
+
+    fn cancel(&self, id: &str) -> (u16, Value) {
+        let Some(job) = self.jobs.lock().unwrap().get(id).cloned() else {
+            return (404, json!({ "error": "job not found" }));
+        };
+        if job.finished() {
+            return (409, json!({ "error": "job already finished", "phase": *job.state.lock().unwrap() }));
+        }
+        // rename 开始后中止会留下半完成的切换，只能等待完成（或之后以相同 job_id 重新提交继续）
+        if job.report.lock().unwrap().cutover == "started" {
+            return (409, json!({ "error": "cutover already started" }));
+        }
+        if let Some(h) = job.abort.lock().unwrap().take() {
+            h.abort();
+        }
+        job.close("cancelled", &Err(anyhow::anyhow!("cancelled")));
+        warn!("任务 {} 已取消", id);
+        (202, json!({ "accepted": "cancel", "id": id }))
+    }
+}
+
+// 与单表运行相同：有未完成的切换时从切换状态继续
+async fn execute(job: &Job, insert_permits: Arc<Semaphore>) -> anyhow::Result<()> {
+    let (t, report) = (&job.opt, job.report.clone());
+    priority::check(t).await?;
+    let res = match cutover_state::pending(t)? {
+        Some(mut s) if s.done(cutover_state::Step::BakFilled) => {
+            report.lock().unwrap().cutover = "started".to_string();
+            s.finish(t, &report, &job.done_segments_file).await
+        }
+        Some(s) => run_migration(&s.run_opt(t), &job.done_segments_file, report, insert_permits, Some(s)).await,
+        None => run_migration(t, &job.done_segments_file, report, insert_permits, None).await,
+    };
+    // 结束（含失败）时恢复本任务暂停的物化视图与目标表合并
+    for e in restore_paused(t, &job.done_segments_file).await {
+        error!("{e}");
+    }
+    res
+}
+
+pub async fn run(opt: &Opt, listen: &str, max_concurrent_jobs: usize, token: &str, insert_permits: Arc<Semaphore>) -> anyhow::Result<()> {
+    let addr = if listen.starts_with(':') { format!("127.0.0.1{}", listen) } else { listen.to_string() };
+    let listener = tokio::net::TcpListener::bind(&addr).await.map_err(|e| anyhow::anyhow!(format!("serve 监听 {} 失败: {}", addr, e)))?;
+    if token.is_empty() && !addr.starts_with("127.") && !addr.starts_with("localhost") && !addr.starts_with("[::1]") {
+        warn!("serve 监听 {} 且未指定 --token，任何能访问该地址的人都可以提交迁移任务", addr);
+    }
+    info!("datacp serve: http://{}/jobs，最多同时运行 {} 个任务", addr, max_concurrent_jobs.max(1));
+    let srv = Arc::new(Server {
+        opt: opt.clone(),
+        token: token.to_string(),
+        jobs: Mutex::new(BTreeMap::new()),
+        slots: Arc::new(Semaphore::new(max_concurrent_jobs.max(1))),
+        insert_permits,
+        seq: AtomicU64::new(0),
+    });
+    loop {
+        if let Ok((sock, _)) = listener.accept().await {
+            tokio::spawn(handle(srv.clone(), sock));
+        }
+    }
+}
+
+// 读取一个请求：请求行、头部与 Content-Length 指定长度的请求体
+async fn read_request(sock: &mut tokio::net::TcpStream) -> Option<(String, String)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = tokio::time::timeout(Duration::from_secs(5), sock.read(&mut chunk)).await.ok()?.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&buf[..end]).to_string();
+            let len = head
+                .lines()
+                .find_map(|l| l.split_once(':').filter(|(k, _)| k.eq_ignore_ascii_case("content-length")).map(|(_, v)| v.trim().parse::<usize>().unwrap_or(0)))
+                .unwrap_or(0);
+            if len > MAX_BODY {
+                return None;
+            }
+            if buf.len() >= end + 4 + len {
+                return Some((head, String::from_utf8_lossy(&buf[end + 4..end + 4 + len]).to_string()));
+            }
+        }
+        if buf.len() > MAX_BODY {
+            return None;
+        }
+    }
+}
+
+// 每个连接只处理一个请求，响应后关闭
+async fn handle(srv: Arc<Server>, mut sock: tokio::net::TcpStream) {
+    let Some((head, body)) = read_request(&mut sock).await else { return };
+    let mut parts = head.lines().next().unwrap_or("").split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = path.split('?').next().unwrap_or("").trim_end_matches('/');
+    let authorized = srv.token.is_empty()
+        || head.lines().any(|l| {
+            l.split_once(':')
+                .is_some_and(|(k, v)| k.eq_ignore_ascii_case("authorization") && v.trim().strip_prefix("Bearer ") == Some(srv.token.as_str()))
+        });
+    let segs: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    let (code, body) = match (method, segs.as_slice()) {
+        _ if !authorized => (401, json!({ "error": "unauthorized" })),
+        ("POST", ["jobs"]) => match srv.create(&body).and_then(|job| srv.submit(job.clone()).map(|()| job)) {
+            Ok(job) => (201, job.to_json(false)),
+            Err((code, e)) => (code, json!({ "error": e })),
+        },
+        ("GET", ["jobs"]) => (200, Value::Array(srv.jobs.lock().unwrap().values().map(|j| j.to_json(false)).collect())),
+        ("GET", ["jobs", id]) => match srv.jobs.lock().unwrap().get(*id) {
+            Some(j) => (200, j.to_json(true)),
+            None => (404, json!({ "error": "job not found" })),
+        },
+        ("POST", ["jobs", id, "cancel"]) => srv.cancel(id),
+        _ => (404, json!({ "error": "not found" })),
+    };
+    let reason = match code {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        409 => "Conflict",
+        500 => "Internal Server Error",
+        _ => "Not Found",
+    };
+    let body = body.to_string();
+    let resp = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        body.len(),
+        body
+    );
+    let _ = sock.write_all(resp.as_bytes()).await;
+    let _ = sock.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    #[test]
+    fn job_ids_stay_inside_the_jobs_directory() {
+        for id in ["nightly", "events-2024_01", "20240101120000-3"] {
+            assert!(valid_id(id), "{}", id);
+        }
+        for id in ["", ".", "..", "...", "a.b", "../x", "a/b", "a b"] {
+            assert!(!valid_id(id), "{}", id);
+        }
+    }
+
+    // 没有运行槽位时任务一直排队：相同 ID 的第二次提交被拒绝，结束后可以再次提交
+    #[tokio::test]
+    async fn duplicate_job_id_is_rejected_at_submit() {
+        let dir = std::env::temp_dir().join(format!("datacp_serve_{}", std::process::id()));
+        let state_dir = dir.to_string_lossy().to_string();
+        let srv = Arc::new(Server {
+            opt: Opt::from_iter(["datacp", "--time-field", "ts", "--state-dir", &state_dir]),
+            token: String::new(),
+            jobs: Mutex::new(BTreeMap::new()),
+            slots: Arc::new(Semaphore::new(0)),
+            insert_permits: Arc::new(Semaphore::new(1)),
+            seq: AtomicU64::new(0),
+        });
+        let body = r#"{"job_id":"nightly","table":{"src_table":"events"}}"#;
+        let (first, second) = (srv.create(body).unwrap(), srv.create(body).unwrap());
+        assert!(srv.submit(first).is_ok());
+        assert_eq!(srv.submit(second).map_err(|(code, _)| code), Err(409));
+        assert_eq!(srv.cancel("nightly").0, 202);
+        assert!(srv.submit(srv.create(body).unwrap()).is_ok());
+        srv.cancel("nightly");
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(srv.create(r#"{"job_id":"..","table":{"src_table":"events"}}"#).err().map(|(code, _)| code), Some(400));
+    }
+}
@@ -0,0 +1,275 @@
+// ===================== 服务端拷贝（--copy-mode remote-secure / attach-partition） =====================
+// 在目标库执行 INSERT INTO dst SELECT ... FROM remoteSecure(...)，数据不经过 datacp，
+// datacp 只负责分段编排、断点续传与计数校验。源为分布式表时按分片展开为多个 remoteSecure 读取。
+
+use log::{error, info, warn};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::report::{PartitionAttach, RunReport};
+use crate::{ch_query_rows, endpoint, health, json_u64, parse_clickhouse_dsn, qualified, save_done_segment, shard, sql_log, time_zone, Opt};
+
+// remoteSecure 读取端
+#[derive(Debug)]
+pub struct RemoteSource {
+    pub shards: Vec<String>,                  // 每个分片的地址，副本间以 | 分隔，如 'h1:9440|h2:9440'
+    pub local: Option<(String, String)>,      // 源为分布式表时各分片读取的本地表 (db, table)
+    pub user: String,
+    pub password: String,
+}
+
+// 解析源端读取地址：分布式表按 system.clusters 展开分片，否则使用 DSN 主机
+pub async fn resolve_remote_source(opt: &Opt) -> anyhow::Result<RemoteSource> {
+    let (_, user, password, _) = parse_clickhouse_dsn(&opt.src_dsn, &opt.src_db)?;
+    let (engine, engine_full) = shard::table_engine(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    let port = opt.src_secure_port;
+    if engine == "Distributed" {
+        let (cluster, local_db, local_table, _) = shard::parse_distributed_engine(&engine_full)?;
+        let local_db = if local_db.is_empty() { opt.src_db.clone() } else { local_db };
+        let shards = shard::cluster_shard_hosts(&opt.src_dsn, &opt.src_db, &cluster)
+            .await?
+            .into_iter()
+            .map(|(_, hosts)| hosts.iter().map(|h| format!("{}:{}", h, port)).collect::<Vec<_>>().join("|"))
+            .collect::<Vec<_>>();
+        info!("remote-secure: 源表为分布式表, 集群 {} 共 {} 个分片, 读取本地表 {}.{}", cluster, shards.len(), local_db, local_table);
+        Ok(RemoteSource { shards, local: Some((local_db, local_table)), user, password })
+    } else {
+        let host = format!("{}:{}", shard::dsn_host(&opt.src_dsn), port);
+        Ok(RemoteSource { shards: vec![host], local: None, user, password })
+    }
+}
+
+// 对日志中的 SQL 脱敏：remote()/remoteSecure() 内联的用户名密码替换为 ***
+pub fn redact_sql(sql: &str) -> String {
+    let re = regex::Regex::new(
+        r"(?i)(remote(?:Secure)?\s*\(\s*'[^']*'\s*,\s*'[^']*'\s*,\s*'[^']*'\s*,\s*)'(?:[^'\\]|\\.)*'\s*,\s*'(?:[^'\\]|\\.)*'",
+    )
+    .unwrap();
+    re.replace_all(sql, "$1'***', '***'").to_string()
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+async fn count_rows(dsn: &str, db: &str, table: &str, where_sql: &str) -> anyhow::Result<u64> {
+    let sql = format!("SELECT count() AS c FROM {} WHERE {} FORMAT JSONEachRow", qualified(db, table), where_sql);
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    Ok(json_u64(rows.first().and_then(|r| r.get("c"))))
+}
+
+// 单次执行（不重试）：INSERT ... SELECT 失败后由下一次运行按差集重做，避免重复写入
+async fn execute_once(dsn: &str, db: &str, sql: &str, timeout: Duration) -> anyhow::Result<()> {
+    let (url, user, pass, _) = parse_clickhouse_dsn(dsn, db)?;
+    let client = endpoint::builder(dsn).timeout(timeout).build()?;
+    let stmt = sql_log::begin();
+    let resp = client.post(&url).basic_auth(&user, Some(&pass)).query(&stmt.params()).body(sql.to_string()).send().await
+        .map_err(|e| {
+            stmt.end(dsn, sql, &format!("连接失败: {}", e), None);
+            health::connection_error(dsn);
+            anyhow::anyhow!(format!("ClickHouse HTTP 连接失败: {}", e))
+        })?;
+    let status = resp.status();
+    let text = resp.text().await?;
+    if !status.is_success() {
+        stmt.end(dsn, sql, &format!("{} {}", status, text), None);
+        anyhow::bail!(format!("ClickHouse HTTP 错误: {} {}", status, redact_sql(&text)));
+    }
+    stmt.end(dsn, sql, "ok", None);
+    Ok(())
+}
+
+// 服务端拷贝一个分段，成功返回本次写入行数（按写入前后目标端计数之差）
+#[allow(clippy::too_many_arguments)]
+pub async fn copy_segment_remote(
+    remote: &RemoteSource,
+    opt_timeout: Duration,
+    src_dsn: &str,
+    src_db: &str,
+    src_table: &str,
+    dst_dsn: &str,
+    dst_db: &str,
+    dst_table: &str,
+    dst_read_table: &str,
+    time_field: &str,
+    col_names: &[String],
+    seg: &str,
+    seg_end: &str,
+    filter: &str,
+    tz: Option<&time_zone::ColumnTz>,
+) -> anyhow::Result<u64> {
+    let window = format!("{} >= {} AND {} < {}{}", time_field, time_zone::lit(tz, seg), time_field, time_zone::lit(tz, seg_end), filter);
+    let src_count = count_rows(src_dsn, src_db, src_table, &window).await?;
+    let dst_before = count_rows(dst_dsn, dst_db, dst_read_table, &window).await?;
+    if dst_before >= src_count {
+        info!("segment {seg} remote: 目标端已有 {} 行 (源 {} 行)，跳过", dst_before, src_count);
+        return Ok(0);
+    }
+    let cols = col_names.join(",");
+    // 目标端已有部分数据时按整行 cityHash64 在服务端做差集，避免重复写入
+    let diff = if dst_before > 0 {
+        format!(
+            " AND cityHash64({}) NOT IN (SELECT cityHash64({}) FROM {}.{} WHERE {})",
+            cols, cols, dst_db, dst_read_table, window
+        )
+    } else {
+        String::new()
+    };
+    for addr in &remote.shards {
+        let (db, table) = match &remote.local {
+            Some((d, t)) => (d.as_str(), t.as_str()),
+            None => (src_db, src_table),
+        };
+        let sql = format!(
+            "INSERT INTO {}.{} ({}) SELECT {} FROM remoteSecure({}, {}, {}, {}, {}) WHERE {}{}",
+            dst_db, dst_table, cols, cols,
+            quote(addr), quote(db), quote(table), quote(&remote.user), quote(&remote.password),
+            window, diff
+        );
+        info!("segment {seg} remote SQL: {}", redact_sql(&sql));
+        if let Err(e) = execute_once(dst_dsn, dst_db, &sql, opt_timeout).await {
+            error!("segment {seg} remote copy from {} failed: {e}", addr);
+            return Err(e);
+        }
+    }
+    let dst_after = count_rows(dst_dsn, dst_db, dst_read_table, &window).await?;
+    if dst_after < src_count {
+        anyhow::bail!(format!("segment {} 计数校验失败: 源 {} 行, 目标 {} 行", seg, src_count, dst_after));
+    }
+    if dst_after > src_count {
+        warn!("segment {seg} 目标端行数 {} 多于源端 {}", dst_after, src_count);
+    }
+    info!("segment {seg} remote copy done, src_rows={}, dst_rows={}", src_count, dst_after);
+    Ok(dst_after.saturating_sub(dst_before))
+}
+
+// ===================== 同实例分区挂载（--copy-mode attach-partition） =====================
+// 源表与目标表位于同一实例时，按分区执行 ALTER TABLE dst ATTACH PARTITION ID '...' FROM src，
+// 只做元数据操作（硬链接），每个分区挂载后校验计数并写入断点续传记录。
+// 有分区挂载失败、因目标端已有数据被跳过或计数不符时，处理完其余分区后迁移失败，不进入切换。
+
+async fn server_identity(dsn: &str, db: &str) -> anyhow::Result<String> {
+    let rows = ch_query_rows(dsn, db, "SELECT hostName() AS h, toString(tcpPort()) AS p FORMAT JSONEachRow").await?;
+    let r = rows.first().ok_or_else(|| anyhow::anyhow!("无法获取服务器标识"))?;
+    Ok(format!(
+        "{}:{}",
+        r.get("h").and_then(|v| v.as_str()).unwrap_or(""),
+        r.get("p").and_then(|v| v.as_str()).unwrap_or("")
+    ))
+}
+
+pub async fn table_keys(dsn: &str, db: &str, table: &str) -> anyhow::Result<HashMap<String, String>> {
+    let sql = format!(
+        "SELECT engine, partition_key, sorting_key, primary_key FROM system.tables WHERE database = '{}' AND name = '{}' FORMAT JSONEachRow",
+        db, table
+    );
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    let r = rows.into_iter().next().ok_or_else(|| anyhow::anyhow!(format!("表 {}.{} 不存在", db, table)))?;
+    Ok(r.into_iter().map(|(k, v)| (k, v.as_str().unwrap_or("").to_string())).collect())
+}
+
+async fn partition_rows(dsn: &str, db: &str, table: &str) -> anyhow::Result<Vec<(String, u64)>> {
+    let sql = format!(
+        "SELECT partition_id, sum(rows) AS rows FROM system.parts WHERE database = '{}' AND table = '{}' AND active \
+         GROUP BY partition_id ORDER BY partition_id FORMAT JSONEachRow",
+        db, table
+    );
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    Ok(rows
+        .iter()
+        .map(|r| (r.get("partition_id").and_then(|v| v.as_str()).unwrap_or("").to_string(), json_u64(r.get("rows"))))
+        .collect())
+}
+
+// 按分区挂载源表数据到目标表，已完成分区以 partition:<id> 形式记录在断点续传文件中
+pub async fn attach_partitions(
+    opt: &Opt,
+    done_segments_file: &str,
+    done: &HashSet<String>,
+    report: &Arc<Mutex<RunReport>>,
+) -> anyhow::Result<()> {
+    let src_id = server_identity(&opt.src_dsn, &opt.src_db).await?;
+    let dst_id = server_identity(&opt.dst_dsn, &opt.dst_db).await?;
+    if src_id != dst_id {
+        anyhow::bail!(format!(
+            "--copy-mode attach-partition 要求源表与目标表在同一实例: 源 {} 目标 {}，请改用 http 或 remote-secure",
+            src_id, dst_id
+        ));
+    }
+    let sk = table_keys(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+    let dk = table_keys(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+    for key in ["partition_key", "sorting_key", "primary_key"] {
+        if sk.get(key) != dk.get(key) {
+            anyhow::bail!(format!(
+                "源表与目标表 {} 不一致，无法 ATTACH PARTITION: 源 [{}] 目标 [{}]",
+                key,
+                sk.get(key).map(|s| s.as_str()).unwrap_or(""),
+                dk.get(key).map(|s| s.as_str()).unwrap_or("")
+            ));
+        }
+    }
+    let engine_ok = |e: Option<&String>| e.map(|e| e.contains("MergeTree")).unwrap_or(false);
+    if !engine_ok(sk.get("engine")) || !engine_ok(dk.get("engine")) {
+        anyhow::bail!("--copy-mode attach-partition 仅支持 MergeTree 系列引擎");
+    }
+    let dst_parts: HashMap<String, u64> = partition_rows(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?.into_iter().collect();
+    // 挂载失败、跳过或计数不符的分区：全部处理完后拒绝继续，避免缺数据时进入切换
+    let mut failed = Vec::new();
+    for (pid, src_rows) in partition_rows(&opt.src_dsn, &opt.src_db, &opt.src_table).await? {
+        let key = format!("partition:{}", pid);
+        if done.contains(&key) {
+            continue;
+        }
+        let dst_existing = dst_parts.get(&pid).copied().unwrap_or(0);
+        let (dst_rows, status) = if dst_existing > 0 && dst_existing != src_rows {
+            // 目标分区已有数据，再次挂载会产生重复，需人工清理目标分区后重跑
+            warn!("partition {pid} 目标端已有 {} 行(源 {} 行)，跳过挂载", dst_existing, src_rows);
+            (dst_existing, "skipped".to_string())
+        } else {
+            let mut attach_error = None;
+            if dst_existing == 0 {
+                let sql = format!(
+                    "ALTER TABLE {}.{} ATTACH PARTITION ID '{}' FROM {}.{}",
+                    opt.dst_db, opt.read_table(), pid, opt.src_db, opt.src_table
+                );
+                info!("partition {pid} attach SQL: {sql}");
+                let started = std::time::Instant::now();
+                match execute_once(&opt.dst_dsn, &opt.dst_db, &sql, opt.remote_query_timeout).await {
+                    Ok(()) => info!("partition {pid} attach 耗时 {:?}", started.elapsed()),
+                    Err(e) => {
+                        error!("partition {pid} attach failed: {e}");
+                        attach_error = Some(e);
+                    }
+                }
+            }
+            if let Some(e) = attach_error {
+                (0, format!("failed: {}", e))
+            } else {
+                let dst_rows = count_rows(&opt.dst_dsn, &opt.dst_db, opt.read_table(), &format!("_partition_id = '{}'", pid)).await?;
+                let src_now = count_rows(&opt.src_dsn, &opt.src_db, &opt.src_table, &format!("_partition_id = '{}'", pid)).await?;
+                if dst_rows == src_now {
+                    if let Err(e) = save_done_segment(done_segments_file, &key) {
+                        error!("save_done_segment failed: {e}");
+                    }
+                    (dst_rows, "attached".to_string())
+                } else {
+                    error!("partition {pid} 计数校验失败: 源 {} 行, 目标 {} 行", src_now, dst_rows);
+                    (dst_rows, format!("count-mismatch: src={} dst={}", src_now, dst_rows))
+                }
+            }
+        };
+        if status != "attached" {
+            failed.push(format!("{} ({})", pid, status));
+        }
+        report.lock().unwrap().partitions_attached.push(PartitionAttach { partition_id: pid, src_rows, dst_rows, status });
+    }
+    if !failed.is_empty() {
+        anyhow::bail!(format!(
+            "{} 个分区未完成挂载，停止迁移且不切换（已完成的分区已记录，处理后重跑即可）: {}",
+            failed.len(),
+            failed.join("; ")
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+// ===================== 服务端计算行摘要（--dst-digest server） =====================
+// 默认目标端读取分段内所有比对字段的完整行，只为在客户端计算摘要。--dst-digest server 时目标端只返回每行的
+//   hex(SHA256(concatWithSeparator('\x01', ifNull(toString(c1), '\x02'), ...)))
+// （c1... 为摘要字段按列名排序，二进制列与 --column-default 覆盖的列使用与读取相同的取值表达式），
+// 每行约 70 字节，与行宽无关。
+// 客户端以 JSON 文本计算的 DIGEST_VERSION 摘要无法逐一复现 toString 的格式（数组内字符串的引号、浮点数、Bool 等），
+// 因此源端读取时在 SELECT 中附带同一表达式，两端摘要都由服务端按相同的 toString 语义算出，取出后从行中移除，不写入目标端。
+// 表达式无法表示的列类型（Nested、Object/JSON、AggregateFunction）与需要目标端完整行的 --mirror 回退为客户端摘要，按表记录原因
+
+use log::{info, warn};
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::binary::BinaryColumns;
+use crate::{qualified, Opt};
+
+// 源端与目标端 SELECT 中摘要列的别名
+pub const DIGEST_COLUMN: &str = "__datacp_digest";
+
+pub struct ServerDigest {
+    expr: String,
+}
+
+// 表达式不能表示的列类型，返回原因
+fn unsupported(column: &str, t: &str) -> Option<&'static str> {
+    if t.contains("Nested(") || (column.contains('.') && t.starts_with("Array(")) {
+        Some("Nested 列")
+    } else if t.contains("Object(") || t.starts_with("JSON") {
+        Some("Object/JSON 列")
+    } else if t.contains("AggregateFunction(") {
+        Some("AggregateFunction 列")
+    } else {
+        None
+    }
+}
+
+impl ServerDigest {
+    // 未启用或回退为客户端摘要时返回 None；digest 为摘要字段（按列名排序）
+    pub fn plan(opt: &Opt, binary: &BinaryColumns, digest: &[String]) -> anyhow::Result<Option<Self>> {
+        match opt.dst_digest.as_str() {
+            "client" => return Ok(None),
+            "server" => {}
+            m => anyhow::bail!(format!("--dst-digest 只支持 client|server: {}", m)),
+        }
+        let table = qualified(&opt.dst_db, &opt.dst_table);
+        let fallback = |reason: String| {
+            warn!("{}: --dst-digest server 回退为客户端摘要（{}）", table, reason);
+            Ok(None)
+        };
+        if opt.mirror {
+            return fallback("--mirror 需要目标端完整行".to_string());
+        }
+        if digest.is_empty() {
+            return fallback("没有参与比对的字段".to_string());
+        }
+        for c in digest {
+            if let Some(reason) = unsupported(c, binary.type_of(c).unwrap_or("")) {
+                return fallback(format!("{} 为{}", c, reason));
+            }
+        }
+        let parts: Vec<String> = binary.value_exprs(digest).iter().map(|e| format!("ifNull(toString({}), '\\x02')", e)).collect();
+        info!("{}: 目标端摘要由服务端计算（--dst-digest server），{} 个摘要字段", table, digest.len());
+        Ok(Some(ServerDigest { expr: format!("hex(SHA256(concatWithSeparator('\\x01', {})))", parts.join(", ")) }))
+    }
+
+    // SELECT 中的摘要列
+    pub fn select(&self) -> String {
+        format!("{} AS {}", self.expr, DIGEST_COLUMN)
+    }
+}
+
+// 取出各行的服务端摘要并从行中移除
+pub fn take(rows: &mut [HashMap<String, Value>]) -> anyhow::Result<Vec<[u8; 32]>> {
+    rows.iter_mut()
+        .map(|r| {
+            let hex = r.remove(DIGEST_COLUMN).and_then(|v| v.as_str().map(|s| s.to_string())).unwrap_or_default();
+            let mut d = [0u8; 32];
+            if hex.len() != 64 || !hex.is_ascii() {
+                anyhow::bail!(format!("服务端摘要 {} 格式不正确: {:?}", DIGEST_COLUMN, hex));
+            }
+            for (i, b) in d.iter_mut().enumerate() {
+                *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| anyhow::anyhow!(format!("服务端摘要不是十六进制: {}", hex)))?;
+            }
+            Ok(d)
+        })
+        .collect()
+}
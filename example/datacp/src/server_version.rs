@@ -0,0 +1,226 @@
+// ===================== 服务端版本与功能检测 =====================
+// 两端可能是不同大版本的 ClickHouse（如 21.8 与 23.x），部分语句在旧版本上不存在。启动时两端各查询一次 SELECT version()，
+// 解析为可比较的版本号，按 CAPABILITIES 得到各功能是否可用：
+//   - 可选的代码路径按功能选择（如 --auto-rollback 回滚时同库且支持 EXCHANGE TABLES 则原子交换，否则两次 RENAME；
+//     目标端支持 insert_deduplication_token 时写入重试带同一去重令牌）
+//   - 明确指定的参数依赖的功能不可用时启动即拒绝（--workload、--fix-overcopy），不在迁移或切换中途才失败
+// 版本无法查询或解析时按未知处理：不拒绝运行，可选功能一律不用。检测结果与各功能的取舍写入报告与运行清单
+
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::report::ServerVersion;
+use crate::{ch_query_rows, sql_log, Opt};
+
+// 功能与最低版本（major, minor）
+const CAPABILITIES: [(&str, u32, u32); 4] = [
+    ("deduplicate_by", 21, 1),  // OPTIMIZE ... DEDUPLICATE BY（--fix-overcopy）
+    ("exchange_tables", 21, 4), // EXCHANGE TABLES（--auto-rollback 原子回滚）
+    ("dedup_token", 22, 2),     // insert_deduplication_token（写入重试去重）
+    ("workloads", 24, 10),      // system.workloads 与 workload 设置（--workload）
+];
+
+// 各端点（host:port）检测到的版本，None 为无法查询或解析
+static DETECTED: Mutex<Option<HashMap<String, Option<Version>>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub build: u32,
+}
+
+impl Version {
+    // 23.8.2.7、21.8.15.7-lts、22.3.12.19.altinitystable：取前面的数字段，至少需要 major.minor
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = Vec::new();
+        for p in s.trim().split(['.', '-']) {
+            match p.parse::<u32>() {
+                Ok(n) if parts.len() < 4 => parts.push(n),
+                _ => break,
+            }
+        }
+        if parts.len() < 2 {
+            return None;
+        }
+        parts.resize(4, 0);
+        Some(Version { major: parts[0], minor: parts[1], patch: parts[2], build: parts[3] })
+    }
+
+    fn at_least(&self, major: u32, minor: u32) -> bool {
+        (self.major, self.minor) >= (major, minor)
+    }
+
+    fn at_least_cap(&self, cap: &str) -> bool {
+        capabilities(Some(self)).get(cap).copied().unwrap_or(false)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.major, self.minor, self.patch, self.build)
+    }
+}
+
+// 各功能是否可用；版本未知时为空
+pub fn capabilities(v: Option<&Version>) -> BTreeMap<String, bool> {
+    let Some(v) = v else { return BTreeMap::new() };
+    CAPABILITIES.iter().map(|(name, major, minor)| (name.to_string(), v.at_least(*major, *minor))).collect()
+}
+
+fn min_version(cap: &str) -> String {
+    CAPABILITIES.iter().find(|(n, _, _)| *n == cap).map(|(_, a, b)| format!("{}.{}", a, b)).unwrap_or_default()
+}
+
+fn detected(dsn: &str) -> Option<Version> {
+    DETECTED.lock().unwrap().as_ref().and_then(|m| m.get(&sql_log::endpoint(dsn)).copied().flatten())
+}
+
+// 该端点已检测到版本且支持该功能；未检测或版本未知时为 false
+pub fn supports(dsn: &str, cap: &str) -> bool {
+    detected(dsn).is_some_and(|v| v.at_least_cap(cap))
+}
+
+// 明确指定的参数依赖的功能：版本已知且不支持时拒绝
+fn refuse(opt: &Opt, src: Option<&Version>, dst: Option<&Version>) -> anyhow::Result<()> {
+    let mut needs: Vec<(&str, &str, &str, Option<&Version>)> = Vec::new();
+    if !opt.workload.is_empty() {
+        needs.push(("--workload", "workloads", "源端", src));
+        needs.push(("--workload", "workloads", "目标端", dst));
+    }
+    if opt.fix_overcopy {
+        needs.push(("--fix-overcopy", "deduplicate_by", "目标端", dst));
+    }
+    for (flag, cap, side, v) in needs {
+        if let Some(v) = v.filter(|v| !v.at_least_cap(cap)) {
+            anyhow::bail!(format!("{} 需要 ClickHouse {} 以上（{}），{}版本为 {}", flag, min_version(cap), cap, side, v));
+        }
+    }
+    Ok(())
+}
+
+async fn query(dsn: &str) -> Option<Version> {
+    match ch_query_rows(dsn, "system", "SELECT version() AS v FORMAT JSONEachRow").await {
+        Ok(rows) => {
+            let text = rows.first().and_then(|r| r.get("v")).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+            let v = Version::parse(&text);
+            if v.is_none() {
+                warn!("无法解析 {} 的 ClickHouse 版本 \"{}\"，按未知版本处理（不使用可选功能）", sql_log::endpoint(dsn), text);
+            }
+            v
+        }
+        Err(e) => {
+            warn!("查询 {} 的 ClickHouse 版本失败，按未知版本处理（不使用可选功能）: {e}", sql_log::endpoint(dsn));
+            None
+        }
+    }
+}
+
+// 预检：两端各查询一次版本（同一端点只查一次），记录后检查明确指定的参数
+pub async fn check(opt: &Opt) -> anyhow::Result<()> {
+    for dsn in [&opt.src_dsn, &opt.dst_dsn] {
+        let ep = sql_log::endpoint(dsn);
+        if DETECTED.lock().unwrap().as_ref().is_some_and(|m| m.contains_key(&ep)) {
+            continue;
+        }
+        let v = query(dsn).await;
+        DETECTED.lock().unwrap().get_or_insert_with(HashMap::new).insert(ep, v);
+    }
+    for s in report(opt) {
+        let off: Vec<&str> = s.capabilities.iter().filter(|(_, on)| !**on).map(|(n, _)| n.as_str()).collect();
+        info!(
+            "{} ClickHouse 版本 {}{}",
+            s.side,
+            s.version.as_deref().unwrap_or("未知"),
+            if off.is_empty() { String::new() } else { format!("，不支持: {}", off.join(", ")) }
+        );
+    }
+    refuse(opt, detected(&opt.src_dsn).as_ref(), detected(&opt.dst_dsn).as_ref())
+}
+
+// 两端（同一端点时合为一项）的版本与功能取舍
+pub fn report(opt: &Opt) -> Vec<ServerVersion> {
+    if DETECTED.lock().unwrap().is_none() {
+        return Vec::new();
+    }
+    let (src, dst) = (sql_log::endpoint(&opt.src_dsn), sql_log::endpoint(&opt.dst_dsn));
+    let sides = if src == dst { vec![("src/dst", &opt.src_dsn)] } else { vec![("src", &opt.src_dsn), ("dst", &opt.dst_dsn)] };
+    sides
+        .into_iter()
+        .map(|(side, dsn)| {
+            let v = detected(dsn);
+            ServerVersion {
+                side: side.to_string(),
+                endpoint: sql_log::endpoint(dsn),
+                version: v.map(|v| v.to_string()),
+                capabilities: capabilities(v.as_ref()),
+            }
+        })
+        .collect()
+}
+
+// 目标端支持时，一个写入批次的各次重试带同一个 insert_deduplication_token，上次实际已写入的块会被去重
+pub fn dedup_token(dsn: &str) -> Vec<(&'static str, String)> {
+    static SEQ: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    if !supports(dsn, "dedup_token") {
+        return Vec::new();
+    }
+    let seq = SEQ.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    vec![("insert_deduplication_token", format!("datacp-{}-{}-{}", std::process::id(), chrono::Utc::now().timestamp_micros(), seq))]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    fn v(s: &str) -> Version {
+        Version::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parses_release_strings() {
+        assert_eq!(v("23.8.2.7"), Version { major: 23, minor: 8, patch: 2, build: 7 });
+        assert_eq!(v("21.8.15.7-lts"), Version { major: 21, minor: 8, patch: 15, build: 7 });
+        assert_eq!(v("22.3.12.19.altinitystable"), Version { major: 22, minor: 3, patch: 12, build: 19 });
+        assert_eq!(v("24.10"), Version { major: 24, minor: 10, patch: 0, build: 0 });
+        assert!(Version::parse("").is_none());
+        assert!(Version::parse("unknown").is_none());
+        assert!(v("21.10.1.1") > v("21.8.15.7"));
+        assert_eq!(v("23.8.2.7").to_string(), "23.8.2.7");
+    }
+
+    #[test]
+    fn capability_map_by_version() {
+        let on = |s: &str| capabilities(Some(&v(s))).into_iter().filter(|(_, on)| *on).map(|(n, _)| n).collect::<Vec<_>>();
+        assert_eq!(on("20.8.3.18"), Vec::<String>::new());
+        assert_eq!(on("21.3.20.1"), vec!["deduplicate_by"]);
+        assert_eq!(on("21.8.15.7"), vec!["deduplicate_by", "exchange_tables"]);
+        assert_eq!(on("22.2.2.1"), vec!["dedup_token", "deduplicate_by", "exchange_tables"]);
+        assert_eq!(on("23.8.2.7"), vec!["dedup_token", "deduplicate_by", "exchange_tables"]);
+        assert_eq!(on("24.10.1.2812"), vec!["dedup_token", "deduplicate_by", "exchange_tables", "workloads"]);
+        assert!(capabilities(None).is_empty());
+    }
+
+    #[test]
+    fn requested_features_are_refused_on_old_servers() {
+        let opt = |extra: &[&str]| {
+            let args = ["datacp", "--src-dsn", "http://default:@a:8123", "--dst-dsn", "http://default:@b:8123"];
+            Opt::from_iter(args.iter().chain(extra))
+        };
+        let (old, new) = (v("21.8.15.7"), v("24.10.1.2812"));
+        let err = refuse(&opt(&["--workload", "etl"]), Some(&new), Some(&old)).unwrap_err().to_string();
+        assert!(err.contains("--workload") && err.contains("24.10") && err.contains("目标端") && err.contains("21.8.15.7"), "{err}");
+        let err = refuse(&opt(&["--workload", "etl"]), Some(&old), Some(&new)).unwrap_err().to_string();
+        assert!(err.contains("源端"), "{err}");
+        refuse(&opt(&["--workload", "etl"]), Some(&new), Some(&new)).unwrap();
+        // 版本未知时不拒绝
+        refuse(&opt(&["--workload", "etl"]), None, None).unwrap();
+        assert!(refuse(&opt(&["--fix-overcopy"]), Some(&new), Some(&v("20.8.3.18"))).is_err());
+        refuse(&opt(&["--fix-overcopy"]), Some(&v("20.8.3.18")), Some(&old)).unwrap();
+        refuse(&opt(&[]), Some(&v("20.8.3.18")), Some(&v("20.8.3.18"))).unwrap();
+    }
+}
@@ -0,0 +1,530 @@
+// ===================== 分布式目标表本地写入（--dst-write-local） =====================
+// 解析目标 Distributed 表的 cluster / 本地表 / sharding key，
+// 在客户端按 ClickHouse 相同的规则计算每行所属分片，直接写入各分片的本地表。
+
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{ch_query_rows, json_u64};
+
+// 客户端可计算的 sharding 表达式
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShardingExpr {
+    Rand,                      // rand()，客户端按权重轮询
+    Column(String),            // 直接以整数列作为 sharding key
+    CityHash64(String),        // cityHash64(col)
+    Modulo(String, u64),       // col % N 或 modulo(col, N)
+    Unsupported(String),       // 其他表达式，回退到 Distributed 写入
+}
+
+// 解析 Distributed 引擎参数中的 sharding 表达式
+pub fn parse_sharding_expr(expr: &str) -> ShardingExpr {
+    let e = expr.trim();
+    let ident = r"`?([A-Za-z_][A-Za-z0-9_\.]*)`?";
+    if regex::Regex::new(r"^(?i)rand(32|64)?\(\s*\)$").unwrap().is_match(e) {
+        return ShardingExpr::Rand;
+    }
+    let re_city = regex::Regex::new(&format!(r"^(?i)cityHash64\(\s*{}\s*\)$", ident)).unwrap();
+    if let Some(c) = re_city.captures(e) {
+        return ShardingExpr::CityHash64(c[1].to_string());
+    }
+    let re_mod = regex::Regex::new(&format!(r"^{}\s*%\s*(\d+)$", ident)).unwrap();
+    if let Some(c) = re_mod.captures(e) {
+        return ShardingExpr::Modulo(c[1].to_string(), c[2].parse().unwrap_or(0));
+    }
+    let re_modf = regex::Regex::new(&format!(r"^(?i)modulo\(\s*{}\s*,\s*(\d+)\s*\)$", ident)).unwrap();
+    if let Some(c) = re_modf.captures(e) {
+        return ShardingExpr::Modulo(c[1].to_string(), c[2].parse().unwrap_or(0));
+    }
+    let re_col = regex::Regex::new(&format!(r"^{}$", ident)).unwrap();
+    if let Some(c) = re_col.captures(e) {
+        return ShardingExpr::Column(c[1].to_string());
+    }
+    ShardingExpr::Unsupported(e.to_string())
+}
+
+// 解析 Distributed('cluster', 'db', 'table'[, sharding_key[, policy]]) 引擎定义，返回 (cluster, db, table, sharding_key)
+pub fn parse_distributed_engine(engine_full: &str) -> anyhow::Result<(String, String, String, Option<String>)> {
+    let start = engine_full.find("Distributed(").ok_or_else(|| anyhow::anyhow!(format!("不是 Distributed 引擎: {}", engine_full)))?;
+    let body = &engine_full[start + "Distributed(".len()..];
+    // 按顶层逗号切分参数，忽略括号与引号内的逗号
+    let mut args = Vec::new();
+    let mut depth = 0i32;
+    let mut in_quote = false;
+    let mut cur = String::new();
+    for ch in body.chars() {
+        match ch {
+            '\'' => { in_quote = !in_quote; cur.push(ch); }
+            '(' if !in_quote => { depth += 1; cur.push(ch); }
+            ')' if !in_quote => {
+                if depth == 0 { break; }
+                depth -= 1;
+                cur.push(ch);
+            }
+            ',' if !in_quote && depth == 0 => { args.push(cur.trim().to_string()); cur.clear(); }
+            _ => cur.push(ch),
+        }
+    }
+    if !cur.trim().is_empty() {
+        args.push(cur.trim().to_string());
+    }
+    if args.len() < 3 {
+        anyhow::bail!(format!("Distributed 引擎参数不足: {}", engine_full));
+    }
+    let unquote = |s: &str| s.trim().trim_matches('\'').trim_matches('`').to_string();
+    Ok((unquote(&args[0]), unquote(&args[1]), unquote(&args[2]), args.get(3).map(|s| s.to_string())))
+}
+
+// ---------- CityHash64 v1.0.2（与 ClickHouse cityHash64 一致，见测试中的对照值） ----------
+const K0: u64 = 0xc3a5c85c97cb3127;
+const K1: u64 = 0xb492b66fbe98f273;
+const K2: u64 = 0x9ae16a3b2f90404f;
+const K3: u64 = 0xc949d7c7509e6557;
+
+fn fetch64(s: &[u8], i: usize) -> u64 {
+    u64::from_le_bytes(s[i..i + 8].try_into().unwrap())
+}
+
+fn fetch32(s: &[u8], i: usize) -> u64 {
+    u32::from_le_bytes(s[i..i + 4].try_into().unwrap()) as u64
+}
+
+fn rotate(v: u64, shift: u32) -> u64 {
+    if shift == 0 { v } else { v.rotate_right(shift) }
+}
+
+fn shift_mix(v: u64) -> u64 {
+    v ^ (v >> 47)
+}
+
+fn hash_len16(u: u64, v: u64) -> u64 {
+    let k_mul: u64 = 0x9ddfea08eb382d69;
+    let mut a = (u ^ v).wrapping_mul(k_mul);
+    a ^= a >> 47;
+    let mut b = (v ^ a).wrapping_mul(k_mul);
+    b ^= b >> 47;
+    b.wrapping_mul(k_mul)
+}
+
+fn hash_len0to16(s: &[u8]) -> u64 {
+    let len = s.len();
+    if len > 8 {
+        let a = fetch64(s, 0);
+        let b = fetch64(s, len - 8);
+        return hash_len16(a, b.wrapping_add(len as u64).rotate_right(len as u32)) ^ b;
+    }
+    if len >= 4 {
+        let a = fetch32(s, 0);
+        return hash_len16((len as u64).wrapping_add(a << 3), fetch32(s, len - 4));
+    }
+    if len > 0 {
+        let a = s[0] as u32;
+        let b = s[len >> 1] as u32;
+        let c = s[len - 1] as u32;
+        let y = a.wrapping_add(b << 8);
+        let z = (len as u32).wrapping_add(c << 2);
+        return shift_mix((y as u64).wrapping_mul(K2) ^ (z as u64).wrapping_mul(K3)).wrapping_mul(K2);
+    }
+    K2
+}
+
+fn hash_len17to32(s: &[u8]) -> u64 {
+    let len = s.len();
+    let a = fetch64(s, 0).wrapping_mul(K1);
+    let b = fetch64(s, 8);
+    let c = fetch64(s, len - 8).wrapping_mul(K2);
+    let d = fetch64(s, len - 16).wrapping_mul(K0);
+    hash_len16(
+        rotate(a.wrapping_sub(b), 43).wrapping_add(rotate(c, 30)).wrapping_add(d),
+        a.wrapping_add(rotate(b ^ K3, 20)).wrapping_sub(c).wrapping_add(len as u64),
+    )
+}
+
+fn weak_hash_len32_with_seeds(s: &[u8], i: usize, mut a: u64, mut b: u64) -> (u64, u64) {
+    let w = fetch64(s, i);
+    let x = fetch64(s, i + 8);
+    let y = fetch64(s, i + 16);
+    let z = fetch64(s, i + 24);
+    a = a.wrapping_add(w);
+    b = rotate(b.wrapping_add(a).wrapping_add(z), 21);
+    let c = a;
+    a = a.wrapping_add(x).wrapping_add(y);
+    b = b.wrapping_add(rotate(a, 44));
+    (a.wrapping_add(z), b.wrapping_add(c))
+}
+
+fn hash_len33to64(s: &[u8]) -> u64 {
+    let len = s.len();
+    let mut z = fetch64(s, 24);
+    let mut a = fetch64(s, 0).wrapping_add((len as u64).wrapping_add(fetch64(s, len - 16)).wrapping_mul(K0));
+    let mut b = rotate(a.wrapping_add(z), 52);
+    let mut c = rotate(a, 37);
+    a = a.wrapping_add(fetch64(s, 8));
+    c = c.wrapping_add(rotate(a, 7));
+    a = a.wrapping_add(fetch64(s, 16));
+    let vf = a.wrapping_add(z);
+    let vs = b.wrapping_add(rotate(a, 31)).wrapping_add(c);
+    a = fetch64(s, 16).wrapping_add(fetch64(s, len - 32));
+    z = fetch64(s, len - 8);
+    b = rotate(a.wrapping_add(z), 52);
+    c = rotate(a, 37);
+    a = a.wrapping_add(fetch64(s, len - 24));
+    c = c.wrapping_add(rotate(a, 7));
+    a = a.wrapping_add(fetch64(s, len - 16));
+    let wf = a.wrapping_add(z);
+    let ws = b.wrapping_add(rotate(a, 31)).wrapping_add(c);
+    let r = shift_mix(vf.wrapping_add(ws).wrapping_mul(K2).wrapping_add(wf.wrapping_add(vs).wrapping_mul(K0)));
+    shift_mix(r.wrapping_mul(K0).wrapping_add(vs)).wrapping_mul(K2)
+}
+
+pub fn city_hash64(s: &[u8]) -> u64 {
+    let len = s.len();
+    if len <= 32 {
+        return if len <= 16 { hash_len0to16(s) } else { hash_len17to32(s) };
+    } else if len <= 64 {
+        return hash_len33to64(s);
+    }
+    // 超过 64 字节：先处理末尾，再按 64 字节分块循环（v1.0.2 的写法，与 v1.1 不同）
+    let mut x = fetch64(s, 0);
+    let mut y = fetch64(s, len - 16) ^ K1;
+    let mut z = fetch64(s, len - 56) ^ K0;
+    let mut v = weak_hash_len32_with_seeds(s, len - 64, len as u64, y);
+    let mut w = weak_hash_len32_with_seeds(s, len - 32, (len as u64).wrapping_mul(K1), K0);
+    z = z.wrapping_add(shift_mix(v.1).wrapping_mul(K1));
+    x = rotate(z.wrapping_add(x), 39).wrapping_mul(K1);
+    y = rotate(y, 33).wrapping_mul(K1);
+    let mut remaining = (len - 1) & !63;
+    let mut p = 0usize;
+    loop {
+        x = rotate(x.wrapping_add(y).wrapping_add(v.0).wrapping_add(fetch64(s, p + 16)), 37).wrapping_mul(K1);
+        y = rotate(y.wrapping_add(v.1).wrapping_add(fetch64(s, p + 48)), 42).wrapping_mul(K1);
+        x ^= w.1;
+        y ^= v.0;
+        z = rotate(z ^ w.0, 33);
+        v = weak_hash_len32_with_seeds(s, p, v.1.wrapping_mul(K1), x.wrapping_add(w.0));
+        w = weak_hash_len32_with_seeds(s, p + 32, z.wrapping_add(w.1), y);
+        std::mem::swap(&mut z, &mut x);
+        p += 64;
+        remaining -= 64;
+        if remaining == 0 {
+            break;
+        }
+    }
+    hash_len16(
+        hash_len16(v.0, w.0).wrapping_add(shift_mix(y).wrapping_mul(K1)).wrapping_add(z),
+        hash_len16(v.1, w.1).wrapping_add(x),
+    )
+}
+
+// ClickHouse 对整数参数的 cityHash64 走 IntHash64 分支
+pub fn int_hash64(x: u64) -> u64 {
+    let mut x = x ^ 0x4CF2D2BAAE6DA887;
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51afd7ed558ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ceb9fe1a85ec53);
+    x ^= x >> 33;
+    x
+}
+
+// 整数列类型：(是否有符号, 位宽)
+fn int_type(col_type: &str) -> Option<(bool, u32)> {
+    let t = col_type.trim_start_matches("LowCardinality(").trim_end_matches(')');
+    Some(match t {
+        "UInt8" => (false, 8), "UInt16" => (false, 16), "UInt32" => (false, 32), "UInt64" => (false, 64),
+        "Int8" => (true, 8), "Int16" => (true, 16), "Int32" => (true, 32), "Int64" => (true, 64),
+        _ => return None,
+    })
+}
+
+// 将 JSONEachRow 中的整数值（可能被引号包裹）按列类型转为 ClickHouse 内存中的 u64 位模式
+fn int_bits(v: &Value, col_type: &str) -> Option<u64> {
+    let (signed, bits) = int_type(col_type)?;
+    if signed {
+        let n: i64 = match v {
+            Value::Number(n) => n.as_i64()?,
+            Value::String(s) => s.parse().ok()?,
+            _ => return None,
+        };
+        // 与 bit_cast<UInt64> / static_cast<UnsignedT> 一致：按原始宽度截断后零扩展
+        Some(if bits == 64 { n as u64 } else { (n as u64) & ((1u64 << bits) - 1) })
+    } else {
+        match v {
+            Value::Number(n) => n.as_u64(),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+// col % N 的结果按 ClickHouse 的规则转为 u64：余数符号随被除数（-7 % 4 = -3）；常量 N 的类型为能容纳它的最小无符号类型，
+// 被除数有符号时结果为其两倍宽度的有符号类型（如 Int64 % 4 为 Int16），Distributed 再按同宽度的无符号数解释（-3 → 65533）
+fn modulo_bits(v: &Value, col_type: &str, n: u64) -> Option<u64> {
+    let (signed, bits) = int_type(col_type)?;
+    let raw = int_bits(v, col_type)?;
+    if !signed {
+        return Some(raw % n);
+    }
+    // 还原有符号值
+    let x = if bits == 64 { raw as i64 } else { ((raw << (64 - bits)) as i64) >> (64 - bits) };
+    let r = (x as i128 % n as i128) as i64;
+    let n_bits = match n {
+        0..=0xff => 8,
+        0x100..=0xffff => 16,
+        0x1_0000..=0xffff_ffff => 32,
+        _ => 64,
+    };
+    let result_bits = (n_bits * 2).min(64);
+    Some(if result_bits == 64 { r as u64 } else { (r as u64) & ((1u64 << result_bits) - 1) })
+}
+
+// 单个分片的写入端点
+#[derive(Debug, Clone)]
+pub struct ShardEndpoint {
+    pub shard_num: u64,
+    pub weight: u64,
+    pub dsn: String,
+}
+
+// 目标 Distributed 表的分片路由
+#[derive(Debug)]
+pub struct ShardRouter {
+    pub local_db: String,
+    pub local_table: String,
+    pub expr: ShardingExpr,
+    pub key_type: String,
+    pub shards: Vec<ShardEndpoint>,
+    slot_to_shard: Vec<usize>,
+    rr: AtomicUsize,
+}
+
+impl ShardRouter {
+    pub fn new(local_db: String, local_table: String, expr: ShardingExpr, key_type: String, shards: Vec<ShardEndpoint>) -> Self {
+        // 与 Distributed 引擎相同：按权重展开 slot -> shard 映射
+        let mut slot_to_shard = Vec::new();
+        for (i, s) in shards.iter().enumerate() {
+            for _ in 0..s.weight.max(1) {
+                slot_to_shard.push(i);
+            }
+        }
+        ShardRouter { local_db, local_table, expr, key_type, shards, slot_to_shard, rr: AtomicUsize::new(0) }
+    }
+
+    // 计算行所属分片下标；无法在客户端计算时返回 None
+    pub fn shard_for_row(&self, row: &HashMap<String, Value>) -> Option<usize> {
+        if self.slot_to_shard.is_empty() {
+            return None;
+        }
+        let value = match &self.expr {
+            ShardingExpr::Rand => self.rr.fetch_add(1, Ordering::Relaxed) as u64,
+            ShardingExpr::Column(c) => int_bits(row.get(c)?, &self.key_type)?,
+            ShardingExpr::Modulo(c, n) => {
+                if *n == 0 { return None; }
+                modulo_bits(row.get(c)?, &self.key_type, *n)?
+            }
+            ShardingExpr::CityHash64(c) => {
+                let v = row.get(c)?;
+                if let Some(bits) = int_bits(v, &self.key_type) {
+                    int_hash64(bits)
+                } else if self.key_type.contains("String") && !self.key_type.contains("FixedString") {
+                    city_hash64(v.as_str()?.as_bytes())
+                } else {
+                    return None;
+                }
+            }
+            ShardingExpr::Unsupported(_) => return None,
+        };
+        Some(self.slot_to_shard[(value % self.slot_to_shard.len() as u64) as usize])
+    }
+}
+
+// 将 DSN 中的主机替换为分片主机，保留用户名密码与端口
+pub fn dsn_with_host(dsn: &str, host: &str) -> String {
+    let re = regex::Regex::new(r"^(https?://[^@]*@)([^/:]+)(.*)$").unwrap();
+    match re.captures(dsn) {
+        Some(c) => format!("{}{}{}", &c[1], host, &c[3]),
+        None => dsn.to_string(),
+    }
+}
+
+// 查询表引擎，返回 (engine, engine_full)
+pub async fn table_engine(dsn: &str, db: &str, table: &str) -> anyhow::Result<(String, String)> {
+    let sql = format!(
+        "SELECT engine, engine_full FROM system.tables WHERE database = '{}' AND name = '{}' FORMAT JSONEachRow",
+        db, table
+    );
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    let row = rows.first().ok_or_else(|| anyhow::anyhow!(format!("表 {}.{} 不存在", db, table)))?;
+    let get = |k: &str| row.get(k).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    Ok((get("engine"), get("engine_full")))
+}
+
+// Distributed 表返回其底层本地表 (db, table)，其他引擎原样返回
+pub async fn resolve_local_table(dsn: &str, db: &str, table: &str) -> anyhow::Result<(String, String)> {
+    let (engine, engine_full) = table_engine(dsn, db, table).await?;
+    if engine != "Distributed" {
+        return Ok((db.to_string(), table.to_string()));
+    }
+    let (_, local_db, local_table, _) = parse_distributed_engine(&engine_full)?;
+    Ok((if local_db.is_empty() { db.to_string() } else { local_db }, local_table))
+}
+
+// 取出 DSN 中的主机名
+pub fn dsn_host(dsn: &str) -> String {
+    let re = regex::Regex::new(r"^https?://[^@]*@([^/:]+)").unwrap();
+    re.captures(dsn).map(|c| c[1].to_string()).unwrap_or_default()
+}
+
+// 按分片列出集群各副本主机，返回 Vec<(shard_num, Vec<host>)>
+pub async fn cluster_shard_hosts(dsn: &str, db: &str, cluster: &str) -> anyhow::Result<Vec<(u64, Vec<String>)>> {
+    let sql = format!(
+        "SELECT shard_num, groupArray(host_name) AS hosts FROM (SELECT shard_num, host_name FROM system.clusters \
+         WHERE cluster = '{}' ORDER BY shard_num, replica_num) GROUP BY shard_num ORDER BY shard_num FORMAT JSONEachRow",
+        cluster
+    );
+    let rows = ch_query_rows(dsn, db, &sql).await?;
+    if rows.is_empty() {
+        anyhow::bail!(format!("system.clusters 中未找到集群 {}", cluster));
+    }
+    Ok(rows
+        .iter()
+        .map(|r| {
+            let shard_num = json_u64(r.get("shard_num"));
+            let hosts = r
+                .get("hosts")
+                .and_then(|v| v.as_array())
+                .map(|a| a.iter().filter_map(|h| h.as_str().map(|s| s.to_string())).collect())
+                .unwrap_or_default();
+            (shard_num, hosts)
+        })
+        .collect())
+}
+
+// 解析目标 Distributed 表并从 system.clusters 发现各分片写入端点
+pub async fn resolve_shard_router(dst_dsn: &str, dst_db: &str, dst_table: &str) -> anyhow::Result<ShardRouter> {
+    let (engine, engine_full) = table_engine(dst_dsn, dst_db, dst_table).await?;
+    if engine != "Distributed" {
+        anyhow::bail!(format!("--dst-write-local 需要目标表为 Distributed 引擎, 实际为 {}", engine));
+    }
+    let (cluster, local_db, local_table, key) = parse_distributed_engine(&engine_full)?;
+    let local_db = if local_db.is_empty() { dst_db.to_string() } else { local_db };
+    let expr = match key {
+        Some(k) => parse_sharding_expr(&k),
+        None => ShardingExpr::Unsupported(String::new()),
+    };
+    let mut key_type = String::new();
+    if let ShardingExpr::Column(c) | ShardingExpr::CityHash64(c) | ShardingExpr::Modulo(c, _) = &expr {
+        let sql = format!(
+            "SELECT type FROM system.columns WHERE database = '{}' AND table = '{}' AND name = '{}' FORMAT JSONEachRow",
+            dst_db, dst_table, c
+        );
+        let rows = ch_query_rows(dst_dsn, dst_db, &sql).await?;
+        key_type = rows.first().and_then(|r| r.get("type")).and_then(|v| v.as_str()).unwrap_or("").to_string();
+    }
+    // 每个分片取第一个副本写入（本地表为 Replicated* 且 internal_replication=true 时由复制同步其余副本）
+    let sql = format!(
+        "SELECT shard_num, any(shard_weight) AS shard_weight, argMin(host_name, replica_num) AS host_name \
+         FROM system.clusters WHERE cluster = '{}' GROUP BY shard_num ORDER BY shard_num FORMAT JSONEachRow",
+        cluster
+    );
+    let rows = ch_query_rows(dst_dsn, dst_db, &sql).await?;
+    if rows.is_empty() {
+        anyhow::bail!(format!("system.clusters 中未找到集群 {}", cluster));
+    }
+    let shards: Vec<ShardEndpoint> = rows
+        .iter()
+        .map(|r| ShardEndpoint {
+            shard_num: json_u64(r.get("shard_num")),
+            weight: json_u64(r.get("shard_weight")),
+            dsn: dsn_with_host(dst_dsn, r.get("host_name").and_then(|v| v.as_str()).unwrap_or("")),
+        })
+        .collect();
+    if let ShardingExpr::Unsupported(e) = &expr {
+        warn!("sharding 表达式 [{}] 无法在客户端计算，所有行将回退为写入 Distributed 表", e);
+    }
+    info!(
+        "dst-write-local: cluster={}, local_table={}.{}, sharding={:?}, key_type={}, shards={}",
+        cluster, local_db, local_table, expr, key_type, shards.len()
+    );
+    Ok(ShardRouter::new(local_db, local_table, expr, key_type, shards))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    // 期望值用 ClickHouse 服务端 cityHash64 使用的实现计算：字符串为 contrib/cityhash102 的 CityHash64，
+    // 整数为 IntHash64Impl(bit_cast<UInt64>(x))，即 SELECT cityHash64('...') / SELECT cityHash64(toInt64(...))
+    #[test]
+    fn city_hash64_matches_clickhouse() {
+        let letters = |n: u8| (0..n).map(|i| (b'a' + i % 26) as char).collect::<String>();
+        let (s64, s65, s100, s200) = (letters(64), letters(65), letters(100), letters(200));
+        let cases = [
+            ("", 11160318154034397263),
+            ("a", 2603192927274642682),
+            ("abc", 4220206313085259313),
+            ("hello", 2578220239953316063),
+            ("clickhouse", 7986503660239546262),
+            ("user-000000000042", 7826315682118235356),
+            ("0123456789abcdef0123456789abcdef01234567", 13203313304749939977),
+            (s64.as_str(), 10870159159245104547),
+            (s65.as_str(), 13407693885279354766),
+            (s100.as_str(), 17850722214328437841),
+            (s200.as_str(), 10258548877153202531),
+        ];
+        for (s, want) in cases {
+            assert_eq!(city_hash64(s.as_bytes()), want, "cityHash64('{}')", s);
+        }
+    }
+
+    #[test]
+    fn int_keys_hash_like_clickhouse() {
+        let cases = [
+            (json!(0), "UInt64", 4761183170873013810),
+            (json!(1), "UInt64", 10577349846663553072),
+            (json!("42"), "Int64", 11490350930367293593),
+            (json!(-1), "Int64", 14600443904207254319),
+            (json!("-42"), "Int64", 1944804401150185880),
+            // 窄类型按原始宽度零扩展后再哈希
+            (json!(-1), "Int32", 9168733277332772950),
+            (json!(-42), "Int32", 5010896688762168755),
+        ];
+        for (v, t, want) in cases {
+            assert_eq!(int_hash64(int_bits(&v, t).unwrap()), want, "cityHash64(to{}({}))", t, v);
+        }
+    }
+
+    fn router(expr: ShardingExpr, key_type: &str, weights: &[u64]) -> ShardRouter {
+        let shards = weights.iter().enumerate().map(|(i, w)| ShardEndpoint { shard_num: i as u64 + 1, weight: *w, dsn: String::new() }).collect();
+        ShardRouter::new(String::new(), String::new(), expr, key_type.to_string(), shards)
+    }
+
+    fn shard(r: &ShardRouter, v: Value) -> usize {
+        r.shard_for_row(&HashMap::from([("k".to_string(), v)])).unwrap()
+    }
+
+    #[test]
+    fn signed_keys_use_clickhouse_unsigned_modulo() {
+        // 直接以列为 key：static_cast<UInt32>(-1) = 4294967295，% 3 = 0
+        let col = router(ShardingExpr::Column("k".to_string()), "Int32", &[1, 1, 1]);
+        assert_eq!(shard(&col, json!(-1)), 0);
+        assert_eq!(shard(&col, json!(-2)), 2);
+        assert_eq!(shard(&col, json!(7)), 1);
+        // k % 4：-7 % 4 = -3（Int16），按 UInt16 为 65533，% 3 = 1
+        let m = router(ShardingExpr::Modulo("k".to_string(), 4), "Int64", &[1, 1, 1]);
+        assert_eq!(shard(&m, json!(-7)), 1);
+        assert_eq!(shard(&m, json!("-8")), 0);
+        assert_eq!(shard(&m, json!(7)), 0);
+        // modulo(k, 1000)：N 为 UInt16，结果为 Int32；-1 → 4294967295，% 2 = 1
+        let wide = router(ShardingExpr::Modulo("k".to_string(), 1000), "Int8", &[1, 1]);
+        assert_eq!(shard(&wide, json!(-1)), 1);
+        let unsigned = router(ShardingExpr::Modulo("k".to_string(), 4), "UInt64", &[1, 1, 1]);
+        assert_eq!(shard(&unsigned, json!(7)), 0);
+        // 按权重展开 slot（0、1 属于第一个分片）：cityHash64(toInt64(-1)) % 3 = 2
+        let city = router(ShardingExpr::CityHash64("k".to_string()), "Int64", &[2, 1]);
+        assert_eq!(shard(&city, json!(-1)), 1);
+    }
+}
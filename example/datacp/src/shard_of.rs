@@ -0,0 +1,62 @@
+// ===================== 多进程分担同一张表（--shard-of） =====================
+// 超大表可由多个 datacp 进程（可在不同机器上）分担：--shard-of i/n --shard-key user_id 时，
+// 所有源端与目标端的读取、计数与校验都追加 cityHash64(user_id) % n = i，各进程处理互不相交、确定的一份行。
+// 断点续传文件与报告文件名带上分片编号，同一状态目录下的各进程互不干扰；分片键与分片数记录在断点续传元数据中，
+// 续传时不一致则拒绝，保证各份合起来覆盖整张表。
+// 切换只能由分片 0 执行（且应在其余分片完成后进行），其余分片须使用 --no-cutover；切换阶段按整张表补差与校验
+
+use crate::Opt;
+
+// 解析 i/n，未指定时返回 None
+pub fn parse(opt: &Opt) -> anyhow::Result<Option<(u64, u64)>> {
+    if opt.shard_of.is_empty() {
+        if !opt.shard_key.is_empty() {
+            anyhow::bail!("--shard-key 需要同时指定 --shard-of i/n");
+        }
+        return Ok(None);
+    }
+    let parsed = opt.shard_of.split_once('/').and_then(|(i, n)| Some((i.trim().parse::<u64>().ok()?, n.trim().parse::<u64>().ok()?)));
+    let Some((i, n)) = parsed.filter(|(i, n)| *n >= 2 && i < n) else {
+        anyhow::bail!(format!("--shard-of 格式为 i/n（0 <= i < n，n >= 2）: {}", opt.shard_of));
+    };
+    if opt.shard_key.is_empty() {
+        anyhow::bail!("--shard-of 需要同时指定 --shard-key");
+    }
+    Ok(Some((i, n)))
+}
+
+// 启动时检查与其他选项的组合
+pub fn validate(opt: &Opt) -> anyhow::Result<()> {
+    let Some((i, _)) = parse(opt)? else { return Ok(()) };
+    if opt.copy_mode == "attach-partition" || opt.replace_partitions {
+        anyhow::bail!("--shard-of 按行划分，不能与 attach-partition 模式或 --replace-partitions 同时使用");
+    }
+    if i != 0 && (!opt.no_cutover || opt.standby) {
+        anyhow::bail!(format!("切换只能由分片 0 执行，分片 {} 须使用 --no-cutover（且不能使用 --standby）", opt.shard_of));
+    }
+    Ok(())
+}
+
+// 追加到 WHERE 之后的分片条件，未分片时为空
+pub fn filter_sql(opt: &Opt) -> String {
+    match parse(opt) {
+        Ok(Some((i, n))) => format!(" AND cityHash64({}) % {} = {}", opt.shard_key, n, i),
+        _ => String::new(),
+    }
+}
+
+// 断点续传文件与报告文件名的后缀，如 _shard0of4
+pub fn suffix(opt: &Opt) -> String {
+    match parse(opt) {
+        Ok(Some((i, n))) => format!("_shard{}of{}", i, n),
+        _ => String::new(),
+    }
+}
+
+// 断点续传元数据中记录的分片参数，未分片时为 null
+pub fn meta(opt: &Opt) -> serde_json::Value {
+    match parse(opt) {
+        Ok(Some((i, n))) => serde_json::json!({ "key": opt.shard_key.trim(), "count": n, "index": i }),
+        _ => serde_json::Value::Null,
+    }
+}
@@ -0,0 +1,193 @@
+// ===================== SQL 审计日志与回放 =====================
+// --sql-log 记录本次运行发往 ClickHouse 的每条语句（INSERT 只记语句与行数，不记数据），
+// 附带时间、端点、query_id、耗时与状态；由单独的写线程顺序落盘，多个 worker 的行不会交错。
+// datacp replay 读取该日志，重新打印（或对幂等语句重新执行）指定类型的语句，用于事后复盘
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use crate::server_copy::redact_sql;
+use crate::{ch_execute, events, Opt};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SqlLogLine {
+    pub time: String,
+    pub side: String, // src / dst / 其他节点为 host
+    pub endpoint: String,
+    pub query_id: String,
+    pub duration_ms: u64,
+    pub status: String, // ok / 错误首行
+    pub kind: String,   // select / insert / mutation
+    pub sql: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rows: Option<usize>,
+}
+
+struct Writer {
+    tx: mpsc::Sender<String>,
+    handle: std::thread::JoinHandle<()>,
+    src: String,
+    dst: String,
+}
+
+static WRITER: OnceLock<Mutex<Option<Writer>>> = OnceLock::new();
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+// DSN 的 host:port
+pub fn endpoint(dsn: &str) -> String {
+    let re = regex::Regex::new(r"^https?://(?:[^@/]*@)?([^/?]+)").unwrap();
+    re.captures(dsn).map(|c| c[1].to_string()).unwrap_or_default()
+}
+
+fn kind(sql: &str) -> &'static str {
+    let head = sql.split_whitespace().next().unwrap_or("").to_ascii_uppercase();
+    match head.as_str() {
+        "SELECT" | "WITH" | "SHOW" | "DESCRIBE" | "DESC" | "EXISTS" => "select",
+        "INSERT" => "insert",
+        _ => "mutation",
+    }
+}
+
+// 打开日志文件并启动写线程
+pub fn init(path: &str, src_dsn: &str, dst_dsn: &str) -> anyhow::Result<()> {
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow::anyhow!(format!("打开 --sql-log {} 失败: {}", path, e)))?;
+    let (tx, rx) = mpsc::channel::<String>();
+    let handle = std::thread::spawn(move || {
+        for line in rx {
+            let _ = f.write_all(line.as_bytes());
+            let _ = f.flush();
+        }
+    });
+    let w = Writer { tx, handle, src: endpoint(src_dsn), dst: endpoint(dst_dsn) };
+    *WRITER.get_or_init(|| Mutex::new(None)).lock().unwrap() = Some(w);
+    info!("SQL 审计日志写入 {}", path);
+    Ok(())
+}
+
+// 关闭写线程并等待剩余行落盘，进程退出前调用
+pub fn close() {
+    if let Some(w) = WRITER.get().and_then(|m| m.lock().unwrap().take()) {
+        drop(w.tx);
+        let _ = w.handle.join();
+    }
+}
+
+// 子命令返回前关闭日志
+pub fn closing<T>(res: T) -> T {
+    close();
+    res
+}
+
+// 一次语句执行（每次重试单独记录）
+pub struct Stmt {
+    query_id: Option<String>,
+    started: Instant,
+}
+
+// 未启用 --sql-log 时不生成 query_id，请求与原来完全一致
+pub fn begin() -> Stmt {
+    let enabled = WRITER.get().map(|m| m.lock().unwrap().is_some()).unwrap_or(false);
+    let query_id = enabled.then(|| format!("datacp-{}-{}", std::process::id(), SEQ.fetch_add(1, Ordering::SeqCst)));
+    Stmt { query_id, started: Instant::now() }
+}
+
+impl Stmt {
+    // 附加到 HTTP 请求的 query_id 参数
+    pub fn params(&self) -> Vec<(&'static str, String)> {
+        self.query_id.iter().map(|id| ("query_id", id.clone())).collect()
+    }
+
+    pub fn end(&self, dsn: &str, sql: &str, status: &str, rows: Option<usize>) {
+        let Some(query_id) = &self.query_id else { return };
+        let guard = WRITER.get().unwrap().lock().unwrap();
+        let Some(w) = guard.as_ref() else { return };
+        let ep = endpoint(dsn);
+        let side = if ep == w.src && ep == w.dst {
+            "src/dst".to_string()
+        } else if ep == w.src {
+            "src".to_string()
+        } else if ep == w.dst {
+            "dst".to_string()
+        } else {
+            ep.clone()
+        };
+        let line = SqlLogLine {
+            time: chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+            side,
+            endpoint: ep,
+            query_id: query_id.clone(),
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            status: redact_sql(status.lines().next().unwrap_or(status)),
+            kind: kind(sql).to_string(),
+            sql: redact_sql(sql.trim()),
+            rows,
+        };
+        if let Ok(s) = serde_json::to_string(&line) {
+            let _ = w.tx.send(s + "\n");
+        }
+    }
+}
+
+// 可安全重复执行的语句：OPTIMIZE、SYSTEM、CREATE ... IF NOT EXISTS、DROP ... IF EXISTS
+fn idempotent(sql: &str) -> bool {
+    let up = sql.trim_start().to_ascii_uppercase();
+    up.starts_with("OPTIMIZE ")
+        || up.starts_with("SYSTEM ")
+        || (up.starts_with("CREATE ") && up.contains(" IF NOT EXISTS "))
+        || (up.starts_with("DROP ") && up.contains(" IF EXISTS "))
+}
+
+// datacp replay --sql-log <path> --only mutations|inserts|selects|all [--dry-run]
+pub async fn replay(opt: &Opt, path: &str, only: &str, dry_run: bool) -> anyhow::Result<()> {
+    let want = match only {
+        "mutations" => Some("mutation"),
+        "inserts" => Some("insert"),
+        "selects" => Some("select"),
+        "all" => None,
+        other => anyhow::bail!(format!("不支持的 --only: {}（mutations|inserts|selects|all）", other)),
+    };
+    let f = std::fs::File::open(path).map_err(|e| anyhow::anyhow!(format!("读取 {} 失败: {}", path, e)))?;
+    let (mut printed, mut executed) = (0, 0);
+    for line in std::io::BufReader::new(f).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let l: SqlLogLine = serde_json::from_str(&line).map_err(|e| anyhow::anyhow!(format!("解析 {} 失败: {}", path, e)))?;
+        if want.map(|k| k != l.kind).unwrap_or(false) {
+            continue;
+        }
+        events::say(&format!(
+            "-- {} {} {} {} {}ms {}{}\n{};\n",
+            l.time, l.side, l.endpoint, l.query_id, l.duration_ms, l.status,
+            l.rows.map(|r| format!(" rows={}", r)).unwrap_or_default(),
+            l.sql
+        ));
+        printed += 1;
+        if dry_run || l.status != "ok" || !idempotent(&l.sql) {
+            continue;
+        }
+        let (dsn, db) = match l.side.as_str() {
+            "src" | "src/dst" => (&opt.src_dsn, &opt.src_db),
+            "dst" => (&opt.dst_dsn, &opt.dst_db),
+            _ => {
+                warn!("{} 在节点 {} 上执行，不在 --src-dsn/--dst-dsn 中，跳过", l.query_id, l.endpoint);
+                continue;
+            }
+        };
+        ch_execute(dsn, db, &l.sql).await?;
+        info!("已重新执行 {}", l.query_id);
+        executed += 1;
+    }
+    info!("回放完成: 打印 {} 条，重新执行 {} 条{}", printed, executed, if dry_run { "（--dry-run）" } else { "" });
+    Ok(())
+}
@@ -0,0 +1,38 @@
+// ===================== 源端查询并发上限 =====================
+// --src-max-concurrent-queries 限制同时发往源端的查询数（数据读取、count、min/max 等元数据查询），
+// 与写入并发、行速率限制相互独立，避免高并发时占满源端 max_concurrent_queries。
+// 等待许可的时间计入 worker 的读源端阶段，并单独汇总到报告
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+struct SrcLimit {
+    dsn: String,
+    permits: Semaphore,
+    waited_ms: AtomicU64,
+}
+
+static LIMIT: OnceLock<SrcLimit> = OnceLock::new();
+
+// 进程启动时设置，0 表示不限（多表迁移共享同一上限）
+pub fn init(src_dsn: &str, max_queries: usize) {
+    if max_queries > 0 {
+        let _ = LIMIT.set(SrcLimit { dsn: src_dsn.to_string(), permits: Semaphore::new(max_queries), waited_ms: AtomicU64::new(0) });
+    }
+}
+
+// 发往源端的请求在发送前获取许可，响应读取完毕后释放
+pub async fn acquire(dsn: &str) -> Option<SemaphorePermit<'static>> {
+    let l = LIMIT.get().filter(|l| l.dsn == dsn)?;
+    let started = Instant::now();
+    let permit = l.permits.acquire().await.ok();
+    l.waited_ms.fetch_add(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    permit
+}
+
+// 累计等待许可的秒数
+pub fn waited_seconds() -> u64 {
+    LIMIT.get().map(|l| l.waited_ms.load(Ordering::Relaxed) / 1000).unwrap_or(0)
+}
@@ -0,0 +1,86 @@
+// ===================== 查询作为源端（--src-query） =====================
+// 源端有时不是物理表，而是连接两张表的视图或自己控制的投影查询。--src-query "SELECT a, b, ts FROM t1 JOIN t2 USING id"
+// 代替 --src-table：查询登记为内部表名 src_query_<查询 sha256 的前 8 位>，qualified() 遇到该名称时换成子查询 (<query>)，
+// 字段发现（DESCRIBE TABLE (<query>)）、分段读取（SELECT ... FROM (<query>) WHERE ts >= ... AND ts < ...）、时间范围、
+// 比对、分批写入、断点续传与校验都照常进行。断点续传文件与元数据按该名称区分，查询改变后不会沿用旧断点。
+// 查询没有引擎与分区：依赖源表物理结构的方式（--select-final、--archive、--replace-partitions、--copy-mode remote-secure /
+// attach-partition、--is-src-distributed、多表迁移与 serve）不支持；源表元数据检查（排序键命中、Replicated 路径）跳过。
+// 切换阶段没有可 rename 的源表，也没有 _bak 补差：指定 --rename-dst-to 时只把目标表改名为该名称，否则跳过切换
+
+use log::info;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::report::RunReport;
+use crate::{blackout, cutover, qualified, status, Command, Opt};
+
+const PREFIX: &str = "src_query_";
+
+// 内部表名 → 查询
+static QUERIES: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+
+// 源端为查询（已登记的内部表名）
+pub fn enabled(opt: &Opt) -> bool {
+    !opt.src_query.trim().is_empty()
+}
+
+// qualified() 使用：内部表名换成子查询
+pub fn subquery(table: &str) -> Option<String> {
+    if !table.starts_with(PREFIX) {
+        return None;
+    }
+    QUERIES.lock().unwrap().as_ref()?.get(table).map(|q| format!("({})", q))
+}
+
+// 启动时校验参数，并把 --src-table 设为查询的内部表名
+pub fn init(opt: &mut Opt) -> anyhow::Result<()> {
+    if !enabled(opt) {
+        if !opt.rename_dst_to.is_empty() {
+            anyhow::bail!("--rename-dst-to 只用于 --src-query（源端为表时切换把目标表改名为源表名）");
+        }
+        return Ok(());
+    }
+    let query = opt.src_query.trim().trim_end_matches(';').trim().to_string();
+    let head = query.split_whitespace().next().unwrap_or("").to_ascii_uppercase();
+    if head != "SELECT" && head != "WITH" {
+        anyhow::bail!(format!("--src-query 须为 SELECT 查询: {}", query));
+    }
+    if !opt.src_table.is_empty() {
+        anyhow::bail!("--src-query 与 --src-table 只能指定其一");
+    }
+    let unsupported = [
+        (opt.select_final, "--select-final"),
+        (opt.archive, "--archive"),
+        (opt.replace_partitions, "--replace-partitions"),
+        (opt.is_src_distributed, "--is-src-distributed"),
+        (opt.copy_mode != "http", "--copy-mode remote-secure / attach-partition"),
+        (!opt.tables_file.is_empty() || opt.all_tables, "--tables-file / --all-tables"),
+        (matches!(opt.cmd, Some(Command::Serve { .. })), "datacp serve"),
+    ];
+    if let Some((_, flag)) = unsupported.iter().find(|(on, _)| *on) {
+        anyhow::bail!(format!("--src-query 的源端没有物理表，不支持 {}", flag));
+    }
+    let hash: String = Sha256::digest(query.as_bytes()).iter().take(4).map(|b| format!("{:02x}", b)).collect();
+    let name = format!("{}{}", PREFIX, hash);
+    QUERIES.lock().unwrap().get_or_insert_with(HashMap::new).insert(name.clone(), query);
+    opt.src_table = name;
+    Ok(())
+}
+
+// 切换：没有源表可 rename，--rename-dst-to 时只把目标表改名（--cutover-into-src-db 时改到源库）
+pub async fn cutover(opt: &Opt, report: &Arc<Mutex<RunReport>>) -> anyhow::Result<()> {
+    if opt.rename_dst_to.is_empty() {
+        info!("源端为 --src-query，没有可 rename 的源表，跳过切换；目标表 {} 保持原名", qualified(&opt.dst_db, opt.read_table()));
+        report.lock().unwrap().cutover = "skipped".to_string();
+        return Ok(());
+    }
+    blackout::wait().await;
+    status::set_phase("cutover");
+    report.lock().unwrap().cutover = "started".to_string();
+    let renamed = Opt { src_table: opt.rename_dst_to.clone(), ..opt.clone() };
+    cutover::rename_dst_to_src(&renamed).await.map_err(|e| anyhow::anyhow!(format!("重命名目标表失败: {e}")))?;
+    info!("目标表 {} 已改名为 {}", qualified(&opt.dst_db, opt.read_table()), qualified(opt.cutover_db(), &opt.rename_dst_to));
+    report.lock().unwrap().cutover = "performed".to_string();
+    Ok(())
+}
@@ -0,0 +1,109 @@
+// ===================== 源端副本选择与一致性读 =====================
+// --src-prefer-replica 把源端读取（数据、count、min/max 等查询）固定发往指定副本，DDL/删除等写操作仍发往 DSN 主机；
+// 启动时按 system.clusters / system.replicas 校验该主机，运行中该副本不可达时告警并改回 DSN 主机，不中止迁移。
+// --src-consistent-reads 为所有源端请求附加 select_sequential_consistency=1，避免落后的副本返回旧数据
+
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use crate::{ch_query_rows, dsn_with_settings, json_u64, Opt};
+
+struct Replica {
+    dsn: String,       // DSN 主机（回退目标）
+    preferred: String, // 改写为首选副本主机后的 DSN
+    host: String,
+    fell_back: AtomicBool,
+}
+
+static REPLICA: OnceLock<Replica> = OnceLock::new();
+
+fn host_of(dsn: &str) -> String {
+    let re = regex::Regex::new(r"^https?://(?:[^@/]*@)?([^/?]+)").unwrap();
+    re.captures(dsn).map(|c| c[1].to_string()).unwrap_or_default()
+}
+
+// 替换 DSN 中的主机，未指定端口时沿用 DSN 的端口
+fn with_host(dsn: &str, host: &str) -> String {
+    let re = regex::Regex::new(r"^(https?://(?:[^@/]*@)?)([^/:?]+)(:\d+)?").unwrap();
+    re.replace(dsn, |c: &regex::Captures| {
+        if host.contains(':') {
+            format!("{}{}", &c[1], host)
+        } else {
+            format!("{}{}{}", &c[1], host, c.get(3).map(|m| m.as_str()).unwrap_or(""))
+        }
+    })
+    .to_string()
+}
+
+// 首选副本须出现在 system.clusters 的 host_name/host_address 或源表 system.replicas 的副本列表中
+async fn validate(opt: &Opt, name: &str) -> anyhow::Result<()> {
+    let n = name.replace('\'', "\\'");
+    let sql = format!("SELECT count() AS c FROM system.clusters WHERE host_name = '{n}' OR host_address = '{n}' FORMAT JSONEachRow");
+    let rows = ch_query_rows(&opt.src_dsn, "system", &sql).await?;
+    if json_u64(rows.first().and_then(|r| r.get("c"))) > 0 {
+        return Ok(());
+    }
+    // replica_is_active 在较早的版本中不存在，查询失败按未找到处理
+    let sql = format!(
+        "SELECT count() AS c FROM system.replicas WHERE has(mapKeys(replica_is_active), '{n}') OR replica_name = '{n}' FORMAT JSONEachRow"
+    );
+    if let Ok(rows) = ch_query_rows(&opt.src_dsn, "system", &sql).await {
+        if json_u64(rows.first().and_then(|r| r.get("c"))) > 0 {
+            return Ok(());
+        }
+    }
+    anyhow::bail!(format!("--src-prefer-replica {} 不在源端 system.clusters / system.replicas 中", name))
+}
+
+// 启动时调用：附加一致性读 settings，校验首选副本并确认可达
+pub async fn init(opt: &mut Opt) -> anyhow::Result<()> {
+    if opt.src_consistent_reads {
+        opt.src_dsn = dsn_with_settings(&opt.src_dsn, &[("select_sequential_consistency".to_string(), "1".to_string())]);
+        info!("源端读取使用 select_sequential_consistency=1");
+    }
+    if opt.src_prefer_replica.is_empty() {
+        return Ok(());
+    }
+    let host = opt.src_prefer_replica.clone();
+    validate(opt, host.split(':').next().unwrap_or(&host)).await?;
+    let preferred = with_host(&opt.src_dsn, &host);
+    let fell_back = match ch_query_rows(&preferred, &opt.src_db, "SELECT 1 AS c FORMAT JSONEachRow").await {
+        Ok(_) => {
+            info!("源端读取固定发往副本 {}", host_of(&preferred));
+            false
+        }
+        Err(e) => {
+            warn!("源端首选副本 {} 不可达，改用 DSN 主机 {}: {e}", host, host_of(&opt.src_dsn));
+            true
+        }
+    };
+    let _ = REPLICA.set(Replica { dsn: opt.src_dsn.clone(), preferred, host, fell_back: AtomicBool::new(fell_back) });
+    Ok(())
+}
+
+// 读取请求实际发往的 DSN
+pub fn route(dsn: &str) -> String {
+    match REPLICA.get() {
+        Some(r) if r.dsn == dsn && !r.fell_back.load(Ordering::Relaxed) => r.preferred.clone(),
+        _ => dsn.to_string(),
+    }
+}
+
+// 读取请求连接失败时调用：首选副本不可达则此后改回 DSN 主机
+pub fn unreachable(dsn: &str, err: &reqwest::Error) {
+    let Some(r) = REPLICA.get() else { return };
+    if r.preferred == dsn && !r.fell_back.swap(true, Ordering::Relaxed) {
+        warn!("源端首选副本 {} 不可达，此后改用 DSN 主机 {}: {err}", r.host, host_of(&r.dsn));
+    }
+}
+
+// 报告中记录实际提供读取的副本
+pub fn served() -> Option<String> {
+    let r = REPLICA.get()?;
+    Some(if r.fell_back.load(Ordering::Relaxed) {
+        format!("{} (fallback, {} unreachable)", host_of(&r.dsn), r.host)
+    } else {
+        host_of(&r.preferred)
+    })
+}
@@ -0,0 +1,235 @@
+// ===================== 分段暂存写入（--staged-inserts） =====================
+// 分段的缺失行分几十批写入目标表，读者会看到写了一半的小时。--staged-inserts 时各分段的缺失行先写入本次运行的暂存表
+// <目标表>__datacp_stage（与目标表同结构的普通 MergeTree，首次写入时创建一次，各分段共用），整段的批次全部成功后记录
+// staged:<分段>\t<令牌>，由一条 INSERT INTO 目标表 SELECT * FROM 暂存表 WHERE <分段时间条件> 在服务端一次性移入，
+// 记录 promoted:<分段>\t<令牌>，再以 ALTER DELETE（同步等待 mutation）清理暂存表中该分段的行。
+// 移入带 insert_deduplication_token=<令牌>（目标端支持时）：移入超时后实际已完成、或记录 promoted 前进程被结束时，
+// 续传重新移入的块由服务端去重（目标表为复制表或设置了 non_replicated_deduplication_window）；移入单线程读取，
+// 整段合并为一个块（不按 min_insert_block_size 切分），重新移入时块的划分与首次相同。有批次失败时不移入，分段按失败处理，
+// 重试前先清理该分段已暂存的行。续传时沿用上次的暂存表：先移入已 staged 未 promoted 的分段，再清空暂存表；
+// 分段迁移阶段结束后删除暂存表（切换阶段的 _bak 补差直接写入目标表）。
+// 不支持 --page-key（页标记推进时行仍在暂存表中）与 --dst-write-local
+
+use log::{info, warn};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::{ch_execute_timeout, ch_query_rows, dsn_with_settings, qualified, save_done_segment, segment, server_version, time_zone, Opt, RunCtx};
+
+// 整段写入暂存表的分段在断点续传文件中的前缀
+pub const STAGED_PREFIX: &str = "staged:";
+// 已移入目标表的分段在断点续传文件中的前缀
+pub const PROMOTED_PREFIX: &str = "promoted:";
+
+pub struct Stage {
+    dsn: String,
+    db: String,
+    target: String, // 带库名的目标表
+    table: String,  // 本次运行的暂存表 <目标表>__datacp_stage
+    time_field: String,
+    time_zone: Option<time_zone::ColumnTz>,
+    timeout: Duration,
+    active: AtomicBool,               // 分段迁移阶段结束后不再暂存
+    created: AtomicBool,              // 暂存表已创建
+    segments: Mutex<HashSet<String>>, // 暂存表中有行、尚未清理的分段
+}
+
+// staged:/promoted: 记录中的分段与移入令牌（令牌为空表示目标端不支持去重令牌）
+fn entries<'a>(done: &'a HashSet<String>, prefix: &str) -> Vec<(&'a str, &'a str)> {
+    done.iter().filter_map(|l| l.strip_prefix(prefix)).map(|e| e.split_once('\t').unwrap_or((e, ""))).collect()
+}
+
+impl Stage {
+    // 未指定 --staged-inserts 时返回 None；沿用上次遗留的暂存表：移入已整段暂存的分段后清空
+    pub async fn new(opt: &Opt, done_segments_file: &str, done: &HashSet<String>) -> anyhow::Result<Option<Self>> {
+        if !opt.staged_inserts {
+            return Ok(None);
+        }
+        if opt.copy_mode != "http" {
+            anyhow::bail!(format!("--staged-inserts 只支持 --copy-mode http，当前为 {}", opt.copy_mode));
+        }
+        if !opt.page_key.is_empty() {
+            anyhow::bail!("--staged-inserts 不支持 --page-key：页标记推进时已写入的行仍在暂存表中");
+        }
+        if opt.dst_write_local {
+            anyhow::bail!("--staged-inserts 不支持 --dst-write-local：暂存表只在目标端入口所在节点");
+        }
+        let stage = Stage {
+            dsn: opt.dst_dsn.clone(),
+            db: opt.dst_db.clone(),
+            target: qualified(&opt.dst_db, &opt.dst_table),
+            table: qualified(&opt.dst_db, &format!("{}__datacp_stage", opt.dst_table)),
+            time_field: opt.time_field.clone(),
+            time_zone: opt.time_zone.clone(),
+            timeout: opt.ddl_timeout,
+            active: AtomicBool::new(true),
+            created: AtomicBool::new(false),
+            segments: Mutex::new(HashSet::new()),
+        };
+        let name = format!("{}__datacp_stage", opt.dst_table);
+        let sql = format!("SELECT name FROM system.tables WHERE database = '{}' AND name = '{}' FORMAT JSONEachRow", opt.dst_db, name);
+        let leftover = ch_query_rows(&stage.dsn, &stage.db, &sql).await?.iter().any(|r| r.get("name").and_then(|v| v.as_str()) == Some(name.as_str()));
+        // 续传：上次整段暂存但未确认移入的分段先移入（按同一令牌，已移入的块被去重）
+        let promoted: HashSet<(&str, &str)> = entries(done, PROMOTED_PREFIX).into_iter().collect();
+        let mut pending: Vec<(&str, &str)> = entries(done, STAGED_PREFIX).into_iter().filter(|e| !promoted.contains(e)).collect();
+        pending.sort();
+        if !leftover {
+            for (seg, _) in &pending {
+                warn!("staged-inserts: segment {seg} 的暂存表 {} 已不存在，分段重新比对写入", stage.table);
+            }
+            return Ok(Some(stage));
+        }
+        for (seg, token) in pending {
+            stage.insert_select(seg, token).await?;
+            save_done_segment(done_segments_file, &format!("{}{}\t{}", PROMOTED_PREFIX, seg, token))?;
+            info!("staged-inserts: segment {seg} 上次已整段暂存，已移入 {}", stage.target);
+        }
+        // 其余是已移入未清理或未完整写入的分段，分段重做时重新比对写入
+        ch_execute_timeout(&stage.dsn, &stage.db, &format!("TRUNCATE TABLE {}", stage.table), stage.timeout).await?;
+        stage.created.store(true, Ordering::Relaxed);
+        Ok(Some(stage))
+    }
+
+    // 暂存表中分段的行
+    fn condition(&self, seg: &str) -> String {
+        let (start, end) = segment::bounds(seg);
+        let tz = self.time_zone.as_ref();
+        format!("{} >= {} AND {} < {}", self.time_field, time_zone::lit(tz, &start), self.time_field, time_zone::lit(tz, &end))
+    }
+
+    // 单线程读取、整段合并为一个块写入，重新移入时块的划分与首次相同，令牌按块去重
+    async fn insert_select(&self, seg: &str, token: &str) -> anyhow::Result<()> {
+        let mut settings = vec![
+            ("max_threads".to_string(), "1".to_string()),
+            ("max_insert_threads".to_string(), "1".to_string()),
+            ("min_insert_block_size_rows".to_string(), u64::MAX.to_string()),
+            ("min_insert_block_size_bytes".to_string(), u64::MAX.to_string()),
+        ];
+        if !token.is_empty() {
+            settings.push(("insert_deduplication_token".to_string(), token.to_string()));
+        }
+        let sql = format!("INSERT INTO {} SELECT * FROM {} WHERE {}", self.target, self.table, self.condition(seg));
+        ch_execute_timeout(&dsn_with_settings(&self.dsn, &settings), &self.db, &sql, self.timeout).await
+    }
+
+    // 清理暂存表中分段的行，同步等待 mutation 完成
+    async fn clear(&self, seg: &str) -> anyhow::Result<()> {
+        let dsn = dsn_with_settings(&self.dsn, &[("mutations_sync".to_string(), "2".to_string())]);
+        ch_execute_timeout(&dsn, &self.db, &format!("ALTER TABLE {} DELETE WHERE {}", self.table, self.condition(seg)), self.timeout).await
+    }
+
+    // 分段即将写入暂存表：首次写入时创建暂存表并返回表名，分段迁移阶段结束后为 None
+    pub async fn staging(&self, seg: &str) -> anyhow::Result<Option<String>> {
+        if !self.active.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        if !self.created.load(Ordering::Relaxed) {
+            // 只取表结构：分布式或复制目标表的暂存表也是本地的普通 MergeTree
+            let create = format!("CREATE TABLE IF NOT EXISTS {} AS {} ENGINE = MergeTree ORDER BY tuple()", self.table, self.target);
+            ch_execute_timeout(&self.dsn, &self.db, &create, self.timeout).await?;
+            self.created.store(true, Ordering::Relaxed);
+        }
+        self.segments.lock().unwrap().insert(seg.to_string());
+        Ok(Some(self.table.clone()))
+    }
+
+    // 分段开始前：清理本次运行中该分段上次失败留下的暂存行，避免重试时重复移入
+    pub async fn prepare(&self, seg: &str) -> anyhow::Result<()> {
+        if !self.segments.lock().unwrap().contains(seg) {
+            return Ok(());
+        }
+        self.clear(seg).await?;
+        self.segments.lock().unwrap().remove(seg);
+        Ok(())
+    }
+
+    // 分段的批次全部写入暂存表后一次性移入目标表；complete 为 false（有批次失败）时不移入
+    pub async fn promote(&self, ctx: &RunCtx, done_segments_file: &str, seg: &str, complete: bool) -> anyhow::Result<()> {
+        if !self.segments.lock().unwrap().contains(seg) {
+            return Ok(());
+        }
+        if !complete {
+            anyhow::bail!("部分批次写入暂存表失败，暂存行不移入目标表");
+        }
+        let token = server_version::dedup_token(&self.dsn).into_iter().next().map(|(_, t)| t).unwrap_or_default();
+        save_done_segment(done_segments_file, &format!("{}{}\t{}", STAGED_PREFIX, seg, token))?;
+        ctx.write_gate.wait_writable().await;
+        self.insert_select(seg, &token).await.map_err(|e| anyhow::anyhow!(format!("暂存行移入目标表失败: {e}")))?;
+        save_done_segment(done_segments_file, &format!("{}{}\t{}", PROMOTED_PREFIX, seg, token))?;
+        match self.clear(seg).await {
+            Ok(()) => {
+                self.segments.lock().unwrap().remove(seg);
+            }
+            // 移入按分段条件读取，残留的行不影响其他分段；分段重做前再清理，分段迁移阶段结束时随暂存表删除
+            Err(e) => warn!("segment {seg} 已移入目标表，清理暂存表 {} 中的行失败: {e}", self.table),
+        }
+        Ok(())
+    }
+
+    // 分段迁移阶段结束：删除暂存表，之后的写入直接进入目标表
+    pub async fn finish(&self) {
+        self.active.store(false, Ordering::Relaxed);
+        self.segments.lock().unwrap().clear();
+        if !self.created.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        match ch_execute_timeout(&self.dsn, &self.db, &format!("DROP TABLE IF EXISTS {}", self.table), self.timeout).await {
+            Ok(()) => info!("staged-inserts: 已删除暂存表 {}", self.table),
+            Err(e) => warn!("staged-inserts: 删除暂存表 {} 失败，请手动删除: {e}", self.table),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_ch;
+    use structopt::StructOpt;
+
+    // 上次运行遗留了暂存表
+    fn leftover(sql: &str) -> (u16, String) {
+        if sql.contains("FROM system.tables") {
+            (200, "{\"name\":\"events_new__datacp_stage\"}\n".to_string())
+        } else {
+            (200, String::new())
+        }
+    }
+
+    // 00:00 移入后、记录 promoted 前进程被结束，02:00 记录 promoted 后、清理前进程被结束：
+    // 续传按同一令牌重新移入 00:00 并清空暂存表；再次中断后续传不再重复移入
+    #[tokio::test]
+    async fn resume_repromotes_after_crash_between_insert_and_cleanup() {
+        let (dsn, seen) = mock_ch::serve(leftover).await;
+        let dir = std::env::temp_dir().join(format!("datacp_staged_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("done_segments.txt").to_string_lossy().to_string();
+        let opt = Opt::from_iter([
+            "datacp", "--dst-dsn", &dsn, "--dst-db", "app_new", "--dst-table", "events_new", "--time-field", "ts", "--staged-inserts",
+        ]);
+        let mut done: HashSet<String> = ["staged:2024-01-01 00:00:00\ttok-0", "staged:2024-01-01 02:00:00\ttok-2", "promoted:2024-01-01 02:00:00\ttok-2"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let stage = Stage::new(&opt, &file, &done).await.unwrap().unwrap();
+        let recorded = std::fs::read_to_string(&file).unwrap();
+        assert_eq!(recorded, "promoted:2024-01-01 00:00:00\ttok-0\n");
+        let stmts = |seen: &mock_ch::Seen| seen.lock().unwrap().drain(..).skip(1).map(|(_, s)| s).collect::<Vec<String>>();
+        assert_eq!(
+            stmts(&seen),
+            [
+                "INSERT INTO app_new.events_new SELECT * FROM app_new.events_new__datacp_stage WHERE ts >= '2024-01-01 00:00:00' AND ts < '2024-01-01 01:00:00'",
+                "TRUNCATE TABLE app_new.events_new__datacp_stage",
+            ]
+        );
+        done.extend(recorded.lines().map(|l| l.to_string()));
+        Stage::new(&opt, &file, &done).await.unwrap().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(stmts(&seen), ["TRUNCATE TABLE app_new.events_new__datacp_stage"]);
+        assert_eq!(
+            stage.condition("2024-01-01 03:00:00..2024-01-01 03:30:00"),
+            "ts >= '2024-01-01 03:00:00' AND ts < '2024-01-01 03:30:00'"
+        );
+    }
+}
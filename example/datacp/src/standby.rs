@@ -0,0 +1,220 @@
+// ===================== 热备模式（--standby） =====================
+// 回填完成后不自动切换：持续增量，并按 --standby-verify-interval 重扫最近 --standby-verify-window 的数据比对补写，
+// 目标表与源端保持秒级差距，定期记录复制延迟与校验情况（日志、状态接口、报告文件）。
+// 切换由人工触发：控制文件（--control-file）写入 cutover、POST /cutover 到状态接口，或向进程发送 SIGUSR1；
+// 收到后派发最后一轮增量并检查目标端副本延迟，随后进入 _bak 补差与 rename。
+// 在此之前收到 cancel（控制文件写入 cancel 或 POST /cancel）则回到热备，不做任何 rename；
+// 控制文件写入 pause / resume 时暂停或继续领取新分段（同 POST /pause、/resume）
+
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::report::{self, RunReport, StandbyCheck};
+use crate::{plan_segments, status, Opt, RunCtx, SegmentBlacklist, WorkerPool};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Cutover,
+    Cancel,
+}
+
+static CONTROL_FILE: OnceLock<String> = OnceLock::new();
+static PENDING: Mutex<Option<Command>> = Mutex::new(None);
+static LAST_CHECK: Mutex<Option<StandbyCheck>> = Mutex::new(None);
+
+pub fn enabled() -> bool {
+    CONTROL_FILE.get().is_some()
+}
+
+// 收到指令（控制文件、状态接口、信号）；未启用热备时返回 false
+pub fn request(cmd: Command, from: &str) -> bool {
+    if !enabled() {
+        return false;
+    }
+    info!("热备模式: 收到 {:?} 指令（{}）", cmd, from);
+    *PENDING.lock().unwrap() = Some(cmd);
+    true
+}
+
+// 读取控制文件中的指令，读取后删除文件
+fn read_control_file() {
+    let Some(path) = CONTROL_FILE.get() else { return };
+    let Ok(text) = std::fs::read_to_string(path) else { return };
+    if let Err(e) = std::fs::remove_file(path) {
+        warn!("删除控制文件 {} 失败: {}", path, e);
+    }
+    match text.trim().to_ascii_lowercase().as_str() {
+        "cutover" => {
+            request(Command::Cutover, "control file");
+        }
+        "cancel" => {
+            request(Command::Cancel, "control file");
+        }
+        "pause" => status::hold(true),
+        "resume" => status::hold(false),
+        other => warn!("控制文件 {} 内容无法识别: {}（cutover / cancel / pause / resume）", path, other),
+    }
+}
+
+// 切换前检查期间等待 cancel；不取走 cutover 指令
+async fn wait_cancel() {
+    loop {
+        read_control_file();
+        {
+            let mut pending = PENDING.lock().unwrap();
+            if *pending == Some(Command::Cancel) {
+                *pending = None;
+                return;
+            }
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+// 状态接口中的热备信息
+pub fn status_json() -> Value {
+    if !enabled() {
+        return Value::Null;
+    }
+    json!({
+        "control_file": CONTROL_FILE.get(),
+        "pending": PENDING.lock().unwrap().map(|c| format!("{:?}", c).to_ascii_lowercase()),
+        "last_check": *LAST_CHECK.lock().unwrap(),
+    })
+}
+
+pub struct Standby {
+    verify_window: Duration,
+    verify_interval: Duration,
+    last_verify: Instant,
+    report_file: String,
+}
+
+impl Standby {
+    // 未指定 --standby 时返回 None
+    pub fn new(opt: &Opt) -> anyhow::Result<Option<Self>> {
+        if !opt.standby {
+            return Ok(None);
+        }
+        if opt.archive || opt.no_cutover || !opt.cutover_when.is_empty() || !opt.cutover_at.is_empty() {
+            anyhow::bail!("--standby 由人工触发切换，不能与 --archive / --no-cutover / --cutover-when / --cutover-at 同时使用");
+        }
+        let _ = CONTROL_FILE.set(opt.control_file.clone());
+        #[cfg(unix)]
+        {
+            let mut sig = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())
+                .map_err(|e| anyhow::anyhow!(format!("注册 SIGUSR1 失败: {}", e)))?;
+            tokio::spawn(async move {
+                while sig.recv().await.is_some() {
+                    request(Command::Cutover, "SIGUSR1");
+                }
+            });
+        }
+        info!(
+            "热备模式: 回填后持续增量，每 {:?} 重扫最近 {:?} 校验；切换指令: 控制文件 {} 写入 cutover / cancel，POST /cutover / /cancel（--status-listen），或 SIGUSR1",
+            opt.standby_verify_interval, opt.standby_verify_window, opt.control_file
+        );
+        Ok(Some(Standby {
+            verify_window: opt.standby_verify_window,
+            verify_interval: opt.standby_verify_interval,
+            last_verify: Instant::now(),
+            report_file: opt.report_file.clone(),
+        }))
+    }
+
+    // 每轮增量调用：收到 cutover 时返回触发原因，调用方立即派发最后一轮增量
+    pub fn poll(&mut self) -> Option<&'static str> {
+        read_control_file();
+        match PENDING.lock().unwrap().take() {
+            Some(Command::Cutover) => {
+                info!("热备模式: 开始切换，派发最后一轮增量");
+                Some("standby")
+            }
+            Some(Command::Cancel) => {
+                info!("热备模式: 没有进行中的切换，忽略 cancel");
+                None
+            }
+            None => None,
+        }
+    }
+
+    // 最后一轮增量完成后检查目标端副本延迟（等待期间可被 cancel 打断），再确认没有 cancel；返回 false 时回到热备
+    pub async fn confirm(&mut self, opt: &Opt, report: &Arc<Mutex<RunReport>>) -> bool {
+        let res = tokio::select! {
+            r = crate::wait_for_dst_replica_lag(opt, report) => r.map(|_| true),
+            _ = wait_cancel() => Ok(false),
+        };
+        match res {
+            Ok(true) => {
+                read_control_file();
+                if PENDING.lock().unwrap().take() == Some(Command::Cancel) {
+                    info!("热备模式: 切换已取消，回到热备，未执行任何 rename");
+                    return false;
+                }
+                info!("热备模式: 进入 _bak 补差与 rename，此后 cancel 不再生效");
+                true
+            }
+            Ok(false) => {
+                info!("热备模式: 切换已取消，回到热备，未执行任何 rename");
+                false
+            }
+            Err(e) => {
+                warn!("热备模式: 切换前检查未通过，回到热备: {e}");
+                false
+            }
+        }
+    }
+
+    // 到达间隔时重扫 [cur_max - window, cur_max) 比对补写，记录复制延迟与校验结果，并写出当前报告
+    pub async fn verify(
+        &mut self,
+        ctx: &RunCtx,
+        pool: &WorkerPool,
+        cur_max: &str,
+        lag: u64,
+        blacklist: &SegmentBlacklist,
+        report: &Arc<Mutex<RunReport>>,
+    ) {
+        if self.last_verify.elapsed() < self.verify_interval {
+            return;
+        }
+        self.last_verify = Instant::now();
+        let Ok(max) = chrono::NaiveDateTime::parse_from_str(cur_max, "%Y-%m-%d %H:%M:%S") else { return };
+        let from = (max - chrono::Duration::from_std(self.verify_window).unwrap_or_else(|_| chrono::Duration::hours(1)))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        let written = ctx.rows_written.load(std::sync::atomic::Ordering::Relaxed);
+        let failed = ctx.failed_segments.lock().unwrap().len();
+        match plan_segments(&from, cur_max, &Default::default(), blacklist, None) {
+            Ok(segments) => pool.recheck(segments).await,
+            Err(e) => warn!("热备校验分段生成失败: {e}"),
+        }
+        let check = StandbyCheck {
+            time: report::now_str(),
+            lag_seconds: lag,
+            verify_from: from,
+            verify_to: cur_max.to_string(),
+            rows_repaired: ctx.rows_written.load(std::sync::atomic::Ordering::Relaxed).saturating_sub(written),
+            failed_segments: ctx.failed_segments.lock().unwrap().len().saturating_sub(failed),
+        };
+        let healthy = check.rows_repaired == 0 && check.failed_segments == 0;
+        let msg = format!(
+            "热备模式: lag={}s，重扫 {} ~ {} 补写 {} 行、失败分段 {}，等待切换指令",
+            check.lag_seconds, check.verify_from, check.verify_to, check.rows_repaired, check.failed_segments
+        );
+        if healthy {
+            info!("{}", msg);
+        } else {
+            warn!("{}", msg);
+        }
+        *LAST_CHECK.lock().unwrap() = Some(check.clone());
+        status::progress();
+        let mut r = report.lock().unwrap();
+        r.standby_checks.push(check);
+        if let Err(e) = r.write(&self.report_file) {
+            warn!("写入报告失败: {e}");
+        }
+    }
+}
@@ -0,0 +1,98 @@
+// ===================== 运行文件目录（--state-dir） =====================
+// 断点续传文件（及 .meta/.pages/.paused_mvs 与切换后归档的副本）、日志、报告、死信文件、SQL 审计日志、热备控制文件
+// 都写在 <state-dir>/<src_db>.<src_table>__<dst_db>.<dst_table>/ 下，不随启动目录变化，不同表的任务互不冲突。
+// 多表迁移与子命令的进程级文件（日志、汇总报告）写在 <state-dir> 下，各表断点续传文件在各自的子目录。
+// 相对路径的 --log-file/--done-segments/--report-file 等按该目录解析，绝对路径原样使用
+
+use log::warn;
+use std::path::{Path, PathBuf};
+
+use crate::{shard_of, Command, Opt};
+
+// 单表运行文件目录
+pub fn table_dir(opt: &Opt) -> PathBuf {
+    Path::new(&opt.state_dir).join(format!("{}.{}__{}.{}", opt.src_db, opt.src_table, opt.dst_db, opt.dst_table))
+}
+
+// resume-cutover 继续单表的切换，文件仍在表目录下
+fn process_level(opt: &Opt) -> bool {
+    !opt.tables_file.is_empty() || opt.all_tables || opt.cmd.as_ref().is_some_and(|c| !matches!(c, Command::ResumeCutover))
+}
+
+// 进程级文件（日志、报告、SQL 审计日志、死信文件）所在目录
+pub fn run_dir(opt: &Opt) -> PathBuf {
+    if process_level(opt) {
+        PathBuf::from(&opt.state_dir)
+    } else {
+        table_dir(opt)
+    }
+}
+
+pub fn resolve(dir: &Path, name: &str) -> String {
+    if Path::new(name).is_absolute() {
+        name.to_string()
+    } else {
+        dir.join(name).to_string_lossy().to_string()
+    }
+}
+
+// 启动时（日志初始化之前）创建目录，并把各输出文件解析为最终路径
+pub fn prepare(opt: &mut Opt) -> anyhow::Result<()> {
+    let dir = run_dir(opt);
+    std::fs::create_dir_all(&dir).map_err(|e| anyhow::anyhow!(format!("创建 --state-dir 目录 {} 失败: {}", dir.display(), e)))?;
+    opt.log_file = resolve(&dir, &opt.log_file);
+    for f in [&mut opt.sql_log, &mut opt.dead_letter_file, &mut opt.report_file, &mut opt.done_segments, &mut opt.control_file] {
+        if !f.is_empty() {
+            *f = resolve(&dir, f);
+        }
+    }
+    if opt.report_file.is_empty() {
+        let name = if process_level(opt) {
+            format!("report_multi{}.json", shard_of::suffix(opt))
+        } else {
+            format!("report_{}_to_{}{}.json", opt.src_table, opt.dst_table, shard_of::suffix(opt))
+        };
+        opt.report_file = resolve(&dir, &name);
+    }
+    Ok(())
+}
+
+// 断点续传文件：--done-segments 已由 prepare 解析；未指定时为表目录下的 done_segments_<src>_to_<dst>.txt，
+// 新位置没有而旧版本的默认位置（启动目录、多表迁移的 --state-dir）有时沿用旧文件，避免从头迁移
+pub fn done_segments(opt: &Opt) -> anyhow::Result<String> {
+    if !opt.done_segments.is_empty() {
+        return Ok(opt.done_segments.clone());
+    }
+    let dir = table_dir(opt);
+    std::fs::create_dir_all(&dir).map_err(|e| anyhow::anyhow!(format!("创建 --state-dir 目录 {} 失败: {}", dir.display(), e)))?;
+    // --shard-of 时文件名带分片编号，如 done_segments_a_to_b_shard0of4.txt
+    let shard = shard_of::suffix(opt);
+    let name = format!("done_segments_{}_to_{}{}.txt", opt.src_table, opt.dst_table, shard);
+    let path = dir.join(&name);
+    if !path.exists() && shard.is_empty() {
+        let multi_name = format!("done_segments_{}.{}_to_{}.{}.txt", opt.src_db, opt.src_table, opt.dst_db, opt.dst_table);
+        let legacy = [PathBuf::from(&name), Path::new(&opt.state_dir).join(&multi_name), Path::new("datacp_state").join(&multi_name)];
+        if let Some(old) = legacy.iter().find(|p| p.exists()) {
+            warn!("沿用旧位置的断点续传文件 {}（新位置为 {}，可手动移动）", old.display(), path.display());
+            return Ok(old.to_string_lossy().to_string());
+        }
+    }
+    Ok(path.to_string_lossy().to_string())
+}
+
+// 运行结束时打印的文件位置
+pub fn summary(opt: &Opt, done_segments_file: Option<&str>) -> String {
+    let mut parts = vec![format!("目录 {}", run_dir(opt).display())];
+    if let Some(f) = done_segments_file {
+        parts.push(format!("断点续传 {}", f));
+    }
+    parts.push(format!("日志 {}", opt.log_file));
+    parts.push(format!("报告 {}", opt.report_file));
+    if !opt.sql_log.is_empty() {
+        parts.push(format!("SQL 审计日志 {}", opt.sql_log));
+    }
+    if !opt.dead_letter_file.is_empty() {
+        parts.push(format!("死信文件 {}", opt.dead_letter_file));
+    }
+    parts.join(", ")
+}
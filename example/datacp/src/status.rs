@@ -0,0 +1,398 @@
+// ===================== 本地状态接口（--status-listen） =====================
+// 长时间运行（容器内）时用 HTTP 查看实时状态，不读断点续传文件：
+// GET /status 返回 JSON（run_id、脱敏后的生效配置、阶段、分段进度、写入行数、吞吐、各 worker 当前分段、最近错误、运行时长）；
+// GET /healthz 在 --status-stall-after 内有进展时返回 200，否则 503，可作为 Kubernetes 存活探针；
+// GET /metrics 以 Prometheus 文本格式输出分段、行数、字节、复制延迟与阶段（与 --pushgateway-url 推送的是同一组指标）；
+// 热备模式（--standby）下 POST /cutover 触发切换，POST /cancel 取消尚未开始 rename 的切换；
+// POST /pause 使 worker 做完手上的分段后暂停领取新分段，POST /resume 继续（--tui 的 p / r 键相同）。
+// 同一份状态也驱动 --tui 仪表盘（分段热力图、各 worker 当前分段与阶段）。
+// 接口没有认证，只应监听 127.0.0.1（只写端口如 :9185 时即绑定 127.0.0.1）
+
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::report::SegmentBytes;
+use crate::standby::{self, Command};
+use crate::{blackout, health, Opt, RunCtx};
+
+// 吞吐按最近这段时间内的写入行数计算
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+struct State {
+    run_id: String,
+    started: Instant,
+    stall_after: Duration,
+    table: Mutex<String>,
+    config: Mutex<Value>,
+    phase: Mutex<String>,
+    ctx: Mutex<Option<Arc<RunCtx>>>,
+    total: AtomicU64,    // 本表已入队的分段数
+    finished: AtomicU64, // 本表已处理完的分段数（含失败）
+    active: Mutex<BTreeMap<usize, (String, Instant, &'static str)>>, // worker → (分段, 开始时间, 阶段)
+    grid: Mutex<BTreeMap<String, Cell>>,                             // 本表已入队分段的状态
+    last_error: Mutex<Option<(String, String)>>, // (时间, 内容)
+    last_progress: Mutex<Instant>,
+    samples: Mutex<VecDeque<(Instant, u64)>>, // (时间, 累计写入行数)
+    lag: Mutex<Option<u64>>,                   // 最近一轮增量的复制延迟（秒），进入增量前为空
+    bytes_read: AtomicU64,                     // 本表已完成分段的源端读取字节
+    bytes_wire: AtomicU64,                     // 本表已完成分段的线路字节
+}
+
+static STATE: OnceLock<State> = OnceLock::new();
+
+// 人工暂停（POST /pause、--tui、热备控制文件 pause），与维护窗口相互独立
+static HELD: AtomicBool = AtomicBool::new(false);
+
+// 分段热力图中单个分段的状态
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cell {
+    Pending,
+    Active,
+    Done,
+    Failed,
+}
+
+// DSN 中的密码替换为 ***
+fn redact(v: Value) -> Value {
+    let re = regex::Regex::new(r"(://[^:@/]*:)[^@/]*@").unwrap();
+    match v {
+        Value::String(s) => Value::String(re.replace_all(&s, "$1***@").to_string()),
+        Value::Array(a) => Value::Array(a.into_iter().map(redact).collect()),
+        Value::Object(o) => Value::Object(o.into_iter().map(|(k, v)| (k, redact(v))).collect()),
+        other => other,
+    }
+}
+
+pub fn config_json(opt: &Opt) -> Value {
+    redact(serde_json::to_value(opt).unwrap_or(Value::Null))
+}
+
+// 进程启动时调用；未指定 --status-listen 时只记录状态，不监听
+pub async fn init(opt: &Opt) -> anyhow::Result<()> {
+    let _ = STATE.set(State {
+        run_id: format!("{}-{}", chrono::Local::now().format("%Y%m%d%H%M%S"), std::process::id()),
+        started: Instant::now(),
+        stall_after: opt.status_stall_after,
+        table: Mutex::new(format!("{}.{}", opt.src_db, opt.src_table)),
+        config: Mutex::new(config_json(opt)),
+        phase: Mutex::new("startup".to_string()),
+        ctx: Mutex::new(None),
+        total: AtomicU64::new(0),
+        finished: AtomicU64::new(0),
+        active: Mutex::new(BTreeMap::new()),
+        grid: Mutex::new(BTreeMap::new()),
+        last_error: Mutex::new(None),
+        last_progress: Mutex::new(Instant::now()),
+        samples: Mutex::new(VecDeque::new()),
+        lag: Mutex::new(None),
+        bytes_read: AtomicU64::new(0),
+        bytes_wire: AtomicU64::new(0),
+    });
+    if opt.status_listen.is_empty() {
+        return Ok(());
+    }
+    let addr = if opt.status_listen.starts_with(':') { format!("127.0.0.1{}", opt.status_listen) } else { opt.status_listen.clone() };
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .map_err(|e| anyhow::anyhow!(format!("--status-listen 监听 {} 失败: {}", addr, e)))?;
+    if !addr.starts_with("127.") && !addr.starts_with("localhost") && !addr.starts_with("[::1]") {
+        warn!("状态接口监听 {}，接口没有认证，建议只绑定 127.0.0.1", addr);
+    }
+    info!("状态接口: http://{}/status, http://{}/healthz", addr, addr);
+    // 随进程退出，不单独关闭
+    tokio::spawn(async move {
+        loop {
+            if let Ok((sock, _)) = listener.accept().await {
+                tokio::spawn(handle(sock));
+            }
+        }
+    });
+    Ok(())
+}
+
+// 开始迁移一张表（多表迁移时逐表调用），计数从零开始
+pub fn begin_table(opt: &Opt, ctx: &Arc<RunCtx>) {
+    let Some(s) = STATE.get() else { return };
+    *s.table.lock().unwrap() = format!("{}.{}", opt.src_db, opt.src_table);
+    *s.config.lock().unwrap() = config_json(opt);
+    *s.ctx.lock().unwrap() = Some(ctx.clone());
+    s.total.store(0, Ordering::Relaxed);
+    s.finished.store(0, Ordering::Relaxed);
+    s.active.lock().unwrap().clear();
+    s.grid.lock().unwrap().clear();
+    s.samples.lock().unwrap().clear();
+    *s.lag.lock().unwrap() = None;
+    s.bytes_read.store(0, Ordering::Relaxed);
+    s.bytes_wire.store(0, Ordering::Relaxed);
+    progress();
+}
+
+// startup / backfill / incremental / standby / bak / cutover / archive / done
+pub fn set_phase(phase: &str) {
+    crate::events::phase_changed(phase);
+    let Some(s) = STATE.get() else { return };
+    *s.phase.lock().unwrap() = phase.to_string();
+    progress();
+}
+
+// 有进展（领取/完成分段、写入一批、增量检查一轮），/healthz 据此判断是否停滞
+pub fn progress() {
+    if let Some(s) = STATE.get() {
+        *s.last_progress.lock().unwrap() = Instant::now();
+    }
+}
+
+// 距上次进展的时长，/healthz 与 --global-stall-timeout 看门狗共用；未初始化时为 None
+pub fn since_progress() -> Option<Duration> {
+    STATE.get().map(|s| s.last_progress.lock().unwrap().elapsed())
+}
+
+// 日志中的 ERROR 记为最近错误
+pub fn error(msg: &str) {
+    if let Some(s) = STATE.get() {
+        *s.last_error.lock().unwrap() = Some((chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(), msg.to_string()));
+    }
+}
+
+// 每轮增量检查时更新复制延迟
+pub fn set_lag(secs: u64) {
+    if let Some(s) = STATE.get() {
+        *s.lag.lock().unwrap() = Some(secs);
+    }
+}
+
+pub fn segments_queued(segments: &[String]) {
+    if let Some(s) = STATE.get() {
+        s.total.fetch_add(segments.len() as u64, Ordering::Relaxed);
+        let mut grid = s.grid.lock().unwrap();
+        for seg in segments {
+            grid.insert(seg.clone(), Cell::Pending);
+        }
+    }
+}
+
+pub fn segment_started(worker: usize, seg: &str) {
+    if let Some(s) = STATE.get() {
+        s.active.lock().unwrap().insert(worker, (seg.to_string(), Instant::now(), "read_src"));
+        s.grid.lock().unwrap().insert(seg.to_string(), Cell::Active);
+        progress();
+    }
+}
+
+// 分段计时器每完成一个阶段调用，记录 worker 接下来所处的阶段
+pub fn worker_phase(worker: usize, phase: &'static str) {
+    if let Some(s) = STATE.get() {
+        if let Some(a) = s.active.lock().unwrap().get_mut(&worker) {
+            a.2 = phase;
+        }
+    }
+}
+
+// 各 worker 当前的分段、已进行时长与阶段
+pub fn in_flight() -> Vec<(usize, String, Duration, &'static str)> {
+    let Some(s) = STATE.get() else { return Vec::new() };
+    s.active.lock().unwrap().iter().map(|(w, (seg, t, phase))| (*w, seg.clone(), t.elapsed(), *phase)).collect()
+}
+
+pub fn segment_finished(worker: usize) {
+    let Some(s) = STATE.get() else { return };
+    let seg = s.active.lock().unwrap().remove(&worker).map(|a| a.0);
+    s.finished.fetch_add(1, Ordering::Relaxed);
+    if let Some(ctx) = s.ctx.lock().unwrap().as_ref() {
+        sample(s, ctx.rows_written.load(Ordering::Relaxed));
+        if let Some(seg) = seg {
+            let failed = ctx.failed_segments.lock().unwrap().contains(&seg);
+            s.grid.lock().unwrap().insert(seg, if failed { Cell::Failed } else { Cell::Done });
+        }
+    }
+    progress();
+}
+
+// 分段完成时累计传输字节
+pub fn segment_bytes(b: &SegmentBytes) {
+    if let Some(s) = STATE.get() {
+        s.bytes_read.fetch_add(b.uncompressed_bytes, Ordering::Relaxed);
+        s.bytes_wire.fetch_add(b.wire_bytes, Ordering::Relaxed);
+    }
+}
+
+pub fn hold(on: bool) {
+    if HELD.swap(on, Ordering::SeqCst) != on {
+        if on {
+            warn!("已人工暂停：当前分段完成后不再领取新分段，resume 后继续");
+        } else {
+            info!("已取消人工暂停，继续迁移");
+        }
+    }
+}
+
+pub fn held() -> bool {
+    HELD.load(Ordering::SeqCst)
+}
+
+// worker 领取分段后、开始处理前调用：人工暂停期间等待
+pub async fn wait_if_held() {
+    while held() {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        progress();
+    }
+}
+
+// 状态快照（与 GET /status 相同），供 --tui 渲染
+pub fn snapshot() -> Option<Value> {
+    STATE.get().map(status_json)
+}
+
+// 本表已入队分段的状态，按分段时间排序
+pub fn grid() -> Vec<(String, Cell)> {
+    STATE.get().map(|s| s.grid.lock().unwrap().iter().map(|(k, v)| (k.clone(), *v)).collect()).unwrap_or_default()
+}
+
+fn sample(s: &State, rows: u64) {
+    let mut samples = s.samples.lock().unwrap();
+    samples.push_back((Instant::now(), rows));
+    while samples.len() > 2 && samples.front().map(|(t, _)| t.elapsed() > RATE_WINDOW).unwrap_or(false) {
+        samples.pop_front();
+    }
+}
+
+// 最近一段时间的写入速率（行/秒）
+fn rows_per_sec(s: &State) -> f64 {
+    let samples = s.samples.lock().unwrap();
+    match (samples.front(), samples.back()) {
+        (Some((t0, r0)), Some((t1, r1))) if t1 > t0 => r1.saturating_sub(*r0) as f64 / (*t1 - *t0).as_secs_f64(),
+        _ => 0.0,
+    }
+}
+
+// --supervise 等待重启、--post-cutover-watch 观察期间没有进展属正常
+fn stalled(s: &State) -> bool {
+    !matches!(s.phase.lock().unwrap().as_str(), "done" | "restart-backoff" | "post-cutover-watch") && s.last_progress.lock().unwrap().elapsed() > s.stall_after
+}
+
+fn status_json(s: &State) -> Value {
+    let ctx = s.ctx.lock().unwrap().clone();
+    let failed = ctx.as_ref().map(|c| c.failed_segments.lock().unwrap().len() as u64).unwrap_or(0);
+    let finished = s.finished.load(Ordering::Relaxed);
+    let active: Vec<Value> = s
+        .active
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(w, (seg, t, phase))| json!({ "worker": w, "segment": seg, "seconds": t.elapsed().as_secs(), "phase": phase }))
+        .collect();
+    json!({
+        "run_id": s.run_id,
+        "table": *s.table.lock().unwrap(),
+        "phase": *s.phase.lock().unwrap(),
+        "uptime_seconds": s.started.elapsed().as_secs(),
+        "segments": {
+            "total": s.total.load(Ordering::Relaxed),
+            "done": finished.saturating_sub(failed),
+            "failed": failed,
+        },
+        "rows_read": ctx.as_ref().map(|c| c.rows_read.load(Ordering::Relaxed)).unwrap_or(0),
+        "rows_inserted": ctx.as_ref().map(|c| c.rows_written.load(Ordering::Relaxed)).unwrap_or(0),
+        "rows_per_sec": (rows_per_sec(s) * 10.0).round() / 10.0,
+        "replication_lag_seconds": *s.lag.lock().unwrap(),
+        "active_segments": active,
+        "last_error": s.last_error.lock().unwrap().as_ref().map(|(t, m)| json!({ "time": t, "message": m })),
+        "seconds_since_progress": s.last_progress.lock().unwrap().elapsed().as_secs(),
+        "stalled": stalled(s),
+        "paused": held(),
+        "standby": standby::status_json(),
+        "health": health::status_json(),
+        "blackout": json!({ "paused": blackout::pausing(), "paused_seconds": blackout::paused_seconds() }),
+        "config": *s.config.lock().unwrap(),
+    })
+}
+
+pub fn run_id() -> String {
+    STATE.get().map(|s| s.run_id.clone()).unwrap_or_default()
+}
+
+// 单个指标：HELP、TYPE 与一行取值
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, labels: &str, value: impl std::fmt::Display) {
+    out.push_str(&format!("# HELP {} {}\n# TYPE {} {}\n{}{{{}}} {}\n", name, help, name, kind, name, labels, value));
+}
+
+// Prometheus 文本格式，/metrics 与 pushgateway 共用；尚未进入增量阶段时不输出复制延迟
+pub fn metrics() -> String {
+    let Some(s) = STATE.get() else { return String::new() };
+    let v = status_json(s);
+    let table = format!("table=\"{}\"", s.table.lock().unwrap());
+    let mut out = String::new();
+    let segments = &v["segments"];
+    metric(&mut out, "datacp_segments_total", "gauge", "Segments queued for the current table.", &table, &segments["total"]);
+    metric(&mut out, "datacp_segments_done", "gauge", "Segments finished without error.", &table, &segments["done"]);
+    metric(&mut out, "datacp_segments_failed", "gauge", "Segments that failed.", &table, &segments["failed"]);
+    metric(&mut out, "datacp_rows_read", "counter", "Rows read from the source.", &table, &v["rows_read"]);
+    metric(&mut out, "datacp_rows_inserted", "counter", "Rows written to the destination.", &table, &v["rows_inserted"]);
+    metric(&mut out, "datacp_bytes_read", "counter", "Uncompressed source response bytes of finished segments.", &table, s.bytes_read.load(Ordering::Relaxed));
+    metric(&mut out, "datacp_bytes_wire", "counter", "Bytes on the wire for finished segments, including retries.", &table, s.bytes_wire.load(Ordering::Relaxed));
+    if let Some(lag) = *s.lag.lock().unwrap() {
+        metric(&mut out, "datacp_replication_lag_seconds", "gauge", "Source max(time_field) minus the end of the latest fully-done segment.", &table, lag);
+    }
+    let phase = format!("{},phase=\"{}\"", table, s.phase.lock().unwrap());
+    metric(&mut out, "datacp_phase", "gauge", "Current phase of the run (always 1).", &phase, 1);
+    out
+}
+
+// 每个连接只处理一个请求，响应后关闭
+async fn handle(mut sock: tokio::net::TcpStream) {
+    let mut buf = [0u8; 4096];
+    let n = match tokio::time::timeout(Duration::from_secs(5), sock.read(&mut buf)).await {
+        Ok(Ok(n)) => n,
+        _ => return,
+    };
+    let req = String::from_utf8_lossy(&buf[..n]);
+    let mut parts = req.lines().next().unwrap_or("").split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let path = path.split('?').next().unwrap_or("");
+    let Some(s) = STATE.get() else { return };
+    let mut content_type = "application/json";
+    let (code, body) = match (method, path) {
+        ("GET", "/status") => (200, status_json(s).to_string()),
+        ("GET", "/metrics") => {
+            content_type = "text/plain; version=0.0.4";
+            (200, metrics())
+        }
+        ("GET", "/healthz") if stalled(s) => (503, json!({ "status": "stalled", "seconds_since_progress": s.last_progress.lock().unwrap().elapsed().as_secs() }).to_string()),
+        ("GET", "/healthz") => (200, json!({ "status": "ok" }).to_string()),
+        ("POST", "/pause") | ("POST", "/resume") => {
+            hold(path == "/pause");
+            (202, json!({ "accepted": path.trim_start_matches('/') }).to_string())
+        }
+        ("POST", "/cutover") | ("POST", "/cancel") => {
+            let cmd = if path == "/cutover" { Command::Cutover } else { Command::Cancel };
+            if standby::request(cmd, "POST") {
+                (202, json!({ "accepted": path.trim_start_matches('/') }).to_string())
+            } else {
+                (409, json!({ "error": "not in --standby mode" }).to_string())
+            }
+        }
+        _ => (404, json!({ "error": "not found" }).to_string()),
+    };
+    let reason = match code {
+        200 => "OK",
+        202 => "Accepted",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Not Found",
+    };
+    let resp = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        reason,
+        content_type,
+        body.len(),
+        body
+    );
+    let _ = sock.write_all(resp.as_bytes()).await;
+    let _ = sock.shutdown().await;
+}
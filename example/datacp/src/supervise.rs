@@ -0,0 +1,115 @@
+// ===================== 监督运行（--supervise / --max-restarts / --restart-backoff） =====================
+// 调度器每个窗口只能启动一次命令时，迁移中途集群重启之类的临时故障会让整个运行失败，需要人工重新启动。
+// --supervise 时顶层进程在本进程内（不启动子进程）执行迁移：整体失败且按 ClickHouse 错误分类可重试
+// （连接失败、5xx、Retry / WaitRetry 类错误码，或熔断时的首个错误属于这些）时，等待 --restart-backoff、
+// 重置熔断器并重新读取断点续传记录后再次运行，最多重启 --max-restarts 次。
+// 表结构不一致、鉴权、语法等错误（Fatal / Data 类错误码，以及 datacp 自身的校验错误）立即退出；
+// 切换开始后失败、超过 --max-duration 与部分分段失败同样不重启。
+// 每次尝试记入报告 attempts；Ctrl-C 直接结束监督进程（退出码 130），不会被当作失败触发重启
+
+use log::{error, info, warn};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::report::{RunAttempt, RunReport};
+use crate::{ch_error_code, classify_ch_error, merge_pause, pushgateway, sql_log, tui, ChErrorClass, Opt};
+
+// 无错误码时按这些文本判断为连接层面的临时故障
+const TRANSIENT: [&str; 5] = ["连接失败", "error sending request", "error decoding response body", "operation timed out", "connection closed"];
+
+// 只支持单表迁移：多表运行重启会重新处理已切换的表，serve 的任务各自管理
+pub fn check(opt: &Opt, serving: bool) -> anyhow::Result<()> {
+    if opt.supervise && (serving || !opt.tables_file.is_empty() || opt.all_tables) {
+        anyhow::bail!("--supervise 只支持单表迁移（不支持 --tables-file / --all-tables 与 serve）");
+    }
+    Ok(())
+}
+
+fn transient(text: &str) -> bool {
+    if ch_error_code(text).is_some() {
+        return matches!(classify_ch_error(text), ChErrorClass::Retry | ChErrorClass::WaitRetry);
+    }
+    let http_5xx = regex::Regex::new(r"HTTP 错误: 5\d\d").unwrap();
+    http_5xx.is_match(text) || TRANSIENT.iter().any(|t| text.contains(t))
+}
+
+// 整体失败是否可重试，可重试时返回判断所依据的错误
+fn retryable(r: &RunReport) -> Option<String> {
+    match r.outcome.as_str() {
+        "failed" => r.error.clone().filter(|e| transient(e)),
+        "circuit-broken" => r.circuit_broken.as_ref().map(|c| c.first_error.clone()).filter(|e| transient(e)),
+        _ => None,
+    }
+}
+
+// 记录本次尝试；需要重启时返回等待时间
+pub fn restart(opt: &Opt, report: &Arc<Mutex<RunReport>>, res: &anyhow::Result<()>, attempts: &mut Vec<RunAttempt>) -> Option<Duration> {
+    if !opt.supervise {
+        return None;
+    }
+    // 在副本上结算，最终报告仍由 main 对最后一次尝试调用 finish
+    let mut r = report.lock().unwrap().clone();
+    r.finish(res);
+    let reason = retryable(&r);
+    let restart = reason.is_some() && attempts.len() < opt.max_restarts;
+    let n = attempts.len() + 1;
+    attempts.push(RunAttempt {
+        attempt: n,
+        started_at: r.started_at.clone(),
+        finished_at: r.finished_at.clone(),
+        duration_seconds: r.duration_seconds,
+        outcome: r.outcome.clone(),
+        error: r.error.clone(),
+        segments_done: r.segments_done,
+        segments_failed: r.segments_failed.len(),
+        rows_written: r.rows_written,
+        restart_reason: reason.clone().filter(|_| restart),
+        backoff_seconds: if restart { opt.restart_backoff.as_secs() } else { 0 },
+    });
+    match reason {
+        Some(e) if restart => {
+            warn!(
+                "第 {} 次运行失败（{}，可重试）: {}；{}s 后重新读取断点续传记录并重启（{}/{}）",
+                n,
+                r.outcome,
+                e,
+                opt.restart_backoff.as_secs(),
+                n,
+                opt.max_restarts
+            );
+            Some(opt.restart_backoff)
+        }
+        Some(_) => {
+            error!("第 {} 次运行失败（{}），已重启 {} 次（--max-restarts），不再重启", n, r.outcome, opt.max_restarts);
+            None
+        }
+        None if r.outcome == "failed" || r.outcome == "circuit-broken" => {
+            error!("第 {} 次运行失败（{}），错误不可重试（表结构、鉴权、语法等），不重启", n, r.outcome);
+            None
+        }
+        None => {
+            if n > 1 {
+                info!("监督运行: 第 {} 次运行结束（{}）", n, r.outcome);
+            }
+            None
+        }
+    }
+}
+
+// Ctrl-C 结束整个监督进程：进行中的尝试与等待重启都直接退出，不进入重启判断
+pub fn watch_interrupt(opt: &Opt) {
+    if !opt.supervise {
+        return;
+    }
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            tui::stop();
+            warn!("收到 Ctrl-C，监督运行停止，不再重启");
+            merge_pause::interrupted().await;
+            pushgateway::finish(130);
+            sql_log::close();
+            std::process::exit(130);
+        }
+    });
+}
+
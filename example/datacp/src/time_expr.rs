@@ -0,0 +1,66 @@
+// ===================== 组合时间表达式 =====================
+// 旧表以 event_date Date + event_hour UInt8 等多列表示时间时，--time-expr 给出计算 DateTime 的表达式，
+// 替代 --time-field 原样用于 min/max、分段条件、归档与 _bak 阶段的查询，源端与目标端完全一致；
+// 表结构校验只检查表达式引用的列（从表达式中粗略解析，或由 --time-expr-columns 指定）。
+// --time-field 也可以是表达式（如 Nested 列 "events.ts[1]"、Tuple 元素 "t.ts"），同样原样用于两端查询
+
+use crate::Opt;
+
+// 表达式中可能出现、但不是列名的关键字
+const KEYWORDS: &[&str] = &[
+    "INTERVAL", "SECOND", "MINUTE", "HOUR", "DAY", "WEEK", "MONTH", "QUARTER", "YEAR", "AND", "OR", "NOT", "NULL",
+    "CASE", "WHEN", "THEN", "ELSE", "END", "AS", "IS", "IN", "LIKE", "TRUE", "FALSE",
+];
+
+// 启动时以表达式替换 time_field，加括号避免与比较运算符的优先级问题
+pub fn apply(opt: &mut Opt) -> anyhow::Result<()> {
+    let expr = format!("({})", opt.time_expr.trim());
+    // 运行清单中已是展开后的时间字段
+    if opt.time_expr.trim().is_empty() || opt.time_field == expr {
+        return Ok(());
+    }
+    if !opt.time_field.is_empty() {
+        anyhow::bail!("--time-expr 与 --time-field 不能同时指定");
+    }
+    opt.time_field = expr;
+    Ok(())
+}
+
+// 时间字段或表达式引用的列
+pub fn referenced_columns(opt: &Opt) -> Vec<String> {
+    if !opt.time_expr.trim().is_empty() && !opt.time_expr_columns.is_empty() {
+        return opt.time_expr_columns.clone();
+    }
+    if !opt.time_expr.trim().is_empty() {
+        return expr_columns(&opt.time_expr);
+    }
+    // 普通列名（含 Nested 子列名）直接校验，否则按表达式解析
+    let plain = regex::Regex::new(r"^[A-Za-z_][A-Za-z0-9_.]*$").unwrap();
+    if plain.is_match(&opt.time_field) {
+        return vec![opt.time_field.clone()];
+    }
+    expr_columns(&opt.time_field)
+}
+
+// 表达式中的列名，点号连接的 Nested 子列（events.ts）作为一个整体
+fn expr_columns(expr: &str) -> Vec<String> {
+    // 去掉字符串字面量后，取不紧跟 '(' 的标识符（函数名除外）
+    let literals = regex::Regex::new(r"'(?:[^'\\]|\\.)*'").unwrap();
+    let expr = literals.replace_all(expr, "''");
+    let ident = regex::Regex::new(r"[A-Za-z_][A-Za-z0-9_]*(?:\.[A-Za-z_][A-Za-z0-9_]*)*").unwrap();
+    let mut cols: Vec<String> = Vec::new();
+    for m in ident.find_iter(&expr) {
+        let name = m.as_str();
+        let is_call = expr[m.end()..].trim_start().starts_with('(');
+        if is_call || KEYWORDS.contains(&name.to_ascii_uppercase().as_str()) || cols.iter().any(|c| c == name) {
+            continue;
+        }
+        cols.push(name.to_string());
+    }
+    cols
+}
+
+// 列是否存在：DESCRIBE 中的列（含展开的 Nested 子列 events.ts），或 Tuple 列的元素（t.ts 对应列 t）
+pub fn column_exists(col_names: &[String], c: &str) -> bool {
+    col_names.iter().any(|n| n == c) || c.split_once('.').map(|(head, _)| col_names.iter().any(|n| n == head)).unwrap_or(false)
+}
@@ -0,0 +1,257 @@
+// ===================== 时间字段的列时区（DateTime('Asia/Shanghai') / DateTime64(3, 'UTC')） =====================
+// 声明了时区的 DateTime 列按列时区解释不带时区的字符串字面量，toString(max(col)) 也按列时区输出；
+// 这些字符串再回到分段条件与断点续传文件时，列时区的偏移变化（夏令时或标准时间调整）会让同一个本地小时出现两次或缺失，
+// 重复拷贝或漏掉一个小时。列声明了时区时：
+//   - 启动时从 DESCRIBE 的类型中取出时区，向源端查询该时区在 1970 年至今后数年内的偏移变化（timeZoneOffset），
+//     客户端据此换算，不依赖本机时区库
+//   - 从 ClickHouse 读回的时间（min/max 等）按列时区解析后换算为 UTC；重复出现的本地时间下界取较早、上界取较晚的时刻
+//   - --start-time 按列时区解释
+//   - 分段键与断点续传记录一律为 UTC；分段条件写成显式带时区的字面量 toDateTime('<UTC>', 'UTC')，
+//     不用列时区的本地时间，因为它在重复的那个小时里有歧义
+//   - 按天/小时分组的查询先 toTimeZone(col, 'UTC')，与分段对齐
+// 未声明时区的列（及时间表达式）行为不变：字符串按服务端时区解释，分段键为服务端本地时间
+
+use chrono::{Duration, NaiveDateTime};
+use log::info;
+
+use crate::{ch_query_rows, clock_skew, json_u64, qualified, Opt};
+
+const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+// 偏移表覆盖到当前时间之后这么多天（增量迁移会读到更晚的数据）
+const AHEAD_DAYS: i64 = 5 * 366;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnTz {
+    pub zone: String,
+    // (自该 UTC 时刻起, 偏移秒数)，按时刻升序；第一项的时刻为 i64::MIN
+    offsets: Vec<(i64, i32)>,
+}
+
+// 从 DESCRIBE 的类型中取出时区：DateTime('Asia/Shanghai')、DateTime64(3, 'UTC')，可包在 Nullable / LowCardinality 中
+pub fn parse_type(type_name: &str) -> Option<String> {
+    let re = regex::Regex::new(r"DateTime(?:64)?\((?:\s*\d+\s*,)?\s*'([^']+)'\s*\)").unwrap();
+    re.captures(type_name).map(|c| c[1].to_string())
+}
+
+fn parse(s: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(s.trim(), FORMAT).ok()
+}
+
+fn secs(t: NaiveDateTime) -> i64 {
+    t.and_utc().timestamp()
+}
+
+fn from_secs(s: i64) -> NaiveDateTime {
+    chrono::DateTime::from_timestamp(s, 0).map(|t| t.naive_utc()).unwrap_or_default()
+}
+
+impl ColumnTz {
+    // transitions 为 (UTC 时刻, 自该时刻起的偏移)，base 为最早的偏移
+    pub fn new(zone: &str, base: i32, transitions: &[(i64, i32)]) -> Self {
+        let mut offsets = vec![(i64::MIN, base)];
+        let mut sorted = transitions.to_vec();
+        sorted.sort();
+        for (at, o) in sorted {
+            if offsets.last().map(|l| l.1) != Some(o) {
+                offsets.push((at, o));
+            }
+        }
+        ColumnTz { zone: zone.to_string(), offsets }
+    }
+
+    // UTC 时刻的偏移
+    fn offset_at(&self, utc: i64) -> i32 {
+        let i = self.offsets.partition_point(|(at, _)| *at <= utc);
+        self.offsets[i.saturating_sub(1)].1
+    }
+
+    pub fn to_local(&self, utc: NaiveDateTime) -> NaiveDateTime {
+        utc + Duration::seconds(self.offset_at(secs(utc)) as i64)
+    }
+
+    // 列时区的本地时间换算为 UTC。偏移回拨时本地时间出现两次：latest 为 false 取较早的时刻，为 true 取较晚的；
+    // 偏移前拨时跳过的本地时间按拨动前的偏移换算（落在拨动之后）
+    pub fn to_utc(&self, local: NaiveDateTime, latest: bool) -> NaiveDateTime {
+        let l = secs(local);
+        let mut candidates: Vec<i64> = self.offsets.iter().map(|(_, o)| l - *o as i64).filter(|u| self.offset_at(*u) as i64 == l - u).collect();
+        candidates.sort();
+        candidates.dedup();
+        let u = match (candidates.first(), candidates.last()) {
+            (Some(first), Some(last)) => if latest { *last } else { *first },
+            _ => {
+                let widest = self.offsets.iter().map(|(_, o)| *o).max().unwrap_or(0);
+                l - self.offset_at(l - widest as i64) as i64
+            }
+        };
+        from_secs(u)
+    }
+}
+
+// 分段条件中的时间字面量：列有时区时为 UTC 显式字面量，否则为原样的字符串
+pub fn lit(tz: Option<&ColumnTz>, t: &str) -> String {
+    match tz {
+        Some(_) => format!("toDateTime('{}', 'UTC')", t),
+        None => format!("'{}'", t),
+    }
+}
+
+// 读回的列时区时间换算为 UTC（latest 见 ColumnTz::to_utc）；空串与无法解析的原样返回
+pub fn from_column(tz: Option<&ColumnTz>, t: &str, latest: bool) -> String {
+    match (tz, parse(t)) {
+        (Some(z), Some(local)) => z.to_utc(local, latest).format(FORMAT).to_string(),
+        _ => t.to_string(),
+    }
+}
+
+// min/max 读回结果：下界取较早、上界取较晚
+pub fn range_from_column(tz: Option<&ColumnTz>, (min, max): (String, String)) -> (String, String) {
+    (from_column(tz, &min, false), from_column(tz, &max, true))
+}
+
+// 按天/小时分组前换算到 UTC，与分段键对齐
+pub fn utc_expr(tz: Option<&ColumnTz>, expr: &str) -> String {
+    match tz {
+        Some(_) => format!("toTimeZone({}, 'UTC')", expr),
+        None => expr.to_string(),
+    }
+}
+
+// 与分段键可比的当前时间（源端 src_dsn 服务器的时钟）
+pub fn now(src_dsn: &str, tz: Option<&ColumnTz>) -> NaiveDateTime {
+    match tz {
+        Some(_) => clock_skew::source_now(src_dsn).naive_utc(),
+        None => clock_skew::source_now_local(src_dsn),
+    }
+}
+
+// 向 ClickHouse 查询时区的偏移变化：先找出偏移与前一天不同的日期，再在这些日期内按分钟找到变化的时刻
+async fn fetch(opt: &Opt, zone: &str) -> anyhow::Result<ColumnTz> {
+    let z = zone.replace('\'', "\\'");
+    let days = chrono::Utc::now().timestamp() / 86400 + AHEAD_DAYS;
+    let base = ch_query_rows(&opt.src_dsn, &opt.src_db, &format!("SELECT timeZoneOffset(toDateTime(0, '{}')) AS o FORMAT JSONEachRow", z)).await?;
+    let base = base.first().and_then(|r| r.get("o")).and_then(|v| v.as_i64()).unwrap_or(0) as i32;
+    let sql = format!(
+        "SELECT toUInt32(number * 86400) AS u FROM numbers(1, {}) \
+         WHERE timeZoneOffset(toDateTime(u, '{z}')) != timeZoneOffset(toDateTime(u - 86400, '{z}')) FORMAT JSONEachRow",
+        days,
+        z = z
+    );
+    let changed: Vec<u64> = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?.iter().map(|r| json_u64(r.get("u"))).collect();
+    let mut transitions = Vec::new();
+    if !changed.is_empty() {
+        let starts: Vec<String> = changed.iter().map(|u| (u - 86400).to_string()).collect();
+        let sql = format!(
+            "SELECT toUInt32(s + m * 60) AS u, timeZoneOffset(toDateTime(u, '{z}')) AS o FROM (SELECT arrayJoin([{}]) AS s) \
+             ARRAY JOIN range(1, 1441) AS m WHERE o != timeZoneOffset(toDateTime(u - 60, '{z}')) ORDER BY u FORMAT JSONEachRow",
+            starts.join(","),
+            z = z
+        );
+        for r in ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await? {
+            transitions.push((json_u64(r.get("u")) as i64, r.get("o").and_then(|v| v.as_i64()).unwrap_or(0) as i32));
+        }
+    }
+    Ok(ColumnTz::new(zone, base, &transitions))
+}
+
+// 时间字段为声明了时区的列时设置 opt.time_zone，并按列时区把 --start-time 换算为 UTC（早于 1970 年时取 1970 年）；
+// 已设置过时原样返回
+pub async fn init(opt: &Opt) -> anyhow::Result<Opt> {
+    let mut opt = opt.clone();
+    if opt.time_zone.is_some() {
+        return Ok(opt);
+    }
+    let sql = format!("DESCRIBE TABLE {} FORMAT JSONEachRow", qualified(&opt.src_db, &opt.src_table));
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let zone = rows
+        .iter()
+        .find(|r| r.get("name").and_then(|v| v.as_str()) == Some(opt.time_field.as_str()))
+        .and_then(|r| r.get("type").and_then(|v| v.as_str()))
+        .and_then(parse_type);
+    let Some(zone) = zone else { return Ok(opt) };
+    let tz = fetch(&opt, &zone).await?;
+    let start = from_column(Some(&tz), &opt.start_time, false);
+    let start = if start.as_str() < "1970-01-01 00:00:00" { "1970-01-01 00:00:00".to_string() } else { start };
+    info!(
+        "时间字段 {} 的列时区为 {}（{} 次偏移变化）：--start-time {} 按列时区解释为 UTC {}，分段键与断点续传记录使用 UTC",
+        opt.time_field,
+        zone,
+        tz.offsets.len() - 1,
+        opt.start_time,
+        start
+    );
+    opt.start_time = start;
+    opt.time_zone = Some(tz);
+    Ok(opt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn t(s: &str) -> NaiveDateTime {
+        parse(s).unwrap()
+    }
+
+    // 中欧时间 2024 年：03-31 01:00 UTC 由 +1 拨到 +2，10-27 01:00 UTC 拨回 +1
+    fn berlin() -> ColumnTz {
+        ColumnTz::new("Europe/Berlin", 3600, &[(secs(t("2024-03-31 01:00:00")), 7200), (secs(t("2024-10-27 01:00:00")), 3600)])
+    }
+
+    #[test]
+    fn type_string_carries_zone() {
+        assert_eq!(parse_type("DateTime('Asia/Shanghai')").as_deref(), Some("Asia/Shanghai"));
+        assert_eq!(parse_type("DateTime64(3, 'UTC')").as_deref(), Some("UTC"));
+        assert_eq!(parse_type("Nullable(DateTime('Europe/Berlin'))").as_deref(), Some("Europe/Berlin"));
+        assert_eq!(parse_type("LowCardinality(Nullable(DateTime64(6,'America/New_York')))").as_deref(), Some("America/New_York"));
+        assert_eq!(parse_type("DateTime"), None);
+        assert_eq!(parse_type("DateTime64(3)"), None);
+    }
+
+    #[test]
+    fn to_local_around_transitions() {
+        let z = berlin();
+        assert_eq!(z.to_local(t("2024-03-31 00:59:59")), t("2024-03-31 01:59:59"));
+        assert_eq!(z.to_local(t("2024-03-31 01:00:00")), t("2024-03-31 03:00:00"));
+        assert_eq!(z.to_local(t("2024-10-27 00:59:59")), t("2024-10-27 02:59:59"));
+        assert_eq!(z.to_local(t("2024-10-27 01:00:00")), t("2024-10-27 02:00:00"));
+    }
+
+    #[test]
+    fn repeated_local_hour_picks_requested_side() {
+        let z = berlin();
+        // 02:30 本地时间出现两次：00:30 UTC（+2）与 01:30 UTC（+1）
+        assert_eq!(z.to_utc(t("2024-10-27 02:30:00"), false), t("2024-10-27 00:30:00"));
+        assert_eq!(z.to_utc(t("2024-10-27 02:30:00"), true), t("2024-10-27 01:30:00"));
+        // 重复区间之外只有一个时刻
+        assert_eq!(z.to_utc(t("2024-10-27 01:59:59"), true), t("2024-10-26 23:59:59"));
+        assert_eq!(z.to_utc(t("2024-10-27 03:00:00"), false), t("2024-10-27 02:00:00"));
+    }
+
+    #[test]
+    fn skipped_local_hour_lands_after_transition() {
+        let z = berlin();
+        assert_eq!(z.to_utc(t("2024-03-31 01:59:59"), false), t("2024-03-31 00:59:59"));
+        assert_eq!(z.to_utc(t("2024-03-31 02:30:00"), false), t("2024-03-31 01:30:00"));
+        assert_eq!(z.to_utc(t("2024-03-31 03:00:00"), true), t("2024-03-31 01:00:00"));
+    }
+
+    // 无夏令时的标准时间调整（+4 改为 +3）：同样出现一个重复的本地小时，换算后两侧都不丢失也不重叠
+    #[test]
+    fn standard_offset_change_round_trips() {
+        let z = ColumnTz::new("Europe/Volgograd", 4 * 3600, &[(secs(t("2018-10-27 22:00:00")), 3 * 3600)]);
+        assert_eq!(z.to_utc(t("2018-10-28 01:30:00"), false), t("2018-10-27 21:30:00"));
+        assert_eq!(z.to_utc(t("2018-10-28 01:30:00"), true), t("2018-10-27 22:30:00"));
+        let mut u = t("2018-10-27 20:00:00");
+        while u < t("2018-10-28 00:00:00") {
+            let local = z.to_local(u);
+            assert!(z.to_utc(local, false) == u || z.to_utc(local, true) == u, "{u}");
+            u += Duration::minutes(30);
+        }
+        // 没有偏移变化的时区只是固定平移
+        let sh = ColumnTz::new("Asia/Shanghai", 8 * 3600, &[]);
+        assert_eq!(from_column(Some(&sh), "2024-01-01 08:00:00", true), "2024-01-01 00:00:00");
+        assert_eq!(range_from_column(Some(&sh), (String::new(), String::new())), (String::new(), String::new()));
+        assert_eq!(lit(Some(&sh), "2024-01-01 00:00:00"), "toDateTime('2024-01-01 00:00:00', 'UTC')");
+        assert_eq!(lit(None, "2024-01-01 00:00:00"), "'2024-01-01 00:00:00'");
+    }
+}
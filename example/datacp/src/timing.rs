@@ -0,0 +1,108 @@
+// ===================== 分段耗时归因 =====================
+// worker 按阶段（读源端、读目标端、哈希比对、写入）累计每个分段的耗时，
+// 结束时按 worker 与全局汇总，输出各阶段占比（含 idle）与各阶段最慢的分段
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::report::{PhaseTimes, SegmentTiming, Throughput, WorkerTiming};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Phase {
+    ReadSrc,
+    ReadDst,
+    Diff,
+    Insert,
+}
+
+// 单个分段的计时器，离开作用域（含 continue 提前结束）时记录
+pub struct SegmentTimer<'a> {
+    sink: &'a Mutex<Vec<SegmentTiming>>,
+    segment: String,
+    worker: usize,
+    times: PhaseTimes,
+    mark: Instant,
+}
+
+impl<'a> SegmentTimer<'a> {
+    pub fn new(sink: &'a Mutex<Vec<SegmentTiming>>, segment: &str, worker: usize) -> Self {
+        SegmentTimer { sink, segment: segment.to_string(), worker, times: PhaseTimes::default(), mark: Instant::now() }
+    }
+
+    // 自上次计时点以来的耗时计入 phase
+    pub fn lap(&mut self, phase: Phase) {
+        let d = self.mark.elapsed().as_secs_f64();
+        let next = match phase {
+            Phase::ReadSrc => {
+                self.times.read_src += d;
+                "read_dst"
+            }
+            Phase::ReadDst => {
+                self.times.read_dst += d;
+                "diff"
+            }
+            Phase::Diff => {
+                self.times.diff += d;
+                "insert"
+            }
+            Phase::Insert => {
+                self.times.insert += d;
+                "insert"
+            }
+        };
+        crate::status::worker_phase(self.worker, next);
+        self.mark = Instant::now();
+    }
+}
+
+impl Drop for SegmentTimer<'_> {
+    fn drop(&mut self) {
+        self.sink.lock().unwrap().push(SegmentTiming {
+            segment: std::mem::take(&mut self.segment),
+            worker: self.worker,
+            phases: self.times.clone(),
+        });
+    }
+}
+
+fn add(a: &mut PhaseTimes, b: &PhaseTimes) {
+    a.read_src += b.read_src;
+    a.read_dst += b.read_dst;
+    a.diff += b.diff;
+    a.insert += b.insert;
+}
+
+// 按 worker 与全局汇总；walls 为各 worker 每次运行的总时长
+pub fn summarize(segments: &[SegmentTiming], walls: &[(usize, Duration)]) -> Throughput {
+    let mut workers: BTreeMap<usize, WorkerTiming> = BTreeMap::new();
+    for (w, d) in walls {
+        let e = workers.entry(*w).or_insert_with(|| WorkerTiming { worker: *w, ..Default::default() });
+        e.wall_seconds += d.as_secs_f64();
+    }
+    let mut total = PhaseTimes::default();
+    for s in segments {
+        let e = workers.entry(s.worker).or_insert_with(|| WorkerTiming { worker: s.worker, ..Default::default() });
+        e.segments += 1;
+        add(&mut e.phases, &s.phases);
+        add(&mut total, &s.phases);
+    }
+    for w in workers.values_mut() {
+        w.idle_seconds = (w.wall_seconds - w.phases.sum()).max(0.0);
+    }
+    let wall_seconds: f64 = workers.values().map(|w| w.wall_seconds).sum();
+    let mut slowest = BTreeMap::new();
+    for name in PhaseTimes::NAMES {
+        let mut v: Vec<SegmentTiming> = segments.to_vec();
+        v.sort_by(|a, b| b.phases.get(name).total_cmp(&a.phases.get(name)));
+        v.truncate(5);
+        slowest.insert(name.to_string(), v);
+    }
+    Throughput {
+        idle_seconds: (wall_seconds - total.sum()).max(0.0),
+        wall_seconds,
+        total,
+        workers: workers.into_values().collect(),
+        slowest,
+    }
+}
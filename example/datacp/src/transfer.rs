@@ -0,0 +1,254 @@
+// ===================== 传输字节统计与剩余量估算 =====================
+// 跨地域迁移按字节而不是行数安排传输窗口。worker 处理分段时累计源端读取的响应体字节（解压后的 JSONEachRow 文本）
+// 与线路上的字节（各次读取响应的 Content-Length，没有时按实际长度；写入请求体按实际长度，含重试），
+// 分段完成时写入断点续传文件（"bytes:分段\t解压字节\t线路字节\t完成时间戳"）与报告 segment_bytes。
+// datacp status / datacp plan 把已完成分段的字节数与按 system.parts 估算的剩余分段相加，输出已复制、剩余与预计耗时；
+// system.parts 的未压缩字节与 JSON 文本大小不同，估算值按已测量分段的实际/估算比例修正；
+// 已完成分段不足以计算速率时，预计耗时按断点续传元数据中上次运行的实测吞吐估算
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::report::SegmentBytes;
+use crate::{ch_query_rows, checkpoint_meta, json_u64, preflight, save_done_segment, segment, shard_of, state_dir, time_zone, Opt};
+
+// 分段字节记录在断点续传文件中的前缀
+pub const BYTES_PREFIX: &str = "bytes:";
+
+// 计算当前速率使用的最近完成分段数
+const RATE_SAMPLES: usize = 100;
+
+// 单个 worker 的计数器，分段开始时清零
+struct Counter {
+    src_dsn: String,
+    uncompressed: AtomicU64,
+    wire: AtomicU64,
+}
+
+tokio::task_local! {
+    static COUNTER: Arc<Counter>;
+}
+
+// worker 在计数器作用域内运行；作用域外（预检、切换等）的请求不计入分段
+pub async fn scope<F: Future>(src_dsn: String, f: F) -> F::Output {
+    let c = Counter { src_dsn, uncompressed: AtomicU64::new(0), wire: AtomicU64::new(0) };
+    COUNTER.scope(Arc::new(c), f).await
+}
+
+// 读取响应：源端响应体计入解压字节，线路字节按 Content-Length
+pub fn read(dsn: &str, body: usize, content_length: Option<u64>) {
+    let _ = COUNTER.try_with(|c| {
+        if dsn == c.src_dsn {
+            c.uncompressed.fetch_add(body as u64, Ordering::Relaxed);
+        }
+        c.wire.fetch_add(content_length.unwrap_or(body as u64), Ordering::Relaxed);
+    });
+}
+
+// 写入请求体
+pub fn written(bytes: u64) {
+    let _ = COUNTER.try_with(|c| c.wire.fetch_add(bytes, Ordering::Relaxed));
+}
+
+pub fn start() {
+    let _ = COUNTER.try_with(|c| {
+        c.uncompressed.store(0, Ordering::Relaxed);
+        c.wire.store(0, Ordering::Relaxed);
+    });
+}
+
+// 分段完成：字节数写入断点续传文件（须在分段完成行之前），返回报告条目
+pub fn finish(done_segments_file: &str, seg: &str) -> anyhow::Result<SegmentBytes> {
+    let (uncompressed, wire) = COUNTER
+        .try_with(|c| (c.uncompressed.load(Ordering::Relaxed), c.wire.load(Ordering::Relaxed)))
+        .unwrap_or((0, 0));
+    let line = format!("{}{}\t{}\t{}\t{}", BYTES_PREFIX, seg, uncompressed, wire, chrono::Local::now().timestamp());
+    save_done_segment(done_segments_file, &line)?;
+    Ok(SegmentBytes { segment: seg.to_string(), uncompressed_bytes: uncompressed, wire_bytes: wire })
+}
+
+struct Record {
+    uncompressed: u64,
+    finished: i64,
+}
+
+fn parse_seg(s: &str) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").ok()
+}
+
+// 已完成分段（起点 → 时长小时数）与各分段的字节记录（按起点，同一分段多次记录时取最后一次）
+fn load(done_segments_file: &str) -> (HashMap<String, f64>, HashMap<String, Record>) {
+    let (mut done, mut bytes) = (HashMap::new(), HashMap::new());
+    let text = std::fs::read_to_string(done_segments_file).unwrap_or_default();
+    for line in text.lines() {
+        if let Some(rest) = line.strip_prefix(BYTES_PREFIX) {
+            let f: Vec<&str> = rest.split('\t').collect();
+            if f.len() >= 4 {
+                let record = Record { uncompressed: f[1].parse().unwrap_or(0), finished: f[3].parse().unwrap_or(0) };
+                bytes.insert(f[0].split("..").next().unwrap_or_default().to_string(), record);
+            }
+        } else if let Some((a, b)) = segment::parse(line) {
+            done.insert(a.format("%Y-%m-%d %H:%M:%S").to_string(), (b - a).num_seconds() as f64 / 3600.0);
+        }
+    }
+    bytes.retain(|s, _| done.contains_key(s));
+    (done, bytes)
+}
+
+pub struct Progress {
+    pub done_segments: usize,
+    pub unmeasured_segments: usize, // 已完成但没有字节记录（旧版本或 remote() 复制）的分段，按估算计入
+    pub copied_bytes: u64,
+    pub total_bytes: u64,
+    pub remaining_bytes: u64,
+    pub rate: Option<f64>, // 最近完成分段的字节/秒
+    pub rate_from_last_run: bool, // rate 取自上次运行的实测吞吐
+}
+
+// 源表各分区的时间范围与未压缩字节，按小时均摊；无时间范围的 part 均摊到整个迁移窗口
+struct Partition {
+    lo: chrono::NaiveDateTime,
+    hi: chrono::NaiveDateTime,
+    per_hour: f64,
+}
+
+fn hour(t: chrono::NaiveDateTime) -> chrono::NaiveDateTime {
+    use chrono::Timelike;
+    t.with_minute(0).and_then(|t| t.with_second(0)).unwrap_or(t)
+}
+
+fn hours(lo: chrono::NaiveDateTime, hi: chrono::NaiveDateTime) -> f64 {
+    ((hi - lo).num_hours() + 1).max(1) as f64
+}
+
+pub async fn progress(opt: &Opt) -> anyhow::Result<Progress> {
+    let opt = &time_zone::init(opt).await?;
+    let tz = opt.time_zone.as_ref();
+    let done_segments_file = state_dir::done_segments(opt)?;
+    let (done, bytes) = load(&done_segments_file);
+    let (db, table, from) = preflight::src_parts_source(opt).await?;
+    // 列声明了时区时分区时间范围按 UTC 输出，与分段键一致
+    let sql = format!(
+        "SELECT toString({}) AS lo, toString({}) AS hi, toUInt32(max(max_time)) = 0 AS untimed, \
+         sum(data_uncompressed_bytes) AS bytes FROM {} WHERE database = '{}' AND table = '{}' AND active \
+         AND (toUInt32(max_time) = 0 OR max_time >= {}) GROUP BY partition_id FORMAT JSONEachRow",
+        time_zone::utc_expr(tz, "min(min_time)"),
+        time_zone::utc_expr(tz, "max(max_time)"),
+        from,
+        db,
+        table,
+        opt.time_lit(&opt.start_time)
+    );
+    let rows = ch_query_rows(&opt.src_dsn, &opt.src_db, &sql).await?;
+    let start = parse_seg(&opt.start_time).map(hour).unwrap_or_default();
+    let now = hour(time_zone::now(&opt.src_dsn, tz));
+    let (mut partitions, mut untimed) = (Vec::new(), 0.0);
+    for r in &rows {
+        let b = json_u64(r.get("bytes")) as f64;
+        let t = |k: &str| r.get(k).and_then(|v| v.as_str()).and_then(parse_seg).map(hour);
+        match (json_u64(r.get("untimed")), t("lo"), t("hi")) {
+            (0, Some(lo), Some(hi)) => partitions.push(Partition { lo, hi, per_hour: b / hours(lo, hi) }),
+            _ => untimed += b,
+        }
+    }
+    // 迁移窗口从起始时间（或最早的数据、最早完成的分段）到当前小时
+    let first = partitions.iter().map(|p| p.lo).chain(done.keys().filter_map(|s| parse_seg(s))).min().unwrap_or(now);
+    let window_hours = hours(start.max(first), now);
+    // 各分段（起点, 小时数）按所在分区的每小时字节估算
+    let estimate = |segs: &mut dyn Iterator<Item = (chrono::NaiveDateTime, f64)>| -> f64 {
+        let (mut sum, mut n) = (0.0, 0.0);
+        for (s, h) in segs {
+            n += h;
+            sum += partitions.iter().filter(|p| p.lo <= s && s <= p.hi).map(|p| p.per_hour).sum::<f64>() * h;
+        }
+        sum + untimed * n / window_hours
+    };
+    let total_est = partitions.iter().map(|p| p.per_hour * hours(p.lo.max(start), p.hi)).sum::<f64>() + untimed;
+    let done_est = estimate(&mut done.iter().filter_map(|(s, h)| Some((parse_seg(s)?, *h))));
+    let measured_est = estimate(&mut bytes.keys().filter_map(|s| Some((parse_seg(s)?, *done.get(s)?))));
+    let measured: u64 = bytes.values().map(|r| r.uncompressed).sum();
+    // --shard-of 时本进程只处理 1/n 的行
+    let share = match shard_of::parse(opt)? {
+        Some((_, n)) => 1.0 / n as f64,
+        None => 1.0,
+    };
+    let ratio = if measured_est > 0.0 && measured > 0 { measured as f64 / (measured_est * share) } else { 1.0 };
+    let copied = measured as f64 + (done_est - measured_est).max(0.0) * share * ratio;
+    let remaining = (total_est - done_est).max(0.0) * share * ratio;
+    let mut recent: Vec<&Record> = bytes.values().collect();
+    recent.sort_by_key(|r| r.finished);
+    let recent = &recent[recent.len().saturating_sub(RATE_SAMPLES)..];
+    let rate = match (recent.first(), recent.last()) {
+        (Some(a), Some(b)) if b.finished > a.finished => {
+            Some(recent[1..].iter().map(|r| r.uncompressed).sum::<u64>() as f64 / (b.finished - a.finished) as f64)
+        }
+        _ => None,
+    };
+    let (rate, rate_from_last_run) = match rate {
+        Some(r) => (Some(r), false),
+        None => (checkpoint_meta::last_throughput(&done_segments_file), true),
+    };
+    Ok(Progress {
+        done_segments: done.len(),
+        unmeasured_segments: done.len() - bytes.len(),
+        copied_bytes: copied as u64,
+        total_bytes: (copied + remaining) as u64,
+        remaining_bytes: remaining as u64,
+        rate,
+        rate_from_last_run,
+    })
+}
+
+pub fn human(bytes: f64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB", "PB"];
+    let (mut v, mut i) = (bytes, 0);
+    while v >= 1024.0 && i + 1 < units.len() {
+        v /= 1024.0;
+        i += 1;
+    }
+    if i == 0 { format!("{} B", v as u64) } else { format!("{:.1} {}", v, units[i]) }
+}
+
+fn duration(secs: f64) -> String {
+    let s = secs as u64;
+    match s {
+        0..=59 => format!("{}s", s),
+        60..=3599 => format!("{}m", s / 60),
+        _ if s < 86400 * 2 => format!("{}h{:02}m", s / 3600, s % 3600 / 60),
+        _ => format!("{}d{}h", s / 86400, s % 86400 / 3600),
+    }
+}
+
+impl Progress {
+    pub fn describe(&self) -> String {
+        let eta = match self.rate {
+            Some(r) if r > 0.0 => format!(
+                "按{} {}/s 预计 {}",
+                if self.rate_from_last_run { "上次运行" } else { "当前" },
+                human(r),
+                duration(self.remaining_bytes as f64 / r)
+            ),
+            _ => "暂无速率".to_string(),
+        };
+        let unmeasured = if self.unmeasured_segments > 0 { format!("（其中 {} 个无字节记录，按估算计入）", self.unmeasured_segments) } else { String::new() };
+        format!(
+            "已完成 {} 个分段{}，已复制 {} / 约 {}，剩余约 {}，{}",
+            self.done_segments,
+            unmeasured,
+            human(self.copied_bytes as f64),
+            human(self.total_bytes as f64),
+            human(self.remaining_bytes as f64),
+            eta
+        )
+    }
+}
+
+// plan/status 输出的一行进度，估算失败时给出原因
+pub async fn describe(opt: &Opt) -> String {
+    match progress(opt).await {
+        Ok(p) => p.describe(),
+        Err(e) => format!("无法估算剩余字节: {}", e),
+    }
+}
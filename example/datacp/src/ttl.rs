@@ -0,0 +1,165 @@
+// ===================== 源表 TTL =====================
+// 源表带删除型 TTL（如 TTL ts + INTERVAL 90 DAY）时，迁移期间边界附近的行在分段迁移后才在源端过期，
+// 目标端的这些行随之成为“多余行”：写入后核对一直报告目标端多于源端，镜像模式会把正常数据删掉。
+// 启动时从 system.tables 读取 TTL 表达式，按当前时间计算随时间推移的过期边界：
+// 起点早于边界的分段不计入比对差异（报告为 ttl-affected），镜像模式不删除时间已越过边界的目标行。
+// TO DISK / TO VOLUME / RECOMPRESS 只移动或重新压缩数据，不受影响；--respect-ttl=false 时不做上述处理
+
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::{shard, time_zone, Opt};
+
+enum Interval {
+    Fixed(chrono::Duration),
+    Months(u32),
+}
+
+pub struct SourceTtl {
+    expr: String,
+    interval: Interval,
+    slack: chrono::Duration, // 表达式按日期取整（toDate 等）时整天一起过期，边界放宽一天
+    time_zone: Option<time_zone::ColumnTz>, // 时间字段声明了时区时边界与分段键按 UTC
+    src_dsn: String,                        // 当前时间取该源端服务器的时钟
+}
+
+// engine_full 中 TTL 子句的各项（顶层逗号分隔）
+fn ttl_entries(engine_full: &str) -> Vec<String> {
+    let Some((_, rest)) = engine_full.split_once(" TTL ") else { return Vec::new() };
+    let clause = rest.split(" SETTINGS ").next().unwrap_or(rest);
+    let (mut entries, mut cur, mut depth, mut quoted) = (Vec::new(), String::new(), 0, false);
+    for ch in clause.chars() {
+        match ch {
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                entries.push(cur.trim().to_string());
+                cur.clear();
+                continue;
+            }
+            _ => {}
+        }
+        cur.push(ch);
+    }
+    entries.push(cur.trim().to_string());
+    entries.into_iter().filter(|e| !e.is_empty()).collect()
+}
+
+fn deletes(entry: &str) -> bool {
+    !regex::Regex::new(r"\s(TO DISK|TO VOLUME|RECOMPRESS)\s").unwrap().is_match(entry)
+}
+
+// TTL 表达式本身（去掉 DELETE / WHERE / GROUP BY 部分）
+fn expr_of(entry: &str) -> &str {
+    [" DELETE", " WHERE ", " GROUP BY "].iter().fold(entry, |e, k| e.split(k).next().unwrap_or(e)).trim()
+}
+
+fn parse_interval(expr: &str) -> Option<Interval> {
+    let re = regex::Regex::new(
+        r"(?i)toInterval(Second|Minute|Hour|Day|Week|Month|Quarter|Year)\(\s*(\d+)\s*\)|INTERVAL\s+(\d+)\s+(SECOND|MINUTE|HOUR|DAY|WEEK|MONTH|QUARTER|YEAR)",
+    )
+    .unwrap();
+    let c = re.captures(expr)?;
+    let (unit, n) = match (c.get(1), c.get(2), c.get(3), c.get(4)) {
+        (Some(u), Some(n), _, _) | (_, _, Some(n), Some(u)) => (u.as_str().to_ascii_lowercase(), n.as_str().parse::<i64>().ok()?),
+        _ => return None,
+    };
+    Some(match unit.as_str() {
+        "second" => Interval::Fixed(chrono::Duration::seconds(n)),
+        "minute" => Interval::Fixed(chrono::Duration::minutes(n)),
+        "hour" => Interval::Fixed(chrono::Duration::hours(n)),
+        "day" => Interval::Fixed(chrono::Duration::days(n)),
+        "week" => Interval::Fixed(chrono::Duration::weeks(n)),
+        "month" => Interval::Months(n as u32),
+        "quarter" => Interval::Months(n as u32 * 3),
+        _ => Interval::Months(n as u32 * 12),
+    })
+}
+
+fn parse_time(s: &str) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::parse_from_str(s.get(..19).unwrap_or(s), "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .or_else(|| chrono::NaiveDate::parse_from_str(s.get(..10).unwrap_or(s), "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0)))
+}
+
+impl SourceTtl {
+    // 源表没有可解析的删除型 TTL 或 --respect-ttl=false 时返回 None
+    pub async fn detect(opt: &Opt) -> anyhow::Result<Option<Self>> {
+        if !opt.respect_ttl {
+            info!("--respect-ttl=false，不按源表 TTL 处理过期行");
+            return Ok(None);
+        }
+        // 分布式表的 TTL 定义在本地表上
+        let (db, table) = shard::resolve_local_table(&opt.src_dsn, &opt.src_db, &opt.src_table).await?;
+        let engine_full = match shard::table_engine(&opt.src_dsn, &db, &table).await {
+            Ok((_, e)) => e,
+            Err(e) => {
+                warn!("读取源表 {}.{} 的 TTL 失败，按无 TTL 处理: {}", db, table, e);
+                return Ok(None);
+            }
+        };
+        let mut found: Option<SourceTtl> = None;
+        for entry in ttl_entries(&engine_full) {
+            if !deletes(&entry) {
+                info!("源表 TTL {} 只移动或重新压缩数据，不影响比对", entry);
+                continue;
+            }
+            let expr = expr_of(&entry);
+            let Some(interval) = parse_interval(expr).filter(|_| expr.contains(&opt.time_field)) else {
+                warn!("无法从源表 TTL {} 推算 {} 的过期边界，按无 TTL 处理；比对持续报告目标端多余行时请确认是否为 TTL 过期", entry, opt.time_field);
+                continue;
+            };
+            let slack = if expr.contains("toDate(") || expr.contains("toStartOfDay(") { chrono::Duration::days(1) } else { chrono::Duration::zero() };
+            let ttl = SourceTtl { expr: expr.to_string(), interval, slack, time_zone: opt.time_zone.clone(), src_dsn: opt.src_dsn.clone() };
+            // 有多项删除型 TTL 时取最先过期（边界最晚）的一项
+            if found.as_ref().map(|f| ttl.boundary() > f.boundary()).unwrap_or(true) {
+                found = Some(ttl);
+            }
+        }
+        if let Some(t) = &found {
+            info!(
+                "源表 TTL {}: 当前过期边界 {}，起点早于边界的分段不计入比对差异{}",
+                t.expr,
+                t.boundary_str(),
+                if opt.mirror { "，镜像模式不删除早于边界的目标行" } else { "" }
+            );
+        }
+        Ok(found)
+    }
+
+    // 过期边界随时间推移，每次调用按当前时间计算
+    pub fn boundary(&self) -> chrono::NaiveDateTime {
+        let now = time_zone::now(&self.src_dsn, self.time_zone.as_ref());
+        let b = match self.interval {
+            Interval::Fixed(d) => now - d,
+            // 按月的间隔在列时区的本地日历上计算
+            Interval::Months(m) => match &self.time_zone {
+                Some(tz) => tz.to_local(now).checked_sub_months(chrono::Months::new(m)).map(|l| tz.to_utc(l, false)).unwrap_or(now),
+                None => now.checked_sub_months(chrono::Months::new(m)).unwrap_or(now),
+            },
+        };
+        b + self.slack
+    }
+
+    pub fn boundary_str(&self) -> String {
+        self.boundary().format("%Y-%m-%d %H:%M:%S").to_string()
+    }
+
+    // 分段（或窗口）起点早于过期边界，其中的行可能已在源端过期
+    pub fn affects(&self, seg: &str) -> bool {
+        parse_time(seg).map(|t| t < self.boundary()).unwrap_or(false)
+    }
+
+    // 行的时间已越过过期边界；时间无法解析时按已越过处理（不删除）。行值按列时区输出，先换算为 UTC
+    pub fn crossed(&self, row: &HashMap<String, Value>, time_field: &str) -> bool {
+        row.get(time_field)
+            .and_then(|v| v.as_str())
+            .and_then(parse_time)
+            .map(|t| time_zone::from_column(self.time_zone.as_ref(), &t.format("%Y-%m-%d %H:%M:%S").to_string(), false))
+            .and_then(|s| parse_time(&s))
+            .map(|t| t < self.boundary())
+            .unwrap_or(true)
+    }
+}
@@ -0,0 +1,236 @@
+// ===================== 终端仪表盘（--tui） =====================
+// 有人值守的迁移用 --tui 在终端全屏显示：分段热力图（每行一天、每格一小时：完成/进行中/失败/待处理）、
+// 写入速率走势、各 worker 当前分段与阶段、增量阶段的复制延迟与最近日志。数据来自状态接口的同一份状态（status::snapshot / grid），
+// 每秒重绘一次。按键：p 暂停领取新分段 / r 继续（同 POST /pause、/resume）、c 热备模式下触发切换（同 POST /cutover）、
+// q 当前分段完成后结束（按截止处理，断点已保存，不切换）。
+// 直接输出 ANSI 控制序列，终端模式通过 stty 设置，不引入额外依赖。
+// 从不默认启用；stdout/stdin 不是终端、指定了 --events-stdout 或终端小于 MIN_COLS x MIN_ROWS 时退回普通日志，运行中终端缩小到下限以下时同样退出仪表盘
+
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::io::{IsTerminal, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::status::{self, Cell};
+use crate::{deadline, merge_pause, standby, Opt};
+
+const MIN_COLS: usize = 80;
+const MIN_ROWS: usize = 24;
+// 保留的日志行数
+const LOG_LINES: usize = 200;
+const SPARK: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+// 进入仪表盘前的终端设置（stty -g），退出时恢复
+static SAVED: Mutex<Option<String>> = Mutex::new(None);
+
+fn stty(args: &[&str]) -> Option<String> {
+    let out = Command::new("stty").args(args).stdin(Stdio::inherit()).stderr(Stdio::null()).output().ok()?;
+    out.status.success().then(|| String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+// 终端行数与列数
+fn size() -> Option<(usize, usize)> {
+    let s = stty(&["size"])?;
+    let (rows, cols) = s.split_once(' ')?;
+    Some((rows.parse().ok()?, cols.parse().ok()?))
+}
+
+fn fits(size: Option<(usize, usize)>) -> bool {
+    size.is_some_and(|(rows, cols)| rows >= MIN_ROWS && cols >= MIN_COLS)
+}
+
+// 退出仪表盘时恢复终端；main 在 process::exit 前显式 drop
+pub struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        stop();
+    }
+}
+
+// 未指定 --tui 或不满足条件时返回 None（普通日志输出）
+pub fn start(opt: &Opt) -> Option<Guard> {
+    if !opt.tui {
+        return None;
+    }
+    let reason = if opt.events_stdout {
+        Some("已指定 --events-stdout".to_string())
+    } else if !std::io::stdout().is_terminal() || !std::io::stdin().is_terminal() {
+        Some("stdout/stdin 不是终端".to_string())
+    } else if !fits(size()) {
+        Some(format!("终端小于 {}x{}", MIN_COLS, MIN_ROWS))
+    } else {
+        None
+    };
+    if let Some(r) = reason {
+        warn!("--tui 未启用（{}），使用普通日志输出", r);
+        return None;
+    }
+    *SAVED.lock().unwrap() = stty(&["-g"]);
+    stty(&["-icanon", "-echo", "min", "1"]);
+    print!("\x1b[?1049h\x1b[?25l");
+    ACTIVE.store(true, Ordering::SeqCst);
+    std::thread::spawn(keys);
+    tokio::spawn(async {
+        let mut rates = VecDeque::new();
+        while ACTIVE.load(Ordering::SeqCst) {
+            let size = size();
+            if !fits(size) {
+                stop();
+                warn!("终端小于 {}x{}，退出仪表盘，改为普通日志输出", MIN_COLS, MIN_ROWS);
+                break;
+            }
+            if let (Some((rows, cols)), Some(snapshot)) = (size, status::snapshot()) {
+                rates.push_back(snapshot["rows_per_sec"].as_f64().unwrap_or(0.0));
+                while rates.len() > cols {
+                    rates.pop_front();
+                }
+                let frame = render(&snapshot, &status::grid(), &rates, rows, cols);
+                let mut out = std::io::stdout().lock();
+                let _ = out.write_all(frame.as_bytes());
+                let _ = out.flush();
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+    // 仪表盘期间 Ctrl-C 仍会结束进程，先恢复终端
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            merge_pause::interrupted().await;
+            stop();
+            std::process::exit(130);
+        }
+    });
+    Some(Guard)
+}
+
+// 恢复终端（其他 Ctrl-C 处理在退出前同样调用）
+pub fn stop() {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    print!("\x1b[?25h\x1b[?1049l");
+    let _ = std::io::stdout().flush();
+    match SAVED.lock().unwrap().take() {
+        Some(s) => stty(&[s.as_str()]),
+        None => stty(&["sane"]),
+    };
+}
+
+// 日志行：仪表盘启用时记入日志面板并返回 true（不再写 stderr）
+pub fn capture(line: &str) -> bool {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return false;
+    }
+    let mut logs = LOGS.lock().unwrap();
+    logs.push_back(line.to_string());
+    while logs.len() > LOG_LINES {
+        logs.pop_front();
+    }
+    true
+}
+
+// 读取按键（独立线程阻塞读取 stdin）
+fn keys() {
+    let mut buf = [0u8; 1];
+    while ACTIVE.load(Ordering::SeqCst) && std::io::stdin().read(&mut buf).is_ok_and(|n| n == 1) {
+        match buf[0] {
+            b'p' => status::hold(true),
+            b'r' => status::hold(false),
+            b'c' if !standby::request(standby::Command::Cutover, "tui") => warn!("未处于热备模式（--standby），c 键不起作用"),
+            b'q' => {
+                info!("仪表盘: 请求退出");
+                deadline::stop();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn cell(c: Cell) -> &'static str {
+    match c {
+        Cell::Done => "\x1b[42m \x1b[0m",
+        Cell::Active => "\x1b[43m \x1b[0m",
+        Cell::Failed => "\x1b[41m \x1b[0m",
+        Cell::Pending => "\x1b[100m \x1b[0m",
+    }
+}
+
+fn sparkline(rates: &VecDeque<f64>) -> String {
+    let max = rates.iter().cloned().fold(0.0, f64::max);
+    rates
+        .iter()
+        .map(|r| if max > 0.0 { SPARK[((r / max) * (SPARK.len() - 1) as f64).round() as usize] } else { SPARK[0] })
+        .collect()
+}
+
+fn clip(s: &str, cols: usize) -> String {
+    s.chars().take(cols).collect()
+}
+
+// 整屏内容：标题 → 热力图 → 速率 → worker → 日志，按终端高度截断
+fn render(s: &serde_json::Value, grid: &[(String, Cell)], rates: &VecDeque<f64>, rows: usize, cols: usize) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let seg = &s["segments"];
+    let lag = s["replication_lag_seconds"].as_u64().map(|l| format!("  延迟 {}s", l)).unwrap_or_default();
+    let paused = if s["paused"].as_bool().unwrap_or(false) { "  [已暂停]" } else if s["blackout"]["paused"].as_bool().unwrap_or(false) { "  [维护窗口]" } else { "" };
+    lines.push(clip(
+        &format!(
+            "{}  阶段 {}  分段 {}/{} 失败 {}  写入 {} 行{}{}",
+            s["table"].as_str().unwrap_or(""),
+            s["phase"].as_str().unwrap_or(""),
+            seg["done"],
+            seg["total"],
+            seg["failed"],
+            s["rows_inserted"],
+            lag,
+            paused
+        ),
+        cols,
+    ));
+    lines.push(String::new());
+    // 热力图：每行一天 24 格，只显示最近的若干天
+    let mut days: Vec<(String, Vec<Option<Cell>>)> = Vec::new();
+    for (segment, c) in grid {
+        let (day, hour) = (segment.get(..10).unwrap_or(""), segment.get(11..13).and_then(|h| h.parse::<usize>().ok()).unwrap_or(0));
+        if days.last().map(|d| d.0.as_str()) != Some(day) {
+            days.push((day.to_string(), vec![None; 24]));
+        }
+        if let Some(d) = days.last_mut() {
+            d.1[hour.min(23)] = Some(*c);
+        }
+    }
+    let heat_rows = rows.saturating_sub(16).max(3);
+    for (day, hours) in &days[days.len().saturating_sub(heat_rows)..] {
+        let cells: String = hours.iter().map(|c| c.map(cell).unwrap_or(" ")).collect();
+        lines.push(format!("{} {}", day, cells));
+    }
+    lines.push(format!("           {} 完成 {} 进行中 {} 失败 {} 待处理", cell(Cell::Done), cell(Cell::Active), cell(Cell::Failed), cell(Cell::Pending)));
+    lines.push(String::new());
+    lines.push(format!("写入速率 {} 行/s", s["rows_per_sec"]));
+    lines.push(sparkline(rates).chars().rev().take(cols).collect::<Vec<_>>().into_iter().rev().collect());
+    lines.push(String::new());
+    for a in s["active_segments"].as_array().into_iter().flatten() {
+        lines.push(clip(
+            &format!("worker {:<3} {}  {:<8} {}s", a["worker"], a["segment"].as_str().unwrap_or(""), a["phase"].as_str().unwrap_or(""), a["seconds"]),
+            cols,
+        ));
+    }
+    lines.push(String::new());
+    let footer = "p 暂停  r 继续  c 切换（热备）  q 结束";
+    let room = rows.saturating_sub(lines.len() + 1);
+    let logs = LOGS.lock().unwrap();
+    for l in logs.iter().skip(logs.len().saturating_sub(room)) {
+        lines.push(clip(l, cols));
+    }
+    lines.truncate(rows.saturating_sub(1));
+    let mut frame = String::from("\x1b[H\x1b[2J");
+    frame.push_str(&lines.join("\r\n"));
+    frame.push_str(&format!("\x1b[{};1H\x1b[7m{}\x1b[0m", rows, footer));
+    frame
+}
@@ -0,0 +1,74 @@
+// ===================== 全局停滞看门狗（--global-stall-timeout） =====================
+// 某个 worker 卡死（半开的 TCP 连接上响应体一直不来，reqwest 的超时没有覆盖到）时，等待队列清空的各阶段会无限期等下去，
+// 整个运行没有任何日志地挂住。WorkerPool 等待每批分段时定期检查全局进展（与 /healthz 共用 status 的最近进展时间）：
+// 超过 --global-stall-timeout 没有任何进展时记录进行中的各分段及其已进行时长与阶段，先通过取消令牌让卡在读取上的分段
+// 按失败退出；宽限期 GRACE 后仍未退出的强制中止所在任务，分段记为失败（不写入断点续传记录，续传或 --supervise 重启时重新迁移），
+// 并在原位置启动新的 worker，本批其余分段照常处理
+
+use log::error;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+use crate::status;
+
+// 取消后等待分段自行退出的时长，之后强制中止
+pub const GRACE: Duration = Duration::from_secs(30);
+
+// worker → 当前分段的取消令牌
+static TOKENS: Mutex<BTreeMap<usize, Arc<watch::Sender<bool>>>> = Mutex::new(BTreeMap::new());
+
+// 卡住的分段
+pub struct Stuck {
+    pub worker: usize,
+    pub segment: String,
+}
+
+// worker 开始处理一个分段时换一个新的取消令牌
+pub fn arm(worker: usize) {
+    TOKENS.lock().unwrap().insert(worker, Arc::new(watch::Sender::new(false)));
+}
+
+fn cancel(worker: usize) {
+    if let Some(t) = TOKENS.lock().unwrap().get(&worker) {
+        t.send_replace(true);
+    }
+}
+
+// 可取消的读取：令牌被取消时立即返回错误，分段按失败处理
+pub async fn guard<T>(worker: usize, fut: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<T> {
+    let token = TOKENS.lock().unwrap().get(&worker).cloned();
+    let Some(token) = token else { return fut.await };
+    let mut rx = token.subscribe();
+    tokio::select! {
+        r = fut => r,
+        _ = rx.wait_for(|c| *c) => Err(anyhow::anyhow!("全局停滞超时（--global-stall-timeout），看门狗已取消本分段")),
+    }
+}
+
+// 检查间隔：超时时长的十分之一，1s ~ 60s
+pub fn poll_interval(timeout: Duration) -> Duration {
+    (timeout / 10).clamp(Duration::from_secs(1), Duration::from_secs(60))
+}
+
+// 超过 timeout 没有任何进展且有进行中的分段时，记录并取消这些分段
+pub fn stalled(timeout: Duration) -> Option<Vec<Stuck>> {
+    let idle = status::since_progress().filter(|d| *d > timeout)?;
+    let active = status::in_flight();
+    if active.is_empty() {
+        return None;
+    }
+    error!("全局 {}s 没有任何进展（--global-stall-timeout {}s），取消进行中的 {} 个分段:", idle.as_secs(), timeout.as_secs(), active.len());
+    for (worker, seg, elapsed, phase) in &active {
+        error!("  worker {} segment {} 已进行 {}s，阶段 {}", worker, seg, elapsed.as_secs(), phase);
+        cancel(*worker);
+    }
+    Some(active.into_iter().map(|(worker, segment, _, _)| Stuck { worker, segment }).collect())
+}
+
+// 该 worker 仍在处理这个分段
+pub fn still_on(s: &Stuck) -> bool {
+    status::in_flight().iter().any(|(w, seg, _, _)| *w == s.worker && *seg == s.segment)
+}
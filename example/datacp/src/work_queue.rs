@@ -0,0 +1,395 @@
+// ===================== 分段工作队列 =====================
+// worker 从共享队列逐个领取分段，首轮、增量与重新校验共用同一组 worker：
+// 每轮只需把新分段放入队列并等待队列清空，不再为一两个增量分段重新启动整组任务。
+// 熔断（每次运行一个熔断器，本次运行的各 worker 共用；多表迁移中某表熔断后不再开始其余表）：--max-consecutive-failures N 连续 N 个分段失败，
+// 或 --max-failure-rate "50% over 10m" 窗口内（至少 MIN_RATE_SAMPLES 个结果）失败比例达到上限时熔断：
+// 队列不再派发新分段（已入队的直接丢弃），进行中的分段照常完成并写入断点，之后按截止处理（不做增量与切换），
+// 报告记录首个与最常见的错误，以单独的退出码结束。任何分段成功都会清零连续失败计数，失败率按窗口内全部结果（含成功）计算，
+// 时好时坏但仍有进展的运行不会被连续失败上限熔断。
+// 去重：队列记录已入队或处理中的分段与本队列已完成的分段，重复入队（增量按新的 min/max 重新生成、与首轮重叠的分段）
+// 不再处理第二次，只计数并记 debug 日志；分段完成时写入断点续传记录与移出处理中集合在同一把锁内完成。
+// 有意的重新校验（mutation 后重扫、热备定期校验）用 requeue，已完成的分段重新处理，但仍不与处理中的同一分段并发
+
+use log::{debug, error};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+
+use crate::report::CircuitBreak;
+use crate::{parse_duration_str, status, Opt};
+
+// 失败率至少基于这么多个分段结果，避免开头一两次失败即熔断
+const MIN_RATE_SAMPLES: usize = 10;
+
+struct Breaker {
+    max_consecutive: usize,
+    max_rate: Option<(f64, Duration)>,
+    streak: Vec<(String, String)>,                          // 本轮连续失败的 (分段, 错误)
+    window: VecDeque<(Instant, Option<(String, String)>)>, // 窗口内的各分段结果，失败时带 (分段, 错误)
+    report: Option<CircuitBreak>,
+}
+
+// 一次运行的熔断器（RunCtx 持有），未设置熔断参数时只记录不熔断
+#[derive(Default)]
+pub struct CircuitBreaker {
+    breaker: Mutex<Option<Breaker>>,
+    tripped: AtomicBool,
+}
+
+// "50% over 10m"
+fn parse_rate(s: &str) -> anyhow::Result<Option<(f64, Duration)>> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(None);
+    }
+    let bad = || anyhow::anyhow!(format!("--max-failure-rate 格式应为 \"50% over 10m\": {}", s));
+    let (pct, window) = s.split_once(" over ").ok_or_else(bad)?;
+    let pct: f64 = pct.trim().trim_end_matches('%').trim().parse().map_err(|_| bad())?;
+    if !(pct > 0.0 && pct <= 100.0) {
+        return Err(bad());
+    }
+    let window = parse_duration_str(window.trim()).map_err(|_| bad())?;
+    if window.is_zero() {
+        return Err(bad());
+    }
+    Ok(Some((pct / 100.0, window)))
+}
+
+// 启动时检查熔断参数，格式错误时在连接 ClickHouse 之前报错
+pub fn check(opt: &Opt) -> anyhow::Result<()> {
+    parse_rate(&opt.max_failure_rate).map(|_| ())
+}
+
+// 错误文本中的数字（时间、行数、query_id 等）替换后再归类，统计最常见的错误
+fn error_kind(e: &str) -> String {
+    let mut out = String::new();
+    for ch in e.chars().take(300) {
+        if ch.is_ascii_digit() {
+            if !out.ends_with('#') {
+                out.push('#');
+            }
+        } else {
+            out.push(ch);
+        }
+    }
+    out
+}
+
+fn summarize(reason: String, failures: &[&(String, String)]) -> CircuitBreak {
+    let mut kinds: HashMap<String, usize> = HashMap::new();
+    for (_, e) in failures {
+        *kinds.entry(error_kind(e)).or_default() += 1;
+    }
+    let (common, count) = kinds.into_iter().max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0))).unwrap_or_default();
+    let first = failures.first();
+    CircuitBreak {
+        reason,
+        phase: String::new(),
+        failures: failures.len(),
+        first_segment: first.map(|f| f.0.clone()).unwrap_or_default(),
+        first_error: first.map(|f| f.1.clone()).unwrap_or_default(),
+        most_common_error: common,
+        most_common_count: count,
+    }
+}
+
+impl Breaker {
+    fn record(&mut self, failure: Option<(String, String)>) -> Option<CircuitBreak> {
+        let now = Instant::now();
+        if let Some((_, w)) = self.max_rate {
+            self.window.push_back((now, failure.clone()));
+            while self.window.front().is_some_and(|(t, _)| now.duration_since(*t) > w) {
+                self.window.pop_front();
+            }
+        }
+        let Some(f) = failure else {
+            self.streak.clear();
+            return None;
+        };
+        self.streak.push(f);
+        if self.max_consecutive > 0 && self.streak.len() >= self.max_consecutive {
+            let failures: Vec<&(String, String)> = self.streak.iter().collect();
+            return Some(summarize(format!("连续 {} 个分段失败（--max-consecutive-failures {}）", failures.len(), self.max_consecutive), &failures));
+        }
+        if let Some((rate, w)) = self.max_rate {
+            let failures: Vec<&(String, String)> = self.window.iter().filter_map(|(_, f)| f.as_ref()).collect();
+            let observed = failures.len() as f64 / self.window.len() as f64;
+            if self.window.len() >= MIN_RATE_SAMPLES && observed >= rate {
+                return Some(summarize(
+                    format!("{}s 内失败比例 {:.0}%（--max-failure-rate {:.0}% over {}s）", w.as_secs(), observed * 100.0, rate * 100.0, w.as_secs()),
+                    &failures,
+                ));
+            }
+        }
+        None
+    }
+}
+
+impl CircuitBreaker {
+    // 按本次运行的参数创建（supervise 重启与 serve 每个任务各自从头计数）
+    pub fn new(opt: &Opt) -> anyhow::Result<Self> {
+        let max_rate = parse_rate(&opt.max_failure_rate)?;
+        let breaker = (opt.max_consecutive_failures > 0 || max_rate.is_some()).then(|| Breaker {
+            max_consecutive: opt.max_consecutive_failures,
+            max_rate,
+            streak: Vec::new(),
+            window: VecDeque::new(),
+            report: None,
+        });
+        Ok(CircuitBreaker { breaker: Mutex::new(breaker), tripped: AtomicBool::new(false) })
+    }
+
+    pub fn tripped(&self) -> bool {
+        self.tripped.load(Ordering::Relaxed)
+    }
+
+    // 熔断信息（写入报告）
+    pub fn circuit_break(&self) -> Option<CircuitBreak> {
+        self.breaker.lock().unwrap().as_ref().and_then(|b| b.report.clone())
+    }
+
+    fn record(&self, failure: Option<(String, String)>) {
+        let mut guard = self.breaker.lock().unwrap();
+        let Some(b) = guard.as_mut() else { return };
+        if b.report.is_some() {
+            return;
+        }
+        if let Some(c) = b.record(failure) {
+            error!(
+                "熔断: {}，不再派发新分段，进行中的分段完成后停止；首个错误（segment {}）: {}；最常见错误（{} 次）: {}",
+                c.reason, c.first_segment, c.first_error, c.most_common_count, c.most_common_error
+            );
+            b.report = Some(c);
+            self.tripped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // 分段（或 replace-partitions 的分区）成功：清零连续失败计数
+    pub fn succeeded(&self) {
+        self.record(None);
+    }
+
+    pub fn failed(&self, seg: &str, error: &str) {
+        self.record(Some((seg.to_string(), error.to_string())));
+    }
+}
+
+#[derive(Default)]
+struct Tracked {
+    in_flight: HashSet<String>, // 已入队或处理中
+    done: HashSet<String>,      // 已完成并写入断点续传记录
+}
+
+pub struct SegmentQueue {
+    breaker: Arc<CircuitBreaker>, // 熔断后不再派发
+    tx: Mutex<Option<mpsc::UnboundedSender<String>>>,
+    rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<String>>,
+    pending: watch::Sender<usize>, // 已入队、尚未处理完的分段数
+    tracked: Mutex<Tracked>,
+    duplicates: AtomicU64, // 重复入队而跳过的次数
+}
+
+// 领取的分段处理结束（包括中途 continue、失败与被中止）时计数减一并移出处理中集合
+pub struct Taken<'a> {
+    queue: &'a SegmentQueue,
+    seg: String,
+}
+
+impl Drop for Taken<'_> {
+    fn drop(&mut self) {
+        self.queue.tracked.lock().unwrap().in_flight.remove(&self.seg);
+        self.queue.pending.send_modify(|n| *n -= 1);
+    }
+}
+
+impl Taken<'_> {
+    // 分段完成：write 写入断点续传记录，成功后在同一把锁内记为已完成，期间同一分段的入队视为重复
+    pub fn complete(&self, write: impl FnOnce() -> anyhow::Result<()>) -> anyhow::Result<()> {
+        let mut t = self.queue.tracked.lock().unwrap();
+        write()?;
+        t.in_flight.remove(&self.seg);
+        t.done.insert(self.seg.clone());
+        Ok(())
+    }
+}
+
+impl SegmentQueue {
+    pub fn new(breaker: Arc<CircuitBreaker>) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        SegmentQueue {
+            breaker,
+            tx: Mutex::new(Some(tx)),
+            rx: tokio::sync::Mutex::new(rx),
+            pending: watch::Sender::new(0),
+            tracked: Mutex::new(Tracked::default()),
+            duplicates: AtomicU64::new(0),
+        }
+    }
+
+    // 放入一批分段：已入队、处理中或已完成的分段跳过
+    pub fn push(&self, segments: Vec<String>) {
+        self.enqueue(segments, false);
+    }
+
+    // 重新校验：已完成的分段重新处理，已入队或处理中的仍跳过
+    pub fn requeue(&self, segments: Vec<String>) {
+        self.enqueue(segments, true);
+    }
+
+    fn enqueue(&self, segments: Vec<String>, again: bool) {
+        let guard = self.tx.lock().unwrap();
+        let Some(tx) = guard.as_ref() else { return };
+        let accepted: Vec<String> = {
+            let mut t = self.tracked.lock().unwrap();
+            segments
+                .into_iter()
+                .filter(|seg| {
+                    if t.in_flight.contains(seg) || (!again && t.done.contains(seg)) {
+                        self.duplicates.fetch_add(1, Ordering::Relaxed);
+                        debug!("segment {seg} 已在队列中、处理中或已完成，跳过重复入队");
+                        return false;
+                    }
+                    t.done.remove(seg);
+                    t.in_flight.insert(seg.clone());
+                    true
+                })
+                .collect()
+        };
+        status::segments_queued(&accepted);
+        self.pending.send_modify(|n| *n += accepted.len());
+        for seg in accepted {
+            let _ = tx.send(seg);
+        }
+    }
+
+    // 重复入队而跳过的次数
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    // 队列关闭且取空后返回 None，worker 随之退出；熔断后丢弃已入队的分段，等待方随之返回
+    pub async fn next(&self) -> Option<(String, Taken<'_>)> {
+        loop {
+            let seg = self.rx.lock().await.recv().await?;
+            let taken = Taken { queue: self, seg: seg.clone() };
+            if !self.breaker.tripped() {
+                return Some((seg, taken));
+            }
+        }
+    }
+
+    // 等待已入队的分段全部处理完
+    pub async fn wait_idle(&self) {
+        let _ = self.pending.subscribe().wait_for(|n| *n == 0).await;
+    }
+
+    pub fn close(&self) {
+        self.tx.lock().unwrap().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    // 若干 worker 领取分段，每个分段处理期间让出执行权，完成时按 complete 记为已完成；返回各分段被处理的次数
+    async fn drain(queue: Arc<SegmentQueue>, workers: usize, flood: impl Fn(&SegmentQueue)) -> HashMap<String, usize> {
+        let seen = Arc::new(Mutex::new(HashMap::new()));
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                let (q, seen) = (queue.clone(), seen.clone());
+                tokio::spawn(async move {
+                    while let Some((seg, taken)) = q.next().await {
+                        *seen.lock().unwrap().entry(seg).or_insert(0) += 1;
+                        tokio::time::sleep(Duration::from_millis(2)).await;
+                        taken.complete(|| Ok(())).unwrap();
+                    }
+                })
+            })
+            .collect();
+        flood(&queue);
+        queue.wait_idle().await;
+        queue.close();
+        for h in handles {
+            h.await.unwrap();
+        }
+        Arc::try_unwrap(seen).unwrap().into_inner().unwrap()
+    }
+
+    fn segments(n: usize) -> Vec<String> {
+        (0..n).map(|h| format!("2024-01-01 {:02}:00:00", h)).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn duplicate_segments_are_processed_once() {
+        let queue = Arc::new(SegmentQueue::new(Arc::default()));
+        let seen = drain(queue.clone(), 8, |q| {
+            // 同一批内重复、各批之间重复，处理中再次入队
+            for _ in 0..20 {
+                let mut batch = segments(10);
+                batch.extend(segments(10));
+                q.push(batch);
+            }
+        })
+        .await;
+        assert_eq!(seen.len(), 10);
+        assert!(seen.values().all(|n| *n == 1), "{:?}", seen);
+        assert_eq!(queue.duplicates(), 20 * 20 - 10);
+        // 已完成的分段再次入队不处理
+        queue.push(segments(10));
+        assert_eq!(*queue.pending.borrow(), 0);
+    }
+
+    #[tokio::test]
+    async fn requeue_reprocesses_done_segments_only() {
+        let queue = Arc::new(SegmentQueue::new(Arc::default()));
+        let seen = drain(queue.clone(), 2, |q| {
+            q.push(segments(3));
+            // 处理中的分段不重复，requeue 同样跳过
+            q.requeue(segments(3));
+        })
+        .await;
+        assert!(seen.values().all(|n| *n == 1), "{:?}", seen);
+        assert_eq!(queue.duplicates(), 3);
+        let again = Arc::new(SegmentQueue::new(Arc::default()));
+        again.tracked.lock().unwrap().done.extend(segments(3));
+        let seen = drain(again.clone(), 2, |q| q.requeue(segments(3))).await;
+        assert_eq!(seen.len(), 3);
+        assert_eq!(again.duplicates(), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_segments_can_be_enqueued_again() {
+        let queue = SegmentQueue::new(Arc::default());
+        queue.push(segments(1));
+        let (seg, taken) = queue.next().await.unwrap();
+        // 未调用 complete（失败或跳过）：移出处理中集合，不记为已完成
+        drop(taken);
+        queue.push(vec![seg.clone()]);
+        let (again, _taken) = queue.next().await.unwrap();
+        assert_eq!(again, seg);
+        assert_eq!(queue.duplicates(), 0);
+    }
+
+    // 每次运行各自的熔断器：一个队列熔断后另一个照常派发
+    #[tokio::test]
+    async fn breaker_trips_only_its_own_queue() {
+        let opt = Opt::from_iter(["datacp", "--max-consecutive-failures", "2"]);
+        let (a, b) = (Arc::new(CircuitBreaker::new(&opt).unwrap()), Arc::new(CircuitBreaker::new(&opt).unwrap()));
+        a.failed("s1", "Code: 241. Memory limit exceeded");
+        a.failed("s2", "Code: 241. Memory limit exceeded");
+        b.failed("s1", "Code: 241. Memory limit exceeded");
+        assert!(a.tripped() && !b.tripped());
+        assert_eq!(a.circuit_break().unwrap().failures, 2);
+        let (qa, qb) = (SegmentQueue::new(a), SegmentQueue::new(b));
+        qa.push(segments(2));
+        qb.push(segments(2));
+        qa.close();
+        qb.close();
+        assert!(qa.next().await.is_none());
+        assert_eq!(qb.next().await.map(|(seg, _)| seg), Some(segments(1)[0].clone()));
+    }
+}
@@ -0,0 +1,89 @@
+// ===================== 目标端只读等待 =====================
+// 目标副本因 ZooKeeper 异常进入只读（Code 242）或分区 part 过多（Code 252）时，
+// 所有 worker 暂停写入，由首个遇到错误的 worker 探测 system.replicas，恢复可写后继续
+
+use log::{info, warn};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::report::RunReport;
+use crate::{ch_query_rows, cluster, json_u64, shard, Opt};
+
+pub struct WriteGate {
+    dsn: String,
+    db: String,
+    probe_sql: String,
+    wait_max: Duration,
+    blocked: AtomicBool,
+    waited_ms: AtomicU64,
+    report: Arc<Mutex<RunReport>>,
+}
+
+impl WriteGate {
+    pub async fn new(opt: &Opt, report: Arc<Mutex<RunReport>>) -> anyhow::Result<Self> {
+        let (db, table) = shard::resolve_local_table(&opt.dst_dsn, &opt.dst_db, opt.read_table()).await?;
+        let from = cluster::replicas_from(opt).await?;
+        Ok(WriteGate {
+            dsn: opt.dst_dsn.clone(),
+            db: opt.dst_db.clone(),
+            probe_sql: format!(
+                "SELECT max(is_readonly) AS readonly FROM {} WHERE database = '{}' AND table = '{}' FORMAT JSONEachRow",
+                from, db, table
+            ),
+            wait_max: opt.readonly_wait_max,
+            blocked: AtomicBool::new(false),
+            waited_ms: AtomicU64::new(0),
+            report,
+        })
+    }
+
+    // 写入前调用：其他 worker 正在等待目标端恢复时一同阻塞
+    pub async fn wait_writable(&self) {
+        while self.blocked.load(Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    async fn is_readonly(&self) -> bool {
+        match ch_query_rows(&self.dsn, &self.db, &self.probe_sql).await {
+            Ok(rows) => json_u64(rows.first().and_then(|r| r.get("readonly"))) > 0,
+            Err(e) => {
+                warn!("探测目标表只读状态失败: {e}");
+                true
+            }
+        }
+    }
+
+    // 写入遇到 242/252 时调用：首个调用者负责探测与退避，其余调用者等待其结果；超过 --readonly-wait-max 返回错误
+    pub async fn wait_until_writable(&self, err: &str) -> anyhow::Result<()> {
+        if self.blocked.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+            self.wait_writable().await;
+            return Ok(());
+        }
+        warn!("目标端暂不可写，暂停所有写入: {}", err.lines().next().unwrap_or(err));
+        let started = Instant::now();
+        let mut backoff = Duration::from_secs(5);
+        let mut last_log = Instant::now();
+        let res = loop {
+            tokio::time::sleep(backoff).await;
+            // TOO_MANY_PARTS 时副本并非只读，至少退避一次等待后台合并
+            if !self.is_readonly().await {
+                info!("目标端已恢复可写，等待 {:.0}s 后继续写入", started.elapsed().as_secs_f64());
+                break Ok(());
+            }
+            if started.elapsed() >= self.wait_max {
+                break Err(anyhow::anyhow!(format!("目标端只读超过 --readonly-wait-max {:?}", self.wait_max)));
+            }
+            if last_log.elapsed() >= Duration::from_secs(30) {
+                info!("目标端仍为只读，已等待 {:.0}s（上限 {:?}）", started.elapsed().as_secs_f64(), self.wait_max);
+                last_log = Instant::now();
+            }
+            backoff = (backoff * 2).min(Duration::from_secs(60));
+        };
+        let total = self.waited_ms.fetch_add(started.elapsed().as_millis() as u64, Ordering::SeqCst) + started.elapsed().as_millis() as u64;
+        self.report.lock().unwrap().readonly_wait_seconds = total / 1000;
+        self.blocked.store(false, Ordering::SeqCst);
+        res
+    }
+}